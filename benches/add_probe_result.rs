@@ -0,0 +1,55 @@
+use std::sync::Arc;
+use std::thread;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use xbp_monitoring::config::Config;
+use xbp_monitoring::test_utils::result_test_utils::ProbeResultBuilder;
+use xbp_monitoring::AppState;
+
+// Results each probe adds per iteration, enough to keep the per-probe history at its limit
+const RESULTS_PER_PROBE: usize = 10;
+
+// Every probe gets its own thread, so all of them contend on the results write lock
+fn add_probe_results_concurrently(app_state: &Arc<AppState>, probe_names: &[String]) {
+    let handles: Vec<_> = probe_names
+        .iter()
+        .map(|probe_name| {
+            let app_state = app_state.clone();
+            let probe_name = probe_name.clone();
+            thread::spawn(move || {
+                for _ in 0..RESULTS_PER_PROBE {
+                    app_state.add_probe_result(
+                        probe_name.clone(),
+                        ProbeResultBuilder::new(&probe_name).build(),
+                    );
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+}
+
+fn bench_add_probe_result(c: &mut Criterion) {
+    let mut group = c.benchmark_group("add_probe_result");
+    for probes in [10, 100, 1000] {
+        let probe_names: Vec<String> = (0..probes).map(|i| format!("probe-{}", i)).collect();
+        let app_state = Arc::new(AppState::new(Config::default()));
+        // Fill the histories first so the benchmark includes evicting the oldest result
+        for _ in 0..10 {
+            add_probe_results_concurrently(&app_state, &probe_names);
+        }
+
+        group.throughput(Throughput::Elements((probes * RESULTS_PER_PROBE) as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(probes),
+            &probe_names,
+            |b, probe_names| b.iter(|| add_probe_results_concurrently(&app_state, probe_names)),
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_add_probe_result);
+criterion_main!(benches);
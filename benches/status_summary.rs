@@ -0,0 +1,54 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::body::Body;
+use axum::http::Request;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use tower::ServiceExt;
+use xbp_monitoring::config::Config;
+use xbp_monitoring::probe::model::ProbeResult;
+use xbp_monitoring::test_utils::result_test_utils::ProbeResultBuilder;
+use xbp_monitoring::web_server::app_router;
+use xbp_monitoring::AppState;
+
+// Enough to keep the per-probe history at its limit
+const RESULTS_PER_PROBE: u64 = 100;
+
+fn probe_result(probe_name: &str, millis: u64) -> ProbeResult {
+    ProbeResultBuilder::new(probe_name)
+        .success(!millis.is_multiple_of(10))
+        .duration(Duration::from_millis(millis))
+        .build()
+}
+
+// The handler serves the precomputed summary, so its latency should barely grow with the
+// number of monitors, apart from serializing the larger body
+fn bench_status_handler(c: &mut Criterion) {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let mut group = c.benchmark_group("status_handler");
+    for probes in [10, 100, 1000] {
+        let app_state = Arc::new(AppState::new(Config::default()));
+        for i in 0..probes {
+            let probe_name = format!("probe-{}", i);
+            for millis in 0..RESULTS_PER_PROBE {
+                app_state.add_probe_result(probe_name.clone(), probe_result(&probe_name, millis));
+            }
+        }
+        app_state.status_summary.refresh(&app_state);
+        let router = app_router(app_state);
+
+        group.bench_with_input(BenchmarkId::from_parameter(probes), &router, |b, router| {
+            b.iter(|| {
+                runtime.block_on(
+                    router
+                        .clone()
+                        .oneshot(Request::get("/status").body(Body::empty()).unwrap()),
+                )
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_status_handler);
+criterion_main!(benches);
@@ -0,0 +1,46 @@
+// Embeds the build identity served by `/-/info`, see `src/build_info.rs`. Only std is used so the
+// build script adds no dependencies; values that can't be determined are left empty.
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn main() {
+    // Builds without a `.git` directory, e.g. in Docker, can pass the commit in
+    let git_commit = std::env::var("XBP_GIT_COMMIT")
+        .ok()
+        .filter(|commit| !commit.is_empty())
+        .or_else(|| command_output("git", &["rev-parse", "HEAD"]))
+        .unwrap_or_default();
+    // Honours SOURCE_DATE_EPOCH for reproducible builds
+    let build_timestamp = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .filter(|epoch| epoch.parse::<i64>().is_ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|since| since.as_secs().to_string())
+                .unwrap_or_default()
+        });
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_owned());
+    let rustc_version = command_output(&rustc, &["--version"]).unwrap_or_default();
+
+    println!("cargo:rustc-env=XBP_GIT_COMMIT={}", git_commit);
+    println!("cargo:rustc-env=XBP_BUILD_TIMESTAMP={}", build_timestamp);
+    println!("cargo:rustc-env=XBP_RUSTC_VERSION={}", rustc_version);
+    println!("cargo:rerun-if-env-changed=XBP_GIT_COMMIT");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+    // Missing paths would rerun the script on every build
+    for path in [".git/HEAD", ".git/refs"] {
+        if std::path::Path::new(path).exists() {
+            println!("cargo:rerun-if-changed={}", path);
+        }
+    }
+}
+
+fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let output = String::from_utf8(output.stdout).ok()?;
+    Some(output.trim().to_owned()).filter(|output| !output.is_empty())
+}
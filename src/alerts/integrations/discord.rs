@@ -1,13 +1,13 @@
-use chrono::{DateTime, Utc};
 use lazy_static::lazy_static;
 use reqwest::{Client, ClientBuilder, Response};
 use serde_json::json;
-use std::error::Error;
 use std::time::Duration;
-use tracing::{error, info};
+use tracing::info;
 
 // crate imports
-use crate::errors::MapToSendError;
+use crate::alerts::model::AlertContext;
+use crate::alerts::outbound_webhook::check_alert_response;
+use crate::errors::{AlertChannel, AlertError};
 use crate::probe::model::ProbeAlert;
 
 const REQUEST_TIMEOUT_SECS: u64 = 30;
@@ -22,18 +22,21 @@ lazy_static! {
 
 pub async fn send_alert_discord(
     alert: &ProbeAlert,
-    probe_name: String,
-    failure_timestamp: DateTime<Utc>,
-) -> Result<u16, Box<dyn Error + Send>> {
-    let client: Client = Client::new();
+    context: &AlertContext,
+) -> Result<(), AlertError> {
+    let to_alert_error =
+        |cause| AlertError::new(AlertChannel::Discord, &context.monitor_name, cause);
     let webhook_url: String = alert.url.clone();
 
     let content: String = format!(
-        "```{} | Probe failed to return status code 200 \n Probe Name: {} \n Failure Timestamp: {}```",
-        probe_name, probe_name, failure_timestamp
+        "```{} | Probe failed to return status code 200 \n Probe Name: {} \n Failure Timestamp: {} \n Run ID: {}```",
+        context.monitor_name,
+        context.monitor_name,
+        context.failure_timestamp,
+        context.run_id.map_or("N/A".to_owned(), |id| id.to_string())
     );
 
-    let alert_response: Response = client
+    let alert_response: Response = CLIENT
         .post(&webhook_url)
         .body(
             json!({
@@ -45,15 +48,41 @@ pub async fn send_alert_discord(
         .timeout(Duration::from_secs(REQUEST_TIMEOUT_SECS))
         .send()
         .await
-        .map_to_send_err()?;
+        .map_err(|err| to_alert_error(err.into()))?;
 
-    let status_code: u16 = alert_response.status().as_u16();
+    check_alert_response(alert_response)
+        .await
+        .map_err(to_alert_error)?;
+    info!("Alert sent successfully");
+
+    Ok(())
+}
+
+pub async fn send_report_discord(
+    alert: &ProbeAlert,
+    report_name: &str,
+    text: &str,
+) -> Result<(), AlertError> {
+    let to_alert_error = |cause| AlertError::new(AlertChannel::Discord, report_name, cause);
+
+    let alert_response: Response = CLIENT
+        .post(&alert.url)
+        .body(
+            json!({
+                "content": format!("```\n{}\n```", text)
+            })
+            .to_string(),
+        )
+        .header("Content-Type", CONTENT_TYPE)
+        .timeout(Duration::from_secs(REQUEST_TIMEOUT_SECS))
+        .send()
+        .await
+        .map_err(|err| to_alert_error(err.into()))?;
 
-    if alert_response.status().is_success() {
-        info!("Alert sent successfully");
-    } else {
-        error!("Failed to send alert: {:?}", alert_response.text().await);
-    }
+    check_alert_response(alert_response)
+        .await
+        .map_err(to_alert_error)?;
+    info!("Report sent successfully");
 
-    Ok(status_code)
+    Ok(())
 }
@@ -0,0 +1,379 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use chrono::Utc;
+use lazy_static::lazy_static;
+use reqwest::header::{AUTHORIZATION, RETRY_AFTER};
+use reqwest::{Client, ClientBuilder, StatusCode, Url};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::alerts::model::AlertContext;
+use crate::alerts::outbound_webhook::check_alert_response;
+use crate::errors::{AlertChannel, AlertError, AlertErrorCause};
+use crate::probe::model::ProbeAlert;
+
+const REQUEST_TIMEOUT_SECS: u64 = 10;
+const SOURCE: &str = "xbp-monitoring";
+// Opsgenie cuts longer messages
+const MAX_MESSAGE_CHARS: usize = 130;
+// Waited after a 429 without a usable Retry-After, and the longest Retry-After honored
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(1);
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(30);
+
+lazy_static! {
+    static ref CLIENT: Client = ClientBuilder::new()
+        .user_agent(concat!("xbp-monitoring/", env!("CARGO_PKG_VERSION")))
+        .build()
+        .expect("Failed to build reqwest client");
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OpsgenieRegion {
+    #[default]
+    Us,
+    Eu,
+}
+
+impl OpsgenieRegion {
+    fn api_url(&self) -> &'static str {
+        match self {
+            OpsgenieRegion::Us => "https://api.opsgenie.com",
+            OpsgenieRegion::Eu => "https://api.eu.opsgenie.com",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OpsgeniePriority {
+    P1,
+    P2,
+    #[default]
+    P3,
+    P4,
+    P5,
+}
+
+// A team, user, escalation or schedule notified of the alert, by `id`, `name` or `username`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OpsgenieResponder {
+    #[serde(rename = "type")]
+    pub responder_type: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct CreateAlert {
+    message: String,
+    alias: String,
+    description: String,
+    priority: OpsgeniePriority,
+    source: &'static str,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    responders: Vec<OpsgenieResponder>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    details: BTreeMap<&'static str, String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct CloseAlert {
+    source: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    note: Option<String>,
+}
+
+// Repeated failures of a monitor deduplicate into one open alert, which its recovery closes
+pub fn alias(monitor_name: &str) -> String {
+    format!("xbp-{}", monitor_name)
+}
+
+pub async fn send_alert_opsgenie(
+    alert: &ProbeAlert,
+    context: &AlertContext,
+) -> Result<(), AlertError> {
+    let probe_name = context.monitor_name.as_str();
+    let details = [
+        (
+            "failure_timestamp",
+            Some(context.failure_timestamp.to_rfc3339()),
+        ),
+        (
+            "status_code",
+            context.status_code.map(|code| code.to_string()),
+        ),
+        ("trace_id", context.trace_id.clone()),
+        ("run_id", context.run_id.map(|id| id.to_string())),
+    ]
+    .into_iter()
+    .filter_map(|(key, value)| Some((key, value?)))
+    .collect();
+    let payload = CreateAlert {
+        message: format!("{} failed", probe_name)
+            .chars()
+            .take(MAX_MESSAGE_CHARS)
+            .collect(),
+        alias: alias(probe_name),
+        description: context.error_message.clone(),
+        priority: alert.priority.unwrap_or_default(),
+        source: SOURCE,
+        tags: alert.tags.clone().unwrap_or_default(),
+        responders: alert.responders.clone().unwrap_or_default(),
+        details,
+    };
+    post(alert, &["v2", "alerts"], &payload)
+        .await
+        .map_err(|cause| AlertError::new(AlertChannel::Opsgenie, probe_name, cause))?;
+    info!("Opsgenie alert created for {}", probe_name);
+    Ok(())
+}
+
+// Closes the alert a failure of the monitor opened, `note` is the rendered recovery template
+pub async fn close_alert_opsgenie(
+    alert: &ProbeAlert,
+    monitor_name: &str,
+    note: Option<&str>,
+) -> Result<(), AlertError> {
+    let payload = CloseAlert {
+        source: SOURCE,
+        note: note.map(str::to_owned),
+    };
+    let alias = alias(monitor_name);
+    post(alert, &["v2", "alerts", &alias, "close"], &payload)
+        .await
+        .map_err(|cause| AlertError::new(AlertChannel::Opsgenie, monitor_name, cause))?;
+    info!("Opsgenie alert closed for {}", monitor_name);
+    Ok(())
+}
+
+// Reports are informational, each one is a P5 alert of its own
+pub async fn send_report_opsgenie(
+    alert: &ProbeAlert,
+    report_name: &str,
+    text: &str,
+) -> Result<(), AlertError> {
+    let payload = CreateAlert {
+        message: format!("Summary report {}", report_name)
+            .chars()
+            .take(MAX_MESSAGE_CHARS)
+            .collect(),
+        alias: format!("xbp-report-{}-{}", report_name, Utc::now().timestamp()),
+        description: text.to_owned(),
+        priority: OpsgeniePriority::P5,
+        source: SOURCE,
+        tags: alert.tags.clone().unwrap_or_default(),
+        responders: alert.responders.clone().unwrap_or_default(),
+        details: BTreeMap::new(),
+    };
+    post(alert, &["v2", "alerts"], &payload)
+        .await
+        .map_err(|cause| AlertError::new(AlertChannel::Opsgenie, report_name, cause))
+}
+
+// `url` replaces the API of the region, e.g. to go through a proxy. The alias is percent-encoded
+// as a path segment.
+fn api_url(alert: &ProbeAlert, segments: &[&str]) -> String {
+    let base = if alert.url.is_empty() {
+        alert.region.unwrap_or_default().api_url()
+    } else {
+        alert.url.as_str()
+    };
+    // Checked by `ProbeAlert::validate`, reqwest reports any other invalid url when sending
+    let Ok(mut url) = Url::parse(base) else {
+        return base.to_owned();
+    };
+    if let Ok(mut path) = url.path_segments_mut() {
+        path.pop_if_empty().extend(segments);
+    }
+    if segments.last() == Some(&"close") {
+        url.query_pairs_mut().append_pair("identifierType", "alias");
+    }
+    url.into()
+}
+
+// A 429 is retried once, after the Retry-After the API asked for
+async fn post<T: Serialize>(
+    alert: &ProbeAlert,
+    segments: &[&str],
+    payload: &T,
+) -> Result<(), AlertErrorCause> {
+    let url = api_url(alert, segments);
+    let body = serde_json::to_string(payload)?;
+    let api_key = alert.api_key.as_deref().unwrap_or_default();
+    let mut retried = false;
+    loop {
+        let response = CLIENT
+            .post(&url)
+            .header(AUTHORIZATION, format!("GenieKey {}", api_key))
+            .header("content-type", "application/json")
+            .body(body.clone())
+            .timeout(Duration::from_secs(REQUEST_TIMEOUT_SECS))
+            .send()
+            .await?;
+        if response.status() == StatusCode::TOO_MANY_REQUESTS && !retried {
+            retried = true;
+            let wait = response
+                .headers()
+                .get(RETRY_AFTER)
+                .and_then(|value| value.to_str().ok()?.trim().parse::<u64>().ok())
+                .map_or(DEFAULT_RETRY_AFTER, Duration::from_secs)
+                .min(MAX_RETRY_AFTER);
+            warn!("Opsgenie rate limited the alert, retrying in {:?}", wait);
+            tokio::time::sleep(wait).await;
+            continue;
+        }
+        return check_alert_response(response).await;
+    }
+}
+
+#[cfg(test)]
+mod opsgenie_tests {
+    use chrono::{TimeZone, Utc};
+    use wiremock::matchers::{body_json, header, method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use super::{close_alert_opsgenie, send_alert_opsgenie, OpsgeniePriority, OpsgenieResponder};
+    use crate::alerts::model::AlertContext;
+    use crate::errors::AlertChannel;
+    use crate::probe::model::ProbeAlert;
+
+    fn opsgenie_alert(mock_server: &MockServer) -> ProbeAlert {
+        ProbeAlert {
+            channel_type: Some(AlertChannel::Opsgenie),
+            url: mock_server.uri(),
+            api_key: Some("secret-key".to_owned()),
+            priority: Some(OpsgeniePriority::P2),
+            tags: Some(vec!["checkout".to_owned()]),
+            responders: Some(vec![OpsgenieResponder {
+                responder_type: "team".to_owned(),
+                id: None,
+                name: Some("payments".to_owned()),
+                username: None,
+            }]),
+            ..Default::default()
+        }
+    }
+
+    fn timeout_context() -> AlertContext {
+        AlertContext {
+            monitor_name: "Checkout".to_owned(),
+            failure_timestamp: Utc::now(),
+            error_message: "timeout".to_owned(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_failure_creates_alert() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v2/alerts"))
+            .and(header("authorization", "GenieKey secret-key"))
+            .and(body_json(serde_json::json!({
+                "message": "Checkout failed",
+                "alias": "xbp-Checkout",
+                "description": "Expected status 200, got 503",
+                "priority": "P2",
+                "source": "xbp-monitoring",
+                "tags": ["checkout"],
+                "responders": [{ "type": "team", "name": "payments" }],
+                "details": {
+                    "failure_timestamp": "2024-01-15T10:30:00+00:00",
+                    "status_code": "503"
+                }
+            })))
+            .respond_with(ResponseTemplate::new(202))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        send_alert_opsgenie(
+            &opsgenie_alert(&mock_server),
+            &AlertContext {
+                monitor_name: "Checkout".to_owned(),
+                failure_timestamp: Utc.with_ymd_and_hms(2024, 1, 15, 10, 30, 0).unwrap(),
+                error_message: "Expected status 200, got 503".to_owned(),
+                status_code: Some(503),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_recovery_closes_alert_by_alias() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v2/alerts/xbp-Checkout/close"))
+            .and(query_param("identifierType", "alias"))
+            .and(header("authorization", "GenieKey secret-key"))
+            .and(body_json(serde_json::json!({
+                "source": "xbp-monitoring",
+                "note": "Checkout recovered"
+            })))
+            .respond_with(ResponseTemplate::new(202))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        close_alert_opsgenie(
+            &opsgenie_alert(&mock_server),
+            "Checkout",
+            Some("Checkout recovered"),
+        )
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_rejected_key_is_an_alert_error() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v2/alerts"))
+            .respond_with(
+                ResponseTemplate::new(401)
+                    .set_body_string(r#"{"message":"Key format is not valid!"}"#),
+            )
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let error = send_alert_opsgenie(&opsgenie_alert(&mock_server), &timeout_context())
+            .await
+            .unwrap_err();
+
+        assert_eq!(AlertChannel::Opsgenie, error.channel);
+        assert_eq!(Some(401), error.status_code());
+        assert!(error.to_string().contains("Key format is not valid"));
+    }
+
+    #[tokio::test]
+    async fn test_rate_limited_request_is_retried_once() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v2/alerts"))
+            .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "0"))
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/v2/alerts"))
+            .respond_with(ResponseTemplate::new(202))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        send_alert_opsgenie(&opsgenie_alert(&mock_server), &timeout_context())
+            .await
+            .unwrap();
+    }
+}
@@ -0,0 +1,275 @@
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use lazy_static::lazy_static;
+use serde::Serialize;
+use tracing::warn;
+
+use crate::errors::{AlertChannel, AlertError};
+use crate::probe::model::ProbeAlert;
+
+// Rotated files kept next to the current one when `max_files` isn't set
+pub(crate) const DEFAULT_MAX_FILES: u32 = 5;
+// At most one warning per interval while alerts fall back to stderr
+const FALLBACK_WARNING_INTERVAL: Duration = Duration::from_secs(60);
+
+lazy_static! {
+    // One writer per path, shared by all monitoring tasks so lines never interleave
+    static ref FILE_SINKS: Mutex<HashMap<PathBuf, FileSink>> = Mutex::new(HashMap::new());
+    static ref LAST_FALLBACK_WARNING: Mutex<Option<Instant>> = Mutex::new(None);
+}
+
+struct FileSink {
+    file: File,
+    size: u64,
+}
+
+impl FileSink {
+    fn open(path: &Path) -> std::io::Result<FileSink> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+        Ok(FileSink { file, size })
+    }
+}
+
+// Writes the payload as a single JSON line to the `stdout` or `file` channel of the alert
+pub fn write_alert_line<T: Serialize>(
+    alert: &ProbeAlert,
+    channel: AlertChannel,
+    monitor_name: &str,
+    payload: &T,
+) -> Result<(), AlertError> {
+    let mut line = serde_json::to_string(payload)
+        .map_err(|err| AlertError::new(channel, monitor_name, err.into()))?;
+    line.push('\n');
+    match (channel, &alert.path) {
+        (AlertChannel::File, Some(path)) => {
+            let max_bytes = alert.max_size_mb.map(|mb| mb * 1024 * 1024);
+            let max_files = alert.max_files.unwrap_or(DEFAULT_MAX_FILES);
+            if let Err(e) = write_file_line(path, &line, max_bytes, max_files) {
+                warn_fallback(path, &e);
+                write_stderr_line(&line);
+            }
+        }
+        _ => write_stdout_line(&line),
+    }
+    Ok(())
+}
+
+pub(crate) fn write_stdout_line(line: &str) {
+    let mut stdout = std::io::stdout().lock();
+    if stdout
+        .write_all(line.as_bytes())
+        .and_then(|_| stdout.flush())
+        .is_err()
+    {
+        write_stderr_line(line);
+    }
+}
+
+fn write_stderr_line(line: &str) {
+    let mut stderr = std::io::stderr().lock();
+    let _ = stderr
+        .write_all(line.as_bytes())
+        .and_then(|_| stderr.flush());
+}
+
+pub(crate) fn write_file_line(
+    path: &Path,
+    line: &str,
+    max_bytes: Option<u64>,
+    max_files: u32,
+) -> std::io::Result<()> {
+    let mut sinks = FILE_SINKS.lock().unwrap();
+    let sink = match sinks.remove(path) {
+        Some(sink) => sink,
+        None => FileSink::open(path)?,
+    };
+    let mut sink = match max_bytes {
+        Some(max_bytes) if sink.size > 0 && sink.size + line.len() as u64 > max_bytes => {
+            drop(sink);
+            rotate(path, max_files)?;
+            FileSink::open(path)?
+        }
+        _ => sink,
+    };
+    sink.file.write_all(line.as_bytes())?;
+    sink.file.flush()?;
+    sink.size += line.len() as u64;
+    sinks.insert(path.to_owned(), sink);
+    Ok(())
+}
+
+// `alerts.jsonl` becomes `alerts.jsonl.1`, `.1` becomes `.2` and so on, dropping the oldest
+fn rotate(path: &Path, max_files: u32) -> std::io::Result<()> {
+    if max_files == 0 {
+        return std::fs::remove_file(path);
+    }
+    let rotated = |index: u32| {
+        let mut name = path.as_os_str().to_owned();
+        name.push(format!(".{}", index));
+        PathBuf::from(name)
+    };
+    for index in (1..max_files).rev() {
+        let from = rotated(index);
+        if from.exists() {
+            std::fs::rename(from, rotated(index + 1))?;
+        }
+    }
+    std::fs::rename(path, rotated(1))
+}
+
+fn warn_fallback(path: &Path, error: &std::io::Error) {
+    let mut last_warning = LAST_FALLBACK_WARNING.lock().unwrap();
+    if last_warning.is_some_and(|last| last.elapsed() < FALLBACK_WARNING_INTERVAL) {
+        return;
+    }
+    *last_warning = Some(Instant::now());
+    warn!(
+        "Could not write alerts to {:?}, writing them to stderr instead: {}",
+        path, error
+    );
+}
+
+#[cfg(test)]
+mod line_sink_tests {
+    use std::env;
+    use std::path::PathBuf;
+
+    use chrono::Utc;
+    use uuid::Uuid;
+
+    use crate::alerts::line_sink::write_alert_line;
+    use crate::alerts::model::WebhookNotification;
+    use crate::errors::AlertChannel;
+    use crate::probe::model::ProbeAlert;
+
+    fn notification(probe_name: &str) -> WebhookNotification {
+        WebhookNotification {
+            message: "Probe failed.".to_owned(),
+            probe_name: probe_name.to_owned(),
+            failure_timestamp: Utc::now(),
+            error_message: "Expected status 200".to_owned(),
+            trace_id: Some("trace".to_owned()),
+            run_id: Some(Uuid::new_v4()),
+            status_code: Some(500),
+            body: None,
+        }
+    }
+
+    fn temp_dir() -> PathBuf {
+        let dir = env::temp_dir().join(format!("xbp-alerts-{}", Uuid::new_v4()));
+        std::fs::create_dir(&dir).unwrap();
+        dir
+    }
+
+    fn read_lines(path: &PathBuf) -> Vec<WebhookNotification> {
+        std::fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_file_lines_round_trip() {
+        let dir = temp_dir();
+        let path = dir.join("alerts.jsonl");
+        let alert = ProbeAlert {
+            channel_type: Some(AlertChannel::File),
+            path: Some(path.clone()),
+            ..Default::default()
+        };
+        let sent = [notification("first"), notification("second")];
+
+        for payload in &sent {
+            write_alert_line(&alert, AlertChannel::File, &payload.probe_name, payload).unwrap();
+        }
+
+        let written = read_lines(&path);
+        assert_eq!(2, written.len());
+        for (sent, written) in sent.iter().zip(&written) {
+            assert_eq!(sent.probe_name, written.probe_name);
+            assert_eq!(sent.failure_timestamp, written.failure_timestamp);
+            assert_eq!(sent.run_id, written.run_id);
+            assert_eq!(sent.status_code, written.status_code);
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_file_rotates_and_keeps_max_files() {
+        let dir = temp_dir();
+        let path = dir.join("alerts.jsonl");
+        let alert = ProbeAlert {
+            channel_type: Some(AlertChannel::File),
+            path: Some(path.clone()),
+            // Zero megabytes rotates before every line but the first of a file
+            max_size_mb: Some(0),
+            max_files: Some(2),
+            ..Default::default()
+        };
+
+        for name in ["one", "two", "three", "four"] {
+            write_alert_line(&alert, AlertChannel::File, name, &notification(name)).unwrap();
+        }
+
+        assert_eq!("four", read_lines(&path)[0].probe_name);
+        assert_eq!(
+            "three",
+            read_lines(&dir.join("alerts.jsonl.1"))[0].probe_name
+        );
+        assert_eq!("two", read_lines(&dir.join("alerts.jsonl.2"))[0].probe_name);
+        assert!(!dir.join("alerts.jsonl.3").exists());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_concurrent_writes_keep_whole_lines() {
+        let dir = temp_dir();
+        let path = dir.join("alerts.jsonl");
+        let alert = ProbeAlert {
+            channel_type: Some(AlertChannel::File),
+            path: Some(path.clone()),
+            ..Default::default()
+        };
+
+        let writers: Vec<_> = (0..8)
+            .map(|writer| {
+                let alert = alert.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..25 {
+                        let name = format!("writer-{}", writer);
+                        write_alert_line(&alert, AlertChannel::File, &name, &notification(&name))
+                            .unwrap();
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        assert_eq!(200, read_lines(&path).len());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_unwritable_file_falls_back() {
+        let dir = temp_dir();
+        let alert = ProbeAlert {
+            channel_type: Some(AlertChannel::File),
+            path: Some(dir.join("missing").join("alerts.jsonl")),
+            ..Default::default()
+        };
+
+        let result = write_alert_line(&alert, AlertChannel::File, "probe", &notification("probe"));
+
+        assert!(result.is_ok());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
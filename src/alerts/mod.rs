@@ -1,3 +1,5 @@
 pub mod integrations;
-mod model;
+pub(crate) mod line_sink;
+pub(crate) mod model;
 pub(crate) mod outbound_webhook;
+pub(crate) mod template;
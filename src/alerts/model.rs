@@ -1,5 +1,6 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use uuid::Uuid;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WebhookNotification {
@@ -10,11 +11,56 @@ pub struct WebhookNotification {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub trace_id: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub run_id: Option<Uuid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub status_code: Option<u32>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub body: Option<String>,
 }
 
+// The failed run every channel is told about, already redacted for sensitive monitors
+#[derive(Debug, Clone, Default)]
+pub struct AlertContext {
+    pub monitor_name: String,
+    pub run_id: Option<Uuid>,
+    pub trace_id: Option<String>,
+    pub failure_timestamp: DateTime<Utc>,
+    pub error_message: String,
+    pub status_code: Option<u32>,
+    // Truncated, or `Redacted`
+    pub body: Option<String>,
+}
+
+impl AlertContext {
+    pub fn notification(&self) -> WebhookNotification {
+        WebhookNotification {
+            message: "Probe failed.".to_owned(),
+            probe_name: self.monitor_name.clone(),
+            failure_timestamp: self.failure_timestamp,
+            error_message: self.error_message.clone(),
+            trace_id: self.trace_id.clone(),
+            run_id: self.run_id,
+            status_code: self.status_code,
+            body: self.body.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReportNotification {
+    pub message: String,
+    pub report_name: String,
+    pub report: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecoveryNotification {
+    pub message: String,
+    pub probe_name: String,
+    pub incident_id: Uuid,
+    pub duration_seconds: i64,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SlackNotification {
     pub blocks: Vec<SlackBlock>,
@@ -33,4 +79,4 @@ pub struct SlackBlock {
 pub struct SlackTextBlock {
     pub r#type: String,
     pub text: String,
-}
\ No newline at end of file
+}
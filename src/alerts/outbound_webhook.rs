@@ -1,15 +1,30 @@
 use std::time::Duration;
 
-use crate::errors::MapToSendError;
-use crate::probe::model::ProbeAlert;
-use crate::{alerts::model::WebhookNotification, probe::model::ProbeResponse};
+use crate::errors::{AlertChannel, AlertError, AlertErrorCause, TemplateError};
+use crate::probe::model::{error_kind, AlertEvent, ProbeAlert};
+use crate::{
+    alerts::model::{AlertContext, RecoveryNotification, ReportNotification},
+    alerts::template::render_template,
+    incidents::model::Incident,
+    probe::model::ProbeResponse,
+};
 use chrono::{DateTime, Utc};
 use lazy_static::lazy_static;
+use std::collections::HashMap;
 use tracing::{info, warn};
+use uuid::Uuid;
 
+use super::integrations::discord::{send_alert_discord, send_report_discord};
+use super::integrations::opsgenie::{
+    close_alert_opsgenie, send_alert_opsgenie, send_report_opsgenie,
+};
+use super::line_sink::write_alert_line;
 use super::model::{SlackBlock, SlackNotification, SlackTextBlock};
 
 const REQUEST_TIMEOUT_SECS: u64 = 10;
+// Number of characters of an unexpected channel response kept in errors
+const RESPONSE_EXCERPT_CHARS: usize = 200;
+const REDACTED: &str = "Redacted";
 
 lazy_static! {
     static ref CLIENT: reqwest::Client = reqwest::ClientBuilder::new()
@@ -18,49 +33,73 @@ lazy_static! {
         .unwrap();
 }
 
+// A failed run as its monitor reports it, `alert_if_failure` redacts it into an `AlertContext`
+pub struct RunFailure<'a> {
+    pub monitor_name: &'a str,
+    pub run_id: Option<Uuid>,
+    pub trace_id: &'a Option<String>,
+    pub timestamp: DateTime<Utc>,
+    pub error: Option<&'a str>,
+    // Appended to the error even when redacted, e.g. that the failure opened a circuit breaker
+    pub note: Option<&'a str>,
+    pub response: Option<&'a ProbeResponse>,
+}
+
 pub async fn alert_if_failure(
     success: bool,
-    error: Option<&str>,
-    probe_response: Option<&ProbeResponse>,
-    probe_name: &str,
-    failure_timestamp: DateTime<Utc>,
+    failure: RunFailure<'_>,
     alerts: &Option<Vec<ProbeAlert>>,
-    trace_id: &Option<String>,
-) -> Result<(), Vec<Box<dyn std::error::Error + Send>>> {
+    redact: bool,
+) -> Result<(), Vec<AlertError>> {
     if success {
         return Ok(());
     }
-    let error_message = error.unwrap_or("No error message");
-    let status_code = probe_response.map(|r| r.status_code);
-    let truncated_body = match probe_response {
-        Some(r) if !r.sensitive => Some(r.truncated_body(500)),
-        Some(_) => Some("Redacted".to_owned()),
-        None => None,
+    // Errors of failed expectations can quote the body, so redacted alerts only name the kind of
+    // failure. Every channel gets these fields, nothing downstream has to redact.
+    let error_message = if redact {
+        redacted_error_message(failure.response.is_some())
+    } else {
+        failure.error.unwrap_or("No error message").to_owned()
+    };
+    let error_message = match failure.note {
+        Some(note) => format!("{} ({})", error_message, note),
+        None => error_message,
+    };
+    let context = AlertContext {
+        monitor_name: failure.monitor_name.to_owned(),
+        run_id: failure.run_id,
+        trace_id: failure.trace_id.clone(),
+        failure_timestamp: failure.timestamp,
+        error_message,
+        status_code: failure.response.map(|r| r.status_code),
+        body: match failure.response {
+            Some(r) if !r.sensitive && !redact => Some(r.truncated_body(500)),
+            Some(_) => Some(REDACTED.to_owned()),
+            None => None,
+        },
     };
-    let log_body = truncated_body
+    let log_body = context
+        .body
         .as_ref()
         .unwrap_or(&"N/A".to_owned())
         .replace('\n', "\\n");
     warn!(
-        "Probe {probe_name} failed at {failure_timestamp} with trace ID {}. Status code: {}. Error: {error_message}. Body: {}",
-        trace_id.as_ref().unwrap_or(&"N/A".to_owned()),
-        status_code.map_or("N/A".to_owned(), |code| code.to_string()),
+        "Probe {} failed at {} with trace ID {} and run ID {}. Status code: {}. Error: {}. Body: {}",
+        context.monitor_name,
+        context.failure_timestamp,
+        context.trace_id.as_ref().unwrap_or(&"N/A".to_owned()),
+        context.run_id.map_or("N/A".to_owned(), |id| id.to_string()),
+        context.status_code.map_or("N/A".to_owned(), |code| code.to_string()),
+        context.error_message,
         log_body,
     );
     let mut errors = Vec::new();
     if let Some(alerts_vec) = alerts {
-        for alert in alerts_vec {
-            if let Err(e) = send_alert(
-                alert,
-                probe_name.to_owned(),
-                status_code,
-                truncated_body.as_deref(),
-                error_message,
-                failure_timestamp,
-                trace_id.clone(),
-            )
-            .await
-            {
+        for alert in alerts_vec
+            .iter()
+            .filter(|alert| alert.sends(AlertEvent::Failure))
+        {
+            if let Err(e) = send_alert(alert, &context).await {
                 errors.push(e);
             }
         }
@@ -73,11 +112,18 @@ pub async fn alert_if_failure(
     }
 }
 
+fn redacted_error_message(has_response: bool) -> String {
+    format!(
+        "{} failed, details are redacted for sensitive monitors",
+        error_kind(false, has_response).unwrap_or("request")
+    )
+}
+
 pub async fn send_generic_webhook(
     url: &String,
     body: String,
     content_type: &str,
-) -> Result<(), Box<dyn std::error::Error + Send>> {
+) -> Result<(), AlertErrorCause> {
     let request = CLIENT
         .post(url)
         .body(body)
@@ -86,55 +132,55 @@ pub async fn send_generic_webhook(
     let alert_response = request
         .timeout(Duration::from_secs(REQUEST_TIMEOUT_SECS))
         .send()
-        .await
-        .map_to_send_err()?;
+        .await?;
     info!(
         "Sent webhook alert. Response status code {}",
         alert_response.status().to_owned()
     );
 
-    Ok(())
+    check_alert_response(alert_response).await
 }
 
-pub async fn send_webhook_alert(
-    url: &String,
-    probe_name: String,
-    status_code: Option<u32>,
-    body: Option<&str>,
-    error_message: &str,
-    failure_timestamp: DateTime<Utc>,
-    trace_id: Option<String>,
-) -> Result<(), Box<dyn std::error::Error + Send>> {
-    let request_body = WebhookNotification {
-        message: "Probe failed.".to_owned(),
-        probe_name,
-        error_message: error_message.to_owned(),
-        failure_timestamp,
-        trace_id,
-        body: body.map(|s| s.to_owned()),
-        status_code,
-    };
+// Turns a non-success channel response into an error carrying the status and a body excerpt
+pub async fn check_alert_response(response: reqwest::Response) -> Result<(), AlertErrorCause> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(());
+    }
+    let response_excerpt = response
+        .text()
+        .await
+        .unwrap_or_default()
+        .chars()
+        .take(RESPONSE_EXCERPT_CHARS)
+        .collect();
+    Err(AlertErrorCause::HttpStatus {
+        status_code: status.as_u16(),
+        response_excerpt,
+    })
+}
 
-    let json = serde_json::to_string(&request_body).map_to_send_err()?;
-    send_generic_webhook(url, json, "application/json").await
+pub async fn send_webhook_alert(url: &String, context: &AlertContext) -> Result<(), AlertError> {
+    let to_alert_error =
+        |cause| AlertError::new(AlertChannel::Webhook, &context.monitor_name, cause);
+    let json =
+        serde_json::to_string(&context.notification()).map_err(|err| to_alert_error(err.into()))?;
+    send_generic_webhook(url, json, "application/json")
+        .await
+        .map_err(to_alert_error)
 }
 
 pub async fn send_slack_alert(
     webhook_url: &String,
-    probe_name: String,
-    status_code: Option<u32>,
-    body: Option<&str>,
-    error_message: &str,
-    failure_timestamp: DateTime<Utc>,
-    trace_id: Option<String>,
-) -> Result<(), Box<dyn std::error::Error + Send>> {
+    context: &AlertContext,
+) -> Result<(), AlertError> {
     // Uses Slack's Block Kit UI to make the message prettier
     let mut blocks = vec![
         SlackBlock {
             r#type: "header".to_owned(),
             text: Some(SlackTextBlock {
                 r#type: "plain_text".to_owned(),
-                text: format!("\"{}\" failed.", probe_name),
+                text: format!("\"{}\" failed.", context.monitor_name),
             }),
             elements: None,
         },
@@ -142,13 +188,13 @@ pub async fn send_slack_alert(
             r#type: "section".to_owned(),
             text: Some(SlackTextBlock {
                 r#type: "mrkdwn".to_owned(),
-                text: format!("Error message:\n\n> {}", error_message),
+                text: format!("Error message:\n\n> {}", context.error_message),
             }),
             elements: None,
         },
     ];
 
-    if let Some(code) = status_code {
+    if let Some(code) = context.status_code {
         blocks.push(SlackBlock {
             r#type: "section".to_owned(),
             elements: None,
@@ -159,7 +205,7 @@ pub async fn send_slack_alert(
         })
     }
 
-    if let Some(s) = body {
+    if let Some(s) = &context.body {
         blocks.push(SlackBlock {
             r#type: "section".to_owned(),
             elements: None,
@@ -175,66 +221,245 @@ pub async fn send_slack_alert(
         elements: Some(vec![
             SlackTextBlock {
                 r#type: "mrkdwn".to_owned(),
-                text: format!("Time: *{}*", failure_timestamp),
+                text: format!("Time: *{}*", context.failure_timestamp),
+            },
+            SlackTextBlock {
+                r#type: "mrkdwn".to_owned(),
+                text: format!(
+                    "Trace ID: *{}*",
+                    context.trace_id.as_deref().unwrap_or("N/A")
+                ),
             },
             SlackTextBlock {
                 r#type: "mrkdwn".to_owned(),
-                text: format!("Trace ID: *{}*", trace_id.unwrap_or("N/A".to_owned())),
+                text: format!(
+                    "Run ID: *{}*",
+                    context.run_id.map_or("N/A".to_owned(), |id| id.to_string())
+                ),
             },
         ]),
         text: None,
     });
+    let to_alert_error = |cause| AlertError::new(AlertChannel::Slack, &context.monitor_name, cause);
     let request_body = SlackNotification { blocks };
-    let json = serde_json::to_string(&request_body).map_to_send_err()?;
-    send_generic_webhook(webhook_url, json, "application/json").await
+    let json = serde_json::to_string(&request_body).map_err(|err| to_alert_error(err.into()))?;
+    send_generic_webhook(webhook_url, json, "application/json")
+        .await
+        .map_err(to_alert_error)
+}
+
+// Unless set with `type`, the channel is derived from the domain of the alert URL, anything
+// unknown is a generic webhook
+pub fn alert_channel(alert: &ProbeAlert) -> AlertChannel {
+    if let Some(channel) = alert.channel_type {
+        return channel;
+    }
+    match alert.url.split('/').nth(2).unwrap_or("") {
+        "hooks.slack.com" => AlertChannel::Slack,
+        "discord.com" => AlertChannel::Discord,
+        _ => AlertChannel::Webhook,
+    }
 }
 
-pub async fn send_alert(
+pub async fn send_alert(alert: &ProbeAlert, context: &AlertContext) -> Result<(), AlertError> {
+    match alert_channel(alert) {
+        AlertChannel::Slack => send_slack_alert(&alert.url, context).await,
+        AlertChannel::Discord => send_alert_discord(alert, context).await,
+        AlertChannel::Opsgenie => send_alert_opsgenie(alert, context).await,
+        AlertChannel::Webhook => send_webhook_alert(&alert.url, context).await,
+        channel @ (AlertChannel::File | AlertChannel::Stdout) => write_alert_line(
+            alert,
+            channel,
+            &context.monitor_name,
+            &context.notification(),
+        ),
+    }
+}
+
+// Sends a rendered summary report, the text is kept preformatted so tables stay aligned
+pub async fn send_report(
     alert: &ProbeAlert,
-    probe_name: String,
-    status_code: Option<u32>,
-    body: Option<&str>,
-    error_message: &str,
-    failure_timestamp: DateTime<Utc>,
-    trace_id: Option<String>,
-) -> Result<(), Box<dyn std::error::Error + Send>> {
-    let domain = alert.url.split('/').nth(2).unwrap_or("");
-    match domain {
-        "hooks.slack.com" => {
-            send_slack_alert(
-                &alert.url,
-                probe_name.clone(),
-                status_code,
-                body,
-                error_message,
-                failure_timestamp,
-                trace_id.clone(),
+    report_name: &str,
+    text: &str,
+) -> Result<(), AlertError> {
+    let channel = alert_channel(alert);
+    let to_alert_error = |cause| AlertError::new(channel, report_name, cause);
+    let json = match channel {
+        AlertChannel::Discord => return send_report_discord(alert, report_name, text).await,
+        AlertChannel::Opsgenie => return send_report_opsgenie(alert, report_name, text).await,
+        AlertChannel::Slack => serde_json::to_string(&SlackNotification {
+            blocks: vec![SlackBlock {
+                r#type: "section".to_owned(),
+                text: Some(SlackTextBlock {
+                    r#type: "mrkdwn".to_owned(),
+                    text: format!("```\n{}\n```", text),
+                }),
+                elements: None,
+            }],
+        }),
+        AlertChannel::Webhook => serde_json::to_string(&ReportNotification {
+            message: "Summary report.".to_owned(),
+            report_name: report_name.to_owned(),
+            report: text.to_owned(),
+        }),
+        AlertChannel::File | AlertChannel::Stdout => {
+            return write_alert_line(
+                alert,
+                channel,
+                report_name,
+                &ReportNotification {
+                    message: "Summary report.".to_owned(),
+                    report_name: report_name.to_owned(),
+                    report: text.to_owned(),
+                },
             )
-            .await
         }
-        _ => {
-            send_webhook_alert(
-                &alert.url,
-                probe_name.clone(),
-                status_code,
-                body,
-                error_message,
-                failure_timestamp,
-                trace_id.clone(),
+    }
+    .map_err(|err| to_alert_error(err.into()))?;
+    send_generic_webhook(&alert.url, json, "application/json")
+        .await
+        .map_err(to_alert_error)
+}
+
+// Placeholders: monitor, incident.id, incident.duration, incident.failures, incident.started,
+// incident.first_error. The error is replaced by `Redacted` when `redact` is set.
+pub fn render_recovery(
+    template: &str,
+    incident: &Incident,
+    redact: bool,
+) -> Result<String, TemplateError> {
+    let duration = Duration::from_secs(incident.duration_seconds.max(0) as u64);
+    let values = HashMap::from([
+        ("monitor", incident.monitor.clone()),
+        ("incident.id", incident.id.to_string()),
+        (
+            "incident.duration",
+            humantime::format_duration(duration).to_string(),
+        ),
+        ("incident.failures", incident.failures.to_string()),
+        ("incident.started", incident.started.to_rfc3339()),
+        (
+            "incident.first_error",
+            match &incident.first_error {
+                Some(_) if redact => REDACTED.to_owned(),
+                first_error => first_error.clone().unwrap_or_default(),
+            },
+        ),
+    ]);
+    render_template(template, &values)
+}
+
+// Sends the `recovery_template` of every alert that has one, once the incident of a monitor closed.
+// Opsgenie alerts are closed either way, with the rendered template as the note.
+pub async fn alert_on_recovery(
+    incident: &Incident,
+    alerts: &Option<Vec<ProbeAlert>>,
+    redact: bool,
+) -> Result<(), Vec<AlertError>> {
+    let mut errors = Vec::new();
+    for alert in alerts.iter().flatten() {
+        if !alert.sends(AlertEvent::Recovery) {
+            continue;
+        }
+        let text = match alert
+            .recovery_template
+            .as_ref()
+            .map(|template| render_recovery(template, incident, redact))
+            .transpose()
+        {
+            Ok(text) => text,
+            Err(e) => {
+                errors.push(AlertError::new(
+                    alert_channel(alert),
+                    &incident.monitor,
+                    e.into(),
+                ));
+                continue;
+            }
+        };
+        let sent = match &text {
+            Some(text) => send_recovery(alert, incident, text).await,
+            None if alert_channel(alert) == AlertChannel::Opsgenie => {
+                close_alert_opsgenie(alert, &incident.monitor, None).await
+            }
+            None => continue,
+        };
+        if let Err(e) = sent {
+            errors.push(e);
+        }
+    }
+
+    if !errors.is_empty() {
+        Err(errors)
+    } else {
+        Ok(())
+    }
+}
+
+async fn send_recovery(
+    alert: &ProbeAlert,
+    incident: &Incident,
+    text: &str,
+) -> Result<(), AlertError> {
+    let channel = alert_channel(alert);
+    let to_alert_error = |cause| AlertError::new(channel, &incident.monitor, cause);
+    let json = match channel {
+        AlertChannel::Opsgenie => {
+            return close_alert_opsgenie(alert, &incident.monitor, Some(text)).await
+        }
+        AlertChannel::Discord => serde_json::to_string(&serde_json::json!({ "content": text })),
+        AlertChannel::Slack => serde_json::to_string(&SlackNotification {
+            blocks: vec![SlackBlock {
+                r#type: "section".to_owned(),
+                text: Some(SlackTextBlock {
+                    r#type: "mrkdwn".to_owned(),
+                    text: text.to_owned(),
+                }),
+                elements: None,
+            }],
+        }),
+        AlertChannel::Webhook => serde_json::to_string(&RecoveryNotification {
+            message: text.to_owned(),
+            probe_name: incident.monitor.clone(),
+            incident_id: incident.id,
+            duration_seconds: incident.duration_seconds,
+        }),
+        AlertChannel::File | AlertChannel::Stdout => {
+            return write_alert_line(
+                alert,
+                channel,
+                &incident.monitor,
+                &RecoveryNotification {
+                    message: text.to_owned(),
+                    probe_name: incident.monitor.clone(),
+                    incident_id: incident.id,
+                    duration_seconds: incident.duration_seconds,
+                },
             )
-            .await
         }
     }
+    .map_err(|err| to_alert_error(err.into()))?;
+    info!("Sending recovery alert for {}", incident.monitor);
+    send_generic_webhook(&alert.url, json, "application/json")
+        .await
+        .map_err(to_alert_error)
 }
 
 #[cfg(test)]
 mod webhook_tests {
 
-    use crate::alerts::outbound_webhook::alert_if_failure;
-    use crate::probe::model::ProbeAlert;
+    use std::time::Duration;
+
+    use crate::alerts::outbound_webhook::{
+        alert_if_failure, alert_on_recovery, render_recovery, send_generic_webhook, RunFailure,
+    };
+    use crate::errors::{AlertChannel, AlertErrorCause};
+    use crate::incidents::model::Incident;
+    use crate::probe::model::{ProbeAlert, ProbeResponse};
 
     use chrono::Utc;
-    use wiremock::matchers::{method, path};
+    use uuid::Uuid;
+    use wiremock::matchers::{body_partial_json, method, path};
     use wiremock::{Mock, MockServer, ResponseTemplate};
 
     #[tokio::test]
@@ -253,20 +478,277 @@ mod webhook_tests {
         let probe_name = "Some Flow".to_owned();
         let alerts = Some(vec![ProbeAlert {
             url: format!("{}{}", mock_server.uri(), alert_url.to_owned()),
+            ..Default::default()
         }]);
         let failure_timestamp = Utc::now();
 
         let alert_result = alert_if_failure(
             false,
-            Some("Test error"),
-            None,
-            &probe_name,
-            failure_timestamp,
+            RunFailure {
+                monitor_name: &probe_name,
+                run_id: None,
+                trace_id: &None,
+                timestamp: failure_timestamp,
+                error: Some("Test error"),
+                note: None,
+                response: None,
+            },
+            &alerts,
+            false,
+        )
+        .await;
+
+        assert!(alert_result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_webhook_alert_includes_run_id() {
+        let mock_server = MockServer::start().await;
+        let run_id = Uuid::new_v4();
+
+        Mock::given(method("POST"))
+            .and(path("/alert-test"))
+            .and(body_partial_json(
+                serde_json::json!({ "run_id": run_id.to_string() }),
+            ))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let alerts = Some(vec![ProbeAlert {
+            url: format!("{}/alert-test", mock_server.uri()),
+            ..Default::default()
+        }]);
+
+        let alert_result = alert_if_failure(
+            false,
+            RunFailure {
+                monitor_name: "Some Flow",
+                run_id: Some(run_id),
+                trace_id: &None,
+                timestamp: Utc::now(),
+                error: Some("Test error"),
+                note: None,
+                response: None,
+            },
             &alerts,
-            &None,
+            false,
         )
         .await;
 
         assert!(alert_result.is_ok());
     }
+
+    #[tokio::test]
+    async fn test_rejected_alert_returns_structured_error() {
+        let mock_server = MockServer::start().await;
+
+        let alert_url = "/deleted-webhook";
+
+        Mock::given(method("POST"))
+            .and(path(alert_url))
+            .respond_with(ResponseTemplate::new(404).set_body_string("no_such_hook"))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let alerts = Some(vec![ProbeAlert {
+            url: format!("{}{}", mock_server.uri(), alert_url.to_owned()),
+            ..Default::default()
+        }]);
+
+        let errors = alert_if_failure(
+            false,
+            RunFailure {
+                monitor_name: "Some Flow",
+                run_id: None,
+                trace_id: &None,
+                timestamp: Utc::now(),
+                error: Some("Test error"),
+                note: None,
+                response: None,
+            },
+            &alerts,
+            false,
+        )
+        .await
+        .unwrap_err();
+
+        assert_eq!(1, errors.len());
+        let error = &errors[0];
+        assert_eq!(AlertChannel::Webhook, error.channel);
+        assert_eq!("Some Flow", error.monitor_name);
+        assert_eq!("http_status", error.kind());
+        assert_eq!(Some(404), error.status_code());
+        assert!(error.to_string().contains("no_such_hook"));
+    }
+
+    #[tokio::test]
+    async fn test_alert_timeout_is_classified() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .and(path("/slow"))
+            .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(11)))
+            .mount(&mock_server)
+            .await;
+
+        let cause = send_generic_webhook(
+            &format!("{}/slow", mock_server.uri()),
+            "{}".to_owned(),
+            "application/json",
+        )
+        .await
+        .unwrap_err();
+
+        assert!(matches!(cause, AlertErrorCause::Timeout));
+    }
+
+    const CUSTOMER_DATA: &str = "jane@example.com";
+
+    // A failed expectation quoting the body, as evaluated by `validate_response`
+    async fn alert_expectation_failure(alerts: &Option<Vec<ProbeAlert>>, redact: bool) {
+        let response = ProbeResponse {
+            timestamp_received: Utc::now(),
+            status_code: 200,
+            body: format!(r#"{{"customer":"{}"}}"#, CUSTOMER_DATA),
+            sensitive: false,
+        };
+        let error = format!("body did not contain 'ok', got: {}", response.body);
+
+        alert_if_failure(
+            false,
+            RunFailure {
+                monitor_name: "Checkout",
+                run_id: None,
+                trace_id: &None,
+                timestamp: Utc::now(),
+                error: Some(&error),
+                note: None,
+                response: Some(&response),
+            },
+            alerts,
+            redact,
+        )
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_redacted_alerts_leave_out_details_on_every_channel() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(3)
+            .mount(&mock_server)
+            .await;
+        let file = std::env::temp_dir().join(format!("xbp-alerts-{}.jsonl", Uuid::new_v4()));
+        let alerts = Some(vec![
+            ProbeAlert {
+                url: format!("{}/webhook", mock_server.uri()),
+                ..Default::default()
+            },
+            ProbeAlert {
+                url: format!("{}/slack", mock_server.uri()),
+                channel_type: Some(AlertChannel::Slack),
+                ..Default::default()
+            },
+            ProbeAlert {
+                url: format!("{}/discord", mock_server.uri()),
+                channel_type: Some(AlertChannel::Discord),
+                ..Default::default()
+            },
+            ProbeAlert {
+                channel_type: Some(AlertChannel::File),
+                path: Some(file.clone()),
+                ..Default::default()
+            },
+        ]);
+
+        alert_expectation_failure(&alerts, true).await;
+
+        let mut payloads: Vec<String> = mock_server
+            .received_requests()
+            .await
+            .unwrap()
+            .iter()
+            .map(|request| String::from_utf8_lossy(&request.body).into_owned())
+            .collect();
+        payloads.push(std::fs::read_to_string(&file).unwrap());
+        std::fs::remove_file(&file).unwrap();
+        assert_eq!(4, payloads.len());
+        for payload in &payloads {
+            assert!(!payload.contains(CUSTOMER_DATA), "{}", payload);
+        }
+        assert!(payloads[0].contains("expectation failed, details are redacted"));
+    }
+
+    #[tokio::test]
+    async fn test_alerts_include_details_when_not_redacted() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        let alerts = Some(vec![ProbeAlert {
+            url: format!("{}/webhook", mock_server.uri()),
+            ..Default::default()
+        }]);
+
+        alert_expectation_failure(&alerts, false).await;
+
+        let requests = mock_server.received_requests().await.unwrap();
+        assert!(String::from_utf8_lossy(&requests[0].body).contains(CUSTOMER_DATA));
+    }
+
+    #[tokio::test]
+    async fn test_unknown_recovery_placeholder_fails_the_alert() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0)
+            .mount(&mock_server)
+            .await;
+        let alerts = Some(vec![ProbeAlert {
+            url: mock_server.uri(),
+            recovery_template: Some(
+                "{{ monitor }} recovered after {{ incident.duraton }}".to_owned(),
+            ),
+            ..Default::default()
+        }]);
+
+        let errors = alert_on_recovery(&Incident::open("Checkout", Utc::now()), &alerts, false)
+            .await
+            .unwrap_err();
+
+        assert_eq!(1, errors.len());
+        assert_eq!("template", errors[0].kind());
+        assert_eq!(
+            "Failed to send webhook alert for 'Checkout': could not render template: unknown placeholder 'incident.duraton'",
+            errors[0].to_string()
+        );
+    }
+
+    #[test]
+    fn test_recovery_template_gets_redacted_error() {
+        let mut incident = Incident::open("Checkout", Utc::now());
+        incident.record_run(false, Some(CUSTOMER_DATA), Utc::now());
+
+        assert_eq!(
+            "Checkout recovered after: Redacted",
+            render_recovery(
+                "{{monitor}} recovered after: {{incident.first_error}}",
+                &incident,
+                true
+            )
+            .unwrap()
+        );
+        assert!(
+            render_recovery("{{incident.first_error}}", &incident, false)
+                .unwrap()
+                .contains(CUSTOMER_DATA)
+        );
+    }
 }
@@ -0,0 +1,53 @@
+use std::collections::HashMap;
+
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::errors::TemplateError;
+
+lazy_static! {
+    static ref PLACEHOLDER_REGEX: Regex = Regex::new(r"\{\{\s*([a-z_.]+)\s*\}\}").unwrap();
+}
+
+// Replaces `{{ name }}` placeholders with their values. An unknown placeholder, e.g. a typo, fails
+// the rendering rather than being sent as it is.
+pub fn render_template(
+    template: &str,
+    values: &HashMap<&str, String>,
+) -> Result<String, TemplateError> {
+    if let Some(caps) = PLACEHOLDER_REGEX
+        .captures_iter(template)
+        .find(|caps| !values.contains_key(&caps[1]))
+    {
+        return Err(TemplateError {
+            placeholder: caps[1].to_owned(),
+        });
+    }
+    Ok(PLACEHOLDER_REGEX
+        .replace_all(template, |caps: &regex::Captures| values[&caps[1]].clone())
+        .to_string())
+}
+
+#[cfg(test)]
+mod template_tests {
+    use std::collections::HashMap;
+
+    use crate::alerts::template::render_template;
+    use crate::errors::TemplateError;
+
+    #[test]
+    fn test_render_template() {
+        let values = HashMap::from([("name", "checkout".to_owned())]);
+
+        assert_eq!(
+            "checkout is down, checkout",
+            render_template("{{name}} is down, {{ name }}", &values).unwrap()
+        );
+        assert_eq!(
+            Err(TemplateError {
+                placeholder: "unknown".to_owned()
+            }),
+            render_template("{{name}} is down, {{ unknown }}", &values)
+        );
+    }
+}
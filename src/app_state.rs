@@ -1,54 +1,1733 @@
-use std::sync::RwLockWriteGuard;
-use std::{collections::HashMap, sync::RwLock};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::RwLock,
+};
+
+use chrono::{DateTime, Utc};
+use futures::future::join_all;
+use opentelemetry::KeyValue;
+use tokio::sync::{Notify, Semaphore};
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+use uuid::Uuid;
 
 use crate::{
-    config::Config,
+    audit::AuditLog,
+    config::{save_runtime_monitors, Config},
+    errors::{ConfigValidationError, RuntimeMonitorError},
+    incidents::model::{Incident, IncidentAck},
     otel::metrics::Metrics,
-    probe::model::{ProbeResult, StoryResult},
+    probe::circuit_breaker::{CircuitBreaker, CircuitState},
+    probe::model::{Probe, ProbeResult, Story, StoryResult},
+    probe::rate_limit::{backoff, Deferral},
+    probe::schedule::{schedule_probes, schedule_stories},
+    reports::schedule::schedule_reports,
+    result_store::{MemoryBudget, MonitorActivity, ResultStore},
+    self_alerts::{raise, run_watchdog, SelfAlertKind, SelfMonitor},
+    status_summary::StatusSummarizer,
+    storage::{MemoryStorage, ResultSink, StorageQueue, StorageRecord},
 };
 
-// Limits the number of results we store per probe. Once we go over this amount we remove the earliest.
+// How long a reload waits for the stopped monitoring tasks before starting the new ones
+const RELOAD_STOP_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Limits the number of results we store per monitor. Once we go over this amount we remove the earliest.
 const PROBE_RESULT_LIMIT: usize = 100;
 
+// Number of consecutive successful runs needed before a failing monitor is reported as OK again.
+pub const DEFAULT_RECOVERY_THRESHOLD: u32 = 1;
+
+// Alerting status of a monitor, derived from its run streaks rather than only the latest result.
+#[derive(Debug, Clone, Default)]
+pub struct MonitorState {
+    pub failing: bool,
+    pub consecutive_failures: u32,
+    pub consecutive_successes: u32,
+    pub recovery_threshold: u32,
+    // Set while the monitor has an open incident
+    pub open_incident: Option<Uuid>,
+}
+
+impl MonitorState {
+    // A monitor is pending recovery when it is still failing but has started succeeding again.
+    pub fn pending_recovery(&self) -> bool {
+        self.failing && self.consecutive_successes > 0
+    }
+}
+
+// Monitor names that appeared or disappeared with a reload, and the groups they touched
+#[derive(Debug, Clone, Default)]
+pub struct ConfigDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    // Groups of the monitors that were added, removed or changed, before and after the reload
+    pub groups: Vec<String>,
+}
+
+// Start and end of a reload, `finished` is unset while the reload is in progress
+#[derive(Debug, Clone, Copy)]
+pub struct ReloadWindow {
+    pub started: DateTime<Utc>,
+    pub finished: Option<DateTime<Utc>>,
+}
+
 pub struct AppState {
-    pub probe_results: RwLock<HashMap<String, Vec<ProbeResult>>>,
-    pub story_results: RwLock<HashMap<String, Vec<StoryResult>>>,
-    pub config: Config,
+    pub probe_results: Arc<ResultStore<ProbeResult>>,
+    pub story_results: Arc<ResultStore<StoryResult>>,
+    // What the history endpoints read, the stores above unless `settings.storage` names a database
+    pub storage: Arc<dyn ResultSink>,
+    // Writes every stored result to `storage` when it is a database
+    storage_queue: Option<StorageQueue>,
+    // `settings.max_result_memory_mb`, shared by both result stores
+    result_budget: Arc<MemoryBudget>,
+    pub monitor_states: RwLock<HashMap<String, MonitorState>>,
+    // Incidents per monitor, oldest first. Closed incidents are kept for `settings.incident_retention`.
+    pub incidents: RwLock<HashMap<String, Vec<Incident>>>,
+    // Probes backing off after being rate limited, see `with.respect_retry_after`
+    pub deferrals: RwLock<HashMap<String, Deferral>>,
+    // Breakers of probes with a `circuit_breaker`, kept across reloads that leave the probe unchanged
+    pub circuit_breakers: RwLock<HashMap<String, CircuitBreaker>>,
+    // Monitors in scope of each report at its last scheduled run, to list added and removed monitors
+    pub report_baselines: RwLock<HashMap<String, BTreeSet<String>>>,
+    // Swapped as a whole on reload, readers can hold on to a snapshot without keeping the lock
+    pub config: RwLock<Arc<Config>>,
+    // Runtime overrides of the `enabled` of probes by name, they take precedence over the config
+    pub runtime_enabled: RwLock<HashMap<String, bool>>,
+    // The file the config was loaded from, reloads read it again
+    pub config_path: Option<PathBuf>,
     pub metrics: Metrics,
+    // Identifies this process in the `X-XBP-Instance-Id` response header
+    pub instance_id: Uuid,
+    // Uptime in `/-/info` counts from here
+    pub started_at: DateTime<Utc>,
+    // The same moment on the monotonic clock, `/-/about` uptime is unaffected by clock changes
+    pub started: Instant,
+    // Number of completed reloads, sent as the `X-XBP-Config-Version` response header
+    pub config_version: AtomicU64,
+    // The latest reload, runs overlapping it are marked `during_reload`
+    pub reload_window: RwLock<Option<ReloadWindow>>,
+    // Served by `/status`, recomputed in the background as results come in
+    pub status_summary: StatusSummarizer,
+    // Sampled records of outbound requests, see `settings.audit`
+    pub audit: AuditLog,
+    // Woken after every stored probe or story result. Waiters must register with `notified()`
+    // before reading the results, `notify_waiters` doesn't wake later ones.
+    pub new_result_notify: Notify,
+    // Problems of xbp itself, served by `/-/timeline`, see `self_alerts`
+    pub self_monitor: SelfMonitor,
+    status_summary_task: Mutex<Option<JoinHandle<()>>>,
+    watchdog_task: Mutex<Option<JoinHandle<()>>>,
+    // Scheduling task of every probe and story by name, configured or added at runtime, so that a
+    // single one can be stopped or restarted
+    monitor_tasks: RwLock<HashMap<String, JoinHandle<()>>>,
+    report_tasks: Mutex<Vec<JoinHandle<()>>>,
+    // Serializes writes of `xbp.runtime.yaml`
+    runtime_monitors_file: tokio::sync::Mutex<()>,
+    // Limits concurrent runs per probe name, created on first use with the probe's `max_in_flight`
+    in_flight: Mutex<HashMap<String, Arc<Semaphore>>>,
 }
 
 impl AppState {
     pub fn new(config: Config) -> AppState {
+        let metrics = Metrics::with_duration_unit(config.settings.metrics.duration_unit);
+        AppState::with_metrics(config, metrics)
+    }
+
+    pub fn with_metrics(config: Config, metrics: Metrics) -> AppState {
+        let result_budget = Arc::new(MemoryBudget::default());
+        result_budget.set_limit_mb(config.settings.max_result_memory_mb);
+        let probe_results = Arc::new(ResultStore::new(PROBE_RESULT_LIMIT, result_budget.clone()));
+        let story_results = Arc::new(ResultStore::new(PROBE_RESULT_LIMIT, result_budget.clone()));
         AppState {
-            probe_results: RwLock::new(HashMap::new()),
-            story_results: RwLock::new(HashMap::new()),
-            config,
-            metrics: Metrics::new(),
+            storage: Arc::new(MemoryStorage::new(
+                probe_results.clone(),
+                story_results.clone(),
+            )),
+            storage_queue: None,
+            probe_results,
+            story_results,
+            result_budget,
+            monitor_states: RwLock::new(HashMap::new()),
+            incidents: RwLock::new(HashMap::new()),
+            deferrals: RwLock::new(HashMap::new()),
+            circuit_breakers: RwLock::new(HashMap::new()),
+            report_baselines: RwLock::new(HashMap::new()),
+            config: RwLock::new(Arc::new(config)),
+            runtime_enabled: RwLock::new(HashMap::new()),
+            config_path: None,
+            metrics,
+            instance_id: Uuid::new_v4(),
+            started_at: Utc::now(),
+            started: Instant::now(),
+            config_version: AtomicU64::new(0),
+            reload_window: RwLock::new(None),
+            status_summary: StatusSummarizer::default(),
+            audit: AuditLog::default(),
+            new_result_notify: Notify::new(),
+            self_monitor: SelfMonitor::default(),
+            status_summary_task: Mutex::new(None),
+            watchdog_task: Mutex::new(None),
+            monitor_tasks: RwLock::new(HashMap::new()),
+            report_tasks: Mutex::new(vec![]),
+            runtime_monitors_file: tokio::sync::Mutex::new(()),
+            in_flight: Mutex::new(HashMap::new()),
         }
     }
 
-    pub fn add_probe_result(&self, probe_name: String, result: ProbeResult) {
-        let mut write_lock: RwLockWriteGuard<'_, HashMap<String, Vec<_>>> =
-            self.probe_results.write().unwrap();
+    pub fn with_config_path<P: Into<PathBuf>>(mut self, path: P) -> AppState {
+        self.config_path = Some(path.into());
+        self
+    }
+
+    // Also writes every stored result to `storage`, from a task of the current Tokio runtime
+    pub fn with_storage(mut self, storage: Arc<dyn ResultSink>) -> AppState {
+        if storage.durable() {
+            self.storage_queue = Some(StorageQueue::start(
+                storage.clone(),
+                &self.config().settings.storage,
+                self.metrics.storage_records_dropped.clone(),
+            ));
+        }
+        self.storage = storage;
+        self
+    }
+
+    // The current config, unaffected by reloads and runtime monitor changes made after the call
+    pub fn config(&self) -> Arc<Config> {
+        self.config.read().unwrap().clone()
+    }
+
+    pub fn in_flight_semaphore(&self, probe_name: &str, max_in_flight: u32) -> Arc<Semaphore> {
+        self.in_flight
+            .lock()
+            .unwrap()
+            .entry(probe_name.to_owned())
+            .or_insert_with(|| Arc::new(Semaphore::new(max_in_flight.max(1) as usize)))
+            .clone()
+    }
+
+    // Schedules every probe, story and report of the current config. Reloads go through here too.
+    pub fn start_monitoring(self: &Arc<Self>) {
+        let config = self.config();
+        self.record_configured_monitors(&config);
+        self.status_summary_task
+            .lock()
+            .unwrap()
+            .get_or_insert_with(|| tokio::spawn(StatusSummarizer::run(self.clone())));
+        self.watchdog_task
+            .lock()
+            .unwrap()
+            .get_or_insert_with(|| tokio::spawn(run_watchdog(self.clone())));
+        let (runtime_probes, probes): (Vec<Probe>, Vec<Probe>) = config
+            .probes
+            .iter()
+            .cloned()
+            .partition(|probe| probe.runtime_added);
+        let (runtime_stories, stories): (Vec<Story>, Vec<Story>) = config
+            .stories
+            .iter()
+            .cloned()
+            .partition(|story| story.runtime_added);
+        let mut tasks = schedule_probes(&probes, self.clone());
+        tasks.extend(schedule_stories(&stories, self.clone()));
+        self.monitor_tasks.write().unwrap().extend(tasks);
+        self.report_tasks
+            .lock()
+            .unwrap()
+            .extend(schedule_reports(&config.reports, self.clone()));
+        for probe in runtime_probes {
+            self.start_runtime_probe(probe);
+        }
+        for story in runtime_stories {
+            self.start_runtime_story(story);
+        }
+    }
+
+    fn record_configured_monitors(&self, config: &Config) {
+        self.metrics
+            .configured_probes
+            .record(config.probes.len() as u64, &[]);
+        self.metrics
+            .configured_stories
+            .record(config.stories.len() as u64, &[]);
+    }
+
+    fn start_runtime_probe(self: &Arc<Self>, probe: Probe) {
+        let tasks = schedule_probes(std::slice::from_ref(&probe), self.clone());
+        self.monitor_tasks.write().unwrap().extend(tasks);
+    }
+
+    fn start_runtime_story(self: &Arc<Self>, story: Story) {
+        let tasks = schedule_stories(std::slice::from_ref(&story), self.clone());
+        self.monitor_tasks.write().unwrap().extend(tasks);
+    }
+
+    // Adds a probe to the running config and starts monitoring it right away. Its expectations
+    // must be a plain list, the API resolves `use` first with `Config::resolve_runtime_probe`.
+    pub fn add_runtime_probe(
+        self: &Arc<Self>,
+        mut probe: Probe,
+    ) -> Result<(), RuntimeMonitorError> {
+        probe.runtime_added = true;
+        {
+            let mut current = self.config.write().unwrap();
+            // Only copies the config while a snapshot of it is held elsewhere
+            let config = Arc::make_mut(&mut current);
+            if config.has_monitor(&probe.name) {
+                return Err(RuntimeMonitorError::NameTaken(probe.name));
+            }
+            Config {
+                settings: config.settings.clone(),
+                alert_channels: config.alert_channels.clone(),
+                probes: vec![probe.clone()],
+                ..Default::default()
+            }
+            .validate()
+            .map_err(RuntimeMonitorError::Invalid)?;
+            config.probes.push(probe.clone());
+            self.record_configured_monitors(config);
+        }
+        info!("Added probe '{}' at runtime", probe.name);
+        self.start_runtime_probe(probe);
+        Ok(())
+    }
 
-        let results = write_lock.entry(probe_name).or_default();
-        results.push(result);
+    // Adds a story to the running config and starts monitoring it right away
+    pub fn add_runtime_story(
+        self: &Arc<Self>,
+        mut story: Story,
+    ) -> Result<(), RuntimeMonitorError> {
+        story.runtime_added = true;
+        {
+            let mut current = self.config.write().unwrap();
+            let config = Arc::make_mut(&mut current);
+            if config.has_monitor(&story.name) {
+                return Err(RuntimeMonitorError::NameTaken(story.name));
+            }
+            Config {
+                settings: config.settings.clone(),
+                alert_channels: config.alert_channels.clone(),
+                stories: vec![story.clone()],
+                ..Default::default()
+            }
+            .validate()
+            .map_err(RuntimeMonitorError::Invalid)?;
+            config.stories.push(story.clone());
+            self.record_configured_monitors(config);
+        }
+        info!("Added story '{}' at runtime", story.name);
+        self.start_runtime_story(story);
+        Ok(())
+    }
+
+    // The runtime override when there is one, the probe's `enabled` otherwise
+    pub fn probe_enabled(&self, probe: &Probe) -> bool {
+        self.runtime_enabled
+            .read()
+            .unwrap()
+            .get(&probe.name)
+            .copied()
+            .unwrap_or(probe.enabled.unwrap_or(true))
+    }
+
+    // Overrides the `enabled` of a configured or runtime-added probe until a reload clears it
+    pub fn set_probe_enabled(&self, name: &str, enabled: bool) -> Result<(), RuntimeMonitorError> {
+        if !self.config().probes.iter().any(|probe| probe.name == name) {
+            return Err(RuntimeMonitorError::NotFound(name.to_owned()));
+        }
+        self.runtime_enabled
+            .write()
+            .unwrap()
+            .insert(name.to_owned(), enabled);
+        info!(
+            "Probe '{}' {} at runtime",
+            name,
+            if enabled { "enabled" } else { "disabled" }
+        );
+        Ok(())
+    }
 
-        // Ensure only the latest 100 elements are kept
-        while results.len() > PROBE_RESULT_LIMIT {
-            results.remove(0);
+    // Stops a runtime-added probe and drops its history. Probes from the config file can't be removed.
+    pub fn remove_runtime_probe(&self, name: &str) -> Result<(), RuntimeMonitorError> {
+        let (expanded, url_results) = {
+            let mut current = self.config.write().unwrap();
+            let config = Arc::make_mut(&mut current);
+            let index = config
+                .probes
+                .iter()
+                .position(|probe| probe.name == name)
+                .ok_or_else(|| RuntimeMonitorError::NotFound(name.to_owned()))?;
+            if !config.probes[index].runtime_added {
+                return Err(RuntimeMonitorError::Configured(name.to_owned()));
+            }
+            let removed = config.probes.remove(index);
+            // Probes it expanded into go with it
+            let expanded: Vec<String> = config
+                .probes
+                .iter()
+                .filter(|probe| probe.expanded_from.as_deref() == Some(name))
+                .map(|probe| probe.name.clone())
+                .collect();
+            config
+                .probes
+                .retain(|probe| probe.expanded_from.as_deref() != Some(name));
+            self.record_configured_monitors(config);
+            (expanded, removed.url_result_names())
+        };
+        for expanded_name in &expanded {
+            self.stop_runtime_monitor(expanded_name);
         }
+        self.prune_results(&url_results);
+        self.stop_runtime_monitor(name);
+        info!("Removed runtime-added probe '{}'", name);
+        Ok(())
+    }
+
+    // Stops a runtime-added story and drops its history. Stories from the config file can't be removed.
+    pub fn remove_runtime_story(&self, name: &str) -> Result<(), RuntimeMonitorError> {
+        {
+            let mut current = self.config.write().unwrap();
+            let config = Arc::make_mut(&mut current);
+            let index = config
+                .stories
+                .iter()
+                .position(|story| story.name == name)
+                .ok_or_else(|| RuntimeMonitorError::NotFound(name.to_owned()))?;
+            if !config.stories[index].runtime_added {
+                return Err(RuntimeMonitorError::Configured(name.to_owned()));
+            }
+            config.stories.remove(index);
+            self.record_configured_monitors(config);
+        }
+        self.stop_runtime_monitor(name);
+        info!("Removed runtime-added story '{}'", name);
+        Ok(())
+    }
+
+    // Registers a probe for every name a meta-probe's response listed and drops the expanded probes
+    // of earlier runs that it no longer lists. Names already taken by another monitor are skipped.
+    pub fn sync_expanded_probes(self: &Arc<Self>, meta_probe: &Probe, names: &[String]) {
+        let (added, removed) = {
+            let mut current = self.config.write().unwrap();
+            // The meta-probe may have been removed by a reload while it ran
+            if !current
+                .probes
+                .iter()
+                .any(|probe| probe.name == meta_probe.name)
+            {
+                return;
+            }
+            let wanted: Vec<Probe> = names.iter().map(|name| meta_probe.expand(name)).collect();
+            let config = Arc::make_mut(&mut current);
+            let is_stale = |probe: &Probe| {
+                probe.expanded_from.as_deref() == Some(meta_probe.name.as_str())
+                    && !wanted.iter().any(|wanted| wanted.name == probe.name)
+            };
+            let removed: Vec<String> = config
+                .probes
+                .iter()
+                .filter(|probe| is_stale(probe))
+                .map(|probe| probe.name.clone())
+                .collect();
+            config.probes.retain(|probe| !is_stale(probe));
+            let mut added = vec![];
+            for probe in wanted {
+                if config.has_monitor(&probe.name) {
+                    let expanded = config.probes.iter().any(|existing| {
+                        existing.name == probe.name && existing.expanded_from == probe.expanded_from
+                    });
+                    if !expanded {
+                        warn!(
+                            "Not expanding '{}' from '{}', the name is taken",
+                            probe.name, meta_probe.name
+                        );
+                    }
+                    continue;
+                }
+                config.probes.push(probe.clone());
+                added.push(probe);
+            }
+            self.record_configured_monitors(config);
+            (added, removed)
+        };
+        for name in &removed {
+            self.stop_runtime_monitor(name);
+        }
+        if !added.is_empty() || !removed.is_empty() {
+            info!(
+                "Expanded '{}', added {:?}, removed {:?}",
+                meta_probe.name,
+                added.iter().map(|probe| &probe.name).collect::<Vec<_>>(),
+                removed
+            );
+        }
+        for probe in added {
+            self.start_runtime_probe(probe);
+        }
+    }
+
+    fn stop_runtime_monitor(&self, name: &str) {
+        if let Some(task) = self.monitor_tasks.write().unwrap().remove(name) {
+            task.abort();
+        }
+        self.in_flight.lock().unwrap().remove(name);
+        self.prune_results(&[name.to_owned()]);
+    }
+
+    // Writes the runtime-added monitors to `xbp.runtime.yaml` when `settings.persist_runtime_monitors`
+    // is set. Without a config file there is nowhere to put them.
+    pub async fn persist_runtime_monitors(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let Some(config_path) = &self.config_path else {
+            return Ok(());
+        };
+        // The snapshot is taken under the lock, so the last write always has the latest monitors
+        let _file = self.runtime_monitors_file.lock().await;
+        let config = self.config();
+        if !config.settings.persist_runtime_monitors {
+            return Ok(());
+        }
+        let saved = save_runtime_monitors(&config, config_path).await;
+        if let Err(e) = &saved {
+            raise(
+                self,
+                SelfAlertKind::StorageWrite,
+                format!("Can't write the runtime-added monitors: {}", e),
+            );
+        }
+        saved
+    }
+
+    // Aborts all monitoring tasks without waiting for them, for synchronous contexts
+    pub fn stop_monitoring(&self) {
+        for (_, task) in self.monitor_tasks.write().unwrap().drain() {
+            task.abort();
+        }
+        for task in self.report_tasks.lock().unwrap().drain(..) {
+            task.abort();
+        }
+    }
+
+    // Aborts all monitoring tasks and waits up to `timeout` for each of them to finish,
+    // so that no run is still in flight once this returns
+    pub async fn stop_monitoring_graceful(&self, timeout: Duration) {
+        let mut tasks: Vec<JoinHandle<()>> = self
+            .monitor_tasks
+            .write()
+            .unwrap()
+            .drain()
+            .map(|(_, task)| task)
+            .collect();
+        tasks.extend(self.report_tasks.lock().unwrap().drain(..));
+        for task in &tasks {
+            task.abort();
+        }
+        let total = tasks.len();
+        let results = join_all(
+            tasks
+                .into_iter()
+                .map(|task| tokio::time::timeout(timeout, task)),
+        )
+        .await;
+        let unfinished = results.iter().filter(|result| result.is_err()).count();
+        if unfinished > 0 {
+            warn!(
+                "{} of {} monitoring tasks did not finish within {:?}",
+                unfinished, total, timeout
+            );
+        }
+    }
+
+    // A reload keeps the runtime-added monitors, which must still fit the new config, e.g. when an
+    // alert channel they refer to was renamed
+    pub fn validate_reload(&self, config: &Config) -> Result<(), ConfigValidationError> {
+        keep_runtime_monitors(config.clone(), &self.config.read().unwrap()).validate()
+    }
+
+    // Replaces the config, restarting monitoring and dropping the history of removed monitors
+    pub async fn reload(self: &Arc<Self>, config: Config) -> ConfigDiff {
+        *self.reload_window.write().unwrap() = Some(ReloadWindow {
+            started: Utc::now(),
+            finished: None,
+        });
+        self.stop_monitoring_graceful(RELOAD_STOP_TIMEOUT).await;
+
+        let diff = {
+            let mut current = self.config.write().unwrap();
+            let config = keep_runtime_monitors(config, &current);
+            let diff = ConfigDiff {
+                added: monitor_names(&config)
+                    .difference(&monitor_names(&current))
+                    .cloned()
+                    .collect(),
+                removed: monitor_names(&current)
+                    .difference(&monitor_names(&config))
+                    .cloned()
+                    .collect(),
+                groups: affected_groups(&current, &config),
+            };
+            // A changed probe starts over with a closed breaker
+            let unchanged = unchanged_probes(&current, &config);
+            self.circuit_breakers
+                .write()
+                .unwrap()
+                .retain(|name, _| unchanged.contains(name));
+            // Overrides last across reloads, unless the probe is gone or the new config file turns
+            // its `enabled` to true
+            self.runtime_enabled.write().unwrap().retain(|name, _| {
+                let enabled = |config: &Config| {
+                    config
+                        .probes
+                        .iter()
+                        .find(|probe| &probe.name == name)
+                        .map(|probe| probe.enabled)
+                };
+                match enabled(&config) {
+                    None => false,
+                    Some(Some(true)) => enabled(&current) == Some(Some(true)),
+                    Some(_) => true,
+                }
+            });
+            self.result_budget
+                .set_limit_mb(config.settings.max_result_memory_mb);
+            *current = Arc::new(config);
+            diff
+        };
+        // Limits may have changed, runs still holding a permit finish on the old semaphore
+        self.in_flight.lock().unwrap().clear();
+        // Also drops results stored for monitors that were already gone, e.g. by a run that
+        // finished after its runtime-added monitor was removed, and those of dropped `urls`
+        let config = self.config();
+        let configured: BTreeSet<String> = monitor_names(&config)
+            .into_iter()
+            .chain(config.probes.iter().flat_map(Probe::url_result_names))
+            .collect();
+        let mut stale: BTreeSet<String> = diff.removed.iter().cloned().collect();
+        stale.extend(
+            self.probe_results
+                .names()
+                .into_iter()
+                .chain(self.story_results.names())
+                .filter(|name| !configured.contains(name)),
+        );
+        self.prune_results(&stale.into_iter().collect::<Vec<_>>());
+        self.enforce_result_budget();
+        self.start_monitoring();
+        if let Some(window) = self.reload_window.write().unwrap().as_mut() {
+            window.finished = Some(Utc::now());
+        }
+        self.config_version.fetch_add(1, Ordering::SeqCst);
+        self.metrics.config_reloads.add(1, &[]);
+
+        info!(
+            "Reloaded config, added {:?}, removed {:?}, groups affected {:?}",
+            diff.added, diff.removed, diff.groups
+        );
+        diff
+    }
+
+    // Whether a run that started at `run_started` and ends now overlapped a reload. Earlier reloads
+    // all finished before the latest one, so only the latest needs checking.
+    pub fn overlaps_reload(&self, run_started: DateTime<Utc>) -> bool {
+        self.reload_window.read().unwrap().is_some_and(|window| {
+            window
+                .finished
+                .is_none_or(|finished| finished >= run_started)
+        })
+    }
+
+    // Whether a result is left out of monitor states, alerting and reports
+    pub fn ignores_result(&self, during_reload: bool) -> bool {
+        during_reload
+            && self
+                .config
+                .read()
+                .unwrap()
+                .settings
+                .ignore_results_during_reload
+    }
+
+    // Pushes the next runs of a rate limited probe back by what `Retry-After` asks for, or by the
+    // doubled interval when it says nothing, capped by `settings.rate_limits.max_backoff`
+    pub fn defer(
+        &self,
+        probe_name: &str,
+        retry_after: Option<Duration>,
+        interval: Duration,
+    ) -> Deferral {
+        let max_backoff = self.config().settings.rate_limits.max_backoff();
+        let mut deferrals = self.deferrals.write().unwrap();
+        let consecutive = deferrals
+            .get(probe_name)
+            .map_or(1, |deferral| deferral.consecutive.saturating_add(1));
+        let delay = backoff(retry_after, interval, consecutive, max_backoff);
+        let until = chrono::Duration::from_std(delay)
+            .ok()
+            .and_then(|delay| Utc::now().checked_add_signed(delay))
+            .unwrap_or(DateTime::<Utc>::MAX_UTC);
+        let deferral = Deferral { until, consecutive };
+        deferrals.insert(probe_name.to_owned(), deferral);
+        deferral
+    }
+
+    pub fn clear_deferral(&self, probe_name: &str) {
+        self.deferrals.write().unwrap().remove(probe_name);
+    }
+
+    // When the next run of a rate limited probe is due at the earliest
+    pub fn deferred_until(&self, probe_name: &str) -> Option<DateTime<Utc>> {
+        self.deferrals
+            .read()
+            .unwrap()
+            .get(probe_name)
+            .map(|deferral| deferral.until)
+    }
+
+    // Counts a run toward the probe's circuit breaker, if it has one. Returns the breaker when the
+    // run changed its state.
+    pub fn record_circuit_run(
+        &self,
+        probe: &Probe,
+        success: bool,
+        attributes: &[KeyValue],
+    ) -> Option<CircuitBreaker> {
+        let settings = probe.circuit_breaker.as_ref()?;
+        let interval = probe.schedule.interval;
+        let mut circuit_breakers = self.circuit_breakers.write().unwrap();
+        let breaker = circuit_breakers
+            .entry(probe.name.clone())
+            .or_insert_with(|| CircuitBreaker::new(interval));
+        let changed = breaker.record(settings, success, interval, Utc::now());
+        self.metrics
+            .circuit_breaker_state
+            .record(breaker.state.as_u64(), attributes);
+        self.metrics
+            .probe_interval
+            .record(breaker.interval.as_secs(), attributes);
+        changed.map(|_| breaker.clone())
+    }
+
+    pub fn circuit_breaker(&self, probe_name: &str) -> Option<CircuitBreaker> {
+        self.circuit_breakers
+            .read()
+            .unwrap()
+            .get(probe_name)
+            .cloned()
+    }
+
+    // The reduced interval of a probe whose breaker is open
+    pub fn open_circuit_interval(&self, probe_name: &str) -> Option<Duration> {
+        self.circuit_breakers
+            .read()
+            .unwrap()
+            .get(probe_name)
+            .filter(|breaker| breaker.state == CircuitState::Open)
+            .map(|breaker| breaker.interval)
+    }
+
+    // Fraction of probes whose latest result leaves them OK, from 0.0 to 1.0, taking recovery
+    // thresholds into account. Probes without results are unknown and left out.
+    pub fn health_score(&self) -> f64 {
+        let latest = self.probe_results.latest_all();
+        let monitor_states = self.monitor_states.read().unwrap();
+        health_score(latest.iter().map(|(name, last, _)| {
+            monitor_states
+                .get(name)
+                .map_or(last.success, |state| !state.failing)
+        }))
+    }
+
+    pub fn prune_results(&self, monitor_names: &[String]) {
+        self.probe_results.prune(monitor_names);
+        self.story_results.prune(monitor_names);
+        let mut monitor_states = self.monitor_states.write().unwrap();
+        let mut incidents = self.incidents.write().unwrap();
+        let mut deferrals = self.deferrals.write().unwrap();
+        let mut circuit_breakers = self.circuit_breakers.write().unwrap();
+        for name in monitor_names {
+            monitor_states.remove(name);
+            incidents.remove(name);
+            deferrals.remove(name);
+            circuit_breakers.remove(name);
+            self.status_summary.mark_changed(name);
+            self.metrics.clear_monitor(name);
+        }
+        self.record_open_incidents(&incidents);
+        self.record_result_memory();
+    }
+
+    pub fn add_probe_result(&self, probe_name: String, result: ProbeResult) {
+        if let Some(queue) = &self.storage_queue {
+            queue.push(StorageRecord::Probe {
+                name: probe_name.clone(),
+                result: Box::new(result.clone()),
+            });
+        }
+        let activity = self.probe_results.record(&probe_name, result);
+        // Marked once the result is stored, so a recomputation can't miss it
+        self.status_summary.mark_changed(&probe_name);
+        self.record_activity(&probe_name, "probe", &activity);
+        self.enforce_result_budget();
+        self.new_result_notify.notify_waiters();
     }
 
     pub fn add_story_result(&self, story_name: String, result: StoryResult) {
-        let mut write_lock: RwLockWriteGuard<'_, HashMap<String, Vec<_>>> = self.story_results.write().unwrap();
+        if let Some(queue) = &self.storage_queue {
+            queue.push(StorageRecord::Story {
+                name: story_name.clone(),
+                result: Box::new(result.clone()),
+            });
+        }
+        let activity = self.story_results.record(&story_name, result);
+        // Marked once the result is stored, so a recomputation can't miss it
+        self.status_summary.mark_changed(&story_name);
+        self.record_activity(&story_name, "story", &activity);
+        self.enforce_result_budget();
+        self.new_result_notify.notify_waiters();
+    }
 
-        let results = write_lock.entry(story_name).or_default();
-        results.push(result);
+    fn record_activity(
+        &self,
+        monitor_name: &str,
+        monitor_type: &'static str,
+        activity: &MonitorActivity,
+    ) {
+        // Every stored result is a completed run to the watchdog
+        self.self_monitor.record_completion(Utc::now());
+        let attributes = [
+            KeyValue::new("name", monitor_name.to_owned()),
+            KeyValue::new("type", monitor_type),
+        ];
+        if let Some(at) = activity.last_success_at {
+            self.metrics
+                .last_success_timestamp
+                .record(at.timestamp().max(0) as u64, &attributes);
+        }
+        if let Some(at) = activity.last_failure_at {
+            self.metrics
+                .last_failure_timestamp
+                .record(at.timestamp().max(0) as u64, &attributes);
+        }
+    }
+
+    fn enforce_result_budget(&self) {
+        self.result_budget
+            .enforce(&[&*self.probe_results, &*self.story_results]);
+        self.record_result_memory();
+    }
+
+    fn record_result_memory(&self) {
+        for (monitor_type, bytes) in [
+            ("probe", self.probe_results.estimated_bytes()),
+            ("story", self.story_results.estimated_bytes()),
+        ] {
+            self.metrics
+                .result_store_memory
+                .record(bytes as u64, &[KeyValue::new("type", monitor_type)]);
+        }
+    }
+
+    // Updates the success/failure streaks of a monitor and returns its resulting state.
+    // A failing monitor only transitions back to OK after `recovery_threshold` consecutive successes.
+    pub fn record_monitor_run(
+        &self,
+        monitor_name: &str,
+        success: bool,
+        recovery_threshold: u32,
+    ) -> MonitorState {
+        let mut write_lock = self.monitor_states.write().unwrap();
+        let state = write_lock.entry(monitor_name.to_owned()).or_default();
+        state.recovery_threshold = recovery_threshold.max(1);
 
-        // Ensure only the latest 100 elements are kept
-        while results.len() > PROBE_RESULT_LIMIT {
-            results.remove(0);
+        if success {
+            state.consecutive_successes += 1;
+            state.consecutive_failures = 0;
+            if state.consecutive_successes >= state.recovery_threshold {
+                state.failing = false;
+            }
+        } else {
+            state.consecutive_failures += 1;
+            state.consecutive_successes = 0;
+            state.failing = true;
         }
+
+        state.clone()
+    }
+
+    // Opens, extends or closes the incident of a monitor, call after `record_monitor_run`.
+    // Returns the incident when this run closed it, so recovery alerts can be sent.
+    pub fn update_incident(
+        &self,
+        monitor_name: &str,
+        success: bool,
+        error: Option<&str>,
+        at: DateTime<Utc>,
+    ) -> Option<Incident> {
+        let retention = self.config.read().unwrap().settings.incident_retention();
+        let mut monitor_states = self.monitor_states.write().unwrap();
+        let state = monitor_states.get_mut(monitor_name)?;
+        let mut incidents = self.incidents.write().unwrap();
+        let monitor_incidents = incidents.entry(monitor_name.to_owned()).or_default();
+
+        let open = monitor_incidents
+            .last_mut()
+            .filter(|incident| incident.ended.is_none());
+        let closed = match (state.failing, open) {
+            (true, Some(incident)) => {
+                incident.record_run(success, error, at);
+                None
+            }
+            (true, None) => {
+                let mut incident = Incident::open(monitor_name, at);
+                incident.record_run(success, error, at);
+                state.open_incident = Some(incident.id);
+                monitor_incidents.push(incident);
+                None
+            }
+            (false, Some(incident)) => {
+                incident.close(at);
+                state.open_incident = None;
+                Some(incident.clone())
+            }
+            (false, None) => None,
+        };
+        monitor_incidents.retain(|incident| {
+            incident
+                .ended
+                .is_none_or(|ended| !(at - ended).to_std().is_ok_and(|age| age >= retention))
+        });
+
+        self.record_open_incidents(&incidents);
+        closed
+    }
+
+    pub fn acknowledge_incident(&self, id: Uuid, by: Option<String>) -> Option<Incident> {
+        let mut incidents = self.incidents.write().unwrap();
+        let incident = incidents
+            .values_mut()
+            .flatten()
+            .find(|incident| incident.id == id)?;
+        incident.acknowledged = Some(IncidentAck { by, at: Utc::now() });
+        Some(incident.clone())
+    }
+
+    fn record_open_incidents(&self, incidents: &HashMap<String, Vec<Incident>>) {
+        let open = incidents
+            .values()
+            .flatten()
+            .filter(|incident| incident.ended.is_none())
+            .count();
+        self.metrics.open_incidents.record(open as u64, &[]);
+    }
+}
+
+// Fraction of the given probes that are OK, 1.0 without any. The one implementation behind
+// `AppState::health_score`, the `/status` summaries and the `composite_status` gauge.
+pub fn health_score(probes_ok: impl IntoIterator<Item = bool>) -> f64 {
+    let (ok, known) = probes_ok.into_iter().fold((0, 0), |(ok, known), is_ok| {
+        (ok + is_ok as usize, known + 1)
+    });
+    if known == 0 {
+        return 1.0;
+    }
+    ok as f64 / known as f64
+}
+
+// Runtime-added monitors survive reloads, unless the new config has a monitor of the same name
+fn keep_runtime_monitors(mut config: Config, current: &Config) -> Config {
+    let runtime = current.runtime_monitors();
+    for probe in runtime.probes {
+        if !config.has_monitor(&probe.name) {
+            config.probes.push(probe);
+        }
+    }
+    for story in runtime.stories {
+        if !config.has_monitor(&story.name) {
+            config.stories.push(story);
+        }
+    }
+    config
+}
+
+fn monitor_names(config: &Config) -> BTreeSet<String> {
+    config
+        .probes
+        .iter()
+        .map(|probe| probe.name.clone())
+        .chain(config.stories.iter().map(|story| story.name.clone()))
+        .collect()
+}
+
+// Probes defined the same way in both configs
+fn unchanged_probes(current: &Config, config: &Config) -> BTreeSet<String> {
+    config
+        .probes
+        .iter()
+        .filter(|probe| {
+            current.probes.iter().any(|before| {
+                before.name == probe.name
+                    && serde_json::to_value(before).ok() == serde_json::to_value(*probe).ok()
+            })
+        })
+        .map(|probe| probe.name.clone())
+        .collect()
+}
+
+// The groups of every monitor whose definition differs between the configs, including added and
+// removed ones. A monitor moved to another group affects both.
+fn affected_groups(current: &Config, config: &Config) -> Vec<String> {
+    let definitions =
+        |config: &Config| -> HashMap<String, (Option<String>, Option<serde_json::Value>)> {
+            config
+                .probes
+                .iter()
+                .map(|probe| {
+                    (
+                        probe.name.clone(),
+                        (probe.group.clone(), serde_json::to_value(probe).ok()),
+                    )
+                })
+                .chain(config.stories.iter().map(|story| {
+                    (
+                        story.name.clone(),
+                        (story.group.clone(), serde_json::to_value(story).ok()),
+                    )
+                }))
+                .collect()
+        };
+    let (before, after) = (definitions(current), definitions(config));
+    let groups: BTreeSet<String> = before
+        .keys()
+        .chain(after.keys())
+        .filter(|name| {
+            before.get(*name).map(|(_, definition)| definition)
+                != after.get(*name).map(|(_, definition)| definition)
+        })
+        .flat_map(|name| [before.get(name), after.get(name)])
+        .flatten()
+        .filter_map(|(group, _)| group.clone())
+        .collect();
+    groups.into_iter().collect()
+}
+
+#[cfg(test)]
+mod app_state_tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use chrono::{DateTime, TimeZone, Utc};
+    use opentelemetry::KeyValue;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use crate::app_state::{AppState, PROBE_RESULT_LIMIT};
+    use crate::config::{Config, Settings};
+    use crate::otel::metrics::{MetricsState, MonitorStatus, REMOVED_HTTP_STATUS_CODE};
+    use crate::probe::circuit_breaker::{CircuitBreakerSettings, CircuitState};
+    use crate::probe::model::ProbeResult;
+    use crate::probe::probe_logic::Monitorable;
+    use crate::test_utils::metrics_test_utils::{counter_value, gauge_value};
+    use crate::test_utils::probe_test_utils::probe_get_with_expected_status;
+    use crate::test_utils::result_test_utils::ProbeResultBuilder;
+
+    fn empty_app_state() -> AppState {
+        AppState::new(Config {
+            probes: vec![],
+            stories: vec![],
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_new_without_meter_provider_does_not_panic() {
+        // No test installs a global meter provider, so this builds every instrument on the no-op one
+        let app_state = empty_app_state();
+
+        app_state
+            .metrics
+            .runs
+            .add(1, &[KeyValue::new("name", "probe")]);
+        app_state
+            .metrics
+            .record_duration(Duration::from_millis(10), &[]);
+        app_state.metrics.open_incidents.record(0, &[]);
+    }
+
+    #[test]
+    fn test_recovery_requires_consecutive_successes() {
+        let app_state = empty_app_state();
+
+        let state = app_state.record_monitor_run("probe", false, 3);
+        assert!(state.failing);
+
+        let state = app_state.record_monitor_run("probe", true, 3);
+        assert!(state.failing);
+        assert!(state.pending_recovery());
+        assert_eq!(1, state.consecutive_successes);
+
+        app_state.record_monitor_run("probe", true, 3);
+        let state = app_state.record_monitor_run("probe", true, 3);
+        assert!(!state.failing);
+        assert!(!state.pending_recovery());
+    }
+
+    #[test]
+    fn test_failure_resets_recovery_progress() {
+        let app_state = empty_app_state();
+
+        app_state.record_monitor_run("probe", false, 2);
+        app_state.record_monitor_run("probe", true, 2);
+        let state = app_state.record_monitor_run("probe", false, 2);
+        assert!(state.failing);
+        assert_eq!(0, state.consecutive_successes);
+        assert_eq!(1, state.consecutive_failures);
+
+        let state = app_state.record_monitor_run("probe", true, 2);
+        assert!(state.failing);
+        assert_eq!(1, state.consecutive_successes);
+    }
+
+    #[test]
+    fn test_default_threshold_recovers_immediately() {
+        let app_state = empty_app_state();
+
+        app_state.record_monitor_run("probe", false, 1);
+        let state = app_state.record_monitor_run("probe", true, 1);
+        assert!(!state.failing);
+    }
+
+    #[tokio::test]
+    async fn test_probe_runs_record_metrics() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/up"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/down"))
+            .respond_with(ResponseTemplate::new(500))
+            .mount(&mock_server)
+            .await;
+
+        let mut passing = probe_get_with_expected_status(
+            reqwest::StatusCode::OK,
+            format!("{}/up", mock_server.uri()),
+            "".to_owned(),
+        );
+        passing.name = "passing".to_owned();
+        let mut failing = probe_get_with_expected_status(
+            reqwest::StatusCode::OK,
+            format!("{}/down", mock_server.uri()),
+            "".to_owned(),
+        );
+        failing.name = "failing".to_owned();
+
+        let metrics_state = MetricsState::for_testing();
+        let app_state = Arc::new(AppState::with_metrics(
+            Config::default(),
+            metrics_state.metrics(),
+        ));
+        passing.probe_and_store_result(app_state.clone()).await;
+        failing.probe_and_store_result(app_state.clone()).await;
+
+        let metrics = metrics_state.collect().unwrap();
+        let passing_attributes = [KeyValue::new("name", "passing")];
+        let failing_attributes = [KeyValue::new("name", "failing")];
+        assert_eq!(
+            Some(1),
+            counter_value(&metrics, "runs", &passing_attributes)
+        );
+        assert_eq!(
+            Some(0),
+            counter_value(&metrics, "errors", &passing_attributes)
+        );
+        assert_eq!(
+            Some(200),
+            gauge_value(&metrics, "http_status_code", &passing_attributes)
+        );
+        assert_eq!(
+            Some(1),
+            counter_value(&metrics, "runs", &failing_attributes)
+        );
+        assert_eq!(
+            Some(1),
+            counter_value(&metrics, "errors", &failing_attributes)
+        );
+        assert_eq!(
+            Some(500),
+            gauge_value(&metrics, "http_status_code", &failing_attributes)
+        );
+        assert_eq!(
+            Some(app_state.probe_results.estimated_bytes() as u64),
+            gauge_value(
+                &metrics,
+                "result_store_memory",
+                &[KeyValue::new("type", "probe")]
+            )
+        );
+        assert!(app_state.probe_results.estimated_bytes() > 0);
+    }
+
+    #[tokio::test]
+    async fn test_pruned_monitors_have_their_gauges_marked_removed() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/down"))
+            .respond_with(ResponseTemplate::new(500))
+            .mount(&mock_server)
+            .await;
+        let mut removed = probe_get_with_expected_status(
+            reqwest::StatusCode::OK,
+            format!("{}/down", mock_server.uri()),
+            "".to_owned(),
+        );
+        removed.name = "removed".to_owned();
+        let mut kept = removed.clone();
+        kept.name = "kept".to_owned();
+
+        let metrics_state = MetricsState::for_testing();
+        let app_state = Arc::new(AppState::with_metrics(
+            Config::default(),
+            metrics_state.metrics(),
+        ));
+        removed.probe_and_store_result(app_state.clone()).await;
+        kept.probe_and_store_result(app_state.clone()).await;
+        app_state.prune_results(&["removed".to_owned()]);
+
+        let metrics = metrics_state.collect().unwrap();
+        let removed_attributes = [KeyValue::new("name", "removed")];
+        let kept_attributes = [KeyValue::new("name", "kept")];
+        assert_eq!(
+            Some(MonitorStatus::Removed.as_u64()),
+            gauge_value(&metrics, "status", &removed_attributes)
+        );
+        assert_eq!(
+            Some(REMOVED_HTTP_STATUS_CODE),
+            gauge_value(&metrics, "http_status_code", &removed_attributes)
+        );
+        assert_eq!(Some(1), gauge_value(&metrics, "status", &kept_attributes));
+        assert_eq!(
+            Some(500),
+            gauge_value(&metrics, "http_status_code", &kept_attributes)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_stop_monitoring_graceful_leaves_no_runs_in_flight() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/slow"))
+            .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_millis(200)))
+            .mount(&mock_server)
+            .await;
+        let probe = probe_get_with_expected_status(
+            reqwest::StatusCode::OK,
+            format!("{}/slow", mock_server.uri()),
+            "".to_owned(),
+        );
+        let app_state = Arc::new(AppState::new(Config {
+            probes: vec![probe],
+            ..Default::default()
+        }));
+
+        app_state.start_monitoring();
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        app_state
+            .stop_monitoring_graceful(Duration::from_secs(1))
+            .await;
+
+        assert!(app_state.monitor_tasks.read().unwrap().is_empty());
+        let requests = mock_server.received_requests().await.unwrap().len();
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert_eq!(
+            requests,
+            mock_server.received_requests().await.unwrap().len()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reload_lists_groups_of_changed_monitors() {
+        let probe = |name: &str, group: &str, interval: u64| {
+            let mut probe = probe_get_with_expected_status(
+                reqwest::StatusCode::OK,
+                "http://localhost/health".to_owned(),
+                "".to_owned(),
+            );
+            probe.name = name.to_owned();
+            probe.group = Some(group.to_owned());
+            probe.schedule.initial_delay = Duration::from_secs(3600);
+            probe.schedule.interval = Duration::from_secs(interval);
+            probe
+        };
+        let app_state = Arc::new(AppState::new(Config {
+            probes: vec![
+                probe("unchanged", "team-a", 60),
+                probe("changed", "team-b", 60),
+                probe("moved", "team-c", 60),
+            ],
+            ..Default::default()
+        }));
+
+        let diff = app_state
+            .reload(Config {
+                probes: vec![
+                    probe("unchanged", "team-a", 60),
+                    probe("changed", "team-b", 30),
+                    probe("moved", "team-d", 60),
+                ],
+                ..Default::default()
+            })
+            .await;
+        app_state.stop_monitoring();
+
+        assert_eq!(vec!["team-b", "team-c", "team-d"], diff.groups);
+    }
+
+    #[tokio::test]
+    async fn test_reload_replaces_config_and_prunes_removed_monitors() {
+        let probe = |name: &str| {
+            let mut probe = probe_get_with_expected_status(
+                reqwest::StatusCode::OK,
+                "http://localhost/health".to_owned(),
+                "".to_owned(),
+            );
+            probe.name = name.to_owned();
+            probe.schedule.initial_delay = Duration::from_secs(3600);
+            probe
+        };
+        let app_state = Arc::new(AppState::new(Config {
+            probes: vec![probe("kept"), probe("removed")],
+            ..Default::default()
+        }));
+        app_state.start_monitoring();
+        app_state.record_monitor_run("removed", false, 1);
+        app_state.record_monitor_run("kept", false, 1);
+        app_state.update_incident("removed", false, None, Utc::now());
+        app_state.update_incident("kept", false, None, Utc::now());
+
+        let diff = app_state
+            .reload(Config {
+                probes: vec![probe("kept"), probe("added")],
+                ..Default::default()
+            })
+            .await;
+
+        assert_eq!(vec!["added".to_owned()], diff.added);
+        assert_eq!(vec!["removed".to_owned()], diff.removed);
+        assert!(diff.groups.is_empty());
+        let monitor_tasks = app_state.monitor_tasks.read().unwrap();
+        assert!(monitor_tasks.contains_key("kept"));
+        assert!(monitor_tasks.contains_key("added"));
+        assert_eq!(2, monitor_tasks.len());
+        drop(monitor_tasks);
+        let monitor_states = app_state.monitor_states.read().unwrap();
+        assert!(monitor_states.contains_key("kept"));
+        assert!(!monitor_states.contains_key("removed"));
+        let incidents = app_state.incidents.read().unwrap();
+        assert!(incidents.contains_key("kept"));
+        assert!(!incidents.contains_key("removed"));
+        app_state.stop_monitoring();
+    }
+
+    #[tokio::test]
+    async fn test_reload_keeps_circuit_breakers_of_unchanged_probes() {
+        let probe = |name: &str, interval: u64| {
+            let mut probe = probe_get_with_expected_status(
+                reqwest::StatusCode::OK,
+                "http://localhost/health".to_owned(),
+                "".to_owned(),
+            );
+            probe.name = name.to_owned();
+            probe.schedule.initial_delay = Duration::from_secs(3600);
+            probe.schedule.interval = Duration::from_secs(interval);
+            probe.circuit_breaker = Some(CircuitBreakerSettings {
+                open_after_failures: 1,
+                probe_interval_when_open: Duration::from_secs(300),
+                close_after_successes: 2,
+            });
+            probe
+        };
+        let app_state = Arc::new(AppState::new(Config {
+            probes: vec![probe("unchanged", 60), probe("changed", 60)],
+            ..Default::default()
+        }));
+        for name in ["unchanged", "changed"] {
+            let opened = app_state.record_circuit_run(&probe(name, 60), false, &[]);
+            assert_eq!(Some(CircuitState::Open), opened.map(|b| b.state));
+        }
+
+        app_state
+            .reload(Config {
+                probes: vec![probe("unchanged", 60), probe("changed", 30)],
+                ..Default::default()
+            })
+            .await;
+        app_state.stop_monitoring();
+
+        assert_eq!(
+            Some(Duration::from_secs(300)),
+            app_state.open_circuit_interval("unchanged")
+        );
+        assert_eq!(None, app_state.circuit_breaker("changed"));
+    }
+
+    #[tokio::test]
+    async fn test_runtime_overrides_last_until_the_config_enables_the_probe() {
+        let probe = |name: &str, enabled: Option<bool>| {
+            let mut probe = probe_get_with_expected_status(
+                reqwest::StatusCode::OK,
+                "http://localhost/health".to_owned(),
+                "".to_owned(),
+            );
+            probe.name = name.to_owned();
+            probe.schedule.initial_delay = Duration::from_secs(3600);
+            probe.enabled = enabled;
+            probe
+        };
+        let app_state = Arc::new(AppState::new(Config {
+            probes: vec![
+                probe("kept", None),
+                probe("cleared", None),
+                probe("gone", None),
+            ],
+            ..Default::default()
+        }));
+        for name in ["kept", "cleared", "gone"] {
+            app_state.set_probe_enabled(name, false).unwrap();
+        }
+
+        app_state
+            .reload(Config {
+                probes: vec![probe("kept", None), probe("cleared", Some(true))],
+                ..Default::default()
+            })
+            .await;
+
+        let config = app_state.config();
+        assert!(!app_state.probe_enabled(&config.probes[0]));
+        assert!(app_state.probe_enabled(&config.probes[1]));
+        assert_eq!(
+            vec!["kept"],
+            app_state
+                .runtime_enabled
+                .read()
+                .unwrap()
+                .keys()
+                .collect::<Vec<_>>()
+        );
+        app_state.stop_monitoring();
+    }
+
+    #[tokio::test]
+    async fn test_meta_probe_expands_into_listed_names() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/services"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string(r#"{"services": [{"name": "billing"}, {"name": "search"}]}"#),
+            )
+            .up_to_n_times(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/services"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string(r#"{"services": [{"name": "billing"}]}"#),
+            )
+            .mount(&mock_server)
+            .await;
+        let mut meta_probe = probe_get_with_expected_status(
+            reqwest::StatusCode::OK,
+            format!("{}/services", mock_server.uri()),
+            "".to_owned(),
+        );
+        meta_probe.name = "services".to_owned();
+        meta_probe.name_from_response = Some("$.services[*].name".to_owned());
+        meta_probe.expanded_url = Some(format!("{}/health/${{{{ name }}}}", mock_server.uri()));
+        // Keeps the expanded probes from running during the test
+        meta_probe.schedule.initial_delay = Duration::from_secs(3600);
+        let app_state = Arc::new(AppState::new(Config {
+            probes: vec![meta_probe.clone()],
+            ..Default::default()
+        }));
+
+        meta_probe.probe_and_store_result(app_state.clone()).await;
+
+        let config = app_state.config();
+        let billing = config
+            .probes
+            .iter()
+            .find(|probe| probe.name == "services:billing")
+            .unwrap();
+        assert_eq!(format!("{}/health/billing", mock_server.uri()), billing.url);
+        assert_eq!(Some("services"), billing.expanded_from.as_deref());
+        assert!(billing.name_from_response.is_none());
+        assert!(config.has_monitor("services:search"));
+        assert_eq!(2, app_state.monitor_tasks.read().unwrap().len());
+
+        meta_probe.probe_and_store_result(app_state.clone()).await;
+
+        let config = app_state.config();
+        assert!(config.has_monitor("services:billing"));
+        assert!(!config.has_monitor("services:search"));
+        assert!(app_state
+            .monitor_tasks
+            .read()
+            .unwrap()
+            .contains_key("services:billing"));
+        // Expanded probes are never written to `xbp.runtime.yaml`
+        assert!(config.runtime_monitors().probes.is_empty());
+        app_state.stop_monitoring();
+    }
+
+    fn probe_result(success: bool, timestamp_started: DateTime<Utc>) -> ProbeResult {
+        ProbeResultBuilder::new("probe")
+            .success(success)
+            .started_at(timestamp_started)
+            .build()
+    }
+
+    #[tokio::test]
+    async fn test_stored_results_wake_waiters() {
+        let app_state = empty_app_state();
+        // Registered on creation, before the result is stored
+        let notified = app_state.new_result_notify.notified();
+
+        app_state.add_probe_result("probe".to_owned(), probe_result(true, Utc::now()));
+
+        tokio::time::timeout(Duration::from_secs(1), notified)
+            .await
+            .unwrap();
+        assert_eq!(1, app_state.probe_results.recent("probe").unwrap().len());
+    }
+
+    #[test]
+    fn test_activity_survives_result_rollover() {
+        let metrics_state = MetricsState::for_testing();
+        let app_state = AppState::with_metrics(Config::default(), metrics_state.metrics());
+        let failed_at = Utc.with_ymd_and_hms(2024, 1, 15, 10, 0, 0).unwrap();
+        app_state.add_probe_result("probe".to_owned(), probe_result(false, failed_at));
+        let runs = PROBE_RESULT_LIMIT + 5;
+        for minute in 1..=runs {
+            let at = failed_at + chrono::Duration::minutes(minute as i64);
+            app_state.add_probe_result("probe".to_owned(), probe_result(true, at));
+        }
+
+        let activity = app_state.probe_results.summary("probe").unwrap();
+        assert_eq!(Some(failed_at), activity.last_failure_at);
+        assert_eq!(
+            Some(failed_at + chrono::Duration::minutes(runs as i64)),
+            activity.last_success_at
+        );
+        assert_eq!(
+            Some(failed_at + chrono::Duration::minutes(1)),
+            activity.last_state_change_at
+        );
+        assert_eq!(runs as u32, activity.success_streak);
+        assert_eq!(0, activity.failure_streak);
+
+        let metrics = metrics_state.collect().unwrap();
+        let attributes = [KeyValue::new("name", "probe")];
+        assert_eq!(
+            activity.last_success_at.map(|at| at.timestamp() as u64),
+            gauge_value(&metrics, "last_success_timestamp", &attributes)
+        );
+        assert_eq!(
+            Some(failed_at.timestamp() as u64),
+            gauge_value(&metrics, "last_failure_timestamp", &attributes)
+        );
+
+        app_state.prune_results(&["probe".to_owned()]);
+        assert!(app_state.probe_results.summary("probe").is_none());
+    }
+
+    #[test]
+    fn test_health_score_counts_probes_with_results() {
+        let app_state = empty_app_state();
+        assert_eq!(1.0, app_state.health_score());
+
+        let now = Utc::now();
+        for (name, success) in [("api", true), ("web", true), ("db", false), ("cache", true)] {
+            app_state.add_probe_result(name.to_owned(), probe_result(success, now));
+        }
+        assert_eq!(0.75, app_state.health_score());
+
+        // Succeeding again, but still short of its recovery threshold
+        app_state.record_monitor_run("cache", false, 2);
+        app_state.record_monitor_run("cache", true, 2);
+        assert_eq!(0.5, app_state.health_score());
+    }
+
+    #[tokio::test]
+    async fn test_config_snapshots_outlive_changes() {
+        let mut probe = probe_get_with_expected_status(
+            reqwest::StatusCode::OK,
+            "http://localhost/health".to_owned(),
+            "".to_owned(),
+        );
+        probe.schedule.initial_delay = Duration::from_secs(3600);
+        let app_state = Arc::new(empty_app_state());
+        let before = app_state.config();
+
+        app_state.add_runtime_probe(probe).unwrap();
+        let added = app_state.config();
+        app_state.reload(Config::default()).await;
+
+        assert!(before.probes.is_empty());
+        assert_eq!(1, added.probes.len());
+        assert!(!Arc::ptr_eq(&added, &app_state.config()));
+        app_state.stop_monitoring();
+    }
+
+    #[tokio::test]
+    async fn test_runs_overlapping_a_reload_are_marked() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/slow"))
+            .respond_with(ResponseTemplate::new(500).set_delay(Duration::from_millis(300)))
+            .mount(&mock_server)
+            .await;
+        let mut probe = probe_get_with_expected_status(
+            reqwest::StatusCode::OK,
+            format!("{}/slow", mock_server.uri()),
+            "".to_owned(),
+        );
+        probe.schedule.initial_delay = Duration::from_secs(3600);
+        let app_state = Arc::new(AppState::new(Config {
+            probes: vec![probe.clone()],
+            ..Default::default()
+        }));
+
+        let overlapping = tokio::spawn({
+            let (probe, app_state) = (probe.clone(), app_state.clone());
+            async move { probe.probe_and_store_result(app_state).await }
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let mut config = (*app_state.config()).clone();
+        config.settings.ignore_results_during_reload = true;
+        app_state.reload(config).await;
+        overlapping.await.unwrap();
+        // The failure overlapped the reload, so it didn't count towards the monitor state
+        assert!(!app_state
+            .monitor_states
+            .read()
+            .unwrap()
+            .contains_key("Test probe"));
+
+        probe.probe_and_store_result(app_state.clone()).await;
+
+        let results = app_state
+            .probe_results
+            .recent("Test probe")
+            .unwrap()
+            .clone();
+        assert_eq!(
+            vec![true, false],
+            results
+                .iter()
+                .map(|result| result.during_reload)
+                .collect::<Vec<_>>()
+        );
+        assert!(app_state.monitor_states.read().unwrap()["Test probe"].failing);
+        app_state.stop_monitoring();
+    }
+
+    #[test]
+    fn test_incident_lifecycle() {
+        let app_state = empty_app_state();
+        let started = Utc::now();
+        let run = |success: bool, error: Option<&str>, seconds: i64| {
+            app_state.record_monitor_run("probe", success, 2);
+            app_state.update_incident(
+                "probe",
+                success,
+                error,
+                started + chrono::Duration::seconds(seconds),
+            )
+        };
+
+        assert!(run(true, None, 0).is_none());
+        assert!(run(false, Some("timeout"), 60).is_none());
+        assert!(run(false, Some("connection refused"), 120).is_none());
+        // Still open until the recovery threshold is reached
+        assert!(run(true, None, 180).is_none());
+        let open_id = app_state.monitor_states.read().unwrap()["probe"].open_incident;
+        let closed = run(true, None, 240).unwrap();
+
+        assert_eq!(open_id, Some(closed.id));
+        assert_eq!(2, closed.failures);
+        assert_eq!(180, closed.duration_seconds);
+        assert_eq!(Some("timeout".to_owned()), closed.first_error);
+        assert_eq!(Some("connection refused".to_owned()), closed.last_error);
+        assert!(app_state.monitor_states.read().unwrap()["probe"]
+            .open_incident
+            .is_none());
+        assert_eq!(1, app_state.incidents.read().unwrap()["probe"].len());
+    }
+
+    #[test]
+    fn test_closed_incidents_are_pruned_after_retention() {
+        let app_state = AppState::new(Config {
+            settings: Settings {
+                incident_retention: Some(Duration::from_secs(60)),
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        let started = Utc::now();
+        let run = |success: bool, seconds: i64| {
+            app_state.record_monitor_run("probe", success, 1);
+            app_state.update_incident(
+                "probe",
+                success,
+                None,
+                started + chrono::Duration::seconds(seconds),
+            );
+        };
+
+        run(false, 0);
+        run(true, 10);
+        run(true, 69);
+        assert_eq!(1, app_state.incidents.read().unwrap()["probe"].len());
+        run(true, 70);
+        assert!(app_state.incidents.read().unwrap()["probe"].is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_configured_monitor_gauges_follow_reloads() {
+        let probe = |name: &str| {
+            let mut probe = probe_get_with_expected_status(
+                reqwest::StatusCode::OK,
+                "http://localhost/health".to_owned(),
+                "".to_owned(),
+            );
+            probe.name = name.to_owned();
+            probe.schedule.initial_delay = Duration::from_secs(3600);
+            probe
+        };
+        let metrics_state = MetricsState::for_testing();
+        let app_state = Arc::new(AppState::with_metrics(
+            Config {
+                probes: vec![probe("first"), probe("second")],
+                ..Default::default()
+            },
+            metrics_state.metrics(),
+        ));
+
+        app_state.start_monitoring();
+        let metrics = metrics_state.collect().unwrap();
+        assert_eq!(Some(2), gauge_value(&metrics, "configured_probes", &[]));
+        assert_eq!(Some(0), gauge_value(&metrics, "configured_stories", &[]));
+
+        app_state.reload(Config::default()).await;
+        let metrics = metrics_state.collect().unwrap();
+        assert_eq!(Some(0), gauge_value(&metrics, "configured_probes", &[]));
+        app_state.stop_monitoring();
     }
 }
@@ -0,0 +1,327 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::BTreeMap;
+use std::hash::{Hash, Hasher};
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::mpsc::{sync_channel, SyncSender};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use opentelemetry::metrics::Counter;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::alerts::line_sink::{write_file_line, write_stdout_line, DEFAULT_MAX_FILES};
+use crate::app_state::AppState;
+use crate::config::AuditSettings;
+
+const DEFAULT_SAMPLE_WINDOW: Duration = Duration::from_secs(5 * 60);
+// Records waiting for the writer, more are dropped and counted in `audit_records_dropped`
+const QUEUE_CAPACITY: usize = 1024;
+// At most one warning per interval while records can't be written
+const WRITE_WARNING_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditOutcome {
+    Success,
+    // No response, or a 4xx or 5xx status
+    Failure,
+}
+
+// One outbound request, written as a JSON line
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditRecord {
+    pub timestamp: DateTime<Utc>,
+    pub monitor: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub step: Option<String>,
+    pub method: String,
+    // Including the query string
+    pub url: String,
+    // Every header sent, valued `<redacted>` unless listed in `settings.audit.header_values`
+    pub headers: BTreeMap<String, String>,
+    pub body_size: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub destination_ip: Option<IpAddr>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status_code: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub outcome: AuditOutcome,
+}
+
+impl AuditRecord {
+    pub fn with_response(mut self, status_code: u32, destination_ip: Option<IpAddr>) -> Self {
+        self.status_code = Some(status_code);
+        self.destination_ip = destination_ip;
+        if status_code >= 400 {
+            self.outcome = AuditOutcome::Failure;
+        }
+        self
+    }
+
+    pub fn with_error(mut self, error: &dyn std::fmt::Display) -> Self {
+        self.error = Some(error.to_string());
+        self.outcome = AuditOutcome::Failure;
+        self
+    }
+}
+
+// The monitor a request is sent for, passed to `call_endpoint` to audit the request
+#[derive(Clone, Copy)]
+pub struct AuditScope<'a> {
+    pub app_state: &'a AppState,
+    pub monitor: &'a str,
+    pub step: Option<&'a str>,
+}
+
+impl AuditScope<'_> {
+    // Describes a request about to be sent, None while `settings.audit` is disabled
+    pub fn start(&self, request: &reqwest::Request) -> Option<AuditRecord> {
+        let config = self.app_state.config();
+        let settings = &config.settings.audit;
+        if !settings.enabled {
+            return None;
+        }
+        let mut headers: BTreeMap<String, String> = BTreeMap::new();
+        for (name, value) in request.headers() {
+            let value = if settings
+                .header_values
+                .iter()
+                .any(|allowed| name.as_str().eq_ignore_ascii_case(allowed))
+            {
+                String::from_utf8_lossy(value.as_bytes()).into_owned()
+            } else {
+                "<redacted>".to_owned()
+            };
+            headers
+                .entry(name.as_str().to_owned())
+                .and_modify(|joined| {
+                    joined.push_str(", ");
+                    joined.push_str(&value);
+                })
+                .or_insert(value);
+        }
+
+        Some(AuditRecord {
+            timestamp: Utc::now(),
+            monitor: self.monitor.to_owned(),
+            step: self.step.map(str::to_owned),
+            method: request.method().to_string(),
+            url: request.url().to_string(),
+            headers,
+            body_size: request
+                .body()
+                .and_then(|body| body.as_bytes())
+                .map_or(0, <[u8]>::len),
+            destination_ip: None,
+            status_code: None,
+            error: None,
+            outcome: AuditOutcome::Success,
+        })
+    }
+
+    // Queues the record if its monitor's current window is sampled, failures are always queued
+    pub fn finish(&self, record: AuditRecord) {
+        let config = self.app_state.config();
+        let settings = &config.settings.audit;
+        if record.outcome == AuditOutcome::Success
+            && !sampled(
+                self.monitor,
+                record.timestamp,
+                settings.sample_window.unwrap_or(DEFAULT_SAMPLE_WINDOW),
+                settings.sample_rate.unwrap_or(1.0),
+            )
+        {
+            return;
+        }
+        self.app_state.audit.queue(
+            settings,
+            &record,
+            &self.app_state.metrics.audit_records_dropped,
+        );
+    }
+}
+
+// Whether the window of `timestamp` is recorded for the monitor. The choice is the same for every
+// request of a monitor within a window, so a window has either all of its records or none.
+pub fn sampled(monitor: &str, timestamp: DateTime<Utc>, window: Duration, rate: f64) -> bool {
+    if rate >= 1.0 {
+        return true;
+    }
+    let window = window.as_secs().max(1) as i64;
+    let mut hasher = DefaultHasher::new();
+    monitor.hash(&mut hasher);
+    timestamp.timestamp().div_euclid(window).hash(&mut hasher);
+    (hasher.finish() as f64 / u64::MAX as f64) < rate
+}
+
+struct QueuedLine {
+    line: String,
+    path: Option<PathBuf>,
+    max_bytes: Option<u64>,
+    max_files: u32,
+}
+
+// Writes audit records on a thread of its own, so probes never wait for the file or stdout
+#[derive(Default)]
+pub struct AuditLog {
+    sender: OnceLock<SyncSender<QueuedLine>>,
+}
+
+impl AuditLog {
+    fn queue(&self, settings: &AuditSettings, record: &AuditRecord, dropped: &Counter<u64>) {
+        let Ok(mut line) = serde_json::to_string(record) else {
+            dropped.add(1, &[]);
+            return;
+        };
+        line.push('\n');
+        let sender = self.sender.get_or_init(|| start_writer(dropped.clone()));
+        let queued = QueuedLine {
+            line,
+            path: settings.path.clone(),
+            max_bytes: settings.max_size_mb.map(|mb| mb * 1024 * 1024),
+            max_files: settings.max_files.unwrap_or(DEFAULT_MAX_FILES),
+        };
+        if sender.try_send(queued).is_err() {
+            dropped.add(1, &[]);
+        }
+    }
+}
+
+fn start_writer(dropped: Counter<u64>) -> SyncSender<QueuedLine> {
+    let (sender, receiver) = sync_channel::<QueuedLine>(QUEUE_CAPACITY);
+    std::thread::Builder::new()
+        .name("xbp-audit".to_owned())
+        .spawn(move || {
+            let mut last_warning: Option<Instant> = None;
+            for queued in receiver {
+                let Some(path) = &queued.path else {
+                    write_stdout_line(&queued.line);
+                    continue;
+                };
+                let Err(e) =
+                    write_file_line(path, &queued.line, queued.max_bytes, queued.max_files)
+                else {
+                    continue;
+                };
+                dropped.add(1, &[]);
+                if last_warning.is_none_or(|last| last.elapsed() >= WRITE_WARNING_INTERVAL) {
+                    last_warning = Some(Instant::now());
+                    warn!("Could not write audit records to {:?}: {}", path, e);
+                }
+            }
+        })
+        .expect("failed to start the audit writer thread");
+    sender
+}
+
+#[cfg(test)]
+mod audit_tests {
+    use std::collections::HashMap;
+    use std::env;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use chrono::{TimeZone, Utc};
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use super::{sampled, AuditOutcome, AuditRecord};
+    use crate::app_state::AppState;
+    use crate::config::{AuditSettings, Config, Settings};
+    use crate::probe::probe_logic::Monitorable;
+    use crate::test_utils::probe_test_utils::probe_get_with_expected_status;
+
+    #[tokio::test]
+    async fn test_failed_requests_are_recorded_outside_sampled_windows() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/ok"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/down"))
+            .respond_with(ResponseTemplate::new(503))
+            .mount(&mock_server)
+            .await;
+        let audit_path = env::temp_dir().join(format!("xbp-audit-{}.jsonl", uuid::Uuid::new_v4()));
+        let app_state = Arc::new(AppState::new(Config {
+            settings: Settings {
+                audit: AuditSettings {
+                    enabled: true,
+                    sample_rate: Some(0.0),
+                    path: Some(audit_path.clone()),
+                    header_values: vec!["Accept".to_owned()],
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            ..Default::default()
+        }));
+        for endpoint in ["ok", "down"] {
+            let mut probe = probe_get_with_expected_status(
+                reqwest::StatusCode::OK,
+                format!("{}/{}?region=eu", mock_server.uri(), endpoint),
+                "ping".to_owned(),
+            );
+            probe.name = endpoint.to_owned();
+            probe.with.as_mut().unwrap().headers = Some(HashMap::from([
+                ("Accept".to_owned(), "application/json".to_owned()),
+                ("X-Api-Key".to_owned(), "secret".to_owned()),
+            ]));
+            probe.probe_and_store_result(app_state.clone()).await;
+        }
+
+        // Written by the audit thread
+        let mut content = String::new();
+        for _ in 0..50 {
+            content = std::fs::read_to_string(&audit_path).unwrap_or_default();
+            if !content.is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        std::fs::remove_file(&audit_path).unwrap();
+
+        let records: Vec<AuditRecord> = content
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(1, records.len());
+        let record = &records[0];
+        assert_eq!("down", record.monitor);
+        assert_eq!(format!("{}/down?region=eu", mock_server.uri()), record.url);
+        assert_eq!(AuditOutcome::Failure, record.outcome);
+        assert_eq!(Some(503), record.status_code);
+        assert_eq!(Some(mock_server.address().ip()), record.destination_ip);
+        assert_eq!(4, record.body_size);
+        assert_eq!("application/json", record.headers["accept"]);
+        assert_eq!("<redacted>", record.headers["x-api-key"]);
+    }
+
+    #[test]
+    fn test_sampling_is_the_same_within_a_window() {
+        let window = Duration::from_secs(300);
+        let start = Utc.with_ymd_and_hms(2024, 1, 15, 10, 0, 0).unwrap();
+
+        let recorded = (0..1000)
+            .filter(|i| {
+                let monitor = format!("probe-{}", i);
+                let first = sampled(&monitor, start, window, 0.25);
+                for offset in [1, 60, 299] {
+                    let at = start + chrono::Duration::seconds(offset);
+                    assert_eq!(first, sampled(&monitor, at, window, 0.25));
+                }
+                first
+            })
+            .count();
+        assert!((150..350).contains(&recorded), "{}", recorded);
+
+        assert!(sampled("api", start, window, 1.0));
+        assert!(!sampled("api", start, window, 0.0));
+    }
+}
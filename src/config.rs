@@ -1,58 +1,1427 @@
-use std::path::PathBuf;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
+use lazy_static::lazy_static;
 use serde::{Deserialize, Serialize};
-use tracing::warn;
+use tracing::{info, warn};
 
+use crate::errors::{ConfigError, ConfigValidationError};
+use crate::probe::duration;
+use crate::probe::expectations::validate_expectations;
 use crate::probe::model::Probe;
+use crate::probe::model::ProbeAlert;
+use crate::probe::model::ProbeExpectation;
+use crate::probe::model::ProbeModules;
+use crate::probe::model::StatusPattern;
 use crate::probe::model::Story;
+use crate::probe::rate_limit::DEFAULT_MAX_BACKOFF;
+use crate::probe::story_expectations::validate_story_expectations;
+use crate::reports::model::Report;
+use crate::strict_config::{strict_from_env, unknown_fields};
+use crate::substitution_check::unusable_substitutions;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+// Closed incidents are kept for a week unless `settings.incident_retention` says otherwise
+const DEFAULT_INCIDENT_RETENTION: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+const DEFAULT_MAX_BLOCKING_DURATION_WARNING_MS: u64 = 500;
+const DEFAULT_BLOCKING_BODY_THRESHOLD_BYTES: usize = 1024 * 1024;
+const DEFAULT_SELF_ALERT_COOLDOWN: Duration = Duration::from_secs(15 * 60);
+const DEFAULT_ALERT_FAILURE_THRESHOLD: u32 = 5;
+const DEFAULT_ALERT_FAILURE_WINDOW: Duration = Duration::from_secs(10 * 60);
+const DEFAULT_WATCHDOG: Duration = Duration::from_secs(15 * 60);
+
+// Headers that usually name the backend or edge node that served a response
+pub const DEFAULT_CAPTURE_HEADERS: [&str; 4] = ["server", "via", "x-served-by", "cf-ray"];
+
+// The config format this build reads without migrating
+pub const CURRENT_CONFIG_VERSION: &str = "1";
+
+// Monitors added through the API are persisted to this file, next to the config file
+pub const RUNTIME_MONITORS_FILE: &str = "xbp.runtime.yaml";
+
+// Overrides the proxy of remote config fetches only, probes keep the standard proxy variables
+pub const REMOTE_CONFIG_PROXY_VAR: &str = "XBP_REMOTE_CONFIG_PROXY";
+// PEM files of the client certificate remote config fetches authenticate with, set both or neither
+pub const REMOTE_CONFIG_CLIENT_CERT_VAR: &str = "XBP_REMOTE_CONFIG_CLIENT_CERT_PATH";
+pub const REMOTE_CONFIG_CLIENT_KEY_VAR: &str = "XBP_REMOTE_CONFIG_CLIENT_KEY_PATH";
+const REMOTE_CONFIG_USER_AGENT: &str = "XBP Config/0.9.4";
+const REMOTE_CONFIG_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Config {
+    // Format of the file, see `migrate_config`. Omitted means `CURRENT_CONFIG_VERSION`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    #[serde(default)]
+    pub settings: Settings,
     #[serde(default)]
     pub probes: Vec<Probe>,
     #[serde(default)]
     pub stories: Vec<Story>,
+    #[serde(default)]
+    pub reports: Vec<Report>,
+    // Alerts defined once and referenced by name with `- channel: <name>`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub alert_channels: BTreeMap<String, ProbeAlert>,
+    // Expectation lists used by probes and steps with `expectations: { use: [<name>] }`, flattened
+    // into their expectations when the config is parsed
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub expectation_sets: BTreeMap<String, Vec<ProbeExpectation>>,
+}
+
+impl Config {
+    // Probe and story names share one namespace, as results and states are keyed by name
+    pub fn has_monitor(&self, name: &str) -> bool {
+        self.probes.iter().any(|probe| probe.name == name)
+            || self.stories.iter().any(|story| story.name == name)
+    }
+
+    // A probe sent to the runtime API, with `expectations: { use: [...] }` resolved against the
+    // `expectation_sets` of this config like `parse_config` resolves the ones of the file
+    pub fn resolve_runtime_probe(
+        &self,
+        probe: serde_json::Value,
+    ) -> Result<Probe, ConfigValidationError> {
+        self.resolve_runtime_monitor(probe, resolve_probe_expectations)
+    }
+
+    // `resolve_runtime_probe` for the steps of a story
+    pub fn resolve_runtime_story(
+        &self,
+        story: serde_json::Value,
+    ) -> Result<Story, ConfigValidationError> {
+        self.resolve_runtime_monitor(story, resolve_story_expectations)
+    }
+
+    fn resolve_runtime_monitor<T: serde::de::DeserializeOwned>(
+        &self,
+        monitor: serde_json::Value,
+        resolve: fn(
+            &serde_yaml::Value,
+            &mut serde_yaml::Value,
+        ) -> Result<bool, ConfigValidationError>,
+    ) -> Result<T, ConfigValidationError> {
+        let invalid = |e: serde_yaml::Error| ConfigValidationError {
+            message: e.to_string(),
+        };
+        let sets = serde_yaml::to_value(&self.expectation_sets).map_err(invalid)?;
+        let mut monitor = serde_yaml::to_value(monitor).map_err(invalid)?;
+        resolve(&sets, &mut monitor)?;
+        serde_yaml::from_value(monitor).map_err(invalid)
+    }
+
+    // The group of the probe or story called `name`, if it has one. The results of a probe's `urls`
+    // are in its group.
+    pub fn monitor_group(&self, name: &str) -> Option<&str> {
+        self.probes
+            .iter()
+            .find(|probe| probe.name == name || probe.url_result_names().iter().any(|n| n == name))
+            .map(|probe| probe.group.as_deref())
+            .or_else(|| {
+                self.stories
+                    .iter()
+                    .find(|story| story.name == name)
+                    .map(|story| story.group.as_deref())
+            })
+            .flatten()
+    }
+
+    // Whether the monitor called `name` is in `group`, any monitor is when no group is asked for
+    pub fn in_group(&self, name: &str, group: Option<&str>) -> bool {
+        group.is_none_or(|group| self.monitor_group(name) == Some(group))
+    }
+
+    pub fn runtime_monitors(&self) -> RuntimeMonitors {
+        RuntimeMonitors {
+            probes: self
+                .probes
+                .iter()
+                .filter(|probe| probe.runtime_added)
+                .cloned()
+                .collect(),
+            stories: self
+                .stories
+                .iter()
+                .filter(|story| story.runtime_added)
+                .cloned()
+                .collect(),
+        }
+    }
+
+    // Replaces channel references with their definition. References to channels that no longer
+    // exist are left out, validation keeps them from getting here.
+    pub fn resolve_alerts(&self, alerts: &Option<Vec<ProbeAlert>>) -> Option<Vec<ProbeAlert>> {
+        alerts.as_ref().map(|alerts| {
+            alerts
+                .iter()
+                .filter_map(|alert| match &alert.channel {
+                    Some(name) => {
+                        let definition = self.alert_channels.get(name);
+                        if definition.is_none() {
+                            warn!("Skipping alert to unknown channel '{}'", name);
+                        }
+                        definition.map(|definition| alert.referencing(definition))
+                    }
+                    None => Some(alert.clone()),
+                })
+                .collect()
+        })
+    }
+
+    fn validate_alert(&self, alert: &ProbeAlert) -> Result<(), String> {
+        match &alert.channel {
+            Some(name) if !self.alert_channels.contains_key(name) => {
+                let known: Vec<&str> = self.alert_channels.keys().map(String::as_str).collect();
+                Err(format!(
+                    "unknown alert channel '{}', known channels: {}",
+                    name,
+                    if known.is_empty() {
+                        "none".to_owned()
+                    } else {
+                        known.join(", ")
+                    }
+                ))
+            }
+            _ => alert.validate(),
+        }
+    }
+
+    // Checks that go beyond the shape of the YAML
+    pub fn validate(&self) -> Result<(), ConfigValidationError> {
+        // Results are keyed by name, a duplicate would mix the history of two monitors
+        let duplicate_probes = duplicates(self.probes.iter().map(|probe| probe.name.as_str()));
+        if !duplicate_probes.is_empty() {
+            return Err(ConfigValidationError {
+                message: format!("duplicate probe names: {}", duplicate_probes.join(", ")),
+            });
+        }
+        let duplicate_stories = duplicates(self.stories.iter().map(|story| story.name.as_str()));
+        if !duplicate_stories.is_empty() {
+            return Err(ConfigValidationError {
+                message: format!("duplicate story names: {}", duplicate_stories.join(", ")),
+            });
+        }
+        // Monitor states, incidents and the `/status` summary are shared by probes and stories
+        let probe_names: BTreeSet<&str> = self
+            .probes
+            .iter()
+            .map(|probe| probe.name.as_str())
+            .collect();
+        let shared: BTreeSet<&str> = self
+            .stories
+            .iter()
+            .map(|story| story.name.as_str())
+            .filter(|name| probe_names.contains(name))
+            .collect();
+        if !shared.is_empty() {
+            return Err(ConfigValidationError {
+                message: format!(
+                    "names used by both a probe and a story: {}",
+                    shared.into_iter().collect::<Vec<_>>().join(", ")
+                ),
+            });
+        }
+        if let Some(rate) = self.settings.audit.sample_rate {
+            if !(0.0..=1.0).contains(&rate) {
+                return Err(ConfigValidationError {
+                    message: format!(
+                        "settings.audit.sample_rate: must be between 0 and 1, got {}",
+                        rate
+                    ),
+                });
+            }
+        }
+        if self.settings.audit.sample_window == Some(Duration::ZERO) {
+            return Err(ConfigValidationError {
+                message: "settings.audit.sample_window: must be longer than 0".to_owned(),
+            });
+        }
+        for pattern in &self.settings.probe_modules.allowed_target_patterns {
+            regex::Regex::new(pattern).map_err(|e| ConfigValidationError {
+                message: format!(
+                    "settings.probe_modules: invalid target pattern '{}': {}",
+                    pattern, e
+                ),
+            })?;
+        }
+        for (name, module) in &self.settings.probe_modules.modules {
+            validate_expectations(&module.expectations).map_err(|message| {
+                ConfigValidationError {
+                    message: format!("settings.probe_modules: module '{}': {}", name, message),
+                }
+            })?;
+        }
+        if let Some(self_alerts) = &self.settings.self_alerts {
+            if !self.alert_channels.contains_key(&self_alerts.channel) {
+                return Err(ConfigValidationError {
+                    message: format!(
+                        "settings.self_alerts: unknown alert channel '{}'",
+                        self_alerts.channel
+                    ),
+                });
+            }
+            if self_alerts.watchdog == Some(Duration::ZERO) {
+                return Err(ConfigValidationError {
+                    message: "settings.self_alerts.watchdog: must be longer than 0".to_owned(),
+                });
+            }
+        }
+        if self.settings.runtime.worker_threads == Some(0)
+            || self.settings.runtime.blocking_threads == Some(0)
+        {
+            return Err(ConfigValidationError {
+                message: "settings.runtime: thread counts must be at least 1".to_owned(),
+            });
+        }
+        let alerts = self
+            .probes
+            .iter()
+            .map(|probe| (format!("probe '{}'", probe.name), &probe.alerts))
+            .chain(
+                self.stories
+                    .iter()
+                    .map(|story| (format!("story '{}'", story.name), &story.alerts)),
+            );
+        for (name, definition) in &self.alert_channels {
+            if definition.channel.is_some() {
+                return Err(ConfigValidationError {
+                    message: format!(
+                        "alert channel '{}': channels can't refer to a channel",
+                        name
+                    ),
+                });
+            }
+            definition
+                .validate()
+                .map_err(|message| ConfigValidationError {
+                    message: format!("alert channel '{}': {}", name, message),
+                })?;
+        }
+        for (monitor, alerts) in alerts {
+            for alert in alerts.iter().flatten() {
+                self.validate_alert(alert)
+                    .map_err(|message| ConfigValidationError {
+                        message: format!("{}: {}", monitor, message),
+                    })?;
+            }
+        }
+        for report in &self.reports {
+            for alert in &report.alerts {
+                self.validate_alert(alert)
+                    .map_err(|message| ConfigValidationError {
+                        message: format!("report '{}': {}", report.name, message),
+                    })?;
+            }
+        }
+        let schedules = self
+            .probes
+            .iter()
+            .map(|probe| (format!("probe '{}'", probe.name), &probe.schedule))
+            .chain(
+                self.stories
+                    .iter()
+                    .map(|story| (format!("story '{}'", story.name), &story.schedule)),
+            );
+        for (monitor, schedule) in schedules {
+            schedule
+                .validate()
+                .map_err(|message| ConfigValidationError {
+                    message: format!("{}: {}", monitor, message),
+                })?;
+        }
+        for probe in &self.probes {
+            probe
+                .validate_expansion()
+                .map_err(|message| ConfigValidationError {
+                    message: format!("probe '{}': {}", probe.name, message),
+                })?;
+            probe
+                .validate_body_metrics()
+                .map_err(|message| ConfigValidationError {
+                    message: format!("probe '{}': {}", probe.name, message),
+                })?;
+            probe
+                .validate_sftp()
+                .map_err(|message| ConfigValidationError {
+                    message: format!("probe '{}': {}", probe.name, message),
+                })?;
+            probe
+                .validate_sla_window()
+                .map_err(|message| ConfigValidationError {
+                    message: format!("probe '{}': {}", probe.name, message),
+                })?;
+            probe
+                .validate_urls()
+                .map_err(|message| ConfigValidationError {
+                    message: format!("probe '{}': {}", probe.name, message),
+                })?;
+            probe
+                .validate_circuit_breaker()
+                .map_err(|message| ConfigValidationError {
+                    message: format!("probe '{}': {}", probe.name, message),
+                })?;
+            probe
+                .validate_composite()
+                .map_err(|message| ConfigValidationError {
+                    message: format!("probe '{}': {}", probe.name, message),
+                })?;
+            probe
+                .validate_samples()
+                .map_err(|message| ConfigValidationError {
+                    message: format!("probe '{}': {}", probe.name, message),
+                })?;
+            probe
+                .validate_prefetch()
+                .map_err(|message| ConfigValidationError {
+                    message: format!("probe '{}': {}", probe.name, message),
+                })?;
+            validate_expectations(&probe.expectations).map_err(|message| {
+                ConfigValidationError {
+                    message: format!("probe '{}': {}", probe.name, message),
+                }
+            })?;
+            if let Some(options) = &probe.with {
+                options
+                    .validate()
+                    .map_err(|message| ConfigValidationError {
+                        message: format!("probe '{}': {}", probe.name, message),
+                    })?;
+            }
+        }
+        for story in &self.stories {
+            story
+                .validate_urls()
+                .map_err(|message| ConfigValidationError {
+                    message: format!("story '{}': {}", story.name, message),
+                })?;
+            validate_story_expectations(story).map_err(|message| ConfigValidationError {
+                message: format!("story '{}': {}", story.name, message),
+            })?;
+            for step in story.all_steps() {
+                validate_expectations(&step.expectations).map_err(|message| {
+                    ConfigValidationError {
+                        message: format!(
+                            "story '{}' step '{}': {}",
+                            story.name, step.name, message
+                        ),
+                    }
+                })?;
+                step.validate_body()
+                    .map_err(|message| ConfigValidationError {
+                        message: format!(
+                            "story '{}' step '{}': {}",
+                            story.name, step.name, message
+                        ),
+                    })?;
+                if let Some(options) = &step.with {
+                    options
+                        .validate()
+                        .map_err(|message| ConfigValidationError {
+                            message: format!(
+                                "story '{}' step '{}': {}",
+                                story.name, step.name, message
+                            ),
+                        })?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// Names that occur more than once, sorted
+fn duplicates<'a>(names: impl Iterator<Item = &'a str>) -> Vec<&'a str> {
+    let mut seen = BTreeSet::new();
+    let duplicates: BTreeSet<&str> = names.filter(|name| !seen.insert(*name)).collect();
+    duplicates.into_iter().collect()
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Settings {
+    // Statuses counted as success for probes and steps without a StatusCode expectation.
+    // When unset any received response is a success.
+    pub default_success_statuses: Option<Vec<StatusPattern>>,
+    #[serde(default)]
+    pub probe_modules: ProbeModules,
+    // Default limit of concurrent runs per probe, unlimited when unset
+    pub max_in_flight: Option<u32>,
+    // Leave runs that overlapped a reload out of monitor states, alerting and reports
+    #[serde(default)]
+    pub ignore_results_during_reload: bool,
+    // How long closed incidents are kept, plain numbers are seconds
+    #[serde(
+        default,
+        deserialize_with = "duration::deserialize_seconds",
+        serialize_with = "duration::serialize",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub incident_retention: Option<Duration>,
+    #[serde(default)]
+    pub runtime: RuntimeSettings,
+    #[serde(default)]
+    pub metrics: MetricsSettings,
+    // Write monitors added through the API to `xbp.runtime.yaml` and load them again on startup
+    #[serde(default)]
+    pub persist_runtime_monitors: bool,
+    // Response headers stored with each http probe run, case-insensitive. Defaults to
+    // `DEFAULT_CAPTURE_HEADERS`, an empty list captures none.
+    pub capture_headers: Option<Vec<String>>,
+    // Log and count http probe runs that connected to a different IP than the run before
+    #[serde(default)]
+    pub log_backend_changes: bool,
+    #[serde(default)]
+    pub audit: AuditSettings,
+    // Run every monitor a `/-/reload` adds or changes once, and report the outcomes in the response
+    #[serde(default)]
+    pub verify_on_reload: bool,
+    // Reject unknown keys instead of ignoring them, as does `XBP_STRICT_CONFIG=true`. See `strict_config`.
+    #[serde(default)]
+    pub strict_config: bool,
+    // Approximate memory the stored probe and story results may take. Response bodies of the oldest
+    // runs are dropped first, then the oldest runs. Unlimited when unset, see `result_store`.
+    pub max_result_memory_mb: Option<u64>,
+    // Where problems of xbp itself are alerted, see `self_alerts`
+    pub self_alerts: Option<SelfAlertSettings>,
+    // How probes with `with.respect_retry_after` back off, see `probe::rate_limit`
+    #[serde(default)]
+    pub rate_limits: RateLimitSettings,
+    // Where results are kept beyond the in-memory window, see `storage`
+    #[serde(default)]
+    pub storage: StorageSettings,
+}
+
+// Read at startup only, a reload keeps the store the process started with
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StorageSettings {
+    // Results are also written to this Postgres database and the history endpoints read from it.
+    // Needs the `postgres` feature. Carries credentials, so it's never serialized.
+    #[serde(default, skip_serializing)]
+    pub postgres_url: Option<String>,
+    // Refuse to start when the database can't be used, instead of keeping results in memory only
+    #[serde(default)]
+    pub strict: bool,
+    // Results waiting to be written, more are dropped and counted in `storage_records_dropped`.
+    // 10000 when unset.
+    pub queue_size: Option<usize>,
+    // Results per insert, 100 when unset
+    pub batch_size: Option<usize>,
+    // How long the database keeps results, forever when unset. Plain numbers are seconds.
+    #[serde(
+        default,
+        deserialize_with = "duration::deserialize_seconds",
+        serialize_with = "duration::serialize",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub retention: Option<Duration>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RateLimitSettings {
+    // The longest a rate limited probe waits for its next run, 1 hour when unset. Plain numbers
+    // are seconds.
+    #[serde(
+        default,
+        deserialize_with = "duration::deserialize_seconds",
+        serialize_with = "duration::serialize",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub max_backoff: Option<Duration>,
+    // Rate limited runs fail the monitor and alert like other failures. By default they are left
+    // out of monitor states and alerting, as runs overlapping a reload can be.
+    #[serde(default)]
+    pub count_as_failure: bool,
+}
+
+impl RateLimitSettings {
+    pub fn max_backoff(&self) -> Duration {
+        self.max_backoff.unwrap_or(DEFAULT_MAX_BACKOFF)
+    }
+}
+
+// Sampled JSON lines describing the requests probes and stories send, see `audit::AuditLog`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AuditSettings {
+    #[serde(default)]
+    pub enabled: bool,
+    // Fraction of monitor time windows whose requests are recorded, 1.0 when unset. Failed
+    // requests are recorded either way.
+    pub sample_rate: Option<f64>,
+    // Length of those windows, 5 minutes when unset. Plain numbers are seconds.
+    #[serde(
+        default,
+        deserialize_with = "duration::deserialize_seconds",
+        serialize_with = "duration::serialize",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub sample_window: Option<Duration>,
+    // Lines are appended to this file, or written to stdout when unset
+    pub path: Option<PathBuf>,
+    // Rotates the file like file alerts do
+    pub max_size_mb: Option<u64>,
+    pub max_files: Option<u32>,
+    // Headers recorded with their value, case-insensitive. Other headers are recorded by name only.
+    #[serde(default)]
+    pub header_values: Vec<String>,
+}
+
+// Alerts about xbp itself, sent like failure alerts of a monitor named after their dedup key
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SelfAlertSettings {
+    // Name of an entry in `alert_channels`
+    pub channel: String,
+    // Least time between two alerts of the same kind, 15 minutes when unset. Events in between
+    // are still counted and listed on `/-/timeline`. Plain numbers are seconds.
+    #[serde(
+        default,
+        deserialize_with = "duration::deserialize_seconds",
+        serialize_with = "duration::serialize",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub cooldown: Option<Duration>,
+    // Failed alert deliveries within `alert_failure_window` that raise an alert, 5 when unset
+    pub alert_failure_threshold: Option<u32>,
+    // 10 minutes when unset
+    #[serde(
+        default,
+        deserialize_with = "duration::deserialize_seconds",
+        serialize_with = "duration::serialize",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub alert_failure_window: Option<Duration>,
+    // Alerts when no monitor completed a run for this long while monitors are configured,
+    // 15 minutes when unset
+    #[serde(
+        default,
+        deserialize_with = "duration::deserialize_seconds",
+        serialize_with = "duration::serialize",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub watchdog: Option<Duration>,
+}
+
+// The contents of `xbp.runtime.yaml`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RuntimeMonitors {
+    #[serde(default)]
+    pub probes: Vec<Probe>,
+    #[serde(default)]
+    pub stories: Vec<Story>,
+}
+
+// Instrument settings, applied once at startup since instruments can't change their unit
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MetricsSettings {
+    #[serde(default)]
+    pub duration_unit: DurationUnit,
+}
+
+// Unit of the `duration` histogram. Milliseconds keep existing dashboards working, finer units
+// separate probes answering in well under a millisecond.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DurationUnit {
+    #[default]
+    Ms,
+    Us,
+    S,
+}
+
+impl DurationUnit {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DurationUnit::Ms => "ms",
+            DurationUnit::Us => "us",
+            DurationUnit::S => "s",
+        }
+    }
+
+    pub fn convert(&self, duration: Duration) -> f64 {
+        match self {
+            DurationUnit::Ms => duration.as_nanos() as f64 / 1_000_000.0,
+            DurationUnit::Us => duration.as_nanos() as f64 / 1_000.0,
+            DurationUnit::S => duration.as_nanos() as f64 / 1_000_000_000.0,
+        }
+    }
+}
+
+// Tokio runtime tuning, applied once at startup. Reloads don't rebuild the runtime.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RuntimeSettings {
+    // Defaults to the number of cores
+    pub worker_threads: Option<usize>,
+    // Defaults to tokio's 512
+    pub blocking_threads: Option<usize>,
+    // Expectation evaluations taking longer than this are logged and counted, defaults to 500
+    pub max_blocking_duration_warning_ms: Option<u64>,
+    // Bodies of at least this many bytes are evaluated on the blocking pool, defaults to 1 MiB
+    pub blocking_body_threshold_bytes: Option<usize>,
+}
+
+impl RuntimeSettings {
+    pub fn max_blocking_duration_warning(&self) -> Duration {
+        Duration::from_millis(
+            self.max_blocking_duration_warning_ms
+                .unwrap_or(DEFAULT_MAX_BLOCKING_DURATION_WARNING_MS),
+        )
+    }
+
+    pub fn blocking_body_threshold_bytes(&self) -> usize {
+        self.blocking_body_threshold_bytes
+            .unwrap_or(DEFAULT_BLOCKING_BODY_THRESHOLD_BYTES)
+    }
+
+    // A multi threaded runtime with the configured thread counts
+    pub fn runtime_builder(&self) -> tokio::runtime::Builder {
+        let mut builder = tokio::runtime::Builder::new_multi_thread();
+        builder.enable_all();
+        if let Some(worker_threads) = self.worker_threads {
+            builder.worker_threads(worker_threads);
+        }
+        if let Some(blocking_threads) = self.blocking_threads {
+            builder.max_blocking_threads(blocking_threads);
+        }
+        builder
+    }
+}
+
+impl Settings {
+    pub fn incident_retention(&self) -> Duration {
+        self.incident_retention
+            .unwrap_or(DEFAULT_INCIDENT_RETENTION)
+    }
+
+    pub fn capture_headers(&self) -> Vec<String> {
+        match &self.capture_headers {
+            Some(headers) => headers.clone(),
+            None => DEFAULT_CAPTURE_HEADERS
+                .iter()
+                .map(|name| name.to_string())
+                .collect(),
+        }
+    }
+}
+
+impl SelfAlertSettings {
+    pub fn cooldown(&self) -> Duration {
+        self.cooldown.unwrap_or(DEFAULT_SELF_ALERT_COOLDOWN)
+    }
+
+    pub fn alert_failure_threshold(&self) -> u32 {
+        self.alert_failure_threshold
+            .unwrap_or(DEFAULT_ALERT_FAILURE_THRESHOLD)
+            .max(1)
+    }
+
+    pub fn alert_failure_window(&self) -> Duration {
+        self.alert_failure_window
+            .unwrap_or(DEFAULT_ALERT_FAILURE_WINDOW)
+    }
+
+    pub fn watchdog(&self) -> Duration {
+        self.watchdog.unwrap_or(DEFAULT_WATCHDOG)
+    }
 }
 
+// `http://` and `https://` paths are fetched with `load_config_from_remote_url`
 pub async fn load_config<P: Into<PathBuf>>(path: P) -> Result<Config, Box<dyn std::error::Error>> {
     let path = path.into();
+    if let Some(url) = remote_config_url(&path) {
+        return load_config_from_remote_url(url).await;
+    }
+    // Errors rather than panics, a failing reload must leave the running config in place
     let config = match tokio::fs::read_to_string(path.clone()).await {
         Ok(content) => content,
         Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => {
-            panic!("Config file not found: {:?}", path)
+            let hint = not_found_hint(&path);
+            return Err(ConfigError::FileNotFound { path, hint }.into());
         }
         Err(e) => {
-            panic!("Failed to read config file: {:?}, err {}", path, e)
+            return Err(ConfigError::ReadFailed {
+                path,
+                source: e.to_string(),
+            }
+            .into())
         }
     };
-    let config = replace_env_vars(&config);
-    let config: Config = serde_yaml::from_str(&config)?;
+    let (mut config, env_substituted) = parse_config(&config)?;
+    if config.settings.persist_runtime_monitors {
+        merge_runtime_monitors(&mut config, &runtime_monitors_path(&path)).await?;
+    }
+    config.validate()?;
+    info!(
+        source = "file",
+        path = %path.display(),
+        probes = config.probes.len(),
+        stories = config.stories.len(),
+        env_substituted,
+        "Loaded config"
+    );
     Ok(config)
 }
 
-pub fn replace_env_vars(content: &str) -> String {
+// What to do about a missing config file, relative paths are resolved against the working directory
+fn not_found_hint(path: &Path) -> String {
+    let working_directory = std::env::current_dir()
+        .map(|directory| format!("{:?}", directory))
+        .unwrap_or_else(|_| "the working directory".to_owned());
+    if path == Path::new(crate::XBP_YAML) {
+        format!(
+            "Create {} in {} (`--init` writes a commented starter config), or pass another config with `--file <path>` or `--file https://...`.",
+            crate::XBP_YAML,
+            working_directory
+        )
+    } else if path.is_relative() {
+        format!(
+            "The path is relative to {}, pass an absolute path to `--file` or start from the directory holding the config.",
+            working_directory
+        )
+    } else {
+        "Check the path passed to `--file`.".to_owned()
+    }
+}
+
+// Written by `--init` for a first run. Every field is explained above it, keep the template loading
+// as it is, see `test_default_config_template_loads`.
+pub const DEFAULT_CONFIG_TEMPLATE: &str = r#"# xbp-monitoring config. Probes check a single endpoint on a schedule, stories run several
+# requests in order. The README has the full reference, including how to read values from
+# environment variables.
+
+# Settings shared by all monitors, every one of them is optional.
+# settings:
+#   # Statuses that count as success for probes and steps without a StatusCode expectation,
+#   # classes such as "2xx" or exact codes such as "302". Defaults to ["2xx"].
+#   default_success_statuses: ["2xx", "3xx"]
+#   # Concurrent runs of each probe, later runs wait for a slot. Unlimited when unset.
+#   max_in_flight: 1
+
+# Alert destinations defined once and referenced by name with `channel:` in a monitor's alerts.
+# alert_channels:
+#   oncall_slack:
+#     type: slack
+#     webhook_url: https://hooks.slack.com/services/T000/B000/XXXX
+
+probes:
+  # Unique across probes and stories, it names the monitor in metrics, alerts and the API.
+  - name: example-homepage
+    # The endpoint to request. `urls:` with a list checks several endpoints in turn instead.
+    url: https://example.com/
+    # Any HTTP method: GET, HEAD, POST, PUT, PATCH or DELETE.
+    http_method: GET
+    # Request options, all optional.
+    with:
+      # Sent with every request, e.g. `Authorization: Bearer <token>`.
+      headers:
+        Accept: text/html
+      # Plain numbers are milliseconds, strings carry their unit, e.g. "5s".
+      timeout: 5000
+      # The request body, e.g. for a POST: body: '{"ping": true}'
+    # Every expectation must pass for a run to succeed.
+    #   field: StatusCode, Body, or Script (a Rhai script, needs the `scripting` feature)
+    #   operation: Equals, NotEquals, IsOneOf (values separated by |), Contains, NotContains,
+    #     Matches (a regex) or Passes (Script only)
+    #   value: always a string, quote numbers such as "200"
+    expectations:
+      # One of several status codes.
+      - field: StatusCode
+        operation: IsOneOf
+        value: "200|304"
+      # Text the body has to contain.
+      - field: Body
+        operation: Contains
+        value: Example Domain
+      # A regex the body has to match.
+      - field: Body
+        operation: Matches
+        value: "<title>[^<]+</title>"
+    # When the probe runs. Both fields are seconds, or strings with a unit such as "5m".
+    # Schedules are intervals, cron expressions aren't supported.
+    schedule:
+      # Wait before the first run.
+      initial_delay: 0
+      # Time between runs, at least 100ms unless `allow_fast: true` is set.
+      interval: 60
+    # Where failures are sent. `on: [failure, recovery]` limits the events, all are sent when unset.
+    # `type` is derived from the url when unset: Slack and Discord webhooks are recognized, any
+    # other url receives the alert as a JSON POST.
+    alerts:
+      # Printed to stdout as a JSON line.
+      - type: stdout
+      # A generic webhook.
+      # - url: https://example.com/xbp-alerts
+      # A Slack incoming webhook.
+      # - type: slack
+      #   url: https://hooks.slack.com/services/T000/B000/XXXX
+      # A Discord webhook.
+      # - type: discord
+      #   url: https://discord.com/api/webhooks/000/XXXX
+      # An Opsgenie alert, closed again when the probe recovers.
+      # - type: opsgenie
+      #   api_key: <opsgenie api key>
+      # JSON lines appended to a local file, rotated at `max_size_mb` when set.
+      # - type: file
+      #   path: /var/log/xbp/alerts.jsonl
+      # A channel from `alert_channels`.
+      # - channel: oncall_slack
+    # Free-form labels, added as attributes to the probe's metrics.
+    tags:
+      team: web
+
+# Stories run their steps in order, later steps can use values captured from earlier responses.
+# Steps take the same `with`, `expectations` and `http_method` as probes.
+# stories:
+#   - name: ip-location
+#     steps:
+#       - name: get-ip
+#         url: https://api.ipify.org/?format=json
+#         http_method: GET
+#         # A dot separated path into the JSON response body, or `{ header: Location }`.
+#         captures:
+#           ip: ip
+#       - name: get-location
+#         # Captured values are used as ${{ steps.<step>.captures.<name> }}.
+#         url: https://ipinfo.io/${{ steps.get-ip.captures.ip }}/geo
+#         http_method: GET
+#         expectations:
+#           - field: StatusCode
+#             operation: Equals
+#             value: "200"
+#     schedule:
+#       initial_delay: 0
+#       interval: 300
+"#;
+
+// Refuses to replace an existing config
+pub fn write_default_config(path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::AlreadyExists => {
+                format!("{:?} already exists, leaving it as it is", path)
+            }
+            _ => format!("Failed to write {:?}: {}", path, e),
+        })?;
+    std::io::Write::write_all(&mut file, DEFAULT_CONFIG_TEMPLATE.as_bytes())?;
+    Ok(())
+}
+
+pub fn remote_config_url(path: &Path) -> Option<&str> {
+    path.to_str()
+        .filter(|path| path.starts_with("http://") || path.starts_with("https://"))
+}
+
+// Runtime monitors aren't merged, there is no directory to keep `xbp.runtime.yaml` in
+pub async fn load_config_from_remote_url(url: &str) -> Result<Config, Box<dyn std::error::Error>> {
+    let proxy = std::env::var(REMOTE_CONFIG_PROXY_VAR)
+        .ok()
+        .filter(|proxy| !proxy.is_empty());
+    let identity = REMOTE_CONFIG_IDENTITY.clone()?;
+    let client = remote_config_client(proxy.as_deref(), identity)?;
+    let fetch_failed = |status: Option<u16>, source: String| ConfigError::RemoteFetchFailed {
+        url: url.to_owned(),
+        status,
+        source,
+    };
+    let response = client
+        .get(url)
+        .send()
+        .await
+        .map_err(|e| fetch_failed(None, e.to_string()))?;
+    let status = response.status();
+    if !status.is_success() {
+        return Err(fetch_failed(
+            Some(status.as_u16()),
+            format!("received status code {}", status),
+        )
+        .into());
+    }
+    let content = response
+        .text()
+        .await
+        .map_err(|e| fetch_failed(Some(status.as_u16()), e.to_string()))?;
+    let (mut config, env_substituted) = parse_config(&content)?;
+    if config.settings.persist_runtime_monitors {
+        warn!("settings.persist_runtime_monitors is ignored for remote configs");
+        config.settings.persist_runtime_monitors = false;
+    }
+    config.validate()?;
+    info!(
+        source = "remote_url",
+        url = %url,
+        probes = config.probes.len(),
+        stories = config.stories.len(),
+        env_substituted,
+        "Loaded config"
+    );
+    Ok(config)
+}
+
+lazy_static! {
+    // Read by the fetch at startup, reloads keep using it. Rotated files need a restart.
+    static ref REMOTE_CONFIG_IDENTITY: Result<Option<reqwest::Identity>, String> =
+        remote_config_identity(
+            std::env::var(REMOTE_CONFIG_CLIENT_CERT_VAR).ok(),
+            std::env::var(REMOTE_CONFIG_CLIENT_KEY_VAR).ok(),
+        );
+}
+
+fn remote_config_identity(
+    cert_path: Option<String>,
+    key_path: Option<String>,
+) -> Result<Option<reqwest::Identity>, String> {
+    let read = |path: &str, var: &str| {
+        std::fs::read(path).map_err(|e| format!("Failed to read {} from {}: {}", path, var, e))
+    };
+    match (
+        cert_path.filter(|path| !path.is_empty()),
+        key_path.filter(|path| !path.is_empty()),
+    ) {
+        (None, None) => Ok(None),
+        (Some(cert_path), Some(key_path)) => {
+            let cert = read(&cert_path, REMOTE_CONFIG_CLIENT_CERT_VAR)?;
+            let key = read(&key_path, REMOTE_CONFIG_CLIENT_KEY_VAR)?;
+            reqwest::Identity::from_pkcs8_pem(&cert, &key)
+                .map(Some)
+                .map_err(|e| {
+                    format!(
+                        "Invalid client certificate in {} and {}, expected a PEM certificate and PKCS#8 PEM key: {}",
+                        REMOTE_CONFIG_CLIENT_CERT_VAR, REMOTE_CONFIG_CLIENT_KEY_VAR, e
+                    )
+                })
+        }
+        _ => Err(format!(
+            "{} and {} must be set together",
+            REMOTE_CONFIG_CLIENT_CERT_VAR, REMOTE_CONFIG_CLIENT_KEY_VAR
+        )),
+    }
+}
+
+// Config fetches get their own client so `XBP_REMOTE_CONFIG_PROXY` and the client certificate
+// don't affect probes. Without a proxy reqwest falls back to `HTTPS_PROXY`, `HTTP_PROXY` and
+// `NO_PROXY`.
+fn remote_config_client(
+    proxy: Option<&str>,
+    identity: Option<reqwest::Identity>,
+) -> Result<reqwest::Client, Box<dyn std::error::Error>> {
+    let mut builder = reqwest::Client::builder()
+        .user_agent(REMOTE_CONFIG_USER_AGENT)
+        .timeout(REMOTE_CONFIG_TIMEOUT);
+    if let Some(identity) = identity {
+        builder = builder.identity(identity);
+    }
+    if let Some(proxy) = proxy {
+        // The proxy url may carry credentials, so it's left out of the error
+        let proxy = reqwest::Proxy::all(proxy)
+            .map_err(|e| format!("Invalid proxy url in {}: {}", REMOTE_CONFIG_PROXY_VAR, e))?;
+        builder = builder.proxy(proxy);
+    }
+    Ok(builder.build()?)
+}
+
+// Also tells whether any `${{ env.* }}` placeholder was substituted
+fn parse_config(content: &str) -> Result<(Config, bool), Box<dyn std::error::Error>> {
+    let (substituted, _) = replace_env_vars(content);
+    let env_substituted = substituted != content;
+    let substituted = migrate_config(&substituted)?;
+    let substituted = resolve_expectation_sets(&substituted)?;
+    let config: Config = serde_yaml::from_str(&substituted).map_err(|e| {
+        // The empty string of an unset variable is the likelier cause than what serde_yaml saw
+        match missing_env_vars(content).into_iter().next() {
+            Some((var, line)) => ConfigError::EnvVarMissing {
+                var,
+                used_in: format!("line {}", line),
+            }
+            .into(),
+            None => name_monitor_in_error(&substituted, e),
+        }
+    })?;
+    // Checked on the document before substitution, to name the variables behind each empty value
+    if let Ok(document) = serde_yaml::from_str::<serde_yaml::Value>(content) {
+        let problems = unusable_substitutions(&document);
+        if !problems.is_empty() {
+            return Err(ConfigValidationError {
+                message: problems.join("; "),
+            }
+            .into());
+        }
+    }
+    if config.settings.strict_config || strict_from_env() {
+        let document = serde_yaml::from_str::<serde_yaml::Value>(&substituted)
+            .map_err(|e| ConfigError::parse(&e))?;
+        let unknown = unknown_fields(&document);
+        if !unknown.is_empty() {
+            return Err(ConfigValidationError {
+                message: unknown.join("; "),
+            }
+            .into());
+        }
+    }
+    Ok((config, env_substituted))
+}
+
+// Brings a config written for an older `version` into the current format before the typed parse.
+// Each breaking change of the format bumps `CURRENT_CONFIG_VERSION` and adds an arm here that
+// rewrites the previous version's document. Configs in the current format are returned as they are.
+fn migrate_config(content: &str) -> Result<Cow<'_, str>, ConfigValidationError> {
+    let Ok(mut document) = serde_yaml::from_str::<serde_yaml::Value>(content) else {
+        // Left to the typed parse, which reports the error
+        return Ok(Cow::Borrowed(content));
+    };
+    let version = match document.get("version") {
+        None | Some(serde_yaml::Value::Null) => return Ok(Cow::Borrowed(content)),
+        Some(serde_yaml::Value::String(version)) => version.clone(),
+        Some(serde_yaml::Value::Number(version)) => version.to_string(),
+        Some(_) => {
+            return Err(ConfigValidationError {
+                message: "version: must be a string, e.g. \"1\"".to_owned(),
+            })
+        }
+    };
+    match version.as_str() {
+        CURRENT_CONFIG_VERSION if document["version"].is_string() => Ok(Cow::Borrowed(content)),
+        // `version: 1` reads as a number, which `Config::version` doesn't accept
+        CURRENT_CONFIG_VERSION => {
+            document["version"] = serde_yaml::Value::String(version);
+            let content = serde_yaml::to_string(&document).map_err(|e| ConfigValidationError {
+                message: e.to_string(),
+            })?;
+            Ok(Cow::Owned(content))
+        }
+        _ => Err(ConfigValidationError {
+            message: format!(
+                "unsupported config version '{}', this build reads version '{}'",
+                version, CURRENT_CONFIG_VERSION
+            ),
+        }),
+    }
+}
+
+// Replaces `expectations: { use: [<set>], also: [...] }` of probes and steps with the flat list of
+// the sets' expectations followed by `also`. Sets are one level deep, a set can't use another set.
+// Configs without references are returned as they are, so parse errors keep pointing at the file.
+fn resolve_expectation_sets(content: &str) -> Result<Cow<'_, str>, ConfigValidationError> {
+    let Ok(mut document) = serde_yaml::from_str::<serde_yaml::Value>(content) else {
+        // Left to the typed parse, which reports the error
+        return Ok(Cow::Borrowed(content));
+    };
+    let sets = document
+        .get("expectation_sets")
+        .cloned()
+        .unwrap_or_default();
+    for (name, set) in sets.as_mapping().into_iter().flatten() {
+        if !set.is_sequence() {
+            return Err(ConfigValidationError {
+                message: format!(
+                    "expectation set '{}': must be a list of expectations, sets can't use other sets",
+                    name.as_str().unwrap_or_default()
+                ),
+            });
+        }
+    }
+
+    let mut resolved_any = false;
+    let probes = document
+        .get_mut("probes")
+        .and_then(serde_yaml::Value::as_sequence_mut);
+    for probe in probes.into_iter().flatten() {
+        resolved_any |= resolve_probe_expectations(&sets, probe)?;
+    }
+    let stories = document
+        .get_mut("stories")
+        .and_then(serde_yaml::Value::as_sequence_mut);
+    for story in stories.into_iter().flatten() {
+        resolved_any |= resolve_story_expectations(&sets, story)?;
+    }
+
+    if !resolved_any {
+        return Ok(Cow::Borrowed(content));
+    }
+    serde_yaml::to_string(&document)
+        .map(Cow::Owned)
+        .map_err(|e| ConfigValidationError {
+            message: format!("failed to resolve expectation sets: {}", e),
+        })
+}
+
+fn monitor_name(monitor: &serde_yaml::Value) -> &str {
+    monitor
+        .get("name")
+        .and_then(serde_yaml::Value::as_str)
+        .unwrap_or_default()
+}
+
+fn resolve_probe_expectations(
+    sets: &serde_yaml::Value,
+    probe: &mut serde_yaml::Value,
+) -> Result<bool, ConfigValidationError> {
+    let owner = format!("probe '{}'", monitor_name(probe));
+    match probe.get_mut("expectations") {
+        Some(expectations) => resolve_expectations(sets, &owner, expectations),
+        None => Ok(false),
+    }
+}
+
+fn resolve_story_expectations(
+    sets: &serde_yaml::Value,
+    story: &mut serde_yaml::Value,
+) -> Result<bool, ConfigValidationError> {
+    let story_name = monitor_name(story).to_owned();
+    let mut resolved_any = false;
+    let steps = story
+        .get_mut("steps")
+        .and_then(serde_yaml::Value::as_sequence_mut);
+    for step in steps.into_iter().flatten() {
+        let owner = format!("story '{}' step '{}'", story_name, monitor_name(step));
+        if let Some(expectations) = step.get_mut("expectations") {
+            resolved_any |= resolve_expectations(sets, &owner, expectations)?;
+        }
+    }
+    Ok(resolved_any)
+}
+
+// Tells whether `expectations` referred to sets and was replaced
+fn resolve_expectations(
+    sets: &serde_yaml::Value,
+    owner: &str,
+    expectations: &mut serde_yaml::Value,
+) -> Result<bool, ConfigValidationError> {
+    let Some(reference) = expectations.as_mapping() else {
+        return Ok(false);
+    };
+    let invalid = |message: String| ConfigValidationError {
+        message: format!("{}: {}", owner, message),
+    };
+    if let Some(key) = reference
+        .keys()
+        .find(|key| !matches!(key.as_str(), Some("use") | Some("also")))
+    {
+        return Err(invalid(format!(
+            "unknown key '{}' in expectations, expected `use` or `also`",
+            key.as_str().unwrap_or_default()
+        )));
+    }
+
+    let mut resolved = vec![];
+    let used = match reference.get("use") {
+        Some(used) => used
+            .as_sequence()
+            .ok_or_else(|| invalid("`expectations.use` must be a list of set names".to_owned()))?
+            .as_slice(),
+        None => &[],
+    };
+    for set_name in used {
+        let set_name = set_name.as_str().unwrap_or_default();
+        match sets.get(set_name).and_then(serde_yaml::Value::as_sequence) {
+            Some(set) => resolved.extend(set.iter().cloned()),
+            None => {
+                let known: Vec<&str> = sets
+                    .as_mapping()
+                    .into_iter()
+                    .flat_map(|sets| sets.keys())
+                    .filter_map(serde_yaml::Value::as_str)
+                    .collect();
+                return Err(invalid(format!(
+                    "unknown expectation set '{}', known sets: {}",
+                    set_name,
+                    if known.is_empty() {
+                        "none".to_owned()
+                    } else {
+                        known.join(", ")
+                    }
+                )));
+            }
+        }
+    }
+    if let Some(also) = reference.get("also") {
+        let also = also.as_sequence().ok_or_else(|| {
+            invalid("`expectations.also` must be a list of expectations".to_owned())
+        })?;
+        resolved.extend(also.iter().cloned());
+    }
+    *expectations = serde_yaml::Value::Sequence(resolved);
+    Ok(true)
+}
+
+pub fn runtime_monitors_path(config_path: &Path) -> PathBuf {
+    config_path.with_file_name(RUNTIME_MONITORS_FILE)
+}
+
+// Adds the persisted runtime monitors to the config. The config file wins on name collisions.
+async fn merge_runtime_monitors(
+    config: &mut Config,
+    path: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let content = match tokio::fs::read_to_string(path).await {
+        Ok(content) => content,
+        Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => {
+            return Err(format!("Failed to read runtime monitors: {:?}, err {}", path, e).into())
+        }
+    };
+    let runtime: RuntimeMonitors = serde_yaml::from_str(&content)
+        .map_err(|e| format!("Invalid runtime monitors: {:?}, err {}", path, e))?;
+    for mut probe in runtime.probes {
+        if config.has_monitor(&probe.name) {
+            warn!(
+                "Skipping runtime-added probe '{}', the config file has a monitor with that name",
+                probe.name
+            );
+            continue;
+        }
+        probe.runtime_added = true;
+        config.probes.push(probe);
+    }
+    for mut story in runtime.stories {
+        if config.has_monitor(&story.name) {
+            warn!(
+                "Skipping runtime-added story '{}', the config file has a monitor with that name",
+                story.name
+            );
+            continue;
+        }
+        story.runtime_added = true;
+        config.stories.push(story);
+    }
+    Ok(())
+}
+
+// Writes the runtime-added monitors of the config next to the config file
+pub async fn save_runtime_monitors(
+    config: &Config,
+    config_path: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let path = runtime_monitors_path(config_path);
+    let mut monitors = config.runtime_monitors();
+    let options = monitors
+        .probes
+        .iter_mut()
+        .filter_map(|probe| probe.with.as_mut())
+        .chain(
+            monitors
+                .stories
+                .iter_mut()
+                .flat_map(|story| {
+                    story
+                        .setup
+                        .iter_mut()
+                        .flatten()
+                        .chain(story.steps.iter_mut())
+                        .chain(story.teardown.iter_mut().flatten())
+                })
+                .filter_map(|step| step.with.as_mut()),
+        );
+    for options in options {
+        options.fold_legacy_timeout();
+    }
+    let content = serde_yaml::to_string(&monitors)?;
+    // Written to a temporary file first so a crash never leaves a truncated file behind
+    let temporary = path.with_extension("yaml.tmp");
+    tokio::fs::write(&temporary, content).await?;
+    tokio::fs::rename(&temporary, &path).await?;
+    Ok(())
+}
+
+// serde_yaml locates errors by index, e.g. `probes[2].with: unknown field `heders``.
+// Prefixes such errors with the name of the probe, story or step they point at.
+fn name_monitor_in_error(content: &str, error: serde_yaml::Error) -> Box<dyn std::error::Error> {
+    let re = regex::Regex::new(r"^(probes|stories)\[(\d+)\](?:\.steps\[(\d+)\])?").unwrap();
+    let message = error.to_string();
+    let Some(caps) = re.captures(&message) else {
+        return ConfigError::parse(&error).into();
+    };
+    let Ok(document) = serde_yaml::from_str::<serde_yaml::Value>(content) else {
+        return ConfigError::parse(&error).into();
+    };
+    let index = |group: usize| {
+        caps.get(group)
+            .and_then(|m| m.as_str().parse::<usize>().ok())
+    };
+    let monitor = index(2).and_then(|i| document.get(&caps[1])?.get(i));
+    let name = |value: Option<&serde_yaml::Value>| {
+        value
+            .and_then(|value| value.get("name")?.as_str())
+            .map(str::to_owned)
+    };
+    let prefix = match (&caps[1], name(monitor), index(3)) {
+        ("probes", Some(probe), _) => format!("probe '{}'", probe),
+        ("stories", Some(story), None) => format!("story '{}'", story),
+        ("stories", Some(story), Some(step)) => {
+            match name(monitor.and_then(|story| story.get("steps")?.get(step))) {
+                Some(step) => format!("story '{}' step '{}'", story, step),
+                None => format!("story '{}'", story),
+            }
+        }
+        _ => return ConfigError::parse(&error).into(),
+    };
+    ConfigValidationError {
+        message: format!("{}: {}", prefix, message),
+    }
+    .into()
+}
+
+// The unset variables of `${{ env.* }}` placeholders, with the line of their first use
+fn missing_env_vars(content: &str) -> Vec<(String, usize)> {
+    let re: regex::Regex = regex::Regex::new(r"\$\{\{\s*env\.(.*?)\s*\}\}").unwrap();
+    let mut missing: Vec<(String, usize)> = vec![];
+    for caps in re.captures_iter(content) {
+        let var_name = &caps[1];
+        if std::env::var(var_name).is_ok() || missing.iter().any(|(var, _)| var == var_name) {
+            continue;
+        }
+        let line = content[..caps.get(0).unwrap().start()]
+            .matches('\n')
+            .count()
+            + 1;
+        missing.push((var_name.to_owned(), line));
+    }
+    missing
+}
+
+// The variables `replace_env_vars` substituted, and those it left empty because they aren't set
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EnvSubstitution {
+    pub substituted: BTreeSet<String>,
+    pub missing: BTreeSet<String>,
+}
+
+// A single pass, a value that contains `${{ env.* }}` is inserted as it is rather than substituted
+// again, so a variable can't pull in the value of another one
+pub fn replace_env_vars(content: &str) -> (String, EnvSubstitution) {
     let re: regex::Regex = regex::Regex::new(r"\$\{\{\s*env\.(.*?)\s*\}\}").unwrap();
+    let mut report = EnvSubstitution::default();
     let replaced = re.replace_all(content, |caps: &regex::Captures| {
         let var_name = &caps[1];
-        // panics on missing enivronment variables, probably desirable?
         match std::env::var(var_name) {
-            Ok(val) => val,
+            Ok(val) => {
+                report.substituted.insert(var_name.to_owned());
+                val
+            }
             Err(_) => {
-                warn!(
-                    "Environment variable {} not found, defaulting to empty string.",
-                    var_name
-                );
+                if report.missing.insert(var_name.to_owned()) {
+                    warn!(
+                        "Environment variable {} not found, defaulting to empty string.",
+                        var_name
+                    );
+                }
                 "".to_string()
             }
         }
     });
-    replaced.to_string()
+    (replaced.into_owned(), report)
 }
 
 #[cfg(test)]
 mod config_tests {
+    use crate::errors::AlertChannel;
+    use crate::probe::model::AlertEvent;
+    use crate::probe::model::ProbeExpectation;
     use crate::{config::load_config, XBP_YAML};
+    use std::collections::BTreeSet;
     use std::env;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
 
     #[tokio::test]
     async fn test_app_yaml_can_load() {
@@ -73,10 +1442,938 @@ mod config_tests {
     async fn test_env_substitution() {
         env::set_var("TEST_ENV_VAR", "test_value");
         let content = "Environment variable ${{ env.TEST_ENV_VAR }} should be replaced even with varying whitespace ${{env.TEST_ENV_VAR}}${{ env.TEST_ENV_VAR}}  ${{env.TEST_ENV_VAR }}${{ env.TEST_ENV_VAR     }}, missing ${{ env.MISSING_VAR }} should be empty";
-        let replaced = super::replace_env_vars(content);
+        let (replaced, report) = super::replace_env_vars(content);
         assert_eq!(
             "Environment variable test_value should be replaced even with varying whitespace test_valuetest_value  test_valuetest_value, missing  should be empty",
             replaced
         );
+        assert_eq!(
+            super::EnvSubstitution {
+                substituted: BTreeSet::from(["TEST_ENV_VAR".to_owned()]),
+                missing: BTreeSet::from(["MISSING_VAR".to_owned()]),
+            },
+            report
+        );
+    }
+
+    #[test]
+    fn test_env_substitution_is_a_single_pass() {
+        env::set_var("TEST_SECRET_VAR", "secret");
+        env::set_var("TEST_REFERRING_VAR", "${{ env.TEST_SECRET_VAR }}");
+
+        let (referring, _) = super::replace_env_vars("${{ env.TEST_REFERRING_VAR }}");
+        let (formed, _) =
+            super::replace_env_vars("${{${{ env.TEST_UNSET_VAR }} env.TEST_SECRET_VAR }}");
+
+        assert_eq!("${{ env.TEST_SECRET_VAR }}", referring);
+        assert_eq!("${{ env.TEST_SECRET_VAR }}", formed);
+    }
+
+    #[test]
+    fn test_invalid_story_expression_names_story() {
+        let config: super::Config = serde_yaml::from_str(
+            r#"
+stories:
+  - name: checkout
+    schedule: { initial_delay: 0, interval: 60 }
+    steps:
+      - name: invoice
+        url: http://localhost/invoice
+        http_method: GET
+        captures: { invoice_total: total }
+    expectations:
+      - expr: "steps.invoice_total >"
+"#,
+        )
+        .unwrap();
+
+        let error = config.validate().unwrap_err().to_string();
+        assert!(
+            error.starts_with("Invalid config: story 'checkout': invalid expression"),
+            "{}",
+            error
+        );
+    }
+
+    async fn load_yaml(content: &str) -> Result<super::Config, String> {
+        let path = env::temp_dir().join(format!("xbp-{}.yaml", uuid::Uuid::new_v4()));
+        std::fs::write(&path, content).unwrap();
+        let result = load_config(&path).await.map_err(|e| e.to_string());
+        std::fs::remove_file(&path).unwrap();
+        result
+    }
+
+    #[tokio::test]
+    async fn test_default_config_template_loads() {
+        let path = env::temp_dir().join(format!("xbp-{}.yaml", uuid::Uuid::new_v4()));
+
+        super::write_default_config(&path).unwrap();
+        let config = load_config(&path).await;
+        let again = super::write_default_config(&path).map_err(|e| e.to_string());
+        std::fs::remove_file(&path).unwrap();
+
+        let config = config.unwrap();
+        assert_eq!(1, config.probes.len());
+        assert_eq!(3, config.probes[0].expectations.as_ref().unwrap().len());
+        assert!(again
+            .unwrap_err()
+            .ends_with("already exists, leaving it as it is"));
+    }
+
+    #[tokio::test]
+    async fn test_with_accepts_numeric_and_string_timeouts() {
+        let config = load_yaml(
+            r#"
+probes:
+  - name: millis
+    url: http://localhost/health
+    schedule: { initial_delay: 0, interval: 60 }
+    with: { timeout: 5000 }
+  - name: text
+    url: http://localhost/health
+    schedule: { initial_delay: 0, interval: 60 }
+    with: { timeout: 5s }
+  - name: legacy
+    url: http://localhost/health
+    schedule: { initial_delay: 0, interval: 60 }
+    with: { timeout_seconds: 5 }
+"#,
+        )
+        .await
+        .unwrap();
+
+        for probe in &config.probes {
+            assert_eq!(
+                Some(std::time::Duration::from_secs(5)),
+                probe.with.as_ref().unwrap().timeout(),
+                "{}",
+                probe.name
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unknown_with_key_names_probe_and_key() {
+        let error = load_yaml(
+            r#"
+probes:
+  - name: healthy
+    url: http://localhost/health
+    schedule: { initial_delay: 0, interval: 60 }
+  - name: typo
+    url: http://localhost/health
+    schedule: { initial_delay: 0, interval: 60 }
+    with:
+      heders: { Accept: application/json }
+"#,
+        )
+        .await
+        .unwrap_err();
+
+        assert!(
+            error.starts_with(
+                "Invalid config: probe 'typo': probes[1].with: unknown field `heders`"
+            ),
+            "{}",
+            error
+        );
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_names_are_rejected() {
+        let probes = load_yaml(
+            r#"
+probes:
+  - name: api
+    url: http://localhost/health
+    schedule: { initial_delay: 0, interval: 60 }
+  - name: web
+    url: http://localhost/health
+    schedule: { initial_delay: 0, interval: 60 }
+  - name: api
+    url: http://localhost/other
+    schedule: { initial_delay: 0, interval: 60 }
+  - name: web
+    url: http://localhost/other
+    schedule: { initial_delay: 0, interval: 60 }
+"#,
+        )
+        .await
+        .unwrap_err();
+        let stories = load_yaml(
+            r#"
+stories:
+  - name: checkout
+    schedule: { initial_delay: 0, interval: 60 }
+    steps: []
+  - name: checkout
+    schedule: { initial_delay: 0, interval: 60 }
+    steps: []
+"#,
+        )
+        .await
+        .unwrap_err();
+
+        let shared = load_yaml(
+            r#"
+probes:
+  - name: checkout
+    url: http://localhost/health
+    schedule: { initial_delay: 0, interval: 60 }
+stories:
+  - name: checkout
+    schedule: { initial_delay: 0, interval: 60 }
+    steps: []
+"#,
+        )
+        .await
+        .unwrap_err();
+
+        assert_eq!("Invalid config: duplicate probe names: api, web", probes);
+        assert_eq!("Invalid config: duplicate story names: checkout", stories);
+        assert_eq!(
+            "Invalid config: names used by both a probe and a story: checkout",
+            shared
+        );
+    }
+
+    #[tokio::test]
+    async fn test_invalid_step_timeout_names_story_and_step() {
+        let error = load_yaml(
+            r#"
+stories:
+  - name: checkout
+    schedule: { initial_delay: 0, interval: 60 }
+    steps:
+      - name: cart
+        url: http://localhost/cart
+        with: { timeout: soon }
+"#,
+        )
+        .await
+        .unwrap_err();
+
+        assert!(
+            error.starts_with("Invalid config: story 'checkout' step 'cart': "),
+            "{}",
+            error
+        );
+        assert!(error.contains("invalid duration 'soon'"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn test_step_body_can_only_be_set_once() {
+        let error = load_yaml(
+            r#"
+stories:
+  - name: checkout
+    schedule: { initial_delay: 0, interval: 60 }
+    steps:
+      - name: order
+        url: http://localhost/orders
+        http_method: POST
+        body: '{"sku": 1}'
+        with: { body: '{"sku": 2}' }
+"#,
+        )
+        .await
+        .unwrap_err();
+
+        assert_eq!(
+            "Invalid config: story 'checkout' step 'order': only one of `body`, `body_template` and `with.body` can be set",
+            error
+        );
+    }
+
+    #[cfg(feature = "scripting")]
+    #[tokio::test]
+    async fn test_script_syntax_error_names_probe() {
+        let error = load_yaml(
+            r#"
+probes:
+  - name: catalog
+    url: http://localhost/items
+    schedule: { initial_delay: 0, interval: 60 }
+    expectations:
+      - field: Script
+        operation: Passes
+        value: "body_json.items.len() >"
+"#,
+        )
+        .await
+        .unwrap_err();
+
+        assert!(
+            error.starts_with("Invalid config: probe 'catalog': invalid script:"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn test_both_timeouts_are_rejected() {
+        let config: super::Config = serde_yaml::from_str(
+            r#"
+probes:
+  - name: both
+    url: http://localhost/health
+    schedule: { initial_delay: 0, interval: 60 }
+    with: { timeout: 5s, timeout_seconds: 5 }
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            "Invalid config: probe 'both': `with` sets both `timeout` and `timeout_seconds`",
+            config.validate().unwrap_err().to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_remote_config_is_fetched() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/xbp.yaml"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"
+probes:
+  - name: api
+    url: http://localhost/health
+    schedule: { initial_delay: 0, interval: 60 }
+"#,
+            ))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let config = load_config(format!("{}/xbp.yaml", mock_server.uri()))
+            .await
+            .unwrap();
+
+        assert_eq!("api", config.probes[0].name);
+    }
+
+    #[tokio::test]
+    async fn test_load_errors_say_what_to_do() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(404))
+            .mount(&mock_server)
+            .await;
+
+        let remote = load_config(format!("{}/xbp.yaml", mock_server.uri()))
+            .await
+            .unwrap_err()
+            .to_string();
+        let missing = load_config(env::temp_dir().join("xbp-missing.yaml"))
+            .await
+            .unwrap_err()
+            .to_string();
+        let relative = load_config("xbp-missing.yaml")
+            .await
+            .unwrap_err()
+            .to_string();
+        let unparsable = load_yaml("probes:\n  - name: api\n    url: [\n")
+            .await
+            .unwrap_err();
+
+        assert!(
+            remote.contains("received status code 404 Not Found"),
+            "{}",
+            remote
+        );
+        assert!(remote.ends_with("check the path."), "{}", remote);
+        assert!(missing.starts_with("Config file not found"), "{}", missing);
+        assert!(missing.ends_with("Check the path passed to `--file`."));
+        assert!(relative.contains("The path is relative to"), "{}", relative);
+        assert!(
+            unparsable.starts_with("Failed to parse config: "),
+            "{}",
+            unparsable
+        );
+        assert!(unparsable.contains("around line "), "{}", unparsable);
+    }
+
+    #[tokio::test]
+    async fn test_unset_env_var_is_named_when_the_config_fails_to_parse() {
+        env::remove_var("XBP_TEST_UNSET_INTERVAL");
+
+        let error = load_yaml(
+            r#"
+probes:
+  - name: api
+    url: http://localhost/health
+    schedule: { initial_delay: 0, interval: ${{ env.XBP_TEST_UNSET_INTERVAL }} }
+"#,
+        )
+        .await
+        .unwrap_err();
+
+        assert_eq!(
+            "Environment variable XBP_TEST_UNSET_INTERVAL is not set, the config uses it in line 5. Export it before starting, or replace the `${{ env.XBP_TEST_UNSET_INTERVAL }}` placeholder with a value.",
+            error
+        );
+    }
+
+    #[tokio::test]
+    async fn test_remote_config_client_uses_proxy() {
+        // Acts as the proxy, a plain http proxy receives the request for the original url
+        let proxy = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/xbp.yaml"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("probes: []"))
+            .expect(1)
+            .mount(&proxy)
+            .await;
+
+        let client = super::remote_config_client(Some(&proxy.uri()), None).unwrap();
+        let response = client
+            .get("http://config.invalid/xbp.yaml")
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!("probes: []", response.text().await.unwrap());
+    }
+
+    #[test]
+    fn test_invalid_remote_config_proxy_is_rejected() {
+        let error = super::remote_config_client(Some("http://[::1"), None)
+            .unwrap_err()
+            .to_string();
+
+        assert!(
+            error.starts_with("Invalid proxy url in XBP_REMOTE_CONFIG_PROXY"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn test_remote_config_client_certificate_needs_both_files() {
+        assert!(super::remote_config_identity(None, None).unwrap().is_none());
+        let error = super::remote_config_identity(Some("client.pem".to_owned()), None)
+            .err()
+            .unwrap();
+        assert_eq!(
+            "XBP_REMOTE_CONFIG_CLIENT_CERT_PATH and XBP_REMOTE_CONFIG_CLIENT_KEY_PATH must be set together",
+            error
+        );
+        let error = super::remote_config_identity(
+            Some("/nonexistent/client.pem".to_owned()),
+            Some("/nonexistent/client.key".to_owned()),
+        )
+        .err()
+        .unwrap();
+        assert!(
+            error.starts_with(
+                "Failed to read /nonexistent/client.pem from XBP_REMOTE_CONFIG_CLIENT_CERT_PATH"
+            ),
+            "{}",
+            error
+        );
+    }
+
+    #[tokio::test]
+    async fn test_schedules_accept_numbers_and_durations() {
+        let config = load_yaml(
+            r#"
+probes:
+  - name: numeric
+    url: http://localhost/health
+    schedule: { initial_delay: 300, interval: 5400 }
+  - name: text
+    url: http://localhost/health
+    schedule: { initial_delay: 5m, interval: 1h30m }
+"#,
+        )
+        .await
+        .unwrap();
+
+        for probe in &config.probes {
+            assert_eq!(
+                std::time::Duration::from_secs(300),
+                probe.schedule.initial_delay,
+                "{}",
+                probe.name
+            );
+            assert_eq!(
+                std::time::Duration::from_secs(5400),
+                probe.schedule.interval,
+                "{}",
+                probe.name
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fast_intervals_need_allow_fast() {
+        let rejected = load_yaml(
+            r#"
+stories:
+  - name: checkout
+    schedule: { initial_delay: 0, interval: 50ms }
+    steps: []
+"#,
+        )
+        .await
+        .unwrap_err();
+        let zero = load_yaml(
+            r#"
+probes:
+  - name: api
+    url: http://localhost/health
+    schedule: { initial_delay: 0, interval: 0 }
+"#,
+        )
+        .await
+        .unwrap_err();
+        let allowed = load_yaml(
+            r#"
+probes:
+  - name: api
+    url: http://localhost/health
+    schedule: { initial_delay: 0, interval: 50ms, allow_fast: true }
+"#,
+        )
+        .await;
+
+        assert_eq!(
+            "Invalid config: story 'checkout': schedule.interval of 50ms is shorter than 100ms, set `allow_fast: true` to allow it",
+            rejected
+        );
+        assert!(zero.starts_with("Invalid config: probe 'api': schedule.interval of 0s"));
+        assert!(allowed.is_ok(), "{:?}", allowed);
+    }
+
+    #[test]
+    fn test_alert_channel_references() {
+        std::env::set_var(
+            "TEST_ONCALL_WEBHOOK",
+            "https://hooks.slack.com/services/oncall",
+        );
+        let config: super::Config = serde_yaml::from_str(
+            &super::replace_env_vars(
+                r#"
+alert_channels:
+  oncall_slack:
+    type: slack
+    webhook_url: ${{ env.TEST_ONCALL_WEBHOOK }}
+probes:
+  - name: api
+    url: http://localhost/health
+    schedule: { initial_delay: 0, interval: 60 }
+    alerts:
+      - channel: oncall_slack
+        on: [failure]
+      - url: http://localhost/one-off
+"#,
+            )
+            .0,
+        )
+        .unwrap();
+        config.validate().unwrap();
+
+        let alerts = config.resolve_alerts(&config.probes[0].alerts).unwrap();
+
+        assert_eq!("https://hooks.slack.com/services/oncall", alerts[0].url);
+        assert_eq!(Some(AlertChannel::Slack), alerts[0].channel_type);
+        assert!(alerts[0].sends(AlertEvent::Failure));
+        assert!(!alerts[0].sends(AlertEvent::Recovery));
+        assert_eq!("http://localhost/one-off", alerts[1].url);
+    }
+
+    #[test]
+    fn test_unknown_alert_channel_lists_known_channels() {
+        let config: super::Config = serde_yaml::from_str(
+            r#"
+alert_channels:
+  oncall_slack: { type: slack, webhook_url: "https://hooks.slack.com/services/oncall" }
+  audit: { type: stdout }
+stories:
+  - name: checkout
+    schedule: { initial_delay: 0, interval: 60 }
+    steps: []
+    alerts:
+      - channel: oncall
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            "Invalid config: story 'checkout': unknown alert channel 'oncall', known channels: audit, oncall_slack",
+            config.validate().unwrap_err().to_string()
+        );
+    }
+
+    #[test]
+    fn test_file_alert_needs_path() {
+        let config: super::Config = serde_yaml::from_str(
+            r#"
+probes:
+  - name: api
+    url: http://localhost/health
+    schedule: { initial_delay: 0, interval: 60 }
+    alerts:
+      - type: stdout
+      - type: file
+        max_size_mb: 10
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            "Invalid config: probe 'api': `file` alerts need a `path`",
+            config.validate().unwrap_err().to_string()
+        );
+    }
+
+    #[test]
+    fn test_opsgenie_alert_needs_api_key() {
+        let config: super::Config = serde_yaml::from_str(
+            r#"
+probes:
+  - name: api
+    url: http://localhost/health
+    schedule: { initial_delay: 0, interval: 60 }
+    alerts:
+      - type: opsgenie
+        region: eu
+        priority: P1
+        responders: [{ type: team, name: payments }]
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            "Invalid config: probe 'api': `opsgenie` alerts need an `api_key`",
+            config.validate().unwrap_err().to_string()
+        );
+    }
+
+    #[test]
+    fn test_metrics_from_body_are_validated() {
+        let config = |metrics: &str, sensitive: bool| -> super::Config {
+            serde_yaml::from_str(&format!(
+                r#"
+probes:
+  - name: queue
+    url: http://localhost/health
+    schedule: {{ initial_delay: 0, interval: 60 }}
+    sensitive: {}
+    metrics_from_body: {}
+"#,
+                sensitive, metrics
+            ))
+            .unwrap()
+        };
+
+        assert!(config(r#"{ queue_depth: "$.queue_depth" }"#, false)
+            .validate()
+            .is_ok());
+        assert_eq!(
+            "Invalid config: probe 'queue': invalid metric name 'queue-depth', use letters, digits and underscores and don't start with a digit",
+            config(r#"{ queue-depth: "$.queue_depth" }"#, false)
+                .validate()
+                .unwrap_err()
+                .to_string()
+        );
+        assert_eq!(
+            "Invalid config: probe 'queue': `metrics_from_body` of a sensitive probe needs `allow_sensitive_extraction: true`",
+            config(r#"{ queue_depth: "$.queue_depth" }"#, true)
+                .validate()
+                .unwrap_err()
+                .to_string()
+        );
+    }
+
+    #[test]
+    fn test_prefetch_placeholders_need_an_extraction() {
+        let config = |with: &str| -> super::Config {
+            serde_yaml::from_str(&format!(
+                r#"
+probes:
+  - name: login
+    url: http://localhost/login
+    http_method: POST
+    schedule: {{ initial_delay: 0, interval: 60 }}
+    with: {}
+"#,
+                with
+            ))
+            .unwrap()
+        };
+
+        assert!(config(
+            r#"{ headers: { X-CSRF-Token: "${{ prefetch.csrf }}" }, prefetch: { url: "http://localhost/form", extract: { csrf: { cookie: XSRF-TOKEN } } } }"#
+        )
+        .validate()
+        .is_ok());
+        assert_eq!(
+            "Invalid config: probe 'login': `with.prefetch` doesn't extract `token`",
+            config(
+                r#"{ body: "_token=${{ prefetch.token }}", prefetch: { url: "http://localhost/form", extract: { csrf: { cookie: XSRF-TOKEN } } } }"#
+            )
+            .validate()
+            .unwrap_err()
+            .to_string()
+        );
+        assert_eq!(
+            "Invalid config: probe 'login': `${{ prefetch.csrf }}` needs a `with.prefetch` block",
+            config(r#"{ headers: { X-CSRF-Token: "${{ prefetch.csrf }}" } }"#)
+                .validate()
+                .unwrap_err()
+                .to_string()
+        );
+        // Invalid patterns fail loading rather than every run with "has no match"
+        assert!(config(
+            r#"{ prefetch: { url: "http://localhost/form", extract: { csrf: { regex: 'name="csrf" value="([^"]+)"' } } } }"#
+        )
+        .validate()
+        .is_ok());
+        assert!(config(
+            r#"{ prefetch: { url: "http://localhost/form", extract: { csrf: { regex: "value=\"([^\"]+\"" } } } }"#
+        )
+        .validate()
+        .unwrap_err()
+        .to_string()
+        .starts_with("Invalid config: probe 'login': `prefetch.extract.csrf`: regex parse error"));
+        assert!(config(
+            r#"{ prefetch: { url: "http://localhost/form", extract: { csrf: { selector: "input[name=" } } } }"#
+        )
+        .validate()
+        .unwrap_err()
+        .to_string()
+        .starts_with("Invalid config: probe 'login': `prefetch.extract.csrf`: invalid selector 'input[name='"));
+    }
+
+    #[tokio::test]
+    async fn test_strict_config_rejects_unknown_fields() {
+        let content = r#"
+probes:
+  - name: api
+    url: http://localhost/health
+    schedule: { initial_delay: 0, interval: 60 }
+    retry_count: 3
+"#;
+        assert!(load_yaml(content).await.is_ok());
+
+        let strict = format!("settings: {{ strict_config: true }}{}", content);
+        assert_eq!(
+            "Invalid config: probe 'api': unknown field `retry_count`",
+            load_yaml(&strict).await.unwrap_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_expectation_sets_are_flattened() {
+        let config = load_yaml(
+            r#"
+expectation_sets:
+  json_health:
+    - { field: StatusCode, operation: Equals, value: "200" }
+    - { field: Body, operation: Contains, value: '"status":"ok"' }
+probes:
+  - name: api
+    url: http://localhost/health
+    schedule: { initial_delay: 0, interval: 60 }
+    expectations:
+      use: [json_health]
+      also:
+        - { field: Body, operation: NotContains, value: degraded }
+stories:
+  - name: checkout
+    schedule: { initial_delay: 0, interval: 60 }
+    steps:
+      - name: cart
+        url: http://localhost/cart
+        http_method: GET
+        expectations: { use: [json_health] }
+"#,
+        )
+        .await
+        .unwrap();
+
+        let values = |expectations: &Option<Vec<ProbeExpectation>>| -> Vec<String> {
+            expectations
+                .iter()
+                .flatten()
+                .map(|expectation| expectation.value.clone())
+                .collect()
+        };
+        assert_eq!(
+            vec!["200", r#""status":"ok""#, "degraded"],
+            values(&config.probes[0].expectations)
+        );
+        assert_eq!(
+            vec!["200", r#""status":"ok""#],
+            values(&config.stories[0].steps[0].expectations)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unknown_expectation_set_is_rejected() {
+        let error = load_yaml(
+            r#"
+expectation_sets:
+  json_health:
+    - { field: StatusCode, operation: Equals, value: "200" }
+stories:
+  - name: checkout
+    schedule: { initial_delay: 0, interval: 60 }
+    steps:
+      - name: cart
+        url: http://localhost/cart
+        http_method: GET
+        expectations: { use: [json_healht] }
+"#,
+        )
+        .await
+        .unwrap_err();
+
+        assert_eq!(
+            "Invalid config: story 'checkout' step 'cart': unknown expectation set 'json_healht', known sets: json_health",
+            error
+        );
+    }
+
+    #[tokio::test]
+    async fn test_expectation_sets_cant_use_sets() {
+        let error = load_yaml(
+            r#"
+expectation_sets:
+  base:
+    - { field: StatusCode, operation: Equals, value: "200" }
+  nested:
+    use: [base]
+probes:
+  - name: api
+    url: http://localhost/health
+    schedule: { initial_delay: 0, interval: 60 }
+"#,
+        )
+        .await
+        .unwrap_err();
+
+        assert!(
+            error.starts_with("Invalid config: expectation set 'nested': must be a list"),
+            "{}",
+            error
+        );
+    }
+
+    #[tokio::test]
+    async fn test_relative_step_urls_need_a_base_url() {
+        let error = load_yaml(
+            r#"
+stories:
+  - name: checkout
+    schedule: { initial_delay: 0, interval: 60 }
+    steps:
+      - name: cart
+        url: /cart
+"#,
+        )
+        .await
+        .unwrap_err();
+
+        assert_eq!(
+            "Invalid config: story 'checkout': step 'cart' has the relative url '/cart' but the story has no `base_url`",
+            error
+        );
+    }
+
+    #[tokio::test]
+    async fn test_config_version() {
+        let probes = r#"
+probes:
+  - name: api
+    url: http://localhost/health
+    http_method: GET
+    schedule: { initial_delay: 0, interval: 60 }
+"#;
+
+        let implicit = load_yaml(probes).await.unwrap();
+        assert_eq!(None, implicit.version);
+        for version in ["version: \"1\"", "version: 1"] {
+            let config = load_yaml(&format!("{}\n{}", version, probes))
+                .await
+                .unwrap();
+            assert_eq!(Some("1"), config.version.as_deref());
+            assert_eq!("api", config.probes[0].name);
+        }
+
+        let error = load_yaml(&format!("version: \"2\"\n{}", probes))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            "Invalid config: unsupported config version '2', this build reads version '1'",
+            error
+        );
+    }
+
+    mod replace_env_vars_properties {
+        use proptest::prelude::*;
+        use std::env;
+
+        use crate::config::replace_env_vars;
+
+        // Whole templates of set variables next to fragments of the template syntax. Substitution
+        // is a single pass, so there's no `$` outside the templates to form new ones with.
+        const FRAGMENTS: &[&str] = &[
+            "${{ env.PROPTEST_A }}",
+            "${{env.PROPTEST_B}}",
+            "${{  env.PROPTEST_A}}",
+            "env.",
+            " env.",
+            "}}",
+            " }}",
+            "}",
+            "{",
+            "{{",
+            " ",
+            "\n",
+            "PROPTEST_A",
+            "PROPTEST_B",
+            "text",
+            ".",
+        ];
+
+        fn template_content() -> impl Strategy<Value = String> {
+            prop::collection::vec(prop::sample::select(FRAGMENTS), 0..24)
+                .prop_map(|fragments| fragments.concat())
+        }
+
+        fn set_referenced_vars() {
+            env::set_var("PROPTEST_A", "value_a");
+            env::set_var("PROPTEST_B", "value_b");
+        }
+
+        proptest! {
+            #[test]
+            fn leaves_no_unsubstituted_templates(content in template_content()) {
+                set_referenced_vars();
+                let template = regex::Regex::new(r"\$\{\{\s*env\.(.*?)\s*\}\}").unwrap();
+
+                let (replaced, _) = replace_env_vars(&content);
+
+                prop_assert!(!template.is_match(&replaced), "left a template in {:?}", replaced);
+            }
+
+            #[test]
+            fn never_panics(content in any::<String>()) {
+                replace_env_vars(&content);
+            }
+
+            #[test]
+            fn never_panics_on_template_like_input(content in template_content(), name in any::<String>()) {
+                replace_env_vars(&format!("{content}${{{{ env.{name} }}}}{content}"));
+            }
+
+            #[test]
+            fn content_without_templates_is_unchanged(content in any::<String>().prop_filter("no template start", |content| !content.contains("${{"))) {
+                prop_assert_eq!(&content, &replace_env_vars(&content).0);
+            }
+        }
     }
 }
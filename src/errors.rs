@@ -1,19 +1,10 @@
 use std::error::Error;
+use std::path::PathBuf;
 
-use crate::probe::model::{ExpectField, ExpectOperation};
-
-pub trait MapToSendError<T, E> {
-    fn map_to_send_err(self) -> Result<T, Box<dyn std::error::Error + Send>>;
-}
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
 
-impl<T, E> MapToSendError<T, E> for Result<T, E>
-where
-    E: std::error::Error + Send + 'static,
-{
-    fn map_to_send_err(self) -> Result<T, Box<dyn std::error::Error + Send>> {
-        self.map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send>)
-    }
-}
+use crate::probe::model::{ExpectField, ExpectOperation};
 
 pub struct ExpectationFailedError {
     pub field: ExpectField,
@@ -21,12 +12,17 @@ pub struct ExpectationFailedError {
     pub body: String,
     pub operation: ExpectOperation,
     pub status_code: u32,
+    // What a `Script` expectation failed with, its error string or the limit it hit
+    pub reason: Option<String>,
 }
 
 impl Error for ExpectationFailedError {}
 
 impl std::fmt::Display for ExpectationFailedError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if let Some(reason) = &self.reason {
+            return write!(f, "Script expectation failed: {}", reason);
+        }
         write!(
             f,
             "Failed to meet expectation for field '{:?}' with operation {:?} {:?}.",
@@ -37,6 +33,13 @@ impl std::fmt::Display for ExpectationFailedError {
 
 impl std::fmt::Debug for ExpectationFailedError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if let Some(reason) = &self.reason {
+            return write!(
+                f,
+                "Script expectation failed: {}. Received: status '{}', body '{}'",
+                reason, self.status_code, self.body
+            );
+        }
         write!(
             f,
             "Failed to meet expectation for field '{:?}' with operation {:?} {:?}. Received: status '{}', body '{}'",
@@ -44,3 +47,313 @@ impl std::fmt::Debug for ExpectationFailedError {
         )
     }
 }
+
+// A config that parses, but can't be run as written
+#[derive(Debug)]
+pub struct ConfigValidationError {
+    pub message: String,
+}
+
+impl Error for ConfigValidationError {}
+
+impl std::fmt::Display for ConfigValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Invalid config: {}", self.message)
+    }
+}
+
+// A result store that can't be opened, written or read, see `storage`
+#[derive(Debug)]
+pub struct StorageError {
+    pub message: String,
+}
+
+impl Error for StorageError {}
+
+impl std::fmt::Display for StorageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Result storage failed: {}", self.message)
+    }
+}
+
+// Why a config couldn't be loaded at all, each with what to do about it. Configs that load but
+// can't run as written are a `ConfigValidationError`.
+pub enum ConfigError {
+    FileNotFound {
+        path: PathBuf,
+        hint: String,
+    },
+    ReadFailed {
+        path: PathBuf,
+        source: String,
+    },
+    // `status` is unset when no response arrived
+    RemoteFetchFailed {
+        url: String,
+        status: Option<u16>,
+        source: String,
+    },
+    // serde_yaml's message, which names the line and column as well
+    ParseError {
+        source: String,
+        line: Option<usize>,
+        column: Option<usize>,
+    },
+    // A `${{ env.X }}` placeholder of an unset variable, substituted with an empty string, in a
+    // config that then failed to parse
+    EnvVarMissing {
+        var: String,
+        used_in: String,
+    },
+}
+
+impl ConfigError {
+    pub fn parse(error: &serde_yaml::Error) -> ConfigError {
+        let location = error.location();
+        ConfigError::ParseError {
+            source: error.to_string(),
+            line: location.as_ref().map(|location| location.line()),
+            column: location.as_ref().map(|location| location.column()),
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ConfigError::FileNotFound { path, hint } => {
+                write!(f, "Config file not found: {:?}. {}", path, hint)
+            }
+            ConfigError::ReadFailed { path, source } => write!(
+                f,
+                "Failed to read config file {:?}: {}. Check that it's a file this user can read.",
+                path, source
+            ),
+            ConfigError::RemoteFetchFailed {
+                url,
+                status,
+                source,
+            } => {
+                write!(f, "Failed to fetch config from {}: {}. ", url, source)?;
+                match status {
+                    Some(401 | 403) => f.write_str(
+                        "The server refused the request, check the credentials in the url.",
+                    ),
+                    Some(404) => f.write_str("The server has no config at that url, check the path."),
+                    Some(_) => f.write_str("Check the server's logs, or load a local file with `--file <path>` meanwhile."),
+                    None => write!(
+                        f,
+                        "Check that the host resolves and is reachable from here, set {} when it's only reachable through a proxy.",
+                        crate::config::REMOTE_CONFIG_PROXY_VAR
+                    ),
+                }
+            }
+            ConfigError::ParseError { source, line, .. } => {
+                write!(f, "Failed to parse config: {}. ", source)?;
+                match line {
+                    Some(line) => write!(
+                        f,
+                        "Check the indentation and quoting around line {}, and the field names against the README.",
+                        line
+                    ),
+                    None => f.write_str(
+                        "Check the indentation and quoting, and the field names against the README.",
+                    ),
+                }
+            }
+            ConfigError::EnvVarMissing { var, used_in } => write!(
+                f,
+                "Environment variable {} is not set, the config uses it in {}. Export it before starting, or replace the `${{{{ env.{} }}}}` placeholder with a value.",
+                var, used_in, var
+            ),
+        }
+    }
+}
+
+// `main` returns the error, which prints its Debug form
+impl std::fmt::Debug for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        std::fmt::Display::fmt(self, f)
+    }
+}
+
+// Why a monitor couldn't be added or removed through the API
+#[derive(Debug)]
+pub enum RuntimeMonitorError {
+    // Another probe or story already has the name
+    NameTaken(String),
+    // The monitor comes from the config file, only runtime-added monitors can be removed
+    Configured(String),
+    NotFound(String),
+    Invalid(ConfigValidationError),
+}
+
+impl Error for RuntimeMonitorError {}
+
+impl std::fmt::Display for RuntimeMonitorError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            RuntimeMonitorError::NameTaken(name) => {
+                write!(f, "A monitor named '{}' already exists", name)
+            }
+            RuntimeMonitorError::Configured(name) => write!(
+                f,
+                "'{}' is configured in the config file and can't be removed through the API",
+                name
+            ),
+            RuntimeMonitorError::NotFound(name) => write!(f, "No monitor named '{}'", name),
+            RuntimeMonitorError::Invalid(e) => e.fmt(f),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum AlertChannel {
+    Webhook,
+    Slack,
+    Discord,
+    // Alerts created through the Opsgenie Alert API and closed on recovery
+    Opsgenie,
+    // JSON lines written locally, for deployments that can't reach any webhook
+    File,
+    Stdout,
+}
+
+impl AlertChannel {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AlertChannel::Webhook => "webhook",
+            AlertChannel::Slack => "slack",
+            AlertChannel::Discord => "discord",
+            AlertChannel::Opsgenie => "opsgenie",
+            AlertChannel::File => "file",
+            AlertChannel::Stdout => "stdout",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum AlertErrorCause {
+    // The channel answered with a non-success status code
+    HttpStatus {
+        status_code: u16,
+        response_excerpt: String,
+    },
+    Timeout,
+    Request(reqwest::Error),
+    Serialization(serde_json::Error),
+    Template(TemplateError),
+}
+
+impl From<reqwest::Error> for AlertErrorCause {
+    fn from(err: reqwest::Error) -> AlertErrorCause {
+        if err.is_timeout() {
+            AlertErrorCause::Timeout
+        } else {
+            AlertErrorCause::Request(err)
+        }
+    }
+}
+
+impl From<serde_json::Error> for AlertErrorCause {
+    fn from(err: serde_json::Error) -> AlertErrorCause {
+        AlertErrorCause::Serialization(err)
+    }
+}
+
+impl From<TemplateError> for AlertErrorCause {
+    fn from(err: TemplateError) -> AlertErrorCause {
+        AlertErrorCause::Template(err)
+    }
+}
+
+// A recovery or report template uses a placeholder there's no value for, e.g. a typo
+#[derive(Debug, Clone, PartialEq)]
+pub struct TemplateError {
+    pub placeholder: String,
+}
+
+impl Error for TemplateError {}
+
+impl std::fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "unknown placeholder '{}'", self.placeholder)
+    }
+}
+
+// Failure to deliver an alert to a single channel for a single monitor
+#[derive(Debug)]
+pub struct AlertError {
+    pub channel: AlertChannel,
+    pub monitor_name: String,
+    pub cause: AlertErrorCause,
+}
+
+impl AlertError {
+    pub fn new(channel: AlertChannel, monitor_name: &str, cause: AlertErrorCause) -> AlertError {
+        AlertError {
+            channel,
+            monitor_name: monitor_name.to_owned(),
+            cause,
+        }
+    }
+
+    // Low cardinality classification, used as the `error.kind` metric attribute
+    pub fn kind(&self) -> &'static str {
+        match self.cause {
+            AlertErrorCause::HttpStatus { .. } => "http_status",
+            AlertErrorCause::Timeout => "timeout",
+            AlertErrorCause::Request(_) => "request",
+            AlertErrorCause::Serialization(_) => "serialization",
+            AlertErrorCause::Template(_) => "template",
+        }
+    }
+
+    pub fn status_code(&self) -> Option<u16> {
+        match self.cause {
+            AlertErrorCause::HttpStatus { status_code, .. } => Some(status_code),
+            _ => None,
+        }
+    }
+}
+
+impl Error for AlertError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match &self.cause {
+            AlertErrorCause::Request(err) => Some(err),
+            AlertErrorCause::Serialization(err) => Some(err),
+            AlertErrorCause::Template(err) => Some(err),
+            AlertErrorCause::HttpStatus { .. } | AlertErrorCause::Timeout => None,
+        }
+    }
+}
+
+impl std::fmt::Display for AlertError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "Failed to send {} alert for '{}': ",
+            self.channel.as_str(),
+            self.monitor_name
+        )?;
+        match &self.cause {
+            AlertErrorCause::HttpStatus {
+                status_code,
+                response_excerpt,
+            } => write!(
+                f,
+                "received status code {} with body '{}'",
+                status_code, response_excerpt
+            ),
+            AlertErrorCause::Timeout => write!(f, "request timed out"),
+            AlertErrorCause::Request(err) => write!(f, "request failed: {}", err),
+            AlertErrorCause::Serialization(err) => {
+                write!(f, "could not serialize payload: {}", err)
+            }
+            AlertErrorCause::Template(err) => write!(f, "could not render template: {}", err),
+        }
+    }
+}
@@ -0,0 +1,80 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+// A contiguous period during which a monitor was failing. Opens when the monitor starts failing
+// and closes once it has recovered, so the recovery threshold is part of the incident.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Incident {
+    pub id: Uuid,
+    pub monitor: String,
+    pub started: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ended: Option<DateTime<Utc>>,
+    // Until the end, or until the latest run while the incident is open
+    pub duration_seconds: i64,
+    pub failures: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub first_error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub acknowledged: Option<IncidentAck>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IncidentAck {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub by: Option<String>,
+    pub at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IncidentState {
+    Open,
+    Closed,
+}
+
+impl Incident {
+    pub fn open(monitor: &str, started: DateTime<Utc>) -> Incident {
+        Incident {
+            id: Uuid::new_v4(),
+            monitor: monitor.to_owned(),
+            started,
+            ended: None,
+            duration_seconds: 0,
+            failures: 0,
+            first_error: None,
+            last_error: None,
+            acknowledged: None,
+        }
+    }
+
+    pub fn state(&self) -> IncidentState {
+        match self.ended {
+            Some(_) => IncidentState::Closed,
+            None => IncidentState::Open,
+        }
+    }
+
+    // Takes a run of the monitor into account while the incident is open. Successful runs only
+    // extend the duration, they happen while the monitor works towards its recovery threshold.
+    pub fn record_run(&mut self, success: bool, error: Option<&str>, at: DateTime<Utc>) {
+        if !success {
+            self.failures += 1;
+            if self.first_error.is_none() {
+                self.first_error = error.map(str::to_owned);
+            }
+            if error.is_some() {
+                self.last_error = error.map(str::to_owned);
+            }
+        }
+        self.duration_seconds = (at - self.started).num_seconds();
+    }
+
+    pub fn close(&mut self, at: DateTime<Utc>) {
+        self.ended = Some(at);
+        self.duration_seconds = (at - self.started).num_seconds();
+    }
+}
@@ -0,0 +1,29 @@
+pub mod alerts;
+pub mod app_state;
+pub mod audit;
+pub mod build_info;
+pub mod config;
+pub mod errors;
+pub mod incidents;
+pub mod otel;
+pub mod probe;
+pub mod reports;
+pub mod result_store;
+pub mod self_alerts;
+pub mod status_summary;
+pub mod storage;
+pub mod strict_config;
+pub mod substitution_check;
+pub mod wait_healthy;
+pub mod web_server;
+
+// What crates embedding xbp-monitoring as a sub-router need
+pub use app_state::AppState;
+pub use config::{load_config, Config};
+pub use otel::metrics::{Metrics, MonitorStatus};
+pub use web_server::{app_router, app_router_with_extra_routes};
+
+pub const XBP_YAML: &str = "xbp.yaml";
+
+#[cfg(any(test, feature = "test-utils"))]
+pub mod test_utils;
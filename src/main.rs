@@ -1,21 +1,24 @@
-mod alerts;
-mod app_state;
-mod config;
-mod errors;
-mod otel;
-mod probe;
-mod web_server;
-
 use clap::Parser;
-use probe::schedule::schedule_probes;
-use probe::schedule::schedule_stories;
+use std::path::Path;
 use std::sync::Arc;
-use web_server::start_axum_server;
-use web_server::start_prometheus_server;
+use std::time::Duration;
+use xbp_monitoring::build_info::InstanceInfo;
+use xbp_monitoring::otel;
+use xbp_monitoring::self_alerts::{raise, SelfAlertKind};
+use xbp_monitoring::storage;
+use xbp_monitoring::wait_healthy::{
+    gating_config, wait_healthy, WaitOptions, DEFAULT_RETRY_INTERVAL,
+};
+use xbp_monitoring::web_server::start_axum_server;
+use xbp_monitoring::web_server::start_prometheus_server;
 
-use crate::{app_state::AppState, config::load_config};
+use xbp_monitoring::{
+    config::{load_config, write_default_config, Config},
+    AppState, XBP_YAML,
+};
 
-const XBP_YAML: &str = "xbp.yml";
+// How long in-flight runs and each telemetry provider get to finish on shutdown
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
@@ -23,32 +26,140 @@ struct Args {
     // Test definition file to execute
     #[arg(short, long, default_value = XBP_YAML)]
     file: String,
+    // Run the monitors until each passed once and exit, 0 when all did and 1 otherwise
+    #[arg(long)]
+    wait_healthy: bool,
+    // How long `--wait-healthy` waits, e.g. "300s" or "5m"
+    #[arg(long, value_parser = humantime::parse_duration, default_value = "5m")]
+    timeout: Duration,
+    // Time between `--wait-healthy` attempts, monitor schedules are ignored
+    #[arg(long, value_parser = humantime::parse_duration)]
+    retry_interval: Option<Duration>,
+    // Only monitors with this tag gate `--wait-healthy`: `key=value` or `key`, repeatable
+    #[arg(long)]
+    only: Vec<String>,
+    // Write a commented starter config to `--file` and exit, an existing file is left alone
+    #[arg(long)]
+    init: bool,
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
+// The runtime is built from `settings.runtime`, so the config is loaded before it exists
+fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args = Args::parse();
+    if args.init {
+        write_default_config(Path::new(&args.file))?;
+        println!("Wrote a starter config to {}", args.file);
+        return Ok(());
+    }
+    let bootstrap = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    // The OpenTelemetry subscriber needs the runtime, a plain one logs warnings of the config load
+    let config = tracing::subscriber::with_default(tracing_subscriber::fmt().finish(), || {
+        bootstrap.block_on(load_config(&args.file))
+    })?;
+    drop(bootstrap);
+
+    config
+        .settings
+        .runtime
+        .runtime_builder()
+        .build()?
+        .block_on(run(args, config))
+}
+
+async fn run(args: Args, config: Config) -> Result<(), Box<dyn std::error::Error>> {
+    if args.wait_healthy {
+        return run_wait_healthy(args, config).await;
+    }
     let otel_state = otel::init();
+    // Shares this runtime with the API server
     if let Some(registry) = &otel_state.metrics.registry {
         tokio::spawn(start_prometheus_server(registry.clone()));
     }
 
-    let config = load_config(args.file).await?;
-
-    let app_state = Arc::new(AppState::new(config));
+    // Opened before the scheduler starts, so no result misses the database
+    let storage = storage::open(&config.settings.storage).await;
+    let strict_storage = config.settings.storage.strict;
+    let mut app_state = AppState::new(config).with_config_path(args.file);
+    let storage_error = match storage {
+        Ok(Some(storage)) => {
+            app_state = app_state.with_storage(storage);
+            None
+        }
+        Ok(None) => None,
+        Err(e) if strict_storage => return Err(e.into()),
+        Err(e) => Some(e),
+    };
+    let app_state = Arc::new(app_state);
+    tracing::info!("{}", InstanceInfo::of(&app_state).banner());
+    if let Some(e) = storage_error {
+        raise(
+            &app_state,
+            SelfAlertKind::ResultStorage,
+            format!("{}, keeping results in memory only", e),
+        );
+    }
+    for fallback in &otel_state.exporter_fallbacks {
+        raise(
+            &app_state,
+            SelfAlertKind::ExporterFallback,
+            format!("Can't build the {}, exporting nothing", fallback),
+        );
+    }
 
-    start_monitoring(app_state.clone()).await?;
+    app_state.start_monitoring();
 
-    start_axum_server(app_state.clone()).await;
+    tokio::select! {
+        _ = start_axum_server(app_state.clone(), None) => {}
+        _ = shutdown_signal() => tracing::info!("Shutting down"),
+    }
+    app_state.stop_monitoring_graceful(SHUTDOWN_TIMEOUT).await;
+    otel_state.shutdown(SHUTDOWN_TIMEOUT).await;
 
     Ok(())
 }
 
-async fn start_monitoring(app_state: Arc<AppState>) -> Result<(), Box<dyn std::error::Error>> {
-    schedule_probes(&app_state.config.probes, app_state.clone());
-    schedule_stories(&app_state.config.stories, app_state.clone());
-    Ok(())
+// Ctrl-C, or SIGTERM as sent by container runtimes
+async fn shutdown_signal() {
+    let interrupt = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(_) => std::future::pending().await,
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    tokio::select! {
+        _ = interrupt => {}
+        _ = terminate => {}
+    }
 }
 
-#[cfg(test)]
-mod test_utils;
+// No scheduler and no servers, only the convergence loop
+async fn run_wait_healthy(args: Args, config: Config) -> Result<(), Box<dyn std::error::Error>> {
+    let otel_state = otel::init();
+    let app_state = Arc::new(AppState::new(gating_config(config, &args.only)));
+    let options = WaitOptions {
+        timeout: args.timeout,
+        retry_interval: args.retry_interval.unwrap_or(DEFAULT_RETRY_INTERVAL),
+    };
+    let interrupt = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+
+    let report = wait_healthy(app_state, &options, interrupt).await;
+    println!("{}", report);
+    // Exiting skips destructors, the spans of the runs would be lost
+    otel_state.shutdown(SHUTDOWN_TIMEOUT).await;
+    if !report.healthy() {
+        std::process::exit(1);
+    }
+    Ok(())
+}
@@ -0,0 +1,104 @@
+// Trace ids of recent runs, attached to the buckets of the `duration` histogram when `/metrics` is
+// scraped as OpenMetrics. The Prometheus exporter has no exemplars of its own.
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+use chrono::{DateTime, Utc};
+use lazy_static::lazy_static;
+use opentelemetry::KeyValue;
+
+// Runs remembered per series, enough to cover the buckets a monitor's durations spread over
+const RECENT_RUNS: usize = 32;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Exemplar {
+    pub trace_id: String,
+    // In the histogram's `duration_unit`
+    pub value: f64,
+    pub timestamp: DateTime<Utc>,
+}
+
+type Labels = Vec<(String, String)>;
+
+lazy_static! {
+    // Keyed by the recorded attributes, with the keys sanitized like the exporter's label names
+    static ref EXEMPLARS: Mutex<HashMap<Labels, VecDeque<Exemplar>>> = Mutex::new(HashMap::new());
+}
+
+// The Prometheus exporter turns every other character of an attribute key into `_`
+fn label_name(key: &str) -> String {
+    key.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == ':' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+pub fn record(attributes: &[KeyValue], exemplar: Exemplar) {
+    let mut labels = attributes
+        .iter()
+        .map(|kv| (label_name(kv.key.as_str()), kv.value.to_string()))
+        .collect::<Labels>();
+    labels.sort();
+    let mut exemplars = EXEMPLARS.lock().unwrap();
+    let recent = exemplars.entry(labels).or_default();
+    if recent.len() == RECENT_RUNS {
+        recent.pop_front();
+    }
+    recent.push_back(exemplar);
+}
+
+// The latest run of the series with these labels whose value falls in the bucket `(lower, upper]`.
+// Labels the exporter adds on its own, like `otel_scope_name`, are ignored.
+pub fn for_bucket(labels: &[(&str, &str)], lower: f64, upper: f64) -> Option<Exemplar> {
+    let exemplars = EXEMPLARS.lock().unwrap();
+    exemplars
+        .iter()
+        .filter(|(recorded, _)| {
+            recorded
+                .iter()
+                .all(|(key, value)| labels.contains(&(key.as_str(), value.as_str())))
+        })
+        .flat_map(|(_, recent)| recent.iter())
+        .filter(|exemplar| lower < exemplar.value && exemplar.value <= upper)
+        .max_by_key(|exemplar| exemplar.timestamp)
+        .cloned()
+}
+
+#[cfg(test)]
+mod exemplars_tests {
+    use chrono::Utc;
+    use opentelemetry::KeyValue;
+
+    use super::{for_bucket, record, Exemplar};
+
+    #[test]
+    fn test_exemplars_are_found_by_series_and_bucket() {
+        let attributes = [
+            KeyValue::new("name", "Exemplar series"),
+            KeyValue::new("team.name", "core"),
+        ];
+        record(
+            &attributes,
+            Exemplar {
+                trace_id: "0af7651916cd43dd8448eb211c80319c".to_owned(),
+                value: 42.0,
+                timestamp: Utc::now(),
+            },
+        );
+        let labels = [
+            ("name", "Exemplar series"),
+            ("otel_scope_name", "xbp"),
+            ("team_name", "core"),
+        ];
+
+        let exemplar = for_bucket(&labels, 25.0, 50.0).unwrap();
+        assert_eq!("0af7651916cd43dd8448eb211c80319c", exemplar.trace_id);
+        assert_eq!(None, for_bucket(&labels, 50.0, 100.0));
+        assert_eq!(None, for_bucket(&[("name", "Exemplar series")], 25.0, 50.0));
+    }
+}
@@ -1,41 +1,26 @@
 use opentelemetry::{
     global,
-    metrics::{Counter, Gauge, Histogram},
+    metrics::{Counter, Gauge, Histogram, Meter, MeterProvider},
+    KeyValue,
 };
 use opentelemetry_otlp::{MetricExporter, WithExportConfig};
 use opentelemetry_sdk::metrics::{
-    reader::MetricReader, MeterProviderBuilder, PeriodicReader, SdkMeterProvider,
+    data::ResourceMetrics, reader::MetricReader, InMemoryMetricExporter, MeterProviderBuilder,
+    PeriodicReader, SdkMeterProvider,
 };
 
 use chrono::Utc;
-use std::{env, fs::OpenOptions, io::Write, sync::Arc};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 use tracing::debug;
 
-use crate::otel::create_otlp_export_config;
-
-use super::resource;
-
-// #region agent log
-fn agent_log(hypothesis_id: &str, location: &str, message: &str, data: serde_json::Value) {
-    if let Ok(mut file) = OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open("c:\\Users\\floris\\Documents\\GitHub\\xbp-monitoring\\.cursor\\debug.log")
-    {
-        if let Ok(line) = serde_json::to_string(&serde_json::json!({
-            "sessionId": "debug-session",
-            "runId": "pre-fix",
-            "hypothesisId": hypothesis_id,
-            "location": location,
-            "message": message,
-            "data": data,
-            "timestamp": Utc::now().timestamp_millis(),
-        })) {
-            let _ = writeln!(file, "{}", line);
-        }
-    }
-}
-// #endregion
+use super::exemplars::{self, Exemplar};
+use super::{resource, ExporterKind, OtelConfig};
+use crate::config::DurationUnit;
+use crate::probe::model::HttpTimings;
 
 fn build_meter_provider<T>(reader: T) -> SdkMeterProvider
 where
@@ -50,23 +35,60 @@ where
 pub struct MetricsState {
     pub meter: Option<SdkMeterProvider>,
     pub registry: Option<Arc<prometheus::Registry>>,
+    // Only set by `for_testing`, holds everything the meter exported
+    pub in_memory: Option<InMemoryMetricExporter>,
+    // Why the configured exporter couldn't be built, raised as a self alert once the app state exists
+    pub fallback: Option<String>,
 }
 
-pub fn initialize() -> MetricsState {
-    let exporter_env = env::var("OTEL_METRICS_EXPORTER").ok();
-    // #region agent log
-    agent_log(
-        "A",
-        "metrics.rs:initialize",
-        "env OTEL_METRICS_EXPORTER",
-        serde_json::json!({ "value": exporter_env }),
-    );
-    // #endregion
-
-    let (meter_provider, prometheus_registry) = match exporter_env.as_deref() {
-        Some("otlp") => {
+impl MetricsState {
+    // A meter provider exporting to memory. It isn't installed globally so tests don't share instruments.
+    pub fn for_testing() -> MetricsState {
+        let exporter = InMemoryMetricExporter::default();
+        let reader = PeriodicReader::builder(exporter.clone()).build();
+        MetricsState {
+            meter: Some(build_meter_provider(reader)),
+            registry: None,
+            in_memory: Some(exporter),
+            fallback: None,
+        }
+    }
+
+    // Without a meter provider instruments record to the global one, which is a no-op here
+    fn fallback(reason: String) -> MetricsState {
+        MetricsState {
+            meter: None,
+            registry: None,
+            in_memory: None,
+            fallback: Some(reason),
+        }
+    }
+
+    // Instruments recording to this state's meter provider, or the global one when there is none
+    pub fn metrics(&self) -> Metrics {
+        self.metrics_with_unit(DurationUnit::default())
+    }
+
+    pub fn metrics_with_unit(&self, duration_unit: DurationUnit) -> Metrics {
+        match &self.meter {
+            Some(provider) => Metrics::from_meter(&provider.meter("xbp"), duration_unit),
+            None => Metrics::with_duration_unit(duration_unit),
+        }
+    }
+
+    // Flushes the meter provider and returns the latest export of the in-memory exporter
+    pub fn collect(&self) -> Option<ResourceMetrics> {
+        let exporter = self.in_memory.as_ref()?;
+        self.meter.as_ref()?.force_flush().ok()?;
+        exporter.get_finished_metrics().ok()?.pop()
+    }
+}
+
+pub fn initialize(config: &OtelConfig) -> MetricsState {
+    let (meter_provider, prometheus_registry) = match config.metrics_exporter {
+        ExporterKind::Otlp => {
             debug!("Using OTLP metrics exporter");
-            let export_config = create_otlp_export_config();
+            let export_config = config.otlp.export_config();
             let exporter = match export_config.protocol {
                 opentelemetry_otlp::Protocol::Grpc => {
                     debug!("Using OTLP gRPC exporter");
@@ -74,7 +96,6 @@ pub fn initialize() -> MetricsState {
                         .with_tonic()
                         .with_export_config(export_config)
                         .build()
-                        .unwrap()
                 }
                 _ => {
                     debug!("Using OTLP HTTP exporter");
@@ -90,96 +111,271 @@ pub fn initialize() -> MetricsState {
                             base_endpoint.trim_end_matches('/')
                         ))
                         .build()
-                        .unwrap()
                 }
             };
+            let exporter = match exporter {
+                Ok(exporter) => exporter,
+                Err(e) => return MetricsState::fallback(format!("OTLP metrics exporter: {}", e)),
+            };
             let reader = PeriodicReader::builder(exporter).build();
             (build_meter_provider(reader), None)
         }
-        Some("stdout") => {
+        ExporterKind::Stdout => {
             debug!("Using stdout metrics exporter");
             let exporter = opentelemetry_stdout::MetricExporter::default();
             let reader = PeriodicReader::builder(exporter).build();
             (build_meter_provider(reader), None)
         }
-        Some("prometheus") => {
+        ExporterKind::Prometheus => {
             debug!("Using Prometheus metrics exporter");
             let registry = prometheus::Registry::new();
-            let reader = opentelemetry_prometheus::exporter()
+            let reader = match opentelemetry_prometheus::exporter()
                 .with_registry(registry.clone())
                 .build()
-                .unwrap();
+            {
+                Ok(reader) => reader,
+                Err(e) => return MetricsState::fallback(format!("Prometheus exporter: {}", e)),
+            };
             (build_meter_provider(reader), Some(Arc::new(registry)))
         }
-        _ => {
+        ExporterKind::None => {
             debug!("No metrics exporter configured");
-            // #region agent log
-            agent_log(
-                "B",
-                "metrics.rs:initialize",
-                "no exporter configured",
-                serde_json::json!({}),
-            );
-            // #endregion
             return MetricsState {
                 meter: None,
                 registry: None,
+                in_memory: None,
+                fallback: None,
             };
         }
     };
 
     global::set_meter_provider(meter_provider.clone());
-    // #region agent log
-    agent_log(
-        "B",
-        "metrics.rs:initialize",
-        "meter provider ready",
-        serde_json::json!({ "has_registry": prometheus_registry.is_some() }),
-    );
-    // #endregion
 
     MetricsState {
         meter: Some(meter_provider),
         registry: prometheus_registry,
+        in_memory: None,
+        fallback: None,
     }
 }
 
 pub struct Metrics {
-    pub duration: Histogram<u64>,
+    // Recorded in `duration_unit`, see `record_duration`
+    pub duration: Histogram<f64>,
+    pub duration_unit: DurationUnit,
+    // Each request of a probe run with `with.samples`, see `record_sample_duration`. The run's
+    // aggregate goes to `duration` with a `samples` attribute.
+    pub sample_duration: Histogram<f64>,
+    // The `with.prefetch` request of a probe run, left out of `duration` unless
+    // `prefetch.include_in_duration` is set
+    pub prefetch_duration: Histogram<f64>,
     pub runs: Counter<u64>,
     pub errors: Counter<u64>,
     pub status: Gauge<u64>,
     pub http_status_code: Gauge<u64>,
+    pub alerts_failed: Counter<u64>,
+    pub config_reloads: Counter<u64>,
+    pub config_reload_errors: Counter<u64>,
+    pub open_incidents: Gauge<u64>,
+    pub configured_probes: Gauge<u64>,
+    pub configured_stories: Gauge<u64>,
+    // The `/status` health score, see `app_state::health_score`
+    pub composite_status: Gauge<f64>,
+    pub slow_expectations: Counter<u64>,
+    pub probe_retries: Counter<u64>,
+    pub circuit_breaker_state: Gauge<u64>,
+    pub probe_interval: Gauge<u64>,
+    pub last_success_timestamp: Gauge<u64>,
+    pub last_failure_timestamp: Gauge<u64>,
+    pub clock_offset_ms: Gauge<f64>,
+    // Latest run of each HTTP probe in `duration_unit`, see `record_http_timings`
+    pub dns_lookup_duration: Gauge<f64>,
+    pub ttfb_duration: Gauge<f64>,
+    pub backend_changes: Counter<u64>,
+    pub audit_records_dropped: Counter<u64>,
+    pub storage_records_dropped: Counter<u64>,
+    pub body_extraction_failures: Counter<u64>,
+    pub result_store_memory: Gauge<u64>,
+    pub self_alert_events: Counter<u64>,
+    // Instruments are named after the probe's `metrics_from_body`, so they are created on first use
+    meter: Meter,
+    extracted: Mutex<HashMap<String, Gauge<f64>>>,
+    // The attribute sets `status` and `http_status_code` were recorded with, by monitor, see
+    // `clear_monitor`
+    gauge_series: Mutex<HashMap<String, Vec<Vec<KeyValue>>>>,
 }
 
 #[derive(Debug, Clone, Copy)]
 pub enum MonitorStatus {
     Ok = 0,
     Error = 1,
+    // Set by `Metrics::clear_monitor` once a reload or the runtime API removed the monitor
+    Removed = 2,
 }
 
+// `http_status_code` of a removed monitor. 0 already means the call failed and no response has a
+// status below 100.
+pub const REMOVED_HTTP_STATUS_CODE: u64 = MonitorStatus::Removed as u64;
+
 impl MonitorStatus {
     pub fn as_u64(&self) -> u64 {
         *self as u64
     }
+
+    pub fn from_failing(failing: bool) -> MonitorStatus {
+        if failing {
+            MonitorStatus::Error
+        } else {
+            MonitorStatus::Ok
+        }
+    }
 }
 
 impl Metrics {
-    pub fn new() -> Metrics {
-        let meter: opentelemetry::metrics::Meter = opentelemetry::global::meter("xbp");
-        // #region agent log
-        agent_log(
-            "C",
-            "metrics.rs:new",
-            "meter created",
-            serde_json::json!({}),
+    // The `xbp_extracted_<metric>` gauge, see `probe::body_metrics`
+    pub fn extracted_gauge(&self, metric: &str) -> Gauge<f64> {
+        self.extracted
+            .lock()
+            .unwrap()
+            .entry(metric.to_owned())
+            .or_insert_with(|| {
+                self.meter
+                    .f64_gauge(format!("xbp_extracted_{}", metric))
+                    .with_description("a value extracted from the response body of a probe")
+                    .build()
+            })
+            .clone()
+    }
+
+    // `monitor` is the probe or story the series belongs to, steps record under their story
+    pub fn record_status(&self, monitor: &str, status: u64, attributes: &[KeyValue]) {
+        self.remember_series(monitor, attributes);
+        self.status.record(status, attributes);
+    }
+
+    pub fn record_http_status_code(
+        &self,
+        monitor: &str,
+        status_code: u64,
+        attributes: &[KeyValue],
+    ) {
+        self.remember_series(monitor, attributes);
+        self.http_status_code.record(status_code, attributes);
+    }
+
+    fn remember_series(&self, monitor: &str, attributes: &[KeyValue]) {
+        let mut gauge_series = self.gauge_series.lock().unwrap();
+        let series = gauge_series.entry(monitor.to_owned()).or_default();
+        if !series.iter().any(|known| known == attributes) {
+            series.push(attributes.to_vec());
+        }
+    }
+
+    // Marks the `status` and `http_status_code` series of a removed monitor as removed. Gauges keep
+    // their last value, so dashboards would otherwise show the removed monitor as it last ran.
+    pub fn clear_monitor(&self, monitor: &str) {
+        let Some(series) = self.gauge_series.lock().unwrap().remove(monitor) else {
+            return;
+        };
+        for attributes in series {
+            self.status
+                .record(MonitorStatus::Removed.as_u64(), &attributes);
+            self.http_status_code
+                .record(REMOVED_HTTP_STATUS_CODE, &attributes);
+        }
+    }
+
+    pub fn record_duration(&self, duration: Duration, attributes: &[KeyValue]) {
+        self.duration
+            .record(self.duration_unit.convert(duration), attributes);
+    }
+
+    pub fn record_sample_duration(&self, duration: Duration, attributes: &[KeyValue]) {
+        self.sample_duration
+            .record(self.duration_unit.convert(duration), attributes);
+    }
+
+    pub fn record_prefetch_duration(&self, duration: Duration, attributes: &[KeyValue]) {
+        self.prefetch_duration
+            .record(self.duration_unit.convert(duration), attributes);
+    }
+
+    // Keeps the run's trace id as an exemplar of the bucket the duration falls in, see `otel::exemplars`
+    pub fn record_run_duration(
+        &self,
+        duration: Duration,
+        attributes: &[KeyValue],
+        trace_id: Option<String>,
+    ) {
+        let value = self.duration_unit.convert(duration);
+        self.duration.record(value, attributes);
+        if let Some(trace_id) = trace_id {
+            exemplars::record(
+                attributes,
+                Exemplar {
+                    trace_id,
+                    value,
+                    timestamp: Utc::now(),
+                },
+            );
+        }
+    }
+
+    // The DNS lookup gauge is left alone for urls holding an IP address
+    pub fn record_http_timings(&self, timings: &HttpTimings, attributes: &[KeyValue]) {
+        if let Some(dns_lookup) = timings.dns_lookup {
+            self.dns_lookup_duration
+                .record(self.duration_unit.convert(dns_lookup), attributes);
+        }
+        self.ttfb_duration.record(
+            self.duration_unit.convert(timings.time_to_first_byte),
+            attributes,
         );
-        // #endregion
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Metrics::new()
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        Metrics::with_duration_unit(DurationUnit::default())
+    }
+
+    // Instruments of the global meter, with the `duration` histogram in `settings.metrics.duration_unit`
+    pub fn with_duration_unit(duration_unit: DurationUnit) -> Metrics {
+        Metrics::from_meter(&global::meter("xbp"), duration_unit)
+    }
+
+    pub fn from_meter(meter: &Meter, duration_unit: DurationUnit) -> Metrics {
         Metrics {
             duration: meter
-                .u64_histogram("duration")
-                .with_unit("ms")
-                .with_description("request duration histogram in milliseconds")
+                .f64_histogram("duration")
+                .with_unit(duration_unit.as_str())
+                .with_description(format!(
+                    "request duration histogram in {}",
+                    duration_unit.as_str()
+                ))
+                .build(),
+            duration_unit,
+            sample_duration: meter
+                .f64_histogram("sample_duration")
+                .with_unit(duration_unit.as_str())
+                .with_description(format!(
+                    "duration of each sampled request in {}",
+                    duration_unit.as_str()
+                ))
+                .build(),
+            prefetch_duration: meter
+                .f64_histogram("prefetch_duration")
+                .with_unit(duration_unit.as_str())
+                .with_description(format!(
+                    "duration of the prefetch request of a probe run in {}",
+                    duration_unit.as_str()
+                ))
                 .build(),
             runs: meter
                 .u64_counter("runs")
@@ -191,14 +387,144 @@ impl Metrics {
                 .build(),
             status: meter
                 .u64_gauge("status")
-                .with_description("the current status of each monitor OK = 0 Error = 1")
+                .with_description("the current status of each monitor OK = 0 Error = 1 Removed = 2")
                 .build(),
             http_status_code: meter
                 .u64_gauge("http_status_code")
                 .with_description(
-                    "the current HTTP status code of the step, 0 if the HTTP call fails",
+                    "the current HTTP status code of the step, 0 if the HTTP call fails, 2 once the monitor was removed",
+                )
+                .build(),
+            alerts_failed: meter
+                .u64_counter("alerts_failed")
+                .with_description("the total number of alerts that could not be delivered")
+                .build(),
+            config_reloads: meter
+                .u64_counter("config_reloads")
+                .with_description("the total number of completed config reloads")
+                .build(),
+            config_reload_errors: meter
+                .u64_counter("config_reload_errors")
+                .with_description(
+                    "the total number of config reloads rejected because the config failed to load",
+                )
+                .build(),
+            open_incidents: meter
+                .u64_gauge("open_incidents")
+                .with_description("the number of monitors with an open incident")
+                .build(),
+            configured_probes: meter
+                .u64_gauge("configured_probes")
+                .with_description("the number of probes in the running config")
+                .build(),
+            configured_stories: meter
+                .u64_gauge("configured_stories")
+                .with_description("the number of stories in the running config")
+                .build(),
+            composite_status: meter
+                .f64_gauge("composite_status")
+                .with_description(
+                    "the fraction of probes that are OK from 0.0 to 1.0, the health_score of /status",
+                )
+                .build(),
+            slow_expectations: meter
+                .u64_counter("slow_expectations")
+                .with_description(
+                    "the total number of expectation evaluations exceeding settings.runtime.max_blocking_duration_warning_ms",
+                )
+                .build(),
+            probe_retries: meter
+                .u64_counter("probe_retries")
+                .with_description("the total number of probe runs that only succeeded after a retry")
+                .build(),
+            circuit_breaker_state: meter
+                .u64_gauge("circuit_breaker_state")
+                .with_description(
+                    "the circuit breaker state of each probe with one Closed = 0 Open = 1 Half-open = 2",
+                )
+                .build(),
+            // Exported to Prometheus as `probe_interval_seconds`
+            probe_interval: meter
+                .u64_gauge("probe_interval")
+                .with_unit("s")
+                .with_description("the interval each probe with a circuit breaker currently runs at")
+                .build(),
+            // Exported to Prometheus as `last_success_timestamp_seconds`, for rules like
+            // `time() - last_success_timestamp_seconds > 900`
+            last_success_timestamp: meter
+                .u64_gauge("last_success_timestamp")
+                .with_unit("s")
+                .with_description("unix time of the latest successful run of each monitor")
+                .build(),
+            last_failure_timestamp: meter
+                .u64_gauge("last_failure_timestamp")
+                .with_unit("s")
+                .with_description("unix time of the latest failed run of each monitor")
+                .build(),
+            // No unit so the exported name stays `clock_offset_ms`
+            clock_offset_ms: meter
+                .f64_gauge("clock_offset_ms")
+                .with_description(
+                    "offset of the server clock from the local clock measured by ntp probes, in milliseconds",
+                )
+                .build(),
+            dns_lookup_duration: meter
+                .f64_gauge("dns_lookup_duration")
+                .with_unit(duration_unit.as_str())
+                .with_description(format!(
+                    "DNS lookup time of the latest run of each http probe in {}",
+                    duration_unit.as_str()
+                ))
+                .build(),
+            ttfb_duration: meter
+                .f64_gauge("ttfb_duration")
+                .with_unit(duration_unit.as_str())
+                .with_description(format!(
+                    "time from the DNS lookup to the response headers of the latest run of each http probe, connect and TLS handshake included, in {}",
+                    duration_unit.as_str()
+                ))
+                .build(),
+            backend_changes: meter
+                .u64_counter("backend_changes")
+                .with_description(
+                    "the total number of http probe runs that connected to a different IP than the run before, with settings.log_backend_changes",
+                )
+                .build(),
+            audit_records_dropped: meter
+                .u64_counter("audit_records_dropped")
+                .with_description(
+                    "the total number of audit records that were not written, because the queue was full or the sink failed",
+                )
+                .build(),
+            storage_records_dropped: meter
+                .u64_counter("storage_records_dropped")
+                .with_description(
+                    "the total number of results that were not written to settings.storage, because the queue was full or the database failed",
+                )
+                .build(),
+            body_extraction_failures: meter
+                .u64_counter("body_extraction_failures")
+                .with_description(
+                    "the total number of `metrics_from_body` values that were missing or not a number",
+                )
+                .build(),
+            // Exported to Prometheus as `result_store_memory_bytes`
+            result_store_memory: meter
+                .u64_gauge("result_store_memory")
+                .with_unit("By")
+                .with_description(
+                    "the estimated memory taken by the stored probe and story results",
+                )
+                .build(),
+            self_alert_events: meter
+                .u64_counter("self_alert_events")
+                .with_description(
+                    "the total number of problems of xbp itself, by kind, see settings.self_alerts",
                 )
                 .build(),
+            meter: meter.clone(),
+            extracted: Mutex::new(HashMap::new()),
+            gauge_series: Mutex::new(HashMap::new()),
         }
     }
 }
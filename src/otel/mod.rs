@@ -1,62 +1,219 @@
 use std::{env, time::Duration};
 
+use crate::build_info::BuildInfo;
 use metrics::MetricsState;
 use opentelemetry_otlp::{ExportConfig, Protocol};
-use opentelemetry_sdk::{
-    resource::Resource,
-};
+use opentelemetry_sdk::error::OTelSdkResult;
+use opentelemetry_sdk::logs::SdkLoggerProvider;
+use opentelemetry_sdk::resource::Resource;
+use opentelemetry_sdk::trace::SdkTracerProvider;
 use tracing_subscriber::prelude::*;
 use tracing_subscriber::EnvFilter;
 
-pub(crate) mod metrics;
+pub mod exemplars;
+pub mod metrics;
 pub(crate) mod tracing;
 
+// Carries the build identity, `service.version` and `git.commit`, unless `OTEL_RESOURCE_ATTRIBUTES` sets them
 pub fn resource() -> Resource {
-    Resource::builder().build()
+    Resource::builder()
+        .with_attributes(BuildInfo::current().resource_attributes())
+        .build()
 }
 
-pub struct OtelGuard {
+// Every provider `init` built. `shutdown` flushes them, dropping the state without it only
+// shuts them down.
+pub struct OtelState {
     pub metrics: MetricsState,
+    // None when spans aren't exported
+    pub tracer: Option<SdkTracerProvider>,
+    // No log exporter exists yet, logs only go to the `fmt` layer
+    pub logger: Option<SdkLoggerProvider>,
+    // Exporters that couldn't be built and were replaced by no-op ones. The subscriber isn't
+    // installed while they are built, so they are logged once raised as self alerts.
+    pub exporter_fallbacks: Vec<String>,
 }
 
-impl Drop for OtelGuard {
+impl OtelState {
+    // Shuts the providers down logger first and metrics last, so what the others report while
+    // shutting down is still exported. Each one gets up to `timeout`, a provider whose exporter
+    // hangs is left behind.
+    pub async fn shutdown(mut self, timeout: Duration) {
+        if let Some(logger) = self.logger.take() {
+            shutdown_provider("logger", timeout, move || logger.shutdown()).await;
+        }
+        if let Some(tracer) = self.tracer.take() {
+            shutdown_provider("tracer", timeout, move || tracer.shutdown()).await;
+        }
+        if let Some(meter) = self.metrics.meter.take() {
+            shutdown_provider("meter", timeout, move || meter.shutdown()).await;
+        }
+    }
+}
+
+// Provider shutdowns block until their exporters finished, so they run off the runtime threads
+async fn shutdown_provider(
+    name: &str,
+    timeout: Duration,
+    shutdown: impl FnOnce() -> OTelSdkResult + Send + 'static,
+) {
+    match tokio::time::timeout(timeout, tokio::task::spawn_blocking(shutdown)).await {
+        Ok(Ok(Ok(()))) => {}
+        Ok(Ok(Err(err))) => ::tracing::warn!("Failed to shutdown {} provider: {:?}", name, err),
+        Ok(Err(err)) => ::tracing::warn!("Failed to shutdown {} provider: {}", name, err),
+        Err(_) => ::tracing::warn!("The {} provider didn't shutdown within {:?}", name, timeout),
+    }
+}
+
+impl Drop for OtelState {
     fn drop(&mut self) {
+        if let Some(Err(err)) = self.logger.as_ref().map(|lp| lp.shutdown()) {
+            eprintln!("Failed to shutdown logger provider: {err:?}");
+        }
+        if let Some(Err(err)) = self.tracer.as_ref().map(|tp| tp.shutdown()) {
+            eprintln!("Failed to shutdown tracer provider: {err:?}");
+        }
         if let Some(Err(err)) = self.metrics.meter.as_ref().map(|mp| mp.shutdown()) {
             eprintln!("Failed to shutdown meter provider: {err:?}");
         }
     }
 }
 
-pub fn init() -> OtelGuard {
-    let metrics_state = metrics::initialize();
-    tracing::create_tracer();
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ExporterKind {
+    #[default]
+    None,
+    Otlp,
+    Stdout,
+    Prometheus,
+}
+
+impl ExporterKind {
+    // Parses an `OTEL_*_EXPORTER` value, anything unknown disables the exporter
+    pub fn parse(value: Option<&str>) -> ExporterKind {
+        match value {
+            Some("otlp") => ExporterKind::Otlp,
+            Some("stdout") => ExporterKind::Stdout,
+            Some("prometheus") => ExporterKind::Prometheus,
+            _ => ExporterKind::None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct OtlpConfig {
+    pub endpoint: String,
+    pub protocol: Protocol,
+    pub timeout: Duration,
+}
+
+impl Default for OtlpConfig {
+    fn default() -> Self {
+        OtlpConfig {
+            endpoint: "http://localhost:4317".to_string(),
+            protocol: Protocol::Grpc,
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+impl OtlpConfig {
+    pub fn from_env() -> OtlpConfig {
+        let defaults = OtlpConfig::default();
+        OtlpConfig {
+            endpoint: env::var("OTEL_EXPORTER_OTLP_ENDPOINT").unwrap_or(defaults.endpoint),
+            protocol: match env::var("OTEL_EXPORTER_OTLP_PROTOCOL") {
+                Ok(protocol) if protocol == "http/protobuf" => Protocol::HttpBinary,
+                Ok(protocol) if protocol == "http/json" => Protocol::HttpJson,
+                _ => defaults.protocol,
+            },
+            timeout: env::var("OTEL_EXPORTER_OTLP_TIMEOUT")
+                .map(|timeout| {
+                    Duration::from_secs(
+                        timeout
+                            .parse::<u64>()
+                            .expect("OTEL_EXPORTER_OTLP_TIMEOUT must be a number"),
+                    )
+                })
+                .unwrap_or(defaults.timeout),
+        }
+    }
+
+    pub(crate) fn export_config(&self) -> ExportConfig {
+        ExportConfig {
+            endpoint: Some(self.endpoint.clone()),
+            protocol: self.protocol,
+            timeout: Some(self.timeout),
+        }
+    }
+}
+
+// Exporter selection. Only `from_env` reads the environment so tests can pass their own.
+#[derive(Debug, Clone, Default)]
+pub struct OtelConfig {
+    pub metrics_exporter: ExporterKind,
+    pub traces_exporter: ExporterKind,
+    pub otlp: OtlpConfig,
+}
+
+impl OtelConfig {
+    pub fn from_env() -> OtelConfig {
+        OtelConfig {
+            metrics_exporter: ExporterKind::parse(
+                env::var("OTEL_METRICS_EXPORTER").ok().as_deref(),
+            ),
+            traces_exporter: ExporterKind::parse(env::var("OTEL_TRACES_EXPORTER").ok().as_deref()),
+            otlp: OtlpConfig::from_env(),
+        }
+    }
+}
+
+pub fn init() -> OtelState {
+    init_with_config(&OtelConfig::from_env())
+}
+
+pub fn init_with_config(config: &OtelConfig) -> OtelState {
+    let metrics_state = metrics::initialize(config);
+    let (tracer, traces_fallback) = tracing::create_tracer(config);
     let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
     tracing_subscriber::registry()
         .with(filter)
         .with(tracing_subscriber::fmt::layer())
         .init();
 
-    OtelGuard {
+    let exporter_fallbacks = metrics_state
+        .fallback
+        .iter()
+        .cloned()
+        .chain(traces_fallback)
+        .collect();
+    OtelState {
         metrics: metrics_state,
+        tracer,
+        logger: None,
+        exporter_fallbacks,
     }
 }
 
-fn create_otlp_export_config() -> ExportConfig {
-    ExportConfig {
-        endpoint: Some(
-            env::var("OTEL_EXPORTER_OTLP_ENDPOINT")
-                .unwrap_or_else(|_| "http://localhost:4317".to_string()),
-        ),
-        protocol: match env::var("OTEL_EXPORTER_OTLP_PROTOCOL") {
-            Ok(protocol) if protocol == "http/protobuf" => Protocol::HttpBinary,
-            Ok(protocol) if protocol == "http/json" => Protocol::HttpJson,
-            _ => Protocol::Grpc,
-        },
-        timeout: Some(Duration::from_secs(
-            env::var("OTEL_EXPORTER_OTLP_TIMEOUT")
-                .unwrap_or_else(|_| "10".to_string())
-                .parse::<u64>()
-                .expect("OTEL_EXPORTER_OTLP_TIMEOUT must be a number"),
-        )),
-    }
-}
\ No newline at end of file
+#[cfg(test)]
+mod otel_tests {
+    use std::time::Duration;
+
+    use super::metrics::MetricsState;
+    use super::OtelState;
+
+    #[tokio::test]
+    async fn test_shutdown_takes_the_providers() {
+        let state = OtelState {
+            metrics: MetricsState::for_testing(),
+            tracer: None,
+            logger: None,
+            exporter_fallbacks: vec![],
+        };
+        let meter = state.metrics.meter.clone().unwrap();
+
+        state.shutdown(Duration::from_secs(1)).await;
+
+        assert!(meter.shutdown().is_err());
+    }
+}
@@ -1,43 +1,19 @@
-use std::env;
-
 use opentelemetry::global;
 use opentelemetry_otlp::{SpanExporter, WithExportConfig};
 use opentelemetry_sdk::propagation::TraceContextPropagator;
 
 use opentelemetry_sdk::trace::{BatchSpanProcessor, SdkTracerProvider};
 use tracing::debug;
-use std::fs::OpenOptions;
-use std::io::Write;
-use chrono::Utc;
 
-use super::{create_otlp_export_config, resource};
+use super::{resource, ExporterKind, OtelConfig};
 
-// #region agent log
-fn agent_log(hypothesis_id: &str, location: &str, message: &str, data: serde_json::Value) {
-    if let Ok(mut file) = OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open("c:\\Users\\floris\\Documents\\GitHub\\xbp-monitoring\\.cursor\\debug.log")
-    {
-        if let Ok(line) = serde_json::to_string(&serde_json::json!({
-            "sessionId": "debug-session",
-            "runId": "pre-fix",
-            "hypothesisId": hypothesis_id,
-            "location": location,
-            "message": message,
-            "data": data,
-            "timestamp": Utc::now().timestamp_millis(),
-        })) {
-            let _ = writeln!(file, "{}", line);
-        }
-    }
-}
-// #endregion
-
-pub fn create_tracer() {
-    let provider = match env::var("OTEL_TRACES_EXPORTER").ok().as_deref() {
-        Some("otlp") => {
-            let export_config = create_otlp_export_config();
+// Installs the tracer provider and returns it when spans are exported. Also returns why the
+// configured exporter couldn't be built if it couldn't, spans are dropped then.
+pub fn create_tracer(config: &OtelConfig) -> (Option<SdkTracerProvider>, Option<String>) {
+    let mut fallback = None;
+    let provider = match config.traces_exporter {
+        ExporterKind::Otlp => {
+            let export_config = config.otlp.export_config();
             let span_exporter = match export_config.protocol {
                 opentelemetry_otlp::Protocol::Grpc => {
                     debug!("Using OTLP gRPC exporter");
@@ -45,7 +21,6 @@ pub fn create_tracer() {
                         .with_tonic()
                         .with_export_config(export_config)
                         .build()
-                        .unwrap()
                 }
                 _ => {
                     debug!("Using OTLP HTTP exporter");
@@ -58,20 +33,25 @@ pub fn create_tracer() {
                         .with_export_config(export_config)
                         .with_endpoint(format!("{}/v1/traces", base_endpoint.trim_end_matches('/')))
                         .build()
-                        .unwrap()
                 }
             };
-            let processor = BatchSpanProcessor::builder(span_exporter).build();
-            SdkTracerProvider::builder()
-                .with_span_processor(processor)
-                .with_resource(resource())
-                .build()
+            match span_exporter {
+                Ok(span_exporter) => {
+                    let processor = BatchSpanProcessor::builder(span_exporter).build();
+                    SdkTracerProvider::builder()
+                        .with_span_processor(processor)
+                        .with_resource(resource())
+                        .build()
+                }
+                Err(e) => {
+                    fallback = Some(format!("OTLP span exporter: {}", e));
+                    SdkTracerProvider::default()
+                }
+            }
         }
-        Some("stdout") => {
-            let processor = BatchSpanProcessor::builder(
-                opentelemetry_stdout::SpanExporter::default()
-            )
-            .build();
+        ExporterKind::Stdout => {
+            let processor =
+                BatchSpanProcessor::builder(opentelemetry_stdout::SpanExporter::default()).build();
             SdkTracerProvider::builder()
                 .with_span_processor(processor)
                 .build()
@@ -79,13 +59,11 @@ pub fn create_tracer() {
         _ => SdkTracerProvider::default(),
     };
     global::set_tracer_provider(provider.clone());
+    let exported = fallback.is_none()
+        && matches!(
+            config.traces_exporter,
+            ExporterKind::Otlp | ExporterKind::Stdout
+        );
     global::set_text_map_propagator(TraceContextPropagator::new());
-    // #region agent log
-    agent_log(
-        "D",
-        "tracing.rs:create_tracer",
-        "tracer initialized",
-        serde_json::json!({ "has_traces": true }),
-    );
-    // #endregion
+    (exported.then_some(provider), fallback)
 }
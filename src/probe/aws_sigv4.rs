@@ -0,0 +1,301 @@
+use std::fmt::Write;
+
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use reqwest::header::HeaderValue;
+use reqwest::Request;
+use sha2::{Digest, Sha256};
+
+use super::model::AwsSigV4Parameters;
+
+const ALGORITHM: &str = "AWS4-HMAC-SHA256";
+// Error codes AWS returns when x-amz-date is too far from its own clock
+const CLOCK_SKEW_ERRORS: &[&str] = &["RequestTimeTooSkewed", "Signature expired"];
+
+#[derive(Debug)]
+pub struct SigV4Error {
+    pub message: String,
+}
+
+impl std::error::Error for SigV4Error {}
+
+impl std::fmt::Display for SigV4Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "AWS SigV4 error: {}", self.message)
+    }
+}
+
+struct AwsCredentials {
+    access_key_id: String,
+    secret_access_key: String,
+    session_token: Option<String>,
+}
+
+impl AwsCredentials {
+    // Explicit credentials win, otherwise the standard AWS environment variables are used
+    fn resolve(params: &AwsSigV4Parameters) -> Result<AwsCredentials, SigV4Error> {
+        let env_var = |name: &str| std::env::var(name).ok().filter(|value| !value.is_empty());
+        let access_key_id = params
+            .access_key_id
+            .clone()
+            .filter(|value| !value.is_empty())
+            .or_else(|| env_var("AWS_ACCESS_KEY_ID"));
+        let secret_access_key = params
+            .secret_access_key
+            .clone()
+            .filter(|value| !value.is_empty())
+            .or_else(|| env_var("AWS_SECRET_ACCESS_KEY"));
+        match (access_key_id, secret_access_key) {
+            (Some(access_key_id), Some(secret_access_key)) => Ok(AwsCredentials {
+                access_key_id,
+                secret_access_key,
+                session_token: params
+                    .session_token
+                    .clone()
+                    .or_else(|| env_var("AWS_SESSION_TOKEN")),
+            }),
+            _ => Err(SigV4Error {
+                message: "no AWS credentials configured, set access_key_id and secret_access_key or AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY".to_owned(),
+            }),
+        }
+    }
+}
+
+// Whether a rejected response is AWS complaining about the request time rather than the credentials
+pub fn is_clock_skew_rejection(status_code: u32, body: &str) -> bool {
+    status_code == 403 && CLOCK_SKEW_ERRORS.iter().any(|error| body.contains(error))
+}
+
+pub fn clock_skew_error(signed_at: DateTime<Utc>) -> SigV4Error {
+    SigV4Error {
+        message: format!(
+            "AWS rejected the request because of clock skew, the request was signed at {} - check the system clock",
+            signed_at.format("%Y-%m-%dT%H:%M:%SZ")
+        ),
+    }
+}
+
+// Adds x-amz-date, x-amz-security-token and authorization headers to a fully built request
+pub fn sign_request(
+    request: &mut Request,
+    params: &AwsSigV4Parameters,
+    now: DateTime<Utc>,
+) -> Result<(), SigV4Error> {
+    let credentials = AwsCredentials::resolve(params)?;
+    sign_request_with_credentials(request, params, &credentials, now)
+}
+
+fn sign_request_with_credentials(
+    request: &mut Request,
+    params: &AwsSigV4Parameters,
+    credentials: &AwsCredentials,
+    now: DateTime<Utc>,
+) -> Result<(), SigV4Error> {
+    let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+    let date = now.format("%Y%m%d").to_string();
+
+    let url = request.url();
+    let host = match (url.host_str(), url.port()) {
+        (Some(host), Some(port)) => format!("{}:{}", host, port),
+        (Some(host), None) => host.to_owned(),
+        (None, _) => {
+            return Err(SigV4Error {
+                message: format!("url '{}' has no host", url),
+            })
+        }
+    };
+
+    let mut signed_headers = vec![("host", host), ("x-amz-date", amz_date.clone())];
+    if let Some(token) = &credentials.session_token {
+        signed_headers.push(("x-amz-security-token", token.clone()));
+    }
+    let canonical_headers: String = signed_headers
+        .iter()
+        .map(|(name, value)| format!("{}:{}\n", name, value.trim()))
+        .collect();
+    let signed_header_names = signed_headers
+        .iter()
+        .map(|(name, _)| *name)
+        .collect::<Vec<_>>()
+        .join(";");
+
+    let body = request
+        .body()
+        .and_then(|body| body.as_bytes())
+        .unwrap_or_default();
+    let canonical_request = format!(
+        "{}\n{}\n{}\n{}\n{}\n{}",
+        request.method().as_str(),
+        canonical_uri(url.path()),
+        canonical_query(url),
+        canonical_headers,
+        signed_header_names,
+        hex_sha256(body)
+    );
+
+    let scope = format!("{}/{}/{}/aws4_request", date, params.region, params.service);
+    let string_to_sign = format!(
+        "{}\n{}\n{}\n{}",
+        ALGORITHM,
+        amz_date,
+        scope,
+        hex_sha256(canonical_request.as_bytes())
+    );
+
+    let signing_key = [
+        params.region.as_bytes(),
+        params.service.as_bytes(),
+        b"aws4_request",
+    ]
+    .iter()
+    .fold(
+        hmac_sha256(
+            format!("AWS4{}", credentials.secret_access_key).as_bytes(),
+            date.as_bytes(),
+        ),
+        |key, part| hmac_sha256(&key, part),
+    );
+    let signature = hex(&hmac_sha256(&signing_key, string_to_sign.as_bytes()));
+
+    let authorization = format!(
+        "{} Credential={}/{}, SignedHeaders={}, Signature={}",
+        ALGORITHM, credentials.access_key_id, scope, signed_header_names, signature
+    );
+
+    let headers = request.headers_mut();
+    for (name, value) in signed_headers.into_iter().skip(1) {
+        headers.insert(name, header_value(&value)?);
+    }
+    headers.insert("authorization", header_value(&authorization)?);
+    Ok(())
+}
+
+fn header_value(value: &str) -> Result<HeaderValue, SigV4Error> {
+    HeaderValue::from_str(value).map_err(|e| SigV4Error {
+        message: e.to_string(),
+    })
+}
+
+// The path is already percent-encoded once by the url parser, non-S3 services expect it encoded twice
+fn canonical_uri(path: &str) -> String {
+    if path.is_empty() {
+        return "/".to_owned();
+    }
+    path.split('/')
+        .map(uri_encode)
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn canonical_query(url: &reqwest::Url) -> String {
+    let mut pairs: Vec<(String, String)> = url
+        .query_pairs()
+        .map(|(key, value)| (uri_encode(&key), uri_encode(&value)))
+        .collect();
+    pairs.sort();
+    pairs
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+// RFC 3986 encoding, leaving only unreserved characters as they are
+fn uri_encode(value: &str) -> String {
+    value.bytes().fold(String::new(), |mut encoded, byte| {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'_' | b'.' | b'~') {
+            encoded.push(byte as char);
+        } else {
+            let _ = write!(encoded, "%{:02X}", byte);
+        }
+        encoded
+    })
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn hex_sha256(data: &[u8]) -> String {
+    hex(&Sha256::digest(data))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut hex, byte| {
+        let _ = write!(hex, "{:02x}", byte);
+        hex
+    })
+}
+
+#[cfg(test)]
+mod aws_sigv4_tests {
+    use chrono::{TimeZone, Utc};
+    use reqwest::{Method, Request, Url};
+
+    use crate::probe::aws_sigv4::{
+        is_clock_skew_rejection, sign_request_with_credentials, uri_encode, AwsCredentials,
+    };
+    use crate::probe::model::AwsSigV4Parameters;
+
+    // Credentials and expected signatures from the AWS SigV4 test suite
+    fn sign(url: &str) -> String {
+        let mut request = Request::new(Method::GET, Url::parse(url).unwrap());
+        let params = AwsSigV4Parameters {
+            region: "us-east-1".to_owned(),
+            service: "service".to_owned(),
+            access_key_id: None,
+            secret_access_key: None,
+            session_token: None,
+        };
+        let credentials = AwsCredentials {
+            access_key_id: "AKIDEXAMPLE".to_owned(),
+            secret_access_key: "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".to_owned(),
+            session_token: None,
+        };
+        let now = Utc.with_ymd_and_hms(2015, 8, 30, 12, 36, 0).unwrap();
+
+        sign_request_with_credentials(&mut request, &params, &credentials, now).unwrap();
+
+        assert_eq!("20150830T123600Z", request.headers()["x-amz-date"]);
+        request.headers()["authorization"]
+            .to_str()
+            .unwrap()
+            .to_owned()
+    }
+
+    #[test]
+    fn test_get_vanilla() {
+        assert_eq!(
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/service/aws4_request, SignedHeaders=host;x-amz-date, Signature=5fa00fa31553b73ebf1942676e86291e8372ff2a2260956d9b8aae1d763fbf31",
+            sign("https://example.amazonaws.com/")
+        );
+    }
+
+    #[test]
+    fn test_get_vanilla_query_order() {
+        assert_eq!(
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/service/aws4_request, SignedHeaders=host;x-amz-date, Signature=b97d918cfa904a5beff61c982a1b6f458b799221646efd99d3219ec94cdf2500",
+            sign("https://example.amazonaws.com/?Param2=value2&Param1=value1")
+        );
+    }
+
+    #[test]
+    fn test_uri_encode() {
+        assert_eq!("a-b_c.d~e", uri_encode("a-b_c.d~e"));
+        assert_eq!("a%20b%2Fc%3D", uri_encode("a b/c="));
+    }
+
+    #[test]
+    fn test_clock_skew_rejection() {
+        assert!(is_clock_skew_rejection(
+            403,
+            r#"{"message":"Signature expired: 20150830T123600Z is now earlier than 20150830T124100Z"}"#
+        ));
+        assert!(!is_clock_skew_rejection(
+            403,
+            r#"{"message":"The security token included in the request is invalid."}"#
+        ));
+    }
+}
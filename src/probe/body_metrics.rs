@@ -0,0 +1,214 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use lazy_static::lazy_static;
+use opentelemetry::KeyValue;
+use regex::Regex;
+use serde_json::Value;
+use tracing::warn;
+
+use super::model::Probe;
+use super::variables::{json_path_values, parse_json_path};
+use crate::app_state::AppState;
+
+// Larger responses aren't parsed, each of their metrics counts as an extraction failure
+pub const MAX_EXTRACTION_BODY_BYTES: usize = 1024 * 1024;
+
+// A metric failing on every run is logged once per interval, the failure counter still counts each run
+const FAILURE_LOG_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+lazy_static! {
+    // Prometheus metric names, without the colons reserved for recording rules
+    static ref METRIC_NAME_REGEX: Regex = Regex::new(r"^[a-zA-Z_][a-zA-Z0-9_]*$").unwrap();
+    // When a failure was last logged, by probe and metric
+    static ref LOGGED_FAILURES: Mutex<HashMap<(String, String), Instant>> =
+        Mutex::new(HashMap::new());
+}
+
+pub fn validate_metric_name(name: &str) -> Result<(), String> {
+    if METRIC_NAME_REGEX.is_match(name) {
+        Ok(())
+    } else {
+        Err(format!(
+            "invalid metric name '{}', use letters, digits and underscores and don't start with a digit",
+            name
+        ))
+    }
+}
+
+// The number each metric's path selects in the body, or why there is none
+pub fn extract_values<'a>(
+    metrics: &'a BTreeMap<String, String>,
+    body: &str,
+) -> Vec<(&'a str, Result<f64, String>)> {
+    let json = if body.len() > MAX_EXTRACTION_BODY_BYTES {
+        Err(format!(
+            "response is larger than {} bytes",
+            MAX_EXTRACTION_BODY_BYTES
+        ))
+    } else {
+        serde_json::from_str::<Value>(body).map_err(|e| format!("response is not JSON: {}", e))
+    };
+    metrics
+        .iter()
+        .map(|(metric, path)| {
+            let value = match &json {
+                Ok(json) => extract_number(path, json),
+                Err(e) => Err(e.clone()),
+            };
+            (metric.as_str(), value)
+        })
+        .collect()
+}
+
+// The value is left out of the error, the probe may be sensitive
+fn extract_number(path: &str, json: &Value) -> Result<f64, String> {
+    let steps = parse_json_path(path)?;
+    match json_path_values(&steps, json).as_slice() {
+        [] => Err(format!("'{}' selects nothing", path)),
+        [value] => value
+            .as_f64()
+            .ok_or_else(|| format!("'{}' is not a number", path)),
+        values => Err(format!("'{}' selects {} values", path, values.len())),
+    }
+}
+
+// Sets the gauges of a successful run's `metrics_from_body`, counting the metrics that couldn't be extracted
+pub fn record_body_metrics(
+    app_state: &AppState,
+    probe: &Probe,
+    body: &str,
+    probe_attributes: &[KeyValue],
+) {
+    let Some(metrics) = &probe.metrics_from_body else {
+        return;
+    };
+    if !probe.extracts_body_metrics() {
+        return;
+    }
+    for (metric, value) in extract_values(metrics, body) {
+        match value {
+            Ok(value) => app_state
+                .metrics
+                .extracted_gauge(metric)
+                .record(value, probe_attributes),
+            Err(e) => {
+                let attributes = probe_attributes
+                    .iter()
+                    .cloned()
+                    .chain([KeyValue::new("metric", metric.to_owned())])
+                    .collect::<Vec<_>>();
+                app_state
+                    .metrics
+                    .body_extraction_failures
+                    .add(1, &attributes);
+                if should_log_failure(&probe.name, metric, Instant::now()) {
+                    warn!(
+                        "Can't extract metric '{}' of probe '{}': {}",
+                        metric, probe.name, e
+                    );
+                }
+            }
+        }
+    }
+}
+
+fn should_log_failure(probe: &str, metric: &str, now: Instant) -> bool {
+    let mut logged = LOGGED_FAILURES.lock().unwrap();
+    let key = (probe.to_owned(), metric.to_owned());
+    match logged.get(&key) {
+        Some(last) if now.duration_since(*last) < FAILURE_LOG_INTERVAL => false,
+        _ => {
+            logged.insert(key, now);
+            true
+        }
+    }
+}
+
+#[cfg(test)]
+mod body_metrics_tests {
+    use std::collections::BTreeMap;
+    use std::time::{Duration, Instant};
+
+    use super::{extract_values, should_log_failure, validate_metric_name};
+
+    fn metrics(paths: &[(&str, &str)]) -> BTreeMap<String, String> {
+        paths
+            .iter()
+            .map(|(name, path)| (name.to_string(), path.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_metric_names_follow_prometheus_rules() {
+        assert!(validate_metric_name("queue_depth").is_ok());
+        assert!(validate_metric_name("_oldest_item_age_s").is_ok());
+        assert!(validate_metric_name("1st").is_err());
+        assert!(validate_metric_name("queue-depth").is_err());
+        assert!(validate_metric_name("queue:depth").is_err());
+        assert!(validate_metric_name("").is_err());
+    }
+
+    #[test]
+    fn test_extracts_numbers_and_reports_the_rest() {
+        let metrics = metrics(&[
+            ("age", "$.oldest_item_age_s"),
+            ("depth", "$.queue_depth"),
+            ("missing", "$.nope"),
+            ("status", "$.status"),
+            ("workers", "$.workers[*].busy"),
+        ]);
+        let body = r#"{"queue_depth": 42, "oldest_item_age_s": 17.5, "status": "ok",
+            "workers": [{"busy": 1}, {"busy": 0}]}"#;
+
+        let values = extract_values(&metrics, body);
+
+        assert_eq!(
+            vec![
+                ("age", Ok(17.5)),
+                ("depth", Ok(42.0)),
+                ("missing", Err("'$.nope' selects nothing".to_owned())),
+                ("status", Err("'$.status' is not a number".to_owned())),
+                (
+                    "workers",
+                    Err("'$.workers[*].busy' selects 2 values".to_owned())
+                ),
+            ],
+            values
+        );
+    }
+
+    #[test]
+    fn test_large_and_non_json_bodies_fail_every_metric() {
+        let metrics = metrics(&[("depth", "$.queue_depth")]);
+
+        let large = format!(
+            r#"{{"queue_depth": 1, "padding": "{}"}}"#,
+            "x".repeat(1 << 20)
+        );
+        let values = extract_values(&metrics, &large);
+        assert!(values[0].1.as_ref().unwrap_err().contains("larger than"));
+
+        let values = extract_values(&metrics, "OK");
+        assert!(values[0].1.as_ref().unwrap_err().contains("not JSON"));
+    }
+
+    #[test]
+    fn test_failures_are_logged_once_per_hour() {
+        let now = Instant::now();
+
+        assert!(should_log_failure("Throttled probe", "depth", now));
+        assert!(!should_log_failure(
+            "Throttled probe",
+            "depth",
+            now + Duration::from_secs(60)
+        ));
+        assert!(should_log_failure("Throttled probe", "age", now));
+        assert!(should_log_failure(
+            "Throttled probe",
+            "depth",
+            now + Duration::from_secs(60 * 60)
+        ));
+    }
+}
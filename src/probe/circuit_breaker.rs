@@ -0,0 +1,198 @@
+// Probing a hard-down target less often, see a probe's `circuit_breaker`
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use super::duration;
+
+fn default_close_after_successes() -> u32 {
+    1
+}
+
+// `open_after_failures: 20`, `probe_interval_when_open: 5m`, `close_after_successes: 2`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CircuitBreakerSettings {
+    pub open_after_failures: u32,
+    // Plain numbers are seconds
+    #[serde(
+        deserialize_with = "duration::deserialize_required_seconds",
+        serialize_with = "duration::serialize_required"
+    )]
+    pub probe_interval_when_open: Duration,
+    #[serde(default = "default_close_after_successes")]
+    pub close_after_successes: u32,
+}
+
+impl CircuitBreakerSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.open_after_failures == 0 || self.close_after_successes == 0 {
+            return Err(
+                "`circuit_breaker` needs `open_after_failures` and `close_after_successes` of at least 1"
+                    .to_owned(),
+            );
+        }
+        Ok(())
+    }
+}
+
+// Closed probes on the normal schedule, open at `probe_interval_when_open`. The first success of an
+// open breaker makes it half-open, which probes on the normal schedule again until
+// `close_after_successes` successes in a row close it. Any failure while half-open reopens it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    #[default]
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+impl CircuitState {
+    // Value of the `circuit_breaker_state` gauge
+    pub fn as_u64(&self) -> u64 {
+        match self {
+            CircuitState::Closed => 0,
+            CircuitState::Open => 1,
+            CircuitState::HalfOpen => 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct CircuitBreaker {
+    pub state: CircuitState,
+    pub consecutive_failures: u32,
+    pub consecutive_successes: u32,
+    // The interval the probe currently runs at
+    #[serde(
+        deserialize_with = "duration::deserialize_required_seconds",
+        serialize_with = "duration::serialize_required"
+    )]
+    #[schemars(with = "String")]
+    pub interval: Duration,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub opened_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub half_opened_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub closed_at: Option<DateTime<Utc>>,
+}
+
+impl CircuitBreaker {
+    pub fn new(interval: Duration) -> CircuitBreaker {
+        CircuitBreaker {
+            state: CircuitState::Closed,
+            consecutive_failures: 0,
+            consecutive_successes: 0,
+            interval,
+            opened_at: None,
+            half_opened_at: None,
+            closed_at: None,
+        }
+    }
+
+    // Counts a run and returns the new state when it changed. `interval` is the probe's schedule.
+    pub fn record(
+        &mut self,
+        settings: &CircuitBreakerSettings,
+        success: bool,
+        interval: Duration,
+        now: DateTime<Utc>,
+    ) -> Option<CircuitState> {
+        if success {
+            self.consecutive_successes = self.consecutive_successes.saturating_add(1);
+            self.consecutive_failures = 0;
+        } else {
+            self.consecutive_failures = self.consecutive_failures.saturating_add(1);
+            self.consecutive_successes = 0;
+        }
+        let closes = self.consecutive_successes >= settings.close_after_successes;
+        let next = match (self.state, success) {
+            (CircuitState::Closed, false)
+                if self.consecutive_failures >= settings.open_after_failures =>
+            {
+                CircuitState::Open
+            }
+            (CircuitState::Open, true) if closes => CircuitState::Closed,
+            (CircuitState::Open, true) => CircuitState::HalfOpen,
+            (CircuitState::HalfOpen, true) if closes => CircuitState::Closed,
+            (CircuitState::HalfOpen, false) => CircuitState::Open,
+            (state, _) => state,
+        };
+        self.interval = match next {
+            CircuitState::Open => settings.probe_interval_when_open,
+            _ => interval,
+        };
+        if next == self.state {
+            return None;
+        }
+        match next {
+            CircuitState::Closed => self.closed_at = Some(now),
+            CircuitState::Open => self.opened_at = Some(now),
+            CircuitState::HalfOpen => self.half_opened_at = Some(now),
+        }
+        self.state = next;
+        Some(next)
+    }
+
+    // Mentioned in the alert of the run that opened the breaker
+    pub fn opened_note(&self) -> String {
+        format!(
+            "circuit opened, probing every {}",
+            humantime::format_duration(self.interval)
+        )
+    }
+}
+
+#[cfg(test)]
+mod circuit_breaker_tests {
+    use std::time::Duration;
+
+    use chrono::Utc;
+
+    use super::{CircuitBreaker, CircuitBreakerSettings, CircuitState};
+
+    #[test]
+    fn test_breaker_opens_half_opens_and_closes() {
+        let settings = CircuitBreakerSettings {
+            open_after_failures: 3,
+            probe_interval_when_open: Duration::from_secs(300),
+            close_after_successes: 2,
+        };
+        let interval = Duration::from_secs(10);
+        let mut breaker = CircuitBreaker::new(interval);
+        let mut record = |success| breaker.record(&settings, success, interval, Utc::now());
+
+        assert_eq!(None, record(false));
+        assert_eq!(None, record(false));
+        assert_eq!(Some(CircuitState::Open), record(false));
+        assert_eq!(None, record(false));
+        assert_eq!(Some(CircuitState::HalfOpen), record(true));
+        assert_eq!(Some(CircuitState::Open), record(false));
+        assert_eq!(Some(CircuitState::HalfOpen), record(true));
+        assert_eq!(Some(CircuitState::Closed), record(true));
+
+        assert_eq!(interval, breaker.interval);
+        assert!(breaker.opened_at.is_some() && breaker.closed_at.is_some());
+    }
+
+    #[test]
+    fn test_open_breaker_probes_at_the_reduced_interval() {
+        let settings = CircuitBreakerSettings {
+            open_after_failures: 1,
+            probe_interval_when_open: Duration::from_secs(300),
+            close_after_successes: 1,
+        };
+        let mut breaker = CircuitBreaker::new(Duration::from_secs(10));
+
+        breaker.record(&settings, false, Duration::from_secs(10), Utc::now());
+
+        assert_eq!(CircuitState::Open, breaker.state);
+        assert_eq!(Duration::from_secs(300), breaker.interval);
+        assert_eq!("circuit opened, probing every 5m", breaker.opened_note());
+        breaker.record(&settings, true, Duration::from_secs(10), Utc::now());
+        assert_eq!(CircuitState::Closed, breaker.state);
+    }
+}
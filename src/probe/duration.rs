@@ -0,0 +1,210 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+// A duration in the config, either a plain number in the unit of the field or a string such as "5s" or "1500ms"
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawDuration {
+    Number(u64),
+    Text(String),
+}
+
+fn parse(raw: RawDuration, unit: Duration) -> Result<Duration, String> {
+    match raw {
+        RawDuration::Number(number) => Ok(unit.saturating_mul(number.min(u32::MAX as u64) as u32)),
+        RawDuration::Text(text) => humantime::parse_duration(text.trim())
+            .map_err(|e| format!("invalid duration '{}': {}", text, e)),
+    }
+}
+
+fn deserialize_in_unit<'de, D>(
+    deserializer: D,
+    unit: Duration,
+) -> Result<Option<Duration>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Option::<RawDuration>::deserialize(deserializer)?
+        .map(|raw| parse(raw, unit))
+        .transpose()
+        .map_err(D::Error::custom)
+}
+
+// Plain numbers are milliseconds
+pub fn deserialize_millis<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserialize_in_unit(deserializer, Duration::from_millis(1))
+}
+
+// Plain numbers are seconds
+pub fn deserialize_seconds<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserialize_in_unit(deserializer, Duration::from_secs(1))
+}
+
+// A required field where plain numbers are seconds, as in `schedule`
+pub fn deserialize_required_seconds<'de, D>(deserializer: D) -> Result<Duration, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    parse(
+        RawDuration::deserialize(deserializer)?,
+        Duration::from_secs(1),
+    )
+    .map_err(D::Error::custom)
+}
+
+pub fn serialize_required<S>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.serialize_str(&humantime::format_duration(*duration).to_string())
+}
+
+// Writes durations back in the string form, e.g. "1s 500ms"
+pub fn serialize<S>(duration: &Option<Duration>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    match duration {
+        Some(duration) => {
+            serializer.serialize_str(&humantime::format_duration(*duration).to_string())
+        }
+        None => serializer.serialize_none(),
+    }
+}
+
+// Measured time between two timestamps, zero when the clock went backwards
+pub fn between(start: DateTime<Utc>, end: DateTime<Utc>) -> Duration {
+    (end - start).to_std().unwrap_or_default()
+}
+
+pub fn as_millis_f64(duration: Duration) -> f64 {
+    duration.as_nanos() as f64 / 1_000_000.0
+}
+
+// Measured durations as fractional milliseconds, e.g. `0.42`, so sub-millisecond runs don't read 0
+pub mod millis_f64 {
+    use std::time::Duration;
+
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S>(duration: &Option<Duration>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match duration {
+            Some(duration) => serializer.serialize_f64(super::as_millis_f64(*duration)),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Option::<f64>::deserialize(deserializer)?
+            .map(|millis| Duration::try_from_secs_f64(millis / 1_000.0).map_err(D::Error::custom))
+            .transpose()
+    }
+}
+
+#[cfg(test)]
+mod duration_tests {
+    use std::time::Duration;
+
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct Timeouts {
+        #[serde(
+            default,
+            deserialize_with = "super::deserialize_millis",
+            serialize_with = "super::serialize"
+        )]
+        millis: Option<Duration>,
+        #[serde(default, deserialize_with = "super::deserialize_seconds")]
+        seconds: Option<Duration>,
+    }
+
+    #[test]
+    fn test_numbers_use_the_unit_of_the_field() {
+        let timeouts: Timeouts = serde_yaml::from_str("millis: 5000\nseconds: 5").unwrap();
+
+        assert_eq!(Some(Duration::from_secs(5)), timeouts.millis);
+        assert_eq!(Some(Duration::from_secs(5)), timeouts.seconds);
+    }
+
+    #[test]
+    fn test_strings_carry_their_own_unit() {
+        let timeouts: Timeouts = serde_yaml::from_str("millis: 1s 500ms\nseconds: \"2m\"").unwrap();
+
+        assert_eq!(Some(Duration::from_millis(1500)), timeouts.millis);
+        assert_eq!(Some(Duration::from_secs(120)), timeouts.seconds);
+        assert_eq!(
+            "millis: 1s 500ms",
+            serde_yaml::to_string(&timeouts)
+                .unwrap()
+                .lines()
+                .next()
+                .unwrap()
+        );
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct Schedule {
+        #[serde(
+            deserialize_with = "super::deserialize_required_seconds",
+            serialize_with = "super::serialize_required"
+        )]
+        interval: Duration,
+    }
+
+    #[test]
+    fn test_required_durations_are_normalized() {
+        let numeric: Schedule = serde_yaml::from_str("interval: 5400").unwrap();
+        let text: Schedule = serde_yaml::from_str("interval: 1h30m").unwrap();
+        let millis: Schedule = serde_yaml::from_str("interval: 250ms").unwrap();
+
+        assert_eq!(Duration::from_secs(5400), numeric.interval);
+        assert_eq!(Duration::from_secs(5400), text.interval);
+        assert_eq!(Duration::from_millis(250), millis.interval);
+        assert_eq!(
+            "interval: 1h 30m\n",
+            serde_yaml::to_string(&numeric).unwrap()
+        );
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct Measured {
+        #[serde(default, with = "super::millis_f64")]
+        duration_ms: Option<Duration>,
+    }
+
+    #[test]
+    fn test_measured_durations_keep_sub_millisecond_precision() {
+        let measured = Measured {
+            duration_ms: Some(Duration::from_micros(420)),
+        };
+
+        let json = serde_json::to_string(&measured).unwrap();
+        assert_eq!(r#"{"duration_ms":0.42}"#, json);
+        let parsed: Measured = serde_json::from_str(&json).unwrap();
+        assert_eq!(Some(Duration::from_micros(420)), parsed.duration_ms);
+    }
+
+    #[test]
+    fn test_invalid_duration_is_rejected() {
+        let error = serde_yaml::from_str::<Timeouts>("millis: five seconds").unwrap_err();
+
+        assert!(error
+            .to_string()
+            .contains("invalid duration 'five seconds'"));
+    }
+}
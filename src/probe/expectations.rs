@@ -1,7 +1,16 @@
+use std::time::Instant;
+
+use opentelemetry::KeyValue;
+use tracing::warn;
+
+use crate::app_state::AppState;
 use crate::errors::ExpectationFailedError;
 use crate::probe::model::ExpectField;
 use crate::probe::model::ExpectOperation;
 use crate::probe::model::ProbeExpectation;
+use crate::probe::model::ResponseMeta;
+use crate::probe::model::StatusPattern;
+use crate::probe::script::{self, ScriptInput};
 use regex::Regex;
 use tracing::debug;
 
@@ -9,10 +18,17 @@ pub fn validate_response(
     step_name: &String,
     status_code: u32,
     body: String,
+    meta: &ResponseMeta,
     expectations: &Option<Vec<ProbeExpectation>>,
+    success_statuses: Option<&[StatusPattern]>,
 ) -> Result<(), ExpectationFailedError> {
+    // Explicit status expectations always win over the default success statuses
+    if let Some(statuses) = success_statuses.filter(|_| !has_status_expectation(expectations)) {
+        validate_success_status(statuses, status_code, &body)?;
+    }
     match expectations {
-        Some(expect_back) => match validate_response_internal(expect_back, status_code, body) {
+        Some(expect_back) => match validate_response_internal(expect_back, status_code, body, meta)
+        {
             Ok(_) => {
                 debug!("Successful response for {}, as expected", step_name);
                 Ok(())
@@ -35,13 +51,121 @@ pub fn validate_response(
     }
 }
 
+// `validate_response` for monitor runs. Large bodies and scripts are evaluated on the blocking pool so
+// regexes over megabytes of text don't stall the scheduler, and slow evaluations are logged and counted.
+pub async fn evaluate_expectations(
+    app_state: &AppState,
+    name: &str,
+    status_code: u32,
+    body: String,
+    meta: ResponseMeta,
+    expectations: &Option<Vec<ProbeExpectation>>,
+    success_statuses: Option<Vec<StatusPattern>>,
+) -> Result<(), ExpectationFailedError> {
+    let runtime = app_state.config.read().unwrap().settings.runtime.clone();
+    let started = Instant::now();
+    let body_size = body.len();
+    let result = if body_size >= runtime.blocking_body_threshold_bytes()
+        || has_script_expectation(expectations)
+    {
+        let name = name.to_owned();
+        let expectations = expectations.clone();
+        tokio::task::spawn_blocking(move || {
+            validate_response(
+                &name,
+                status_code,
+                body,
+                &meta,
+                &expectations,
+                success_statuses.as_deref(),
+            )
+        })
+        .await
+        .unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()))
+    } else {
+        validate_response(
+            &name.to_owned(),
+            status_code,
+            body,
+            &meta,
+            expectations,
+            success_statuses.as_deref(),
+        )
+    };
+
+    let elapsed = started.elapsed();
+    if elapsed >= runtime.max_blocking_duration_warning() {
+        warn!(
+            "Expectations of {} took {:?} over a body of {} bytes",
+            name, elapsed, body_size
+        );
+        app_state
+            .metrics
+            .slow_expectations
+            .add(1, &[KeyValue::new("name", name.to_owned())]);
+    }
+    result
+}
+
+pub fn has_status_expectation(expectations: &Option<Vec<ProbeExpectation>>) -> bool {
+    expectations
+        .iter()
+        .flatten()
+        .any(|expectation| matches!(expectation.field, ExpectField::StatusCode))
+}
+
+fn has_script_expectation(expectations: &Option<Vec<ProbeExpectation>>) -> bool {
+    expectations
+        .iter()
+        .flatten()
+        .any(|expectation| matches!(expectation.field, ExpectField::Script))
+}
+
+// Checks that go beyond the shape of the YAML, scripts are compiled here so syntax errors fail loading
+pub fn validate_expectations(expectations: &Option<Vec<ProbeExpectation>>) -> Result<(), String> {
+    for expectation in expectations.iter().flatten() {
+        let is_script = matches!(expectation.field, ExpectField::Script);
+        let passes = matches!(expectation.operation, ExpectOperation::Passes);
+        if is_script != passes {
+            return Err("the Script field and the Passes operation only go together".to_owned());
+        }
+        if is_script {
+            script::compile(&expectation.value)?;
+        }
+    }
+    Ok(())
+}
+
+fn validate_success_status(
+    statuses: &[StatusPattern],
+    status_code: u32,
+    body: &str,
+) -> Result<(), ExpectationFailedError> {
+    if statuses.iter().any(|status| status.matches(status_code)) {
+        return Ok(());
+    }
+    Err(ExpectationFailedError {
+        field: ExpectField::StatusCode,
+        expected: statuses
+            .iter()
+            .map(|status| status.to_string())
+            .collect::<Vec<_>>()
+            .join("|"),
+        body: body.to_owned(),
+        operation: ExpectOperation::IsOneOf,
+        status_code,
+        reason: None,
+    })
+}
+
 pub fn validate_response_internal(
     expect: &Vec<ProbeExpectation>,
     status_code: u32,
     body: String,
+    meta: &ResponseMeta,
 ) -> Result<(), ExpectationFailedError> {
     for expectation in expect {
-        validate_expectation(expectation, status_code, &body)?;
+        validate_expectation(expectation, status_code, &body, meta)?;
     }
 
     Ok(())
@@ -56,6 +180,8 @@ fn expectation_met(operation: &ExpectOperation, expected: &String, received: &St
         ExpectOperation::IsOneOf => expected.split('|').any(|part| part == received),
         // TODO: This regex could probably be pre-compiled?
         ExpectOperation::Matches => Regex::new(expected).unwrap().is_match(received),
+        // Only valid with the Script field, which doesn't compare values
+        ExpectOperation::Passes => false,
     }
 }
 
@@ -63,12 +189,14 @@ fn validate_expectation(
     expect: &ProbeExpectation,
     status_code: u32,
     body: &String,
+    meta: &ResponseMeta,
 ) -> Result<(), ExpectationFailedError> {
     let expected_value = &expect.value;
     let status_string = status_code.to_string();
     let received_value = match expect.field {
         ExpectField::Body => body,
         ExpectField::StatusCode => &status_string,
+        ExpectField::Script => return validate_script(expect, status_code, body, meta),
     };
     let success = expectation_met(&expect.operation, expected_value, received_value);
     if success {
@@ -80,10 +208,135 @@ fn validate_expectation(
             operation: expect.operation.clone(),
             field: expect.field.clone(),
             status_code,
+            reason: None,
         })
     }
 }
 
+fn validate_script(
+    expect: &ProbeExpectation,
+    status_code: u32,
+    body: &String,
+    meta: &ResponseMeta,
+) -> Result<(), ExpectationFailedError> {
+    let input = ScriptInput {
+        status: status_code,
+        headers: &meta.headers,
+        body,
+        duration: meta.duration,
+    };
+    script::run(&expect.value, &input).map_err(|reason| ExpectationFailedError {
+        expected: expect.value.clone(),
+        body: body.clone(),
+        operation: expect.operation.clone(),
+        field: expect.field.clone(),
+        status_code,
+        reason: Some(reason),
+    })
+}
+
+#[test]
+fn test_default_success_statuses() {
+    let statuses = [
+        StatusPattern::try_from("2xx".to_owned()).unwrap(),
+        StatusPattern::try_from("3XX".to_owned()).unwrap(),
+    ];
+    let name = "probe".to_owned();
+    let meta = ResponseMeta::default();
+
+    assert!(validate_response(&name, 302, "".to_owned(), &meta, &None, Some(&statuses)).is_ok());
+    assert!(validate_response(&name, 500, "".to_owned(), &meta, &None, Some(&statuses)).is_err());
+    assert!(validate_response(&name, 500, "".to_owned(), &meta, &None, None).is_ok());
+
+    // An explicit status expectation wins over the defaults
+    let expectations = Some(vec![ProbeExpectation {
+        field: ExpectField::StatusCode,
+        operation: ExpectOperation::Equals,
+        value: "503".to_owned(),
+    }]);
+    assert!(validate_response(
+        &name,
+        503,
+        "".to_owned(),
+        &meta,
+        &expectations,
+        Some(&statuses)
+    )
+    .is_ok());
+}
+
+#[test]
+fn test_script_field_and_passes_operation_go_together() {
+    let expectation = |field, operation| {
+        Some(vec![ProbeExpectation {
+            field,
+            operation,
+            value: "true".to_owned(),
+        }])
+    };
+
+    assert!(
+        validate_expectations(&expectation(ExpectField::Body, ExpectOperation::Passes)).is_err()
+    );
+    assert!(
+        validate_expectations(&expectation(ExpectField::Script, ExpectOperation::Equals)).is_err()
+    );
+}
+
+#[cfg(feature = "scripting")]
+#[test]
+fn test_script_failure_carries_the_reason() {
+    let expectations = Some(vec![ProbeExpectation {
+        field: ExpectField::Script,
+        operation: ExpectOperation::Passes,
+        value: r#"if headers["x-served-by"] == "eu-1" { true } else { "wrong backend" }"#
+            .to_owned(),
+    }]);
+    let meta = |served_by: &str| ResponseMeta {
+        headers: std::collections::HashMap::from([(
+            "x-served-by".to_owned(),
+            served_by.to_owned(),
+        )]),
+        duration: std::time::Duration::from_millis(5),
+    };
+    let name = "probe".to_owned();
+
+    assert!(validate_response(
+        &name,
+        200,
+        "".to_owned(),
+        &meta("eu-1"),
+        &expectations,
+        None
+    )
+    .is_ok());
+    let error = validate_response(
+        &name,
+        200,
+        "".to_owned(),
+        &meta("us-1"),
+        &expectations,
+        None,
+    )
+    .unwrap_err();
+    assert_eq!(Some("wrong backend"), error.reason.as_deref());
+    assert_eq!(
+        "Script expectation failed: wrong backend",
+        error.to_string()
+    );
+}
+
+#[test]
+fn test_status_pattern_parsing() {
+    assert_eq!(
+        StatusPattern::Exact(302),
+        StatusPattern::try_from("302".to_owned()).unwrap()
+    );
+    assert!(StatusPattern::try_from("6xx".to_owned()).is_err());
+    assert!(StatusPattern::try_from("ok".to_owned()).is_err());
+    assert_eq!("4xx", StatusPattern::Class(4).to_string());
+}
+
 #[tokio::test]
 async fn test_validate_expectations_equals() {
     let success_result = expectation_met(
@@ -184,4 +437,4 @@ async fn test_validate_expectations_matches() {
         &"1234".to_owned(),
     );
     assert!(!fail_result);
-}
\ No newline at end of file
+}
@@ -0,0 +1,184 @@
+// The DNS and TLS phases of a `composite: full_stack` probe, its http phase is a regular http run
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use chrono::{TimeZone, Utc};
+use tokio::net::TcpStream;
+use tokio::time::Instant;
+
+use super::http_probe::DEFAULT_REQUEST_TIMEOUT_SECS;
+use super::model::{CertificateDetails, FullStackDetails, FullStackParameters, FullStackPhase};
+
+fn phase(name: &str, started: Instant, error: Option<String>) -> FullStackPhase {
+    FullStackPhase {
+        name: name.to_owned(),
+        success: error.is_none(),
+        duration_ms: started.elapsed().as_millis() as u64,
+        error,
+    }
+}
+
+// The host and port of an https url, 443 unless the url names another
+pub fn host_and_port(url: &str) -> Result<(String, u16), String> {
+    let parsed = reqwest::Url::parse(url).map_err(|e| format!("invalid url '{}': {}", url, e))?;
+    let host = parsed
+        .host_str()
+        .ok_or_else(|| format!("url '{}' has no host", url))?;
+    Ok((
+        host.trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_owned(),
+        parsed.port_or_known_default().unwrap_or(443),
+    ))
+}
+
+async fn resolve(host: &str, port: u16, timeout: Duration) -> Result<Vec<SocketAddr>, String> {
+    let addresses = tokio::time::timeout(timeout, tokio::net::lookup_host((host, port)))
+        .await
+        .map_err(|_| format!("timed out resolving {}", host))?
+        .map_err(|e| format!("resolving {} failed: {}", host, e))?
+        .collect::<Vec<_>>();
+    if addresses.is_empty() {
+        return Err(format!("{} has no addresses", host));
+    }
+    Ok(addresses)
+}
+
+// Connects to the address itself, so the handshake tests the server DNS pointed at
+async fn handshake(host: &str, address: SocketAddr) -> Result<Option<CertificateDetails>, String> {
+    let stream = TcpStream::connect(address)
+        .await
+        .map_err(|e| format!("connecting to {} failed: {}", address, e))?;
+    let connector = native_tls::TlsConnector::new()
+        .map(tokio_native_tls::TlsConnector::from)
+        .map_err(|e| e.to_string())?;
+    let tls_stream = connector
+        .connect(host, stream)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(certificate(&tls_stream))
+}
+
+fn certificate(tls_stream: &tokio_native_tls::TlsStream<TcpStream>) -> Option<CertificateDetails> {
+    let certificate = tls_stream.get_ref().peer_certificate().ok()??;
+    let der = certificate.to_der().ok()?;
+    let (_, parsed) = x509_parser::parse_x509_certificate(&der).ok()?;
+    let not_after = Utc
+        .timestamp_opt(parsed.validity().not_after.timestamp(), 0)
+        .single()?;
+    Some(CertificateDetails {
+        subject: parsed.subject().to_string(),
+        issuer: parsed.issuer().to_string(),
+        not_after,
+        expires_in_days: (not_after - Utc::now()).num_days(),
+    })
+}
+
+// The `dns` and `tls` phases, the handshake only runs once DNS passed
+pub async fn check_dns_and_tls(url: &str, params: &FullStackParameters) -> FullStackDetails {
+    let default_timeout = Duration::from_secs(DEFAULT_REQUEST_TIMEOUT_SECS);
+    let mut details = FullStackDetails::default();
+
+    let started = Instant::now();
+    let resolved = match host_and_port(url) {
+        Ok((host, port)) => resolve(&host, port, params.dns_timeout.unwrap_or(default_timeout))
+            .await
+            .map(|addresses| (host, addresses)),
+        Err(e) => Err(e),
+    };
+    let (host, address) = match resolved {
+        Ok((host, addresses)) => {
+            details.phases.push(phase("dns", started, None));
+            details.addresses = addresses.iter().map(SocketAddr::ip).collect();
+            (host, addresses[0])
+        }
+        Err(e) => {
+            details.phases.push(phase("dns", started, Some(e)));
+            return details;
+        }
+    };
+
+    let started = Instant::now();
+    let timeout = params.tls_timeout.unwrap_or(default_timeout);
+    match tokio::time::timeout(timeout, handshake(&host, address)).await {
+        Ok(Ok(certificate)) => {
+            details.phases.push(phase("tls", started, None));
+            details.certificate = certificate;
+        }
+        Ok(Err(e)) => details.phases.push(phase("tls", started, Some(e))),
+        Err(_) => details.phases.push(phase(
+            "tls",
+            started,
+            Some("timed out during the handshake".to_owned()),
+        )),
+    }
+    details
+}
+
+#[cfg(test)]
+mod full_stack_probe_tests {
+    use std::time::Duration;
+
+    use tokio::net::TcpListener;
+
+    use super::{check_dns_and_tls, host_and_port};
+    use crate::probe::model::{FullStackDetails, FullStackParameters, FullStackPhase};
+
+    #[test]
+    fn test_host_and_port() {
+        assert_eq!(
+            ("example.com".to_owned(), 443),
+            host_and_port("https://example.com/health").unwrap()
+        );
+        assert_eq!(
+            ("::1".to_owned(), 8443),
+            host_and_port("https://[::1]:8443/").unwrap()
+        );
+        assert!(host_and_port("not a url").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_handshake_with_a_plain_tcp_server_fails_the_tls_phase() {
+        // Accepts and closes every connection, so the handshake fails right away
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                drop(stream);
+            }
+        });
+        let params = FullStackParameters {
+            dns_timeout: None,
+            tls_timeout: Some(Duration::from_secs(5)),
+        };
+
+        let details = check_dns_and_tls(&format!("https://{}/health", address), &params).await;
+
+        assert_eq!(2, details.phases.len());
+        assert!(details.phases[0].success);
+        assert_eq!(vec![address.ip()], details.addresses);
+        assert_eq!("tls", details.phases[1].name);
+        assert!(!details.phases[1].success);
+        assert!(details.certificate.is_none());
+        assert_eq!("DNS ok, TLS failed", details.summary(None));
+    }
+
+    #[test]
+    fn test_summary_names_the_status_of_a_failed_http_phase() {
+        let phase = |name: &str, success| FullStackPhase {
+            name: name.to_owned(),
+            success,
+            duration_ms: 1,
+            error: None,
+        };
+        let details = FullStackDetails {
+            phases: vec![phase("dns", true), phase("tls", true), phase("http", false)],
+            ..Default::default()
+        };
+
+        assert_eq!(
+            "DNS ok, TLS ok, HTTP failed (503)",
+            details.summary(Some(503))
+        );
+    }
+}
@@ -1,8 +1,11 @@
-
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::net::SocketAddr;
 use std::str::FromStr;
-use std::time::Duration;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use crate::errors::MapToSendError;
+use crate::audit::AuditScope;
 use chrono::Utc;
 use lazy_static::lazy_static;
 use opentelemetry::KeyValue;
@@ -12,86 +15,174 @@ use opentelemetry::trace::Span;
 use opentelemetry::trace::SpanId;
 use opentelemetry::trace::TraceId;
 
-use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
-use reqwest::RequestBuilder;
 use http::HeaderMap as HttpHeaderMap;
+use hyper::client::connect::dns::Name;
+use reqwest::dns::{Addrs, Resolve, Resolving};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, SET_COOKIE, USER_AGENT};
+use reqwest::RequestBuilder;
 
+use super::aws_sigv4::{clock_skew_error, is_clock_skew_rejection, sign_request};
 use super::model::EndpointResult;
-use super::model::ProbeInputParameters;
+use super::model::HttpTimings;
+use super::model::ProbeOptions;
+use opentelemetry::baggage::BaggageExt;
 use opentelemetry::trace::TraceContextExt;
 use opentelemetry::Context;
 use opentelemetry::{global, trace::Tracer};
-use std::fs::OpenOptions;
-use std::io::Write;
 
-const DEFAULT_REQUEST_TIMEOUT_SECS: u64 = 10;
+pub(crate) const DEFAULT_REQUEST_TIMEOUT_SECS: u64 = 10;
+// Baggage key set by stories, forwarded to the target as the X-Story-Run-Id header
+pub(crate) const STORY_RUN_ID_KEY: &str = "story_run_id";
+const STORY_RUN_ID_HEADER: &str = "x-story-run-id";
 
-// #region agent log
-fn agent_log(hypothesis_id: &str, location: &str, message: &str, data: serde_json::Value) {
-    if let Ok(mut file) = OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open("c:\\Users\\floris\\Documents\\GitHub\\xbp-monitoring\\.cursor\\debug.log")
-    {
-        if let Ok(line) = serde_json::to_string(&serde_json::json!({
-            "sessionId": "debug-session",
-            "runId": "pre-fix",
-            "hypothesisId": hypothesis_id,
-            "location": location,
-            "message": message,
-            "data": data,
-            "timestamp": Utc::now().timestamp_millis(),
-        })) {
-            let _ = writeln!(file, "{}", line);
-        }
-    }
+lazy_static! {
+    // Doesn't follow redirects, the probe records the 3xx response itself so expectations and
+    // `success_statuses` see what the endpoint answered
+    static ref CLIENT: reqwest::Client = client_builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+    // For `with.follow_redirects`, up to reqwest's default of 10 hops
+    static ref REDIRECT_CLIENT: reqwest::Client = client_builder().build().unwrap();
 }
-// #endregion
 
-lazy_static! {
-    static ref CLIENT: reqwest::Client = reqwest::ClientBuilder::new()
-        .user_agent("Prodzilla Probe/1.0")
+fn client_builder() -> reqwest::ClientBuilder {
+    reqwest::ClientBuilder::new()
+        .user_agent(concat!("xbp-monitoring/", env!("CARGO_PKG_VERSION")))
         .pool_idle_timeout(None)
         .pool_max_idle_per_host(0)
-        .build()
-        .unwrap();
+        .dns_resolver(Arc::new(TimedResolver))
+}
+
+fn client(input_parameters: &Option<ProbeOptions>) -> &'static reqwest::Client {
+    match input_parameters {
+        Some(params) if params.follow_redirects => &REDIRECT_CLIENT,
+        _ => &CLIENT,
+    }
+}
+
+tokio::task_local! {
+    // Time spent in DNS lookups by the request `call_endpoint` is sending
+    static DNS_LOOKUP: Cell<Option<Duration>>;
+}
+
+// The system resolver, adding the time of each lookup to `DNS_LOOKUP`. Connections aren't pooled,
+// so every request looks its host up again.
+struct TimedResolver;
+
+impl Resolve for TimedResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let started = Instant::now();
+            let addrs: Vec<SocketAddr> =
+                tokio::net::lookup_host((name.as_str(), 0)).await?.collect();
+            let _ = DNS_LOOKUP.try_with(|lookup| {
+                lookup.set(Some(lookup.get().unwrap_or_default() + started.elapsed()))
+            });
+            Ok::<_, Box<dyn std::error::Error + Send + Sync>>(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
 }
 
 pub async fn call_endpoint(
     http_method: &str,
     url: &String,
-    input_parameters: &Option<ProbeInputParameters>,
+    input_parameters: &Option<ProbeOptions>,
     sensitive: bool,
-) -> Result<EndpointResult, Box<dyn std::error::Error + Send>> {
+    audit: Option<AuditScope<'_>>,
+) -> Result<EndpointResult, Box<dyn std::error::Error + Send + Sync>> {
     let timestamp_start = Utc::now();
     let (otel_headers, cx, span_id, trace_id) =
         get_otel_headers(format!("{} {}", http_method, url));
 
-    let request = build_request(http_method, url, input_parameters, otel_headers)?;
-    let request_timeout = Duration::from_secs(
-        input_parameters
-            .as_ref()
-            .and_then(|params| params.timeout_seconds)
-            .unwrap_or(DEFAULT_REQUEST_TIMEOUT_SECS),
-    );
-    let response = request
+    let request_timeout = input_parameters
+        .as_ref()
+        .and_then(|params| params.timeout())
+        .unwrap_or(Duration::from_secs(DEFAULT_REQUEST_TIMEOUT_SECS));
+    let mut request = build_request(http_method, url, input_parameters, otel_headers)?
         .timeout(request_timeout)
-        .send()
-        .with_context(cx.clone())
-        .await
-        .map_to_send_err()?;
+        .build()?;
+
+    // Signed right before sending so every run gets a fresh signature and x-amz-date
+    let sigv4 = input_parameters
+        .as_ref()
+        .and_then(|params| params.auth.as_ref())
+        .and_then(|auth| auth.aws_sigv4.as_ref());
+    let signed_at = Utc::now();
+    if let Some(sigv4) = sigv4 {
+        sign_request(&mut request, sigv4, signed_at)?;
+    }
+
+    let audit_record = audit.and_then(|scope| scope.start(&request));
+    let sent = Instant::now();
+    let (response, dns_lookup) = DNS_LOOKUP
+        .scope(Cell::new(None), async {
+            let response = client(input_parameters)
+                .execute(request)
+                .with_context(cx.clone())
+                .await;
+            (response, DNS_LOOKUP.with(Cell::get))
+        })
+        .await;
+    let headers_received = Instant::now();
+    let response = match response {
+        Ok(response) => response,
+        Err(e) => {
+            if let (Some(scope), Some(record)) = (audit, audit_record) {
+                scope.finish(record.with_error(&e));
+            }
+            return Err(e.into());
+        }
+    };
 
     let timestamp_response = Utc::now();
+    let status_code = response.status().as_u16() as u32;
+    let remote_addr = response.remote_addr();
+    if let (Some(scope), Some(record)) = (audit, audit_record) {
+        scope.finish(record.with_response(status_code, remote_addr.map(|addr| addr.ip())));
+    }
+    let mut headers: HashMap<String, String> = HashMap::new();
+    for (name, value) in response.headers() {
+        let value = String::from_utf8_lossy(value.as_bytes());
+        headers
+            .entry(name.as_str().to_owned())
+            .and_modify(|joined| {
+                joined.push_str(", ");
+                joined.push_str(&value);
+            })
+            .or_insert_with(|| value.into_owned());
+    }
+    let set_cookies = response
+        .headers()
+        .get_all(SET_COOKIE)
+        .iter()
+        .map(|value| String::from_utf8_lossy(value.as_bytes()).into_owned())
+        .collect();
+    let body = response.text().await?;
+    let timings = HttpTimings {
+        dns_lookup,
+        time_to_first_byte: (headers_received - sent)
+            .saturating_sub(dns_lookup.unwrap_or_default()),
+        body_download: headers_received.elapsed(),
+    };
 
     let result = EndpointResult {
         timestamp_request_started: timestamp_start,
         timestamp_response_received: timestamp_response,
-        status_code: response.status().as_u16() as u32,
-        body: response.text().await.map_to_send_err()?,
+        timestamp_body_received: Utc::now(),
+        status_code,
+        headers,
+        set_cookies,
+        remote_addr,
+        body,
         sensitive,
         trace_id: trace_id.to_string(),
         span_id: span_id.to_string(),
+        timings,
     };
+    if sigv4.is_some() && is_clock_skew_rejection(result.status_code, &result.body) {
+        return Err(Box::new(clock_skew_error(signed_at)));
+    }
     let span = cx.span();
     span.set_attributes(vec![
         KeyValue::new("http.request.method", http_method.to_owned()),
@@ -101,18 +192,6 @@ pub async fn call_endpoint(
         "http.response.status_code",
         result.status_code as i64,
     ));
-    // #region agent log
-    agent_log(
-        "E",
-        "http_probe.rs:call_endpoint",
-        "response received",
-        serde_json::json!({
-            "method": http_method,
-            "url": url,
-            "status": result.status_code
-        }),
-    );
-    // #endregion
     if !sensitive {
         span.add_event(
             "response",
@@ -134,7 +213,10 @@ fn get_otel_headers(span_name: String) -> (HeaderMap, Context, SpanId, TraceId)
 
     let mut otel_headers = HttpHeaderMap::new();
     global::get_text_map_propagator(|propagator| {
-        propagator.inject_context(&cx, &mut opentelemetry_http::HeaderInjector(&mut otel_headers));
+        propagator.inject_context(
+            &cx,
+            &mut opentelemetry_http::HeaderInjector(&mut otel_headers),
+        );
     });
 
     let mut reqwest_headers = HeaderMap::new();
@@ -146,19 +228,35 @@ fn get_otel_headers(span_name: String) -> (HeaderMap, Context, SpanId, TraceId)
             reqwest_headers.insert(req_name, req_value);
         }
     }
+    if let Some(story_run_id) = cx.baggage().get(STORY_RUN_ID_KEY) {
+        if let Ok(value) = HeaderValue::from_str(story_run_id.as_ref()) {
+            reqwest_headers.insert(STORY_RUN_ID_HEADER, value);
+        }
+    }
 
     (reqwest_headers, cx, span_id, trace_id)
 }
 
 fn build_request(
     http_method: &str,
-    url: &String,
-    input_parameters: &Option<ProbeInputParameters>,
+    url: &str,
+    input_parameters: &Option<ProbeOptions>,
     otel_headers: HeaderMap,
-) -> Result<RequestBuilder, Box<dyn std::error::Error + Send>> {
-    let method = reqwest::Method::from_str(http_method).map_to_send_err()?;
+) -> Result<RequestBuilder, Box<dyn std::error::Error + Send + Sync>> {
+    let method = reqwest::Method::from_str(http_method)?;
+
+    let mut url = reqwest::Url::parse(url)?;
+    if let Some(query) = input_parameters
+        .as_ref()
+        .and_then(|params| params.query.as_ref())
+    {
+        // Sorted so the url is the same on every run
+        let mut pairs: Vec<_> = query.iter().collect();
+        pairs.sort();
+        url.query_pairs_mut().extend_pairs(pairs);
+    }
 
-    let mut request = CLIENT.request(method, url);
+    let mut request = client(input_parameters).request(method, url);
     request = request.headers(otel_headers);
 
     if let Some(probe_input_parameters) = input_parameters {
@@ -170,6 +268,10 @@ fn build_request(
                 request = request.header(key, value);
             }
         }
+        // Request headers take precedence over the client's default User-Agent
+        if let Some(user_agent) = &probe_input_parameters.user_agent {
+            request = request.header(USER_AGENT, user_agent);
+        }
     }
 
     Ok(request)
@@ -178,19 +280,21 @@ fn build_request(
 #[cfg(test)]
 mod http_tests {
 
-    use std::env;
+    use std::collections::HashMap;
     use std::time::Duration;
 
     use crate::otel;
+    use crate::otel::{ExporterKind, OtelConfig};
     use crate::probe::expectations::validate_response;
     use crate::probe::http_probe::call_endpoint;
+    use crate::probe::model::{AwsSigV4Parameters, ProbeAuth, ProbeOptions};
     use crate::test_utils::probe_test_utils::{
         probe_get_with_expected_status, probe_get_with_timeout_and_expected_status,
         probe_post_with_expected_body,
     };
 
     use reqwest::StatusCode;
-    use wiremock::matchers::{body_string, header_exists, method, path};
+    use wiremock::matchers::{body_string, header, header_exists, method, path, query_param};
     use wiremock::{Mock, MockServer, ResponseTemplate};
 
     // Note: These tests are a bit odd because they have been updated since a refactor
@@ -210,19 +314,87 @@ mod http_tests {
             format!("{}/test", mock_server.uri()),
             "".to_owned(),
         );
-        let endpoint_result = call_endpoint(&probe.http_method, &probe.url, &probe.with, false)
-            .await
-            .unwrap();
+        let endpoint_result =
+            call_endpoint(&probe.http_method, &probe.url, &probe.with, false, None)
+                .await
+                .unwrap();
         let check_expectations_result = validate_response(
             &probe.name,
             endpoint_result.status_code,
-            endpoint_result.body,
+            endpoint_result.body.clone(),
+            &endpoint_result.meta(),
             &probe.expectations,
+            None,
         );
 
         assert!(check_expectations_result.is_ok());
     }
 
+    #[tokio::test]
+    async fn test_dns_lookup_is_timed_for_host_names_only() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/test"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&mock_server)
+            .await;
+        let by_ip = format!("{}/test", mock_server.uri());
+        let by_name = by_ip.replace("127.0.0.1", "localhost");
+
+        let ip_result = call_endpoint("GET", &by_ip, &None, false, None)
+            .await
+            .unwrap();
+        let name_result = call_endpoint("GET", &by_name, &None, false, None)
+            .await
+            .unwrap();
+
+        assert_eq!(None, ip_result.timings.dns_lookup);
+        assert!(name_result.timings.dns_lookup.is_some());
+        let phases: Vec<_> = name_result
+            .timings
+            .phases()
+            .into_iter()
+            .map(|phase| phase.name)
+            .collect();
+        assert_eq!(vec!["dns_lookup", "ttfb", "body"], phases);
+    }
+
+    #[tokio::test]
+    async fn test_user_agent_defaults_to_xbp_and_can_be_overridden() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/default"))
+            .and(header(
+                "user-agent",
+                concat!("xbp-monitoring/", env!("CARGO_PKG_VERSION")),
+            ))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/custom"))
+            .and(header("user-agent", "acme-synthetics/2"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&mock_server)
+            .await;
+
+        let mut probe = probe_get_with_expected_status(
+            StatusCode::OK,
+            format!("{}/default", mock_server.uri()),
+            "".to_owned(),
+        );
+        let default = call_endpoint(&probe.http_method, &probe.url, &probe.with, false, None)
+            .await
+            .unwrap();
+        probe.url = format!("{}/custom", mock_server.uri());
+        probe.with.as_mut().unwrap().user_agent = Some("acme-synthetics/2".to_owned());
+        let custom = call_endpoint(&probe.http_method, &probe.url, &probe.with, false, None)
+            .await
+            .unwrap();
+
+        assert_eq!((200, 200), (default.status_code, custom.status_code));
+    }
+
     #[tokio::test]
     async fn test_requests_get_timeout() {
         let mock_server = MockServer::start().await;
@@ -241,7 +413,7 @@ mod http_tests {
             body.to_string(),
         );
         let endpoint_result =
-            call_endpoint(&probe.http_method, &probe.url, &probe.with, false).await;
+            call_endpoint(&probe.http_method, &probe.url, &probe.with, false, None).await;
 
         assert!(endpoint_result.is_err());
     }
@@ -265,7 +437,7 @@ mod http_tests {
             Some(1), // Timeout is 1 second, reduced from default of 10
         );
         let endpoint_result =
-            call_endpoint(&probe.http_method, &probe.url, &probe.with, false).await;
+            call_endpoint(&probe.http_method, &probe.url, &probe.with, false, None).await;
 
         assert!(endpoint_result.is_err());
     }
@@ -288,14 +460,17 @@ mod http_tests {
             format!("{}/test", mock_server.uri()),
             body.to_string(),
         );
-        let endpoint_result = call_endpoint(&probe.http_method, &probe.url, &probe.with, false)
-            .await
-            .unwrap();
+        let endpoint_result =
+            call_endpoint(&probe.http_method, &probe.url, &probe.with, false, None)
+                .await
+                .unwrap();
         let check_expectations_result = validate_response(
             &probe.name,
             endpoint_result.status_code,
-            endpoint_result.body,
+            endpoint_result.body.clone(),
+            &endpoint_result.meta(),
             &probe.expectations,
+            None,
         );
 
         assert!(check_expectations_result.is_ok());
@@ -304,8 +479,10 @@ mod http_tests {
     #[tokio::test]
     async fn test_requests_post_200_with_body() {
         // necessary for trace propagation
-        env::set_var("OTEL_TRACES_EXPORTER", "otlp");
-        otel::tracing::create_tracer();
+        otel::tracing::create_tracer(&OtelConfig {
+            traces_exporter: ExporterKind::Otlp,
+            ..Default::default()
+        });
         let mock_server = MockServer::start().await;
 
         let request_body = "request body";
@@ -325,16 +502,101 @@ mod http_tests {
             format!("{}/test", mock_server.uri()),
             request_body.to_owned(),
         );
-        let endpoint_result = call_endpoint(&probe.http_method, &probe.url, &probe.with, false)
-            .await
-            .unwrap();
+        let endpoint_result =
+            call_endpoint(&probe.http_method, &probe.url, &probe.with, false, None)
+                .await
+                .unwrap();
         let check_expectations_result = validate_response(
             &probe.name,
             endpoint_result.status_code,
-            endpoint_result.body,
+            endpoint_result.body.clone(),
+            &endpoint_result.meta(),
             &probe.expectations,
+            None,
         );
 
         assert!(check_expectations_result.is_ok());
     }
-}
\ No newline at end of file
+
+    fn sigv4_input_parameters() -> Option<ProbeOptions> {
+        Some(ProbeOptions {
+            headers: None,
+            body: None,
+            timeout: None,
+            timeout_seconds: None,
+            query: Some(HashMap::from([
+                ("name".to_owned(), "a b&c".to_owned()),
+                ("after".to_owned(), "2024-01-01".to_owned()),
+            ])),
+            auth: Some(ProbeAuth {
+                aws_sigv4: Some(AwsSigV4Parameters {
+                    region: "eu-west-1".to_owned(),
+                    service: "execute-api".to_owned(),
+                    access_key_id: Some("AKIDEXAMPLE".to_owned()),
+                    secret_access_key: Some("secret".to_owned()),
+                    session_token: None,
+                }),
+            }),
+            user_agent: None,
+            respect_retry_after: false,
+            follow_redirects: false,
+            allow_empty: None,
+            samples: None,
+            prefetch: None,
+        })
+    }
+
+    #[tokio::test]
+    async fn test_sigv4_signed_request_with_query() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path("/items"))
+            .and(query_param("name", "a b&c"))
+            .and(query_param("after", "2024-01-01"))
+            .and(header_exists("x-amz-date"))
+            .and(header_exists("authorization"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let endpoint_result = call_endpoint(
+            "GET",
+            &format!("{}/items", mock_server.uri()),
+            &sigv4_input_parameters(),
+            false,
+            None,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(200, endpoint_result.status_code);
+    }
+
+    #[tokio::test]
+    async fn test_sigv4_clock_skew_is_reported() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path("/items"))
+            .respond_with(ResponseTemplate::new(403).set_body_string(
+                r#"{"message":"Signature expired: 20240101T000000Z is now earlier than 20240101T000500Z"}"#,
+            ))
+            .mount(&mock_server)
+            .await;
+
+        let error = call_endpoint(
+            "GET",
+            &format!("{}/items", mock_server.uri()),
+            &sigv4_input_parameters(),
+            false,
+            None,
+        )
+        .await
+        .err()
+        .unwrap();
+
+        assert!(error.to_string().contains("clock skew"));
+    }
+}
@@ -1,6 +1,21 @@
+pub(crate) mod aws_sigv4;
+pub(crate) mod body_metrics;
+pub(crate) mod circuit_breaker;
+pub(crate) mod duration;
 pub(crate) mod expectations;
+pub(crate) mod full_stack_probe;
 pub(crate) mod http_probe;
-pub(crate) mod model;
+pub mod model;
+pub(crate) mod ntp_probe;
+pub(crate) mod prefetch;
 pub(crate) mod probe_logic;
-pub(crate) mod schedule;
-pub(crate) mod variables;
\ No newline at end of file
+pub(crate) mod rate_limit;
+pub(crate) mod samples;
+pub mod schedule;
+pub(crate) mod script;
+pub(crate) mod sftp_probe;
+pub(crate) mod sla_window;
+pub(crate) mod smtp_probe;
+pub(crate) mod span_events;
+pub(crate) mod story_expectations;
+pub(crate) mod variables;
@@ -1,28 +1,757 @@
 use chrono::{DateTime, Utc};
 
+use lazy_static::lazy_static;
+use regex::Regex;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+
+use crate::alerts::integrations::opsgenie::{OpsgeniePriority, OpsgenieRegion, OpsgenieResponder};
+use crate::config::Settings;
+use crate::errors::AlertChannel;
+use crate::probe::body_metrics::validate_metric_name;
+use crate::probe::circuit_breaker::CircuitBreakerSettings;
+use crate::probe::duration;
+use crate::probe::prefetch;
+use crate::probe::sftp_probe;
+use crate::probe::sla_window::ParsedSlaWindow;
+use crate::probe::variables::parse_json_path;
+use std::collections::{BTreeMap, HashMap};
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::time::Duration;
+use uuid::Uuid;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Probe {
     pub name: String,
+    #[serde(default, rename = "type")]
+    pub probe_type: ProbeType,
+    // Unset when the probe has `urls`
+    #[serde(default)]
     pub url: String,
+    // Endpoints checked in turn on every run instead of `url`, e.g. each backend behind a load
+    // balancer. The run fails when any of them fails.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub urls: Option<Vec<String>>,
+    #[serde(default = "default_http_method")]
     pub http_method: String,
-    pub with: Option<ProbeInputParameters>,
+    pub with: Option<ProbeOptions>,
     pub expectations: Option<Vec<ProbeExpectation>>,
     pub schedule: ProbeScheduleParameters,
     pub alerts: Option<Vec<ProbeAlert>>,
     #[serde(default)] // default to false
     pub sensitive: bool,
     pub tags: Option<HashMap<String, String>>,
+    // The team or namespace the probe belongs to, `?group=` limits API listings to one group
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    // Only results inside it count toward the SLA uptime, the others are still stored and alert
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sla_window: Option<SlaWindow>,
+    // Consecutive successful runs required before a failing probe is reported as OK again
+    pub recovery_threshold: Option<u32>,
+    // Failed runs are repeated right away up to this many times, only the last attempt is stored
+    pub retries: Option<u32>,
+    // Probes a monitor that keeps failing less often until it recovers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub circuit_breaker: Option<CircuitBreakerSettings>,
+    pub smtp: Option<SmtpParameters>,
+    pub ntp: Option<NtpParameters>,
+    pub sftp: Option<SftpParameters>,
+    // `full_stack` checks DNS, the TLS handshake and the request of an https probe as separate
+    // phases, so a failure says which of them broke
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub composite: Option<Composite>,
+    // Timeouts of the DNS and TLS phases of a `composite: full_stack` probe
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub full_stack: Option<FullStackParameters>,
+    // Overrides `settings.default_success_statuses` for this probe
+    pub success_statuses: Option<Vec<StatusPattern>>,
+    // Concurrent runs of this probe, e.g. scheduled and triggered, overrides `settings.max_in_flight`
+    pub max_in_flight: Option<u32>,
+    // Sends the error and body of failures of a sensitive probe to its alerts anyway
+    #[serde(default)]
+    pub alerts_include_details: bool,
+    // Makes this a meta-probe: a JSONPath such as `$.services[*].name` into its response, each value
+    // found becomes a probe of its own requesting `expanded_url`
+    pub name_from_response: Option<String>,
+    // Url of the expanded probes, `${{ name }}` is replaced with the value from the response
+    pub expanded_url: Option<String>,
+    // Gauges `xbp_extracted_<name>` set from a JSON path into the response of each successful run,
+    // e.g. `queue_depth: "$.queue_depth"`, see `body_metrics::record_body_metrics`
+    pub metrics_from_body: Option<BTreeMap<String, String>>,
+    // Extracts `metrics_from_body` even though the probe is sensitive
+    #[serde(default)]
+    pub allow_sensitive_extraction: bool,
+    // Disabled probes stay scheduled but skip their runs, true when unset. Overridden at runtime by
+    // `/-/probes/:name/enable` and `/-/probes/:name/disable`, see `AppState::probe_enabled`.
+    pub enabled: Option<bool>,
+    // Added through `POST /probes` rather than the config file
+    #[serde(skip)]
+    pub runtime_added: bool,
+    // The meta-probe this probe was expanded from. Expanded probes are never persisted and are dropped
+    // once the meta-probe's response no longer lists them.
+    #[serde(skip)]
+    pub expanded_from: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ProbeInputParameters {
+impl Probe {
+    // Alerts of sensitive probes carry only the kind of failure, see `alert_if_failure`
+    pub fn redacts_alerts(&self) -> bool {
+        self.sensitive && !self.alerts_include_details
+    }
+
+    // The probe requesting `expanded_url` for a value listed by this meta-probe, named `<meta>:<value>`
+    pub fn expand(&self, value: &str) -> Probe {
+        let url = self.expanded_url.as_deref().unwrap_or_default();
+        Probe {
+            name: format!("{}:{}", self.name, value),
+            url: EXPANDED_NAME_REGEX
+                .replace_all(url, regex::NoExpand(value))
+                .into_owned(),
+            name_from_response: None,
+            expanded_url: None,
+            runtime_added: false,
+            expanded_from: Some(self.name.clone()),
+            ..self.clone()
+        }
+    }
+
+    // The probe checking a single one of its `urls`
+    pub fn for_url(&self, url: &str) -> Probe {
+        Probe {
+            url: url.to_owned(),
+            urls: None,
+            ..self.clone()
+        }
+    }
+
+    // Names the result of each of the probe's `urls` is stored under, `<probe>:<index>`
+    pub fn url_result_names(&self) -> Vec<String> {
+        (0..self.urls.as_ref().map_or(0, Vec::len))
+            .map(|index| url_result_name(&self.name, index))
+            .collect()
+    }
+
+    pub fn validate_urls(&self) -> Result<(), String> {
+        match (&self.urls, self.url.is_empty()) {
+            (None, true) => Err("a probe needs a `url` or `urls`".to_owned()),
+            (None, false) => Ok(()),
+            (Some(_), false) => Err("`url` and `urls` can't be combined".to_owned()),
+            (Some(urls), true) if urls.is_empty() => Err("`urls` is empty".to_owned()),
+            (Some(_), true) if self.name_from_response.is_some() => {
+                Err("probes with `urls` can't expand from their response".to_owned())
+            }
+            (Some(_), true) => Ok(()),
+        }
+    }
+
+    pub fn validate_expansion(&self) -> Result<(), String> {
+        match (&self.name_from_response, &self.expanded_url) {
+            (None, None) => Ok(()),
+            (Some(path), Some(_)) => {
+                if self.probe_type != ProbeType::Http {
+                    return Err("only http probes can expand from their response".to_owned());
+                }
+                parse_json_path(path).map(|_| ())
+            }
+            _ => Err("`name_from_response` and `expanded_url` go together".to_owned()),
+        }
+    }
+
+    pub fn validate_body_metrics(&self) -> Result<(), String> {
+        let Some(metrics) = &self.metrics_from_body else {
+            return Ok(());
+        };
+        if self.probe_type != ProbeType::Http {
+            return Err("only http probes can have `metrics_from_body`".to_owned());
+        }
+        if self.sensitive && !self.allow_sensitive_extraction {
+            return Err(
+                "`metrics_from_body` of a sensitive probe needs `allow_sensitive_extraction: true`"
+                    .to_owned(),
+            );
+        }
+        for (name, path) in metrics {
+            validate_metric_name(name)?;
+            parse_json_path(path)?;
+        }
+        Ok(())
+    }
+
+    pub fn validate_sftp(&self) -> Result<(), String> {
+        match (self.probe_type, &self.sftp) {
+            (ProbeType::Sftp, Some(params)) => {
+                sftp_probe::supported()?;
+                params.validate()
+            }
+            (ProbeType::Sftp, None) => Err("`sftp` probes need an `sftp` block".to_owned()),
+            (_, Some(_)) => Err("only `sftp` probes take an `sftp` block".to_owned()),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn validate_composite(&self) -> Result<(), String> {
+        match (self.composite, &self.full_stack) {
+            (Some(Composite::FullStack), _) if self.probe_type != ProbeType::Http => {
+                Err("only http probes can be `composite: full_stack`".to_owned())
+            }
+            (Some(Composite::FullStack), _) => {
+                let urls = self.urls.clone().unwrap_or_else(|| vec![self.url.clone()]);
+                match urls.iter().find(|url| !url.starts_with("https://")) {
+                    Some(url) => Err(format!(
+                        "`composite: full_stack` needs https urls, '{}' isn't one",
+                        url
+                    )),
+                    None => Ok(()),
+                }
+            }
+            (None, Some(_)) => Err("a `full_stack` block needs `composite: full_stack`".to_owned()),
+            (None, None) => Ok(()),
+        }
+    }
+
+    // Samples are plain http requests, the phases of other probes aren't sampled
+    pub fn validate_samples(&self) -> Result<(), String> {
+        if self.samples().is_none() {
+            return Ok(());
+        }
+        if self.probe_type != ProbeType::Http {
+            return Err("only http probes can take `with.samples`".to_owned());
+        }
+        if self.is_full_stack() {
+            return Err("`composite: full_stack` probes can't take `with.samples`".to_owned());
+        }
+        Ok(())
+    }
+
+    pub fn samples(&self) -> Option<&Samples> {
+        self.with.as_ref()?.samples.as_ref()
+    }
+
+    // Every `${{ prefetch.<name> }}` needs a prefetch that extracts it, as empty values would
+    // otherwise be sent
+    pub fn validate_prefetch(&self) -> Result<(), String> {
+        let placeholders = self
+            .with
+            .as_ref()
+            .map(prefetch::placeholders)
+            .unwrap_or_default();
+        let Some(prefetch) = self.prefetch() else {
+            return match placeholders.first() {
+                Some(name) => Err(format!(
+                    "`${{{{ prefetch.{} }}}}` needs a `with.prefetch` block",
+                    name
+                )),
+                None => Ok(()),
+            };
+        };
+        if self.probe_type != ProbeType::Http {
+            return Err("only http probes can take `with.prefetch`".to_owned());
+        }
+        if self.is_full_stack() {
+            return Err("`composite: full_stack` probes can't take `with.prefetch`".to_owned());
+        }
+        // Compiles the regex and selector extractions, which would otherwise only fail at runtime
+        prefetch.validate()?;
+        match placeholders
+            .iter()
+            .find(|name| !prefetch.extract.contains_key(*name))
+        {
+            Some(name) => Err(format!("`with.prefetch` doesn't extract `{}`", name)),
+            None => Ok(()),
+        }
+    }
+
+    pub fn prefetch(&self) -> Option<&Prefetch> {
+        self.with.as_ref()?.prefetch.as_ref()
+    }
+
+    pub fn is_full_stack(&self) -> bool {
+        self.composite == Some(Composite::FullStack)
+    }
+
+    pub fn validate_sla_window(&self) -> Result<(), String> {
+        match &self.sla_window {
+            Some(window) => ParsedSlaWindow::parse(window).map(|_| ()),
+            None => Ok(()),
+        }
+    }
+
+    pub fn validate_circuit_breaker(&self) -> Result<(), String> {
+        match &self.circuit_breaker {
+            Some(settings) => settings.validate(),
+            None => Ok(()),
+        }
+    }
+
+    // Validated with the config, so an invalid window only shows up in probes built by hand
+    pub fn parsed_sla_window(&self) -> Option<ParsedSlaWindow> {
+        ParsedSlaWindow::parse(self.sla_window.as_ref()?).ok()
+    }
+
+    // Sensitive probes only extract metrics with `allow_sensitive_extraction`
+    pub fn extracts_body_metrics(&self) -> bool {
+        self.metrics_from_body.is_some() && (!self.sensitive || self.allow_sensitive_extraction)
+    }
+
+    // The probe's own success statuses, falling back to the global default
+    pub fn success_statuses<'a>(&'a self, settings: &'a Settings) -> Option<&'a [StatusPattern]> {
+        self.success_statuses
+            .as_deref()
+            .or(settings.default_success_statuses.as_deref())
+    }
+}
+
+lazy_static! {
+    static ref EXPANDED_NAME_REGEX: Regex = Regex::new(r"\$\{\{\s*name\s*\}\}").unwrap();
+}
+
+fn default_http_method() -> String {
+    "GET".to_owned()
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProbeType {
+    #[default]
+    Http,
+    Smtp,
+    Ntp,
+    // Needs the `sftp` feature
+    Sftp,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Composite {
+    FullStack,
+}
+
+// The request of a `full_stack` probe keeps `with.timeout`. Plain numbers are milliseconds like
+// there, both default to `DEFAULT_REQUEST_TIMEOUT_SECS`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct FullStackParameters {
+    #[serde(
+        default,
+        deserialize_with = "duration::deserialize_millis",
+        serialize_with = "duration::serialize",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub dns_timeout: Option<Duration>,
+    // Connecting to the first resolved address and the handshake
+    #[serde(
+        default,
+        deserialize_with = "duration::deserialize_millis",
+        serialize_with = "duration::serialize",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub tls_timeout: Option<Duration>,
+}
+
+// Parameters of an `smtp` probe, the url is the server address e.g. `smtp://mail.example.com:25`
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct SmtpParameters {
+    #[serde(default)]
+    pub starttls: bool,
+    pub ehlo_name: Option<String>,
+    pub username: Option<String>,
+    // Credentials are only ever taken from the config, never serialized or logged
+    #[serde(skip_serializing)]
+    pub password: Option<String>,
+    pub mail_from: Option<String>,
+    pub rcpt_to: Option<String>,
+    #[serde(default)]
+    pub expect: SmtpExpectations,
+}
+
+impl std::fmt::Debug for SmtpParameters {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SmtpParameters")
+            .field("starttls", &self.starttls)
+            .field("ehlo_name", &self.ehlo_name)
+            .field("username", &self.username)
+            .field("password", &self.password.as_ref().map(|_| "<redacted>"))
+            .field("mail_from", &self.mail_from)
+            .field("rcpt_to", &self.rcpt_to)
+            .field("expect", &self.expect)
+            .finish()
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SmtpExpectations {
+    pub supports_starttls: Option<bool>,
+    pub max_banner_ms: Option<u64>,
+    // Overrides the expected reply code of a stage, e.g. `rcpt_to: 251`
+    #[serde(default)]
+    pub reply_codes: HashMap<String, u16>,
+}
+
+// The hours an SLA covers, e.g. `days: [mon-fri]`, `start: "08:00"`, `end: "18:00"`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SlaWindow {
+    // Days like `sat` or ranges like `mon-fri`, every day when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub days: Option<Vec<String>>,
+    // Local HH:MM, the start is part of the window and the end isn't. `end: "24:00"` is midnight.
+    pub start: String,
+    pub end: String,
+    // IANA timezone name such as `Europe/Amsterdam`, defaults to UTC
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>,
+}
+
+// Parameters of an `ntp` probe, the url is the server address e.g. `ntp://pool.ntp.org:123`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct NtpParameters {
+    #[serde(default)]
+    pub expect: NtpExpectations,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct NtpExpectations {
+    // Largest accepted difference between the local clock and the server, in either direction
+    pub max_offset_ms: Option<u64>,
+    pub max_delay_ms: Option<u64>,
+}
+
+// Parameters of an `sftp` probe, the url is the server address e.g. `sftp://files.example.com:22`
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct SftpParameters {
+    pub username: String,
+    // Credentials are only ever taken from the config, never serialized or logged
+    #[serde(skip_serializing)]
+    pub password: Option<String>,
+    // An OpenSSH private key, used instead of the password when set
+    pub private_key_path: Option<PathBuf>,
+    #[serde(skip_serializing)]
+    pub private_key_passphrase: Option<String>,
+    // The server's key as printed by `ssh-keygen -lf`, e.g. `SHA256:...`
+    pub host_key_fingerprint: Option<String>,
+    // An OpenSSH known_hosts file listing the server
+    pub known_hosts: Option<PathBuf>,
+    // Skips host key verification. Without it a fingerprint or known_hosts file is required.
     #[serde(default)]
+    pub accept_any_host_key: bool,
+    // A directory that must exist and be listable
+    pub list_path: Option<String>,
+    // A pattern with `*` and `?` that a file in `list_path` must match, e.g. `invoice_*.csv`
+    pub expect_file_matching: Option<String>,
+    // How old the newest matching file may be
+    pub max_age_hours: Option<u64>,
+}
+
+impl SftpParameters {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.username.is_empty() {
+            return Err("`sftp.username` is required".to_owned());
+        }
+        if self.password.is_none() && self.private_key_path.is_none() {
+            return Err("`sftp` needs a `password` or a `private_key_path`".to_owned());
+        }
+        if self.host_key_fingerprint.is_none()
+            && self.known_hosts.is_none()
+            && !self.accept_any_host_key
+        {
+            return Err(
+                "`sftp` needs a `host_key_fingerprint` or `known_hosts` to verify the server, or `accept_any_host_key: true`"
+                    .to_owned(),
+            );
+        }
+        if let Some(pattern) = &self.expect_file_matching {
+            if self.list_path.is_none() {
+                return Err("`sftp.expect_file_matching` needs a `list_path`".to_owned());
+            }
+            sftp_probe::file_pattern(pattern)?;
+        } else if self.max_age_hours.is_some() {
+            return Err("`sftp.max_age_hours` needs `expect_file_matching`".to_owned());
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for SftpParameters {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SftpParameters")
+            .field("username", &self.username)
+            .field("password", &self.password.as_ref().map(|_| "<redacted>"))
+            .field("private_key_path", &self.private_key_path)
+            .field(
+                "private_key_passphrase",
+                &self.private_key_passphrase.as_ref().map(|_| "<redacted>"),
+            )
+            .field("host_key_fingerprint", &self.host_key_fingerprint)
+            .field("known_hosts", &self.known_hosts)
+            .field("accept_any_host_key", &self.accept_any_host_key)
+            .field("list_path", &self.list_path)
+            .field("expect_file_matching", &self.expect_file_matching)
+            .field("max_age_hours", &self.max_age_hours)
+            .finish()
+    }
+}
+
+// The `with` block of probes and steps. Unknown keys are rejected so typos don't silently do nothing.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProbeOptions {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub headers: Option<HashMap<String, String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub body: Option<String>,
-    pub timeout_seconds: Option<u64>,
+    // Plain numbers are milliseconds, strings carry their unit: `5000` or "5s"
+    #[serde(
+        default,
+        deserialize_with = "duration::deserialize_millis",
+        serialize_with = "duration::serialize",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub timeout: Option<Duration>,
+    // Older spelling of `timeout`, plain numbers are seconds. Shown as `timeout` in resolved output.
+    #[serde(
+        default,
+        deserialize_with = "duration::deserialize_seconds",
+        skip_serializing
+    )]
+    pub timeout_seconds: Option<Duration>,
+    // Appended to the url as a properly encoded query string
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub query: Option<HashMap<String, String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth: Option<ProbeAuth>,
+    // Replaces the default `xbp-monitoring/<version>` User-Agent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_agent: Option<String>,
+    // Defers the next scheduled run of a probe answered with 429, or 503 with `Retry-After`, until
+    // the time the server asks for. Steps ignore it.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub respect_retry_after: bool,
+    // Follows redirects and judges the final response, rather than recording the 3xx itself
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub follow_redirects: bool,
+    // Headers that may be empty after `${{ env.* }}` substitution, case-insensitive. Other templated
+    // headers that end up empty fail loading, see `substitution_check`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allow_empty: Option<Vec<String>>,
+    // Several requests per probe run, judged on their aggregate, see `probe::samples`. Steps
+    // ignore it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub samples: Option<Samples>,
+    // A request sent before the probe's own, see `probe::prefetch`. Steps ignore it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prefetch: Option<Prefetch>,
+}
+
+impl ProbeOptions {
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout.or(self.timeout_seconds)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.timeout.is_some() && self.timeout_seconds.is_some() {
+            return Err("`with` sets both `timeout` and `timeout_seconds`".to_owned());
+        }
+        match &self.samples {
+            Some(samples) => samples.validate(),
+            None => Ok(()),
+        }
+    }
+
+    // `timeout_seconds` isn't serialized, so it's moved into `timeout` before options are written out
+    pub fn fold_legacy_timeout(&mut self) {
+        self.timeout = self.timeout();
+        self.timeout_seconds = None;
+    }
+
+    // Options as shown by the resolved config: the legacy timeout folded into `timeout`,
+    // header values and sensitive bodies hidden
+    pub fn resolved(&self, sensitive: bool) -> ProbeOptions {
+        ProbeOptions {
+            headers: self.headers.as_ref().map(|headers| {
+                headers
+                    .keys()
+                    .map(|name| (name.clone(), "<redacted>".to_owned()))
+                    .collect()
+            }),
+            body: self.body.as_ref().map(|body| {
+                if sensitive {
+                    "<redacted>".to_owned()
+                } else {
+                    body.clone()
+                }
+            }),
+            timeout: self.timeout(),
+            timeout_seconds: None,
+            query: self.query.clone(),
+            auth: self.auth.clone(),
+            user_agent: self.user_agent.clone(),
+            respect_retry_after: self.respect_retry_after,
+            follow_redirects: self.follow_redirects,
+            allow_empty: self.allow_empty.clone(),
+            samples: self.samples.clone(),
+            prefetch: self.prefetch.clone(),
+        }
+    }
+}
+
+// `with.samples`: `count` requests per run, `concurrency` of them at a time, each after the first
+// waiting `delay_between_ms` before it starts. The run fails when more than `max_failures` of
+// them fail, or when the p95 or the slowest of their durations reaches its threshold.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Samples {
+    pub count: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub concurrency: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub delay_between_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub p95_of_samples_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_of_samples_ms: Option<u64>,
+    // Failed samples a run tolerates, 0 when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_failures: Option<u32>,
+}
+
+impl Samples {
+    pub fn concurrency(&self) -> u32 {
+        self.concurrency.unwrap_or(1)
+    }
+
+    pub fn delay_between(&self) -> Duration {
+        Duration::from_millis(self.delay_between_ms.unwrap_or(0))
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.count == 0 {
+            return Err("`samples.count` must be at least 1".to_owned());
+        }
+        if self.concurrency() == 0 {
+            return Err("`samples.concurrency` must be at least 1".to_owned());
+        }
+        if self.max_failures.is_some_and(|max| max >= self.count) {
+            return Err(format!(
+                "`samples.max_failures` must be less than `samples.count` ({})",
+                self.count
+            ));
+        }
+        Ok(())
+    }
+}
+
+// `with.prefetch`: a request sent before the probe's own, e.g. for the CSRF token of a login form.
+// What it extracts fills the `${{ prefetch.<name> }}` placeholders of the probe's headers and
+// body, and the cookies it's given are sent along with the probe's request.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Prefetch {
+    pub url: String,
+    #[serde(default = "default_http_method")]
+    pub http_method: String,
+    // Placeholder name to where its value is found, a run fails when one isn't. Written as
+    // `{ cookie: XSRF-TOKEN }`, serde_yaml would otherwise want a `!cookie` tag.
+    #[serde(
+        default,
+        skip_serializing_if = "BTreeMap::is_empty",
+        with = "serde_yaml::with::singleton_map_recursive"
+    )]
+    pub extract: BTreeMap<String, Extraction>,
+    // The probe's duration otherwise starts with its own request
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub include_in_duration: bool,
+}
+
+impl Prefetch {
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.url.starts_with("http://") && !self.url.starts_with("https://") {
+            return Err(format!(
+                "`prefetch.url` '{}' isn't an http(s) url",
+                self.url
+            ));
+        }
+        for (name, extraction) in &self.extract {
+            extraction
+                .validate()
+                .map_err(|message| format!("`prefetch.extract.{}`: {}", name, message))?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Extraction {
+    // Matched case-insensitively
+    Header(String),
+    // A cookie the response sets
+    Cookie(String),
+    // The first capture group in the body, or the whole match of a regex without groups
+    Regex(String),
+    // The `value` attribute of the first element in the body that matches, or its text when it
+    // has none, e.g. `input[name=csrf_token]`
+    Selector(String),
+}
+
+impl Extraction {
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            Extraction::Regex(regex) => Regex::new(regex).map(|_| ()).map_err(|e| e.to_string()),
+            Extraction::Selector(selector) => scraper::Selector::parse(selector)
+                .map(|_| ())
+                .map_err(|e| format!("invalid selector '{}': {}", selector, e)),
+            Extraction::Header(_) | Extraction::Cookie(_) => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProbeAuth {
+    pub aws_sigv4: Option<AwsSigV4Parameters>,
+}
+
+// Signs requests for IAM protected AWS endpoints. Credentials fall back to the standard AWS env vars.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AwsSigV4Parameters {
+    pub region: String,
+    pub service: String,
+    pub access_key_id: Option<String>,
+    #[serde(skip_serializing)]
+    pub secret_access_key: Option<String>,
+    #[serde(skip_serializing)]
+    pub session_token: Option<String>,
+}
+
+impl std::fmt::Debug for AwsSigV4Parameters {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AwsSigV4Parameters")
+            .field("region", &self.region)
+            .field("service", &self.service)
+            .field("access_key_id", &self.access_key_id)
+            .field(
+                "secret_access_key",
+                &self.secret_access_key.as_ref().map(|_| "<redacted>"),
+            )
+            .field(
+                "session_token",
+                &self.session_token.as_ref().map(|_| "<redacted>"),
+            )
+            .finish()
+    }
+}
+
+// The `failed_phase` of runs whose `with.prefetch` failed, they never sent their own request
+pub const PREFETCH_PHASE: &str = "prefetch";
+
+// Failed runs without a response never reached the target, the others failed an expectation
+pub fn error_kind(success: bool, has_response: bool) -> Option<&'static str> {
+    match (success, has_response) {
+        (true, _) => None,
+        (false, true) => Some("expectation"),
+        (false, false) => Some("request"),
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -32,7 +761,7 @@ pub struct ProbeExpectation {
     pub value: String,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub enum ExpectOperation {
     Equals,
     NotEquals,
@@ -40,27 +769,226 @@ pub enum ExpectOperation {
     Contains,
     NotContains,
     Matches,
+    // The `Script` in `value` returned `true`
+    Passes,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub enum ExpectField {
     Body,
     StatusCode,
+    // A Rhai script in `value`, needs the `scripting` feature
+    Script,
+}
+
+// A status class such as `2xx` or an exact status code such as `302`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum StatusPattern {
+    Class(u32),
+    Exact(u32),
+}
+
+impl StatusPattern {
+    pub fn matches(&self, status_code: u32) -> bool {
+        match self {
+            StatusPattern::Class(class) => status_code / 100 == *class,
+            StatusPattern::Exact(code) => status_code == *code,
+        }
+    }
+}
+
+impl TryFrom<String> for StatusPattern {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let invalid = || {
+            format!(
+                "invalid status pattern '{}', expected e.g. 2xx or 302",
+                value
+            )
+        };
+        let lowercase = value.to_ascii_lowercase();
+        match lowercase.strip_suffix("xx") {
+            Some(class) => match class.parse::<u32>() {
+                Ok(class @ 1..=5) => Ok(StatusPattern::Class(class)),
+                _ => Err(invalid()),
+            },
+            None => match lowercase.parse::<u32>() {
+                Ok(code @ 100..=599) => Ok(StatusPattern::Exact(code)),
+                _ => Err(invalid()),
+            },
+        }
+    }
+}
+
+impl From<StatusPattern> for String {
+    fn from(pattern: StatusPattern) -> Self {
+        pattern.to_string()
+    }
+}
+
+impl std::fmt::Display for StatusPattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StatusPattern::Class(class) => write!(f, "{}xx", class),
+            StatusPattern::Exact(code) => write!(f, "{}", code),
+        }
+    }
+}
+
+// Ad-hoc probe templates for the blackbox_exporter compatible `/probe` endpoint
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ProbeModules {
+    // Regexes a target must fully match, no target is allowed when empty
+    #[serde(default)]
+    pub allowed_target_patterns: Vec<String>,
+    #[serde(default)]
+    pub modules: HashMap<String, ProbeModule>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ProbeScheduleParameters {
-    pub initial_delay: u32,
-    pub interval: u32,
+pub struct ProbeModule {
+    #[serde(default = "default_http_method")]
+    pub http_method: String,
+    pub headers: Option<HashMap<String, String>>,
+    pub timeout_seconds: Option<u64>,
+    pub expectations: Option<Vec<ProbeExpectation>>,
+    pub success_statuses: Option<Vec<StatusPattern>>,
 }
 
+// Plain numbers are seconds, strings carry their unit: `90`, "90s", "1h30m" or "250ms"
 #[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProbeScheduleParameters {
+    #[serde(
+        deserialize_with = "duration::deserialize_required_seconds",
+        serialize_with = "duration::serialize_required"
+    )]
+    pub initial_delay: Duration,
+    #[serde(
+        deserialize_with = "duration::deserialize_required_seconds",
+        serialize_with = "duration::serialize_required"
+    )]
+    pub interval: Duration,
+    // Allows intervals shorter than `MIN_INTERVAL`, including 0
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub allow_fast: bool,
+}
+
+impl ProbeScheduleParameters {
+    pub const MIN_INTERVAL: Duration = Duration::from_millis(100);
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.interval < Self::MIN_INTERVAL && !self.allow_fast {
+            return Err(format!(
+                "schedule.interval of {} is shorter than {}, set `allow_fast: true` to allow it",
+                humantime::format_duration(self.interval),
+                humantime::format_duration(Self::MIN_INTERVAL)
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct ProbeAlert {
+    // Name of an entry in the top-level `alert_channels`, which supplies the channel's fields
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub channel: Option<String>,
+    // Events this alert is sent for, all of them when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on: Option<Vec<AlertEvent>>,
+    // Where webhook, Slack and Discord alerts are posted, unused by `file` and `stdout`
+    #[serde(
+        default,
+        alias = "webhook_url",
+        skip_serializing_if = "String::is_empty"
+    )]
     pub url: String,
+    // Derived from the url when unset, see `alerts::outbound_webhook::alert_channel`
+    #[serde(default, rename = "type", skip_serializing_if = "Option::is_none")]
+    pub channel_type: Option<AlertChannel>,
+    // The JSON lines file of a `file` channel
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>,
+    // Rotates the file once it would grow past this size, never rotated when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_size_mb: Option<u64>,
+    // Rotated files kept next to the current one, defaults to 5
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_files: Option<u32>,
+    // Sent when an incident of the monitor closes, see `alerts::outbound_webhook::alert_on_recovery`
+    // for the placeholders. Monitors only send recovery alerts when this is set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recovery_template: Option<String>,
+    // Of an `opsgenie` channel, sent as `Authorization: GenieKey <api_key>`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_key: Option<String>,
+    // The Opsgenie API used when `url` is unset, `us` by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<OpsgenieRegion>,
+    // Of the Opsgenie alerts of failures, P3 by default. Reports are always P5.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<OpsgeniePriority>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tags: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub responders: Option<Vec<OpsgenieResponder>>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl ProbeAlert {
+    pub fn sends(&self, event: AlertEvent) -> bool {
+        self.on.as_ref().is_none_or(|on| on.contains(&event))
+    }
+
+    // The definition of a referenced channel, with the `on` and `recovery_template` of the reference
+    pub fn referencing(&self, definition: &ProbeAlert) -> ProbeAlert {
+        ProbeAlert {
+            channel: None,
+            on: self.on.clone().or_else(|| definition.on.clone()),
+            recovery_template: self
+                .recovery_template
+                .clone()
+                .or_else(|| definition.recovery_template.clone()),
+            ..definition.clone()
+        }
+    }
+
+    // References are checked against `alert_channels` by `Config::validate`
+    pub fn validate(&self) -> Result<(), String> {
+        if self.channel.is_some() {
+            return Ok(());
+        }
+        match self.channel_type {
+            Some(AlertChannel::Stdout) => Ok(()),
+            Some(AlertChannel::File) if self.path.is_none() => {
+                Err("`file` alerts need a `path`".to_owned())
+            }
+            Some(AlertChannel::File) => Ok(()),
+            Some(AlertChannel::Opsgenie) if self.api_key.as_deref().is_none_or(str::is_empty) => {
+                Err("`opsgenie` alerts need an `api_key`".to_owned())
+            }
+            Some(AlertChannel::Opsgenie) if !self.url.is_empty() => reqwest::Url::parse(&self.url)
+                .map(|_| ())
+                .map_err(|e| format!("invalid Opsgenie `url` '{}': {}", self.url, e)),
+            Some(AlertChannel::Opsgenie) => Ok(()),
+            _ if self.url.is_empty() => Err("alerts need a `url` or a `type`".to_owned()),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AlertEvent {
+    Failure,
+    Recovery,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct ProbeResult {
+    // Identifies a single run across spans, logs, API responses and alerts
+    pub run_id: Uuid,
     pub probe_name: String,
     pub timestamp_started: DateTime<Utc>,
     pub success: bool,
@@ -68,13 +996,313 @@ pub struct ProbeResult {
     pub error_message: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub response: Option<ProbeResponse>,
+    // Until the response headers arrived, or the whole exchange for smtp. Fractional milliseconds in JSON.
+    #[serde(
+        default,
+        rename = "duration_ms",
+        with = "duration::millis_f64",
+        skip_serializing_if = "Option::is_none"
+    )]
+    #[schemars(with = "Option<f64>")]
+    pub duration: Option<Duration>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub trace_id: Option<String>,
+    // Per-stage timings of protocols with several stages, such as smtp
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub phases: Option<Vec<PhaseTiming>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub failed_phase: Option<String>,
+    // The expectation an http run failed on
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub failed_expectation: Option<FailedExpectation>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls: Option<TlsDetails>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ntp: Option<NtpDetails>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sftp: Option<SftpDetails>,
+    // Each phase of a `composite: full_stack` run, see `FullStackDetails::summary`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub full_stack: Option<FullStackDetails>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connection: Option<ConnectionDetails>,
+    // The run overlapped a config reload, see `AppState::reload_window`
+    #[serde(default)]
+    pub during_reload: bool,
+    // The server asked to back off, see `with.respect_retry_after`
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub rate_limited: bool,
+    // Runs of the probe it took to get this result, more than 1 when it was retried
+    #[serde(default = "default_attempts")]
+    pub attempts: u32,
+    // The error message of each failed attempt before this one
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub retry_reasons: Vec<String>,
+    // Each request of a run with `with.samples`, in the order they were started
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub samples: Option<Vec<SampleResult>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct SampleResult {
+    pub success: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status_code: Option<u32>,
+    #[serde(
+        default,
+        rename = "duration_ms",
+        with = "duration::millis_f64",
+        skip_serializing_if = "Option::is_none"
+    )]
+    #[schemars(with = "Option<f64>")]
+    pub duration: Option<Duration>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_message: Option<String>,
+}
+
+pub fn url_result_name(probe_name: &str, index: usize) -> String {
+    format!("{}:{}", probe_name, index)
+}
+
+fn default_attempts() -> u32 {
+    1
+}
+
+impl ProbeResult {
+    // A single attempt outside any reload, with none of the per-type details set
+    pub fn new(
+        run_id: Uuid,
+        probe_name: String,
+        timestamp_started: DateTime<Utc>,
+        success: bool,
+    ) -> ProbeResult {
+        ProbeResult {
+            run_id,
+            probe_name,
+            timestamp_started,
+            success,
+            error_message: None,
+            response: None,
+            duration: None,
+            trace_id: None,
+            phases: None,
+            failed_phase: None,
+            failed_expectation: None,
+            tls: None,
+            ntp: None,
+            sftp: None,
+            full_stack: None,
+            connection: None,
+            during_reload: false,
+            rate_limited: false,
+            attempts: 1,
+            retry_reasons: vec![],
+            samples: None,
+        }
+    }
+
+    // Like `error_kind`, but with the more precise kind ntp and sftp probes report, `prefetch` for
+    // runs whose prefetch failed and `rate_limited` for failed runs the server asked to back off
+    pub fn error_kind(&self) -> Option<&'static str> {
+        if self.rate_limited && !self.success {
+            return Some("rate_limited");
+        }
+        if !self.success && self.failed_phase.as_deref() == Some(PREFETCH_PHASE) {
+            return Some("prefetch");
+        }
+        let precise = match (&self.ntp, &self.sftp) {
+            (Some(ntp), _) => ntp.error_kind.map(|kind| kind.as_str()),
+            (_, Some(sftp)) => sftp.error_kind.map(|kind| kind.as_str()),
+            _ => None,
+        };
+        match precise {
+            Some(kind) if !self.success => Some(kind),
+            _ => error_kind(self.success, self.response.is_some()),
+        }
+    }
+}
+
+// `actual` is `<redacted>` for sensitive monitors, as on the `expectation.failed` span event
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct FailedExpectation {
+    pub field: ExpectField,
+    pub operation: ExpectOperation,
+    pub expected: String,
+    pub actual: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct PhaseTiming {
+    pub name: String,
+    pub duration_ms: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct TlsDetails {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub certificate_not_after: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct FullStackDetails {
+    // `dns`, `tls` and `http` in order, up to the first that failed
+    pub phases: Vec<FullStackPhase>,
+    // The resolved addresses, the handshake went to the first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub addresses: Vec<IpAddr>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub certificate: Option<CertificateDetails>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct FullStackPhase {
+    pub name: String,
+    pub success: bool,
+    pub duration_ms: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+// The server certificate of a handshake that passed. The chain was verified against the system
+// trust store during the handshake, the issuer names its next link.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct CertificateDetails {
+    pub subject: String,
+    pub issuer: String,
+    pub not_after: DateTime<Utc>,
+    // Negative once expired
+    pub expires_in_days: i64,
+}
+
+impl FullStackDetails {
+    // E.g. "DNS ok, TLS ok, HTTP failed (503)" for the alert of a failed run
+    pub fn summary(&self, status_code: Option<u32>) -> String {
+        self.phases
+            .iter()
+            .map(|phase| {
+                let name = phase.name.to_uppercase();
+                match (phase.success, status_code) {
+                    (true, _) => format!("{} ok", name),
+                    (false, Some(status_code)) if phase.name == "http" => {
+                        format!("{} failed ({})", name, status_code)
+                    }
+                    (false, _) => format!("{} failed", name),
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+// Which backend an http run reached, to tell them apart during failovers
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct ConnectionDetails {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remote_addr: Option<SocketAddr>,
+    // The response headers listed in `settings.capture_headers`, by lowercase name
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+}
+
+impl ConnectionDetails {
+    pub fn capture(endpoint_result: &EndpointResult, capture_headers: &[String]) -> Self {
+        ConnectionDetails {
+            remote_addr: endpoint_result.remote_addr,
+            headers: capture_headers
+                .iter()
+                .filter_map(|name| {
+                    let name = name.to_lowercase();
+                    let value = endpoint_result.headers.get(&name)?.clone();
+                    Some((name, value))
+                })
+                .collect(),
+        }
+    }
+}
+
+// Offset is the server clock minus the local clock, positive when the local clock is behind
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct NtpDetails {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub offset_ms: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delay_ms: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stratum: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_kind: Option<NtpErrorKind>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum NtpErrorKind {
+    // No reply within the timeout, UDP gives no other sign of an unreachable server
+    Timeout,
+    // The server asked us to go away, e.g. `RATE` or `DENY`
+    KissOfDeath,
+    Unsynchronized,
+    Malformed,
+    Io,
+    Expectation,
+}
+
+impl NtpErrorKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NtpErrorKind::Timeout => "timeout",
+            NtpErrorKind::KissOfDeath => "kiss_of_death",
+            NtpErrorKind::Unsynchronized => "unsynchronized",
+            NtpErrorKind::Malformed => "malformed",
+            NtpErrorKind::Io => "io",
+            NtpErrorKind::Expectation => "expectation",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct SftpDetails {
+    // The newest file matching `expect_file_matching`, when there is one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub matched_file: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub matched_file_modified: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_kind: Option<SftpErrorKind>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SftpErrorKind {
+    // No connection or SSH handshake within the timeout, or the connection broke
+    Network,
+    // The server's key doesn't match the configured fingerprint or known_hosts entry
+    HostKey,
+    Auth,
+    // `list_path` doesn't exist or can't be listed
+    Path,
+    // No fresh enough file matches `expect_file_matching`
+    Expectation,
+    // xbp-monitoring was built without the `sftp` feature
+    Unsupported,
+}
+
+impl SftpErrorKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SftpErrorKind::Network => "network",
+            SftpErrorKind::HostKey => "host_key",
+            SftpErrorKind::Auth => "auth",
+            SftpErrorKind::Path => "path",
+            SftpErrorKind::Expectation => "expectation",
+            SftpErrorKind::Unsupported => "unsupported",
+        }
+    }
 }
 
 // todo track application errors
 // also track the request and response bodies that were sent now that variables exist
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[schemars(rename = "ProbeHttpResponse")]
 pub struct ProbeResponse {
     pub timestamp_received: DateTime<Utc>,
     pub status_code: u32,
@@ -91,32 +1319,206 @@ impl ProbeResponse {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Story {
     pub name: String,
+    // Prefixes step urls starting with `/`, absolute step urls are left as they are
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base_url: Option<String>,
+    // Run before `steps`, a failing setup step fails the story without running the main steps
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub setup: Option<Vec<Step>>,
     pub steps: Vec<Step>,
+    // Run after the main steps even when they failed. Failures are logged, the story's status only
+    // reflects the setup and main steps.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub teardown: Option<Vec<Step>>,
     pub schedule: ProbeScheduleParameters,
     pub alerts: Option<Vec<ProbeAlert>>,
     pub tags: Option<HashMap<String, String>>,
+    // The team or namespace the story belongs to, `?group=` limits API listings to one group
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    // Consecutive successful runs required before a failing story is reported as OK again
+    pub recovery_threshold: Option<u32>,
+    // Evaluated after all steps succeeded, against the values captured by the steps
+    pub expectations: Option<Vec<StoryExpectation>>,
+    // Leaves captured values out of story results, as does any sensitive step
+    #[serde(default)] // default to false
+    pub sensitive: bool,
+    // Sends the error and body of failures of a sensitive story to its alerts anyway
+    #[serde(default)]
+    pub alerts_include_details: bool,
+    // Added through `POST /stories` rather than the config file
+    #[serde(skip)]
+    pub runtime_added: bool,
+}
+
+impl Step {
+    // At most one of `body`, `body_template` and `with.body`, checked by `Config::validate`
+    pub fn validate_body(&self) -> Result<(), String> {
+        let with_body = self.with.as_ref().and_then(|with| with.body.as_ref());
+        let bodies = [
+            self.body.is_some(),
+            self.body_template.is_some(),
+            with_body.is_some(),
+        ];
+        if bodies.into_iter().filter(|set| *set).count() > 1 {
+            return Err(
+                "only one of `body`, `body_template` and `with.body` can be set".to_owned(),
+            );
+        }
+        Ok(())
+    }
+}
+
+impl Story {
+    // The url a step requests, before variables are substituted
+    pub fn step_url(&self, url: &str) -> String {
+        match &self.base_url {
+            Some(base_url) if url.starts_with('/') => {
+                format!("{}{}", base_url.trim_end_matches('/'), url)
+            }
+            _ => url.to_owned(),
+        }
+    }
+
+    // A base url must be absolute, and steps with a relative url need one
+    pub fn validate_urls(&self) -> Result<(), String> {
+        if let Some(base_url) = &self.base_url {
+            let parsed = reqwest::Url::parse(base_url)
+                .map_err(|e| format!("invalid `base_url` '{}': {}", base_url, e))?;
+            if !matches!(parsed.scheme(), "http" | "https") {
+                return Err(format!("`base_url` '{}' must be an http(s) url", base_url));
+            }
+        }
+        let relative = self
+            .all_steps()
+            .find(|step| self.base_url.is_none() && step.url.starts_with('/'));
+        if let Some(step) = relative {
+            return Err(format!(
+                "step '{}' has the relative url '{}' but the story has no `base_url`",
+                step.name, step.url
+            ));
+        }
+        Ok(())
+    }
+
+    // Setup, main and teardown steps in the order they run
+    pub fn all_steps(&self) -> impl Iterator<Item = &Step> {
+        self.setup
+            .iter()
+            .flatten()
+            .chain(&self.steps)
+            .chain(self.teardown.iter().flatten())
+    }
+
+    pub fn is_sensitive(&self) -> bool {
+        self.sensitive || self.all_steps().any(|step| step.sensitive)
+    }
+
+    pub fn redacts_alerts(&self) -> bool {
+        self.is_sensitive() && !self.alerts_include_details
+    }
+
+    // Standalone probes requesting the same method and url as one of the steps, so tooling can tell
+    // which stories are affected by a probe change
+    pub fn referenced_probes(&self, probes: &[Probe]) -> Vec<String> {
+        probes
+            .iter()
+            .filter(|probe| {
+                self.all_steps().any(|step| {
+                    self.step_url(&step.url) == probe.url
+                        && step.http_method.eq_ignore_ascii_case(&probe.http_method)
+                })
+            })
+            .map(|probe| probe.name.clone())
+            .collect()
+    }
+}
+
+// An expression over captured values such as `steps.cart_total == steps.invoice_total`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoryExpectation {
+    pub expr: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct StoryExpectationResult {
+    pub expr: String,
+    pub success: bool,
+    // The expression with captured values substituted, left out for sensitive stories
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub evaluated: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_message: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Step {
     pub name: String,
     pub url: String,
+    #[serde(default = "default_http_method")]
     pub http_method: String,
-    pub with: Option<ProbeInputParameters>,
+    pub with: Option<ProbeOptions>,
+    // Sent as it is, e.g. a fixed JSON document
+    pub body: Option<String>,
+    // Sent with placeholders such as `${{steps.login.response.body.token}}` substituted, as is `with.body`
+    pub body_template: Option<String>,
     pub expectations: Option<Vec<ProbeExpectation>>,
     #[serde(default)] // default to false
     pub sensitive: bool,
+    // Capture name to a dot separated path into the JSON response body, e.g. `cart.total`, or to a
+    // response header, e.g. `{ header: Location }`
+    pub captures: Option<HashMap<String, Capture>>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Capture {
+    // Paths that aren't found are left out, the step still succeeds
+    Path(String),
+    Header(HeaderCapture),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HeaderCapture {
+    // Matched case-insensitively
+    pub header: String,
+    // Captured when the response lacks the header, which otherwise fails the step
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default: Option<String>,
+    // Resolves a relative value, e.g. a `Location` of `/orders/42`, against the step's url
+    #[serde(default)]
+    pub resolve_relative: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct StoryResult {
+    // Shared by the story span, its step spans and the X-Story-Run-Id header of step requests
+    pub story_run_id: Uuid,
     pub story_name: String,
     pub timestamp_started: DateTime<Utc>,
     pub success: bool,
+    // Until the response headers of the last step that got a response arrived
+    #[serde(
+        default,
+        rename = "duration_ms",
+        with = "duration::millis_f64",
+        skip_serializing_if = "Option::is_none"
+    )]
+    #[schemars(with = "Option<f64>")]
+    pub duration: Option<Duration>,
+    // Setup steps followed by the main steps, up to the first that failed
     pub step_results: Vec<StepResult>,
+    // Every teardown step, they don't count towards `success`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub teardown_results: Vec<StepResult>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expectations: Option<Vec<StoryExpectationResult>>,
+    #[serde(default)]
+    pub during_reload: bool,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct StepResult {
     pub step_name: String,
     pub timestamp_started: DateTime<Utc>,
@@ -129,19 +1531,80 @@ pub struct StepResult {
     pub trace_id: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub span_id: Option<String>,
+    // The values the step captured, `<redacted>` for sensitive steps
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub captures: Option<BTreeMap<String, serde_json::Value>>,
 }
 
 pub struct EndpointResult {
     pub timestamp_request_started: DateTime<Utc>,
+    // When the status and headers arrived
     pub timestamp_response_received: DateTime<Utc>,
+    pub timestamp_body_received: DateTime<Utc>,
     pub status_code: u32,
+    // Lowercase names, repeated headers are joined with ", "
+    pub headers: HashMap<String, String>,
+    // Each `Set-Cookie` header as it was sent, they can't be told apart once joined in `headers`
+    pub set_cookies: Vec<String>,
+    // The address the request connected to, after DNS resolution
+    pub remote_addr: Option<SocketAddr>,
     pub body: String,
     pub trace_id: String,
     pub span_id: String,
     pub sensitive: bool,
+    pub timings: HttpTimings,
+}
+
+// Where the time of an HTTP request went. reqwest connects inside its own connector, so the TCP
+// connect and TLS handshake can't be told apart from the server's time to the first byte.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HttpTimings {
+    // The lookup of the request's host. Unset when the url holds an IP address.
+    pub dns_lookup: Option<Duration>,
+    // From the end of the DNS lookup to the response headers: connect, TLS handshake and server time
+    pub time_to_first_byte: Duration,
+    pub body_download: Duration,
+}
+
+impl HttpTimings {
+    pub fn phases(&self) -> Vec<PhaseTiming> {
+        let phase = |name: &str, duration: Duration| PhaseTiming {
+            name: name.to_owned(),
+            duration_ms: duration.as_millis() as u64,
+        };
+        self.dns_lookup
+            .map(|dns_lookup| phase("dns_lookup", dns_lookup))
+            .into_iter()
+            .chain([
+                phase("ttfb", self.time_to_first_byte),
+                phase("body", self.body_download),
+            ])
+            .collect()
+    }
+}
+
+// Parts of a response that only `Script` expectations look at
+#[derive(Debug, Clone, Default)]
+pub struct ResponseMeta {
+    pub headers: HashMap<String, String>,
+    pub duration: Duration,
 }
 
 impl EndpointResult {
+    pub fn duration(&self) -> Duration {
+        duration::between(
+            self.timestamp_request_started,
+            self.timestamp_response_received,
+        )
+    }
+
+    pub fn meta(&self) -> ResponseMeta {
+        ResponseMeta {
+            headers: self.headers.clone(),
+            duration: self.duration(),
+        }
+    }
+
     pub fn to_probe_response(&self) -> ProbeResponse {
         ProbeResponse {
             timestamp_received: self.timestamp_response_received,
@@ -150,4 +1613,4 @@ impl EndpointResult {
             sensitive: self.sensitive,
         }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,357 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use tokio::net::{lookup_host, UdpSocket};
+use tokio::time::Instant;
+use tracing::debug;
+
+use super::model::{NtpDetails, NtpErrorKind, NtpParameters};
+
+const DEFAULT_NTP_PORT: u16 = 123;
+const NTP_PACKET_LEN: usize = 48;
+// Seconds between the NTP epoch, 1900-01-01, and the unix epoch
+const NTP_UNIX_OFFSET_SECS: i64 = 2_208_988_800;
+// Leap indicator 0, version 4, mode 3 (client)
+const CLIENT_HEADER: u8 = 0x23;
+const MODE_SERVER: u8 = 4;
+const LEAP_UNSYNCHRONIZED: u8 = 3;
+
+pub struct NtpCheckOutcome {
+    pub details: NtpDetails,
+    pub error: Option<NtpCheckError>,
+}
+
+#[derive(Debug)]
+pub struct NtpCheckError {
+    pub kind: NtpErrorKind,
+    pub message: String,
+}
+
+impl std::fmt::Display for NtpCheckError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "NTP check failed ({}): {}",
+            self.kind.as_str(),
+            self.message
+        )
+    }
+}
+
+impl std::error::Error for NtpCheckError {}
+
+fn fail(kind: NtpErrorKind, message: impl Into<String>) -> NtpCheckError {
+    NtpCheckError {
+        kind,
+        message: message.into(),
+    }
+}
+
+// Accepts `ntp://host:port`, `host:port` or `host`
+pub fn parse_ntp_address(url: &str) -> Result<(String, u16), String> {
+    let address = url.strip_prefix("ntp://").unwrap_or(url);
+    let address = address.trim_end_matches('/');
+    match address.rsplit_once(':') {
+        Some((host, port)) => port
+            .parse::<u16>()
+            .map(|port| (host.to_owned(), port))
+            .map_err(|_| format!("invalid port in NTP address '{}'", url)),
+        None if !address.is_empty() => Ok((address.to_owned(), DEFAULT_NTP_PORT)),
+        None => Err("NTP address is empty".to_owned()),
+    }
+}
+
+// 32.32 fixed point seconds since the NTP epoch
+fn to_ntp_timestamp(time: DateTime<Utc>) -> u64 {
+    let seconds = (time.timestamp() + NTP_UNIX_OFFSET_SECS) as u64;
+    let fraction = ((time.timestamp_subsec_nanos() as u64) << 32) / 1_000_000_000;
+    (seconds << 32) | fraction
+}
+
+// Difference `later - earlier` of two NTP timestamps in nanoseconds. Wrapping keeps it right across era rollovers.
+fn ntp_diff_nanos(later: u64, earlier: u64) -> i128 {
+    ((later.wrapping_sub(earlier) as i64 as i128) * 1_000_000_000) >> 32
+}
+
+fn read_u64(packet: &[u8], at: usize) -> u64 {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&packet[at..at + 8]);
+    u64::from_be_bytes(bytes)
+}
+
+fn nanos_to_millis(nanos: i128) -> f64 {
+    nanos as f64 / 1_000_000.0
+}
+
+async fn exchange(
+    address: SocketAddr,
+    timeout: Duration,
+) -> Result<(Vec<u8>, u64, Duration), NtpCheckError> {
+    let bind = if address.is_ipv4() {
+        "0.0.0.0:0"
+    } else {
+        "[::]:0"
+    };
+    let io = |e: std::io::Error| fail(NtpErrorKind::Io, e.to_string());
+    let socket = UdpSocket::bind(bind).await.map_err(io)?;
+    socket.connect(address).await.map_err(io)?;
+
+    // The local wall clock is only read once, for the transmit timestamp. The receive time is derived
+    // from the monotonic clock so a clock stepping during the exchange can't skew the result.
+    let transmit = to_ntp_timestamp(Utc::now());
+    let started = Instant::now();
+    let mut request = [0u8; NTP_PACKET_LEN];
+    request[0] = CLIENT_HEADER;
+    request[40..48].copy_from_slice(&transmit.to_be_bytes());
+    socket.send(&request).await.map_err(io)?;
+
+    let mut response = vec![0u8; 512];
+    let received = tokio::time::timeout(timeout, socket.recv(&mut response))
+        .await
+        .map_err(|_| {
+            fail(
+                NtpErrorKind::Timeout,
+                format!("no reply from {} within {:?}", address, timeout),
+            )
+        })?
+        .map_err(io)?;
+    let elapsed = started.elapsed();
+    response.truncate(received);
+    Ok((response, transmit, elapsed))
+}
+
+fn evaluate(
+    packet: &[u8],
+    transmit: u64,
+    elapsed: Duration,
+    details: &mut NtpDetails,
+) -> Result<(), NtpCheckError> {
+    if packet.len() < NTP_PACKET_LEN {
+        return Err(fail(
+            NtpErrorKind::Malformed,
+            format!("reply of {} bytes is too short", packet.len()),
+        ));
+    }
+    let leap = packet[0] >> 6;
+    let mode = packet[0] & 0x07;
+    if mode != MODE_SERVER {
+        return Err(fail(
+            NtpErrorKind::Malformed,
+            format!("unexpected mode {} in reply", mode),
+        ));
+    }
+    // Replies must echo our transmit timestamp, anything else isn't an answer to this request
+    if read_u64(packet, 24) != transmit {
+        return Err(fail(
+            NtpErrorKind::Malformed,
+            "reply doesn't match the request",
+        ));
+    }
+    let stratum = packet[1];
+    details.stratum = Some(stratum);
+    if stratum == 0 {
+        let code = String::from_utf8_lossy(&packet[12..16])
+            .trim_end_matches('\0')
+            .to_owned();
+        return Err(fail(
+            NtpErrorKind::KissOfDeath,
+            format!("server sent kiss-of-death '{}'", code),
+        ));
+    }
+    if leap == LEAP_UNSYNCHRONIZED {
+        return Err(fail(
+            NtpErrorKind::Unsynchronized,
+            "server clock is not synchronized",
+        ));
+    }
+
+    // T1 transmit, T2 server receive, T3 server transmit, T4 receive = T1 + elapsed.
+    // offset = ((T2 - T1) + (T3 - T4)) / 2, delay = (T4 - T1) - (T3 - T2)
+    let server_received = ntp_diff_nanos(read_u64(packet, 32), transmit);
+    let server_transmitted = ntp_diff_nanos(read_u64(packet, 40), transmit);
+    let elapsed = elapsed.as_nanos() as i128;
+    let offset = (server_received + server_transmitted - elapsed) / 2;
+    let delay = elapsed - (server_transmitted - server_received);
+    details.offset_ms = Some(nanos_to_millis(offset));
+    details.delay_ms = Some(nanos_to_millis(delay.max(0)));
+    Ok(())
+}
+
+fn check_expectations(params: &NtpParameters, details: &NtpDetails) -> Result<(), NtpCheckError> {
+    if let (Some(max), Some(offset)) = (params.expect.max_offset_ms, details.offset_ms) {
+        if offset.abs() > max as f64 {
+            return Err(fail(
+                NtpErrorKind::Expectation,
+                format!("clock offset of {:.3}ms exceeds {}ms", offset, max),
+            ));
+        }
+    }
+    if let (Some(max), Some(delay)) = (params.expect.max_delay_ms, details.delay_ms) {
+        if delay > max as f64 {
+            return Err(fail(
+                NtpErrorKind::Expectation,
+                format!("round-trip delay of {:.3}ms exceeds {}ms", delay, max),
+            ));
+        }
+    }
+    Ok(())
+}
+
+async fn run_check(
+    url: &str,
+    params: &NtpParameters,
+    timeout: Duration,
+    details: &mut NtpDetails,
+) -> Result<(), NtpCheckError> {
+    let (host, port) = parse_ntp_address(url).map_err(|e| fail(NtpErrorKind::Io, e))?;
+    let address = lookup_host((host.as_str(), port))
+        .await
+        .map_err(|e| fail(NtpErrorKind::Io, e.to_string()))?
+        .next()
+        .ok_or_else(|| fail(NtpErrorKind::Io, format!("no address found for {}", host)))?;
+    let (packet, transmit, elapsed) = exchange(address, timeout).await?;
+    evaluate(&packet, transmit, elapsed, details)?;
+    debug!(
+        "NTP server {} offset {:?}ms delay {:?}ms",
+        address, details.offset_ms, details.delay_ms
+    );
+    check_expectations(params, details)
+}
+
+pub async fn check_ntp(url: &str, params: &NtpParameters, timeout: Duration) -> NtpCheckOutcome {
+    let mut details = NtpDetails::default();
+    let error = run_check(url, params, timeout, &mut details).await.err();
+    details.error_kind = error.as_ref().map(|e| e.kind);
+    NtpCheckOutcome { details, error }
+}
+
+#[cfg(test)]
+mod ntp_tests {
+    use std::time::Duration;
+
+    use chrono::Utc;
+    use tokio::net::UdpSocket;
+
+    use crate::probe::model::{NtpErrorKind, NtpExpectations, NtpParameters, Probe, ProbeType};
+    use crate::probe::ntp_probe::{check_ntp, parse_ntp_address, to_ntp_timestamp};
+
+    enum Reply {
+        // A synchronized server whose clock is ahead of ours by this many seconds
+        Shifted(i64),
+        KissOfDeath(&'static [u8; 4]),
+        Silent,
+    }
+
+    async fn fake_ntp_server(reply: Reply) -> String {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let address = socket.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut request = [0u8; 48];
+            let (_, peer) = socket.recv_from(&mut request).await.unwrap();
+            let mut response = [0u8; 48];
+            response[24..32].copy_from_slice(&request[40..48]);
+            match reply {
+                Reply::Shifted(seconds) => {
+                    let now = to_ntp_timestamp(Utc::now() + chrono::Duration::seconds(seconds));
+                    response[0] = 0x24;
+                    response[1] = 2;
+                    response[32..40].copy_from_slice(&now.to_be_bytes());
+                    response[40..48].copy_from_slice(&now.to_be_bytes());
+                }
+                Reply::KissOfDeath(code) => {
+                    response[0] = 0xe4;
+                    response[12..16].copy_from_slice(code);
+                }
+                Reply::Silent => return,
+            }
+            socket.send_to(&response, peer).await.unwrap();
+        });
+        format!("ntp://{}", address)
+    }
+
+    #[test]
+    fn test_parse_ntp_address() {
+        assert_eq!(
+            ("pool.ntp.org".to_owned(), 123),
+            parse_ntp_address("ntp://pool.ntp.org").unwrap()
+        );
+        assert_eq!(
+            ("127.0.0.1".to_owned(), 1123),
+            parse_ntp_address("127.0.0.1:1123").unwrap()
+        );
+        assert!(parse_ntp_address("ntp://pool.ntp.org:port").is_err());
+    }
+
+    #[test]
+    fn test_ntp_probe_config() {
+        let probe: Probe = serde_yaml::from_str(
+            r#"
+            name: Time sync
+            type: ntp
+            url: ntp://pool.ntp.org
+            schedule:
+              initial_delay: 0
+              interval: 60
+            ntp:
+              expect:
+                max_offset_ms: 500
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(ProbeType::Ntp, probe.probe_type);
+        assert_eq!(Some(500), probe.ntp.unwrap().expect.max_offset_ms);
+    }
+
+    #[tokio::test]
+    async fn test_ntp_check_measures_offset() {
+        let url = fake_ntp_server(Reply::Shifted(40)).await;
+
+        let outcome = check_ntp(&url, &NtpParameters::default(), Duration::from_secs(5)).await;
+
+        assert!(outcome.error.is_none());
+        let offset = outcome.details.offset_ms.unwrap();
+        assert!((offset - 40_000.0).abs() < 1_000.0, "offset {}", offset);
+        assert!(outcome.details.delay_ms.unwrap() >= 0.0);
+        assert_eq!(Some(2), outcome.details.stratum);
+    }
+
+    #[tokio::test]
+    async fn test_ntp_check_fails_max_offset() {
+        let url = fake_ntp_server(Reply::Shifted(-40)).await;
+        let params = NtpParameters {
+            expect: NtpExpectations {
+                max_offset_ms: Some(500),
+                max_delay_ms: None,
+            },
+        };
+
+        let outcome = check_ntp(&url, &params, Duration::from_secs(5)).await;
+
+        assert_eq!(NtpErrorKind::Expectation, outcome.error.unwrap().kind);
+        assert!(outcome.details.offset_ms.unwrap() < -39_000.0);
+    }
+
+    #[tokio::test]
+    async fn test_ntp_check_reports_kiss_of_death() {
+        let url = fake_ntp_server(Reply::KissOfDeath(b"RATE")).await;
+
+        let outcome = check_ntp(&url, &NtpParameters::default(), Duration::from_secs(5)).await;
+
+        let error = outcome.error.unwrap();
+        assert_eq!(NtpErrorKind::KissOfDeath, error.kind);
+        assert!(error.message.contains("RATE"));
+        assert_eq!(Some(NtpErrorKind::KissOfDeath), outcome.details.error_kind);
+    }
+
+    #[tokio::test]
+    async fn test_ntp_check_times_out() {
+        let url = fake_ntp_server(Reply::Silent).await;
+
+        let outcome = check_ntp(&url, &NtpParameters::default(), Duration::from_millis(100)).await;
+
+        assert_eq!(NtpErrorKind::Timeout, outcome.error.unwrap().kind);
+        assert_eq!(None, outcome.details.offset_ms);
+    }
+}
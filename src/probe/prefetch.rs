@@ -0,0 +1,349 @@
+// Probe runs with `with.prefetch`: a request before the probe's own, whose response fills the
+// `${{ prefetch.<name> }}` placeholders of the probe's headers and body. The cookies it's given are
+// sent with the probe's request, as a cookie jar shared by the two would.
+use std::collections::{BTreeSet, HashMap};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::audit::AuditScope;
+
+use super::duration;
+use super::http_probe::call_endpoint;
+use super::model::{EndpointResult, Extraction, Prefetch, ProbeOptions};
+
+lazy_static! {
+    static ref PLACEHOLDER: Regex = Regex::new(r"\$\{\{\s*prefetch\.(.*?)\s*\}\}").unwrap();
+}
+
+#[derive(Debug)]
+pub struct Prefetched {
+    pub timestamp_started: DateTime<Utc>,
+    // Until the body arrived, the probe's request can't be sent before
+    pub duration: Duration,
+    // Extraction name to the value it found
+    pub values: HashMap<String, String>,
+    // Name and value of each cookie the response set, in order, the last one of a name kept
+    pub cookies: Vec<(String, String)>,
+}
+
+#[derive(Debug)]
+pub struct PrefetchError {
+    pub timestamp_started: DateTime<Utc>,
+    // Unset when no response arrived
+    pub duration: Option<Duration>,
+    pub message: String,
+}
+
+// The names of the placeholders in the header values and body of `with`, sorted, each once
+pub fn placeholders(with: &ProbeOptions) -> Vec<String> {
+    with.headers
+        .iter()
+        .flat_map(|headers| headers.values())
+        .chain(with.body.as_ref())
+        .flat_map(|text| {
+            PLACEHOLDER
+                .captures_iter(text)
+                .map(|caps| caps[1].to_owned())
+        })
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect()
+}
+
+// Sent with the timeout and User-Agent of the probe. Error statuses and extractions that find
+// nothing fail the prefetch.
+pub async fn fetch(
+    prefetch: &Prefetch,
+    with: Option<&ProbeOptions>,
+    sensitive: bool,
+    audit: Option<AuditScope<'_>>,
+) -> Result<Prefetched, PrefetchError> {
+    let options = Some(ProbeOptions {
+        timeout: with.and_then(ProbeOptions::timeout),
+        user_agent: with.and_then(|with| with.user_agent.clone()),
+        ..ProbeOptions::default()
+    });
+    let timestamp_started = Utc::now();
+    let endpoint_result = call_endpoint(
+        &prefetch.http_method,
+        &prefetch.url,
+        &options,
+        sensitive,
+        audit,
+    )
+    .await
+    .map_err(|e| PrefetchError {
+        timestamp_started,
+        duration: None,
+        message: format!("prefetch of {} failed: {}", prefetch.url, e),
+    })?;
+
+    let timestamp_started = endpoint_result.timestamp_request_started;
+    let duration = duration::between(timestamp_started, endpoint_result.timestamp_body_received);
+    let failed = |message: String| PrefetchError {
+        timestamp_started,
+        duration: Some(duration),
+        message: format!("prefetch of {} {}", prefetch.url, message),
+    };
+    if endpoint_result.status_code >= 400 {
+        return Err(failed(format!(
+            "got status {}",
+            endpoint_result.status_code
+        )));
+    }
+    let mut cookies: Vec<(String, String)> = vec![];
+    for (name, value) in endpoint_result
+        .set_cookies
+        .iter()
+        .filter_map(|header| parse_set_cookie(header))
+    {
+        cookies.retain(|(kept, _)| *kept != name);
+        cookies.push((name, value));
+    }
+    let mut values = HashMap::new();
+    for (name, extraction) in &prefetch.extract {
+        let Some(value) = extract(extraction, &endpoint_result, &cookies) else {
+            return Err(failed(format!(
+                "has no {} for `{}`",
+                describe(extraction),
+                name
+            )));
+        };
+        values.insert(name.clone(), value);
+    }
+    Ok(Prefetched {
+        timestamp_started,
+        duration,
+        values,
+        cookies,
+    })
+}
+
+impl Prefetched {
+    // The probe's `with` for its own request: placeholders filled, and the cookies appended to the
+    // `Cookie` header the probe sets, if any
+    pub fn apply(&self, with: &ProbeOptions) -> ProbeOptions {
+        let mut headers: HashMap<String, String> = with
+            .headers
+            .iter()
+            .flatten()
+            .map(|(name, value)| (name.clone(), self.substitute(value)))
+            .collect();
+        if !self.cookies.is_empty() {
+            let jar = self
+                .cookies
+                .iter()
+                .map(|(name, value)| format!("{}={}", name, value))
+                .collect::<Vec<_>>()
+                .join("; ");
+            match headers
+                .iter_mut()
+                .find(|(name, _)| name.eq_ignore_ascii_case("cookie"))
+            {
+                Some((_, cookie)) => {
+                    cookie.push_str("; ");
+                    cookie.push_str(&jar);
+                }
+                None => {
+                    headers.insert("Cookie".to_owned(), jar);
+                }
+            }
+        }
+        ProbeOptions {
+            headers: (!headers.is_empty()).then_some(headers),
+            body: with.body.as_ref().map(|body| self.substitute(body)),
+            ..with.clone()
+        }
+    }
+
+    fn substitute(&self, text: &str) -> String {
+        PLACEHOLDER
+            .replace_all(text, |caps: &regex::Captures| {
+                self.values.get(&caps[1]).cloned().unwrap_or_default()
+            })
+            .into_owned()
+    }
+}
+
+// The name and value of a `Set-Cookie` header, its attributes don't matter within a run
+fn parse_set_cookie(header: &str) -> Option<(String, String)> {
+    let (name, value) = header.split(';').next()?.split_once('=')?;
+    let name = name.trim();
+    (!name.is_empty()).then(|| (name.to_owned(), value.trim().to_owned()))
+}
+
+fn extract(
+    extraction: &Extraction,
+    response: &EndpointResult,
+    cookies: &[(String, String)],
+) -> Option<String> {
+    match extraction {
+        Extraction::Header(name) => response.headers.get(&name.to_ascii_lowercase()).cloned(),
+        Extraction::Cookie(name) => cookies
+            .iter()
+            .find(|(cookie, _)| cookie == name)
+            .map(|(_, value)| value.clone()),
+        Extraction::Regex(regex) => {
+            let caps = Regex::new(regex).ok()?.captures(&response.body)?;
+            caps.get(1)
+                .or_else(|| caps.get(0))
+                .map(|found| found.as_str().to_owned())
+        }
+        Extraction::Selector(selector) => {
+            let selector = scraper::Selector::parse(selector).ok()?;
+            let document = scraper::Html::parse_document(&response.body);
+            let element = document.select(&selector).next()?;
+            Some(match element.value().attr("value") {
+                Some(value) => value.to_owned(),
+                None => element.text().collect(),
+            })
+        }
+    }
+}
+
+fn describe(extraction: &Extraction) -> String {
+    match extraction {
+        Extraction::Header(name) => format!("header {}", name),
+        Extraction::Cookie(name) => format!("cookie {}", name),
+        Extraction::Regex(regex) => format!("match of `{}`", regex),
+        Extraction::Selector(selector) => format!("element matching `{}`", selector),
+    }
+}
+
+#[cfg(test)]
+mod prefetch_tests {
+    use std::collections::{BTreeMap, HashMap};
+    use std::time::Duration;
+
+    use chrono::Utc;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use super::{fetch, placeholders, Prefetched};
+    use crate::probe::model::{Extraction, Prefetch, ProbeOptions};
+
+    fn prefetch(url: String, extract: &[(&str, Extraction)]) -> Prefetch {
+        Prefetch {
+            url,
+            http_method: "GET".to_owned(),
+            extract: extract
+                .iter()
+                .map(|(name, extraction)| (name.to_string(), extraction.clone()))
+                .collect::<BTreeMap<_, _>>(),
+            include_in_duration: false,
+        }
+    }
+
+    #[test]
+    fn test_placeholders_are_listed_once() {
+        let with = ProbeOptions {
+            headers: Some(HashMap::from([
+                ("X-CSRF-Token".to_owned(), "${{ prefetch.csrf }}".to_owned()),
+                ("X-Request-Id".to_owned(), "${{generate.uuid}}".to_owned()),
+            ])),
+            body: Some("_token=${{prefetch.form}}&again=${{ prefetch.csrf }}".to_owned()),
+            ..ProbeOptions::default()
+        };
+
+        assert_eq!(vec!["csrf", "form"], placeholders(&with));
+    }
+
+    #[test]
+    fn test_apply_fills_placeholders_and_sends_the_cookies() {
+        let prefetched = Prefetched {
+            timestamp_started: Utc::now(),
+            duration: Duration::from_millis(10),
+            values: HashMap::from([("csrf".to_owned(), "abc".to_owned())]),
+            cookies: vec![
+                ("session".to_owned(), "s1".to_owned()),
+                ("XSRF-TOKEN".to_owned(), "abc".to_owned()),
+            ],
+        };
+        let with = ProbeOptions {
+            headers: Some(HashMap::from([
+                ("X-CSRF-Token".to_owned(), "${{ prefetch.csrf }}".to_owned()),
+                ("cookie".to_owned(), "consent=yes".to_owned()),
+            ])),
+            body: Some("_token=${{ prefetch.csrf }}&user=probe".to_owned()),
+            ..ProbeOptions::default()
+        };
+
+        let applied = prefetched.apply(&with);
+
+        let headers = applied.headers.unwrap();
+        assert_eq!("abc", headers["X-CSRF-Token"]);
+        assert_eq!("consent=yes; session=s1; XSRF-TOKEN=abc", headers["cookie"]);
+        assert_eq!(Some("_token=abc&user=probe".to_owned()), applied.body);
+    }
+
+    #[tokio::test]
+    async fn test_every_kind_of_extraction() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/login"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("X-CSRF-Token", "from-header")
+                    .append_header("Set-Cookie", "XSRF-TOKEN=stale; Path=/")
+                    .append_header("Set-Cookie", "XSRF-TOKEN=from-cookie; Path=/; HttpOnly")
+                    .set_body_string(
+                        r#"<form><input type="hidden" name="_token" value="from-input">
+                        <script>window.nonce = "from-regex";</script></form>"#,
+                    ),
+            )
+            .mount(&mock_server)
+            .await;
+        let prefetch = prefetch(
+            format!("{}/login", mock_server.uri()),
+            &[
+                ("header", Extraction::Header("x-csrf-token".to_owned())),
+                ("cookie", Extraction::Cookie("XSRF-TOKEN".to_owned())),
+                (
+                    "regex",
+                    Extraction::Regex(r#"nonce = "([^"]+)""#.to_owned()),
+                ),
+                (
+                    "input",
+                    Extraction::Selector("input[name=_token]".to_owned()),
+                ),
+            ],
+        );
+
+        let prefetched = fetch(&prefetch, None, false, None).await.unwrap();
+
+        assert_eq!("from-header", prefetched.values["header"]);
+        assert_eq!("from-cookie", prefetched.values["cookie"]);
+        assert_eq!("from-regex", prefetched.values["regex"]);
+        assert_eq!("from-input", prefetched.values["input"]);
+        assert_eq!(
+            vec![("XSRF-TOKEN".to_owned(), "from-cookie".to_owned())],
+            prefetched.cookies
+        );
+    }
+
+    #[tokio::test]
+    async fn test_missing_value_fails_the_prefetch() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/login"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("<form></form>"))
+            .mount(&mock_server)
+            .await;
+        let url = format!("{}/login", mock_server.uri());
+        let prefetch = prefetch(
+            url.clone(),
+            &[("csrf", Extraction::Cookie("XSRF-TOKEN".to_owned()))],
+        );
+
+        let error = fetch(&prefetch, None, false, None).await.unwrap_err();
+
+        assert!(error.duration.is_some());
+        assert_eq!(
+            format!("prefetch of {} has no cookie XSRF-TOKEN for `csrf`", url),
+            error.message
+        );
+    }
+}
@@ -1,8 +1,11 @@
+use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Duration;
 
-use chrono::Utc;
+use chrono::{DateTime, Utc};
+use futures::stream::{self, StreamExt};
+use opentelemetry::baggage::BaggageExt;
 use opentelemetry::global;
-use opentelemetry::trace;
 use opentelemetry::trace::FutureExt;
 use opentelemetry::trace::Status;
 use opentelemetry::trace::TraceContextExt;
@@ -12,34 +15,323 @@ use opentelemetry::KeyValue;
 use opentelemetry_semantic_conventions as semconv;
 use tracing::error;
 use tracing::info;
+use tracing::warn;
+use uuid::Uuid;
 
-use crate::alerts::outbound_webhook::alert_if_failure;
+use crate::alerts::outbound_webhook::{alert_if_failure, alert_on_recovery, RunFailure};
+use crate::app_state::DEFAULT_RECOVERY_THRESHOLD;
+use crate::audit::AuditScope;
+use crate::errors::AlertError;
 use crate::otel::metrics::MonitorStatus;
 use crate::probe::model::StepResult;
-use crate::probe::variables::substitute_input_parameters;
+use crate::probe::story_expectations::evaluate_story_expectation;
+use crate::probe::variables::capture_values;
+use crate::probe::variables::expanded_names;
+use crate::probe::variables::step_input_parameters;
 use crate::probe::variables::substitute_variables;
 use crate::probe::variables::StepVariables;
 use crate::probe::variables::StoryVariables;
+use crate::self_alerts::record_alert_failures;
 
-use super::expectations::validate_response;
+use super::body_metrics::record_body_metrics;
+use super::circuit_breaker::CircuitState;
+use super::duration;
+use super::expectations::evaluate_expectations;
+use super::full_stack_probe::check_dns_and_tls;
 use super::http_probe::call_endpoint;
+use super::http_probe::DEFAULT_REQUEST_TIMEOUT_SECS;
+use super::http_probe::STORY_RUN_ID_KEY;
+use super::model::error_kind;
+use super::model::url_result_name;
+use super::model::ConnectionDetails;
+use super::model::EndpointResult;
+use super::model::FullStackPhase;
+use super::model::PhaseTiming;
+use super::model::Prefetch;
 use super::model::Probe;
 use super::model::ProbeResult;
 use super::model::ProbeScheduleParameters;
+use super::model::ProbeType;
+use super::model::Samples;
+use super::model::Step;
 use super::model::Story;
 use super::model::StoryResult;
+use super::model::TlsDetails;
+use super::model::PREFETCH_PHASE;
+use super::ntp_probe::check_ntp;
+use super::prefetch::{fetch, Prefetched};
+use super::rate_limit::{is_rate_limited, parse_retry_after};
+use super::samples::aggregate;
+use super::sftp_probe::check_sftp;
+use super::smtp_probe::check_smtp;
+use super::span_events::set_error_status;
+use super::span_events::{failed_expectation, record_expectation_failure};
 use crate::AppState;
 
 pub trait Monitorable {
     async fn probe_and_store_result(&self, app_state: Arc<AppState>);
     fn get_name(&self) -> String;
     fn get_schedule(&self) -> &ProbeScheduleParameters;
+    // Whether scheduled runs happen, triggered runs ignore it
+    fn enabled(&self, _app_state: &AppState) -> bool {
+        true
+    }
+}
+
+fn time_since(timestamp: &chrono::DateTime<Utc>) -> Duration {
+    duration::between(*timestamp, Utc::now())
+}
+
+fn prefetch_phase(duration: Duration) -> PhaseTiming {
+    PhaseTiming {
+        name: PREFETCH_PHASE.to_owned(),
+        duration_ms: duration.as_millis() as u64,
+    }
+}
+
+// The trace id of a run whose spans are exported, the exemplar of its `duration` recording
+fn sampled_trace_id(cx: &Context) -> Option<String> {
+    let span = cx.span();
+    let span_context = span.span_context();
+    (span_context.is_valid() && span_context.is_sampled())
+        .then(|| span_context.trace_id().to_string())
+}
+
+fn record_alert_errors(app_state: &AppState, errors: Vec<AlertError>) {
+    record_alert_failures(app_state, errors.len());
+    for error in errors {
+        error!(
+            channel = error.channel.as_str(),
+            error.kind = error.kind(),
+            "Error sending out alert: {}",
+            error
+        );
+        app_state.metrics.alerts_failed.add(
+            1,
+            &[
+                KeyValue::new("name", error.monitor_name.clone()),
+                KeyValue::new("channel", error.channel.as_str()),
+                KeyValue::new("error.kind", error.kind()),
+            ],
+        );
+    }
+}
+
+// What the steps of one story run share
+struct StoryRun {
+    root_cx: Context,
+    run_id_attribute: KeyValue,
+    timestamp_started: DateTime<Utc>,
+    // Responses of the steps that passed, and their captures, for the steps after them
+    variables: StoryVariables,
+    captures: HashMap<String, serde_json::Value>,
 }
 
-fn time_since(timestamp: &chrono::DateTime<Utc>) -> u64 {
-    Utc::now()
-        .signed_duration_since(*timestamp)
-        .num_milliseconds() as u64
+impl Story {
+    // Runs `steps` in order until one fails, tells whether all of them passed
+    async fn run_steps(
+        &self,
+        app_state: &AppState,
+        run: &mut StoryRun,
+        steps: &[Step],
+        step_results: &mut Vec<StepResult>,
+    ) -> bool {
+        for step in steps {
+            let step_result = self.run_step(app_state, run, step).await;
+            let success = step_result.success;
+            step_results.push(step_result);
+            if !success {
+                return false;
+            }
+        }
+        true
+    }
+
+    async fn run_step(&self, app_state: &AppState, run: &mut StoryRun, step: &Step) -> StepResult {
+        let step_started = Utc::now();
+        let step_tags = [
+            KeyValue::new("name", step.name.clone()),
+            KeyValue::new("story_name", self.name.clone()),
+            KeyValue::new("type", "step"),
+        ]
+        .into_iter()
+        .chain(self.tags.iter().flat_map(|tags| {
+            tags.iter()
+                .map(|(k, v)| KeyValue::new(k.clone(), v.clone()))
+        }))
+        .collect::<Vec<_>>();
+
+        app_state.metrics.runs.add(1, &step_tags);
+        let tracer = global::tracer("probe_logic");
+        let step_span = tracer
+            .span_builder(step.name.clone())
+            .with_attributes([run.run_id_attribute.clone()])
+            .start_with_context(&tracer, &run.root_cx);
+        let step_cx = run.root_cx.with_span(step_span);
+
+        let url = substitute_variables(&self.step_url(&step.url), &run.variables);
+        let input_parameters = step_input_parameters(step, &run.variables);
+
+        let audit = AuditScope {
+            app_state,
+            monitor: &self.name,
+            step: Some(&step.name),
+        };
+        let call_endpoint_result = call_endpoint(
+            &step.http_method,
+            &url,
+            &input_parameters,
+            step.sensitive,
+            Some(audit),
+        )
+        .with_context(step_cx.clone())
+        .await;
+
+        match call_endpoint_result {
+            Ok(endpoint_result) => {
+                app_state.metrics.record_http_status_code(
+                    &self.name,
+                    endpoint_result.status_code.into(),
+                    &step_tags,
+                );
+                let probe_response = endpoint_result.to_probe_response();
+                let span = step_cx.span();
+                span.set_attribute(opentelemetry::KeyValue::new(
+                    semconv::trace::HTTP_RESPONSE_STATUS_CODE,
+                    endpoint_result.status_code.to_string(),
+                ));
+                let default_success_statuses = app_state
+                    .config
+                    .read()
+                    .unwrap()
+                    .settings
+                    .default_success_statuses
+                    .clone();
+                let response_meta = endpoint_result.meta();
+                let expectations_result = evaluate_expectations(
+                    app_state,
+                    &step.name,
+                    endpoint_result.status_code,
+                    endpoint_result.body,
+                    response_meta,
+                    &step.expectations,
+                    default_success_statuses,
+                )
+                .await;
+                // Only captured once the expectations passed, a missing header fails the step
+                let captures = match (&expectations_result, &step.captures) {
+                    (Ok(()), Some(step_captures)) => capture_values(
+                        step_captures,
+                        &probe_response.body,
+                        &endpoint_result.headers,
+                        &url,
+                    ),
+                    _ => Ok(HashMap::new()),
+                };
+                let mut monitor_status = MonitorStatus::Ok.as_u64();
+                if let Err(err) = expectations_result.as_ref() {
+                    span.record_error(&err);
+                    record_expectation_failure(&span, err, step.sensitive);
+                    set_error_status(&span, "expectation");
+                    app_state
+                        .metrics
+                        .record_duration(time_since(&step_started), &step_tags);
+                    app_state.metrics.errors.add(1, &step_tags);
+                    monitor_status = MonitorStatus::Error.as_u64();
+                }
+                if let Err(err) = captures.as_ref() {
+                    span.add_event(
+                        "capture.failed",
+                        vec![KeyValue::new("error.message", err.clone())],
+                    );
+                    set_error_status(&span, "expectation");
+                    app_state
+                        .metrics
+                        .record_duration(time_since(&step_started), &step_tags);
+                    app_state.metrics.errors.add(1, &step_tags);
+                    monitor_status = MonitorStatus::Error.as_u64();
+                }
+                app_state
+                    .metrics
+                    .record_status(&self.name, monitor_status, &step_tags);
+
+                let error_message = match (&expectations_result, &captures) {
+                    (Err(e), _) => Some(e.to_string()),
+                    (_, Err(e)) => Some(e.clone()),
+                    _ => None,
+                };
+                let step_result = StepResult {
+                    step_name: step.name.clone(),
+                    timestamp_started: endpoint_result.timestamp_request_started,
+                    success: error_message.is_none(),
+                    error_message,
+                    response: Some(probe_response),
+                    trace_id: Some(endpoint_result.trace_id),
+                    span_id: Some(endpoint_result.span_id),
+                    captures: None,
+                };
+                let captures = match captures {
+                    Ok(captures) if step_result.success => captures,
+                    _ => return step_result,
+                };
+                let redacted = step.sensitive || self.sensitive;
+                let step_result = StepResult {
+                    captures: step.captures.as_ref().map(|_| {
+                        captures
+                            .iter()
+                            .map(|(name, value)| {
+                                let value = if redacted {
+                                    serde_json::Value::from("<redacted>")
+                                } else {
+                                    value.clone()
+                                };
+                                (name.clone(), value)
+                            })
+                            .collect()
+                    }),
+                    ..step_result
+                };
+
+                // Add 0 to ensure this is exported with value 0, so e.g. rate
+                // queries in promql don't miss the step from 0 -> 1
+                app_state.metrics.errors.add(0, &step_tags);
+                step_cx.span().set_status(Status::Ok);
+                run.captures.extend(captures.clone());
+                let step_variables = StepVariables {
+                    response_body: step_result.response.clone().unwrap().body,
+                    captures,
+                };
+                run.variables
+                    .steps
+                    .insert(step.name.clone(), step_variables);
+                app_state
+                    .metrics
+                    .record_duration(time_since(&run.timestamp_started), &step_tags);
+                step_result
+            }
+            Err(e) => {
+                error!("Error calling endpoint: {}", e);
+                app_state
+                    .metrics
+                    .record_http_status_code(&self.name, 0, &step_tags);
+                step_cx.span().record_error(&*e);
+                set_error_status(&step_cx.span(), "request");
+                app_state
+                    .metrics
+                    .record_duration(time_since(&run.timestamp_started), &step_tags);
+                StepResult {
+                    step_name: step.name.clone(),
+                    success: false,
+                    error_message: Some(e.to_string()),
+                    timestamp_started: Utc::now(),
+                    response: None,
+                    trace_id: None,
+                    span_id: None,
+                    captures: None,
+                }
+            }
+        }
+    }
 }
 
 // TODOs here: Step / Probe can be the same object
@@ -60,165 +352,173 @@ impl Monitorable for Story {
         }))
         .collect::<Vec<_>>();
         app_state.metrics.runs.add(1, &story_attributes);
-        let mut story_variables = StoryVariables::new();
-        let mut step_results: Vec<StepResult> = vec![];
         let timestamp_started = Utc::now();
 
+        let story_run_id = Uuid::new_v4();
+        let story_run_id_attribute = KeyValue::new(STORY_RUN_ID_KEY, story_run_id.to_string());
         let tracer = global::tracer("probe_logic");
-        let root_span = tracer.start(self.name.clone());
-        let root_cx = Context::default().with_span(root_span);
-        for step in &self.steps {
-            let step_started = Utc::now();
-            let step_tags = [
-                KeyValue::new("name", step.name.clone()),
-                KeyValue::new("story_name", self.name.clone()),
-                KeyValue::new("type", "step"),
-            ]
-            .into_iter()
-            .chain(self.tags.iter().flat_map(|tags| {
-                tags.iter()
-                    .map(|(k, v)| KeyValue::new(k.clone(), v.clone()))
-            }))
-            .collect::<Vec<_>>();
-
-            app_state.metrics.runs.add(1, &step_tags);
-            let step_span = tracer.start_with_context(step.name.clone(), &root_cx);
-            let step_cx = root_cx.with_span(step_span);
-
-            let url = substitute_variables(&step.url, &story_variables);
-            let input_parameters = substitute_input_parameters(&step.with, &story_variables);
-
-            let call_endpoint_result =
-                call_endpoint(&step.http_method, &url, &input_parameters, step.sensitive)
-                    .with_context(step_cx.clone())
-                    .await;
-
-            match call_endpoint_result {
-                Ok(endpoint_result) => {
-                    app_state
-                        .metrics
-                        .http_status_code
-                        .record(endpoint_result.status_code.into(), &step_tags);
-                    let probe_response = endpoint_result.to_probe_response();
-                    let span = step_cx.span();
-                    span.set_attribute(opentelemetry::KeyValue::new(
-                        semconv::trace::HTTP_RESPONSE_STATUS_CODE,
-                        endpoint_result.status_code.to_string(),
-                    ));
-                    let expectations_result = validate_response(
-                        &step.name,
-                        endpoint_result.status_code,
-                        endpoint_result.body,
-                        &step.expectations,
-                    );
-                    let mut monitor_status = MonitorStatus::Ok.as_u64();
-                    if let Err(err) = expectations_result.as_ref() {
-                        span.record_error(&err);
-                        span.set_status(Status::Error {
-                            description: "Expectation failed".into(),
-                        });
-                        app_state
-                            .metrics
-                            .duration
-                            .record(time_since(&step_started), &step_tags);
-                        app_state.metrics.errors.add(1, &step_tags);
-                        monitor_status = MonitorStatus::Error.as_u64();
-                    }
-                    app_state
-                        .metrics
-                        .status
-                        .record(monitor_status, &story_attributes);
-
-                    let step_result = StepResult {
-                        step_name: step.name.clone(),
-                        timestamp_started: endpoint_result.timestamp_request_started,
-                        success: expectations_result.is_ok(),
-                        error_message: expectations_result.as_ref().err().map(|e| e.to_string()),
-                        response: Some(probe_response),
-                        trace_id: Some(endpoint_result.trace_id),
-                        span_id: Some(endpoint_result.span_id),
-                    };
-                    step_results.push(step_result);
-
-                    if expectations_result.is_err() {
-                        break;
-                    }
+        let root_span = tracer
+            .span_builder(self.name.clone())
+            .with_attributes([story_run_id_attribute.clone()])
+            .start(&tracer);
+        // The baggage carries the run ID to the step spans and the outgoing X-Story-Run-Id header
+        let root_cx = Context::default()
+            .with_span(root_span)
+            .with_baggage([story_run_id_attribute.clone()]);
+        let mut run = StoryRun {
+            root_cx: root_cx.clone(),
+            run_id_attribute: story_run_id_attribute,
+            timestamp_started,
+            variables: StoryVariables::new(),
+            captures: HashMap::new(),
+        };
+        // A failed setup step fails the story right away, the main steps don't run
+        let mut step_results: Vec<StepResult> = vec![];
+        let setup = self.setup.as_deref().unwrap_or_default();
+        if self
+            .run_steps(&app_state, &mut run, setup, &mut step_results)
+            .await
+        {
+            self.run_steps(&app_state, &mut run, &self.steps, &mut step_results)
+                .await;
+        }
+        let last_step = step_results.last().unwrap();
+        let mut story_success = last_step.success;
+        let mut error_message = last_step.error_message.clone();
 
-                    // Add 0 to ensure this is exported with value 0, so e.g. rate
-                    // queries in promql don't miss the step from 0 -> 1
-                    app_state.metrics.errors.add(0, &step_tags);
-                    step_cx.span().set_status(Status::Ok);
-                    let step_variables = StepVariables {
-                        response_body: step_results.last().unwrap().response.clone().unwrap().body,
-                    };
-                    story_variables
-                        .steps
-                        .insert(step.name.clone(), step_variables);
-                    app_state
-                        .metrics
-                        .duration
-                        .record(time_since(&timestamp_started), &step_tags);
+        // Story expectations only make sense once every step has run
+        let expectation_results = match (&self.expectations, story_success) {
+            (Some(expectations), true) => {
+                let results: Vec<_> = expectations
+                    .iter()
+                    .map(|expectation| {
+                        evaluate_story_expectation(expectation, &run.captures, self.is_sensitive())
+                    })
+                    .collect();
+                for failed in results.iter().filter(|result| !result.success) {
+                    root_cx.span().add_event(
+                        "expectation.failed",
+                        vec![
+                            KeyValue::new("expectation.kind", "Story"),
+                            KeyValue::new("expected", failed.expr.clone()),
+                            KeyValue::new(
+                                "actual",
+                                failed
+                                    .evaluated
+                                    .clone()
+                                    .unwrap_or_else(|| "<redacted>".to_owned()),
+                            ),
+                        ],
+                    );
                 }
-                Err(e) => {
-                    error!("Error calling endpoint: {}", e);
-                    app_state.metrics.http_status_code.record(0, &step_tags);
-                    trace::get_active_span(|span| {
-                        span.record_error(&*e);
-                    });
-                    step_results.push(StepResult {
-                        step_name: step.name.clone(),
-                        success: false,
-                        error_message: Some(e.to_string()),
-                        timestamp_started: Utc::now(),
-                        response: None,
-                        trace_id: None,
-                        span_id: None,
-                    });
-                    app_state
-                        .metrics
-                        .duration
-                        .record(time_since(&timestamp_started), &step_tags);
-                    break;
+                if let Some(failed) = results.iter().find(|result| !result.success) {
+                    story_success = false;
+                    error_message = failed.error_message.clone();
                 }
-            };
+                Some(results)
+            }
+            _ => None,
+        };
+        // Teardown always runs, its failures are logged but leave the story's status alone
+        let mut teardown_results = vec![];
+        for step in self.teardown.iter().flatten() {
+            let step_result = self.run_step(&app_state, &mut run, step).await;
+            if !step_result.success {
+                warn!(
+                    "Teardown step {} of story {} failed, story_run_id: {}: {}",
+                    step.name,
+                    self.name,
+                    story_run_id,
+                    step_result.error_message.as_deref().unwrap_or_default()
+                );
+            }
+            teardown_results.push(step_result);
+        }
+        if let Some(kind) = error_kind(story_success, last_step.response.is_some()) {
+            set_error_status(&root_cx.span(), kind);
         }
-        let last_step = step_results.last().unwrap();
-        let story_success = last_step.success;
         if !story_success {
             app_state.metrics.errors.add(1, &story_attributes);
         } else {
             app_state.metrics.errors.add(0, &story_attributes);
         }
-        app_state
-            .metrics
-            .duration
-            .record(time_since(&timestamp_started), &story_attributes);
+        let during_reload = app_state.overlaps_reload(timestamp_started);
+        let ignored = app_state.ignores_result(during_reload);
+        let mut closed_incident = None;
+        if !ignored {
+            let monitor_state = app_state.record_monitor_run(
+                &self.name,
+                story_success,
+                self.recovery_threshold
+                    .unwrap_or(DEFAULT_RECOVERY_THRESHOLD),
+            );
+            app_state.metrics.record_status(
+                &self.name,
+                MonitorStatus::from_failing(monitor_state.failing).as_u64(),
+                &story_attributes,
+            );
+            closed_incident = app_state.update_incident(
+                &self.name,
+                story_success,
+                error_message.as_deref(),
+                timestamp_started,
+            );
+        }
+        app_state.metrics.record_run_duration(
+            time_since(&timestamp_started),
+            &story_attributes,
+            sampled_trace_id(&root_cx),
+        );
 
         info!(
-            "Finished scheduled story {}, success: {}",
-            &self.name, story_success
+            "Finished scheduled story {}, story_run_id: {}, success: {}",
+            &self.name, story_run_id, story_success
         );
 
+        // Channel references are resolved per run, so they follow reloads of `alert_channels`
+        let alerts = app_state
+            .config
+            .read()
+            .unwrap()
+            .resolve_alerts(&self.alerts);
+        // Runs ignored because they overlapped a reload never alert
         let send_alert_result = alert_if_failure(
-            story_success,
-            last_step.error_message.as_deref(),
-            last_step.response.as_ref(),
-            &self.name,
-            timestamp_started,
-            &self.alerts,
-            &last_step.trace_id,
+            story_success || ignored,
+            RunFailure {
+                monitor_name: &self.name,
+                run_id: Some(story_run_id),
+                trace_id: &last_step.trace_id,
+                timestamp: timestamp_started,
+                error: error_message.as_deref(),
+                note: None,
+                response: last_step.response.as_ref(),
+            },
+            &alerts,
+            self.redacts_alerts(),
         )
         .await;
         if let Err(e) = send_alert_result {
-            for error in e {
-                error!("Error sending out alert: {}", error);
+            record_alert_errors(&app_state, e);
+        }
+        if let Some(incident) = closed_incident {
+            if let Err(e) = alert_on_recovery(&incident, &alerts, self.redacts_alerts()).await {
+                record_alert_errors(&app_state, e);
             }
         }
         let story_result = StoryResult {
+            story_run_id,
             story_name: self.name.clone(),
             timestamp_started,
             success: story_success,
+            duration: step_results
+                .iter()
+                .filter_map(|step| step.response.as_ref())
+                .next_back()
+                .map(|response| duration::between(timestamp_started, response.timestamp_received)),
             step_results,
+            teardown_results,
+            expectations: expectation_results,
+            during_reload,
         };
 
         app_state.add_story_result(self.name.clone(), story_result);
@@ -232,158 +532,830 @@ impl Monitorable for Story {
     }
 }
 
-impl Monitorable for Probe {
-    async fn probe_and_store_result(&self, app_state: Arc<AppState>) {
-        let probe_attributes = [
-            KeyValue::new("name", self.name.clone()),
-            KeyValue::new("type", "probe"),
-        ]
-        .into_iter()
-        .chain(self.tags.iter().flat_map(|tags| {
-            tags.iter()
-                .map(|(k, v)| KeyValue::new(k.clone(), v.clone()))
-        }))
-        .collect::<Vec<_>>();
-        app_state.metrics.runs.add(1, &probe_attributes);
+impl Probe {
+    // Attempts share the run, rate limited ones aren't retried since the server asked to back off
+    async fn run_attempts(
+        &self,
+        app_state: &AppState,
+        root_cx: &Context,
+        probe_attributes: &[KeyValue],
+        run_id: Uuid,
+    ) -> ProbeResult {
+        let mut retry_reasons = vec![];
+        let mut probe_result = loop {
+            let attempt = match self.probe_type {
+                ProbeType::Http if self.is_full_stack() => {
+                    self.run_full_stack(app_state, root_cx, probe_attributes, run_id)
+                        .await
+                }
+                ProbeType::Http => match self.samples() {
+                    Some(samples) => {
+                        self.run_samples(samples, app_state, root_cx, probe_attributes, run_id)
+                            .await
+                    }
+                    None => {
+                        self.run_http(app_state, root_cx, probe_attributes, run_id)
+                            .await
+                    }
+                },
+                ProbeType::Smtp => self.run_smtp(root_cx, run_id).await,
+                ProbeType::Ntp => {
+                    self.run_ntp(app_state, root_cx, probe_attributes, run_id)
+                        .await
+                }
+                ProbeType::Sftp => self.run_sftp(root_cx, run_id).await,
+            };
+            if attempt.success
+                || attempt.rate_limited
+                || retry_reasons.len() as u32 >= self.retries.unwrap_or(0)
+            {
+                break attempt;
+            }
+            retry_reasons.push(
+                attempt
+                    .error_message
+                    .unwrap_or_else(|| "unknown error".to_owned()),
+            );
+        };
+        probe_result.attempts = retry_reasons.len() as u32 + 1;
+        probe_result.retry_reasons = retry_reasons;
+        if probe_result.success && probe_result.attempts > 1 {
+            app_state.metrics.probe_retries.add(1, probe_attributes);
+        }
+        probe_result
+    }
 
-        let root_span = global::tracer("probe_logic").start(self.name.clone());
+    // Checks the `urls` in turn, storing each result under `<probe>:<index>`. The result of the
+    // probe itself is the first failed one, or the last one when they all succeeded.
+    async fn run_each_url(
+        &self,
+        urls: &[String],
+        app_state: &AppState,
+        root_cx: &Context,
+        probe_attributes: &[KeyValue],
+        run_id: Uuid,
+    ) -> ProbeResult {
+        let mut results = vec![];
+        for (index, url) in urls.iter().enumerate() {
+            let mut result = self
+                .for_url(url)
+                .run_attempts(app_state, root_cx, probe_attributes, run_id)
+                .await;
+            result.probe_name = url_result_name(&self.name, index);
+            result.during_reload = app_state.overlaps_reload(result.timestamp_started);
+            app_state.add_probe_result(result.probe_name.clone(), result.clone());
+            results.push(result);
+        }
+        let failed = results.iter().filter(|result| !result.success).count();
+        let timestamp_started = results[0].timestamp_started;
+        let duration = results.iter().map(|result| result.duration).sum();
+        let (index, mut probe_result) = results
+            .into_iter()
+            .enumerate()
+            .reduce(|kept, (index, result)| {
+                if kept.1.success {
+                    (index, result)
+                } else {
+                    kept
+                }
+            })
+            .unwrap();
+        if !probe_result.success {
+            probe_result.error_message = Some(format!(
+                "{} of {} urls failed, {} ({}): {}",
+                failed,
+                urls.len(),
+                url_result_name(&self.name, index),
+                urls[index],
+                probe_result
+                    .error_message
+                    .as_deref()
+                    .unwrap_or("unknown error")
+            ));
+        }
+        probe_result.probe_name = self.name.clone();
+        probe_result.timestamp_started = timestamp_started;
+        probe_result.duration = duration;
+        probe_result
+    }
 
-        let root_cx = Context::default().with_span(root_span);
-        let call_endpoint_result =
-            call_endpoint(&self.http_method, &self.url, &self.with, self.sensitive)
-                .with_context(root_cx.clone())
+    // DNS, the TLS handshake to the first resolved address, then the regular http run. The first
+    // phase that fails ends the run, its error names the state of each phase.
+    async fn run_full_stack(
+        &self,
+        app_state: &AppState,
+        root_cx: &Context,
+        probe_attributes: &[KeyValue],
+        run_id: Uuid,
+    ) -> ProbeResult {
+        let timestamp_started = Utc::now();
+        let params = self.full_stack.clone().unwrap_or_default();
+        let mut details = check_dns_and_tls(&self.url, &params).await;
+        let connected = details.phases.iter().all(|phase| phase.success);
+        let mut probe_result = if connected {
+            let http_result = self
+                .run_http(app_state, root_cx, probe_attributes, run_id)
                 .await;
+            details.phases.push(FullStackPhase {
+                name: "http".to_owned(),
+                success: http_result.success,
+                duration_ms: http_result
+                    .duration
+                    .map_or(0, |duration| duration.as_millis() as u64),
+                error: http_result.error_message.clone(),
+            });
+            http_result
+        } else {
+            ProbeResult {
+                duration: Some(time_since(&timestamp_started)),
+                trace_id: Some(root_cx.span().span_context().trace_id().to_string()),
+                ..ProbeResult::new(run_id, self.name.clone(), timestamp_started, false)
+            }
+        };
+
+        if let Some(failed) = details.phases.iter().find(|phase| !phase.success) {
+            let status_code = probe_result
+                .response
+                .as_ref()
+                .map(|response| response.status_code);
+            let message = format!(
+                "{}: {}",
+                details.summary(status_code),
+                failed.error.as_deref().unwrap_or("unknown error")
+            );
+            if failed.name != "http" {
+                error!(
+                    "Error checking probe {} for run {}: {}",
+                    self.name, run_id, message
+                );
+            }
+            root_cx.span().set_attribute(KeyValue::new(
+                "full_stack.failed_phase",
+                failed.name.clone(),
+            ));
+            probe_result.failed_phase = Some(failed.name.clone());
+            probe_result.error_message = Some(message);
+        }
+        let timings = details.phases.iter().map(|phase| PhaseTiming {
+            name: phase.name.clone(),
+            duration_ms: phase.duration_ms,
+        });
+        probe_result.phases = Some(
+            timings
+                .chain(probe_result.phases.take().unwrap_or_default())
+                .collect(),
+        );
+        probe_result.tls = details.certificate.as_ref().map(|certificate| TlsDetails {
+            certificate_not_after: Some(certificate.not_after),
+        });
+        probe_result.timestamp_started = timestamp_started;
+        probe_result.duration = Some(time_since(&timestamp_started));
+        probe_result.full_stack = Some(details);
+        probe_result
+    }
+
+    // Each sample is a request of its own, so it takes its own `max_in_flight` slot rather than
+    // sharing the run's
+    async fn run_samples(
+        &self,
+        samples: &Samples,
+        app_state: &AppState,
+        root_cx: &Context,
+        probe_attributes: &[KeyValue],
+        run_id: Uuid,
+    ) -> ProbeResult {
+        let semaphore = self
+            .in_flight_limit(app_state)
+            .map(|limit| app_state.in_flight_semaphore(&self.name, limit));
+        let delay_between = samples.delay_between();
+        let results: Vec<ProbeResult> = stream::iter(0..samples.count)
+            .map(|index| {
+                let semaphore = semaphore.clone();
+                async move {
+                    if index > 0 && !delay_between.is_zero() {
+                        tokio::time::sleep(delay_between).await;
+                    }
+                    let _permit = match semaphore {
+                        Some(semaphore) => semaphore.acquire_owned().await.ok(),
+                        None => None,
+                    };
+                    self.run_http(app_state, root_cx, probe_attributes, run_id)
+                        .await
+                }
+            })
+            .buffered(samples.concurrency() as usize)
+            .collect()
+            .await;
+        for duration in results.iter().filter_map(|result| result.duration) {
+            app_state
+                .metrics
+                .record_sample_duration(duration, probe_attributes);
+        }
+        aggregate(samples, results)
+    }
+
+    // `max_in_flight` of the probe, or of `settings` for all probes
+    fn in_flight_limit(&self, app_state: &AppState) -> Option<u32> {
+        self.max_in_flight
+            .or_else(|| app_state.config.read().unwrap().settings.max_in_flight)
+    }
+
+    async fn run_http(
+        &self,
+        app_state: &AppState,
+        root_cx: &Context,
+        probe_attributes: &[KeyValue],
+        run_id: Uuid,
+    ) -> ProbeResult {
+        let prefetched = match self.prefetch() {
+            Some(prefetch) => {
+                match self
+                    .run_prefetch(prefetch, app_state, root_cx, probe_attributes, run_id)
+                    .await
+                {
+                    Ok(prefetched) => Some(prefetched),
+                    Err(failed) => return failed,
+                }
+            }
+            None => None,
+        };
+        let prefetched_with = match (&prefetched, &self.with) {
+            (Some(prefetched), Some(with)) => Some(prefetched.apply(with)),
+            _ => None,
+        };
+        let with = match prefetched_with {
+            Some(_) => &prefetched_with,
+            None => &self.with,
+        };
+        let audit = AuditScope {
+            app_state,
+            monitor: &self.name,
+            step: None,
+        };
+        let call_endpoint_result = call_endpoint(
+            &self.http_method,
+            &self.url,
+            with,
+            self.sensitive,
+            Some(audit),
+        )
+        .with_context(root_cx.clone())
+        .await;
 
-        let probe_result = match call_endpoint_result {
+        match call_endpoint_result {
             Ok(endpoint_result) => {
+                app_state.metrics.record_http_status_code(
+                    &self.name,
+                    endpoint_result.status_code.into(),
+                    probe_attributes,
+                );
                 app_state
                     .metrics
-                    .http_status_code
-                    .record(endpoint_result.status_code.into(), &probe_attributes);
+                    .record_http_timings(&endpoint_result.timings, probe_attributes);
+                let rate_limited = self.note_rate_limit(app_state, &endpoint_result, run_id);
                 let probe_response = endpoint_result.to_probe_response();
-                let expectations_result = validate_response(
+                let (success_statuses, connection) = {
+                    let config = app_state.config.read().unwrap();
+                    (
+                        self.success_statuses(&config.settings)
+                            .map(|statuses| statuses.to_vec()),
+                        ConnectionDetails::capture(
+                            &endpoint_result,
+                            &config.settings.capture_headers(),
+                        ),
+                    )
+                };
+                self.record_connection(app_state, root_cx, probe_attributes, &connection);
+                let response_meta = endpoint_result.meta();
+                let duration = response_meta.duration;
+                let expectations_result = evaluate_expectations(
+                    app_state,
                     &self.name,
                     endpoint_result.status_code,
                     endpoint_result.body,
+                    response_meta,
                     &self.expectations,
-                );
+                    success_statuses,
+                )
+                .await;
 
                 if let Err(err) = expectations_result.as_ref() {
                     root_cx.span().record_error(&err);
+                    record_expectation_failure(&root_cx.span(), err, self.sensitive);
                 }
 
-                let mut monitor_status = MonitorStatus::Ok.as_u64();
-                if expectations_result.is_err() {
-                    monitor_status = MonitorStatus::Error.as_u64();
-                }
-                app_state
-                    .metrics
-                    .status
-                    .record(monitor_status, &probe_attributes);
+                // The prefetch only counts towards the run when configured to
+                let (timestamp_started, duration) = match (&prefetched, self.prefetch()) {
+                    (Some(prefetched), Some(prefetch)) if prefetch.include_in_duration => {
+                        (prefetched.timestamp_started, prefetched.duration + duration)
+                    }
+                    _ => (endpoint_result.timestamp_request_started, duration),
+                };
+                let phases = prefetched
+                    .iter()
+                    .map(|prefetched| prefetch_phase(prefetched.duration))
+                    .chain(endpoint_result.timings.phases())
+                    .collect();
 
+                let success = expectations_result.is_ok();
                 ProbeResult {
-                    probe_name: self.name.clone(),
-                    timestamp_started: endpoint_result.timestamp_request_started,
-                    success: expectations_result.is_ok(),
-                    error_message: expectations_result.err().map(|e| e.to_string()),
+                    error_message: expectations_result.as_ref().err().map(|e| e.to_string()),
                     response: Some(probe_response),
+                    duration: Some(duration),
                     trace_id: Some(endpoint_result.trace_id),
+                    phases: Some(phases),
+                    failed_expectation: expectations_result
+                        .err()
+                        .map(|e| failed_expectation(&e, self.sensitive)),
+                    connection: Some(connection),
+                    rate_limited,
+                    ..ProbeResult::new(run_id, self.name.clone(), timestamp_started, success)
                 }
             }
             Err(e) => {
                 app_state
                     .metrics
-                    .http_status_code
-                    .record(0, &probe_attributes);
-                app_state
-                    .metrics
-                    .status
-                    .record(MonitorStatus::Error.as_u64(), &probe_attributes);
-                error!("Error calling endpoint: {}", e);
+                    .record_http_status_code(&self.name, 0, probe_attributes);
+                error!("Error calling endpoint for run {}: {}", run_id, e);
                 root_cx.span().record_error(&*e);
                 ProbeResult {
-                    success: false,
-                    probe_name: self.name.clone(),
-                    timestamp_started: Utc::now(),
                     error_message: Some(e.to_string()),
-                    response: None,
-                    trace_id: None,
+                    phases: prefetched.map(|prefetched| vec![prefetch_phase(prefetched.duration)]),
+                    ..ProbeResult::new(run_id, self.name.clone(), Utc::now(), false)
                 }
             }
-        };
+        }
+    }
 
-        if probe_result.success {
-            app_state.metrics.errors.add(0, &probe_attributes);
-            root_cx.span().set_status(Status::Ok);
-        } else {
-            app_state.metrics.errors.add(1, &probe_attributes);
-            root_cx.span().set_status(Status::Error {
-                description: "Expectation failed".into(),
-            });
+    // The values and cookies of the probe's `with.prefetch`, or the failed result of the run when the
+    // prefetch didn't get them
+    async fn run_prefetch(
+        &self,
+        prefetch: &Prefetch,
+        app_state: &AppState,
+        root_cx: &Context,
+        probe_attributes: &[KeyValue],
+        run_id: Uuid,
+    ) -> Result<Prefetched, ProbeResult> {
+        let audit = AuditScope {
+            app_state,
+            monitor: &self.name,
+            step: Some(PREFETCH_PHASE),
+        };
+        let fetched = fetch(prefetch, self.with.as_ref(), self.sensitive, Some(audit))
+            .with_context(root_cx.clone())
+            .await;
+        let duration = match &fetched {
+            Ok(prefetched) => Some(prefetched.duration),
+            Err(e) => e.duration,
+        };
+        if let Some(duration) = duration {
+            app_state
+                .metrics
+                .record_prefetch_duration(duration, probe_attributes);
         }
-        let timestamp = probe_result.timestamp_started;
+        let e = match fetched {
+            Ok(prefetched) => return Ok(prefetched),
+            Err(e) => e,
+        };
 
         app_state
             .metrics
-            .duration
-            .record(time_since(&timestamp), &probe_attributes);
+            .record_http_status_code(&self.name, 0, probe_attributes);
+        error!("Prefetch failed for run {}: {}", run_id, e.message);
+        root_cx.span().add_event(
+            "prefetch.failed",
+            vec![KeyValue::new("error.message", e.message.clone())],
+        );
+        Err(ProbeResult {
+            error_message: Some(e.message),
+            phases: e.duration.map(|duration| vec![prefetch_phase(duration)]),
+            failed_phase: Some(PREFETCH_PHASE.to_owned()),
+            ..ProbeResult::new(run_id, self.name.clone(), e.timestamp_started, false)
+        })
+    }
 
+    // With `respect_retry_after`, defers the next runs when the response asks to back off and
+    // lifts the deferral once it doesn't. Returns whether the run was rate limited.
+    fn note_rate_limit(
+        &self,
+        app_state: &AppState,
+        endpoint_result: &EndpointResult,
+        run_id: Uuid,
+    ) -> bool {
+        if !self
+            .with
+            .as_ref()
+            .is_some_and(|with| with.respect_retry_after)
+        {
+            return false;
+        }
+        let retry_after = endpoint_result
+            .headers
+            .get("retry-after")
+            .map(String::as_str);
+        if !is_rate_limited(endpoint_result.status_code, retry_after) {
+            app_state.clear_deferral(&self.name);
+            return false;
+        }
+        let deferral = app_state.defer(
+            &self.name,
+            retry_after.and_then(|value| parse_retry_after(value, Utc::now())),
+            self.schedule.interval,
+        );
         info!(
-            "Finished scheduled probe {}, success: {}",
-            &self.name, probe_result.success,
+            "Probe {} was rate limited in run {}, next run not before {}",
+            self.name, run_id, deferral.until
         );
+        true
+    }
 
-        let send_alert_result = alert_if_failure(
-            probe_result.success,
-            probe_result.error_message.as_deref(),
-            probe_result.response.as_ref(),
-            &self.name,
-            timestamp,
-            &self.alerts,
-            &probe_result.trace_id,
-        )
-        .await;
-        if let Err(e) = send_alert_result {
-            for error in e {
-                error!("Error sending out alert: {}", error);
-            }
+    // Puts the connection details on the root span, and logs when the probe reached a different
+    // IP than its previous run
+    fn record_connection(
+        &self,
+        app_state: &AppState,
+        root_cx: &Context,
+        probe_attributes: &[KeyValue],
+        connection: &ConnectionDetails,
+    ) {
+        let span = root_cx.span();
+        if let Some(remote_addr) = connection.remote_addr {
+            span.set_attribute(KeyValue::new(
+                semconv::attribute::NETWORK_PEER_ADDRESS,
+                remote_addr.ip().to_string(),
+            ));
+            span.set_attribute(KeyValue::new(
+                semconv::attribute::NETWORK_PEER_PORT,
+                remote_addr.port() as i64,
+            ));
+        }
+        for (name, value) in &connection.headers {
+            span.set_attribute(KeyValue::new(
+                format!("http.response.header.{}", name),
+                value.clone(),
+            ));
         }
-        app_state.add_probe_result(self.name.clone(), probe_result);
-    }
 
-    fn get_name(&self) -> String {
-        self.name.clone()
+        if !app_state
+            .config
+            .read()
+            .unwrap()
+            .settings
+            .log_backend_changes
+        {
+            return;
+        }
+        let Some(current) = connection.remote_addr.map(|addr| addr.ip()) else {
+            return;
+        };
+        let previous = app_state
+            .probe_results
+            .read(&self.name, |results| {
+                results
+                    .iter()
+                    .rev()
+                    .find_map(|result| result.connection.as_ref()?.remote_addr)
+            })
+            .flatten()
+            .map(|addr| addr.ip());
+        if let Some(previous) = previous.filter(|previous| *previous != current) {
+            info!(
+                "Backend changed for probe {}: {} -> {}",
+                self.name, previous, current
+            );
+            app_state.metrics.backend_changes.add(1, probe_attributes);
+        }
     }
-    fn get_schedule(&self) -> &ProbeScheduleParameters {
-        &self.schedule
+
+    async fn run_smtp(&self, root_cx: &Context, run_id: Uuid) -> ProbeResult {
+        let timestamp_started = Utc::now();
+        let timeout = self
+            .with
+            .as_ref()
+            .and_then(|params| params.timeout())
+            .unwrap_or(Duration::from_secs(DEFAULT_REQUEST_TIMEOUT_SECS));
+        let params = self.smtp.clone().unwrap_or_default();
+        let outcome = check_smtp(&self.url, &params, timeout).await;
+        root_cx.span().set_attribute(KeyValue::new(
+            "smtp.supports_starttls",
+            outcome.supports_starttls,
+        ));
+
+        if let Some(err) = outcome.error.as_ref() {
+            error!("Error checking SMTP server for run {}: {}", run_id, err);
+            root_cx.span().record_error(err);
+        }
+        let span_context = root_cx.span().span_context().clone();
+        let success = outcome.error.is_none();
+
+        ProbeResult {
+            error_message: outcome.error.as_ref().map(|e| e.to_string()),
+            duration: Some(time_since(&timestamp_started)),
+            trace_id: Some(span_context.trace_id().to_string()),
+            phases: Some(outcome.phases),
+            failed_phase: outcome.error.map(|e| e.phase),
+            tls: outcome.tls,
+            ..ProbeResult::new(run_id, self.name.clone(), timestamp_started, success)
+        }
     }
-}
 
-#[cfg(test)]
-mod probe_logic_tests {
+    async fn run_ntp(
+        &self,
+        app_state: &AppState,
+        root_cx: &Context,
+        probe_attributes: &[KeyValue],
+        run_id: Uuid,
+    ) -> ProbeResult {
+        let timestamp_started = Utc::now();
+        let timeout = self
+            .with
+            .as_ref()
+            .and_then(|params| params.timeout())
+            .unwrap_or(Duration::from_secs(DEFAULT_REQUEST_TIMEOUT_SECS));
+        let params = self.ntp.clone().unwrap_or_default();
+        let outcome = check_ntp(&self.url, &params, timeout).await;
+        if let Some(offset) = outcome.details.offset_ms {
+            app_state
+                .metrics
+                .clock_offset_ms
+                .record(offset, probe_attributes);
+            root_cx
+                .span()
+                .set_attribute(KeyValue::new("ntp.offset_ms", offset));
+        }
 
-    use std::collections::HashMap;
-    use std::sync::Arc;
+        if let Some(err) = outcome.error.as_ref() {
+            error!("Error checking NTP server for run {}: {}", run_id, err);
+            root_cx.span().record_error(err);
+        }
+        let span_context = root_cx.span().span_context().clone();
 
-    use crate::app_state::AppState;
-    use crate::config::Config;
-    use crate::probe::model::{
-        ExpectField, ExpectOperation, ProbeAlert, ProbeExpectation, ProbeInputParameters,
-        ProbeScheduleParameters, Step, Story,
-    };
-    use crate::probe::probe_logic::Monitorable;
-    use wiremock::matchers::{header, method, path};
-    use wiremock::{Mock, MockServer, ResponseTemplate};
+        ProbeResult {
+            error_message: outcome.error.as_ref().map(|e| e.to_string()),
+            duration: Some(time_since(&timestamp_started)),
+            trace_id: Some(span_context.trace_id().to_string()),
+            ntp: Some(outcome.details),
+            ..ProbeResult::new(
+                run_id,
+                self.name.clone(),
+                timestamp_started,
+                outcome.error.is_none(),
+            )
+        }
+    }
 
-    #[tokio::test]
-    async fn test_story_success() {
-        let mock_server = MockServer::start().await;
-        let step1_path = "/test1";
+    async fn run_sftp(&self, root_cx: &Context, run_id: Uuid) -> ProbeResult {
+        let timestamp_started = Utc::now();
+        let timeout = self
+            .with
+            .as_ref()
+            .and_then(|params| params.timeout())
+            .unwrap_or(Duration::from_secs(DEFAULT_REQUEST_TIMEOUT_SECS));
+        let params = self.sftp.clone().unwrap_or_default();
+        let outcome = check_sftp(&self.url, &params, timeout).await;
+        if let Some(file) = outcome.details.matched_file.as_ref() {
+            root_cx
+                .span()
+                .set_attribute(KeyValue::new("sftp.matched_file", file.clone()));
+        }
+
+        if let Some(err) = outcome.error.as_ref() {
+            error!("Error checking SFTP server for run {}: {}", run_id, err);
+            root_cx.span().record_error(err);
+        }
+        let span_context = root_cx.span().span_context().clone();
+        let success = outcome.error.is_none();
+
+        ProbeResult {
+            error_message: outcome.error.as_ref().map(|e| e.to_string()),
+            duration: Some(time_since(&timestamp_started)),
+            trace_id: Some(span_context.trace_id().to_string()),
+            phases: Some(outcome.phases),
+            failed_phase: outcome.error.map(|e| e.phase),
+            sftp: Some(outcome.details),
+            ..ProbeResult::new(run_id, self.name.clone(), timestamp_started, success)
+        }
+    }
+}
+
+impl Monitorable for Probe {
+    async fn probe_and_store_result(&self, app_state: Arc<AppState>) {
+        // Waits for a slot when `max_in_flight` runs of this probe are already in progress. Sampled
+        // runs take a slot per sample instead, see `run_samples`.
+        let max_in_flight = self
+            .in_flight_limit(&app_state)
+            .filter(|_| self.samples().is_none());
+        let _permit = match max_in_flight {
+            Some(limit) => app_state
+                .in_flight_semaphore(&self.name, limit)
+                .acquire_owned()
+                .await
+                .ok(),
+            None => None,
+        };
+
+        let probe_attributes = [
+            KeyValue::new("name", self.name.clone()),
+            KeyValue::new("type", "probe"),
+        ]
+        .into_iter()
+        .chain(self.tags.iter().flat_map(|tags| {
+            tags.iter()
+                .map(|(k, v)| KeyValue::new(k.clone(), v.clone()))
+        }))
+        .collect::<Vec<_>>();
+        app_state.metrics.runs.add(1, &probe_attributes);
+
+        let run_id = Uuid::new_v4();
+        let root_span = global::tracer("probe_logic")
+            .span_builder(self.name.clone())
+            .with_attributes([KeyValue::new("run_id", run_id.to_string())])
+            .start(&global::tracer("probe_logic"));
+
+        let root_cx = Context::default().with_span(root_span);
+        let mut probe_result = match &self.urls {
+            Some(urls) => {
+                self.run_each_url(urls, &app_state, &root_cx, &probe_attributes, run_id)
+                    .await
+            }
+            None => {
+                self.run_attempts(&app_state, &root_cx, &probe_attributes, run_id)
+                    .await
+            }
+        };
+
+        probe_result.during_reload = app_state.overlaps_reload(probe_result.timestamp_started);
+        // Rate limited runs say nothing about the probed service unless configured to
+        let ignored = app_state.ignores_result(probe_result.during_reload)
+            || (probe_result.rate_limited
+                && !app_state.config().settings.rate_limits.count_as_failure);
+        let mut closed_incident = None;
+        let mut circuit_note = None;
+        if !ignored {
+            if let Some(breaker) =
+                app_state.record_circuit_run(self, probe_result.success, &probe_attributes)
+            {
+                info!(
+                    "Circuit breaker of probe {} is now {:?}, probing every {}",
+                    &self.name,
+                    breaker.state,
+                    humantime::format_duration(breaker.interval)
+                );
+                if breaker.state == CircuitState::Open {
+                    circuit_note = Some(breaker.opened_note());
+                }
+            }
+            let monitor_state = app_state.record_monitor_run(
+                &self.name,
+                probe_result.success,
+                self.recovery_threshold
+                    .unwrap_or(DEFAULT_RECOVERY_THRESHOLD),
+            );
+            app_state.metrics.record_status(
+                &self.name,
+                MonitorStatus::from_failing(monitor_state.failing).as_u64(),
+                &probe_attributes,
+            );
+            closed_incident = app_state.update_incident(
+                &self.name,
+                probe_result.success,
+                probe_result.error_message.as_deref(),
+                probe_result.timestamp_started,
+            );
+        }
+
+        match probe_result.error_kind() {
+            None => {
+                app_state.metrics.errors.add(0, &probe_attributes);
+                root_cx.span().set_status(Status::Ok);
+            }
+            Some(kind) => {
+                // Phased probes attribute the error to the phase that failed
+                let mut error_attributes = probe_attributes.clone();
+                if let Some(phase) = &probe_result.failed_phase {
+                    error_attributes.push(KeyValue::new("phase", phase.clone()));
+                }
+                app_state.metrics.errors.add(1, &error_attributes);
+                set_error_status(&root_cx.span(), kind);
+            }
+        }
+        let timestamp = probe_result.timestamp_started;
+
+        // A sampled run records its aggregate, the p95 of its samples
+        match (self.samples(), probe_result.duration) {
+            (Some(samples), Some(aggregate)) => {
+                let mut sampled_attributes = probe_attributes.clone();
+                sampled_attributes.push(KeyValue::new("samples", samples.count as i64));
+                app_state.metrics.record_run_duration(
+                    aggregate,
+                    &sampled_attributes,
+                    sampled_trace_id(&root_cx),
+                );
+            }
+            _ => app_state.metrics.record_run_duration(
+                time_since(&timestamp),
+                &probe_attributes,
+                sampled_trace_id(&root_cx),
+            ),
+        }
+
+        info!(
+            "Finished scheduled probe {}, run_id: {}, success: {}",
+            &self.name, run_id, probe_result.success,
+        );
+
+        // Channel references are resolved per run, so they follow reloads of `alert_channels`
+        let alerts = app_state
+            .config
+            .read()
+            .unwrap()
+            .resolve_alerts(&self.alerts);
+        // Runs ignored because they overlapped a reload or were rate limited never alert
+        let send_alert_result = alert_if_failure(
+            probe_result.success || ignored,
+            RunFailure {
+                monitor_name: &self.name,
+                run_id: Some(run_id),
+                trace_id: &probe_result.trace_id,
+                timestamp,
+                error: probe_result.error_message.as_deref(),
+                note: circuit_note.as_deref(),
+                response: probe_result.response.as_ref(),
+            },
+            &alerts,
+            self.redacts_alerts(),
+        )
+        .await;
+        if let Err(e) = send_alert_result {
+            record_alert_errors(&app_state, e);
+        }
+        if let Some(incident) = closed_incident {
+            if let Err(e) = alert_on_recovery(&incident, &alerts, self.redacts_alerts()).await {
+                record_alert_errors(&app_state, e);
+            }
+        }
+        // A meta-probe keeps the probes it expanded into when a run fails or can't be read
+        if let (Some(path), Some(response)) = (&self.name_from_response, &probe_result.response) {
+            if probe_result.success {
+                match expanded_names(path, &response.body) {
+                    Ok(names) => app_state.sync_expanded_probes(self, &names),
+                    Err(e) => warn!("Not expanding '{}': {}", self.name, e),
+                }
+            }
+        }
+        if let (true, Some(response)) = (probe_result.success, &probe_result.response) {
+            record_body_metrics(&app_state, self, &response.body, &probe_attributes);
+        }
+        app_state.add_probe_result(self.name.clone(), probe_result);
+    }
+
+    fn get_name(&self) -> String {
+        self.name.clone()
+    }
+    fn get_schedule(&self) -> &ProbeScheduleParameters {
+        &self.schedule
+    }
+
+    fn enabled(&self, app_state: &AppState) -> bool {
+        app_state.probe_enabled(self)
+    }
+}
+
+#[cfg(test)]
+mod probe_logic_tests {
+
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use crate::app_state::AppState;
+    use crate::config::{Config, DurationUnit, RuntimeSettings, Settings};
+    use crate::otel::metrics::MetricsState;
+    use crate::probe::model::{
+        Capture, Composite, ExpectField, ExpectOperation, Extraction, HeaderCapture, Prefetch,
+        ProbeAlert, ProbeExpectation, ProbeOptions, ProbeScheduleParameters, Samples, Step, Story,
+        StoryExpectation,
+    };
+    use crate::probe::probe_logic::Monitorable;
+    use crate::test_utils::metrics_test_utils::{
+        counter_value, f64_gauge_value, histogram_sum, metric_unit,
+    };
+    use crate::test_utils::probe_test_utils::probe_get_with_expected_status;
+    use opentelemetry::KeyValue;
+    use wiremock::matchers::{body_partial_json, body_string, header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn test_story_success() {
+        let mock_server = MockServer::start().await;
+        let step1_path = "/test1";
         let step2_path = "/test2";
         let story_name = "User Flow";
         let app_state = Arc::new(AppState::new(Config {
             probes: vec![],
             stories: vec![],
+            ..Default::default()
         }));
 
         Mock::given(method("GET"))
@@ -402,6 +1374,8 @@ mod probe_logic_tests {
 
         let story = Story {
             name: story_name.to_owned(),
+            base_url: None,
+            setup: None,
             steps: vec![
                 Step {
                     name: "Step 1".to_owned(),
@@ -410,6 +1384,9 @@ mod probe_logic_tests {
                     http_method: "GET".to_owned(),
                     expectations: None,
                     sensitive: false,
+                    body: None,
+                    body_template: None,
+                    captures: None,
                 },
                 Step {
                     name: "Step 2".to_owned(),
@@ -418,24 +1395,42 @@ mod probe_logic_tests {
                     http_method: "GET".to_owned(),
                     expectations: None,
                     sensitive: false,
+                    body: None,
+                    body_template: None,
+                    captures: None,
                 },
             ],
+            teardown: None,
             schedule: ProbeScheduleParameters {
-                initial_delay: 0,
-                interval: 0,
+                initial_delay: Duration::ZERO,
+                interval: Duration::ZERO,
+                allow_fast: true,
             },
             tags: None,
+            group: None,
             alerts: None,
+            recovery_threshold: None,
+            expectations: None,
+            sensitive: false,
+            alerts_include_details: false,
+            runtime_added: false,
         };
 
         story.probe_and_store_result(app_state.clone()).await;
+        let requests = mock_server.received_requests().await.unwrap();
 
-        let story_result_map = app_state.story_results.read().unwrap();
-        let results = &story_result_map[story_name];
+        let results = app_state.story_results.recent(story_name).unwrap();
         assert_eq!(1, results.len());
         let story_result = &results[0];
         assert!(story_result.success);
         assert_eq!(2, story_result.step_results.len());
+        assert_eq!(2, requests.len());
+        for request in requests {
+            assert_eq!(
+                story_result.story_run_id.to_string(),
+                request.headers[&"x-story-run-id".parse().unwrap()].as_str()
+            );
+        }
     }
 
     #[tokio::test]
@@ -448,6 +1443,7 @@ mod probe_logic_tests {
         let app_state = Arc::new(AppState::new(Config {
             probes: vec![],
             stories: vec![],
+            ..Default::default()
         }));
 
         Mock::given(method("GET"))
@@ -466,6 +1462,8 @@ mod probe_logic_tests {
 
         let story = Story {
             name: story_name.to_owned(),
+            base_url: None,
+            setup: None,
             steps: vec![
                 Step {
                     name: "Step 1".to_owned(),
@@ -474,6 +1472,9 @@ mod probe_logic_tests {
                     http_method: "GET".to_owned(),
                     expectations: None,
                     sensitive: false,
+                    body: None,
+                    body_template: None,
+                    captures: None,
                 },
                 Step {
                     name: "Step 2".to_owned(),
@@ -486,22 +1487,33 @@ mod probe_logic_tests {
                         value: "200".to_owned(),
                     }]),
                     sensitive: false,
+                    body: None,
+                    body_template: None,
+                    captures: None,
                 },
             ],
+            teardown: None,
             schedule: ProbeScheduleParameters {
-                initial_delay: 0,
-                interval: 0,
+                initial_delay: Duration::ZERO,
+                interval: Duration::ZERO,
+                allow_fast: true,
             },
             alerts: Some(vec![ProbeAlert {
                 url: format!("{}{}", mock_server.uri(), alert_path.to_owned()),
+                ..Default::default()
             }]),
             tags: None,
+            group: None,
+            recovery_threshold: None,
+            expectations: None,
+            sensitive: false,
+            alerts_include_details: false,
+            runtime_added: false,
         };
 
         story.probe_and_store_result(app_state.clone()).await;
 
-        let story_result_map = app_state.story_results.read().unwrap();
-        let results = &story_result_map[story_name];
+        let results = app_state.story_results.recent(story_name).unwrap();
         assert_eq!(1, results.len());
         let story_result = &results[0];
         assert!(!story_result.success);
@@ -529,6 +1541,7 @@ mod probe_logic_tests {
         let app_state = Arc::new(AppState::new(Config {
             probes: vec![],
             stories: vec![],
+            ..Default::default()
         }));
 
         Mock::given(method("GET"))
@@ -548,6 +1561,8 @@ mod probe_logic_tests {
 
         let story = Story {
             name: story_name.to_owned(),
+            base_url: None,
+            setup: None,
             steps: vec![
                 Step {
                     name: "step1".to_owned(),
@@ -556,14 +1571,26 @@ mod probe_logic_tests {
                     http_method: "GET".to_owned(),
                     expectations: None,
                     sensitive: false,
+                    body: None,
+                    body_template: None,
+                    captures: None,
                 },
                 Step {
                     name: "Step 2".to_owned(),
                     url: format!("{}{}", mock_server.uri(), step2_path.to_owned()),
-                    with: Some(ProbeInputParameters {
+                    with: Some(ProbeOptions {
                         headers: Some(step2_headers),
                         body: Some(step2_body_str.to_owned()),
+                        timeout: None,
                         timeout_seconds: None,
+                        query: None,
+                        auth: None,
+                        user_agent: None,
+                        respect_retry_after: false,
+                        follow_redirects: false,
+                        allow_empty: None,
+                        samples: None,
+                        prefetch: None,
                     }),
                     http_method: "POST".to_owned(),
                     expectations: Some(vec![ProbeExpectation {
@@ -572,23 +1599,1131 @@ mod probe_logic_tests {
                         value: "200".to_owned(),
                     }]),
                     sensitive: false,
+                    body: None,
+                    body_template: None,
+                    captures: None,
                 },
             ],
+            teardown: None,
             schedule: ProbeScheduleParameters {
-                initial_delay: 0,
-                interval: 0,
+                initial_delay: Duration::ZERO,
+                interval: Duration::ZERO,
+                allow_fast: true,
             },
             alerts: None,
             tags: None,
+            group: None,
+            recovery_threshold: None,
+            expectations: None,
+            sensitive: false,
+            alerts_include_details: false,
+            runtime_added: false,
         };
 
         story.probe_and_store_result(app_state.clone()).await;
 
-        let story_result_map = app_state.story_results.read().unwrap();
-        let results = &story_result_map[story_name];
+        let results = app_state.story_results.recent(story_name).unwrap();
         assert_eq!(1, results.len());
         let story_result = &results[0];
         assert!(story_result.success);
         assert_eq!(2, story_result.step_results.len());
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn test_story_expectations_use_captures_of_all_steps() {
+        let mock_server = MockServer::start().await;
+        for (step_path, body) in [
+            ("/cart1", r#"{"total": 10}"#),
+            ("/cart2", r#"{"total": 20.5}"#),
+            ("/invoice", r#"{"invoice": {"total": 31}}"#),
+        ] {
+            Mock::given(method("GET"))
+                .and(path(step_path))
+                .respond_with(ResponseTemplate::new(200).set_body_string(body))
+                .mount(&mock_server)
+                .await;
+        }
+        let step = |name: &str, capture: &str, capture_path: &str| Step {
+            name: name.to_owned(),
+            url: format!("{}/{}", mock_server.uri(), name),
+            with: None,
+            http_method: "GET".to_owned(),
+            expectations: None,
+            sensitive: false,
+            body: None,
+            body_template: None,
+            captures: Some(HashMap::from([(
+                capture.to_owned(),
+                Capture::Path(capture_path.to_owned()),
+            )])),
+        };
+
+        let story = Story {
+            name: "checkout".to_owned(),
+            base_url: None,
+            setup: None,
+            steps: vec![
+                step("cart1", "cart1_total", "total"),
+                step("cart2", "cart2_total", "total"),
+                step("invoice", "invoice_total", "invoice.total"),
+            ],
+            teardown: None,
+            schedule: ProbeScheduleParameters {
+                initial_delay: Duration::ZERO,
+                interval: Duration::ZERO,
+                allow_fast: true,
+            },
+            alerts: None,
+            tags: None,
+            group: None,
+            recovery_threshold: None,
+            expectations: Some(vec![StoryExpectation {
+                expr: "steps.cart1_total + steps.cart2_total == steps.invoice_total".to_owned(),
+            }]),
+            sensitive: false,
+            alerts_include_details: false,
+            runtime_added: false,
+        };
+        let app_state = Arc::new(AppState::new(Config::default()));
+
+        story.probe_and_store_result(app_state.clone()).await;
+
+        let story_results = app_state.story_results.recent("checkout").unwrap();
+        let story_result = &story_results[0];
+        assert!(!story_result.success);
+        assert!(story_result.step_results.iter().all(|step| step.success));
+        let expectation = &story_result.expectations.as_ref().unwrap()[0];
+        assert_eq!(Some("10 + 20.5 == 31".to_owned()), expectation.evaluated);
+    }
+
+    #[tokio::test]
+    async fn test_story_captures_response_headers() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/orders"))
+            .respond_with(
+                ResponseTemplate::new(201)
+                    .insert_header("Location", "/api/orders/42")
+                    .insert_header("X-Request-Id", "req-1"),
+            )
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/orders/42"))
+            .and(header("X-Request-Id", "req-1"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        let header_capture = |name: &str, resolve_relative: bool| {
+            Capture::Header(HeaderCapture {
+                header: name.to_owned(),
+                default: None,
+                resolve_relative,
+            })
+        };
+        let step = |name: &str, http_method: &str, url: String| Step {
+            name: name.to_owned(),
+            url,
+            with: None,
+            http_method: http_method.to_owned(),
+            expectations: None,
+            sensitive: false,
+            body: None,
+            body_template: None,
+            captures: None,
+        };
+        let mut story = Story {
+            name: "orders".to_owned(),
+            base_url: None,
+            setup: None,
+            steps: vec![
+                Step {
+                    captures: Some(HashMap::from([
+                        ("created_url".to_owned(), header_capture("Location", true)),
+                        ("req_id".to_owned(), header_capture("X-Request-Id", false)),
+                    ])),
+                    ..step(
+                        "create",
+                        "POST",
+                        format!("{}/api/orders", mock_server.uri()),
+                    )
+                },
+                Step {
+                    with: Some(ProbeOptions {
+                        headers: Some(HashMap::from([(
+                            "X-Request-Id".to_owned(),
+                            "${{steps.create.captures.req_id}}".to_owned(),
+                        )])),
+                        ..Default::default()
+                    }),
+                    ..step(
+                        "fetch",
+                        "GET",
+                        "${{steps.create.captures.created_url}}".to_owned(),
+                    )
+                },
+            ],
+            teardown: None,
+            schedule: ProbeScheduleParameters {
+                initial_delay: Duration::ZERO,
+                interval: Duration::ZERO,
+                allow_fast: true,
+            },
+            alerts: None,
+            tags: None,
+            group: None,
+            recovery_threshold: None,
+            expectations: None,
+            sensitive: false,
+            alerts_include_details: false,
+            runtime_added: false,
+        };
+        let app_state = Arc::new(AppState::new(Config::default()));
+
+        story.probe_and_store_result(app_state.clone()).await;
+
+        let story_result = app_state.story_results.latest("orders").unwrap();
+        assert!(story_result.success);
+        let captures = story_result.step_results[0].captures.as_ref().unwrap();
+        assert_eq!(
+            Some(&serde_json::Value::from(format!(
+                "{}/api/orders/42",
+                mock_server.uri()
+            ))),
+            captures.get("created_url")
+        );
+
+        story.steps[0].captures = Some(HashMap::from([(
+            "trace".to_owned(),
+            header_capture("X-Trace", false),
+        )]));
+        story.probe_and_store_result(app_state.clone()).await;
+
+        let story_result = app_state.story_results.latest("orders").unwrap();
+        assert!(!story_result.success);
+        assert_eq!(
+            Some("capture 'trace': the response has no X-Trace header"),
+            story_result.step_results[0].error_message.as_deref()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_story_base_url_prefixes_relative_step_urls() {
+        let mock_server = MockServer::start().await;
+        let other_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/cart"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/status"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&other_server)
+            .await;
+        let step = |name: &str, url: String| Step {
+            name: name.to_owned(),
+            url,
+            with: None,
+            http_method: "GET".to_owned(),
+            expectations: None,
+            sensitive: false,
+            body: None,
+            body_template: None,
+            captures: None,
+        };
+        let story = Story {
+            name: "checkout".to_owned(),
+            base_url: Some(format!("{}/api/", mock_server.uri())),
+            setup: None,
+            steps: vec![
+                step("cart", "/cart".to_owned()),
+                step("status", format!("{}/status", other_server.uri())),
+            ],
+            teardown: None,
+            schedule: ProbeScheduleParameters {
+                initial_delay: Duration::ZERO,
+                interval: Duration::ZERO,
+                allow_fast: true,
+            },
+            tags: None,
+            group: None,
+            alerts: None,
+            recovery_threshold: None,
+            expectations: None,
+            sensitive: false,
+            alerts_include_details: false,
+            runtime_added: false,
+        };
+        let app_state = Arc::new(AppState::new(Config::default()));
+
+        story.probe_and_store_result(app_state.clone()).await;
+
+        assert!(app_state.story_results.recent("checkout").unwrap()[0].success);
+    }
+
+    #[tokio::test]
+    async fn test_story_teardown_runs_after_failed_steps() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/users"))
+            .respond_with(ResponseTemplate::new(201).set_body_string(r#"{"id": "42"}"#))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/users/42"))
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("DELETE"))
+            .and(path("/users/42"))
+            .respond_with(ResponseTemplate::new(500))
+            .expect(2)
+            .mount(&mock_server)
+            .await;
+        let step = |name: &str, http_method: &str, url: &str| Step {
+            name: name.to_owned(),
+            url: url.to_owned(),
+            with: None,
+            http_method: http_method.to_owned(),
+            expectations: None,
+            sensitive: false,
+            body: None,
+            body_template: None,
+            captures: None,
+        };
+        // Without an expected status the 500s would pass their steps
+        let mut profile = step(
+            "profile",
+            "GET",
+            "/users/${{steps.create.response.body.id}}",
+        );
+        profile.expectations = Some(vec![ProbeExpectation {
+            field: ExpectField::StatusCode,
+            operation: ExpectOperation::Equals,
+            value: "200".to_owned(),
+        }]);
+        let mut delete = step(
+            "delete",
+            "DELETE",
+            "/users/${{steps.create.response.body.id}}",
+        );
+        delete.expectations = profile.expectations.clone();
+        let mut story = Story {
+            name: "profile".to_owned(),
+            base_url: Some(mock_server.uri()),
+            setup: Some(vec![step("create", "POST", "/users")]),
+            steps: vec![profile],
+            teardown: Some(vec![delete]),
+            schedule: ProbeScheduleParameters {
+                initial_delay: Duration::ZERO,
+                interval: Duration::ZERO,
+                allow_fast: true,
+            },
+            tags: None,
+            group: None,
+            alerts: None,
+            recovery_threshold: None,
+            expectations: None,
+            sensitive: false,
+            alerts_include_details: false,
+            runtime_added: false,
+        };
+        let app_state = Arc::new(AppState::new(Config::default()));
+
+        story.probe_and_store_result(app_state.clone()).await;
+
+        {
+            let story_results = app_state.story_results.recent("profile").unwrap();
+            let story_result = &story_results[0];
+            assert!(!story_result.success);
+            assert_eq!(2, story_result.step_results.len());
+            assert!(!story_result.step_results[1].success);
+            assert_eq!(1, story_result.teardown_results.len());
+            assert!(!story_result.teardown_results[0].success);
+        }
+
+        // A failing teardown leaves a passing story alone
+        story.setup = None;
+        story.steps = vec![step("create", "POST", "/users")];
+        story.probe_and_store_result(app_state.clone()).await;
+
+        let story_results = app_state.story_results.recent("profile").unwrap();
+        let story_result = story_results
+            .iter()
+            .find(|result| result.teardown_results.len() == 1 && result.success);
+        assert!(story_result.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_max_in_flight_serializes_runs_of_a_probe() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/slow"))
+            .respond_with(
+                ResponseTemplate::new(200).set_delay(std::time::Duration::from_millis(300)),
+            )
+            .mount(&mock_server)
+            .await;
+        let mut probe = probe_get_with_expected_status(
+            reqwest::StatusCode::OK,
+            format!("{}/slow", mock_server.uri()),
+            "".to_owned(),
+        );
+        probe.max_in_flight = Some(1);
+        let app_state = Arc::new(AppState::new(Config::default()));
+
+        let started = std::time::Instant::now();
+        tokio::join!(
+            probe.probe_and_store_result(app_state.clone()),
+            probe.probe_and_store_result(app_state.clone())
+        );
+
+        assert!(started.elapsed() >= std::time::Duration::from_millis(600));
+        assert_eq!(
+            2,
+            app_state.probe_results.recent("Test probe").unwrap().len()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_samples_take_a_slot_each_and_fail_on_their_p95() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/slow"))
+            .respond_with(
+                ResponseTemplate::new(200).set_delay(std::time::Duration::from_millis(200)),
+            )
+            .expect(4)
+            .mount(&mock_server)
+            .await;
+        let mut probe = probe_get_with_expected_status(
+            reqwest::StatusCode::OK,
+            format!("{}/slow", mock_server.uri()),
+            "".to_owned(),
+        );
+        probe.max_in_flight = Some(1);
+        probe.with.as_mut().unwrap().samples = Some(Samples {
+            count: 4,
+            concurrency: Some(4),
+            delay_between_ms: None,
+            p95_of_samples_ms: Some(100),
+            max_of_samples_ms: None,
+            max_failures: None,
+        });
+        let metrics_state = MetricsState::for_testing();
+        let app_state = Arc::new(AppState::with_metrics(
+            Config::default(),
+            metrics_state.metrics(),
+        ));
+
+        let started = std::time::Instant::now();
+        probe.probe_and_store_result(app_state.clone()).await;
+
+        assert!(started.elapsed() >= std::time::Duration::from_millis(800));
+        let result = app_state.probe_results.latest("Test probe").unwrap();
+        assert!(!result.success);
+        assert_eq!(4, result.samples.as_ref().unwrap().len());
+        let error_message = result.error_message.unwrap();
+        assert!(
+            error_message.starts_with("p95 of samples "),
+            "{}",
+            error_message
+        );
+        assert!(error_message.contains("#4 200 in "), "{}", error_message);
+        let metrics = metrics_state.collect().unwrap();
+        let name = KeyValue::new("name", "Test probe");
+        assert!(
+            histogram_sum(&metrics, "sample_duration", std::slice::from_ref(&name)).unwrap()
+                >= 800.0
+        );
+        assert!(
+            histogram_sum(
+                &metrics,
+                "duration",
+                &[name, KeyValue::new("samples", 4_i64)]
+            )
+            .unwrap()
+                >= 200.0
+        );
+    }
+
+    #[tokio::test]
+    async fn test_prefetch_fills_the_request_and_is_timed_on_its_own() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/form"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .append_header("Set-Cookie", "session=s1; Path=/; HttpOnly")
+                    .append_header("Set-Cookie", "XSRF-TOKEN=abc; Path=/")
+                    .set_body_string(r#"<input type="hidden" name="_token" value="form-1">"#)
+                    .set_delay(std::time::Duration::from_millis(150)),
+            )
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/login"))
+            .and(header("X-XSRF-Token", "abc"))
+            .and(header("Cookie", "session=s1; XSRF-TOKEN=abc"))
+            .and(body_string("_token=form-1&user=probe"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        let mut probe = probe_get_with_expected_status(
+            reqwest::StatusCode::OK,
+            format!("{}/login", mock_server.uri()),
+            "_token=${{ prefetch.token }}&user=probe".to_owned(),
+        );
+        probe.http_method = "POST".to_owned();
+        let with = probe.with.as_mut().unwrap();
+        with.headers = Some(HashMap::from([(
+            "X-XSRF-Token".to_owned(),
+            "${{ prefetch.csrf }}".to_owned(),
+        )]));
+        with.prefetch = Some(Prefetch {
+            url: format!("{}/form", mock_server.uri()),
+            http_method: "GET".to_owned(),
+            extract: [
+                ("csrf", Extraction::Cookie("XSRF-TOKEN".to_owned())),
+                (
+                    "token",
+                    Extraction::Selector("input[name=_token]".to_owned()),
+                ),
+            ]
+            .into_iter()
+            .map(|(name, extraction)| (name.to_owned(), extraction))
+            .collect(),
+            include_in_duration: false,
+        });
+        let metrics_state = MetricsState::for_testing();
+        let app_state = Arc::new(AppState::with_metrics(
+            Config::default(),
+            metrics_state.metrics(),
+        ));
+
+        probe.probe_and_store_result(app_state.clone()).await;
+
+        let result = app_state.probe_results.latest("Test probe").unwrap();
+        assert!(result.success, "{:?}", result.error_message);
+        assert!(result.duration.unwrap() < std::time::Duration::from_millis(150));
+        let phases = result.phases.unwrap();
+        assert_eq!("prefetch", phases[0].name);
+        assert!(phases[0].duration_ms >= 150);
+        let metrics = metrics_state.collect().unwrap();
+        let name = KeyValue::new("name", "Test probe");
+        assert!(
+            histogram_sum(&metrics, "prefetch_duration", std::slice::from_ref(&name)).unwrap()
+                >= 150.0
+        );
+        assert!(histogram_sum(&metrics, "duration", &[name]).unwrap() < 150.0);
+    }
+
+    #[tokio::test]
+    async fn test_failed_prefetch_fails_the_probe_with_its_own_kind() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/form"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/login"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0)
+            .mount(&mock_server)
+            .await;
+        let mut probe = probe_get_with_expected_status(
+            reqwest::StatusCode::OK,
+            format!("{}/login", mock_server.uri()),
+            "_token=${{ prefetch.csrf }}".to_owned(),
+        );
+        probe.http_method = "POST".to_owned();
+        probe.with.as_mut().unwrap().prefetch = Some(Prefetch {
+            url: format!("{}/form", mock_server.uri()),
+            http_method: "GET".to_owned(),
+            extract: [(
+                "csrf".to_owned(),
+                Extraction::Regex(r#"name="_token" value="([^"]+)""#.to_owned()),
+            )]
+            .into_iter()
+            .collect(),
+            include_in_duration: false,
+        });
+        let metrics_state = MetricsState::for_testing();
+        let app_state = Arc::new(AppState::with_metrics(
+            Config::default(),
+            metrics_state.metrics(),
+        ));
+
+        probe.probe_and_store_result(app_state.clone()).await;
+
+        let result = app_state.probe_results.latest("Test probe").unwrap();
+        assert!(!result.success);
+        assert_eq!(Some("prefetch"), result.error_kind());
+        assert_eq!(
+            Some(format!(
+                "prefetch of {}/form has no match of `name=\"_token\" value=\"([^\"]+)\"` for `csrf`",
+                mock_server.uri()
+            )),
+            result.error_message
+        );
+        let metrics = metrics_state.collect().unwrap();
+        assert_eq!(
+            Some(1),
+            counter_value(
+                &metrics,
+                "errors",
+                &[
+                    KeyValue::new("name", "Test probe"),
+                    KeyValue::new("phase", "prefetch")
+                ]
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rate_limited_probe_is_deferred_without_failing() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/limited"))
+            .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "120"))
+            .mount(&mock_server)
+            .await;
+        let mut probe = probe_get_with_expected_status(
+            reqwest::StatusCode::OK,
+            format!("{}/limited", mock_server.uri()),
+            "".to_owned(),
+        );
+        probe.with.as_mut().unwrap().respect_retry_after = true;
+        let app_state = Arc::new(AppState::new(Config::default()));
+
+        probe.probe_and_store_result(app_state.clone()).await;
+
+        let result = app_state.probe_results.latest("Test probe").unwrap();
+        assert!(result.rate_limited);
+        assert_eq!(Some("rate_limited"), result.error_kind());
+        let until = app_state.deferred_until("Test probe").unwrap();
+        assert!(until > chrono::Utc::now() + chrono::TimeDelta::seconds(100));
+        assert!(!app_state
+            .monitor_states
+            .read()
+            .unwrap()
+            .contains_key("Test probe"));
+    }
+
+    #[tokio::test]
+    async fn test_recovery_alert_renders_incident() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/health"))
+            .respond_with(ResponseTemplate::new(500))
+            .up_to_n_times(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/health"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/alert"))
+            .and(body_partial_json(
+                serde_json::json!({ "message": "Probe failed." }),
+            ))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/alert"))
+            .and(body_partial_json(
+                serde_json::json!({ "message": "Test probe recovered after 0s" }),
+            ))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        let mut probe = probe_get_with_expected_status(
+            reqwest::StatusCode::OK,
+            format!("{}/health", mock_server.uri()),
+            "".to_owned(),
+        );
+        probe.alerts = Some(vec![ProbeAlert {
+            url: format!("{}/alert", mock_server.uri()),
+            recovery_template: Some(
+                "{{ monitor }} recovered after {{ incident.duration }}".to_owned(),
+            ),
+            ..Default::default()
+        }]);
+        let app_state = Arc::new(AppState::new(Config::default()));
+
+        probe.probe_and_store_result(app_state.clone()).await;
+        probe.probe_and_store_result(app_state.clone()).await;
+
+        let incidents = app_state.incidents.read().unwrap();
+        assert!(incidents["Test probe"][0].ended.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_slow_expectations_are_counted() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/large"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("x".repeat(4096)))
+            .mount(&mock_server)
+            .await;
+        let probe = probe_get_with_expected_status(
+            reqwest::StatusCode::OK,
+            format!("{}/large", mock_server.uri()),
+            "".to_owned(),
+        );
+        let metrics_state = MetricsState::for_testing();
+        // Every body goes to the blocking pool and every evaluation counts as slow
+        let app_state = Arc::new(AppState::with_metrics(
+            Config {
+                settings: Settings {
+                    runtime: RuntimeSettings {
+                        max_blocking_duration_warning_ms: Some(0),
+                        blocking_body_threshold_bytes: Some(0),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            metrics_state.metrics(),
+        ));
+
+        probe.probe_and_store_result(app_state.clone()).await;
+
+        assert!(app_state.probe_results.recent("Test probe").unwrap()[0].success);
+        let metrics = metrics_state.collect().unwrap();
+        assert_eq!(
+            Some(1),
+            counter_value(
+                &metrics,
+                "slow_expectations",
+                &[KeyValue::new("name", "Test probe")]
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fast_responses_record_a_nonzero_duration() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/fast"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&mock_server)
+            .await;
+        let probe = probe_get_with_expected_status(
+            reqwest::StatusCode::OK,
+            format!("{}/fast", mock_server.uri()),
+            "".to_owned(),
+        );
+        let metrics_state = MetricsState::for_testing();
+        let app_state = Arc::new(AppState::with_metrics(
+            Config::default(),
+            metrics_state.metrics_with_unit(DurationUnit::Us),
+        ));
+
+        probe.probe_and_store_result(app_state.clone()).await;
+
+        let result = app_state.probe_results.recent("Test probe").unwrap()[0].clone();
+        // A local response usually takes well under a millisecond, which used to be recorded as 0
+        assert!(result
+            .duration
+            .is_some_and(|duration| duration > Duration::ZERO));
+        let serialized = serde_json::to_value(&result).unwrap();
+        assert!(serialized["duration_ms"].as_f64().unwrap() > 0.0);
+        let metrics = metrics_state.collect().unwrap();
+        assert_eq!(Some("us".to_owned()), metric_unit(&metrics, "duration"));
+        let recorded =
+            histogram_sum(&metrics, "duration", &[KeyValue::new("name", "Test probe")]).unwrap();
+        assert!(recorded > 0.0);
+    }
+
+    #[tokio::test]
+    async fn test_redirects_are_recorded_rather_than_followed() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/moved"))
+            .respond_with(
+                ResponseTemplate::new(302)
+                    .insert_header("Location", format!("{}/target", mock_server.uri()).as_str()),
+            )
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/target"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0)
+            .mount(&mock_server)
+            .await;
+        let probe = probe_get_with_expected_status(
+            reqwest::StatusCode::FOUND,
+            format!("{}/moved", mock_server.uri()),
+            "".to_owned(),
+        );
+        let app_state = Arc::new(AppState::new(Config::default()));
+
+        probe.probe_and_store_result(app_state.clone()).await;
+
+        let result = app_state.probe_results.recent("Test probe").unwrap()[0].clone();
+        assert!(result.success);
+        assert_eq!(302, result.response.unwrap().status_code);
+    }
+
+    #[tokio::test]
+    async fn test_retried_probe_records_its_attempts() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/flaky"))
+            .respond_with(ResponseTemplate::new(503))
+            .up_to_n_times(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/flaky"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&mock_server)
+            .await;
+        let mut probe = probe_get_with_expected_status(
+            reqwest::StatusCode::OK,
+            format!("{}/flaky", mock_server.uri()),
+            "".to_owned(),
+        );
+        probe.retries = Some(2);
+        let metrics_state = MetricsState::for_testing();
+        let app_state = Arc::new(AppState::with_metrics(
+            Config::default(),
+            metrics_state.metrics(),
+        ));
+
+        probe.probe_and_store_result(app_state.clone()).await;
+
+        let results = app_state.probe_results.recent("Test probe").unwrap();
+        assert_eq!(1, results.len());
+        assert!(results[0].success);
+        assert_eq!(2, results[0].attempts);
+        assert_eq!(1, results[0].retry_reasons.len());
+        let metrics = metrics_state.collect().unwrap();
+        assert_eq!(
+            Some(1),
+            counter_value(
+                &metrics,
+                "probe_retries",
+                &[KeyValue::new("name", "Test probe")]
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_full_stack_probe_names_the_phase_that_failed() {
+        // Accepts and closes every connection, so DNS passes and the handshake fails
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                drop(stream);
+            }
+        });
+        let mut probe = probe_get_with_expected_status(
+            reqwest::StatusCode::OK,
+            format!("https://{}/health", address),
+            "".to_owned(),
+        );
+        probe.composite = Some(Composite::FullStack);
+        let metrics_state = MetricsState::for_testing();
+        let app_state = Arc::new(AppState::with_metrics(
+            Config::default(),
+            metrics_state.metrics(),
+        ));
+
+        probe.probe_and_store_result(app_state.clone()).await;
+
+        let result = app_state.probe_results.latest("Test probe").unwrap();
+        assert!(!result.success);
+        assert_eq!(Some("tls".to_owned()), result.failed_phase);
+        assert!(result
+            .error_message
+            .unwrap()
+            .starts_with("DNS ok, TLS failed: "));
+        assert_eq!(2, result.full_stack.unwrap().phases.len());
+        let metrics = metrics_state.collect().unwrap();
+        assert_eq!(
+            Some(1),
+            counter_value(
+                &metrics,
+                "errors",
+                &[
+                    KeyValue::new("name", "Test probe"),
+                    KeyValue::new("phase", "tls")
+                ]
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_probe_with_urls_stores_a_result_per_url() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/backend-a"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/backend-b"))
+            .respond_with(ResponseTemplate::new(503))
+            .mount(&mock_server)
+            .await;
+        let mut probe =
+            probe_get_with_expected_status(reqwest::StatusCode::OK, "".to_owned(), "".to_owned());
+        probe.urls = Some(vec![
+            format!("{}/backend-a", mock_server.uri()),
+            format!("{}/backend-b", mock_server.uri()),
+        ]);
+        let app_state = Arc::new(AppState::new(Config::default()));
+
+        probe.probe_and_store_result(app_state.clone()).await;
+
+        let latest = |name: &str| app_state.probe_results.latest(name).unwrap();
+        assert!(latest("Test probe:0").success);
+        assert!(!latest("Test probe:1").success);
+        let probe_result = latest("Test probe");
+        assert!(!probe_result.success);
+        assert!(probe_result
+            .error_message
+            .unwrap()
+            .starts_with("1 of 2 urls failed, Test probe:1"));
+        assert!(app_state.monitor_states.read().unwrap()["Test probe"].failing);
+    }
+
+    #[tokio::test]
+    async fn test_http_timings_are_recorded() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/health"))
+            .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_millis(50)))
+            .mount(&mock_server)
+            .await;
+        let probe = probe_get_with_expected_status(
+            reqwest::StatusCode::OK,
+            format!("{}/health", mock_server.uri()),
+            "".to_owned(),
+        );
+        let metrics_state = MetricsState::for_testing();
+        let app_state = Arc::new(AppState::with_metrics(
+            Config::default(),
+            metrics_state.metrics(),
+        ));
+
+        probe.probe_and_store_result(app_state.clone()).await;
+
+        let metrics = metrics_state.collect().unwrap();
+        let attributes = [KeyValue::new("name", "Test probe")];
+        assert!(f64_gauge_value(&metrics, "ttfb_duration", &attributes).unwrap() >= 50.0);
+        // The mock server is reached by IP, there is nothing to look up
+        assert_eq!(
+            None,
+            f64_gauge_value(&metrics, "dns_lookup_duration", &attributes)
+        );
+        let result = app_state.probe_results.latest("Test probe").unwrap();
+        let phases: Vec<_> = result
+            .phases
+            .unwrap()
+            .into_iter()
+            .map(|phase| phase.name)
+            .collect();
+        assert_eq!(vec!["ttfb", "body"], phases);
+    }
+
+    #[tokio::test]
+    async fn test_metrics_are_extracted_from_the_body() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/health"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string(r#"{"queue_depth": 42, "oldest_item_age_s": "17"}"#),
+            )
+            .mount(&mock_server)
+            .await;
+        let mut probe = probe_get_with_expected_status(
+            reqwest::StatusCode::OK,
+            format!("{}/health", mock_server.uri()),
+            "".to_owned(),
+        );
+        probe.metrics_from_body = Some(
+            [
+                ("queue_depth", "$.queue_depth"),
+                ("oldest_item_age_s", "$.oldest_item_age_s"),
+            ]
+            .into_iter()
+            .map(|(name, path)| (name.to_owned(), path.to_owned()))
+            .collect(),
+        );
+        let metrics_state = MetricsState::for_testing();
+        let app_state = Arc::new(AppState::with_metrics(
+            Config::default(),
+            metrics_state.metrics(),
+        ));
+
+        probe.probe_and_store_result(app_state.clone()).await;
+
+        let metrics = metrics_state.collect().unwrap();
+        let attributes = [KeyValue::new("name", "Test probe")];
+        assert_eq!(
+            Some(42.0),
+            f64_gauge_value(&metrics, "xbp_extracted_queue_depth", &attributes)
+        );
+        assert_eq!(
+            None,
+            f64_gauge_value(&metrics, "xbp_extracted_oldest_item_age_s", &attributes)
+        );
+        assert_eq!(
+            Some(1),
+            counter_value(
+                &metrics,
+                "body_extraction_failures",
+                &[
+                    KeyValue::new("name", "Test probe"),
+                    KeyValue::new("metric", "oldest_item_age_s")
+                ]
+            )
+        );
+
+        // Sensitive probes don't extract without `allow_sensitive_extraction`
+        probe.sensitive = true;
+        probe.metrics_from_body = Some(
+            [("sensitive_depth".to_owned(), "$.queue_depth".to_owned())]
+                .into_iter()
+                .collect(),
+        );
+        probe.probe_and_store_result(app_state.clone()).await;
+        let metrics = metrics_state.collect().unwrap();
+        assert_eq!(
+            None,
+            f64_gauge_value(&metrics, "xbp_extracted_sensitive_depth", &attributes)
+        );
+
+        probe.allow_sensitive_extraction = true;
+        probe.probe_and_store_result(app_state.clone()).await;
+        let metrics = metrics_state.collect().unwrap();
+        assert_eq!(
+            Some(42.0),
+            f64_gauge_value(&metrics, "xbp_extracted_sensitive_depth", &attributes)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_connection_details_are_recorded() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/backend"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("Server", "nginx")
+                    .insert_header("X-Served-By", "cache-ams-1"),
+            )
+            .mount(&mock_server)
+            .await;
+        let probe = probe_get_with_expected_status(
+            reqwest::StatusCode::OK,
+            format!("{}/backend", mock_server.uri()),
+            "".to_owned(),
+        );
+        let metrics_state = MetricsState::for_testing();
+        let app_state = Arc::new(AppState::with_metrics(
+            Config {
+                settings: Settings {
+                    capture_headers: Some(vec!["Server".to_owned(), "Via".to_owned()]),
+                    log_backend_changes: true,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            metrics_state.metrics(),
+        ));
+
+        probe.probe_and_store_result(app_state.clone()).await;
+
+        let connection = app_state.probe_results.recent("Test probe").unwrap()[0]
+            .connection
+            .clone()
+            .unwrap();
+        assert_eq!(Some(*mock_server.address()), connection.remote_addr);
+        assert_eq!(
+            vec![("server".to_owned(), "nginx".to_owned())],
+            connection.headers.into_iter().collect::<Vec<_>>()
+        );
+
+        // Pretend the previous run reached another backend
+        let mut previous = app_state.probe_results.latest("Test probe").unwrap();
+        previous.connection.as_mut().unwrap().remote_addr = Some("10.0.0.1:80".parse().unwrap());
+        app_state.probe_results.record("Test probe", previous);
+        probe.probe_and_store_result(app_state.clone()).await;
+
+        let metrics = metrics_state.collect().unwrap();
+        assert_eq!(
+            Some(1),
+            counter_value(
+                &metrics,
+                "backend_changes",
+                &[KeyValue::new("name", "Test probe")]
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_failed_expectation_is_a_span_event() {
+        use opentelemetry::trace::{Status, TraceContextExt, Tracer, TracerProvider};
+        use opentelemetry::Context;
+        use opentelemetry_sdk::trace::{InMemorySpanExporter, SdkTracerProvider};
+
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/down"))
+            .respond_with(ResponseTemplate::new(503))
+            .mount(&mock_server)
+            .await;
+        let probe = probe_get_with_expected_status(
+            reqwest::StatusCode::OK,
+            format!("{}/down", mock_server.uri()),
+            "".to_owned(),
+        );
+        let app_state = AppState::new(Config::default());
+        // A local provider, the global one is shared between tests
+        let exporter = InMemorySpanExporter::default();
+        let provider = SdkTracerProvider::builder()
+            .with_simple_exporter(exporter.clone())
+            .build();
+        let root_cx = Context::default().with_span(provider.tracer("test").start("Test probe"));
+
+        let result = probe
+            .run_http(&app_state, &root_cx, &[], uuid::Uuid::new_v4())
+            .await;
+        let kind = super::error_kind(result.success, result.response.is_some()).unwrap();
+        super::set_error_status(&root_cx.span(), kind);
+        root_cx.span().end();
+
+        let span = exporter.get_finished_spans().unwrap().remove(0);
+        let event = span
+            .events
+            .iter()
+            .find(|event| event.name == "expectation.failed")
+            .unwrap();
+        assert!(event
+            .attributes
+            .contains(&KeyValue::new("expectation.kind", "StatusCode.Equals")));
+        assert!(event.attributes.contains(&KeyValue::new("actual", "503")));
+        assert_eq!(Status::error("expectation"), span.status);
+    }
+}
@@ -0,0 +1,104 @@
+// Backing off probes a rate limited API answered with 429, see `with.respect_retry_after`
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+
+// The longest a probe is deferred when `settings.rate_limits.max_backoff` is unset
+pub const DEFAULT_MAX_BACKOFF: Duration = Duration::from_secs(60 * 60);
+
+// A probe whose latest run was rate limited. Its scheduled runs wait until `until`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Deferral {
+    pub until: DateTime<Utc>,
+    // Rate limited runs in a row, the fallback backoff doubles with each one
+    pub consecutive: u32,
+}
+
+// Whether a response asks to back off: any 429, or a 503 saying for how long
+pub fn is_rate_limited(status_code: u32, retry_after: Option<&str>) -> bool {
+    status_code == 429 || (status_code == 503 && retry_after.is_some())
+}
+
+// `Retry-After` holds either delay seconds or an HTTP-date, a date in the past means right away
+pub fn parse_retry_after(value: &str, now: DateTime<Utc>) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    Some(
+        (date.with_timezone(&Utc) - now)
+            .to_std()
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
+// What `Retry-After` asks for, otherwise the interval doubled for each rate limited run in a row.
+// Either way no more than `max_backoff`.
+pub fn backoff(
+    retry_after: Option<Duration>,
+    interval: Duration,
+    consecutive: u32,
+    max_backoff: Duration,
+) -> Duration {
+    let delay = retry_after.unwrap_or_else(|| {
+        interval
+            .max(Duration::from_secs(1))
+            .saturating_mul(2u32.saturating_pow(consecutive))
+    });
+    delay.min(max_backoff)
+}
+
+#[cfg(test)]
+mod rate_limit_tests {
+    use std::time::Duration;
+
+    use chrono::{TimeZone, Utc};
+
+    use super::{backoff, is_rate_limited, parse_retry_after};
+
+    #[test]
+    fn test_parse_retry_after() {
+        let now = Utc.with_ymd_and_hms(2015, 10, 21, 7, 27, 0).unwrap();
+
+        assert_eq!(
+            Some(Duration::from_secs(120)),
+            parse_retry_after("120", now)
+        );
+        assert_eq!(
+            Some(Duration::from_secs(60)),
+            parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT", now)
+        );
+        assert_eq!(
+            Some(Duration::ZERO),
+            parse_retry_after("Wed, 21 Oct 2015 07:00:00 GMT", now)
+        );
+        assert_eq!(None, parse_retry_after("soon", now));
+    }
+
+    #[test]
+    fn test_backoff_doubles_without_retry_after_and_is_capped() {
+        let interval = Duration::from_secs(30);
+        let max = Duration::from_secs(600);
+
+        assert_eq!(
+            Duration::from_secs(90),
+            backoff(Some(Duration::from_secs(90)), interval, 1, max)
+        );
+        assert_eq!(Duration::from_secs(60), backoff(None, interval, 1, max));
+        assert_eq!(Duration::from_secs(240), backoff(None, interval, 3, max));
+        assert_eq!(max, backoff(None, interval, 40, max));
+        assert_eq!(
+            max,
+            backoff(Some(Duration::from_secs(86400)), interval, 1, max)
+        );
+    }
+
+    #[test]
+    fn test_503_needs_retry_after() {
+        assert!(is_rate_limited(429, None));
+        assert!(is_rate_limited(503, Some("10")));
+        assert!(!is_rate_limited(503, None));
+        assert!(!is_rate_limited(500, Some("10")));
+    }
+}
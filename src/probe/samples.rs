@@ -0,0 +1,240 @@
+// Probe runs with `with.samples`: several requests, judged on their aggregate rather than on a
+// single, noisy one. The samples themselves are regular http runs, see `Probe::run_samples`.
+use std::time::Duration;
+
+use super::model::{ProbeResult, SampleResult, Samples};
+
+// Nearest rank, so the p95 of 5 samples is the slowest
+pub fn percentile(durations: &[Duration], percentile: f64) -> Option<Duration> {
+    let mut sorted = durations.to_vec();
+    sorted.sort();
+    let rank = (percentile / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted.get(rank.max(1) - 1).copied()
+}
+
+fn sample(result: &ProbeResult) -> SampleResult {
+    SampleResult {
+        success: result.success,
+        status_code: result
+            .response
+            .as_ref()
+            .map(|response| response.status_code),
+        duration: result.duration,
+        error_message: result.error_message.clone(),
+    }
+}
+
+// E.g. "#1 200 in 120ms; #2 no response, failed: timed out", for the alert of a failed run
+pub fn breakdown(samples: &[SampleResult]) -> String {
+    samples
+        .iter()
+        .enumerate()
+        .map(|(index, sample)| {
+            let outcome = match (sample.status_code, sample.duration) {
+                (Some(status_code), Some(duration)) => {
+                    format!("{} in {}ms", status_code, duration.as_millis())
+                }
+                _ => "no response".to_owned(),
+            };
+            match (&sample.error_message, sample.success) {
+                (Some(error), false) => format!("#{} {}, failed: {}", index + 1, outcome, error),
+                _ => format!("#{} {}", index + 1, outcome),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+// One result for the run. It carries the response of the first failed sample, or of the slowest
+// one when the samples only failed on their durations, and the p95 as its duration.
+pub fn aggregate(settings: &Samples, results: Vec<ProbeResult>) -> ProbeResult {
+    let samples: Vec<SampleResult> = results.iter().map(sample).collect();
+    let durations: Vec<Duration> = samples
+        .iter()
+        .filter_map(|sample| sample.duration)
+        .collect();
+    let p95 = percentile(&durations, 95.0);
+    let slowest = durations.iter().max().copied();
+
+    let mut reasons = vec![];
+    let failed = samples.iter().filter(|sample| !sample.success).count() as u32;
+    let max_failures = settings.max_failures.unwrap_or(0);
+    if failed > max_failures {
+        reasons.push(format!(
+            "{} of {} samples failed, at most {} may",
+            failed,
+            samples.len(),
+            max_failures
+        ));
+    }
+    let thresholds = [
+        ("p95", p95, settings.p95_of_samples_ms),
+        ("max", slowest, settings.max_of_samples_ms),
+    ];
+    for (name, duration, threshold) in thresholds {
+        if let (Some(duration), Some(threshold)) = (duration, threshold) {
+            if duration >= Duration::from_millis(threshold) {
+                reasons.push(format!(
+                    "{} of samples {}ms isn't under {}ms",
+                    name,
+                    duration.as_millis(),
+                    threshold
+                ));
+            }
+        }
+    }
+
+    let success = reasons.is_empty();
+    let timestamp_started = results.iter().map(|result| result.timestamp_started).min();
+    let rate_limited = results.iter().any(|result| result.rate_limited);
+    let representative = results
+        .iter()
+        .position(|result| !success && !result.success)
+        .or_else(|| {
+            results
+                .iter()
+                .enumerate()
+                .filter(|(_, result)| result.success || !success)
+                .max_by_key(|(_, result)| result.duration)
+                .map(|(index, _)| index)
+        })
+        .unwrap_or(0);
+    let mut probe_result = results.into_iter().nth(representative).unwrap();
+    probe_result.success = success;
+    if let Some(timestamp_started) = timestamp_started {
+        probe_result.timestamp_started = timestamp_started;
+    }
+    probe_result.duration = p95;
+    probe_result.rate_limited = rate_limited;
+    if success {
+        probe_result.error_message = None;
+        probe_result.failed_expectation = None;
+    } else {
+        probe_result.error_message = Some(format!(
+            "{}. Samples: {}",
+            reasons.join(", "),
+            breakdown(&samples)
+        ));
+    }
+    probe_result.samples = Some(samples);
+    probe_result
+}
+
+#[cfg(test)]
+mod samples_tests {
+    use std::time::Duration;
+
+    use chrono::Utc;
+    use uuid::Uuid;
+
+    use super::{aggregate, percentile};
+    use crate::probe::model::{ProbeResponse, ProbeResult, Samples};
+
+    fn settings() -> Samples {
+        Samples {
+            count: 5,
+            concurrency: None,
+            delay_between_ms: None,
+            p95_of_samples_ms: Some(300),
+            max_of_samples_ms: Some(1000),
+            max_failures: Some(1),
+        }
+    }
+
+    fn sample(status_code: u32, millis: u64) -> ProbeResult {
+        let success = status_code == 200;
+        ProbeResult {
+            run_id: Uuid::new_v4(),
+            probe_name: "api".to_owned(),
+            timestamp_started: Utc::now(),
+            success,
+            error_message: (!success)
+                .then(|| format!("expected status code 200, got {}", status_code)),
+            response: Some(ProbeResponse {
+                timestamp_received: Utc::now(),
+                status_code,
+                body: String::new(),
+                sensitive: false,
+            }),
+            duration: Some(Duration::from_millis(millis)),
+            trace_id: None,
+            phases: None,
+            failed_phase: None,
+            failed_expectation: None,
+            tls: None,
+            ntp: None,
+            sftp: None,
+            full_stack: None,
+            connection: None,
+            during_reload: false,
+            rate_limited: false,
+            attempts: 1,
+            retry_reasons: vec![],
+            samples: None,
+        }
+    }
+
+    #[test]
+    fn test_percentile_is_nearest_rank() {
+        let durations: Vec<Duration> = [50, 10, 40, 20, 30]
+            .into_iter()
+            .map(Duration::from_millis)
+            .collect();
+
+        assert_eq!(
+            Some(Duration::from_millis(50)),
+            percentile(&durations, 95.0)
+        );
+        assert_eq!(
+            Some(Duration::from_millis(30)),
+            percentile(&durations, 50.0)
+        );
+        assert_eq!(None, percentile(&[], 95.0));
+    }
+
+    #[test]
+    fn test_tolerated_failures_pass_the_run() {
+        let results = vec![
+            sample(200, 100),
+            sample(503, 20),
+            sample(200, 150),
+            sample(200, 120),
+            sample(200, 110),
+        ];
+
+        let result = aggregate(&settings(), results);
+
+        assert!(result.success);
+        assert_eq!(None, result.error_message);
+        assert_eq!(Some(Duration::from_millis(150)), result.duration);
+        assert_eq!(200, result.response.unwrap().status_code);
+        assert_eq!(5, result.samples.unwrap().len());
+    }
+
+    #[test]
+    fn test_failed_run_lists_every_reason_and_sample() {
+        let results = vec![
+            sample(200, 100),
+            sample(503, 20),
+            sample(503, 30),
+            sample(200, 400),
+            sample(200, 110),
+        ];
+
+        let result = aggregate(&settings(), results);
+
+        assert!(!result.success);
+        assert_eq!(503, result.response.unwrap().status_code);
+        assert_eq!(
+            Some(
+                "2 of 5 samples failed, at most 1 may, p95 of samples 400ms isn't under 300ms. \
+                Samples: #1 200 in 100ms; \
+                #2 503 in 20ms, failed: expected status code 200, got 503; \
+                #3 503 in 30ms, failed: expected status code 200, got 503; \
+                #4 200 in 400ms; #5 200 in 110ms"
+                    .to_owned()
+            ),
+            result.error_message
+        );
+    }
+}
@@ -1,5 +1,7 @@
 use std::sync::Arc;
 
+use chrono::Utc;
+use tokio::task::JoinHandle;
 use tokio::time::Instant;
 use tracing::info;
 
@@ -10,33 +12,47 @@ use crate::AppState;
 use super::model::Story;
 
 // TODO: Can update these signatures to just use app_state
-pub fn schedule_probes(probes: &Vec<Probe>, app_state: Arc<AppState>) {
-    for probe in probes {
-        let probe_clone = probe.clone();
-        let task_state = app_state.clone();
-        tokio::spawn(async move {
-            probing_loop(&probe_clone, task_state).await;
-        });
-    }
+// Each task is returned with the name of its probe, so `AppState` can stop or restart a single one
+pub fn schedule_probes(
+    probes: &[Probe],
+    app_state: Arc<AppState>,
+) -> Vec<(String, JoinHandle<()>)> {
+    probes
+        .iter()
+        .map(|probe| {
+            let probe_clone = probe.clone();
+            let task_state = app_state.clone();
+            let task = tokio::spawn(async move {
+                probing_loop(&probe_clone, task_state).await;
+            });
+            (probe.name.clone(), task)
+        })
+        .collect()
 }
 
-pub fn schedule_stories(stories: &Vec<Story>, app_state: Arc<AppState>) {
-    for story in stories {
-        let story_clone = story.clone();
-        let task_state = app_state.clone();
-        tokio::spawn(async move {
-            probing_loop(&story_clone, task_state).await;
-        });
-    }
+pub fn schedule_stories(
+    stories: &[Story],
+    app_state: Arc<AppState>,
+) -> Vec<(String, JoinHandle<()>)> {
+    stories
+        .iter()
+        .map(|story| {
+            let story_clone = story.clone();
+            let task_state = app_state.clone();
+            let task = tokio::spawn(async move {
+                probing_loop(&story_clone, task_state).await;
+            });
+            (story.name.clone(), task)
+        })
+        .collect()
 }
 
-pub async fn probing_loop<T: Monitorable>(monitorable: &T, app_state: Arc<AppState>) {
+pub(crate) async fn probing_loop<T: Monitorable>(monitorable: &T, app_state: Arc<AppState>) {
     info!("Started monitoring {}", monitorable.get_name());
 
     let schedule = monitorable.get_schedule();
 
-    let mut next_run_time =
-        Instant::now() + std::time::Duration::from_secs(schedule.initial_delay as u64);
+    let mut next_run_time = Instant::now() + schedule.initial_delay;
 
     loop {
         let now = Instant::now();
@@ -44,9 +60,26 @@ pub async fn probing_loop<T: Monitorable>(monitorable: &T, app_state: Arc<AppSta
             tokio::time::sleep(next_run_time - now).await;
         }
 
-        next_run_time += std::time::Duration::from_secs(schedule.interval as u64);
+        let scheduled = next_run_time;
+        next_run_time += schedule.interval;
 
+        // Disabled monitors keep their schedule, enabling one resumes it without a burst of runs.
+        // Yields so a zero interval doesn't hold on to the worker thread.
+        if !monitorable.enabled(&app_state) {
+            tokio::task::yield_now().await;
+            continue;
+        }
         monitorable.probe_and_store_result(app_state.clone()).await;
+
+        // A rate limited probe skips the runs due before its deferral ends
+        if let Some(until) = app_state.deferred_until(&monitorable.get_name()) {
+            let wait = (until - Utc::now()).to_std().unwrap_or_default();
+            next_run_time = next_run_time.max(Instant::now() + wait);
+        }
+        // An open circuit breaker probes at its own, longer interval
+        if let Some(interval) = app_state.open_circuit_interval(&monitorable.get_name()) {
+            next_run_time = next_run_time.max(scheduled + interval);
+        }
     }
 }
 
@@ -99,11 +132,12 @@ mod schedule_tests {
         let config = Config {
             probes: vec![probe],
             stories: vec![],
+            ..Default::default()
         };
 
         let app_state = Arc::new(AppState::new(config));
 
-        schedule_probes(&app_state.config.probes, app_state.clone());
+        schedule_probes(&app_state.config.read().unwrap().probes, app_state.clone());
 
         // As delay and interval are 0, we'd expect that within 15 seconds our probe has been hit twice
         // One for first probe, then 10s timeout on request, then second probe
@@ -134,11 +168,12 @@ mod schedule_tests {
         let config = Config {
             probes: vec![probe],
             stories: vec![],
+            ..Default::default()
         };
 
         let app_state = Arc::new(AppState::new(config));
 
-        schedule_probes(&app_state.config.probes, app_state.clone());
+        schedule_probes(&app_state.config.read().unwrap().probes, app_state.clone());
 
         // As delay and interval are 0, we'd expect that within 15 seconds our probe has been hit twice
         // One for first probe, then 10s timeout on request, then second probe
@@ -146,4 +181,4 @@ mod schedule_tests {
 
         // If we don't fail here it means our .expect() has succeded
     }
-}
\ No newline at end of file
+}
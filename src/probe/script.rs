@@ -0,0 +1,220 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+// What a `Script` expectation sees of a response
+pub struct ScriptInput<'a> {
+    pub status: u32,
+    // Lowercase header names, repeated headers are joined with ", "
+    pub headers: &'a HashMap<String, String>,
+    pub body: &'a str,
+    pub duration: Duration,
+}
+
+#[cfg(feature = "scripting")]
+mod engine {
+    use std::collections::HashMap;
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+    use lazy_static::lazy_static;
+    use rhai::{Dynamic, Engine, Map, Scope, AST};
+
+    use super::ScriptInput;
+    use crate::probe::duration::as_millis_f64;
+
+    // Limits per run, a script that goes over them fails the expectation
+    const MAX_DURATION: Duration = Duration::from_millis(100);
+    const MAX_OPERATIONS: u64 = 1_000_000;
+    const MAX_CALL_LEVELS: usize = 32;
+    const MAX_STRING_SIZE: usize = 1024 * 1024;
+    const MAX_COLLECTION_SIZE: usize = 100_000;
+
+    lazy_static! {
+        // Compiled scripts by source, filled when the config is validated
+        static ref COMPILED: Mutex<HashMap<String, Arc<AST>>> = Mutex::new(HashMap::new());
+    }
+
+    // Scripts only get the values in scope: `print` and `debug` go nowhere, `eval` is disabled,
+    // and Rhai itself has no file, network or process access
+    fn engine() -> Engine {
+        let mut engine = Engine::new();
+        engine
+            .set_max_operations(MAX_OPERATIONS)
+            .set_max_call_levels(MAX_CALL_LEVELS)
+            .set_max_string_size(MAX_STRING_SIZE)
+            .set_max_array_size(MAX_COLLECTION_SIZE)
+            .set_max_map_size(MAX_COLLECTION_SIZE)
+            .on_print(|_| {})
+            .on_debug(|_, _, _| {})
+            .disable_symbol("eval");
+        engine
+    }
+
+    pub fn compile(source: &str) -> Result<Arc<AST>, String> {
+        if let Some(ast) = COMPILED.lock().unwrap().get(source) {
+            return Ok(ast.clone());
+        }
+        let ast = Arc::new(
+            engine()
+                .compile(source)
+                .map_err(|e| format!("invalid script: {}", e))?,
+        );
+        COMPILED
+            .lock()
+            .unwrap()
+            .insert(source.to_owned(), ast.clone());
+        Ok(ast)
+    }
+
+    pub fn run(source: &str, input: &ScriptInput) -> Result<(), String> {
+        let ast = compile(source)?;
+        let mut scope = Scope::new();
+        scope.push("status", input.status as i64);
+        scope.push(
+            "headers",
+            input
+                .headers
+                .iter()
+                .map(|(name, value)| (name.as_str().into(), Dynamic::from(value.clone())))
+                .collect::<Map>(),
+        );
+        scope.push("body_text", input.body.to_owned());
+        scope.push(
+            "body_json",
+            serde_json::from_str::<serde_json::Value>(input.body)
+                .ok()
+                .and_then(|json| rhai::serde::to_dynamic(json).ok())
+                .unwrap_or(Dynamic::UNIT),
+        );
+        scope.push("duration_ms", as_millis_f64(input.duration));
+
+        let started = Instant::now();
+        let mut engine = engine();
+        engine.on_progress(move |_| {
+            (started.elapsed() > MAX_DURATION)
+                .then(|| Dynamic::from("time limit exceeded".to_owned()))
+        });
+        let outcome = catch_unwind(AssertUnwindSafe(|| {
+            engine.eval_ast_with_scope::<Dynamic>(&mut scope, &ast)
+        }))
+        .map_err(|_| "script panicked".to_owned())?
+        .map_err(|e| format!("script error: {}", e))?;
+
+        if outcome.is_bool() {
+            return match outcome.as_bool() {
+                Ok(true) => Ok(()),
+                _ => Err("script returned false".to_owned()),
+            };
+        }
+        let type_name = outcome.type_name();
+        match outcome.into_string() {
+            Ok(message) => Err(message),
+            Err(_) => Err(format!(
+                "script returned {}, expected true or an error string",
+                type_name
+            )),
+        }
+    }
+}
+
+#[cfg(not(feature = "scripting"))]
+const DISABLED: &str = "script expectations need xbp-monitoring built with the `scripting` feature";
+
+// Compiles and caches a script, so syntax errors show up when the config is loaded
+#[cfg(feature = "scripting")]
+pub fn compile(source: &str) -> Result<(), String> {
+    engine::compile(source).map(|_| ())
+}
+
+#[cfg(not(feature = "scripting"))]
+pub fn compile(_source: &str) -> Result<(), String> {
+    Err(DISABLED.to_owned())
+}
+
+// Runs a script, which passes by returning `true`. A string it returns is the failure reason.
+#[cfg(feature = "scripting")]
+pub fn run(source: &str, input: &ScriptInput) -> Result<(), String> {
+    engine::run(source, input)
+}
+
+#[cfg(not(feature = "scripting"))]
+pub fn run(_source: &str, _input: &ScriptInput) -> Result<(), String> {
+    Err(DISABLED.to_owned())
+}
+
+#[cfg(all(test, feature = "scripting"))]
+mod script_tests {
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    use super::{compile, run, ScriptInput};
+
+    fn input<'a>(headers: &'a HashMap<String, String>, body: &'a str) -> ScriptInput<'a> {
+        ScriptInput {
+            status: 200,
+            headers,
+            body,
+            duration: Duration::from_millis(42),
+        }
+    }
+
+    #[test]
+    fn test_script_sees_the_response() {
+        let headers = HashMap::from([("content-type".to_owned(), "application/json".to_owned())]);
+        let body = r#"{"items":[{"created_at":"2024-02-01","price":5},{"created_at":"2024-01-01","price":3}]}"#;
+        let script = r#"
+            let items = body_json.items;
+            for i in 1..items.len() {
+                if items[i - 1].created_at < items[i].created_at {
+                    return "items are not sorted by created_at";
+                }
+            }
+            for item in items {
+                if item.price <= 0 { return `price ${item.price} is not positive`; }
+            }
+            status == 200 && headers["content-type"] == "application/json" && duration_ms == 42.0
+        "#;
+
+        assert_eq!(Ok(()), run(script, &input(&headers, body)));
+        assert_eq!(
+            Err("price -1 is not positive".to_owned()),
+            run(
+                script,
+                &input(&headers, r#"{"items":[{"created_at":"x","price":-1}]}"#)
+            )
+        );
+    }
+
+    #[test]
+    fn test_script_results_other_than_true_fail() {
+        let headers = HashMap::new();
+
+        assert_eq!(
+            Err("script returned false".to_owned()),
+            run("body_text == \"ok\"", &input(&headers, "down"))
+        );
+        assert_eq!(
+            Err("script returned i64, expected true or an error string".to_owned()),
+            run("1 + 1", &input(&headers, ""))
+        );
+        assert!(run("throw \"boom\"", &input(&headers, ""))
+            .unwrap_err()
+            .contains("boom"));
+    }
+
+    #[test]
+    fn test_script_limits() {
+        let headers = HashMap::new();
+
+        let error = run("loop {}", &input(&headers, "")).unwrap_err();
+        assert!(error.starts_with("script error:"), "{}", error);
+        assert!(run("eval(\"true\")", &input(&headers, "")).is_err());
+    }
+
+    #[test]
+    fn test_syntax_errors_are_reported_on_compile() {
+        assert!(compile("true").is_ok());
+        assert!(compile("if (").unwrap_err().starts_with("invalid script:"));
+    }
+}
@@ -0,0 +1,525 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use regex::Regex;
+
+use super::model::{PhaseTiming, SftpDetails, SftpErrorKind, SftpParameters};
+
+const DEFAULT_SFTP_PORT: u16 = 22;
+
+pub struct SftpCheckOutcome {
+    pub phases: Vec<PhaseTiming>,
+    pub details: SftpDetails,
+    pub error: Option<SftpCheckError>,
+}
+
+// The phase the check stopped at and why
+#[derive(Debug)]
+pub struct SftpCheckError {
+    pub kind: SftpErrorKind,
+    pub phase: String,
+    pub message: String,
+}
+
+impl std::fmt::Display for SftpCheckError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "SFTP check failed during {} ({}): {}",
+            self.phase,
+            self.kind.as_str(),
+            self.message
+        )
+    }
+}
+
+impl std::error::Error for SftpCheckError {}
+
+fn fail(kind: SftpErrorKind, phase: &str, message: impl Into<String>) -> SftpCheckError {
+    SftpCheckError {
+        kind,
+        phase: phase.to_owned(),
+        message: message.into(),
+    }
+}
+
+// A file listed in `list_path`, without its modification time when the server doesn't send one
+#[derive(Debug, Clone, PartialEq)]
+pub struct SftpEntry {
+    pub name: String,
+    pub modified: Option<DateTime<Utc>>,
+}
+
+// Accepts `sftp://host:port`, `host:port` or `host`
+pub fn parse_sftp_address(url: &str) -> Result<(String, u16), String> {
+    let address = url.strip_prefix("sftp://").unwrap_or(url);
+    let address = address.trim_end_matches('/');
+    match address.rsplit_once(':') {
+        Some((host, port)) => port
+            .parse::<u16>()
+            .map(|port| (host.to_owned(), port))
+            .map_err(|_| format!("invalid port in SFTP address '{}'", url)),
+        None if !address.is_empty() => Ok((address.to_owned(), DEFAULT_SFTP_PORT)),
+        None => Err("SFTP address is empty".to_owned()),
+    }
+}
+
+// `*` matches any run of characters and `?` a single one, everything else matches itself
+pub fn file_pattern(pattern: &str) -> Result<Regex, String> {
+    let escaped = regex::escape(pattern)
+        .replace(r"\*", ".*")
+        .replace(r"\?", ".");
+    Regex::new(&format!("^{}$", escaped))
+        .map_err(|e| format!("invalid file pattern '{}': {}", pattern, e))
+}
+
+// Records the newest file matching `expect_file_matching` and fails when there is none, or when it
+// is older than `max_age_hours`
+fn check_entries(
+    params: &SftpParameters,
+    entries: &[SftpEntry],
+    now: DateTime<Utc>,
+    details: &mut SftpDetails,
+) -> Result<(), SftpCheckError> {
+    let Some(pattern) = &params.expect_file_matching else {
+        return Ok(());
+    };
+    let matcher = file_pattern(pattern).map_err(|e| fail(SftpErrorKind::Expectation, "list", e))?;
+    let newest = entries
+        .iter()
+        .filter(|entry| matcher.is_match(&entry.name))
+        .max_by_key(|entry| entry.modified)
+        .ok_or_else(|| {
+            fail(
+                SftpErrorKind::Expectation,
+                "list",
+                format!("no file matches '{}'", pattern),
+            )
+        })?;
+    details.matched_file = Some(newest.name.clone());
+    details.matched_file_modified = newest.modified;
+    let Some(max_age_hours) = params.max_age_hours else {
+        return Ok(());
+    };
+    let Some(modified) = newest.modified else {
+        return Err(fail(
+            SftpErrorKind::Expectation,
+            "list",
+            format!("the server sent no modification time for '{}'", newest.name),
+        ));
+    };
+    let age = now - modified;
+    if age > chrono::Duration::hours(max_age_hours as i64) {
+        return Err(fail(
+            SftpErrorKind::Expectation,
+            "list",
+            format!(
+                "newest match '{}' is {}h old, more than {}h",
+                newest.name,
+                age.num_hours(),
+                max_age_hours
+            ),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(feature = "sftp")]
+mod session {
+    use std::path::PathBuf;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use chrono::DateTime;
+    use russh::client::{self, Handle};
+    use russh::keys::ssh_key::{HashAlg, PublicKey};
+    use russh::keys::{check_known_hosts_path, load_secret_key, PrivateKeyWithHashAlg};
+    use russh::Disconnect;
+    use russh_sftp::client::SftpSession;
+    use tokio::time::Instant;
+
+    use super::{fail, SftpCheckError, SftpEntry};
+    use crate::probe::model::{PhaseTiming, SftpErrorKind, SftpParameters};
+
+    // Accepts the server only when its key matches the fingerprint or known_hosts file
+    struct HostKeyCheck {
+        host: String,
+        port: u16,
+        fingerprint: Option<String>,
+        known_hosts: Option<PathBuf>,
+        accept_any: bool,
+        // Why the key was rejected, the handshake error only says that it was
+        rejected: Arc<Mutex<Option<String>>>,
+    }
+
+    fn timing(phase: &str, started: Instant) -> PhaseTiming {
+        PhaseTiming {
+            name: phase.to_owned(),
+            duration_ms: started.elapsed().as_millis() as u64,
+        }
+    }
+
+    impl HostKeyCheck {
+        fn verify(&self, key: &PublicKey) -> Result<(), String> {
+            if let Some(expected) = &self.fingerprint {
+                let actual = key.fingerprint(HashAlg::Sha256).to_string();
+                if actual != *expected {
+                    return Err(format!(
+                        "host key fingerprint {} doesn't match {}",
+                        actual, expected
+                    ));
+                }
+                return Ok(());
+            }
+            if let Some(path) = &self.known_hosts {
+                return match check_known_hosts_path(&self.host, self.port, key, path) {
+                    Ok(true) => Ok(()),
+                    Ok(false) => Err(format!(
+                        "{}:{} isn't listed in {}",
+                        self.host,
+                        self.port,
+                        path.display()
+                    )),
+                    Err(e) => Err(format!("host key doesn't match {}: {}", path.display(), e)),
+                };
+            }
+            if self.accept_any {
+                return Ok(());
+            }
+            Err("no fingerprint or known_hosts file to verify the host key".to_owned())
+        }
+    }
+
+    impl client::Handler for HostKeyCheck {
+        type Error = russh::Error;
+
+        async fn check_server_key(
+            &mut self,
+            server_public_key: &PublicKey,
+        ) -> Result<bool, Self::Error> {
+            let verified = self.verify(server_public_key);
+            if let Err(reason) = &verified {
+                *self.rejected.lock().unwrap() = Some(reason.clone());
+            }
+            Ok(verified.is_ok())
+        }
+    }
+
+    fn network(phase: &'static str) -> impl Fn(russh::Error) -> SftpCheckError {
+        move |e| fail(SftpErrorKind::Network, phase, e.to_string())
+    }
+
+    // Each phase is timed out on its own, returns the listing of `list_path` when there is one
+    pub async fn run(
+        host: &str,
+        port: u16,
+        params: &SftpParameters,
+        timeout: Duration,
+        phases: &mut Vec<PhaseTiming>,
+    ) -> Result<Option<Vec<SftpEntry>>, SftpCheckError> {
+        let timed_out = |phase: &str| {
+            fail(
+                SftpErrorKind::Network,
+                phase,
+                format!("timed out after {:?}", timeout),
+            )
+        };
+
+        let started = Instant::now();
+        let rejected = Arc::new(Mutex::new(None));
+        let handler = HostKeyCheck {
+            host: host.to_owned(),
+            port,
+            fingerprint: params.host_key_fingerprint.clone(),
+            known_hosts: params.known_hosts.clone(),
+            accept_any: params.accept_any_host_key,
+            rejected: rejected.clone(),
+        };
+        let config = Arc::new(client::Config {
+            inactivity_timeout: Some(timeout),
+            ..Default::default()
+        });
+        let connected =
+            tokio::time::timeout(timeout, client::connect(config, (host, port), handler))
+                .await
+                .map_err(|_| timed_out("connect"))?;
+        let mut session = connected.map_err(|e| match rejected.lock().unwrap().take() {
+            Some(reason) => fail(SftpErrorKind::HostKey, "connect", reason),
+            None => fail(SftpErrorKind::Network, "connect", e.to_string()),
+        })?;
+        phases.push(timing("connect", started));
+
+        let started = Instant::now();
+        tokio::time::timeout(timeout, authenticate(&mut session, params))
+            .await
+            .map_err(|_| timed_out("auth"))??;
+        phases.push(timing("auth", started));
+
+        let entries = match &params.list_path {
+            Some(path) => {
+                let started = Instant::now();
+                let entries = tokio::time::timeout(timeout, list(&session, path))
+                    .await
+                    .map_err(|_| timed_out("list"))??;
+                phases.push(timing("list", started));
+                Some(entries)
+            }
+            None => None,
+        };
+
+        // The check already passed, a failing disconnect shouldn't fail the probe
+        let _ = session
+            .disconnect(Disconnect::ByApplication, "", "en")
+            .await;
+        Ok(entries)
+    }
+
+    async fn authenticate(
+        session: &mut Handle<HostKeyCheck>,
+        params: &SftpParameters,
+    ) -> Result<(), SftpCheckError> {
+        let result = match &params.private_key_path {
+            Some(path) => {
+                let key = load_secret_key(path, params.private_key_passphrase.as_deref()).map_err(
+                    |e| {
+                        fail(
+                            SftpErrorKind::Auth,
+                            "auth",
+                            format!("can't load private key {}: {}", path.display(), e),
+                        )
+                    },
+                )?;
+                let hash_alg = session
+                    .best_supported_rsa_hash()
+                    .await
+                    .map_err(network("auth"))?
+                    .flatten();
+                session
+                    .authenticate_publickey(
+                        &params.username,
+                        PrivateKeyWithHashAlg::new(Arc::new(key), hash_alg),
+                    )
+                    .await
+            }
+            None => {
+                session
+                    .authenticate_password(
+                        &params.username,
+                        params.password.as_deref().unwrap_or_default(),
+                    )
+                    .await
+            }
+        }
+        .map_err(network("auth"))?;
+        if !result.success() {
+            return Err(fail(
+                SftpErrorKind::Auth,
+                "auth",
+                format!(
+                    "the server rejected the credentials of '{}'",
+                    params.username
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    async fn list(
+        session: &Handle<HostKeyCheck>,
+        path: &str,
+    ) -> Result<Vec<SftpEntry>, SftpCheckError> {
+        let channel = session
+            .channel_open_session()
+            .await
+            .map_err(network("list"))?;
+        channel
+            .request_subsystem(true, "sftp")
+            .await
+            .map_err(network("list"))?;
+        let sftp = SftpSession::new(channel.into_stream())
+            .await
+            .map_err(|e| fail(SftpErrorKind::Network, "list", e.to_string()))?;
+        let entries = sftp.read_dir(path).await.map_err(|e| {
+            fail(
+                SftpErrorKind::Path,
+                "list",
+                format!("can't list '{}': {}", path, e),
+            )
+        })?;
+        Ok(entries
+            .map(|entry| SftpEntry {
+                name: entry.file_name(),
+                modified: entry
+                    .metadata()
+                    .mtime
+                    .and_then(|mtime| DateTime::from_timestamp(mtime as i64, 0)),
+            })
+            .collect())
+    }
+}
+
+#[cfg(not(feature = "sftp"))]
+const DISABLED: &str = "sftp probes need xbp-monitoring built with the `sftp` feature";
+
+// Checked when the config is loaded, so probes never run without the feature
+#[cfg(feature = "sftp")]
+pub fn supported() -> Result<(), String> {
+    Ok(())
+}
+
+#[cfg(not(feature = "sftp"))]
+pub fn supported() -> Result<(), String> {
+    Err(DISABLED.to_owned())
+}
+
+#[cfg(feature = "sftp")]
+async fn run_session(
+    host: &str,
+    port: u16,
+    params: &SftpParameters,
+    timeout: Duration,
+    phases: &mut Vec<PhaseTiming>,
+) -> Result<Option<Vec<SftpEntry>>, SftpCheckError> {
+    session::run(host, port, params, timeout, phases).await
+}
+
+#[cfg(not(feature = "sftp"))]
+async fn run_session(
+    _host: &str,
+    _port: u16,
+    _params: &SftpParameters,
+    _timeout: Duration,
+    _phases: &mut Vec<PhaseTiming>,
+) -> Result<Option<Vec<SftpEntry>>, SftpCheckError> {
+    Err(fail(SftpErrorKind::Unsupported, "connect", DISABLED))
+}
+
+async fn run_check(
+    url: &str,
+    params: &SftpParameters,
+    timeout: Duration,
+    phases: &mut Vec<PhaseTiming>,
+    details: &mut SftpDetails,
+) -> Result<(), SftpCheckError> {
+    let (host, port) =
+        parse_sftp_address(url).map_err(|e| fail(SftpErrorKind::Network, "connect", e))?;
+    let entries = run_session(&host, port, params, timeout, phases).await?;
+    check_entries(params, &entries.unwrap_or_default(), Utc::now(), details)
+}
+
+pub async fn check_sftp(url: &str, params: &SftpParameters, timeout: Duration) -> SftpCheckOutcome {
+    let mut phases = vec![];
+    let mut details = SftpDetails::default();
+    let error = run_check(url, params, timeout, &mut phases, &mut details)
+        .await
+        .err();
+    details.error_kind = error.as_ref().map(|e| e.kind);
+    SftpCheckOutcome {
+        phases,
+        details,
+        error,
+    }
+}
+
+#[cfg(test)]
+mod sftp_tests {
+    use chrono::{Duration, TimeZone, Utc};
+
+    use super::{check_entries, file_pattern, parse_sftp_address, SftpEntry};
+    use crate::probe::model::{SftpDetails, SftpErrorKind, SftpParameters};
+
+    #[test]
+    fn test_parse_sftp_address() {
+        assert_eq!(
+            ("files.example.com".to_owned(), 2222),
+            parse_sftp_address("sftp://files.example.com:2222").unwrap()
+        );
+        assert_eq!(
+            ("files.example.com".to_owned(), 22),
+            parse_sftp_address("files.example.com").unwrap()
+        );
+        assert!(parse_sftp_address("sftp://files.example.com:ssh").is_err());
+    }
+
+    #[test]
+    fn test_file_patterns_match_whole_names() {
+        let pattern = file_pattern("invoice_*.csv").unwrap();
+
+        assert!(pattern.is_match("invoice_2024-07-08.csv"));
+        assert!(!pattern.is_match("invoice_2024-07-08.csv.tmp"));
+        assert!(!pattern.is_match("old_invoice_1.csv"));
+        assert!(!pattern.is_match("invoice_1xcsv"));
+        assert!(file_pattern("report_?.txt")
+            .unwrap()
+            .is_match("report_1.txt"));
+    }
+
+    #[test]
+    fn test_newest_match_must_be_fresh() {
+        let now = Utc.with_ymd_and_hms(2024, 7, 8, 12, 0, 0).unwrap();
+        let entry = |name: &str, hours_old: i64| SftpEntry {
+            name: name.to_owned(),
+            modified: Some(now - Duration::hours(hours_old)),
+        };
+        let entries = vec![
+            entry("invoice_1.csv", 30),
+            entry("invoice_2.csv", 20),
+            entry("notes.txt", 1),
+        ];
+        let params = SftpParameters {
+            expect_file_matching: Some("invoice_*.csv".to_owned()),
+            max_age_hours: Some(24),
+            ..Default::default()
+        };
+
+        let mut details = SftpDetails::default();
+        check_entries(&params, &entries, now, &mut details).unwrap();
+        assert_eq!(Some("invoice_2.csv"), details.matched_file.as_deref());
+
+        let stale = SftpParameters {
+            max_age_hours: Some(12),
+            ..params.clone()
+        };
+        let error = check_entries(&stale, &entries, now, &mut SftpDetails::default()).unwrap_err();
+        assert_eq!(SftpErrorKind::Expectation, error.kind);
+        assert!(error.message.contains("20h old"));
+
+        let missing = SftpParameters {
+            expect_file_matching: Some("payment_*.csv".to_owned()),
+            ..params
+        };
+        let error =
+            check_entries(&missing, &entries, now, &mut SftpDetails::default()).unwrap_err();
+        assert_eq!("no file matches 'payment_*.csv'", error.message);
+    }
+
+    #[test]
+    fn test_sftp_probes_need_credentials_and_a_host_key() {
+        let params: SftpParameters = serde_yaml::from_str(
+            r#"
+username: xbp
+password: secret
+list_path: /outgoing
+expect_file_matching: "invoice_*.csv"
+max_age_hours: 26
+"#,
+        )
+        .unwrap();
+        assert!(params
+            .validate()
+            .unwrap_err()
+            .contains("host_key_fingerprint"));
+        assert!(!format!("{:?}", params).contains("secret"));
+
+        let verified = SftpParameters {
+            host_key_fingerprint: Some("SHA256:abc".to_owned()),
+            ..params.clone()
+        };
+        assert!(verified.validate().is_ok());
+        let without_credentials = SftpParameters {
+            password: None,
+            ..verified
+        };
+        assert!(without_credentials.validate().is_err());
+    }
+}
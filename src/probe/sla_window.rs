@@ -0,0 +1,169 @@
+// The hours of a probe's `sla_window`, only results inside them count toward its SLA uptime
+use chrono::{DateTime, Datelike, NaiveTime, Utc, Weekday};
+use chrono_tz::Tz;
+
+use super::model::SlaWindow;
+
+const ALL_DAYS: [Weekday; 7] = [
+    Weekday::Mon,
+    Weekday::Tue,
+    Weekday::Wed,
+    Weekday::Thu,
+    Weekday::Fri,
+    Weekday::Sat,
+    Weekday::Sun,
+];
+
+// An `sla_window` with its days, times and timezone parsed
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParsedSlaWindow {
+    days: Vec<Weekday>,
+    start: NaiveTime,
+    // None for "24:00", the end of the day
+    end: Option<NaiveTime>,
+    timezone: Tz,
+}
+
+fn parse_time(value: &str) -> Result<NaiveTime, String> {
+    NaiveTime::parse_from_str(value, "%H:%M")
+        .map_err(|_| format!("invalid `sla_window` time '{}', expected HH:MM", value))
+}
+
+fn parse_day(value: &str) -> Result<Weekday, String> {
+    value
+        .trim()
+        .parse::<Weekday>()
+        .map_err(|_| format!("invalid `sla_window` day '{}'", value))
+}
+
+// A single day like `sat` or a range like `mon-fri`, ranges may wrap around the week
+fn parse_days(value: &str) -> Result<Vec<Weekday>, String> {
+    let Some((first, last)) = value.split_once('-') else {
+        return Ok(vec![parse_day(value)?]);
+    };
+    let (first, last) = (parse_day(first)?, parse_day(last)?);
+    let mut days = vec![first];
+    let mut day = first;
+    while day != last {
+        day = day.succ();
+        days.push(day);
+    }
+    Ok(days)
+}
+
+impl ParsedSlaWindow {
+    pub fn parse(window: &SlaWindow) -> Result<ParsedSlaWindow, String> {
+        let days = match &window.days {
+            Some(days) => {
+                let mut parsed = vec![];
+                for value in days {
+                    parsed.extend(parse_days(value)?);
+                }
+                parsed
+            }
+            None => ALL_DAYS.to_vec(),
+        };
+        let start = parse_time(&window.start)?;
+        let end = match window.end.as_str() {
+            "24:00" => None,
+            end => Some(parse_time(end)?),
+        };
+        if end.is_some_and(|end| end <= start) {
+            return Err(format!(
+                "`sla_window` ends at {} before it starts at {}",
+                window.end, window.start
+            ));
+        }
+        let timezone = match &window.timezone {
+            Some(timezone) => timezone
+                .parse::<Tz>()
+                .map_err(|e| format!("invalid `sla_window` timezone '{}': {}", timezone, e))?,
+            None => Tz::UTC,
+        };
+        Ok(ParsedSlaWindow {
+            days,
+            start,
+            end,
+            timezone,
+        })
+    }
+
+    // Compares the local wall clock time, so the window keeps its hours across DST changes. A result
+    // at exactly the start belongs to the window, one at exactly the end doesn't.
+    pub fn contains(&self, timestamp: DateTime<Utc>) -> bool {
+        let local = timestamp.with_timezone(&self.timezone);
+        let time = local.time();
+        self.days.contains(&local.weekday())
+            && time >= self.start
+            && self.end.is_none_or(|end| time < end)
+    }
+}
+
+#[cfg(test)]
+mod sla_window_tests {
+    use chrono::{TimeZone, Utc};
+
+    use super::ParsedSlaWindow;
+    use crate::probe::model::SlaWindow;
+
+    fn business_hours() -> ParsedSlaWindow {
+        ParsedSlaWindow::parse(&SlaWindow {
+            days: Some(vec!["mon-fri".to_owned()]),
+            start: "08:00".to_owned(),
+            end: "18:00".to_owned(),
+            timezone: Some("Europe/Amsterdam".to_owned()),
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_start_is_inclusive_and_end_exclusive() {
+        let window = business_hours();
+
+        // Wednesday 2026-01-14, Amsterdam is UTC+1
+        let at = |h, m, s| Utc.with_ymd_and_hms(2026, 1, 14, h, m, s).unwrap();
+        assert!(!window.contains(at(6, 59, 59)));
+        assert!(window.contains(at(7, 0, 0)));
+        assert!(window.contains(at(16, 59, 59)));
+        assert!(!window.contains(at(17, 0, 0)));
+    }
+
+    #[test]
+    fn test_window_follows_the_wall_clock_across_dst() {
+        let window = business_hours();
+
+        // Friday 2026-03-27 is on CET (UTC+1), Monday 2026-03-30 on CEST (UTC+2)
+        assert!(window.contains(Utc.with_ymd_and_hms(2026, 3, 27, 7, 0, 0).unwrap()));
+        assert!(!window.contains(Utc.with_ymd_and_hms(2026, 3, 27, 17, 0, 0).unwrap()));
+        assert!(window.contains(Utc.with_ymd_and_hms(2026, 3, 30, 6, 0, 0).unwrap()));
+        assert!(!window.contains(Utc.with_ymd_and_hms(2026, 3, 30, 16, 0, 0).unwrap()));
+        // Sunday 2026-03-29, the day of the change, is a weekend day
+        assert!(!window.contains(Utc.with_ymd_and_hms(2026, 3, 29, 10, 0, 0).unwrap()));
+    }
+
+    #[test]
+    fn test_days_and_invalid_windows() {
+        let window = |days: Vec<&str>, start: &str, end: &str, timezone: Option<&str>| {
+            ParsedSlaWindow::parse(&SlaWindow {
+                days: Some(days.into_iter().map(str::to_owned).collect()),
+                start: start.to_owned(),
+                end: end.to_owned(),
+                timezone: timezone.map(str::to_owned),
+            })
+        };
+
+        // Saturday 2026-01-17 and Sunday 2026-01-18, wrapping around the week
+        let weekend = window(vec!["sat-sun"], "00:00", "24:00", None).unwrap();
+        assert!(weekend.contains(Utc.with_ymd_and_hms(2026, 1, 17, 0, 0, 0).unwrap()));
+        assert!(weekend.contains(Utc.with_ymd_and_hms(2026, 1, 18, 23, 59, 59).unwrap()));
+        assert!(!weekend.contains(Utc.with_ymd_and_hms(2026, 1, 19, 0, 0, 0).unwrap()));
+        let fri_mon = window(vec!["fri-mon"], "00:00", "24:00", None).unwrap();
+        assert!(fri_mon.contains(Utc.with_ymd_and_hms(2026, 1, 19, 12, 0, 0).unwrap()));
+        assert!(!fri_mon.contains(Utc.with_ymd_and_hms(2026, 1, 20, 12, 0, 0).unwrap()));
+
+        assert!(window(vec!["mon"], "18:00", "08:00", None).is_err());
+        assert!(window(vec!["mon"], "8am", "18:00", None).is_err());
+        assert!(window(vec!["someday"], "08:00", "18:00", None).is_err());
+        assert!(window(vec!["mon"], "08:00", "18:00", Some("Mars/Olympus")).is_err());
+    }
+}
@@ -0,0 +1,499 @@
+use std::time::Duration;
+
+use base64::Engine;
+use chrono::{DateTime, TimeZone, Utc};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::time::Instant;
+use tracing::debug;
+
+use super::model::{PhaseTiming, SmtpParameters, TlsDetails};
+
+const DEFAULT_SMTP_PORT: u16 = 25;
+const DEFAULT_EHLO_NAME: &str = "xbp-monitoring";
+
+pub struct SmtpCheckOutcome {
+    pub phases: Vec<PhaseTiming>,
+    pub supports_starttls: bool,
+    pub tls: Option<TlsDetails>,
+    pub error: Option<SmtpCheckError>,
+}
+
+// The phase the check stopped at and why
+#[derive(Debug)]
+pub struct SmtpCheckError {
+    pub phase: String,
+    pub message: String,
+}
+
+impl std::fmt::Display for SmtpCheckError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "SMTP check failed during {}: {}",
+            self.phase, self.message
+        )
+    }
+}
+
+impl std::error::Error for SmtpCheckError {}
+
+struct SmtpReply {
+    code: u16,
+    lines: Vec<String>,
+}
+
+impl SmtpReply {
+    fn text(&self) -> String {
+        self.lines.join(" ")
+    }
+}
+
+struct SmtpConnection<S> {
+    stream: BufReader<S>,
+    timeout: Duration,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> SmtpConnection<S> {
+    fn new(stream: S, timeout: Duration) -> SmtpConnection<S> {
+        SmtpConnection {
+            stream: BufReader::new(stream),
+            timeout,
+        }
+    }
+
+    // Reads a possibly multi-line reply, e.g. `250-first`, `250-second`, `250 last`
+    async fn read_reply(&mut self) -> Result<SmtpReply, String> {
+        let mut lines = vec![];
+        loop {
+            let mut line = String::new();
+            let read = tokio::time::timeout(self.timeout, self.stream.read_line(&mut line))
+                .await
+                .map_err(|_| "timed out waiting for reply".to_owned())?
+                .map_err(|e| e.to_string())?;
+            if read == 0 {
+                return Err("connection closed by server".to_owned());
+            }
+            let line = line.trim_end();
+            let code = line
+                .get(0..3)
+                .and_then(|code| code.parse::<u16>().ok())
+                .ok_or_else(|| format!("malformed reply '{}'", line))?;
+            let is_last = line.as_bytes().get(3) != Some(&b'-');
+            lines.push(line.get(4..).unwrap_or_default().to_owned());
+            if is_last {
+                return Ok(SmtpReply { code, lines });
+            }
+        }
+    }
+
+    async fn command(&mut self, command: &str) -> Result<SmtpReply, String> {
+        tokio::time::timeout(
+            self.timeout,
+            self.stream
+                .get_mut()
+                .write_all(format!("{}\r\n", command).as_bytes()),
+        )
+        .await
+        .map_err(|_| "timed out sending command".to_owned())?
+        .map_err(|e| e.to_string())?;
+        self.read_reply().await
+    }
+}
+
+struct SmtpSession<'a> {
+    params: &'a SmtpParameters,
+    phases: Vec<PhaseTiming>,
+    supports_starttls: bool,
+    tls: Option<TlsDetails>,
+}
+
+impl SmtpSession<'_> {
+    fn expected_code(&self, phase: &str, default: u16) -> u16 {
+        self.params
+            .expect
+            .reply_codes
+            .get(phase)
+            .copied()
+            .unwrap_or(default)
+    }
+
+    // Runs a timed phase and checks its reply code
+    async fn phase<S: AsyncRead + AsyncWrite + Unpin>(
+        &mut self,
+        connection: &mut SmtpConnection<S>,
+        phase: &str,
+        command: Option<&str>,
+        default_code: u16,
+    ) -> Result<SmtpReply, SmtpCheckError> {
+        let started = Instant::now();
+        let reply = match command {
+            Some(command) => connection.command(command).await,
+            None => connection.read_reply().await,
+        }
+        .map_err(|message| fail(phase, message))?;
+        self.phases.push(PhaseTiming {
+            name: phase.to_owned(),
+            duration_ms: started.elapsed().as_millis() as u64,
+        });
+
+        let expected = self.expected_code(phase, default_code);
+        if reply.code != expected {
+            return Err(fail(
+                phase,
+                format!(
+                    "expected reply code {}, received {} '{}'",
+                    expected,
+                    reply.code,
+                    reply.text()
+                ),
+            ));
+        }
+        Ok(reply)
+    }
+
+    fn ehlo_command(&self) -> String {
+        format!(
+            "EHLO {}",
+            self.params
+                .ehlo_name
+                .as_deref()
+                .unwrap_or(DEFAULT_EHLO_NAME)
+        )
+    }
+
+    // Everything after the (optional) TLS upgrade, identical for plain and encrypted connections
+    async fn transaction<S: AsyncRead + AsyncWrite + Unpin>(
+        &mut self,
+        connection: &mut SmtpConnection<S>,
+    ) -> Result<(), SmtpCheckError> {
+        if let (Some(username), Some(password)) = (&self.params.username, &self.params.password) {
+            let credentials = base64::engine::general_purpose::STANDARD
+                .encode(format!("\0{}\0{}", username, password));
+            self.phase(
+                connection,
+                "auth",
+                Some(&format!("AUTH PLAIN {}", credentials)),
+                235,
+            )
+            .await?;
+        }
+
+        if let Some(mail_from) = &self.params.mail_from {
+            self.phase(
+                connection,
+                "mail_from",
+                Some(&format!("MAIL FROM:<{}>", mail_from)),
+                250,
+            )
+            .await?;
+            if let Some(rcpt_to) = &self.params.rcpt_to {
+                self.phase(
+                    connection,
+                    "rcpt_to",
+                    Some(&format!("RCPT TO:<{}>", rcpt_to)),
+                    250,
+                )
+                .await?;
+            }
+            self.phase(connection, "rset", Some("RSET"), 250).await?;
+        }
+
+        // The check already passed, a failing QUIT shouldn't fail the probe
+        let _ = connection.command("QUIT").await;
+        Ok(())
+    }
+}
+
+fn fail(phase: &str, message: String) -> SmtpCheckError {
+    SmtpCheckError {
+        phase: phase.to_owned(),
+        message,
+    }
+}
+
+// Accepts `smtp://host:port`, `host:port` or `host`
+pub fn parse_smtp_address(url: &str) -> Result<(String, u16), String> {
+    let address = url.strip_prefix("smtp://").unwrap_or(url);
+    let address = address.trim_end_matches('/');
+    match address.rsplit_once(':') {
+        Some((host, port)) => port
+            .parse::<u16>()
+            .map(|port| (host.to_owned(), port))
+            .map_err(|_| format!("invalid port in SMTP address '{}'", url)),
+        None if !address.is_empty() => Ok((address.to_owned(), DEFAULT_SMTP_PORT)),
+        None => Err("SMTP address is empty".to_owned()),
+    }
+}
+
+fn certificate_not_after(
+    tls_stream: &tokio_native_tls::TlsStream<TcpStream>,
+) -> Option<DateTime<Utc>> {
+    let certificate = tls_stream.get_ref().peer_certificate().ok()??;
+    let der = certificate.to_der().ok()?;
+    let (_, parsed) = x509_parser::parse_x509_certificate(&der).ok()?;
+    Utc.timestamp_opt(parsed.validity().not_after.timestamp(), 0)
+        .single()
+}
+
+pub async fn check_smtp(url: &str, params: &SmtpParameters, timeout: Duration) -> SmtpCheckOutcome {
+    let mut session = SmtpSession {
+        params,
+        phases: vec![],
+        supports_starttls: false,
+        tls: None,
+    };
+    let result = run_session(&mut session, url, timeout).await;
+    SmtpCheckOutcome {
+        phases: session.phases,
+        supports_starttls: session.supports_starttls,
+        tls: session.tls,
+        error: result.err(),
+    }
+}
+
+async fn run_session(
+    session: &mut SmtpSession<'_>,
+    url: &str,
+    timeout: Duration,
+) -> Result<(), SmtpCheckError> {
+    let (host, port) = parse_smtp_address(url).map_err(|message| fail("connect", message))?;
+
+    let connect_started = Instant::now();
+    let tcp_stream = tokio::time::timeout(timeout, TcpStream::connect((host.as_str(), port)))
+        .await
+        .map_err(|_| fail("connect", "timed out connecting".to_owned()))?
+        .map_err(|e| fail("connect", e.to_string()))?;
+    session.phases.push(PhaseTiming {
+        name: "connect".to_owned(),
+        duration_ms: connect_started.elapsed().as_millis() as u64,
+    });
+
+    let mut connection = SmtpConnection::new(tcp_stream, timeout);
+    session.phase(&mut connection, "banner", None, 220).await?;
+    if let Some(max_banner_ms) = session.params.expect.max_banner_ms {
+        let banner_ms = session.phases.last().map_or(0, |phase| phase.duration_ms);
+        if banner_ms > max_banner_ms {
+            return Err(fail(
+                "banner",
+                format!(
+                    "banner took {}ms, expected at most {}ms",
+                    banner_ms, max_banner_ms
+                ),
+            ));
+        }
+    }
+
+    let ehlo_command = session.ehlo_command();
+    let ehlo_reply = session
+        .phase(&mut connection, "ehlo", Some(&ehlo_command), 250)
+        .await?;
+    session.supports_starttls = ehlo_reply
+        .lines
+        .iter()
+        .any(|line| line.eq_ignore_ascii_case("STARTTLS"));
+    if let Some(expected) = session.params.expect.supports_starttls {
+        if expected != session.supports_starttls {
+            return Err(fail(
+                "ehlo",
+                format!(
+                    "expected supports_starttls to be {}, server advertised {}",
+                    expected, session.supports_starttls
+                ),
+            ));
+        }
+    }
+
+    if !session.params.starttls {
+        return session.transaction(&mut connection).await;
+    }
+
+    session
+        .phase(&mut connection, "starttls", Some("STARTTLS"), 220)
+        .await?;
+    let handshake_started = Instant::now();
+    let connector = native_tls::TlsConnector::new()
+        .map(tokio_native_tls::TlsConnector::from)
+        .map_err(|e| fail("tls_handshake", e.to_string()))?;
+    let tls_stream = tokio::time::timeout(
+        timeout,
+        connector.connect(&host, connection.stream.into_inner()),
+    )
+    .await
+    .map_err(|_| fail("tls_handshake", "timed out during handshake".to_owned()))?
+    .map_err(|e| fail("tls_handshake", e.to_string()))?;
+    session.phases.push(PhaseTiming {
+        name: "tls_handshake".to_owned(),
+        duration_ms: handshake_started.elapsed().as_millis() as u64,
+    });
+    session.tls = Some(TlsDetails {
+        certificate_not_after: certificate_not_after(&tls_stream),
+    });
+    debug!("Upgraded SMTP connection to {} to TLS", host);
+
+    let mut tls_connection = SmtpConnection::new(tls_stream, timeout);
+    session
+        .phase(&mut tls_connection, "ehlo_tls", Some(&ehlo_command), 250)
+        .await?;
+    session.transaction(&mut tls_connection).await
+}
+
+#[cfg(test)]
+mod smtp_tests {
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    use crate::probe::model::{Probe, ProbeType, SmtpExpectations, SmtpParameters};
+    use crate::probe::smtp_probe::{check_smtp, parse_smtp_address};
+
+    // Serves a scripted SMTP conversation: the banner, then one reply per received command
+    async fn fake_smtp_server(ehlo_extensions: &'static [&'static str]) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (read, mut write) = stream.into_split();
+            let mut lines = BufReader::new(read).lines();
+            write.write_all(b"220 fake ESMTP\r\n").await.unwrap();
+            while let Ok(Some(line)) = lines.next_line().await {
+                let reply = match line.split_whitespace().next().unwrap_or_default() {
+                    "EHLO" => {
+                        let mut reply = "250-fake greets you\r\n".to_owned();
+                        for extension in ehlo_extensions {
+                            reply.push_str(&format!("250-{}\r\n", extension));
+                        }
+                        reply.push_str("250 SIZE 1000\r\n");
+                        reply
+                    }
+                    "MAIL" => "250 OK\r\n".to_owned(),
+                    "RCPT" if line.contains("unknown@") => "550 No such user\r\n".to_owned(),
+                    "RCPT" => "250 OK\r\n".to_owned(),
+                    "RSET" => "250 OK\r\n".to_owned(),
+                    "QUIT" => "221 Bye\r\n".to_owned(),
+                    _ => "502 Not implemented\r\n".to_owned(),
+                };
+                write.write_all(reply.as_bytes()).await.unwrap();
+            }
+        });
+        format!("smtp://{}", address)
+    }
+
+    fn params(rcpt_to: &str) -> SmtpParameters {
+        SmtpParameters {
+            mail_from: Some("probe@example.com".to_owned()),
+            rcpt_to: Some(rcpt_to.to_owned()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_parse_smtp_address() {
+        assert_eq!(
+            ("mail.example.com".to_owned(), 587),
+            parse_smtp_address("smtp://mail.example.com:587").unwrap()
+        );
+        assert_eq!(
+            ("mail.example.com".to_owned(), 25),
+            parse_smtp_address("mail.example.com").unwrap()
+        );
+        assert!(parse_smtp_address("smtp://mail.example.com:port").is_err());
+    }
+
+    #[test]
+    fn test_smtp_probe_config_redacts_password() {
+        let probe: Probe = serde_yaml::from_str(
+            r#"
+            name: Mail relay
+            type: smtp
+            url: smtp://mail.example.com:25
+            schedule:
+              initial_delay: 0
+              interval: 60
+            smtp:
+              username: monitor
+              password: hunter2
+              expect:
+                supports_starttls: true
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(ProbeType::Smtp, probe.probe_type);
+        assert!(!format!("{:?}", probe).contains("hunter2"));
+        assert!(!serde_json::to_string(&probe).unwrap().contains("hunter2"));
+    }
+
+    #[tokio::test]
+    async fn test_smtp_check_success() {
+        let url = fake_smtp_server(&["STARTTLS"]).await;
+
+        let outcome = check_smtp(
+            &url,
+            &params("postmaster@example.com"),
+            Duration::from_secs(5),
+        )
+        .await;
+
+        assert!(outcome.error.is_none());
+        assert!(outcome.supports_starttls);
+        let phases: Vec<&str> = outcome
+            .phases
+            .iter()
+            .map(|phase| phase.name.as_str())
+            .collect();
+        assert_eq!(
+            vec!["connect", "banner", "ehlo", "mail_from", "rcpt_to", "rset"],
+            phases
+        );
+    }
+
+    #[tokio::test]
+    async fn test_smtp_check_reports_failing_phase() {
+        let url = fake_smtp_server(&[]).await;
+
+        let outcome =
+            check_smtp(&url, &params("unknown@example.com"), Duration::from_secs(5)).await;
+
+        let error = outcome.error.unwrap();
+        assert_eq!("rcpt_to", error.phase);
+        assert!(error.message.contains("550"));
+    }
+
+    #[tokio::test]
+    async fn test_smtp_check_expects_starttls() {
+        let url = fake_smtp_server(&[]).await;
+        let params = SmtpParameters {
+            expect: SmtpExpectations {
+                supports_starttls: Some(true),
+                max_banner_ms: None,
+                reply_codes: HashMap::new(),
+            },
+            ..Default::default()
+        };
+
+        let outcome = check_smtp(&url, &params, Duration::from_secs(5)).await;
+
+        let error = outcome.error.unwrap();
+        assert_eq!("ehlo", error.phase);
+        assert!(!outcome.supports_starttls);
+    }
+
+    #[tokio::test]
+    async fn test_smtp_connect_failure() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        drop(listener);
+
+        let outcome = check_smtp(
+            &format!("smtp://{}", address),
+            &SmtpParameters::default(),
+            Duration::from_secs(5),
+        )
+        .await;
+
+        assert_eq!("connect", outcome.error.unwrap().phase);
+    }
+}
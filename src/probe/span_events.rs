@@ -0,0 +1,139 @@
+use opentelemetry::trace::{SpanRef, Status};
+use opentelemetry::KeyValue;
+use opentelemetry_semantic_conventions as semconv;
+
+use crate::errors::ExpectationFailedError;
+use crate::probe::model::{ExpectField, FailedExpectation};
+
+// Bodies are cut off at the same length as the `response` event of the request span
+const ACTUAL_VALUE_LIMIT: usize = 500;
+
+// The expected and received value of a failed expectation. The received value is left out for
+// sensitive monitors.
+pub(crate) fn failed_expectation(
+    error: &ExpectationFailedError,
+    sensitive: bool,
+) -> FailedExpectation {
+    let actual = if sensitive {
+        "<redacted>".to_owned()
+    } else {
+        match error.field {
+            ExpectField::StatusCode => error.status_code.to_string(),
+            ExpectField::Body => error.body.chars().take(ACTUAL_VALUE_LIMIT).collect(),
+            ExpectField::Script => error.reason.clone().unwrap_or_default(),
+        }
+    };
+    FailedExpectation {
+        field: error.field.clone(),
+        operation: error.operation.clone(),
+        expected: error.expected.clone(),
+        actual,
+    }
+}
+
+// Adds an `expectation.failed` event with the expected and received value
+pub(crate) fn record_expectation_failure(
+    span: &SpanRef,
+    error: &ExpectationFailedError,
+    sensitive: bool,
+) {
+    let failed = failed_expectation(error, sensitive);
+    span.add_event(
+        "expectation.failed",
+        vec![
+            KeyValue::new(
+                "expectation.kind",
+                format!("{:?}.{:?}", failed.field, failed.operation),
+            ),
+            KeyValue::new("expected", failed.expected),
+            KeyValue::new("actual", failed.actual),
+        ],
+    );
+}
+
+// Marks the span as failed with the kind of error the run failed with, see `error_kind`
+pub(crate) fn set_error_status(span: &SpanRef, kind: &'static str) {
+    span.set_attribute(KeyValue::new(semconv::attribute::ERROR_TYPE, kind));
+    span.set_status(Status::error(kind));
+}
+
+#[cfg(test)]
+mod span_events_tests {
+    use opentelemetry::trace::{Status, TraceContextExt, Tracer, TracerProvider};
+    use opentelemetry::{Context, KeyValue, Value};
+    use opentelemetry_sdk::trace::{InMemorySpanExporter, SdkTracerProvider, SpanData};
+
+    use crate::errors::ExpectationFailedError;
+    use crate::probe::model::{ExpectField, ExpectOperation};
+
+    use super::{record_expectation_failure, set_error_status};
+
+    fn failure() -> ExpectationFailedError {
+        ExpectationFailedError {
+            field: ExpectField::Body,
+            expected: "ok".to_owned(),
+            body: "{\"status\":\"down\"}".to_owned(),
+            operation: ExpectOperation::Contains,
+            status_code: 200,
+            reason: None,
+        }
+    }
+
+    // Records into a span of a local provider, the global one is shared between tests
+    fn finished_span(record: impl FnOnce(&Context)) -> SpanData {
+        let exporter = InMemorySpanExporter::default();
+        let provider = SdkTracerProvider::builder()
+            .with_simple_exporter(exporter.clone())
+            .build();
+        let tracer = provider.tracer("span_events_tests");
+        let cx = Context::default().with_span(tracer.start("probe"));
+        record(&cx);
+        cx.span().end();
+        exporter.get_finished_spans().unwrap().remove(0)
+    }
+
+    fn attribute(attributes: &[KeyValue], key: &str) -> Option<Value> {
+        attributes
+            .iter()
+            .find(|attribute| attribute.key.as_str() == key)
+            .map(|attribute| attribute.value.clone())
+    }
+
+    #[test]
+    fn test_expectation_failure_event() {
+        let span = finished_span(|cx| {
+            record_expectation_failure(&cx.span(), &failure(), false);
+            set_error_status(&cx.span(), "expectation");
+        });
+
+        let event = &span.events.events[0];
+        assert_eq!("expectation.failed", event.name);
+        assert_eq!(
+            Some(Value::from("Body.Contains")),
+            attribute(&event.attributes, "expectation.kind")
+        );
+        assert_eq!(
+            Some(Value::from("ok")),
+            attribute(&event.attributes, "expected")
+        );
+        assert_eq!(
+            Some(Value::from("{\"status\":\"down\"}")),
+            attribute(&event.attributes, "actual")
+        );
+        assert_eq!(Status::error("expectation"), span.status);
+        assert_eq!(
+            Some(Value::from("expectation")),
+            attribute(&span.attributes, "error.type")
+        );
+    }
+
+    #[test]
+    fn test_sensitive_actual_value_is_redacted() {
+        let span = finished_span(|cx| record_expectation_failure(&cx.span(), &failure(), true));
+
+        assert_eq!(
+            Some(Value::from("<redacted>")),
+            attribute(&span.events.events[0].attributes, "actual")
+        );
+    }
+}
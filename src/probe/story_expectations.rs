@@ -0,0 +1,299 @@
+use std::collections::HashMap;
+
+use evalexpr::{ContextWithMutableVariables, EvalexprError, HashMapContext, Node, Value};
+use serde_json::Value as JsonValue;
+
+use super::model::{Story, StoryExpectation, StoryExpectationResult};
+
+// Captured values are referenced in expressions as `steps.<capture name>`
+const CAPTURE_PREFIX: &str = "steps.";
+
+// Parses every story expectation and checks it only references captures of the story's steps
+pub fn validate_story_expectations(story: &Story) -> Result<(), String> {
+    // Teardown runs after the expectations are evaluated, so its captures can't be referenced
+    let captures: Vec<&String> = story
+        .setup
+        .iter()
+        .flatten()
+        .chain(&story.steps)
+        .flat_map(|step| step.captures.iter().flat_map(|captures| captures.keys()))
+        .collect();
+    for expectation in story.expectations.iter().flatten() {
+        let node = parse(expectation)?;
+        for identifier in node.iter_variable_identifiers() {
+            let known = identifier
+                .strip_prefix(CAPTURE_PREFIX)
+                .is_some_and(|name| captures.iter().any(|capture| *capture == name));
+            if !known {
+                return Err(format!(
+                    "expression '{}' references '{}', which no step of the story captures",
+                    expectation.expr, identifier
+                ));
+            }
+        }
+        dry_run(expectation, &node)?;
+    }
+    Ok(())
+}
+
+// Operators missing an operand only show up when evaluating, so evaluate once with every capture
+// set to a number. Type errors are ignored here as the actual captures may be strings.
+fn dry_run(expectation: &StoryExpectation, node: &Node) -> Result<(), String> {
+    let mut context = HashMapContext::new();
+    for identifier in node.iter_variable_identifiers() {
+        let _ = context.set_value(identifier.to_owned(), Value::Float(1.0));
+    }
+    match node.eval_with_context(&context) {
+        Err(
+            e @ (EvalexprError::WrongOperatorArgumentAmount { .. }
+            | EvalexprError::WrongFunctionArgumentAmount { .. }
+            | EvalexprError::FunctionIdentifierNotFound(_)),
+        ) => Err(format!("invalid expression '{}': {}", expectation.expr, e)),
+        _ => Ok(()),
+    }
+}
+
+fn parse(expectation: &StoryExpectation) -> Result<Node, String> {
+    evalexpr::build_operator_tree(&expectation.expr)
+        .map_err(|e| format!("invalid expression '{}': {}", expectation.expr, e))
+}
+
+fn to_expression_value(value: &JsonValue) -> Value {
+    match value {
+        // Floats only, so that e.g. `10 + 20 == 30.0` compares equal
+        JsonValue::Number(number) => Value::Float(number.as_f64().unwrap_or(f64::NAN)),
+        JsonValue::String(string) => Value::String(string.clone()),
+        JsonValue::Bool(boolean) => Value::Boolean(*boolean),
+        other => Value::String(other.to_string()),
+    }
+}
+
+// Evaluates a story expectation against the values captured by the steps. When `sensitive` is set
+// the expression with substituted values is left out of the result.
+pub fn evaluate_story_expectation(
+    expectation: &StoryExpectation,
+    captures: &HashMap<String, JsonValue>,
+    sensitive: bool,
+) -> StoryExpectationResult {
+    let failed = |error_message: String, evaluated: Option<String>| StoryExpectationResult {
+        expr: expectation.expr.clone(),
+        success: false,
+        evaluated: evaluated.filter(|_| !sensitive),
+        error_message: Some(error_message),
+    };
+
+    let node = match parse(expectation) {
+        Ok(node) => node,
+        Err(e) => return failed(e, None),
+    };
+
+    let mut referenced: Vec<(String, Value)> = vec![];
+    for identifier in node.iter_variable_identifiers() {
+        if referenced.iter().any(|(name, _)| name == identifier) {
+            continue;
+        }
+        let captured = identifier
+            .strip_prefix(CAPTURE_PREFIX)
+            .and_then(|name| captures.get(name));
+        match captured {
+            Some(value) => referenced.push((identifier.to_owned(), to_expression_value(value))),
+            None => return failed(format!("{} was not captured", identifier), None),
+        }
+    }
+    let evaluated = substitute(&expectation.expr, &referenced);
+
+    let strings: Vec<&str> = referenced
+        .iter()
+        .filter(|(_, value)| value.is_string())
+        .map(|(name, _)| name.as_str())
+        .collect();
+    if let Some((number, _)) = referenced.iter().find(|(_, value)| value.is_number()) {
+        if !strings.is_empty() {
+            return failed(
+                format!(
+                    "type mismatch: {} is a string while {} is a number",
+                    strings.join(", "),
+                    number
+                ),
+                Some(evaluated),
+            );
+        }
+    }
+
+    let mut context = HashMapContext::new();
+    for (name, value) in &referenced {
+        // Setting a variable only fails when it changes type, every name is only set once
+        let _ = context.set_value(name.clone(), value.clone());
+    }
+    match node.eval_with_context(&context) {
+        Ok(Value::Boolean(true)) => StoryExpectationResult {
+            expr: expectation.expr.clone(),
+            success: true,
+            evaluated: Some(evaluated).filter(|_| !sensitive),
+            error_message: None,
+        },
+        Ok(Value::Boolean(false)) => failed(
+            format!("expression '{}' evaluated to false", expectation.expr),
+            Some(evaluated),
+        ),
+        Ok(other) => failed(
+            format!(
+                "expression '{}' evaluated to {} instead of a boolean",
+                expectation.expr,
+                // The value itself may be sensitive, its type isn't
+                if sensitive {
+                    "a non boolean value".to_owned()
+                } else {
+                    other.to_string()
+                }
+            ),
+            Some(evaluated),
+        ),
+        Err(e) if !strings.is_empty() => failed(
+            format!("type mismatch: {} is a string: {}", strings.join(", "), e),
+            Some(evaluated),
+        ),
+        Err(e) => failed(e.to_string(), Some(evaluated)),
+    }
+}
+
+// The expression with every capture replaced by its value, e.g. `10 + 20 == 30`
+fn substitute(expr: &str, referenced: &[(String, Value)]) -> String {
+    // Longest names first, so `steps.total` doesn't replace part of `steps.total_tax`
+    let mut by_length: Vec<&(String, Value)> = referenced.iter().collect();
+    by_length.sort_by_key(|(name, _)| std::cmp::Reverse(name.len()));
+    by_length
+        .iter()
+        .fold(expr.to_owned(), |expr, (name, value)| {
+            expr.replace(name.as_str(), &value.to_string())
+        })
+}
+
+#[cfg(test)]
+mod story_expectations_tests {
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    use serde_json::json;
+
+    use crate::probe::model::{Capture, ProbeScheduleParameters, Step, Story, StoryExpectation};
+    use crate::probe::story_expectations::{
+        evaluate_story_expectation, validate_story_expectations,
+    };
+
+    fn expectation(expr: &str) -> StoryExpectation {
+        StoryExpectation {
+            expr: expr.to_owned(),
+        }
+    }
+
+    fn captures() -> HashMap<String, serde_json::Value> {
+        HashMap::from([
+            ("cart1_total".to_owned(), json!(10)),
+            ("cart2_total".to_owned(), json!(20.5)),
+            ("invoice_total".to_owned(), json!(30.5)),
+            ("invoice_id".to_owned(), json!("inv-1")),
+        ])
+    }
+
+    #[test]
+    fn test_aggregate_expression_passes() {
+        let result = evaluate_story_expectation(
+            &expectation("steps.cart1_total + steps.cart2_total == steps.invoice_total"),
+            &captures(),
+            false,
+        );
+
+        assert!(result.success, "{:?}", result.error_message);
+        assert_eq!(Some("10 + 20.5 == 30.5".to_owned()), result.evaluated);
+    }
+
+    #[test]
+    fn test_false_expression_fails() {
+        let result = evaluate_story_expectation(
+            &expectation("steps.cart1_total > steps.invoice_total"),
+            &captures(),
+            false,
+        );
+
+        assert!(!result.success);
+        assert_eq!(Some("10 > 30.5".to_owned()), result.evaluated);
+    }
+
+    #[test]
+    fn test_type_mismatch_names_variable() {
+        let result = evaluate_story_expectation(
+            &expectation("steps.cart1_total + steps.invoice_id == steps.invoice_total"),
+            &captures(),
+            false,
+        );
+
+        assert!(!result.success);
+        let error_message = result.error_message.unwrap();
+        assert!(
+            error_message.contains("steps.invoice_id is a string"),
+            "{}",
+            error_message
+        );
+    }
+
+    #[test]
+    fn test_sensitive_story_hides_values() {
+        let result = evaluate_story_expectation(
+            &expectation("steps.cart1_total < steps.invoice_total"),
+            &captures(),
+            true,
+        );
+
+        assert!(result.success);
+        assert_eq!(None, result.evaluated);
+    }
+
+    #[test]
+    fn test_validation_rejects_invalid_expressions() {
+        let mut story = Story {
+            name: "checkout".to_owned(),
+            base_url: None,
+            setup: None,
+            steps: vec![Step {
+                name: "cart".to_owned(),
+                url: "http://localhost".to_owned(),
+                http_method: "GET".to_owned(),
+                with: None,
+                expectations: None,
+                sensitive: false,
+                body: None,
+                body_template: None,
+                captures: Some(HashMap::from([(
+                    "cart1_total".to_owned(),
+                    Capture::Path("total".to_owned()),
+                )])),
+            }],
+            teardown: None,
+            schedule: ProbeScheduleParameters {
+                initial_delay: Duration::ZERO,
+                interval: Duration::ZERO,
+                allow_fast: true,
+            },
+            alerts: None,
+            tags: None,
+            group: None,
+            recovery_threshold: None,
+            expectations: Some(vec![expectation("steps.cart1_total > 0")]),
+            sensitive: false,
+            alerts_include_details: false,
+            runtime_added: false,
+        };
+        assert!(validate_story_expectations(&story).is_ok());
+
+        story.expectations = Some(vec![expectation("steps.cart1_total + == 1")]);
+        assert!(validate_story_expectations(&story)
+            .unwrap_err()
+            .starts_with("invalid expression"));
+
+        story.expectations = Some(vec![expectation("steps.cart2_total > 0")]);
+        assert!(validate_story_expectations(&story)
+            .unwrap_err()
+            .contains("'steps.cart2_total'"));
+    }
+}
@@ -1,4 +1,3 @@
-
 use lazy_static::lazy_static;
 use regex::Regex;
 use serde_json::Value;
@@ -6,7 +5,7 @@ use std::collections::HashMap;
 use tracing::error;
 use uuid::Uuid;
 
-use super::model::ProbeInputParameters;
+use super::model::{Capture, HeaderCapture, ProbeOptions, Step};
 
 pub struct StoryVariables {
     pub steps: HashMap<String, StepVariables>,
@@ -22,6 +21,8 @@ impl StoryVariables {
 
 pub struct StepVariables {
     pub response_body: String,
+    // Referenced as `${{steps.<step>.captures.<name>}}`
+    pub captures: HashMap<String, Value>,
 }
 
 lazy_static! {
@@ -29,10 +30,10 @@ lazy_static! {
 }
 
 pub fn substitute_input_parameters(
-    input_parameters: &Option<ProbeInputParameters>,
+    input_parameters: &Option<ProbeOptions>,
     variables: &StoryVariables,
-) -> Option<ProbeInputParameters> {
-    input_parameters.as_ref().map(|input| ProbeInputParameters {
+) -> Option<ProbeOptions> {
+    input_parameters.as_ref().map(|input| ProbeOptions {
         body: input
             .body
             .as_ref()
@@ -41,10 +42,41 @@ pub fn substitute_input_parameters(
             .headers
             .as_ref()
             .map(|headers| substitute_variables_in_headers(headers, variables)),
+        timeout: input.timeout,
         timeout_seconds: input.timeout_seconds,
+        query: input
+            .query
+            .as_ref()
+            .map(|query| substitute_variables_in_headers(query, variables)),
+        auth: input.auth.clone(),
+        user_agent: input
+            .user_agent
+            .as_ref()
+            .map(|user_agent| substitute_variables(user_agent, variables)),
+        respect_retry_after: input.respect_retry_after,
+        follow_redirects: input.follow_redirects,
+        allow_empty: input.allow_empty.clone(),
+        samples: input.samples.clone(),
+        prefetch: input.prefetch.clone(),
     })
 }
 
+// The `with` block of a step with variables substituted, carrying the step's `body` or `body_template`
+pub fn step_input_parameters(step: &Step, variables: &StoryVariables) -> Option<ProbeOptions> {
+    let mut input_parameters = substitute_input_parameters(&step.with, variables);
+    let body = step.body.clone().or_else(|| {
+        step.body_template
+            .as_ref()
+            .map(|template| substitute_variables(template, variables))
+    });
+    if body.is_some() {
+        input_parameters
+            .get_or_insert_with(ProbeOptions::default)
+            .body = body;
+    }
+    input_parameters
+}
+
 pub fn substitute_variables_in_headers(
     headers: &HashMap<String, String>,
     variables: &StoryVariables,
@@ -86,6 +118,14 @@ fn substitute_step_value(parts: &[&str], variables: &StoryVariables) -> String {
     let step_name = parts[0];
 
     match variables.steps.get(step_name) {
+        Some(step) if parts.get(1) == Some(&"captures") => parts
+            .get(2)
+            .and_then(|name| step.captures.get(*name))
+            .map(json_value_to_string)
+            .unwrap_or_else(|| {
+                error!("Error: Capture '{}' not found.", parts[2..].join("."));
+                "".to_string()
+            }),
         Some(step) => {
             // TODO: We should check .response and .body
             if parts.len() > 3 {
@@ -125,6 +165,72 @@ fn get_nested_json_value(parts: &[&str], json_string: &String) -> String {
     json_value_to_string(current_value)
 }
 
+// Looks up each capture path in a JSON response body, paths that aren't found are left out. A
+// missing header fails the step unless the capture has a default.
+pub fn capture_values(
+    captures: &HashMap<String, Capture>,
+    response_body: &str,
+    headers: &HashMap<String, String>,
+    url: &str,
+) -> Result<HashMap<String, Value>, String> {
+    let mut values = HashMap::new();
+    let mut json_value = None;
+    for (name, capture) in captures {
+        match capture {
+            Capture::Header(capture) => {
+                let value = capture_header(capture, headers, url)
+                    .map_err(|e| format!("capture '{}': {}", name, e))?;
+                values.insert(name.clone(), Value::String(value));
+            }
+            Capture::Path(path) => {
+                // Only parsed for path captures, header captures work with any body
+                let json_value = json_value.get_or_insert_with(|| {
+                    serde_json::from_str::<Value>(response_body).map_err(|_| {
+                        error!(
+                            "Error parsing json response for captures: {}",
+                            response_body
+                        );
+                    })
+                });
+                let Ok(json_value) = json_value else {
+                    continue;
+                };
+                let value = path
+                    .split('.')
+                    .try_fold(&*json_value, |current, part| current.get(part));
+                match value {
+                    Some(value) => {
+                        values.insert(name.clone(), value.clone());
+                    }
+                    None => error!("Error finding capture {} at path {}", name, path),
+                }
+            }
+        }
+    }
+    Ok(values)
+}
+
+fn capture_header(
+    capture: &HeaderCapture,
+    headers: &HashMap<String, String>,
+    url: &str,
+) -> Result<String, String> {
+    let value = match headers.get(&capture.header.to_lowercase()) {
+        Some(value) => value.clone(),
+        None => capture
+            .default
+            .clone()
+            .ok_or_else(|| format!("the response has no {} header", capture.header))?,
+    };
+    if !capture.resolve_relative {
+        return Ok(value);
+    }
+    reqwest::Url::parse(url)
+        .and_then(|base| base.join(&value))
+        .map(|resolved| resolved.to_string())
+        .map_err(|e| format!("can't resolve '{}' against {}: {}", value, url, e))
+}
+
 fn json_value_to_string(value: &Value) -> String {
     match value {
         Value::String(s) => s.clone(),
@@ -132,6 +238,85 @@ fn json_value_to_string(value: &Value) -> String {
     }
 }
 
+// A step of the JSONPath subset meta-probes use: `$.services[*].name`, `services.*.name`, `items[0].id`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JsonPathStep {
+    Key(String),
+    Index(usize),
+    Wildcard,
+}
+
+pub fn parse_json_path(path: &str) -> Result<Vec<JsonPathStep>, String> {
+    let invalid = || format!("invalid JSONPath '{}'", path);
+    let trimmed = path.trim();
+    let trimmed = trimmed
+        .strip_prefix("$.")
+        .or_else(|| trimmed.strip_prefix('$'))
+        .unwrap_or(trimmed);
+    let mut steps = vec![];
+    for segment in trimmed.split('.').filter(|segment| !segment.is_empty()) {
+        let (key, mut brackets) = segment.split_at(segment.find('[').unwrap_or(segment.len()));
+        match key {
+            "" => {}
+            "*" => steps.push(JsonPathStep::Wildcard),
+            key => steps.push(JsonPathStep::Key(key.to_owned())),
+        }
+        while !brackets.is_empty() {
+            let end = brackets.find(']').ok_or_else(invalid)?;
+            let index = brackets.get(1..end).ok_or_else(invalid)?;
+            steps.push(match index {
+                "*" => JsonPathStep::Wildcard,
+                index => JsonPathStep::Index(index.parse().map_err(|_| invalid())?),
+            });
+            brackets = &brackets[end + 1..];
+            if !brackets.is_empty() && !brackets.starts_with('[') {
+                return Err(invalid());
+            }
+        }
+    }
+    if steps.is_empty() {
+        return Err(invalid());
+    }
+    Ok(steps)
+}
+
+// Every value the path selects, a wildcard fans out over the elements of an array or object
+pub fn json_path_values<'a>(steps: &[JsonPathStep], value: &'a Value) -> Vec<&'a Value> {
+    let Some((step, rest)) = steps.split_first() else {
+        return vec![value];
+    };
+    let selected: Vec<&Value> = match (step, value) {
+        (JsonPathStep::Key(key), _) => value.get(key).into_iter().collect(),
+        (JsonPathStep::Index(index), _) => value.get(index).into_iter().collect(),
+        (JsonPathStep::Wildcard, Value::Array(items)) => items.iter().collect(),
+        (JsonPathStep::Wildcard, Value::Object(fields)) => fields.values().collect(),
+        (JsonPathStep::Wildcard, _) => vec![],
+    };
+    selected
+        .into_iter()
+        .flat_map(|value| json_path_values(rest, value))
+        .collect()
+}
+
+// The names a meta-probe expands into, strings and numbers in order of appearance without duplicates
+pub fn expanded_names(path: &str, response_body: &str) -> Result<Vec<String>, String> {
+    let steps = parse_json_path(path)?;
+    let json_value = serde_json::from_str::<Value>(response_body)
+        .map_err(|e| format!("response is not JSON: {}", e))?;
+    let mut names: Vec<String> = vec![];
+    for value in json_path_values(&steps, &json_value) {
+        let name = match value {
+            Value::String(name) => name.clone(),
+            Value::Number(number) => number.to_string(),
+            _ => continue,
+        };
+        if !name.is_empty() && !names.contains(&name) {
+            names.push(name);
+        }
+    }
+    Ok(names)
+}
+
 #[tokio::test]
 async fn test_substitute_several_variables() {
     let content = r#"
@@ -151,6 +336,7 @@ async fn test_substitute_several_variables() {
             "get-token".to_string(),
             StepVariables {
                 response_body: body_str.to_string(),
+                captures: HashMap::new(),
             },
         )]),
     };
@@ -172,17 +358,27 @@ async fn test_substitute_input_parameters() {
             "get-token".to_string(),
             StepVariables {
                 response_body: body_str.to_string(),
+                captures: HashMap::new(),
             },
         )]),
     };
 
-    let input_parameters = Some(ProbeInputParameters {
+    let input_parameters = Some(ProbeOptions {
         body: Some("entire_body: ${{steps.get-token.response.body}}".to_owned()),
         headers: Some(HashMap::from([(
             "Authorization".to_owned(),
             "Bearer ${{steps.get-token.response.body.token}}".to_owned(),
         )])),
+        timeout: None,
         timeout_seconds: None,
+        query: None,
+        auth: None,
+        user_agent: None,
+        respect_retry_after: false,
+        follow_redirects: false,
+        allow_empty: None,
+        samples: None,
+        prefetch: None,
     });
 
     let result = substitute_input_parameters(&input_parameters, &variables);
@@ -192,6 +388,40 @@ async fn test_substitute_input_parameters() {
     );
 }
 
+#[test]
+fn test_step_body_and_body_template() {
+    let variables = StoryVariables {
+        steps: HashMap::from([(
+            "login".to_string(),
+            StepVariables {
+                response_body: r#"{"token": "12345"}"#.to_string(),
+                captures: HashMap::new(),
+            },
+        )]),
+    };
+    let mut step: Step = serde_yaml::from_str(
+        r#"
+name: create
+url: http://localhost/items
+body_template: '{"token": "${{steps.login.response.body.token}}"}'
+"#,
+    )
+    .unwrap();
+
+    assert_eq!("GET", step.http_method);
+    assert_eq!(
+        Some(r#"{"token": "12345"}"#.to_owned()),
+        step_input_parameters(&step, &variables).unwrap().body
+    );
+
+    step.body_template = None;
+    step.body = Some("${{steps.login.response.body.token}}".to_owned());
+    assert_eq!(
+        step.body,
+        step_input_parameters(&step, &variables).unwrap().body
+    );
+}
+
 #[tokio::test]
 async fn test_substitute_input_parameters_empty() {
     let result = substitute_input_parameters(&None, &StoryVariables::new());
@@ -212,6 +442,7 @@ async fn test_substitute_variable_doesnt_exist_in_json() {
             "get-token".to_string(),
             StepVariables {
                 response_body: body_str.to_string(),
+                captures: HashMap::new(),
             },
         )]),
     };
@@ -232,4 +463,138 @@ async fn test_substitute_variable_step_doesnt_exist() {
     assert_eq!("field: ".to_owned(), result);
 }
 
-// TODO test what happens with spaces in the ${{ steps.etc }}
\ No newline at end of file
+#[test]
+fn test_capture_values() {
+    let captures = HashMap::from([
+        ("total".to_owned(), Capture::Path("cart.total".to_owned())),
+        (
+            "missing".to_owned(),
+            Capture::Path("cart.missing".to_owned()),
+        ),
+    ]);
+
+    let values = capture_values(
+        &captures,
+        r#"{"cart": {"total": 12.5}}"#,
+        &HashMap::new(),
+        "http://localhost/cart",
+    )
+    .unwrap();
+    assert_eq!(Some(&serde_json::json!(12.5)), values.get("total"));
+    assert!(!values.contains_key("missing"));
+}
+
+#[test]
+fn test_capture_header_values() {
+    let header = |name: &str| HeaderCapture {
+        header: name.to_owned(),
+        default: None,
+        resolve_relative: false,
+    };
+    let headers = HashMap::from([
+        ("location".to_owned(), "/orders/42".to_owned()),
+        ("x-request-id".to_owned(), "req-1".to_owned()),
+    ]);
+    let captures = HashMap::from([
+        (
+            "created_url".to_owned(),
+            Capture::Header(HeaderCapture {
+                resolve_relative: true,
+                ..header("Location")
+            }),
+        ),
+        ("req_id".to_owned(), Capture::Header(header("X-Request-Id"))),
+        (
+            "trace".to_owned(),
+            Capture::Header(HeaderCapture {
+                default: Some("none".to_owned()),
+                ..header("X-Trace")
+            }),
+        ),
+    ]);
+
+    let values = capture_values(
+        &captures,
+        "not json",
+        &headers,
+        "http://localhost/api/orders",
+    )
+    .unwrap();
+    assert_eq!(
+        Some(&Value::from("http://localhost/orders/42")),
+        values.get("created_url")
+    );
+    assert_eq!(Some(&Value::from("req-1")), values.get("req_id"));
+    assert_eq!(Some(&Value::from("none")), values.get("trace"));
+
+    let missing = HashMap::from([("trace".to_owned(), Capture::Header(header("X-Trace")))]);
+    assert_eq!(
+        Err("capture 'trace': the response has no X-Trace header".to_owned()),
+        capture_values(&missing, "", &headers, "http://localhost/api/orders")
+    );
+}
+
+#[test]
+fn test_substitute_captured_values() {
+    let variables = StoryVariables {
+        steps: HashMap::from([(
+            "create".to_string(),
+            StepVariables {
+                response_body: "".to_string(),
+                captures: HashMap::from([(
+                    "created_url".to_owned(),
+                    Value::from("http://localhost/orders/42"),
+                )]),
+            },
+        )]),
+    };
+
+    assert_eq!(
+        "http://localhost/orders/42/items",
+        substitute_variables("${{steps.create.captures.created_url}}/items", &variables)
+    );
+}
+
+// TODO test what happens with spaces in the ${{ steps.etc }}
+
+#[test]
+fn test_json_path_parsing() {
+    assert_eq!(
+        Ok(vec![
+            JsonPathStep::Key("services".to_owned()),
+            JsonPathStep::Wildcard,
+            JsonPathStep::Key("name".to_owned()),
+        ]),
+        parse_json_path("$.services[*].name")
+    );
+    assert_eq!(
+        parse_json_path("services.*.name"),
+        parse_json_path("$.services[*].name")
+    );
+    assert_eq!(
+        Ok(vec![
+            JsonPathStep::Index(0),
+            JsonPathStep::Key("id".to_owned())
+        ]),
+        parse_json_path("$[0].id")
+    );
+    assert!(parse_json_path("$").is_err());
+    assert!(parse_json_path("items[first]").is_err());
+    assert!(parse_json_path("items[0").is_err());
+}
+
+#[test]
+fn test_expanded_names() {
+    let body = r#"{"services": [{"name": "billing"}, {"name": "search"}, {"name": "billing"}, {"id": 3}, {"name": 7}]}"#;
+
+    assert_eq!(
+        Ok(vec![
+            "billing".to_owned(),
+            "search".to_owned(),
+            "7".to_owned()
+        ]),
+        expanded_names("$.services[*].name", body)
+    );
+    assert_eq!(Ok(vec![]), expanded_names("$.missing[*].name", body));
+    assert!(expanded_names("$.services[*].name", "not json").is_err());
+}
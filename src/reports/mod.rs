@@ -0,0 +1,3 @@
+pub mod model;
+pub mod schedule;
+pub(crate) mod summary;
@@ -0,0 +1,25 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::probe::model::ProbeAlert;
+
+// A summary of all monitors in scope, sent to the report's alerts on a cron schedule
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Report {
+    pub name: String,
+    pub schedule: ReportSchedule,
+    pub alerts: Vec<ProbeAlert>,
+    // Only monitors carrying all of these tags are included
+    pub tags: Option<HashMap<String, String>>,
+    // Overrides the default compact table, see `reports::summary` for the placeholders
+    pub template: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReportSchedule {
+    // Standard 5 field cron expression, or 6 fields with leading seconds
+    pub cron: String,
+    // IANA timezone name such as `Europe/Amsterdam`, defaults to UTC
+    pub timezone: Option<String>,
+}
@@ -0,0 +1,178 @@
+use std::str::FromStr;
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use cron::Schedule;
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+
+use crate::app_state::AppState;
+use crate::reports::model::{Report, ReportSchedule};
+use crate::reports::summary::run_report;
+
+#[derive(Debug)]
+pub struct ReportError {
+    pub message: String,
+}
+
+impl std::error::Error for ReportError {}
+
+impl std::fmt::Display for ReportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Report error: {}", self.message)
+    }
+}
+
+// A report schedule with its cron expression and timezone parsed
+pub struct ParsedSchedule {
+    schedule: Schedule,
+    timezone: Tz,
+}
+
+impl ParsedSchedule {
+    pub fn parse(schedule: &ReportSchedule) -> Result<ParsedSchedule, ReportError> {
+        // The cron crate expects a leading seconds field, standard 5 field expressions run on second 0
+        let expression = match schedule.cron.split_whitespace().count() {
+            5 => format!("0 {}", schedule.cron),
+            _ => schedule.cron.clone(),
+        };
+        let cron = Schedule::from_str(&expression).map_err(|e| ReportError {
+            message: format!("invalid cron expression '{}': {}", schedule.cron, e),
+        })?;
+        let timezone = match &schedule.timezone {
+            Some(timezone) => timezone.parse::<Tz>().map_err(|e| ReportError {
+                message: format!("invalid timezone '{}': {}", timezone, e),
+            })?,
+            None => Tz::UTC,
+        };
+        Ok(ParsedSchedule {
+            schedule: cron,
+            timezone,
+        })
+    }
+
+    pub fn next_run(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        self.schedule
+            .after(&after.with_timezone(&self.timezone))
+            .next()
+            .map(|time| time.with_timezone(&Utc))
+    }
+
+    pub fn previous_run(&self, before: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        self.schedule
+            .after(&before.with_timezone(&self.timezone))
+            .next_back()
+            .map(|time| time.with_timezone(&Utc))
+    }
+
+    // The period a run at `end` covers, from the previous scheduled time until `end`
+    pub fn period_ending(&self, end: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let interval = self.next_run(end)? - self.previous_run(end)?;
+        Some(end - interval)
+    }
+}
+
+pub fn schedule_reports(reports: &[Report], app_state: Arc<AppState>) -> Vec<JoinHandle<()>> {
+    let mut handles = vec![];
+    for report in reports {
+        let schedule = match ParsedSchedule::parse(&report.schedule) {
+            Ok(schedule) => schedule,
+            Err(e) => {
+                error!("Not scheduling report {}: {}", report.name, e);
+                continue;
+            }
+        };
+        let report = report.clone();
+        let task_state = app_state.clone();
+        handles.push(tokio::spawn(async move {
+            reporting_loop(report, schedule, task_state).await;
+        }));
+    }
+    handles
+}
+
+async fn reporting_loop(report: Report, schedule: ParsedSchedule, app_state: Arc<AppState>) {
+    info!("Started scheduling report {}", report.name);
+
+    loop {
+        let now = Utc::now();
+        let Some(run_time) = schedule.next_run(now) else {
+            warn!("Report {} has no upcoming runs, stopping", report.name);
+            return;
+        };
+        let period_start = schedule.previous_run(run_time).unwrap_or(now);
+        tokio::time::sleep((run_time - now).to_std().unwrap_or_default()).await;
+
+        // Run in its own task so that a panic only loses this period's report
+        let task_report = report.clone();
+        let task_state = app_state.clone();
+        let result = tokio::spawn(async move {
+            run_report(&task_state, &task_report, period_start, run_time, true).await
+        })
+        .await;
+        match result {
+            Ok(run) if run.failed_alerts() > 0 => warn!(
+                "Report {} failed to send to {} channel(s), retrying next period",
+                report.name,
+                run.failed_alerts()
+            ),
+            Ok(_) => info!("Sent report {}", report.name),
+            Err(e) => error!(
+                "Report {} failed to generate, retrying next period: {}",
+                report.name, e
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod report_schedule_tests {
+    use chrono::{TimeZone, Utc};
+
+    use crate::reports::model::ReportSchedule;
+    use crate::reports::schedule::ParsedSchedule;
+
+    fn parse(cron: &str, timezone: Option<&str>) -> ParsedSchedule {
+        ParsedSchedule::parse(&ReportSchedule {
+            cron: cron.to_owned(),
+            timezone: timezone.map(str::to_owned),
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_weekly_schedule_in_timezone() {
+        // Mondays at 08:00 in Amsterdam, which is 06:00 UTC in summer
+        let schedule = parse("0 8 * * Mon", Some("Europe/Amsterdam"));
+        let now = Utc.with_ymd_and_hms(2024, 7, 10, 12, 0, 0).unwrap();
+
+        assert_eq!(
+            Some(Utc.with_ymd_and_hms(2024, 7, 15, 6, 0, 0).unwrap()),
+            schedule.next_run(now)
+        );
+        assert_eq!(
+            Some(Utc.with_ymd_and_hms(2024, 7, 8, 6, 0, 0).unwrap()),
+            schedule.previous_run(now)
+        );
+        assert_eq!(
+            Some(Utc.with_ymd_and_hms(2024, 7, 3, 12, 0, 0).unwrap()),
+            schedule.period_ending(now)
+        );
+    }
+
+    #[test]
+    fn test_invalid_schedule() {
+        let invalid_cron = ParsedSchedule::parse(&ReportSchedule {
+            cron: "every monday".to_owned(),
+            timezone: None,
+        });
+        assert!(invalid_cron.is_err());
+
+        let invalid_timezone = ParsedSchedule::parse(&ReportSchedule {
+            cron: "0 8 * * *".to_owned(),
+            timezone: Some("Mars/Olympus".to_owned()),
+        });
+        assert!(invalid_timezone.is_err());
+    }
+}
@@ -0,0 +1,567 @@
+use std::collections::{BTreeSet, HashMap};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use tracing::warn;
+
+use crate::alerts::outbound_webhook::{alert_channel, send_report};
+use crate::alerts::template::render_template;
+use crate::app_state::AppState;
+use crate::errors::{AlertChannel, AlertError, TemplateError};
+use crate::probe::duration::as_millis_f64;
+use crate::reports::model::Report;
+use crate::self_alerts::record_alert_failures;
+
+// Number of monitors listed under `slowest`
+const SLOWEST_MONITORS: usize = 3;
+
+// Placeholders: report_name, period_start, period_end, uptime_percent, incident_count,
+// downtime_minutes, table, slowest, added, removed
+pub const DEFAULT_REPORT_TEMPLATE: &str =
+    "{{ report_name }} report, {{ period_start }} to {{ period_end }}
+Uptime {{ uptime_percent }}% | Incidents {{ incident_count }} | Downtime {{ downtime_minutes }} min
+
+{{ table }}
+
+Slowest: {{ slowest }}
+Added: {{ added }}
+Removed: {{ removed }}";
+
+// A single run of a monitor, as far as a report is concerned
+struct Sample {
+    timestamp: DateTime<Utc>,
+    success: bool,
+    duration: Option<Duration>,
+    // Inside the probe's `sla_window`, always true without one
+    in_sla_window: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MonitorSummary {
+    pub name: String,
+    // Only the runs inside the probe's `sla_window` count toward the uptime
+    pub runs: usize,
+    pub successes: usize,
+    // Runs that failed after the previous run succeeded
+    pub incidents: usize,
+    // Time from each failing run until the next run or the end of the period
+    pub downtime_minutes: f64,
+    // Rounded to microseconds, so fast monitors don't average to 0
+    pub avg_duration_ms: Option<f64>,
+}
+
+impl MonitorSummary {
+    pub fn uptime_percent(&self) -> Option<f64> {
+        (self.runs > 0).then(|| self.successes as f64 * 100.0 / self.runs as f64)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ReportSummary {
+    pub report_name: String,
+    pub period_start: DateTime<Utc>,
+    pub period_end: DateTime<Utc>,
+    pub monitors: Vec<MonitorSummary>,
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+impl ReportSummary {
+    pub fn uptime_percent(&self) -> Option<f64> {
+        let runs: usize = self.monitors.iter().map(|monitor| monitor.runs).sum();
+        let successes: usize = self.monitors.iter().map(|monitor| monitor.successes).sum();
+        (runs > 0).then(|| successes as f64 * 100.0 / runs as f64)
+    }
+
+    pub fn incident_count(&self) -> usize {
+        self.monitors.iter().map(|monitor| monitor.incidents).sum()
+    }
+
+    pub fn downtime_minutes(&self) -> f64 {
+        self.monitors
+            .iter()
+            .map(|monitor| monitor.downtime_minutes)
+            .sum()
+    }
+
+    pub fn slowest(&self) -> Vec<&MonitorSummary> {
+        let mut timed: Vec<&MonitorSummary> = self
+            .monitors
+            .iter()
+            .filter(|monitor| monitor.avg_duration_ms.is_some())
+            .collect();
+        timed.sort_by(|a, b| {
+            let average = |monitor: &MonitorSummary| monitor.avg_duration_ms.unwrap_or_default();
+            average(b).total_cmp(&average(a))
+        });
+        timed.truncate(SLOWEST_MONITORS);
+        timed
+    }
+
+    // Fixed width table with a row per monitor
+    pub fn table(&self) -> String {
+        let name_width = self
+            .monitors
+            .iter()
+            .map(|monitor| monitor.name.len())
+            .max()
+            .unwrap_or(0)
+            .max("MONITOR".len());
+        let mut lines = vec![format!(
+            "{:<name_width$}  {:>7}  {:>9}  {:>9}  {:>7}",
+            "MONITOR", "UPTIME", "INCIDENTS", "DOWN(MIN)", "AVG(MS)"
+        )];
+        for monitor in &self.monitors {
+            lines.push(format!(
+                "{:<name_width$}  {:>7}  {:>9}  {:>9.1}  {:>7}",
+                monitor.name,
+                format_percent(monitor.uptime_percent()),
+                monitor.incidents,
+                monitor.downtime_minutes,
+                monitor
+                    .avg_duration_ms
+                    .map_or("-".to_owned(), |ms| ms.to_string()),
+            ));
+        }
+        lines.join("\n")
+    }
+
+    pub fn render(&self, template: Option<&str>) -> Result<String, TemplateError> {
+        let list = |names: &[String]| match names.is_empty() {
+            true => "none".to_owned(),
+            false => names.join(", "),
+        };
+        let slowest = self
+            .slowest()
+            .iter()
+            .map(|monitor| format!("{} ({} ms)", monitor.name, monitor.avg_duration_ms.unwrap()))
+            .collect::<Vec<_>>();
+        let values = HashMap::from([
+            ("report_name", self.report_name.clone()),
+            (
+                "period_start",
+                self.period_start.format("%Y-%m-%d %H:%M UTC").to_string(),
+            ),
+            (
+                "period_end",
+                self.period_end.format("%Y-%m-%d %H:%M UTC").to_string(),
+            ),
+            ("uptime_percent", format_percent(self.uptime_percent())),
+            ("incident_count", self.incident_count().to_string()),
+            (
+                "downtime_minutes",
+                format!("{:.1}", self.downtime_minutes()),
+            ),
+            ("table", self.table()),
+            ("slowest", list(&slowest)),
+            ("added", list(&self.added)),
+            ("removed", list(&self.removed)),
+        ]);
+        render_template(template.unwrap_or(DEFAULT_REPORT_TEMPLATE), &values)
+    }
+}
+
+fn format_percent(percent: Option<f64>) -> String {
+    percent.map_or("-".to_owned(), |percent| format!("{:.2}", percent))
+}
+
+fn matches_tags(
+    monitor_tags: &Option<HashMap<String, String>>,
+    report_tags: &Option<HashMap<String, String>>,
+) -> bool {
+    let Some(report_tags) = report_tags else {
+        return true;
+    };
+    report_tags.iter().all(|(key, value)| {
+        monitor_tags
+            .as_ref()
+            .and_then(|tags| tags.get(key))
+            .is_some_and(|tag_value| tag_value == value)
+    })
+}
+
+// Samples of every monitor in scope of the report, keyed by monitor name
+fn collect_samples(app_state: &AppState, report: &Report) -> Vec<(String, Vec<Sample>)> {
+    let config = app_state.config();
+    let ignore_reloads = config.settings.ignore_results_during_reload;
+
+    let probes = config
+        .probes
+        .iter()
+        .filter(|probe| matches_tags(&probe.tags, &report.tags))
+        .map(|probe| {
+            let sla_window = probe.parsed_sla_window();
+            let samples = app_state
+                .probe_results
+                .read(&probe.name, |results| {
+                    results
+                        .iter()
+                        .filter(|result| !(ignore_reloads && result.during_reload))
+                        .map(|result| Sample {
+                            timestamp: result.timestamp_started,
+                            success: result.success,
+                            duration: result.duration,
+                            in_sla_window: sla_window
+                                .as_ref()
+                                .is_none_or(|window| window.contains(result.timestamp_started)),
+                        })
+                        .collect()
+                })
+                .unwrap_or_default();
+            (probe.name.clone(), samples)
+        });
+    let stories = config
+        .stories
+        .iter()
+        .filter(|story| matches_tags(&story.tags, &report.tags))
+        .map(|story| {
+            let samples = app_state
+                .story_results
+                .read(&story.name, |results| {
+                    results
+                        .iter()
+                        .filter(|result| !(ignore_reloads && result.during_reload))
+                        .map(|result| Sample {
+                            timestamp: result.timestamp_started,
+                            success: result.success,
+                            duration: result.duration,
+                            in_sla_window: true,
+                        })
+                        .collect()
+                })
+                .unwrap_or_default();
+            (story.name.clone(), samples)
+        });
+    probes.chain(stories).collect()
+}
+
+fn summarize_monitor(
+    name: String,
+    samples: &[Sample],
+    period_start: DateTime<Utc>,
+    period_end: DateTime<Utc>,
+) -> MonitorSummary {
+    let mut previous_success = samples
+        .iter()
+        .rfind(|sample| sample.timestamp < period_start)
+        .is_none_or(|sample| sample.success);
+    let in_period: Vec<&Sample> = samples
+        .iter()
+        .filter(|sample| sample.timestamp >= period_start && sample.timestamp < period_end)
+        .collect();
+
+    let mut summary = MonitorSummary {
+        name,
+        runs: in_period
+            .iter()
+            .filter(|sample| sample.in_sla_window)
+            .count(),
+        successes: 0,
+        incidents: 0,
+        downtime_minutes: 0.0,
+        avg_duration_ms: None,
+    };
+    for (index, sample) in in_period.iter().enumerate() {
+        if sample.success {
+            summary.successes += sample.in_sla_window as usize;
+        } else {
+            if previous_success {
+                summary.incidents += 1;
+            }
+            let down_until = in_period
+                .get(index + 1)
+                .map_or(period_end, |next| next.timestamp);
+            summary.downtime_minutes +=
+                (down_until - sample.timestamp).num_milliseconds() as f64 / 60_000.0;
+        }
+        previous_success = sample.success;
+    }
+
+    let durations: Vec<Duration> = in_period
+        .iter()
+        .filter_map(|sample| sample.duration)
+        .collect();
+    if !durations.is_empty() {
+        let average = durations.iter().sum::<Duration>() / durations.len() as u32;
+        summary.avg_duration_ms = Some((as_millis_f64(average) * 1_000.0).round() / 1_000.0);
+    }
+    summary
+}
+
+// Summarizes the stored history of the monitors in scope. Only the results still held in memory
+// are taken into account, so long periods of busy monitors only cover their most recent runs.
+// Added and removed monitors are relative to the previous scheduled run of the report.
+pub fn summarize(
+    app_state: &AppState,
+    report: &Report,
+    period_start: DateTime<Utc>,
+    period_end: DateTime<Utc>,
+    update_baseline: bool,
+) -> ReportSummary {
+    let monitors: Vec<MonitorSummary> = collect_samples(app_state, report)
+        .into_iter()
+        .map(|(name, samples)| summarize_monitor(name, &samples, period_start, period_end))
+        .collect();
+
+    let current: BTreeSet<String> = monitors
+        .iter()
+        .map(|monitor| monitor.name.clone())
+        .collect();
+    let mut baselines = app_state.report_baselines.write().unwrap();
+    let (added, removed) = match baselines.get(&report.name) {
+        Some(previous) => (
+            current.difference(previous).cloned().collect(),
+            previous.difference(&current).cloned().collect(),
+        ),
+        None => (vec![], vec![]),
+    };
+    if update_baseline {
+        baselines.insert(report.name.clone(), current);
+    }
+
+    ReportSummary {
+        report_name: report.name.clone(),
+        period_start,
+        period_end,
+        monitors,
+        added,
+        removed,
+    }
+}
+
+pub struct ReportRun {
+    pub text: String,
+    pub deliveries: Vec<(AlertChannel, Result<(), AlertError>)>,
+}
+
+impl ReportRun {
+    pub fn failed_alerts(&self) -> usize {
+        self.deliveries
+            .iter()
+            .filter(|(_, result)| result.is_err())
+            .count()
+    }
+}
+
+// Renders the report and sends it to each of its alerts, a failing channel doesn't stop the others
+pub async fn run_report(
+    app_state: &AppState,
+    report: &Report,
+    period_start: DateTime<Utc>,
+    period_end: DateTime<Utc>,
+    update_baseline: bool,
+) -> ReportRun {
+    let summary = summarize(app_state, report, period_start, period_end, update_baseline);
+    let rendered = summary.render(report.template.as_deref());
+
+    let alerts = app_state
+        .config
+        .read()
+        .unwrap()
+        .resolve_alerts(&Some(report.alerts.clone()))
+        .unwrap_or_default();
+    let mut deliveries = vec![];
+    for alert in &alerts {
+        let result = match &rendered {
+            Ok(text) => send_report(alert, &report.name, text).await,
+            Err(e) => Err(AlertError::new(
+                alert_channel(alert),
+                &report.name,
+                e.clone().into(),
+            )),
+        };
+        if let Err(e) = &result {
+            warn!("Failed to send report {}: {}", report.name, e);
+            record_alert_failures(app_state, 1);
+        }
+        deliveries.push((alert_channel(alert), result));
+    }
+    ReportRun {
+        text: rendered.unwrap_or_default(),
+        deliveries,
+    }
+}
+
+#[cfg(test)]
+mod summary_tests {
+    use std::collections::HashMap;
+
+    use chrono::{DateTime, Duration, TimeZone, Utc};
+
+    use crate::app_state::AppState;
+    use crate::config::Config;
+    use crate::probe::model::{ProbeResult, SlaWindow};
+    use crate::reports::model::{Report, ReportSchedule};
+    use crate::reports::summary::summarize;
+    use crate::test_utils::probe_test_utils::probe_get_with_expected_status;
+    use crate::test_utils::result_test_utils::ProbeResultBuilder;
+
+    fn start() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 7, 8, 6, 0, 0).unwrap()
+    }
+
+    fn result(name: &str, minutes: i64, success: bool, duration_ms: u64) -> ProbeResult {
+        ProbeResultBuilder::new(name)
+            .success(success)
+            .started_at(start() + Duration::minutes(minutes))
+            .duration(std::time::Duration::from_millis(duration_ms))
+            .response(200, "")
+            .build()
+    }
+
+    fn report(tags: Option<HashMap<String, String>>) -> Report {
+        Report {
+            name: "weekly".to_owned(),
+            schedule: ReportSchedule {
+                cron: "0 8 * * Mon".to_owned(),
+                timezone: None,
+            },
+            alerts: vec![],
+            tags,
+            template: None,
+        }
+    }
+
+    fn app_state(names: &[&str]) -> AppState {
+        let probes = names
+            .iter()
+            .map(|name| {
+                let mut probe = probe_get_with_expected_status(
+                    reqwest::StatusCode::OK,
+                    "http://localhost".to_owned(),
+                    "".to_owned(),
+                );
+                probe.name = name.to_string();
+                probe.tags = Some(HashMap::from([("team".to_owned(), name.to_string())]));
+                probe
+            })
+            .collect();
+        AppState::new(Config {
+            probes,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_summarize_counts_incidents_and_downtime() {
+        let app_state = app_state(&["api", "web"]);
+        for (minutes, success) in [
+            (-10, false),
+            (0, false),
+            (10, true),
+            (20, false),
+            (30, true),
+        ] {
+            app_state.add_probe_result("api".to_owned(), result("api", minutes, success, 100));
+        }
+        app_state.add_probe_result("web".to_owned(), result("web", 0, true, 300));
+
+        let summary = summarize(
+            &app_state,
+            &report(None),
+            start(),
+            start() + Duration::minutes(40),
+            false,
+        );
+
+        let api = &summary.monitors[0];
+        assert_eq!(4, api.runs);
+        assert_eq!(Some(50.0), api.uptime_percent());
+        // Already failing before the period started, so only the failure at 20 is a new incident
+        assert_eq!(1, api.incidents);
+        assert_eq!(20.0, api.downtime_minutes);
+        assert_eq!(Some(100.0), api.avg_duration_ms);
+
+        assert_eq!(Some(60.0), summary.uptime_percent());
+        assert_eq!("web", summary.slowest()[0].name);
+    }
+
+    #[test]
+    fn test_uptime_only_counts_runs_inside_the_sla_window() {
+        let mut probe = probe_get_with_expected_status(
+            reqwest::StatusCode::OK,
+            "http://localhost".to_owned(),
+            "".to_owned(),
+        );
+        probe.name = "api".to_owned();
+        probe.sla_window = Some(SlaWindow {
+            days: Some(vec!["mon".to_owned()]),
+            start: "06:00".to_owned(),
+            end: "06:30".to_owned(),
+            timezone: None,
+        });
+        let app_state = AppState::new(Config {
+            probes: vec![probe],
+            ..Default::default()
+        });
+        for (minutes, success) in [(0, false), (10, true), (30, false), (40, false)] {
+            app_state.add_probe_result("api".to_owned(), result("api", minutes, success, 100));
+        }
+
+        let summary = summarize(
+            &app_state,
+            &report(None),
+            start(),
+            start() + Duration::minutes(50),
+            false,
+        );
+
+        let api = &summary.monitors[0];
+        assert_eq!(2, api.runs);
+        assert_eq!(Some(50.0), api.uptime_percent());
+        // Failures outside the window still count as incidents and downtime
+        assert_eq!(2, api.incidents);
+        assert_eq!(30.0, api.downtime_minutes);
+    }
+
+    #[test]
+    fn test_summarize_scopes_by_tag() {
+        let app_state = app_state(&["api", "web"]);
+        let tags = Some(HashMap::from([("team".to_owned(), "web".to_owned())]));
+
+        let summary = summarize(&app_state, &report(tags), start(), start(), false);
+
+        assert_eq!(1, summary.monitors.len());
+        assert_eq!("web", summary.monitors[0].name);
+    }
+
+    #[test]
+    fn test_summarize_tracks_added_and_removed_monitors() {
+        let app_state = app_state(&["api", "web"]);
+        let report = report(None);
+        app_state.report_baselines.write().unwrap().insert(
+            "weekly".to_owned(),
+            ["api".to_owned(), "legacy".to_owned()].into(),
+        );
+
+        let summary = summarize(&app_state, &report, start(), start(), true);
+        assert_eq!(vec!["web".to_owned()], summary.added);
+        assert_eq!(vec!["legacy".to_owned()], summary.removed);
+
+        let summary = summarize(&app_state, &report, start(), start(), true);
+        assert!(summary.added.is_empty());
+        assert!(summary.removed.is_empty());
+    }
+
+    #[test]
+    fn test_default_template_renders_table() {
+        let app_state = app_state(&["api"]);
+        app_state.add_probe_result("api".to_owned(), result("api", 0, true, 120));
+
+        let text = summarize(
+            &app_state,
+            &report(None),
+            start(),
+            start() + Duration::days(7),
+            false,
+        )
+        .render(None)
+        .unwrap();
+
+        assert!(text.starts_with("weekly report, 2024-07-08 06:00 UTC to 2024-07-15 06:00 UTC"));
+        assert!(text.contains("MONITOR   UPTIME  INCIDENTS  DOWN(MIN)  AVG(MS)"));
+        assert!(text.contains("api       100.00          0        0.0      120"));
+        assert!(text.contains("Slowest: api (120 ms)"));
+        assert!(!text.contains("{{"));
+    }
+}
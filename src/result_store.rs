@@ -0,0 +1,542 @@
+// The stored results of every monitor of one kind, probes or stories. Each monitor keeps its latest
+// results in a ring buffer, plus a summary that outlives them. Stores sharing a `MemoryBudget`
+// account for the approximate memory of their results; `MemoryBudget::enforce` frees the oldest
+// response bodies across all of them first, then the oldest results.
+use std::collections::{HashMap, VecDeque};
+use std::mem::size_of;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+
+use chrono::{DateTime, Utc};
+
+use crate::probe::model::{ProbeResult, StepResult, StoryResult};
+
+pub trait StoredResult: Clone {
+    fn success(&self) -> bool;
+    fn timestamp_started(&self) -> DateTime<Utc>;
+    // Roughly what the result takes, the struct itself and the strings it owns
+    fn estimated_bytes(&self) -> usize;
+    fn has_body(&self) -> bool;
+    // Empties the response bodies, the rest of the result is kept
+    fn drop_bodies(&mut self);
+}
+
+impl StoredResult for ProbeResult {
+    fn success(&self) -> bool {
+        self.success
+    }
+
+    fn timestamp_started(&self) -> DateTime<Utc> {
+        self.timestamp_started
+    }
+
+    fn estimated_bytes(&self) -> usize {
+        size_of::<ProbeResult>()
+            + self.probe_name.len()
+            + self.error_message.as_ref().map_or(0, String::len)
+            + self
+                .response
+                .as_ref()
+                .map_or(0, |response| response.body.len())
+            + self.trace_id.as_ref().map_or(0, String::len)
+    }
+
+    fn has_body(&self) -> bool {
+        self.response
+            .as_ref()
+            .is_some_and(|response| !response.body.is_empty())
+    }
+
+    fn drop_bodies(&mut self) {
+        if let Some(response) = &mut self.response {
+            response.body = String::new();
+        }
+    }
+}
+
+impl StoredResult for StoryResult {
+    fn success(&self) -> bool {
+        self.success
+    }
+
+    fn timestamp_started(&self) -> DateTime<Utc> {
+        self.timestamp_started
+    }
+
+    fn estimated_bytes(&self) -> usize {
+        size_of::<StoryResult>()
+            + self.story_name.len()
+            + story_steps(self).map(step_bytes).sum::<usize>()
+    }
+
+    fn has_body(&self) -> bool {
+        story_steps(self).any(|step| {
+            step.response
+                .as_ref()
+                .is_some_and(|response| !response.body.is_empty())
+        })
+    }
+
+    fn drop_bodies(&mut self) {
+        for step in self
+            .step_results
+            .iter_mut()
+            .chain(self.teardown_results.iter_mut())
+        {
+            if let Some(response) = &mut step.response {
+                response.body = String::new();
+            }
+        }
+    }
+}
+
+fn story_steps(result: &StoryResult) -> impl Iterator<Item = &StepResult> {
+    result.step_results.iter().chain(&result.teardown_results)
+}
+
+fn step_bytes(step: &StepResult) -> usize {
+    size_of::<StepResult>()
+        + step.step_name.len()
+        + step.error_message.as_ref().map_or(0, String::len)
+        + step
+            .response
+            .as_ref()
+            .map_or(0, |response| response.body.len())
+        + step.trace_id.as_ref().map_or(0, String::len)
+        + step.span_id.as_ref().map_or(0, String::len)
+}
+
+// When a monitor last succeeded or failed, kept apart from the capped result history so it survives
+// the history rolling over. Counts every stored result, including those ignored during reloads.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MonitorActivity {
+    pub last_success_at: Option<DateTime<Utc>>,
+    pub last_failure_at: Option<DateTime<Utc>>,
+    // The first run, or the latest run with a different outcome than the one before it
+    pub last_state_change_at: Option<DateTime<Utc>>,
+    pub success_streak: u32,
+    pub failure_streak: u32,
+}
+
+impl MonitorActivity {
+    fn record(&mut self, success: bool, at: DateTime<Utc>) {
+        let previous = match (self.success_streak, self.failure_streak) {
+            (0, 0) => None,
+            (_, 0) => Some(true),
+            _ => Some(false),
+        };
+        if previous != Some(success) {
+            self.last_state_change_at = Some(at);
+        }
+        if success {
+            self.last_success_at = Some(at);
+            self.success_streak = self.success_streak.saturating_add(1);
+            self.failure_streak = 0;
+        } else {
+            self.last_failure_at = Some(at);
+            self.failure_streak = self.failure_streak.saturating_add(1);
+            self.success_streak = 0;
+        }
+    }
+}
+
+// Shared by the probe and story stores, `settings.max_result_memory_mb`
+#[derive(Debug, Default)]
+pub struct MemoryBudget {
+    // In bytes, 0 when unlimited
+    limit: AtomicUsize,
+    used: AtomicUsize,
+    // Orders the results of all stores sharing the budget, lower ones were recorded earlier
+    generation: AtomicU64,
+}
+
+impl MemoryBudget {
+    pub fn set_limit_mb(&self, limit_mb: Option<u64>) {
+        self.set_limit(limit_mb.map_or(0, |mb| (mb as usize).saturating_mul(1024 * 1024)));
+    }
+
+    // In bytes, 0 lifts the limit
+    pub fn set_limit(&self, limit: usize) {
+        self.limit.store(limit, Ordering::Relaxed);
+    }
+
+    pub fn used(&self) -> usize {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    fn exceeded(&self) -> bool {
+        let limit = self.limit.load(Ordering::Relaxed);
+        limit != 0 && self.used() > limit
+    }
+
+    // Frees memory until the stores fit the budget: the oldest response bodies across the stores,
+    // then the oldest results. The latest result of each monitor is always kept.
+    pub fn enforce(&self, stores: &[&dyn Evict]) {
+        for eviction in [Eviction::Body, Eviction::Result] {
+            while self.exceeded() {
+                let oldest = stores
+                    .iter()
+                    .filter_map(|store| Some((store.oldest(eviction)?, store)))
+                    .min_by_key(|(generation, _)| *generation);
+                let Some((_, store)) = oldest else {
+                    break;
+                };
+                if !store.evict_oldest(eviction) {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Eviction {
+    // Empties the response bodies of a result
+    Body,
+    // Removes a result, unless it is the only one of its monitor
+    Result,
+}
+
+// What `MemoryBudget::enforce` needs of a store, independent of its result type
+pub trait Evict {
+    // Generation of the result `evict_oldest` would free next
+    fn oldest(&self, eviction: Eviction) -> Option<u64>;
+    // False when there was nothing left to free
+    fn evict_oldest(&self, eviction: Eviction) -> bool;
+}
+
+// Accounting of a stored result
+struct Entry {
+    generation: u64,
+    bytes: usize,
+}
+
+struct MonitorResults<T> {
+    // Oldest first, `entries` has one entry per result
+    results: VecDeque<T>,
+    entries: VecDeque<Entry>,
+    summary: MonitorActivity,
+}
+
+impl<T> Default for MonitorResults<T> {
+    fn default() -> Self {
+        MonitorResults {
+            results: VecDeque::new(),
+            entries: VecDeque::new(),
+            summary: MonitorActivity::default(),
+        }
+    }
+}
+
+impl<T> MonitorResults<T> {
+    fn pop_front(&mut self) -> Option<Entry> {
+        self.results.pop_front();
+        self.entries.pop_front()
+    }
+}
+
+pub struct ResultStore<T> {
+    monitors: RwLock<HashMap<String, MonitorResults<T>>>,
+    // Results kept per monitor, the earliest are dropped beyond it
+    limit: usize,
+    budget: Arc<MemoryBudget>,
+    // This store's part of `budget.used`
+    bytes: AtomicUsize,
+}
+
+impl<T: StoredResult> ResultStore<T> {
+    pub fn new(limit: usize, budget: Arc<MemoryBudget>) -> ResultStore<T> {
+        ResultStore {
+            monitors: RwLock::new(HashMap::new()),
+            limit: limit.max(1),
+            budget,
+            bytes: AtomicUsize::new(0),
+        }
+    }
+
+    // Stores a result and returns the monitor's updated summary. Doesn't enforce the budget,
+    // `AppState` does that for both stores at once.
+    pub fn record(&self, monitor_name: &str, result: T) -> MonitorActivity {
+        let generation = self.budget.generation.fetch_add(1, Ordering::Relaxed);
+        let bytes = result.estimated_bytes();
+        let mut monitors = self.monitors.write().unwrap();
+        let monitor = monitors.entry(monitor_name.to_owned()).or_default();
+        monitor
+            .summary
+            .record(result.success(), result.timestamp_started());
+        monitor.results.push_back(result);
+        monitor.entries.push_back(Entry { generation, bytes });
+        self.add_bytes(bytes);
+        while monitor.results.len() > self.limit {
+            if let Some(entry) = monitor.pop_front() {
+                self.sub_bytes(entry.bytes);
+            }
+        }
+        monitor.summary.clone()
+    }
+
+    // The stored results of a monitor, oldest first. None for monitors without results.
+    pub fn recent(&self, monitor_name: &str) -> Option<Vec<T>> {
+        self.read(monitor_name, |results| results.iter().cloned().collect())
+    }
+
+    pub fn latest(&self, monitor_name: &str) -> Option<T> {
+        self.read(monitor_name, |results| results.back().cloned())
+            .flatten()
+    }
+
+    // Looks at the results of a monitor, oldest first, without copying them. Keep `read` short,
+    // recording results waits for it.
+    pub fn read<R>(&self, monitor_name: &str, read: impl FnOnce(&VecDeque<T>) -> R) -> Option<R> {
+        let monitors = self.monitors.read().unwrap();
+        monitors
+            .get(monitor_name)
+            .map(|monitor| read(&monitor.results))
+    }
+
+    pub fn summary(&self, monitor_name: &str) -> Option<MonitorActivity> {
+        let monitors = self.monitors.read().unwrap();
+        monitors
+            .get(monitor_name)
+            .map(|monitor| monitor.summary.clone())
+    }
+
+    // Every monitor with its latest result and summary, by name
+    pub fn latest_all(&self) -> Vec<(String, T, MonitorActivity)> {
+        let monitors = self.monitors.read().unwrap();
+        let mut latest: Vec<_> = monitors
+            .iter()
+            .filter_map(|(name, monitor)| {
+                let last = monitor.results.back()?;
+                Some((name.clone(), last.clone(), monitor.summary.clone()))
+            })
+            .collect();
+        latest.sort_by(|a, b| a.0.cmp(&b.0));
+        latest
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.monitors.read().unwrap().keys().cloned().collect()
+    }
+
+    pub fn contains(&self, monitor_name: &str) -> bool {
+        self.monitors.read().unwrap().contains_key(monitor_name)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.monitors.read().unwrap().is_empty()
+    }
+
+    // Drops the results and summaries of monitors, e.g. removed with a reload
+    pub fn prune(&self, monitor_names: &[String]) {
+        let mut monitors = self.monitors.write().unwrap();
+        for name in monitor_names {
+            if let Some(monitor) = monitors.remove(name) {
+                self.sub_bytes(monitor.entries.iter().map(|entry| entry.bytes).sum());
+            }
+        }
+    }
+
+    pub fn estimated_bytes(&self) -> usize {
+        self.bytes.load(Ordering::Relaxed)
+    }
+
+    fn add_bytes(&self, bytes: usize) {
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+        self.budget.used.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    fn sub_bytes(&self, bytes: usize) {
+        self.bytes.fetch_sub(bytes, Ordering::Relaxed);
+        self.budget.used.fetch_sub(bytes, Ordering::Relaxed);
+    }
+}
+
+impl<T: StoredResult> Evict for ResultStore<T> {
+    fn oldest(&self, eviction: Eviction) -> Option<u64> {
+        let monitors = self.monitors.read().unwrap();
+        monitors
+            .values()
+            .filter_map(|monitor| oldest_entry(monitor, eviction))
+            .map(|(_, generation)| generation)
+            .min()
+    }
+
+    fn evict_oldest(&self, eviction: Eviction) -> bool {
+        let mut monitors = self.monitors.write().unwrap();
+        let Some(((index, _), monitor)) = monitors
+            .values_mut()
+            .filter_map(|monitor| Some((oldest_entry(monitor, eviction)?, monitor)))
+            .min_by_key(|((_, generation), _)| *generation)
+        else {
+            return false;
+        };
+        match eviction {
+            Eviction::Body => {
+                let result = &mut monitor.results[index];
+                result.drop_bodies();
+                let entry = &mut monitor.entries[index];
+                let bytes = result.estimated_bytes();
+                let freed = entry.bytes.saturating_sub(bytes);
+                entry.bytes = bytes;
+                self.sub_bytes(freed);
+            }
+            Eviction::Result => {
+                if let Some(entry) = monitor.pop_front() {
+                    self.sub_bytes(entry.bytes);
+                }
+            }
+        }
+        true
+    }
+}
+
+// Index and generation of the entry of a monitor that would be freed next. Generations grow
+// within a monitor, so that is the first one that qualifies.
+fn oldest_entry<T: StoredResult>(
+    monitor: &MonitorResults<T>,
+    eviction: Eviction,
+) -> Option<(usize, u64)> {
+    match eviction {
+        Eviction::Body => monitor
+            .results
+            .iter()
+            .position(StoredResult::has_body)
+            .map(|index| (index, monitor.entries[index].generation)),
+        Eviction::Result if monitor.results.len() > 1 => {
+            monitor.entries.front().map(|entry| (0, entry.generation))
+        }
+        Eviction::Result => None,
+    }
+}
+
+#[cfg(test)]
+mod result_store_tests {
+    use std::sync::Arc;
+
+    use chrono::Utc;
+    use uuid::Uuid;
+
+    use super::{MemoryBudget, ResultStore, StoredResult};
+    use crate::probe::model::{ProbeResult, StepResult, StoryResult};
+    use crate::test_utils::result_test_utils::ProbeResultBuilder;
+
+    fn probe_result(body: &str) -> ProbeResult {
+        ProbeResultBuilder::new("probe").response(200, body).build()
+    }
+
+    fn story_result(body: &str) -> StoryResult {
+        let probe_result = probe_result(body);
+        StoryResult {
+            story_run_id: Uuid::new_v4(),
+            story_name: "story".to_owned(),
+            timestamp_started: Utc::now(),
+            success: true,
+            duration: None,
+            step_results: vec![StepResult {
+                step_name: "step".to_owned(),
+                timestamp_started: Utc::now(),
+                success: true,
+                error_message: None,
+                response: probe_result.response,
+                trace_id: None,
+                span_id: None,
+                captures: None,
+            }],
+            teardown_results: vec![],
+            expectations: None,
+            during_reload: false,
+        }
+    }
+
+    fn bodies<T: StoredResult>(store: &ResultStore<T>, monitor_name: &str) -> Vec<bool> {
+        store
+            .read(monitor_name, |results| {
+                results.iter().map(StoredResult::has_body).collect()
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn test_ring_buffer_keeps_the_latest_results() {
+        let store = ResultStore::new(3, Arc::new(MemoryBudget::default()));
+        for body in ["1", "2", "3", "4", "5"] {
+            store.record("api", probe_result(body));
+        }
+
+        let recent = store.recent("api").unwrap();
+        assert_eq!(
+            vec!["3", "4", "5"],
+            recent
+                .iter()
+                .map(|result| result.response.as_ref().unwrap().body.as_str())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(5, store.summary("api").unwrap().success_streak);
+        assert_eq!(
+            recent
+                .iter()
+                .map(StoredResult::estimated_bytes)
+                .sum::<usize>(),
+            store.estimated_bytes()
+        );
+
+        store.prune(&["api".to_owned()]);
+        assert!(store.is_empty());
+        assert_eq!(0, store.estimated_bytes());
+    }
+
+    #[test]
+    fn test_oldest_bodies_are_evicted_first_across_stores() {
+        let budget = Arc::new(MemoryBudget::default());
+        let probes = ResultStore::new(10, budget.clone());
+        let stories = ResultStore::new(10, budget.clone());
+        let body = "x".repeat(10_000);
+        // Room for two probe results and a story result, minus half a body
+        let limit = 2 * probe_result(&body).estimated_bytes()
+            + story_result(&body).estimated_bytes()
+            - body.len() / 2;
+        budget.set_limit(limit);
+
+        probes.record("api", probe_result(&body));
+        stories.record("checkout", story_result(&body));
+        budget.enforce(&[&probes, &stories]);
+        assert_eq!(vec![true], bodies(&probes, "api"));
+
+        probes.record("web", probe_result(&body));
+        budget.enforce(&[&probes, &stories]);
+        assert_eq!(vec![false], bodies(&probes, "api"));
+        assert_eq!(vec![true], bodies(&stories, "checkout"));
+        assert_eq!(vec![true], bodies(&probes, "web"));
+
+        probes.record("api", probe_result(&body));
+        budget.enforce(&[&probes, &stories]);
+        assert_eq!(vec![false, true], bodies(&probes, "api"));
+        assert_eq!(vec![false], bodies(&stories, "checkout"));
+        assert_eq!(vec![true], bodies(&probes, "web"));
+        assert!(budget.used() <= limit);
+        assert_eq!(
+            budget.used(),
+            probes.estimated_bytes() + stories.estimated_bytes()
+        );
+    }
+
+    #[test]
+    fn test_results_are_evicted_once_bodies_are_gone() {
+        let budget = Arc::new(MemoryBudget::default());
+        let probes = ResultStore::new(10, budget.clone());
+        let stories: ResultStore<StoryResult> = ResultStore::new(10, budget.clone());
+        for _ in 0..3 {
+            probes.record("api", probe_result(""));
+        }
+        probes.record("web", probe_result(""));
+
+        budget.set_limit(1);
+        budget.enforce(&[&probes, &stories]);
+
+        // The latest result of each monitor stays, whatever the budget
+        assert_eq!(1, probes.recent("api").unwrap().len());
+        assert_eq!(1, probes.recent("web").unwrap().len());
+        assert_eq!(3, probes.summary("api").unwrap().success_streak);
+    }
+}
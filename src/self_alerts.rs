@@ -0,0 +1,374 @@
+// Problems of xbp itself: reloads that fail, alert deliveries failing faster than
+// `alert_failure_threshold`, exporters that fell back to no-op ones, failed writes of
+// `xbp.runtime.yaml`, result databases that can't be used and monitors that stopped completing
+// runs. Every event is logged, counted in `self_alert_events` and listed on `/-/timeline`. With
+// `settings.self_alerts` it is also sent to that channel, at most once per cooldown for each kind.
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use opentelemetry::KeyValue;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::alerts::model::AlertContext;
+use crate::alerts::outbound_webhook::send_alert;
+use crate::app_state::AppState;
+use crate::config::SelfAlertSettings;
+use crate::probe::duration;
+
+// Events kept for `/-/timeline`, the oldest are dropped first
+const TIMELINE_LIMIT: usize = 200;
+// How often the watchdog looks for completed runs
+const WATCHDOG_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SelfAlertKind {
+    ConfigReload,
+    AlertDelivery,
+    ExporterFallback,
+    StorageWrite,
+    ResultStorage,
+    Watchdog,
+}
+
+impl SelfAlertKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SelfAlertKind::ConfigReload => "config_reload",
+            SelfAlertKind::AlertDelivery => "alert_delivery",
+            SelfAlertKind::ExporterFallback => "exporter_fallback",
+            SelfAlertKind::StorageWrite => "storage_write",
+            SelfAlertKind::ResultStorage => "result_storage",
+            SelfAlertKind::Watchdog => "watchdog",
+        }
+    }
+
+    // Sent as the monitor name of the alert, so channels deduplicating by it, like Opsgenie with
+    // its alias, keep one open alert per kind
+    pub fn dedup_key(&self) -> String {
+        format!("xbp/{}", self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct SelfEvent {
+    pub timestamp: DateTime<Utc>,
+    pub kind: SelfAlertKind,
+    pub dedup_key: String,
+    pub message: String,
+    // Sent to the channel of `settings.self_alerts`, false without one and during the cooldown
+    pub alerted: bool,
+}
+
+#[derive(Debug, Default)]
+struct Watchdog {
+    last_completion: Option<DateTime<Utc>>,
+    // Set once a stall was raised, so it is raised once until a run completes again
+    reported: bool,
+}
+
+#[derive(Default)]
+pub struct SelfMonitor {
+    events: Mutex<VecDeque<SelfEvent>>,
+    last_alerted: Mutex<HashMap<SelfAlertKind, DateTime<Utc>>>,
+    // Failed alert deliveries not yet raised, oldest first
+    alert_failures: Mutex<VecDeque<DateTime<Utc>>>,
+    watchdog: Mutex<Watchdog>,
+}
+
+impl SelfMonitor {
+    // Oldest first
+    pub fn events(&self) -> Vec<SelfEvent> {
+        self.events.lock().unwrap().iter().cloned().collect()
+    }
+
+    fn record(&self, event: SelfEvent) {
+        let mut events = self.events.lock().unwrap();
+        events.push_back(event);
+        while events.len() > TIMELINE_LIMIT {
+            events.pop_front();
+        }
+    }
+
+    // Claims the alert of the kind unless one was sent within the cooldown
+    fn should_alert(&self, kind: SelfAlertKind, now: DateTime<Utc>, cooldown: Duration) -> bool {
+        let mut last_alerted = self.last_alerted.lock().unwrap();
+        match last_alerted.get(&kind) {
+            Some(last) if duration::between(*last, now) < cooldown => false,
+            _ => {
+                last_alerted.insert(kind, now);
+                true
+            }
+        }
+    }
+
+    // Adds failed deliveries, returns how many failed within the window once they reach the
+    // threshold. Those are forgotten then, the next alert needs as many new failures.
+    fn add_alert_failures(
+        &self,
+        failures: usize,
+        now: DateTime<Utc>,
+        settings: &SelfAlertSettings,
+    ) -> Option<usize> {
+        let mut alert_failures = self.alert_failures.lock().unwrap();
+        for _ in 0..failures {
+            alert_failures.push_back(now);
+        }
+        let window = settings.alert_failure_window();
+        while alert_failures
+            .front()
+            .is_some_and(|failed| duration::between(*failed, now) > window)
+        {
+            alert_failures.pop_front();
+        }
+        if alert_failures.len() < settings.alert_failure_threshold() as usize {
+            return None;
+        }
+        let count = alert_failures.len();
+        alert_failures.clear();
+        Some(count)
+    }
+
+    pub fn record_completion(&self, timestamp: DateTime<Utc>) {
+        let mut watchdog = self.watchdog.lock().unwrap();
+        watchdog.last_completion = Some(timestamp);
+        watchdog.reported = false;
+    }
+
+    // The last completed run, or `since` without one, when that was at least `timeout` ago and the
+    // stall hasn't been reported yet
+    fn stalled(
+        &self,
+        since: DateTime<Utc>,
+        now: DateTime<Utc>,
+        timeout: Duration,
+    ) -> Option<DateTime<Utc>> {
+        let mut watchdog = self.watchdog.lock().unwrap();
+        let last = watchdog.last_completion.unwrap_or(since);
+        if watchdog.reported || duration::between(last, now) < timeout {
+            return None;
+        }
+        watchdog.reported = true;
+        Some(last)
+    }
+}
+
+// Logs, counts and lists the event, and sends it to the self alert channel unless the kind is
+// cooling down. Needs a Tokio runtime to send.
+pub fn raise(app_state: &AppState, kind: SelfAlertKind, message: String) {
+    let now = Utc::now();
+    warn!(kind = kind.as_str(), "xbp self alert: {}", message);
+    app_state
+        .metrics
+        .self_alert_events
+        .add(1, &[KeyValue::new("kind", kind.as_str())]);
+    let config = app_state.config();
+    let alert = config.settings.self_alerts.as_ref().and_then(|settings| {
+        let alert = config.alert_channels.get(&settings.channel)?;
+        app_state
+            .self_monitor
+            .should_alert(kind, now, settings.cooldown())
+            .then(|| alert.clone())
+    });
+    app_state.self_monitor.record(SelfEvent {
+        timestamp: now,
+        kind,
+        dedup_key: kind.dedup_key(),
+        message: message.clone(),
+        alerted: alert.is_some(),
+    });
+    if let Some(alert) = alert {
+        tokio::spawn(async move {
+            let context = AlertContext {
+                monitor_name: kind.dedup_key(),
+                failure_timestamp: now,
+                error_message: message,
+                ..Default::default()
+            };
+            let sent = send_alert(&alert, &context).await;
+            // Not counted as a failed delivery, a broken channel would keep alerting about itself
+            if let Err(e) = sent {
+                warn!("Can't send self alert {}: {}", kind.dedup_key(), e);
+            }
+        });
+    }
+}
+
+// Raises `AlertDelivery` once `alert_failure_threshold` deliveries failed within the window
+pub fn record_alert_failures(app_state: &AppState, failures: usize) {
+    if failures == 0 {
+        return;
+    }
+    let settings = app_state
+        .config()
+        .settings
+        .self_alerts
+        .clone()
+        .unwrap_or_default();
+    if let Some(count) = app_state
+        .self_monitor
+        .add_alert_failures(failures, Utc::now(), &settings)
+    {
+        raise(
+            app_state,
+            SelfAlertKind::AlertDelivery,
+            format!(
+                "{} alert deliveries failed within {}",
+                count,
+                humantime::format_duration(settings.alert_failure_window())
+            ),
+        );
+    }
+}
+
+// Raises `Watchdog` when no monitor completed a run for `settings.self_alerts.watchdog` while
+// monitors are configured. Runs for the lifetime of the app state, across reloads.
+pub async fn run_watchdog(app_state: Arc<AppState>) {
+    let mut interval = tokio::time::interval(WATCHDOG_INTERVAL);
+    loop {
+        interval.tick().await;
+        check_watchdog(&app_state, Utc::now());
+    }
+}
+
+fn check_watchdog(app_state: &AppState, now: DateTime<Utc>) {
+    let config = app_state.config();
+    let monitors = config.probes.len() + config.stories.len();
+    if monitors == 0 {
+        return;
+    }
+    let timeout = config
+        .settings
+        .self_alerts
+        .as_ref()
+        .map(SelfAlertSettings::watchdog)
+        .unwrap_or_else(|| SelfAlertSettings::default().watchdog());
+    if let Some(last) = app_state
+        .self_monitor
+        .stalled(app_state.started_at, now, timeout)
+    {
+        raise(
+            app_state,
+            SelfAlertKind::Watchdog,
+            format!(
+                "No monitor completed a run since {}, {} monitors are configured",
+                last.to_rfc3339(),
+                monitors
+            ),
+        );
+    }
+}
+
+#[cfg(test)]
+mod self_alerts_tests {
+    use std::time::Duration;
+
+    use chrono::{TimeDelta, Utc};
+    use opentelemetry::KeyValue;
+
+    use super::{check_watchdog, raise, record_alert_failures, SelfAlertKind, SelfMonitor};
+    use crate::app_state::AppState;
+    use crate::config::{Config, SelfAlertSettings};
+    use crate::otel::metrics::MetricsState;
+    use crate::test_utils::metrics_test_utils::counter_value;
+    use crate::test_utils::probe_test_utils::probe_get_with_expected_status;
+
+    #[test]
+    fn test_alerts_of_a_kind_wait_for_the_cooldown() {
+        let monitor = SelfMonitor::default();
+        let now = Utc::now();
+        let cooldown = Duration::from_secs(15 * 60);
+
+        assert!(monitor.should_alert(SelfAlertKind::ConfigReload, now, cooldown));
+        assert!(!monitor.should_alert(
+            SelfAlertKind::ConfigReload,
+            now + TimeDelta::minutes(5),
+            cooldown
+        ));
+        assert!(monitor.should_alert(SelfAlertKind::Watchdog, now, cooldown));
+        assert!(monitor.should_alert(
+            SelfAlertKind::ConfigReload,
+            now + TimeDelta::minutes(15),
+            cooldown
+        ));
+    }
+
+    #[test]
+    fn test_alert_failures_raise_at_the_threshold_within_the_window() {
+        let monitor = SelfMonitor::default();
+        let settings = SelfAlertSettings {
+            alert_failure_threshold: Some(3),
+            alert_failure_window: Some(Duration::from_secs(60)),
+            ..Default::default()
+        };
+        let now = Utc::now();
+
+        assert_eq!(None, monitor.add_alert_failures(2, now, &settings));
+        // The first two fell out of the window
+        let later = now + TimeDelta::minutes(2);
+        assert_eq!(None, monitor.add_alert_failures(1, later, &settings));
+        assert_eq!(Some(3), monitor.add_alert_failures(2, later, &settings));
+        assert_eq!(None, monitor.add_alert_failures(1, later, &settings));
+    }
+
+    #[tokio::test]
+    async fn test_events_are_counted_and_listed() {
+        let metrics_state = MetricsState::for_testing();
+        let app_state = AppState::with_metrics(Config::default(), metrics_state.metrics());
+
+        raise(
+            &app_state,
+            SelfAlertKind::StorageWrite,
+            "disk full".to_owned(),
+        );
+        for _ in 0..5 {
+            record_alert_failures(&app_state, 1);
+        }
+
+        let events = app_state.self_monitor.events();
+        assert_eq!(2, events.len());
+        assert_eq!(SelfAlertKind::StorageWrite, events[0].kind);
+        assert_eq!("xbp/storage_write", events[0].dedup_key);
+        // No `settings.self_alerts`, nothing is sent
+        assert!(!events[0].alerted);
+        assert_eq!(SelfAlertKind::AlertDelivery, events[1].kind);
+        let metrics = metrics_state.collect().unwrap();
+        assert_eq!(
+            Some(1),
+            counter_value(
+                &metrics,
+                "self_alert_events",
+                &[KeyValue::new("kind", "alert_delivery")]
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_watchdog_raises_once_per_stall() {
+        let mut config = Config::default();
+        config.probes.push(probe_get_with_expected_status(
+            reqwest::StatusCode::OK,
+            "http://localhost".to_owned(),
+            "".to_owned(),
+        ));
+        let app_state = AppState::new(config);
+        let stalled = app_state.started_at + TimeDelta::minutes(20);
+
+        check_watchdog(&app_state, app_state.started_at + TimeDelta::minutes(5));
+        assert!(app_state.self_monitor.events().is_empty());
+
+        check_watchdog(&app_state, stalled);
+        check_watchdog(&app_state, stalled + TimeDelta::minutes(1));
+        assert_eq!(1, app_state.self_monitor.events().len());
+
+        app_state.self_monitor.record_completion(stalled);
+        check_watchdog(&app_state, stalled + TimeDelta::minutes(20));
+        let events = app_state.self_monitor.events();
+        assert_eq!(2, events.len());
+        assert_eq!(SelfAlertKind::Watchdog, events[1].kind);
+    }
+}
@@ -0,0 +1,493 @@
+use std::collections::{BTreeMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use arc_swap::ArcSwap;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Notify;
+
+use crate::app_state::{self, AppState, MonitorState};
+use crate::probe::duration::as_millis_f64;
+use crate::probe::sla_window::ParsedSlaWindow;
+
+// Shortest time between two recomputations, results recorded in between are summarized together
+pub const MIN_REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+
+// Every monitor with stored results, as served by `/status`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StatusSummary {
+    // When the summary was last recomputed, results recorded since then are not included yet
+    pub computed_at: DateTime<Utc>,
+    // `app_state::health_score` over the summarized probes, so it agrees with `monitors`. Also
+    // exported as the `composite_status` gauge.
+    pub health_score: f64,
+    pub monitors: Vec<MonitorSummary>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MonitorSummary {
+    pub name: String,
+    // "probe" or "story"
+    #[serde(rename = "type")]
+    pub monitor_type: String,
+    pub last_run_at: DateTime<Utc>,
+    pub last_success: bool,
+    // Whether the monitor is failing, taking the recovery threshold into account
+    pub failing: bool,
+    // Over the stored results
+    pub runs: usize,
+    // Over the stored results that count, 100 when none does. Results of runs during a reload
+    // don't with `settings.ignore_results_during_reload`.
+    pub uptime_percent: f64,
+    // Over the stored results that count inside the probe's `sla_window`, unset without one. The
+    // percentage is unset while no result fell inside the window.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sla_runs: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sla_uptime_percent: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub p50_duration_ms: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub p95_duration_ms: Option<f64>,
+}
+
+impl StatusSummary {
+    // The summary of the monitors `keep` accepts, with a health score over their probes alone
+    pub fn filtered(&self, keep: impl Fn(&MonitorSummary) -> bool) -> StatusSummary {
+        let monitors: Vec<MonitorSummary> = self
+            .monitors
+            .iter()
+            .filter(|monitor| keep(monitor))
+            .cloned()
+            .collect();
+        StatusSummary {
+            computed_at: self.computed_at,
+            health_score: health_score(&monitors),
+            monitors,
+        }
+    }
+}
+
+// `app_state::health_score` over the summarized probes
+fn health_score(monitors: &[MonitorSummary]) -> f64 {
+    app_state::health_score(
+        monitors
+            .iter()
+            .filter(|monitor| monitor.monitor_type == "probe")
+            .map(|monitor| !monitor.failing),
+    )
+}
+
+// A single stored run, as far as the summary is concerned
+struct Run {
+    success: bool,
+    timestamp: DateTime<Utc>,
+    duration: Option<Duration>,
+    during_reload: bool,
+}
+
+// Keeps the status summary ready to serve. Recording a result only marks its monitor as changed,
+// the background task recomputes the changed monitors and swaps in a new summary, so handlers
+// read it without taking any of the result locks.
+pub struct StatusSummarizer {
+    summary: ArcSwap<StatusSummary>,
+    // Monitors with results recorded or removed since the last recomputation
+    changed: Mutex<HashSet<String>>,
+    notify: Notify,
+}
+
+impl Default for StatusSummarizer {
+    fn default() -> Self {
+        StatusSummarizer {
+            summary: ArcSwap::from_pointee(StatusSummary {
+                computed_at: Utc::now(),
+                health_score: 1.0,
+                monitors: vec![],
+            }),
+            changed: Mutex::new(HashSet::new()),
+            notify: Notify::new(),
+        }
+    }
+}
+
+impl StatusSummarizer {
+    pub fn summary(&self) -> Arc<StatusSummary> {
+        self.summary.load_full()
+    }
+
+    pub fn mark_changed(&self, monitor_name: &str) {
+        self.changed.lock().unwrap().insert(monitor_name.to_owned());
+        self.notify.notify_one();
+    }
+
+    // Recomputes the changed monitors and publishes the new summary, the others are carried over
+    pub fn refresh(&self, app_state: &AppState) {
+        let changed = std::mem::take(&mut *self.changed.lock().unwrap());
+        if changed.is_empty() {
+            return;
+        }
+
+        let current = self.summary.load();
+        let mut monitors: BTreeMap<String, MonitorSummary> = current
+            .monitors
+            .iter()
+            .filter(|monitor| !changed.contains(&monitor.name))
+            .map(|monitor| (monitor.name.clone(), monitor.clone()))
+            .collect();
+
+        {
+            let config = app_state.config();
+            let ignore_reloads = config.settings.ignore_results_during_reload;
+            let monitor_states = app_state.monitor_states.read().unwrap();
+            for name in &changed {
+                let sla_window = config
+                    .probes
+                    .iter()
+                    .find(|probe| &probe.name == name)
+                    .and_then(|probe| probe.parsed_sla_window());
+                let probe_summary = app_state.probe_results.read(name, |results| {
+                    summarize(
+                        name,
+                        "probe",
+                        results.iter().map(|result| Run {
+                            success: result.success,
+                            timestamp: result.timestamp_started,
+                            duration: result.duration,
+                            during_reload: result.during_reload,
+                        }),
+                        monitor_states.get(name),
+                        sla_window.as_ref(),
+                        ignore_reloads,
+                    )
+                });
+                let summary = match probe_summary {
+                    Some(summary) => summary,
+                    None => app_state
+                        .story_results
+                        .read(name, |results| {
+                            summarize(
+                                name,
+                                "story",
+                                results.iter().map(|result| Run {
+                                    success: result.success,
+                                    timestamp: result.timestamp_started,
+                                    duration: result.duration,
+                                    during_reload: result.during_reload,
+                                }),
+                                monitor_states.get(name),
+                                None,
+                                ignore_reloads,
+                            )
+                        })
+                        // Pruned, e.g. removed with a reload
+                        .flatten(),
+                };
+                if let Some(summary) = summary {
+                    monitors.insert(name.clone(), summary);
+                }
+            }
+        }
+
+        let monitors: Vec<MonitorSummary> = monitors.into_values().collect();
+        let health_score = health_score(&monitors);
+        app_state.metrics.composite_status.record(health_score, &[]);
+        self.summary.store(Arc::new(StatusSummary {
+            computed_at: Utc::now(),
+            health_score,
+            monitors,
+        }));
+    }
+
+    // Runs for the lifetime of the process, reloads don't restart it
+    pub async fn run(app_state: Arc<AppState>) {
+        loop {
+            app_state.status_summary.notify.notified().await;
+            app_state.status_summary.refresh(&app_state);
+            tokio::time::sleep(MIN_REFRESH_INTERVAL).await;
+        }
+    }
+}
+
+fn summarize(
+    name: &str,
+    monitor_type: &str,
+    runs: impl Iterator<Item = Run>,
+    monitor_state: Option<&MonitorState>,
+    sla_window: Option<&ParsedSlaWindow>,
+    ignore_reloads: bool,
+) -> Option<MonitorSummary> {
+    let runs: Vec<Run> = runs.collect();
+    let last = runs.last()?;
+    // With `settings.ignore_results_during_reload`, reload results don't count toward the uptimes
+    let counts = |run: &&Run| !(ignore_reloads && run.during_reload);
+    let (counted, successes) = runs
+        .iter()
+        .filter(counts)
+        .fold((0, 0), |(counted, successes), run| {
+            (counted + 1, successes + run.success as usize)
+        });
+    let sla = sla_window.map(|window| {
+        let (sla_runs, sla_successes) = runs
+            .iter()
+            .filter(counts)
+            .filter(|run| window.contains(run.timestamp))
+            .fold((0, 0), |(runs, successes), run| {
+                (runs + 1, successes + run.success as usize)
+            });
+        (
+            sla_runs,
+            (sla_runs > 0).then(|| sla_successes as f64 * 100.0 / sla_runs as f64),
+        )
+    });
+    let mut durations: Vec<Duration> = runs.iter().filter_map(|run| run.duration).collect();
+    durations.sort();
+
+    Some(MonitorSummary {
+        name: name.to_owned(),
+        monitor_type: monitor_type.to_owned(),
+        last_run_at: last.timestamp,
+        last_success: last.success,
+        failing: monitor_state.map_or(!last.success, |state| state.failing),
+        runs: runs.len(),
+        uptime_percent: if counted == 0 {
+            100.0
+        } else {
+            successes as f64 * 100.0 / counted as f64
+        },
+        sla_runs: sla.map(|(sla_runs, _)| sla_runs),
+        sla_uptime_percent: sla.and_then(|(_, percent)| percent),
+        p50_duration_ms: percentile(&durations, 50).map(as_millis_f64),
+        p95_duration_ms: percentile(&durations, 95).map(as_millis_f64),
+    })
+}
+
+// Nearest-rank percentile of sorted durations
+fn percentile(sorted: &[Duration], percent: usize) -> Option<Duration> {
+    if sorted.is_empty() {
+        return None;
+    }
+    let rank = (sorted.len() * percent).div_ceil(100).max(1);
+    Some(sorted[rank - 1])
+}
+
+#[cfg(test)]
+mod status_summary_tests {
+    use std::time::Duration;
+
+    use chrono::{TimeZone, Utc};
+    use reqwest::StatusCode;
+
+    use crate::app_state::AppState;
+    use crate::config::Config;
+    use crate::otel::metrics::MetricsState;
+    use crate::probe::model::{ProbeResult, SlaWindow};
+    use crate::test_utils::metrics_test_utils::f64_gauge_value;
+    use crate::test_utils::probe_test_utils::probe_get_with_expected_status;
+    use crate::test_utils::result_test_utils::ProbeResultBuilder;
+
+    fn result(probe_name: &str, success: bool, millis: u64) -> ProbeResult {
+        ProbeResultBuilder::new(probe_name)
+            .success(success)
+            .duration(Duration::from_millis(millis))
+            .build()
+    }
+
+    #[test]
+    fn test_summary_is_computed_from_stored_results() {
+        let app_state = AppState::new(Config::default());
+        for millis in 1..=20 {
+            app_state.add_probe_result("api".to_owned(), result("api", millis != 20, millis));
+        }
+        let before = app_state.status_summary.summary();
+        assert!(before.monitors.is_empty());
+
+        app_state.status_summary.refresh(&app_state);
+
+        let summary = app_state.status_summary.summary();
+        assert!(summary.computed_at >= before.computed_at);
+        let api = &summary.monitors[0];
+        assert_eq!(
+            ("api", "probe"),
+            (api.name.as_str(), api.monitor_type.as_str())
+        );
+        assert_eq!(20, api.runs);
+        assert_eq!(95.0, api.uptime_percent);
+        assert!(!api.last_success);
+        assert_eq!(Some(10.0), api.p50_duration_ms);
+        assert_eq!(Some(19.0), api.p95_duration_ms);
+    }
+
+    #[test]
+    fn test_sla_uptime_only_counts_results_inside_the_window() {
+        let mut probe = probe_get_with_expected_status(
+            StatusCode::OK,
+            "http://localhost".to_owned(),
+            String::new(),
+        );
+        probe.name = "api".to_owned();
+        probe.sla_window = Some(SlaWindow {
+            days: Some(vec!["mon-fri".to_owned()]),
+            start: "08:00".to_owned(),
+            end: "18:00".to_owned(),
+            timezone: Some("Europe/Amsterdam".to_owned()),
+        });
+        let app_state = AppState::new(Config {
+            probes: vec![probe],
+            ..Config::default()
+        });
+        // Wednesday 2026-01-14 08:00 and 17:59:59 Amsterdam time are inside, 18:00 is not and
+        // neither is the Saturday
+        let runs = [
+            (Utc.with_ymd_and_hms(2026, 1, 14, 7, 0, 0).unwrap(), true),
+            (
+                Utc.with_ymd_and_hms(2026, 1, 14, 16, 59, 59).unwrap(),
+                false,
+            ),
+            (Utc.with_ymd_and_hms(2026, 1, 14, 17, 0, 0).unwrap(), false),
+            (Utc.with_ymd_and_hms(2026, 1, 17, 10, 0, 0).unwrap(), false),
+        ];
+        for (timestamp, success) in runs {
+            let result = ProbeResultBuilder::new("api")
+                .success(success)
+                .started_at(timestamp)
+                .build();
+            app_state.add_probe_result("api".to_owned(), result);
+        }
+        app_state.add_probe_result("web".to_owned(), result("web", true, 10));
+
+        app_state.status_summary.refresh(&app_state);
+
+        let summary = app_state.status_summary.summary();
+        let api = &summary.monitors[0];
+        assert_eq!((4, 25.0), (api.runs, api.uptime_percent));
+        assert_eq!(Some(2), api.sla_runs);
+        assert_eq!(Some(50.0), api.sla_uptime_percent);
+        let web = &summary.monitors[1];
+        assert_eq!((None, None), (web.sla_runs, web.sla_uptime_percent));
+    }
+
+    #[test]
+    fn test_results_during_reload_can_be_left_out_of_the_uptime() {
+        for (ignore_reloads, uptime_percent) in [(true, 100.0), (false, 50.0)] {
+            let mut config = Config::default();
+            config.settings.ignore_results_during_reload = ignore_reloads;
+            let app_state = AppState::new(config);
+            app_state.add_probe_result("api".to_owned(), result("api", true, 10));
+            let during_reload = ProbeResultBuilder::new("api")
+                .success(false)
+                .during_reload()
+                .build();
+            app_state.add_probe_result("api".to_owned(), during_reload);
+
+            app_state.status_summary.refresh(&app_state);
+
+            let api = &app_state.status_summary.summary().monitors[0];
+            assert_eq!((2, uptime_percent), (api.runs, api.uptime_percent));
+        }
+    }
+
+    #[test]
+    fn test_results_during_reload_can_be_left_out_of_the_sla_uptime() {
+        let mut probe = probe_get_with_expected_status(
+            StatusCode::OK,
+            "http://localhost".to_owned(),
+            String::new(),
+        );
+        probe.name = "api".to_owned();
+        probe.sla_window = Some(SlaWindow {
+            days: None,
+            start: "08:00".to_owned(),
+            end: "18:00".to_owned(),
+            timezone: None,
+        });
+        let mut config = Config {
+            probes: vec![probe],
+            ..Config::default()
+        };
+        config.settings.ignore_results_during_reload = true;
+        let app_state = AppState::new(config);
+        let inside = Utc.with_ymd_and_hms(2026, 1, 14, 12, 0, 0).unwrap();
+        let before_reload = ProbeResultBuilder::new("api").started_at(inside).build();
+        app_state.add_probe_result("api".to_owned(), before_reload);
+        let during_reload = ProbeResultBuilder::new("api")
+            .success(false)
+            .started_at(inside)
+            .during_reload()
+            .build();
+        app_state.add_probe_result("api".to_owned(), during_reload);
+
+        app_state.status_summary.refresh(&app_state);
+
+        let api = &app_state.status_summary.summary().monitors[0];
+        assert_eq!(Some(1), api.sla_runs);
+        assert_eq!(Some(100.0), api.sla_uptime_percent);
+    }
+
+    #[test]
+    fn test_only_changed_monitors_are_recomputed() {
+        let app_state = AppState::new(Config::default());
+        app_state.add_probe_result("api".to_owned(), result("api", true, 10));
+        app_state.add_probe_result("web".to_owned(), result("web", true, 10));
+        app_state.status_summary.refresh(&app_state);
+
+        // Stored behind the summarizer's back, so a recomputation would pick it up
+        app_state
+            .probe_results
+            .record("api", result("api", false, 10));
+        app_state.add_probe_result("web".to_owned(), result("web", false, 10));
+        app_state.status_summary.refresh(&app_state);
+
+        let summary = app_state.status_summary.summary();
+        assert_eq!(100.0, summary.monitors[0].uptime_percent);
+        assert_eq!(50.0, summary.monitors[1].uptime_percent);
+        assert_eq!(0.5, summary.health_score);
+    }
+
+    #[test]
+    fn test_health_score_is_exported_as_composite_status() {
+        let metrics_state = MetricsState::for_testing();
+        let app_state = AppState::with_metrics(Config::default(), metrics_state.metrics());
+        app_state.add_probe_result("api".to_owned(), result("api", true, 10));
+        app_state.add_probe_result("web".to_owned(), result("web", false, 10));
+        app_state.status_summary.refresh(&app_state);
+
+        let summary = app_state.status_summary.summary();
+        assert_eq!(app_state.health_score(), summary.health_score);
+        let metrics = metrics_state.collect().unwrap();
+        assert_eq!(
+            Some(summary.health_score),
+            f64_gauge_value(&metrics, "composite_status", &[])
+        );
+    }
+
+    #[test]
+    fn test_pruned_monitors_are_dropped() {
+        let app_state = AppState::new(Config::default());
+        app_state.add_probe_result("api".to_owned(), result("api", true, 10));
+        app_state.add_probe_result("web".to_owned(), result("web", true, 10));
+        app_state.status_summary.refresh(&app_state);
+
+        app_state.prune_results(&["api".to_owned()]);
+        app_state.status_summary.refresh(&app_state);
+
+        let summary = app_state.status_summary.summary();
+        assert_eq!(1, summary.monitors.len());
+        assert_eq!("web", summary.monitors[0].name);
+    }
+
+    #[test]
+    fn test_percentile_uses_nearest_rank() {
+        let durations: Vec<Duration> = (1..=4).map(Duration::from_millis).collect();
+
+        assert_eq!(None, super::percentile(&[], 50));
+        assert_eq!(
+            Some(Duration::from_millis(2)),
+            super::percentile(&durations, 50)
+        );
+        assert_eq!(
+            Some(Duration::from_millis(4)),
+            super::percentile(&durations, 95)
+        );
+    }
+}
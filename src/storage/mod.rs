@@ -0,0 +1,492 @@
+// Where results are kept beyond the in-memory window of `result_store`. The window is always kept,
+// monitor states, alerts and `/status` read it. A `ResultSink` is what the history endpoints read:
+// the window itself by default, or a database every result is also written to, see `postgres`.
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+use opentelemetry::metrics::Counter;
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tracing::{info, warn};
+
+use crate::config::StorageSettings;
+use crate::errors::StorageError;
+use crate::probe::model::{ProbeResult, StoryResult};
+use crate::result_store::{ResultStore, StoredResult};
+
+#[cfg(feature = "postgres")]
+pub mod postgres;
+
+const DEFAULT_QUEUE_SIZE: usize = 10_000;
+const DEFAULT_BATCH_SIZE: usize = 100;
+// How often results older than `settings.storage.retention` are deleted
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+// At most one warning per interval while results can't be written
+const WRITE_WARNING_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MonitorKind {
+    Probe,
+    Story,
+}
+
+impl MonitorKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MonitorKind::Probe => "probe",
+            MonitorKind::Story => "story",
+        }
+    }
+}
+
+// A result on its way to the database, boxed as story results are much larger
+#[derive(Debug, Clone)]
+pub enum StorageRecord {
+    Probe {
+        name: String,
+        result: Box<ProbeResult>,
+    },
+    Story {
+        name: String,
+        result: Box<StoryResult>,
+    },
+}
+
+impl StorageRecord {
+    pub fn kind(&self) -> MonitorKind {
+        match self {
+            StorageRecord::Probe { .. } => MonitorKind::Probe,
+            StorageRecord::Story { .. } => MonitorKind::Story,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            StorageRecord::Probe { name, .. } | StorageRecord::Story { name, .. } => name,
+        }
+    }
+}
+
+// Oldest first, like the window
+#[derive(Debug, Clone)]
+pub enum StoredResults {
+    Probes(Vec<ProbeResult>),
+    Stories(Vec<StoryResult>),
+}
+
+impl StoredResults {
+    pub fn into_probes(self) -> Vec<ProbeResult> {
+        match self {
+            StoredResults::Probes(results) => results,
+            StoredResults::Stories(_) => vec![],
+        }
+    }
+
+    pub fn into_stories(self) -> Vec<StoryResult> {
+        match self {
+            StoredResults::Stories(results) => results,
+            StoredResults::Probes(_) => vec![],
+        }
+    }
+}
+
+pub trait ResultSink: Send + Sync {
+    // `memory` or `postgres`, for logs
+    fn name(&self) -> &'static str;
+
+    fn record_probe<'a>(
+        &'a self,
+        monitor_name: &'a str,
+        result: &'a ProbeResult,
+    ) -> BoxFuture<'a, Result<(), StorageError>>;
+
+    fn record_story<'a>(
+        &'a self,
+        monitor_name: &'a str,
+        result: &'a StoryResult,
+    ) -> BoxFuture<'a, Result<(), StorageError>>;
+
+    // What the write queue hands over, one record at a time unless the store can do better
+    fn record_batch<'a>(
+        &'a self,
+        records: &'a [StorageRecord],
+    ) -> BoxFuture<'a, Result<(), StorageError>> {
+        Box::pin(async move {
+            for record in records {
+                match record {
+                    StorageRecord::Probe { name, result } => {
+                        self.record_probe(name, result).await?
+                    }
+                    StorageRecord::Story { name, result } => {
+                        self.record_story(name, result).await?
+                    }
+                }
+            }
+            Ok(())
+        })
+    }
+
+    // The results of a monitor started at `since` or later, the latest `limit` of them
+    fn recent<'a>(
+        &'a self,
+        kind: MonitorKind,
+        monitor_name: &'a str,
+        since: Option<DateTime<Utc>>,
+        limit: Option<usize>,
+    ) -> BoxFuture<'a, Result<StoredResults, StorageError>>;
+
+    // Deletes the results started before `before`, returns how many there were
+    fn prune(&self, before: DateTime<Utc>) -> BoxFuture<'_, Result<u64, StorageError>>;
+
+    // Results are only queued for stores that keep them outside the window
+    fn durable(&self) -> bool {
+        true
+    }
+}
+
+// The default, reading the window `AppState` records into
+pub struct MemoryStorage {
+    probes: Arc<ResultStore<ProbeResult>>,
+    stories: Arc<ResultStore<StoryResult>>,
+}
+
+impl MemoryStorage {
+    pub fn new(
+        probes: Arc<ResultStore<ProbeResult>>,
+        stories: Arc<ResultStore<StoryResult>>,
+    ) -> MemoryStorage {
+        MemoryStorage { probes, stories }
+    }
+}
+
+fn select<T: StoredResult>(
+    results: &VecDeque<T>,
+    since: Option<DateTime<Utc>>,
+    limit: Option<usize>,
+) -> Vec<T> {
+    let mut selected: Vec<T> = results
+        .iter()
+        .filter(|result| since.is_none_or(|since| result.timestamp_started() >= since))
+        .cloned()
+        .collect();
+    if let Some(limit) = limit {
+        selected = selected.split_off(selected.len().saturating_sub(limit));
+    }
+    selected
+}
+
+impl ResultSink for MemoryStorage {
+    fn name(&self) -> &'static str {
+        "memory"
+    }
+
+    // `AppState::add_probe_result` already put the result in the window
+    fn record_probe<'a>(
+        &'a self,
+        _monitor_name: &'a str,
+        _result: &'a ProbeResult,
+    ) -> BoxFuture<'a, Result<(), StorageError>> {
+        Box::pin(async { Ok(()) })
+    }
+
+    fn record_story<'a>(
+        &'a self,
+        _monitor_name: &'a str,
+        _result: &'a StoryResult,
+    ) -> BoxFuture<'a, Result<(), StorageError>> {
+        Box::pin(async { Ok(()) })
+    }
+
+    fn recent<'a>(
+        &'a self,
+        kind: MonitorKind,
+        monitor_name: &'a str,
+        since: Option<DateTime<Utc>>,
+        limit: Option<usize>,
+    ) -> BoxFuture<'a, Result<StoredResults, StorageError>> {
+        let results = match kind {
+            MonitorKind::Probe => StoredResults::Probes(
+                self.probes
+                    .read(monitor_name, |results| select(results, since, limit))
+                    .unwrap_or_default(),
+            ),
+            MonitorKind::Story => StoredResults::Stories(
+                self.stories
+                    .read(monitor_name, |results| select(results, since, limit))
+                    .unwrap_or_default(),
+            ),
+        };
+        Box::pin(async { Ok(results) })
+    }
+
+    // The window is bounded by its result limit and `settings.max_result_memory_mb` instead
+    fn prune(&self, _before: DateTime<Utc>) -> BoxFuture<'_, Result<u64, StorageError>> {
+        Box::pin(async { Ok(0) })
+    }
+
+    fn durable(&self) -> bool {
+        false
+    }
+}
+
+// The store of `settings.storage`, None for the in-memory default. Connecting also brings the
+// database schema up to date.
+pub async fn open(settings: &StorageSettings) -> Result<Option<Arc<dyn ResultSink>>, StorageError> {
+    let Some(url) = settings
+        .postgres_url
+        .as_deref()
+        .filter(|url| !url.is_empty())
+    else {
+        return Ok(None);
+    };
+    let sink = open_postgres(url).await?;
+    info!(storage = sink.name(), "Opened result storage");
+    Ok(Some(sink))
+}
+
+#[cfg(feature = "postgres")]
+async fn open_postgres(url: &str) -> Result<Arc<dyn ResultSink>, StorageError> {
+    Ok(Arc::new(postgres::PostgresStorage::connect(url).await?))
+}
+
+#[cfg(not(feature = "postgres"))]
+async fn open_postgres(_url: &str) -> Result<Arc<dyn ResultSink>, StorageError> {
+    Err(StorageError {
+        message:
+            "settings.storage.postgres_url needs xbp-monitoring built with the `postgres` feature"
+                .to_owned(),
+    })
+}
+
+// Hands results to a writer task without waiting, so a slow database never holds up a run
+pub struct StorageQueue {
+    sender: Sender<StorageRecord>,
+    dropped: Counter<u64>,
+}
+
+impl StorageQueue {
+    // Needs a Tokio runtime
+    pub fn start(
+        sink: Arc<dyn ResultSink>,
+        settings: &StorageSettings,
+        dropped: Counter<u64>,
+    ) -> StorageQueue {
+        let (sender, receiver) = channel(settings.queue_size.unwrap_or(DEFAULT_QUEUE_SIZE).max(1));
+        tokio::spawn(write(
+            sink,
+            receiver,
+            settings.batch_size.unwrap_or(DEFAULT_BATCH_SIZE).max(1),
+            settings.retention,
+            dropped.clone(),
+        ));
+        StorageQueue { sender, dropped }
+    }
+
+    pub fn push(&self, record: StorageRecord) {
+        if self.sender.try_send(record).is_err() {
+            self.dropped.add(1, &[]);
+        }
+    }
+}
+
+async fn write(
+    sink: Arc<dyn ResultSink>,
+    mut receiver: Receiver<StorageRecord>,
+    batch_size: usize,
+    retention: Option<Duration>,
+    dropped: Counter<u64>,
+) {
+    let mut batch = Vec::with_capacity(batch_size);
+    let mut prune = tokio::time::interval(PRUNE_INTERVAL);
+    let mut last_warning: Option<Instant> = None;
+    loop {
+        tokio::select! {
+            received = receiver.recv_many(&mut batch, batch_size) => {
+                // Every sender is gone, the `AppState` was dropped
+                if received == 0 {
+                    return;
+                }
+                if let Err(e) = sink.record_batch(&batch).await {
+                    dropped.add(batch.len() as u64, &[]);
+                    if last_warning.is_none_or(|last| last.elapsed() >= WRITE_WARNING_INTERVAL) {
+                        last_warning = Some(Instant::now());
+                        warn!("Could not write {} results to {}: {}", batch.len(), sink.name(), e);
+                    }
+                }
+                batch.clear();
+            }
+            _ = prune.tick(), if retention.is_some() => {
+                let before = Utc::now() - retention.unwrap_or_default();
+                match sink.prune(before).await {
+                    Ok(pruned) if pruned > 0 => {
+                        info!(storage = sink.name(), pruned, "Pruned results past retention")
+                    }
+                    Ok(_) => {}
+                    Err(e) => warn!("Could not prune results of {}: {}", sink.name(), e),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod storage_tests {
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use chrono::{DateTime, Utc};
+    use futures::future::BoxFuture;
+
+    use super::{MonitorKind, ResultSink, StorageQueue, StorageRecord, StoredResults};
+    use crate::app_state::AppState;
+    use crate::config::{Config, StorageSettings};
+    use crate::errors::StorageError;
+    use crate::otel::metrics::MetricsState;
+    use crate::probe::model::{ProbeResult, StoryResult};
+    use crate::test_utils::app_state_test_utils::seeded_app_state;
+    use crate::test_utils::metrics_test_utils::counter_value;
+
+    // Keeps what it was handed, like a database would
+    #[derive(Default)]
+    struct RecordingSink {
+        probes: Mutex<Vec<(String, ProbeResult)>>,
+    }
+
+    impl ResultSink for RecordingSink {
+        fn name(&self) -> &'static str {
+            "recording"
+        }
+
+        fn record_probe<'a>(
+            &'a self,
+            monitor_name: &'a str,
+            result: &'a ProbeResult,
+        ) -> BoxFuture<'a, Result<(), StorageError>> {
+            self.probes
+                .lock()
+                .unwrap()
+                .push((monitor_name.to_owned(), result.clone()));
+            Box::pin(async { Ok(()) })
+        }
+
+        fn record_story<'a>(
+            &'a self,
+            _monitor_name: &'a str,
+            _result: &'a StoryResult,
+        ) -> BoxFuture<'a, Result<(), StorageError>> {
+            Box::pin(async { Ok(()) })
+        }
+
+        fn recent<'a>(
+            &'a self,
+            _kind: MonitorKind,
+            monitor_name: &'a str,
+            _since: Option<DateTime<Utc>>,
+            _limit: Option<usize>,
+        ) -> BoxFuture<'a, Result<StoredResults, StorageError>> {
+            let results = self
+                .probes
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|(name, _)| name == monitor_name)
+                .map(|(_, result)| result.clone())
+                .collect();
+            Box::pin(async { Ok(StoredResults::Probes(results)) })
+        }
+
+        fn prune(&self, _before: DateTime<Utc>) -> BoxFuture<'_, Result<u64, StorageError>> {
+            Box::pin(async { Ok(0) })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_memory_storage_reads_the_window() {
+        let app_state = seeded_app_state();
+
+        let results = app_state
+            .storage
+            .recent(MonitorKind::Probe, "checkout-api", None, Some(1))
+            .await
+            .unwrap()
+            .into_probes();
+
+        assert_eq!(1, results.len());
+        assert!(!results[0].success);
+        let unknown = app_state
+            .storage
+            .recent(MonitorKind::Story, "unknown", None, None)
+            .await
+            .unwrap();
+        assert!(unknown.into_stories().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_durable_storage_gets_every_stored_result() {
+        let sink = Arc::new(RecordingSink::default());
+        let source = seeded_app_state();
+        let app_state = AppState::new(Config::default()).with_storage(sink.clone());
+
+        for result in source.probe_results.recent("checkout-api").unwrap() {
+            app_state.add_probe_result("checkout-api".to_owned(), result);
+        }
+        // The writer task runs on this runtime, give it the chance to drain the queue
+        let written = async {
+            while sink.probes.lock().unwrap().len() < 2 {
+                tokio::task::yield_now().await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(5), written)
+            .await
+            .unwrap();
+
+        let results = app_state
+            .storage
+            .recent(MonitorKind::Probe, "checkout-api", None, None)
+            .await
+            .unwrap()
+            .into_probes();
+        assert_eq!(2, results.len());
+        assert_eq!(
+            2,
+            app_state
+                .probe_results
+                .recent("checkout-api")
+                .unwrap()
+                .len()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_full_queue_drops_results() {
+        let metrics_state = MetricsState::for_testing();
+        let queue = StorageQueue::start(
+            Arc::new(RecordingSink::default()),
+            &StorageSettings {
+                queue_size: Some(1),
+                ..Default::default()
+            },
+            metrics_state.metrics().storage_records_dropped,
+        );
+        let result = seeded_app_state()
+            .probe_results
+            .latest("checkout-api")
+            .unwrap();
+        let record = || StorageRecord::Probe {
+            name: "checkout-api".to_owned(),
+            result: Box::new(result.clone()),
+        };
+
+        // The writer hasn't run yet, so the second record doesn't fit
+        queue.push(record());
+        queue.push(record());
+
+        let metrics = metrics_state.collect().unwrap();
+        assert_eq!(
+            Some(1),
+            counter_value(&metrics, "storage_records_dropped", &[])
+        );
+    }
+}
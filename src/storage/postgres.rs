@@ -0,0 +1,223 @@
+// Results in a Postgres table, one JSON document per run. The schema is migrated on connect.
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+use serde::de::DeserializeOwned;
+use sqlx::postgres::{PgPool, PgPoolOptions};
+use sqlx::{Postgres, QueryBuilder, Row};
+
+use super::{MonitorKind, ResultSink, StorageRecord, StoredResults};
+use crate::errors::StorageError;
+use crate::probe::model::{ProbeResult, StoryResult};
+
+const MAX_CONNECTIONS: u32 = 4;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Applied in order, each at most once. Only ever append to it.
+const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE xbp_results (
+        id BIGSERIAL PRIMARY KEY,
+        kind TEXT NOT NULL,
+        monitor_name TEXT NOT NULL,
+        timestamp_started TIMESTAMPTZ NOT NULL,
+        success BOOLEAN NOT NULL,
+        result JSONB NOT NULL
+    )",
+    "CREATE INDEX xbp_results_monitor ON xbp_results (kind, monitor_name, timestamp_started)",
+    "CREATE INDEX xbp_results_started ON xbp_results (timestamp_started)",
+];
+
+impl From<sqlx::Error> for StorageError {
+    fn from(err: sqlx::Error) -> StorageError {
+        StorageError {
+            message: err.to_string(),
+        }
+    }
+}
+
+impl From<serde_json::Error> for StorageError {
+    fn from(err: serde_json::Error) -> StorageError {
+        StorageError {
+            message: format!("invalid stored result: {}", err),
+        }
+    }
+}
+
+pub struct PostgresStorage {
+    pool: PgPool,
+}
+
+impl PostgresStorage {
+    pub async fn connect(url: &str) -> Result<PostgresStorage, StorageError> {
+        let pool = PgPoolOptions::new()
+            .max_connections(MAX_CONNECTIONS)
+            .acquire_timeout(CONNECT_TIMEOUT)
+            .connect(url)
+            .await?;
+        migrate(&pool).await?;
+        Ok(PostgresStorage { pool })
+    }
+
+    async fn insert(&self, records: &[StorageRecord]) -> Result<(), StorageError> {
+        if records.is_empty() {
+            return Ok(());
+        }
+        let mut rows = Vec::with_capacity(records.len());
+        for record in records {
+            let (started, success, result) = match record {
+                StorageRecord::Probe { result, .. } => (
+                    result.timestamp_started,
+                    result.success,
+                    serde_json::to_value(result)?,
+                ),
+                StorageRecord::Story { result, .. } => (
+                    result.timestamp_started,
+                    result.success,
+                    serde_json::to_value(result)?,
+                ),
+            };
+            rows.push((record.kind(), record.name(), started, success, result));
+        }
+        let mut insert: QueryBuilder<Postgres> = QueryBuilder::new(
+            "INSERT INTO xbp_results (kind, monitor_name, timestamp_started, success, result) ",
+        );
+        insert.push_values(rows, |mut row, (kind, name, started, success, result)| {
+            row.push_bind(kind.as_str())
+                .push_bind(name)
+                .push_bind(started)
+                .push_bind(success)
+                .push_bind(result);
+        });
+        insert.build().execute(&self.pool).await?;
+        Ok(())
+    }
+
+    // Oldest first, the latest `limit` of them
+    async fn select<T: DeserializeOwned>(
+        &self,
+        kind: MonitorKind,
+        monitor_name: &str,
+        since: Option<DateTime<Utc>>,
+        limit: Option<usize>,
+    ) -> Result<Vec<T>, StorageError> {
+        let rows = sqlx::query(
+            "SELECT result FROM xbp_results
+            WHERE kind = $1 AND monitor_name = $2
+                AND ($3::TIMESTAMPTZ IS NULL OR timestamp_started >= $3)
+            ORDER BY timestamp_started DESC
+            LIMIT $4",
+        )
+        .bind(kind.as_str())
+        .bind(monitor_name)
+        .bind(since)
+        .bind(limit.map(|limit| limit.min(i64::MAX as usize) as i64))
+        .fetch_all(&self.pool)
+        .await?;
+        let mut results = rows
+            .into_iter()
+            .map(|row| {
+                let result: serde_json::Value = row.try_get("result")?;
+                Ok(serde_json::from_value(result)?)
+            })
+            .collect::<Result<Vec<T>, StorageError>>()?;
+        results.reverse();
+        Ok(results)
+    }
+}
+
+async fn migrate(pool: &PgPool) -> Result<(), StorageError> {
+    let mut transaction = pool.begin().await?;
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS xbp_schema_migrations (
+            version INTEGER PRIMARY KEY,
+            applied_at TIMESTAMPTZ NOT NULL DEFAULT now()
+        )",
+    )
+    .execute(&mut *transaction)
+    .await?;
+    // Instances starting at the same time migrate one after the other
+    sqlx::query("LOCK TABLE xbp_schema_migrations IN EXCLUSIVE MODE")
+        .execute(&mut *transaction)
+        .await?;
+    let applied: i32 =
+        sqlx::query_scalar("SELECT COALESCE(MAX(version), 0) FROM xbp_schema_migrations")
+            .fetch_one(&mut *transaction)
+            .await?;
+    for (version, migration) in (1..).zip(MIGRATIONS).skip(applied.max(0) as usize) {
+        sqlx::query(migration).execute(&mut *transaction).await?;
+        sqlx::query("INSERT INTO xbp_schema_migrations (version) VALUES ($1)")
+            .bind(version)
+            .execute(&mut *transaction)
+            .await?;
+    }
+    transaction.commit().await?;
+    Ok(())
+}
+
+impl ResultSink for PostgresStorage {
+    fn name(&self) -> &'static str {
+        "postgres"
+    }
+
+    fn record_probe<'a>(
+        &'a self,
+        monitor_name: &'a str,
+        result: &'a ProbeResult,
+    ) -> BoxFuture<'a, Result<(), StorageError>> {
+        let records = [StorageRecord::Probe {
+            name: monitor_name.to_owned(),
+            result: Box::new(result.clone()),
+        }];
+        Box::pin(async move { self.insert(&records).await })
+    }
+
+    fn record_story<'a>(
+        &'a self,
+        monitor_name: &'a str,
+        result: &'a StoryResult,
+    ) -> BoxFuture<'a, Result<(), StorageError>> {
+        let records = [StorageRecord::Story {
+            name: monitor_name.to_owned(),
+            result: Box::new(result.clone()),
+        }];
+        Box::pin(async move { self.insert(&records).await })
+    }
+
+    // A single insert per batch
+    fn record_batch<'a>(
+        &'a self,
+        records: &'a [StorageRecord],
+    ) -> BoxFuture<'a, Result<(), StorageError>> {
+        Box::pin(self.insert(records))
+    }
+
+    fn recent<'a>(
+        &'a self,
+        kind: MonitorKind,
+        monitor_name: &'a str,
+        since: Option<DateTime<Utc>>,
+        limit: Option<usize>,
+    ) -> BoxFuture<'a, Result<StoredResults, StorageError>> {
+        Box::pin(async move {
+            Ok(match kind {
+                MonitorKind::Probe => {
+                    StoredResults::Probes(self.select(kind, monitor_name, since, limit).await?)
+                }
+                MonitorKind::Story => {
+                    StoredResults::Stories(self.select(kind, monitor_name, since, limit).await?)
+                }
+            })
+        })
+    }
+
+    fn prune(&self, before: DateTime<Utc>) -> BoxFuture<'_, Result<u64, StorageError>> {
+        Box::pin(async move {
+            let deleted = sqlx::query("DELETE FROM xbp_results WHERE timestamp_started < $1")
+                .bind(before)
+                .execute(&self.pool)
+                .await?;
+            Ok(deleted.rows_affected())
+        })
+    }
+}
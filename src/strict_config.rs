@@ -0,0 +1,542 @@
+// Unknown keys of a config, rejected with `XBP_STRICT_CONFIG=true` or `settings.strict_config: true`.
+// serde can't switch `deny_unknown_fields` on at runtime, so the parsed document is walked against the
+// fields of each section instead. Update these lists along with the structs they mirror; the tests
+// compare them with what the structs serialize. `with` blocks always deny unknown fields.
+
+use serde_yaml::Value;
+
+pub const STRICT_CONFIG_VAR: &str = "XBP_STRICT_CONFIG";
+
+const CONFIG_FIELDS: &[&str] = &[
+    "version",
+    "settings",
+    "probes",
+    "stories",
+    "reports",
+    "alert_channels",
+    "expectation_sets",
+];
+const SETTINGS_FIELDS: &[&str] = &[
+    "default_success_statuses",
+    "probe_modules",
+    "max_in_flight",
+    "ignore_results_during_reload",
+    "incident_retention",
+    "runtime",
+    "metrics",
+    "persist_runtime_monitors",
+    "capture_headers",
+    "log_backend_changes",
+    "audit",
+    "verify_on_reload",
+    "strict_config",
+    "max_result_memory_mb",
+    "self_alerts",
+    "rate_limits",
+    "storage",
+];
+const RUNTIME_SETTINGS_FIELDS: &[&str] = &[
+    "worker_threads",
+    "blocking_threads",
+    "max_blocking_duration_warning_ms",
+    "blocking_body_threshold_bytes",
+];
+const METRICS_SETTINGS_FIELDS: &[&str] = &["duration_unit"];
+const AUDIT_SETTINGS_FIELDS: &[&str] = &[
+    "enabled",
+    "sample_rate",
+    "sample_window",
+    "path",
+    "max_size_mb",
+    "max_files",
+    "header_values",
+];
+const SELF_ALERT_SETTINGS_FIELDS: &[&str] = &[
+    "channel",
+    "cooldown",
+    "alert_failure_threshold",
+    "alert_failure_window",
+    "watchdog",
+];
+const RATE_LIMIT_SETTINGS_FIELDS: &[&str] = &["max_backoff", "count_as_failure"];
+const STORAGE_SETTINGS_FIELDS: &[&str] = &[
+    "postgres_url",
+    "strict",
+    "queue_size",
+    "batch_size",
+    "retention",
+];
+const PROBE_MODULES_FIELDS: &[&str] = &["allowed_target_patterns", "modules"];
+const PROBE_MODULE_FIELDS: &[&str] = &[
+    "http_method",
+    "headers",
+    "timeout_seconds",
+    "expectations",
+    "success_statuses",
+];
+const PROBE_FIELDS: &[&str] = &[
+    "name",
+    "type",
+    "url",
+    "urls",
+    "http_method",
+    "with",
+    "expectations",
+    "schedule",
+    "alerts",
+    "sensitive",
+    "tags",
+    "group",
+    "sla_window",
+    "recovery_threshold",
+    "retries",
+    "circuit_breaker",
+    "smtp",
+    "ntp",
+    "sftp",
+    "composite",
+    "full_stack",
+    "success_statuses",
+    "max_in_flight",
+    "alerts_include_details",
+    "name_from_response",
+    "expanded_url",
+    "metrics_from_body",
+    "allow_sensitive_extraction",
+    "enabled",
+];
+const SMTP_FIELDS: &[&str] = &[
+    "starttls",
+    "ehlo_name",
+    "username",
+    "password",
+    "mail_from",
+    "rcpt_to",
+    "expect",
+];
+const SMTP_EXPECT_FIELDS: &[&str] = &["supports_starttls", "max_banner_ms", "reply_codes"];
+const NTP_FIELDS: &[&str] = &["expect"];
+const SLA_WINDOW_FIELDS: &[&str] = &["days", "start", "end", "timezone"];
+const CIRCUIT_BREAKER_FIELDS: &[&str] = &[
+    "open_after_failures",
+    "probe_interval_when_open",
+    "close_after_successes",
+];
+const NTP_EXPECT_FIELDS: &[&str] = &["max_offset_ms", "max_delay_ms"];
+const SFTP_FIELDS: &[&str] = &[
+    "username",
+    "password",
+    "private_key_path",
+    "private_key_passphrase",
+    "host_key_fingerprint",
+    "known_hosts",
+    "accept_any_host_key",
+    "list_path",
+    "expect_file_matching",
+    "max_age_hours",
+];
+const FULL_STACK_FIELDS: &[&str] = &["dns_timeout", "tls_timeout"];
+const SCHEDULE_FIELDS: &[&str] = &["initial_delay", "interval", "allow_fast"];
+const EXPECTATION_FIELDS: &[&str] = &["field", "operation", "value"];
+const ALERT_FIELDS: &[&str] = &[
+    "channel",
+    "on",
+    "url",
+    "webhook_url",
+    "type",
+    "path",
+    "max_size_mb",
+    "max_files",
+    "recovery_template",
+    "api_key",
+    "region",
+    "priority",
+    "tags",
+    "responders",
+];
+const STORY_FIELDS: &[&str] = &[
+    "name",
+    "base_url",
+    "setup",
+    "steps",
+    "teardown",
+    "schedule",
+    "alerts",
+    "tags",
+    "group",
+    "recovery_threshold",
+    "expectations",
+    "sensitive",
+    "alerts_include_details",
+];
+const STORY_EXPECTATION_FIELDS: &[&str] = &["expr"];
+const STEP_FIELDS: &[&str] = &[
+    "name",
+    "url",
+    "http_method",
+    "with",
+    "body",
+    "body_template",
+    "expectations",
+    "sensitive",
+    "captures",
+];
+const REPORT_FIELDS: &[&str] = &["name", "schedule", "alerts", "tags", "template"];
+const REPORT_SCHEDULE_FIELDS: &[&str] = &["cron", "timezone"];
+
+// `XBP_STRICT_CONFIG` set to `true` or `1`
+pub fn strict_from_env() -> bool {
+    std::env::var(STRICT_CONFIG_VAR)
+        .is_ok_and(|value| value == "1" || value.eq_ignore_ascii_case("true"))
+}
+
+// Every unknown key as `<where>: unknown field `<key>``, in document order
+pub fn unknown_fields(document: &Value) -> Vec<String> {
+    let mut unknown = Fields::default();
+    unknown.check(Some(document), CONFIG_FIELDS, "config");
+
+    let settings = document.get("settings");
+    unknown.check(settings, SETTINGS_FIELDS, "settings");
+    let section = |name: &str| settings.and_then(|settings| settings.get(name));
+    unknown.check(
+        section("runtime"),
+        RUNTIME_SETTINGS_FIELDS,
+        "settings.runtime",
+    );
+    unknown.check(
+        section("metrics"),
+        METRICS_SETTINGS_FIELDS,
+        "settings.metrics",
+    );
+    unknown.check(section("audit"), AUDIT_SETTINGS_FIELDS, "settings.audit");
+    unknown.check(
+        section("self_alerts"),
+        SELF_ALERT_SETTINGS_FIELDS,
+        "settings.self_alerts",
+    );
+    unknown.check(
+        section("rate_limits"),
+        RATE_LIMIT_SETTINGS_FIELDS,
+        "settings.rate_limits",
+    );
+    unknown.check(
+        section("storage"),
+        STORAGE_SETTINGS_FIELDS,
+        "settings.storage",
+    );
+    let probe_modules = section("probe_modules");
+    unknown.check(
+        probe_modules,
+        PROBE_MODULES_FIELDS,
+        "settings.probe_modules",
+    );
+    let modules = probe_modules.and_then(|modules| modules.get("modules"));
+    for (name, module) in mapping(modules) {
+        let owner = format!("probe module '{}'", key_name(name));
+        unknown.check(Some(module), PROBE_MODULE_FIELDS, &owner);
+        unknown.check_expectations(module, &owner);
+    }
+
+    for probe in sequence(document.get("probes")) {
+        let owner = format!("probe '{}'", monitor_name(probe));
+        unknown.check(Some(probe), PROBE_FIELDS, &owner);
+        unknown.check_schedule(probe, &owner);
+        unknown.check_alerts(probe, &owner);
+        unknown.check_expectations(probe, &owner);
+        let smtp = probe.get("smtp");
+        unknown.check(smtp, SMTP_FIELDS, &format!("{} smtp", owner));
+        let smtp_expect = smtp.and_then(|smtp| smtp.get("expect"));
+        unknown.check(
+            smtp_expect,
+            SMTP_EXPECT_FIELDS,
+            &format!("{} smtp.expect", owner),
+        );
+        let ntp = probe.get("ntp");
+        unknown.check(ntp, NTP_FIELDS, &format!("{} ntp", owner));
+        let ntp_expect = ntp.and_then(|ntp| ntp.get("expect"));
+        unknown.check(
+            ntp_expect,
+            NTP_EXPECT_FIELDS,
+            &format!("{} ntp.expect", owner),
+        );
+        unknown.check(probe.get("sftp"), SFTP_FIELDS, &format!("{} sftp", owner));
+        unknown.check(
+            probe.get("full_stack"),
+            FULL_STACK_FIELDS,
+            &format!("{} full_stack", owner),
+        );
+        unknown.check(
+            probe.get("sla_window"),
+            SLA_WINDOW_FIELDS,
+            &format!("{} sla_window", owner),
+        );
+        unknown.check(
+            probe.get("circuit_breaker"),
+            CIRCUIT_BREAKER_FIELDS,
+            &format!("{} circuit_breaker", owner),
+        );
+    }
+
+    for story in sequence(document.get("stories")) {
+        let owner = format!("story '{}'", monitor_name(story));
+        unknown.check(Some(story), STORY_FIELDS, &owner);
+        unknown.check_schedule(story, &owner);
+        unknown.check_alerts(story, &owner);
+        for expectation in sequence(story.get("expectations")) {
+            let owner = format!("{} expectation", owner);
+            unknown.check(Some(expectation), STORY_EXPECTATION_FIELDS, &owner);
+        }
+        for (list, kind) in [
+            ("setup", "setup step"),
+            ("steps", "step"),
+            ("teardown", "teardown step"),
+        ] {
+            for step in sequence(story.get(list)) {
+                let owner = format!("{} {} '{}'", owner, kind, monitor_name(step));
+                unknown.check(Some(step), STEP_FIELDS, &owner);
+                unknown.check_expectations(step, &owner);
+            }
+        }
+    }
+
+    for report in sequence(document.get("reports")) {
+        let owner = format!("report '{}'", monitor_name(report));
+        unknown.check(Some(report), REPORT_FIELDS, &owner);
+        let schedule_owner = format!("{} schedule", owner);
+        unknown.check(
+            report.get("schedule"),
+            REPORT_SCHEDULE_FIELDS,
+            &schedule_owner,
+        );
+        unknown.check_alerts(report, &owner);
+    }
+
+    for (name, alert) in mapping(document.get("alert_channels")) {
+        let owner = format!("alert channel '{}'", key_name(name));
+        unknown.check(Some(alert), ALERT_FIELDS, &owner);
+    }
+
+    for (name, set) in mapping(document.get("expectation_sets")) {
+        let owner = format!("expectation set '{}' expectation", key_name(name));
+        for expectation in sequence(Some(set)) {
+            unknown.check(Some(expectation), EXPECTATION_FIELDS, &owner);
+        }
+    }
+
+    unknown.0
+}
+
+#[derive(Default)]
+struct Fields(Vec<String>);
+
+impl Fields {
+    fn check(&mut self, value: Option<&Value>, known: &[&str], owner: &str) {
+        for (key, _) in mapping(value) {
+            let key = key_name(key);
+            if known.contains(&key.as_str()) {
+                continue;
+            }
+            let message = match closest(&key, known) {
+                Some(field) => format!(
+                    "{}: unknown field `{}`, did you mean `{}`?",
+                    owner, key, field
+                ),
+                None => format!("{}: unknown field `{}`", owner, key),
+            };
+            self.0.push(message);
+        }
+    }
+
+    fn check_schedule(&mut self, monitor: &Value, owner: &str) {
+        let owner = format!("{} schedule", owner);
+        self.check(monitor.get("schedule"), SCHEDULE_FIELDS, &owner);
+    }
+
+    fn check_alerts(&mut self, monitor: &Value, owner: &str) {
+        let owner = format!("{} alert", owner);
+        for alert in sequence(monitor.get("alerts")) {
+            self.check(Some(alert), ALERT_FIELDS, &owner);
+        }
+    }
+
+    // Expectation sets are resolved by then, so `expectations` is a list
+    fn check_expectations(&mut self, monitor: &Value, owner: &str) {
+        let owner = format!("{} expectation", owner);
+        for expectation in sequence(monitor.get("expectations")) {
+            self.check(Some(expectation), EXPECTATION_FIELDS, &owner);
+        }
+    }
+}
+
+fn mapping(value: Option<&Value>) -> impl Iterator<Item = (&Value, &Value)> {
+    value
+        .and_then(Value::as_mapping)
+        .into_iter()
+        .flat_map(|mapping| mapping.iter())
+}
+
+fn sequence(value: Option<&Value>) -> impl Iterator<Item = &Value> {
+    value.and_then(Value::as_sequence).into_iter().flatten()
+}
+
+fn key_name(key: &Value) -> String {
+    match key {
+        Value::String(key) => key.clone(),
+        key => serde_yaml::to_string(key)
+            .map(|key| key.trim_end().to_owned())
+            .unwrap_or_default(),
+    }
+}
+
+fn monitor_name(monitor: &Value) -> &str {
+    monitor
+        .get("name")
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+}
+
+// The known field at most two edits away from a typo, e.g. `schedule` for `schedul`
+fn closest<'a>(key: &str, known: &[&'a str]) -> Option<&'a str> {
+    known
+        .iter()
+        .map(|field| (edit_distance(key, field), *field))
+        .filter(|(distance, _)| *distance <= 2)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, field)| field)
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a != *b);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod strict_config_tests {
+    use serde_yaml::Value;
+
+    use super::{unknown_fields, PROBE_FIELDS, SETTINGS_FIELDS, SFTP_FIELDS, STORY_FIELDS};
+    use crate::config::Settings;
+    use crate::probe::model::{ProbeScheduleParameters, SftpParameters, Story};
+    use crate::test_utils::probe_test_utils::probe_get_with_expected_status;
+
+    fn keys(value: Value) -> Vec<String> {
+        value
+            .as_mapping()
+            .unwrap()
+            .keys()
+            .map(|key| key.as_str().unwrap().to_owned())
+            .collect()
+    }
+
+    #[test]
+    fn test_field_lists_cover_the_structs() {
+        let probe = probe_get_with_expected_status(
+            reqwest::StatusCode::OK,
+            "http://localhost".to_owned(),
+            "".to_owned(),
+        );
+        for key in keys(serde_yaml::to_value(probe).unwrap()) {
+            assert!(PROBE_FIELDS.contains(&key.as_str()), "probe field {}", key);
+        }
+        for key in keys(serde_yaml::to_value(SftpParameters::default()).unwrap()) {
+            assert!(SFTP_FIELDS.contains(&key.as_str()), "sftp field {}", key);
+        }
+        for key in keys(serde_yaml::to_value(Settings::default()).unwrap()) {
+            assert!(
+                SETTINGS_FIELDS.contains(&key.as_str()),
+                "settings field {}",
+                key
+            );
+        }
+        let story = Story {
+            name: "checkout".to_owned(),
+            base_url: Some("http://localhost".to_owned()),
+            setup: None,
+            steps: vec![],
+            teardown: None,
+            schedule: ProbeScheduleParameters {
+                initial_delay: Default::default(),
+                interval: Default::default(),
+                allow_fast: true,
+            },
+            alerts: None,
+            tags: None,
+            group: None,
+            recovery_threshold: None,
+            expectations: None,
+            sensitive: false,
+            alerts_include_details: false,
+            runtime_added: false,
+        };
+        for key in keys(serde_yaml::to_value(story).unwrap()) {
+            assert!(STORY_FIELDS.contains(&key.as_str()), "story field {}", key);
+        }
+    }
+
+    #[test]
+    fn test_unknown_fields_are_named_with_their_monitor() {
+        let document: Value = serde_yaml::from_str(
+            r#"
+setings: {}
+probes:
+  - name: api
+    url: http://localhost/health
+    schedul: { initial_delay: 0, interval: 60 }
+    alerts:
+      - { url: http://localhost/hook, colour: red }
+stories:
+  - name: checkout
+    schedule: { initial_delay: 0, interval: 60, jitter: 5 }
+    steps:
+      - name: login
+        url: http://localhost/login
+        expectations:
+          - { field: StatusCode, operation: Equals, value: "200", vaule: "201" }
+    teardown:
+      - name: logout
+        url: http://localhost/logout
+        mehtod: POST
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            vec![
+                "config: unknown field `setings`, did you mean `settings`?",
+                "probe 'api': unknown field `schedul`, did you mean `schedule`?",
+                "probe 'api' alert: unknown field `colour`",
+                "story 'checkout' schedule: unknown field `jitter`",
+                "story 'checkout' step 'login' expectation: unknown field `vaule`, did you mean `value`?",
+                "story 'checkout' teardown step 'logout': unknown field `mehtod`",
+            ],
+            unknown_fields(&document)
+        );
+    }
+
+    #[test]
+    fn test_known_fields_pass() {
+        let document: Value = serde_yaml::from_str(
+            r#"
+version: "1"
+settings: { strict_config: true, audit: { enabled: true } }
+probes:
+  - name: api
+    url: http://localhost/health
+    schedule: { initial_delay: 0, interval: 60 }
+    alerts: [{ webhook_url: http://localhost/hook }]
+"#,
+        )
+        .unwrap();
+
+        assert!(unknown_fields(&document).is_empty());
+    }
+}
@@ -0,0 +1,263 @@
+// Values `${{ env.* }}` substitution left unusable: probe urls that are empty or have no scheme, and
+// templated header values and alert urls that came out empty. Each would only fail once a monitor
+// runs, with errors like "relative URL without a base", so the config is rejected when it loads
+// instead. The document is walked before substitution, each value is substituted on its own to tell
+// which variables it was made of.
+
+use std::collections::BTreeSet;
+
+use serde_yaml::Value;
+
+use crate::config::{replace_env_vars, EnvSubstitution};
+
+// Every problem, in the order of the document
+pub fn unusable_substitutions(document: &Value) -> Vec<String> {
+    let mut problems = Problems::default();
+
+    for probe in sequence(document.get("probes")) {
+        let owner = format!("probe '{}'", monitor_name(probe));
+        if let Some(url) = probe.get("url").and_then(Value::as_str) {
+            problems.check_url(&owner, "url", url);
+        }
+        for (index, url) in sequence(probe.get("urls")).enumerate() {
+            if let Some(url) = url.as_str() {
+                problems.check_url(&owner, &format!("urls[{}]", index), url);
+            }
+        }
+        problems.check_headers(&owner, probe.get("with"));
+        problems.check_alerts(&owner, probe.get("alerts"));
+    }
+
+    for story in sequence(document.get("stories")) {
+        let owner = format!("story '{}'", monitor_name(story));
+        for section in ["setup", "steps", "teardown"] {
+            for step in sequence(story.get(section)) {
+                let owner = format!("{} step '{}'", owner, monitor_name(step));
+                problems.check_headers(&owner, step.get("with"));
+            }
+        }
+        problems.check_alerts(&owner, story.get("alerts"));
+    }
+
+    for report in sequence(document.get("reports")) {
+        let owner = format!("report '{}'", monitor_name(report));
+        problems.check_alerts(&owner, report.get("alerts"));
+    }
+
+    if let Some(channels) = document.get("alert_channels").and_then(Value::as_mapping) {
+        for (name, channel) in channels {
+            let owner = format!("alert channel '{}'", name.as_str().unwrap_or_default());
+            problems.check_alert_url(&owner, channel);
+        }
+    }
+
+    problems.0
+}
+
+#[derive(Default)]
+struct Problems(Vec<String>);
+
+impl Problems {
+    fn check_url(&mut self, owner: &str, field: &str, raw: &str) {
+        let (url, report) = substitute(raw);
+        if url.trim().is_empty() {
+            self.0.push(format!(
+                "{}: {} is empty{}",
+                owner,
+                field,
+                empty_because(&report)
+            ));
+        } else if !url.contains("://") {
+            self.0.push(format!(
+                "{}: {} '{}' has no scheme{}, expected e.g. https://",
+                owner,
+                field,
+                url,
+                unset_because(&report)
+            ));
+        }
+    }
+
+    fn check_headers(&mut self, owner: &str, with: Option<&Value>) {
+        let Some(with) = with else {
+            return;
+        };
+        let allowed: Vec<&str> = sequence(with.get("allow_empty"))
+            .filter_map(Value::as_str)
+            .collect();
+        for (name, value) in mapping(with.get("headers")) {
+            let (Some(name), Some(raw)) = (name.as_str(), value.as_str()) else {
+                continue;
+            };
+            if !is_templated(raw)
+                || allowed
+                    .iter()
+                    .any(|allowed| allowed.eq_ignore_ascii_case(name))
+            {
+                continue;
+            }
+            let (value, report) = substitute(raw);
+            if value.trim().is_empty() {
+                self.0.push(format!(
+                    "{}: header {} is empty{}, list it in `with.allow_empty` if that's intended",
+                    owner,
+                    name,
+                    empty_because(&report)
+                ));
+            }
+        }
+    }
+
+    fn check_alerts(&mut self, owner: &str, alerts: Option<&Value>) {
+        for (index, alert) in sequence(alerts).enumerate() {
+            self.check_alert_url(&format!("{} alert {}", owner, index + 1), alert);
+        }
+    }
+
+    // Alerts without a url are fine, they refer to a channel or write to a file or stdout
+    fn check_alert_url(&mut self, owner: &str, alert: &Value) {
+        for field in ["url", "webhook_url"] {
+            let Some(raw) = alert.get(field).and_then(Value::as_str) else {
+                continue;
+            };
+            if !is_templated(raw) {
+                continue;
+            }
+            let (url, report) = substitute(raw);
+            if url.trim().is_empty() {
+                self.0.push(format!(
+                    "{}: {} is empty{}",
+                    owner,
+                    field,
+                    empty_because(&report)
+                ));
+            }
+        }
+    }
+}
+
+fn is_templated(raw: &str) -> bool {
+    raw.contains("${{")
+}
+
+// The substituted value, with the variables it was made of
+fn substitute(raw: &str) -> (String, EnvSubstitution) {
+    if !is_templated(raw) {
+        return (raw.to_owned(), EnvSubstitution::default());
+    }
+    replace_env_vars(raw)
+}
+
+// Why a value came out empty: unset variables, otherwise variables set to an empty string
+fn empty_because(report: &EnvSubstitution) -> String {
+    if !report.missing.is_empty() {
+        format!(" because {} not set", listed(&report.missing))
+    } else if !report.substituted.is_empty() {
+        format!(" because {} empty", listed(&report.substituted))
+    } else {
+        String::new()
+    }
+}
+
+// A scheme can only be missing for a variable that isn't set, a set one holds the rest of the url
+fn unset_because(report: &EnvSubstitution) -> String {
+    if report.missing.is_empty() {
+        return String::new();
+    }
+    format!(" because {} not set", listed(&report.missing))
+}
+
+// "X is" or "X, Y are"
+fn listed(vars: &BTreeSet<String>) -> String {
+    let names: Vec<&str> = vars.iter().map(String::as_str).collect();
+    match names.len() {
+        1 => format!("environment variable {} is", names[0]),
+        _ => format!("environment variables {} are", names.join(", ")),
+    }
+}
+
+fn mapping(value: Option<&Value>) -> impl Iterator<Item = (&Value, &Value)> {
+    value.and_then(Value::as_mapping).into_iter().flatten()
+}
+
+fn sequence(value: Option<&Value>) -> impl Iterator<Item = &Value> {
+    value.and_then(Value::as_sequence).into_iter().flatten()
+}
+
+fn monitor_name(monitor: &Value) -> &str {
+    monitor
+        .get("name")
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod substitution_check_tests {
+    use std::env;
+
+    use super::unusable_substitutions;
+
+    fn problems(yaml: &str) -> Vec<String> {
+        unusable_substitutions(&serde_yaml::from_str(yaml).unwrap())
+    }
+
+    #[test]
+    fn test_empty_and_schemeless_urls_name_the_variable() {
+        env::remove_var("TEST_CHECK_UNSET_HOST");
+        env::set_var("TEST_CHECK_BARE_HOST", "api.example.com");
+
+        let problems = problems(
+            r#"
+probes:
+  - name: unset
+    url: ${{ env.TEST_CHECK_UNSET_HOST }}
+  - name: bare
+    url: ${{ env.TEST_CHECK_BARE_HOST }}/health
+  - name: literal
+    url: https://example.com/health
+"#,
+        );
+
+        assert_eq!(
+            vec![
+                "probe 'unset': url is empty because environment variable TEST_CHECK_UNSET_HOST is not set",
+                "probe 'bare': url 'api.example.com/health' has no scheme, expected e.g. https://",
+            ],
+            problems
+        );
+    }
+
+    #[test]
+    fn test_empty_templated_headers_and_alert_urls_are_listed() {
+        env::remove_var("TEST_CHECK_UNSET_TOKEN");
+        env::remove_var("TEST_CHECK_UNSET_WEBHOOK");
+
+        let problems = problems(
+            r#"
+probes:
+  - name: api
+    url: https://example.com/health
+    with:
+      headers:
+        Authorization: Bearer ${{ env.TEST_CHECK_UNSET_TOKEN }}
+        X-Token: ${{ env.TEST_CHECK_UNSET_TOKEN }}
+        X-Optional: ${{ env.TEST_CHECK_UNSET_TOKEN }}
+        X-Literal: ""
+      allow_empty: [x-optional]
+alert_channels:
+  oncall:
+    webhook_url: ${{ env.TEST_CHECK_UNSET_WEBHOOK }}
+  shared:
+    type: stdout
+"#,
+        );
+
+        assert_eq!(
+            vec![
+                "probe 'api': header X-Token is empty because environment variable TEST_CHECK_UNSET_TOKEN is not set, list it in `with.allow_empty` if that's intended",
+                "alert channel 'oncall': webhook_url is empty because environment variable TEST_CHECK_UNSET_WEBHOOK is not set",
+            ],
+            problems
+        );
+    }
+}
@@ -1,12 +1,13 @@
 #[cfg(test)]
 pub mod probe_test_utils {
     use std::collections::HashMap;
+    use std::time::Duration;
 
     use reqwest::StatusCode;
 
     use crate::probe::model::{
-        ExpectField, ExpectOperation, Probe, ProbeAlert, ProbeExpectation, ProbeInputParameters,
-        ProbeScheduleParameters,
+        ExpectField, ExpectOperation, Probe, ProbeAlert, ProbeExpectation, ProbeOptions,
+        ProbeScheduleParameters, ProbeType,
     };
 
     pub fn probe_get_with_timeout_and_expected_status(
@@ -17,12 +18,23 @@ pub mod probe_test_utils {
     ) -> Probe {
         Probe {
             name: "Test probe".to_string(),
+            probe_type: ProbeType::Http,
             url,
+            urls: None,
             http_method: "GET".to_string(),
-            with: Some(ProbeInputParameters {
+            with: Some(ProbeOptions {
                 body: Some(body),
                 headers: Some(HashMap::new()),
-                timeout_seconds,
+                timeout: timeout_seconds.map(Duration::from_secs),
+                timeout_seconds: None,
+                query: None,
+                auth: None,
+                user_agent: None,
+                respect_retry_after: false,
+                follow_redirects: false,
+                allow_empty: None,
+                samples: None,
+                prefetch: None,
             }),
             expectations: Some(vec![ProbeExpectation {
                 field: ExpectField::StatusCode,
@@ -30,12 +42,33 @@ pub mod probe_test_utils {
                 value: status_code.as_str().into(),
             }]),
             schedule: ProbeScheduleParameters {
-                initial_delay: 0,
-                interval: 0,
+                initial_delay: Duration::ZERO,
+                interval: Duration::ZERO,
+                allow_fast: true,
             },
             alerts: None,
             tags: None,
+            group: None,
+            sla_window: None,
             sensitive: false,
+            recovery_threshold: None,
+            retries: None,
+            circuit_breaker: None,
+            smtp: None,
+            ntp: None,
+            sftp: None,
+            composite: None,
+            full_stack: None,
+            success_statuses: None,
+            max_in_flight: None,
+            alerts_include_details: false,
+            name_from_response: None,
+            expanded_url: None,
+            metrics_from_body: None,
+            allow_sensitive_extraction: false,
+            enabled: None,
+            runtime_added: false,
+            expanded_from: None,
         }
     }
 
@@ -46,12 +79,23 @@ pub mod probe_test_utils {
     ) -> Probe {
         Probe {
             name: "Test probe".to_string(),
+            probe_type: ProbeType::Http,
             url,
+            urls: None,
             http_method: "GET".to_string(),
-            with: Some(ProbeInputParameters {
+            with: Some(ProbeOptions {
                 body: Some(body),
                 headers: Some(HashMap::new()),
+                timeout: None,
                 timeout_seconds: None,
+                query: None,
+                auth: None,
+                user_agent: None,
+                respect_retry_after: false,
+                follow_redirects: false,
+                allow_empty: None,
+                samples: None,
+                prefetch: None,
             }),
             expectations: Some(vec![ProbeExpectation {
                 field: ExpectField::StatusCode,
@@ -59,12 +103,33 @@ pub mod probe_test_utils {
                 value: status_code.as_str().into(),
             }]),
             schedule: ProbeScheduleParameters {
-                initial_delay: 0,
-                interval: 0,
+                initial_delay: Duration::ZERO,
+                interval: Duration::ZERO,
+                allow_fast: true,
             },
             alerts: None,
             tags: None,
+            group: None,
+            sla_window: None,
             sensitive: false,
+            recovery_threshold: None,
+            retries: None,
+            circuit_breaker: None,
+            smtp: None,
+            ntp: None,
+            sftp: None,
+            composite: None,
+            full_stack: None,
+            success_statuses: None,
+            max_in_flight: None,
+            alerts_include_details: false,
+            name_from_response: None,
+            expanded_url: None,
+            metrics_from_body: None,
+            allow_sensitive_extraction: false,
+            enabled: None,
+            runtime_added: false,
+            expanded_from: None,
         }
     }
 
@@ -76,12 +141,23 @@ pub mod probe_test_utils {
     ) -> Probe {
         Probe {
             name: "Test probe".to_string(),
+            probe_type: ProbeType::Http,
             url,
+            urls: None,
             http_method: "GET".to_string(),
-            with: Some(ProbeInputParameters {
+            with: Some(ProbeOptions {
                 body: Some(body),
                 headers: Some(HashMap::new()),
+                timeout: None,
                 timeout_seconds: None,
+                query: None,
+                auth: None,
+                user_agent: None,
+                respect_retry_after: false,
+                follow_redirects: false,
+                allow_empty: None,
+                samples: None,
+                prefetch: None,
             }),
             expectations: Some(vec![ProbeExpectation {
                 field: ExpectField::StatusCode,
@@ -89,12 +165,36 @@ pub mod probe_test_utils {
                 value: status_code.as_str().into(),
             }]),
             schedule: ProbeScheduleParameters {
-                initial_delay: 0,
-                interval: 0,
+                initial_delay: Duration::ZERO,
+                interval: Duration::ZERO,
+                allow_fast: true,
             },
-            alerts: Some(vec![ProbeAlert { url: alert_url }]),
+            alerts: Some(vec![ProbeAlert {
+                url: alert_url,
+                ..Default::default()
+            }]),
             tags: None,
+            group: None,
+            sla_window: None,
             sensitive: false,
+            recovery_threshold: None,
+            retries: None,
+            circuit_breaker: None,
+            smtp: None,
+            ntp: None,
+            sftp: None,
+            composite: None,
+            full_stack: None,
+            success_statuses: None,
+            max_in_flight: None,
+            alerts_include_details: false,
+            name_from_response: None,
+            expanded_url: None,
+            metrics_from_body: None,
+            allow_sensitive_extraction: false,
+            enabled: None,
+            runtime_added: false,
+            expanded_from: None,
         }
     }
 
@@ -105,12 +205,23 @@ pub mod probe_test_utils {
     ) -> Probe {
         Probe {
             name: "Test probe".to_string(),
+            probe_type: ProbeType::Http,
             url,
+            urls: None,
             http_method: "POST".to_string(),
-            with: Some(ProbeInputParameters {
+            with: Some(ProbeOptions {
                 body: Some(body),
                 headers: Some(HashMap::new()),
+                timeout: None,
                 timeout_seconds: None,
+                query: None,
+                auth: None,
+                user_agent: None,
+                respect_retry_after: false,
+                follow_redirects: false,
+                allow_empty: None,
+                samples: None,
+                prefetch: None,
             }),
             expectations: Some(vec![
                 ProbeExpectation {
@@ -125,12 +236,337 @@ pub mod probe_test_utils {
                 },
             ]),
             schedule: ProbeScheduleParameters {
-                initial_delay: 0,
-                interval: 0,
+                initial_delay: Duration::ZERO,
+                interval: Duration::ZERO,
+                allow_fast: true,
             },
             alerts: None,
             tags: None,
+            group: None,
+            sla_window: None,
             sensitive: false,
+            recovery_threshold: None,
+            retries: None,
+            circuit_breaker: None,
+            smtp: None,
+            ntp: None,
+            sftp: None,
+            composite: None,
+            full_stack: None,
+            success_statuses: None,
+            max_in_flight: None,
+            alerts_include_details: false,
+            name_from_response: None,
+            expanded_url: None,
+            metrics_from_body: None,
+            allow_sensitive_extraction: false,
+            enabled: None,
+            runtime_added: false,
+            expanded_from: None,
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod metrics_test_utils {
+    use opentelemetry::KeyValue;
+    use opentelemetry_sdk::metrics::data::{Gauge, Histogram, Metric, ResourceMetrics, Sum};
+
+    fn find_metric<'a>(metrics: &'a ResourceMetrics, name: &str) -> Option<&'a Metric> {
+        metrics
+            .scope_metrics
+            .iter()
+            .flat_map(|scope| scope.metrics.iter())
+            .find(|metric| metric.name == name)
+    }
+
+    fn has_attributes(point_attributes: &[KeyValue], attributes: &[KeyValue]) -> bool {
+        attributes
+            .iter()
+            .all(|attribute| point_attributes.contains(attribute))
+    }
+
+    // Value of the counter data point carrying all of the given attributes
+    pub fn counter_value(
+        metrics: &ResourceMetrics,
+        name: &str,
+        attributes: &[KeyValue],
+    ) -> Option<u64> {
+        let sum = find_metric(metrics, name)?
+            .data
+            .as_any()
+            .downcast_ref::<Sum<u64>>()?;
+        sum.data_points
+            .iter()
+            .find(|point| has_attributes(&point.attributes, attributes))
+            .map(|point| point.value)
+    }
+
+    // Value of the gauge data point carrying all of the given attributes
+    pub fn gauge_value(
+        metrics: &ResourceMetrics,
+        name: &str,
+        attributes: &[KeyValue],
+    ) -> Option<u64> {
+        let gauge = find_metric(metrics, name)?
+            .data
+            .as_any()
+            .downcast_ref::<Gauge<u64>>()?;
+        gauge
+            .data_points
+            .iter()
+            .find(|point| has_attributes(&point.attributes, attributes))
+            .map(|point| point.value)
+    }
+
+    // Value of the f64 gauge data point carrying all of the given attributes
+    pub fn f64_gauge_value(
+        metrics: &ResourceMetrics,
+        name: &str,
+        attributes: &[KeyValue],
+    ) -> Option<f64> {
+        let gauge = find_metric(metrics, name)?
+            .data
+            .as_any()
+            .downcast_ref::<Gauge<f64>>()?;
+        gauge
+            .data_points
+            .iter()
+            .find(|point| has_attributes(&point.attributes, attributes))
+            .map(|point| point.value)
+    }
+
+    // Sum of the recordings of the histogram data point carrying all of the given attributes
+    pub fn histogram_sum(
+        metrics: &ResourceMetrics,
+        name: &str,
+        attributes: &[KeyValue],
+    ) -> Option<f64> {
+        let histogram = find_metric(metrics, name)?
+            .data
+            .as_any()
+            .downcast_ref::<Histogram<f64>>()?;
+        histogram
+            .data_points
+            .iter()
+            .find(|point| has_attributes(&point.attributes, attributes))
+            .map(|point| point.sum)
+    }
+
+    pub fn metric_unit(metrics: &ResourceMetrics, name: &str) -> Option<String> {
+        find_metric(metrics, name).map(|metric| metric.unit.to_string())
+    }
+}
+
+// An `AppState` with a fixed config and results, for asserting on whole API responses
+#[cfg(test)]
+pub mod app_state_test_utils {
+    use std::time::Duration;
+
+    use chrono::{DateTime, TimeZone, Utc};
+    use uuid::Uuid;
+
+    use crate::app_state::AppState;
+    use crate::config::Config;
+    use crate::probe::model::{
+        ExpectField, ExpectOperation, FailedExpectation, ProbeResponse, ProbeResult, StoryResult,
+    };
+    use crate::test_utils::result_test_utils;
+
+    fn at(minute: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2026, 1, 14, 10, minute, 0).unwrap()
+    }
+
+    fn probe_result(run: u128, minute: u32, success: bool) -> ProbeResult {
+        let status_code = if success { 200 } else { 503 };
+        ProbeResult {
+            run_id: Uuid::from_u128(run),
+            probe_name: "checkout-api".to_owned(),
+            timestamp_started: at(minute),
+            success,
+            error_message: (!success).then(|| "expected status code 200, got 503".to_owned()),
+            response: Some(ProbeResponse {
+                timestamp_received: at(minute),
+                status_code,
+                body: "{}".to_owned(),
+                sensitive: false,
+            }),
+            duration: Some(Duration::from_micros(85_500)),
+            trace_id: Some(format!("{:032x}", run)),
+            phases: None,
+            failed_phase: None,
+            failed_expectation: (!success).then(|| FailedExpectation {
+                field: ExpectField::StatusCode,
+                operation: ExpectOperation::Equals,
+                expected: "200".to_owned(),
+                actual: "503".to_owned(),
+            }),
+            tls: None,
+            ntp: None,
+            sftp: None,
+            full_stack: None,
+            connection: None,
+            during_reload: false,
+            rate_limited: false,
+            attempts: if success { 1 } else { 2 },
+            retry_reasons: if success {
+                vec![]
+            } else {
+                vec!["expected status code 200, got 503".to_owned()]
+            },
+            samples: None,
+        }
+    }
+
+    fn story_result() -> StoryResult {
+        let mut result = result_test_utils::story_result("signup-flow", "checkout", true);
+        result.story_run_id = Uuid::from_u128(3);
+        result.timestamp_started = at(2);
+        let step = &mut result.step_results[0];
+        step.timestamp_started = at(2);
+        step.response.as_mut().unwrap().timestamp_received = at(2);
+        step.trace_id = Some(format!("{:032x}", 3));
+        step.span_id = Some(format!("{:016x}", 1));
+        result
+    }
+
+    // Probe `checkout-api` passed at 10:00 and failed at 10:05, story `signup-flow` passed at
+    // 10:02. Nothing ran through the monitor states, so there are no incidents either.
+    pub fn seeded_app_state() -> AppState {
+        let config: Config = serde_yaml::from_str(
+            r#"
+probes:
+  - name: checkout-api
+    url: http://localhost/checkout
+    schedule: { initial_delay: 0, interval: 30 }
+    tags: { team: payments }
+stories:
+  - name: signup-flow
+    schedule: { initial_delay: 0, interval: 300 }
+    steps: [{ name: checkout, url: http://localhost/checkout }]
+"#,
+        )
+        .unwrap();
+        let app_state = AppState::new(config);
+        app_state.add_probe_result("checkout-api".to_owned(), probe_result(1, 0, true));
+        app_state.add_probe_result("checkout-api".to_owned(), probe_result(2, 5, false));
+        app_state.add_story_result("signup-flow".to_owned(), story_result());
+        app_state
+    }
+}
+
+// Not only for unit tests, the benches build their results with it too
+pub mod result_test_utils {
+    use std::time::Duration;
+
+    use chrono::{DateTime, Utc};
+    use uuid::Uuid;
+
+    use crate::probe::model::{ProbeResponse, ProbeResult, StepResult, StoryResult};
+
+    // A run started now that took 120ms, with one step answered with `200 {}`
+    pub fn story_result(story_name: &str, step_name: &str, success: bool) -> StoryResult {
+        StoryResult {
+            story_run_id: Uuid::new_v4(),
+            story_name: story_name.to_owned(),
+            timestamp_started: Utc::now(),
+            success,
+            duration: Some(Duration::from_millis(120)),
+            step_results: vec![StepResult {
+                step_name: step_name.to_owned(),
+                timestamp_started: Utc::now(),
+                success,
+                error_message: None,
+                response: Some(ProbeResponse {
+                    timestamp_received: Utc::now(),
+                    status_code: 200,
+                    body: "{}".to_owned(),
+                    sensitive: false,
+                }),
+                trace_id: None,
+                span_id: None,
+                captures: None,
+            }],
+            teardown_results: vec![],
+            expectations: None,
+            during_reload: false,
+        }
+    }
+
+    // A successful run started now, with no response or duration until they are set
+    pub struct ProbeResultBuilder {
+        probe_name: String,
+        timestamp_started: DateTime<Utc>,
+        success: bool,
+        duration: Option<Duration>,
+        response: Option<(u32, String)>,
+        error_message: Option<String>,
+        during_reload: bool,
+    }
+
+    impl ProbeResultBuilder {
+        pub fn new(probe_name: &str) -> ProbeResultBuilder {
+            ProbeResultBuilder {
+                probe_name: probe_name.to_owned(),
+                timestamp_started: Utc::now(),
+                success: true,
+                duration: None,
+                response: None,
+                error_message: None,
+                during_reload: false,
+            }
+        }
+
+        pub fn success(mut self, success: bool) -> ProbeResultBuilder {
+            self.success = success;
+            self
+        }
+
+        pub fn started_at(mut self, timestamp_started: DateTime<Utc>) -> ProbeResultBuilder {
+            self.timestamp_started = timestamp_started;
+            self
+        }
+
+        pub fn duration(mut self, duration: Duration) -> ProbeResultBuilder {
+            self.duration = Some(duration);
+            self
+        }
+
+        // Received once the duration has passed since the start
+        pub fn response(mut self, status_code: u32, body: &str) -> ProbeResultBuilder {
+            self.response = Some((status_code, body.to_owned()));
+            self
+        }
+
+        pub fn error_message(mut self, error_message: &str) -> ProbeResultBuilder {
+            self.error_message = Some(error_message.to_owned());
+            self
+        }
+
+        pub fn during_reload(mut self) -> ProbeResultBuilder {
+            self.during_reload = true;
+            self
+        }
+
+        pub fn build(self) -> ProbeResult {
+            let timestamp_received = self.timestamp_started + self.duration.unwrap_or_default();
+            ProbeResult {
+                error_message: self.error_message,
+                response: self.response.map(|(status_code, body)| ProbeResponse {
+                    timestamp_received,
+                    status_code,
+                    body,
+                    sensitive: false,
+                }),
+                duration: self.duration,
+                during_reload: self.during_reload,
+                ..ProbeResult::new(
+                    Uuid::new_v4(),
+                    self.probe_name,
+                    self.timestamp_started,
+                    self.success,
+                )
+            }
         }
     }
 }
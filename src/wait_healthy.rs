@@ -0,0 +1,302 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::future::join_all;
+use tokio::time::{sleep, sleep_until, Instant};
+use tracing::info;
+
+use crate::app_state::AppState;
+use crate::config::Config;
+use crate::probe::probe_logic::Monitorable;
+
+// Time between attempts at the monitors that haven't passed yet, schedules are ignored
+pub const DEFAULT_RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+// `--wait-healthy` runs every gating monitor until each passed once, without the scheduler or
+// the web servers, to gate a deploy on all synthetic checks being green
+pub struct WaitOptions {
+    pub timeout: Duration,
+    pub retry_interval: Duration,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct WaitReport {
+    pub passed: Vec<String>,
+    pub failing: Vec<FailingMonitor>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FailingMonitor {
+    pub name: String,
+    // Of the latest run, None when the monitor never ran
+    pub error: Option<String>,
+}
+
+impl WaitReport {
+    pub fn healthy(&self) -> bool {
+        self.failing.is_empty()
+    }
+
+    pub fn total(&self) -> usize {
+        self.passed.len() + self.failing.len()
+    }
+}
+
+impl fmt::Display for WaitReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{} monitors passing", self.passed.len(), self.total())?;
+        for monitor in &self.failing {
+            write!(
+                f,
+                "\n  {}: {}",
+                monitor.name,
+                monitor.error.as_deref().unwrap_or("not run yet")
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// `key=value` (tag has the value) or `key` (tag is present), a monitor must match all of them
+fn matches_only(tags: &Option<HashMap<String, String>>, only: &[String]) -> bool {
+    only.iter().all(|filter| {
+        let Some(tags) = tags else {
+            return false;
+        };
+        match filter.split_once('=') {
+            Some((key, value)) => tags.get(key).is_some_and(|tag_value| tag_value == value),
+            None => tags.contains_key(filter.as_str()),
+        }
+    })
+}
+
+// The monitors gating the deploy, without alerts so failed attempts don't page anyone and without
+// response expansion, which would schedule the expanded probes
+pub fn gating_config(mut config: Config, only: &[String]) -> Config {
+    config
+        .probes
+        .retain(|probe| matches_only(&probe.tags, only));
+    config
+        .stories
+        .retain(|story| matches_only(&story.tags, only));
+    for probe in &mut config.probes {
+        probe.alerts = None;
+        probe.name_from_response = None;
+        probe.expanded_url = None;
+    }
+    for story in &mut config.stories {
+        story.alerts = None;
+    }
+    config
+}
+
+// A monitor passed once any of its runs succeeded, later failures don't undo that
+pub fn wait_report(app_state: &AppState) -> WaitReport {
+    let config = app_state.config();
+    let mut report = WaitReport::default();
+
+    let probes = config.probes.iter().map(|probe| {
+        let error = app_state
+            .probe_results
+            .latest(&probe.name)
+            .map(|last| last.error_message.unwrap_or_default());
+        let activity = app_state.probe_results.summary(&probe.name);
+        (&probe.name, error, activity)
+    });
+    let stories = config.stories.iter().map(|story| {
+        let error = app_state.story_results.latest(&story.name).map(|last| {
+            last.step_results
+                .iter()
+                .find(|step| !step.success)
+                .map(|step| {
+                    format!(
+                        "step '{}': {}",
+                        step.step_name,
+                        step.error_message.as_deref().unwrap_or_default()
+                    )
+                })
+                .unwrap_or_else(|| "story expectations failed".to_owned())
+        });
+        let activity = app_state.story_results.summary(&story.name);
+        (&story.name, error, activity)
+    });
+    for (name, error, activity) in probes.chain(stories) {
+        let passed = activity.is_some_and(|activity| activity.last_success_at.is_some());
+        if passed {
+            report.passed.push(name.clone());
+        } else {
+            report.failing.push(FailingMonitor {
+                name: name.clone(),
+                error,
+            });
+        }
+    }
+    report
+}
+
+// Runs the monitors that haven't passed yet every `retry_interval` until all of them passed, the
+// timeout elapsed or `interrupt` completed, and reports where they stand
+pub async fn wait_healthy(
+    app_state: Arc<AppState>,
+    options: &WaitOptions,
+    interrupt: impl Future<Output = ()>,
+) -> WaitReport {
+    let deadline = Instant::now() + options.timeout;
+    let converge = async {
+        loop {
+            let config = app_state.config();
+            let pending = wait_report(&app_state);
+            let is_pending = |name: &String| pending.failing.iter().any(|m| &m.name == name);
+            let probes = config
+                .probes
+                .iter()
+                .filter(|probe| is_pending(&probe.name))
+                .map(|probe| probe.probe_and_store_result(app_state.clone()));
+            let stories = config
+                .stories
+                .iter()
+                .filter(|story| is_pending(&story.name))
+                .map(|story| story.probe_and_store_result(app_state.clone()));
+            futures::join!(join_all(probes), join_all(stories));
+
+            let report = wait_report(&app_state);
+            info!("{}", report);
+            if report.healthy() {
+                return;
+            }
+            sleep(options.retry_interval).await;
+        }
+    };
+
+    tokio::select! {
+        _ = converge => {}
+        _ = sleep_until(deadline) => info!("Timed out waiting for the monitors to pass"),
+        _ = interrupt => info!("Interrupted while waiting for the monitors to pass"),
+    }
+    wait_report(&app_state)
+}
+
+#[cfg(test)]
+mod wait_healthy_tests {
+    use std::collections::HashMap;
+    use std::future;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use super::{gating_config, wait_healthy, WaitOptions};
+    use crate::app_state::AppState;
+    use crate::config::Config;
+    use crate::test_utils::probe_test_utils::probe_get_with_expected_status;
+
+    fn options(timeout: Duration) -> WaitOptions {
+        WaitOptions {
+            timeout,
+            retry_interval: Duration::from_millis(10),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_waits_until_every_monitor_passed_once() {
+        let mock_server = MockServer::start().await;
+        // Fails twice before it comes up
+        Mock::given(method("GET"))
+            .and(path("/starting"))
+            .respond_with(ResponseTemplate::new(503))
+            .up_to_n_times(2)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/starting"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&mock_server)
+            .await;
+        let probe = probe_get_with_expected_status(
+            reqwest::StatusCode::OK,
+            format!("{}/starting", mock_server.uri()),
+            "".to_owned(),
+        );
+        let app_state = Arc::new(AppState::new(Config {
+            probes: vec![probe],
+            ..Default::default()
+        }));
+
+        let report = wait_healthy(
+            app_state.clone(),
+            &options(Duration::from_secs(10)),
+            future::pending(),
+        )
+        .await;
+
+        assert!(report.healthy());
+        assert_eq!(vec!["Test probe".to_owned()], report.passed);
+        assert_eq!(
+            3,
+            app_state.probe_results.recent("Test probe").unwrap().len()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_timeout_reports_failing_monitors() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/down"))
+            .respond_with(ResponseTemplate::new(503))
+            .mount(&mock_server)
+            .await;
+        let probe = probe_get_with_expected_status(
+            reqwest::StatusCode::OK,
+            format!("{}/down", mock_server.uri()),
+            "".to_owned(),
+        );
+        let app_state = Arc::new(AppState::new(Config {
+            probes: vec![probe],
+            ..Default::default()
+        }));
+
+        let report = wait_healthy(
+            app_state,
+            &options(Duration::from_millis(200)),
+            future::pending(),
+        )
+        .await;
+
+        assert!(!report.healthy());
+        assert_eq!("Test probe", report.failing[0].name);
+        assert!(report
+            .to_string()
+            .starts_with("0/1 monitors passing\n  Test probe: "));
+    }
+
+    #[test]
+    fn test_only_keeps_matching_monitors_without_alerts() {
+        let mut smoke = probe_get_with_expected_status(
+            reqwest::StatusCode::OK,
+            "http://localhost/smoke".to_owned(),
+            "".to_owned(),
+        );
+        smoke.name = "smoke".to_owned();
+        smoke.tags = Some(HashMap::from([("suite".to_owned(), "smoke".to_owned())]));
+        smoke.alerts = Some(vec![]);
+        let mut other = smoke.clone();
+        other.name = "other".to_owned();
+        other.tags = Some(HashMap::from([("suite".to_owned(), "nightly".to_owned())]));
+
+        let config = gating_config(
+            Config {
+                probes: vec![smoke, other],
+                ..Default::default()
+            },
+            &["suite=smoke".to_owned()],
+        );
+
+        assert_eq!(1, config.probes.len());
+        assert_eq!("smoke", config.probes[0].name);
+        assert!(config.probes[0].alerts.is_none());
+    }
+}
@@ -0,0 +1,201 @@
+use axum::{extract::Query, http::StatusCode, Extension, Json};
+use chrono::Utc;
+use std::sync::Arc;
+use tracing::debug;
+
+use crate::{
+    alerts::{
+        model::AlertContext,
+        outbound_webhook::{alert_channel, send_alert},
+    },
+    app_state::AppState,
+};
+
+use super::model::{AlertFailure, AlertTestQueryParams, AlertTestResult};
+
+const TEST_ALERT_MESSAGE: &str = "Test alert sent from xbp-monitoring";
+
+// Sends a test alert to every alert configured on the given probe or story, or to a single
+// shared channel from `alert_channels`
+pub async fn test_alerts(
+    Query(params): Query<AlertTestQueryParams>,
+    Extension(state): Extension<Arc<AppState>>,
+) -> Result<Json<Vec<AlertTestResult>>, StatusCode> {
+    debug!("Test alerts called");
+
+    let (name, alerts) = {
+        let config = state.config();
+        match (&params.channel, &params.monitor) {
+            (Some(channel), _) => {
+                let definition = config
+                    .alert_channels
+                    .get(channel)
+                    .ok_or(StatusCode::NOT_FOUND)?;
+                (channel.clone(), vec![definition.clone()])
+            }
+            (None, Some(monitor)) => {
+                let alerts = config
+                    .probes
+                    .iter()
+                    .find(|probe| &probe.name == monitor)
+                    .map(|probe| &probe.alerts)
+                    .or_else(|| {
+                        config
+                            .stories
+                            .iter()
+                            .find(|story| &story.name == monitor)
+                            .map(|story| &story.alerts)
+                    })
+                    .ok_or(StatusCode::NOT_FOUND)?;
+                (
+                    monitor.clone(),
+                    config.resolve_alerts(alerts).unwrap_or_default(),
+                )
+            }
+            (None, None) => return Err(StatusCode::BAD_REQUEST),
+        }
+    };
+
+    let mut results = vec![];
+    for alert in &alerts {
+        let context = AlertContext {
+            monitor_name: name.clone(),
+            failure_timestamp: Utc::now(),
+            error_message: TEST_ALERT_MESSAGE.to_owned(),
+            ..Default::default()
+        };
+        let send_result = send_alert(alert, &context).await;
+
+        results.push(AlertTestResult {
+            channel: alert_channel(alert),
+            success: send_result.is_ok(),
+            error: send_result.err().map(|err| AlertFailure {
+                kind: err.kind().to_owned(),
+                status_code: err.status_code(),
+                message: err.to_string(),
+            }),
+        });
+    }
+
+    Ok(Json(results))
+}
+
+#[cfg(test)]
+mod alerts_tests {
+    use std::collections::BTreeMap;
+    use std::sync::Arc;
+
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use crate::app_state::AppState;
+    use crate::config::Config;
+    use crate::errors::AlertChannel;
+    use crate::probe::model::ProbeAlert;
+    use crate::test_utils::probe_test_utils::probe_get_with_expected_status_and_alert;
+    use crate::web_server::app_router;
+    use crate::web_server::model::AlertTestResult;
+
+    const RELOAD_TOKEN: &str = "test-reload-token";
+
+    // The route is token protected, see `require_reload_token`
+    fn authorized_post(uri: &str) -> Request<Body> {
+        std::env::set_var("XBP_RELOAD_TOKEN", RELOAD_TOKEN);
+        Request::post(uri)
+            .header("Authorization", format!("Bearer {}", RELOAD_TOKEN))
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_alert_test_surfaces_cause() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .and(path("/alert"))
+            .respond_with(ResponseTemplate::new(410).set_body_string("webhook deleted"))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let probe = probe_get_with_expected_status_and_alert(
+            reqwest::StatusCode::OK,
+            format!("{}/probe", mock_server.uri()),
+            "".to_owned(),
+            format!("{}/alert", mock_server.uri()),
+        );
+        let app_state = Arc::new(AppState::new(Config {
+            probes: vec![probe],
+            stories: vec![],
+            ..Default::default()
+        }));
+
+        let response = app_router(app_state)
+            .oneshot(authorized_post("/-/alerts/test?monitor=Test%20probe"))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::OK, response.status());
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let results: Vec<AlertTestResult> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(1, results.len());
+        assert_eq!(AlertChannel::Webhook, results[0].channel);
+        assert!(!results[0].success);
+        let error = results[0].error.as_ref().unwrap();
+        assert_eq!("http_status", error.kind);
+        assert_eq!(Some(410), error.status_code);
+    }
+
+    #[tokio::test]
+    async fn test_alert_test_shared_channel() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .and(path("/oncall"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let app_state = Arc::new(AppState::new(Config {
+            alert_channels: BTreeMap::from([(
+                "oncall".to_owned(),
+                ProbeAlert {
+                    url: format!("{}/oncall", mock_server.uri()),
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        }));
+
+        let response = app_router(app_state)
+            .oneshot(authorized_post("/-/alerts/test?channel=oncall"))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::OK, response.status());
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let results: Vec<AlertTestResult> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(1, results.len());
+        assert!(results[0].success);
+    }
+
+    #[tokio::test]
+    async fn test_alert_test_unknown_monitor() {
+        let app_state = Arc::new(AppState::new(Config {
+            probes: vec![],
+            stories: vec![],
+            ..Default::default()
+        }));
+
+        let response = app_router(app_state)
+            .oneshot(authorized_post("/-/alerts/test?monitor=missing"))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, response.status());
+    }
+}
@@ -0,0 +1,289 @@
+use axum::{
+    extract::Query,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Extension,
+};
+use chrono::Utc;
+use prometheus::{Encoder, Gauge, GaugeVec, Opts, Registry, TextEncoder};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::debug;
+
+use crate::{
+    app_state::AppState,
+    probe::{
+        expectations::validate_response,
+        http_probe::call_endpoint,
+        model::{EndpointResult, ProbeModule, ProbeModules, ProbeOptions},
+    },
+};
+
+use super::model::BlackboxProbeQueryParams;
+
+// Runs a single probe against `target` with the settings of a module and returns the outcome in
+// the format of blackbox_exporter, so existing Prometheus scrape configs keep working.
+// Nothing is stored and no alerts are sent.
+pub async fn blackbox_probe(
+    Query(params): Query<BlackboxProbeQueryParams>,
+    Extension(state): Extension<Arc<AppState>>,
+) -> Response {
+    debug!("Blackbox probe called");
+
+    let module = {
+        let probe_modules = &state.config.read().unwrap().settings.probe_modules;
+        let module = match probe_modules.modules.get(&params.module) {
+            Some(module) => module.clone(),
+            None => {
+                return bad_request(format!("Unknown module {:?}", params.module));
+            }
+        };
+        if !target_allowed(probe_modules, &params.target) {
+            return bad_request(format!("Target {:?} is not allowed", params.target));
+        }
+        module
+    };
+
+    match run_module(&module, &params.target).await {
+        Ok(buffer) => (
+            StatusCode::OK,
+            [("content-type", TextEncoder::new().format_type())],
+            buffer,
+        )
+            .into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to encode metrics: {}", e),
+        )
+            .into_response(),
+    }
+}
+
+fn bad_request(message: String) -> Response {
+    (StatusCode::BAD_REQUEST, message).into_response()
+}
+
+// Patterns match the whole target, `https://status\.example\.com/.*` doesn't allow other hosts
+fn target_allowed(probe_modules: &ProbeModules, target: &str) -> bool {
+    probe_modules.allowed_target_patterns.iter().any(|pattern| {
+        regex::Regex::new(&format!("^(?:{})$", pattern)).is_ok_and(|regex| regex.is_match(target))
+    })
+}
+
+async fn run_module(module: &ProbeModule, target: &str) -> Result<Vec<u8>, prometheus::Error> {
+    let target = target.to_owned();
+    let input_parameters = Some(ProbeOptions {
+        headers: module.headers.clone(),
+        timeout: module.timeout_seconds.map(Duration::from_secs),
+        // Only the target passed the allow-list, a redirect could point anywhere
+        follow_redirects: false,
+        ..Default::default()
+    });
+
+    let started = Utc::now();
+    let result = call_endpoint(
+        &module.http_method,
+        &target.to_owned(),
+        &input_parameters,
+        false,
+        None,
+    )
+    .await
+    .ok();
+    let duration = Utc::now() - started;
+
+    let success = result.as_ref().is_some_and(|result| {
+        validate_response(
+            &target,
+            result.status_code,
+            result.body.clone(),
+            &result.meta(),
+            &module.expectations,
+            module.success_statuses.as_deref(),
+        )
+        .is_ok()
+    });
+
+    let registry = Registry::new();
+    let gauge = |name: &str, help: &str, value: f64| -> Result<(), prometheus::Error> {
+        let gauge = Gauge::new(name, help)?;
+        gauge.set(value);
+        registry.register(Box::new(gauge))
+    };
+    gauge(
+        "probe_success",
+        "Displays whether or not the probe was a success",
+        if success { 1.0 } else { 0.0 },
+    )?;
+    gauge(
+        "probe_duration_seconds",
+        "Returns how long the probe took to complete in seconds",
+        seconds(duration),
+    )?;
+    gauge(
+        "probe_http_status_code",
+        "Response HTTP status code",
+        result
+            .as_ref()
+            .map_or(0.0, |result| result.status_code as f64),
+    )?;
+    if let Some(result) = &result {
+        let phases = GaugeVec::new(
+            Opts::new(
+                "probe_http_duration_seconds",
+                "Duration of http request by phase",
+            ),
+            &["phase"],
+        )?;
+        for (phase, duration) in http_phases(result) {
+            phases.with_label_values(&[phase]).set(seconds(duration));
+        }
+        registry.register(Box::new(phases))?;
+    }
+
+    let mut buffer = vec![];
+    TextEncoder::new().encode(&registry.gather(), &mut buffer)?;
+    Ok(buffer)
+}
+
+// Waiting for the response headers, then reading the body
+fn http_phases(result: &EndpointResult) -> [(&'static str, chrono::Duration); 2] {
+    [
+        (
+            "processing",
+            result.timestamp_response_received - result.timestamp_request_started,
+        ),
+        (
+            "transfer",
+            result.timestamp_body_received - result.timestamp_response_received,
+        ),
+    ]
+}
+
+fn seconds(duration: chrono::Duration) -> f64 {
+    duration.num_microseconds().unwrap_or(i64::MAX) as f64 / 1_000_000.0
+}
+
+#[cfg(test)]
+mod blackbox_tests {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use crate::app_state::AppState;
+    use crate::config::{Config, Settings};
+    use crate::probe::model::{ProbeModule, ProbeModules, StatusPattern};
+    use crate::web_server::app_router;
+
+    fn app_state(allowed_target_pattern: &str) -> Arc<AppState> {
+        Arc::new(AppState::new(Config {
+            settings: Settings {
+                probe_modules: ProbeModules {
+                    allowed_target_patterns: vec![allowed_target_pattern.to_owned()],
+                    modules: HashMap::from([(
+                        "http_2xx".to_owned(),
+                        ProbeModule {
+                            http_method: "GET".to_owned(),
+                            headers: None,
+                            timeout_seconds: Some(5),
+                            expectations: None,
+                            success_statuses: Some(vec![StatusPattern::Class(2)]),
+                        },
+                    )]),
+                },
+                ..Default::default()
+            },
+            ..Default::default()
+        }))
+    }
+
+    async fn get(app_state: Arc<AppState>, uri: String) -> (StatusCode, String) {
+        let response = app_router(app_state)
+            .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_probe_returns_blackbox_metrics() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/health"))
+            .respond_with(ResponseTemplate::new(503))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        let app_state = app_state("http://127\\.0\\.0\\.1:\\d+/health");
+
+        let (status, body) = get(
+            app_state.clone(),
+            format!("/probe?module=http_2xx&target={}/health", mock_server.uri()),
+        )
+        .await;
+
+        assert_eq!(StatusCode::OK, status);
+        assert!(body.contains("probe_success 0\n"), "{}", body);
+        assert!(body.contains("probe_http_status_code 503\n"), "{}", body);
+        assert!(body.contains("probe_duration_seconds "), "{}", body);
+        assert!(body.contains("probe_http_duration_seconds{phase=\"transfer\"}"));
+        assert!(app_state.probe_results.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_probe_does_not_follow_redirects_past_the_allow_list() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/health"))
+            .respond_with(ResponseTemplate::new(302).insert_header("Location", "/internal"))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/internal"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0)
+            .mount(&mock_server)
+            .await;
+        let app_state = app_state("http://127\\.0\\.0\\.1:\\d+/health");
+
+        let (status, body) = get(
+            app_state,
+            format!("/probe?module=http_2xx&target={}/health", mock_server.uri()),
+        )
+        .await;
+
+        assert_eq!(StatusCode::OK, status);
+        assert!(body.contains("probe_success 0\n"), "{}", body);
+        assert!(body.contains("probe_http_status_code 302\n"), "{}", body);
+    }
+
+    #[tokio::test]
+    async fn test_probe_rejects_disallowed_targets_and_unknown_modules() {
+        let app_state = app_state("https://status\\.example\\.com/.*");
+
+        let (status, body) = get(
+            app_state.clone(),
+            "/probe?module=http_2xx&target=http://169.254.169.254/latest".to_owned(),
+        )
+        .await;
+        assert_eq!(StatusCode::BAD_REQUEST, status);
+        assert!(body.contains("is not allowed"));
+
+        let (status, body) = get(
+            app_state,
+            "/probe?module=tcp&target=https://status.example.com/".to_owned(),
+        )
+        .await;
+        assert_eq!(StatusCode::BAD_REQUEST, status);
+        assert!(body.contains("Unknown module"));
+    }
+}
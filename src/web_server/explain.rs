@@ -0,0 +1,176 @@
+use axum::{
+    extract::{Path, Query},
+    http::StatusCode,
+    Extension, Json,
+};
+use std::sync::Arc;
+use tracing::debug;
+
+use crate::app_state::AppState;
+use crate::probe::model::ProbeResult;
+
+use super::model::{ExplainQueryParams, ExplainResponse};
+
+// The latest run of a probe, or the one `?run=` names, with what its failure means
+pub async fn explain_probe(
+    Path(name): Path<String>,
+    Query(params): Query<ExplainQueryParams>,
+    Extension(state): Extension<Arc<AppState>>,
+) -> Result<Json<ExplainResponse>, StatusCode> {
+    debug!("Explain probe called");
+
+    let result = state
+        .probe_results
+        .read(&name, |results| match params.run {
+            Some(run_id) => results
+                .iter()
+                .find(|result| result.run_id == run_id)
+                .cloned(),
+            None => results.back().cloned(),
+        })
+        .flatten()
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let monitor_state = state
+        .monitor_states
+        .read()
+        .unwrap()
+        .get(&name)
+        .cloned()
+        .unwrap_or_default();
+
+    let ignored = state.ignores_result(result.during_reload)
+        || (result.rate_limited && !state.config().settings.rate_limits.count_as_failure);
+
+    Ok(Json(explain(
+        result,
+        ignored,
+        monitor_state.consecutive_failures,
+        monitor_state.open_incident,
+    )))
+}
+
+fn explain(
+    result: ProbeResult,
+    ignored: bool,
+    consecutive_failures: u32,
+    open_incident: Option<uuid::Uuid>,
+) -> ExplainResponse {
+    let error_kind = result.error_kind();
+    let phases = result.phases.unwrap_or_default();
+    let slowest_phase = phases
+        .iter()
+        .max_by_key(|phase| phase.duration_ms)
+        .map(|phase| phase.name.clone());
+    ExplainResponse {
+        probe_name: result.probe_name,
+        run_id: result.run_id,
+        timestamp_started: result.timestamp_started,
+        success: result.success,
+        error_kind,
+        explanation: explanation(error_kind),
+        error_message: result.error_message,
+        failed_expectations: result.failed_expectation.into_iter().collect(),
+        phases,
+        slowest_phase,
+        failed_phase: result.failed_phase,
+        during_reload: result.during_reload,
+        ignored,
+        consecutive_failures,
+        open_incident,
+    }
+}
+
+// One sentence per kind of `ProbeResult::error_kind`
+fn explanation(error_kind: Option<&str>) -> &'static str {
+    match error_kind {
+        None => "The run passed.",
+        Some("request") => {
+            "No response arrived: the DNS lookup, connection, TLS handshake or request timed out or failed."
+        }
+        Some("expectation") => "A response arrived but didn't meet an expectation.",
+        Some("rate_limited") => {
+            "The endpoint rate limited the probe, its next runs wait as long as it asked for."
+        }
+        Some("timeout") => "The NTP server didn't reply within the timeout.",
+        Some("kiss_of_death") => {
+            "The NTP server refused the request or asked to be queried less often."
+        }
+        Some("unsynchronized") => "The NTP server isn't synchronized to a reference clock.",
+        Some("malformed") => "The NTP server's reply couldn't be parsed.",
+        Some("io") => "The NTP request couldn't be sent or its reply couldn't be received.",
+        Some("network") => "The SFTP server couldn't be reached or the connection broke.",
+        Some("host_key") => {
+            "The SFTP server's host key doesn't match the configured fingerprint or known_hosts entry."
+        }
+        Some("auth") => "The SFTP server rejected the credentials.",
+        Some("path") => "The SFTP `list_path` doesn't exist or can't be listed.",
+        Some("unsupported") => "This build of xbp-monitoring lacks the feature the probe needs.",
+        Some(_) => "The run failed.",
+    }
+}
+
+#[cfg(test)]
+mod explain_tests {
+    use std::sync::Arc;
+
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+    use http_body_util::BodyExt;
+    use reqwest::StatusCode as ReqwestStatusCode;
+    use tower::ServiceExt;
+    use wiremock::matchers::method;
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use crate::app_state::AppState;
+    use crate::config::Config;
+    use crate::probe::probe_logic::Monitorable;
+    use crate::test_utils::probe_test_utils::probe_get_with_expected_status;
+    use crate::web_server::app_router;
+
+    #[tokio::test]
+    async fn test_explain_failed_expectation() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(503).set_body_string("maintenance"))
+            .mount(&mock_server)
+            .await;
+        let probe =
+            probe_get_with_expected_status(ReqwestStatusCode::OK, mock_server.uri(), "".to_owned());
+        let app_state = Arc::new(AppState::new(Config {
+            probes: vec![probe.clone()],
+            ..Default::default()
+        }));
+        probe.probe_and_store_result(app_state.clone()).await;
+        probe.probe_and_store_result(app_state.clone()).await;
+        let first_run = app_state.probe_results.recent(&probe.name).unwrap()[0].run_id;
+
+        let response = app_router(app_state.clone())
+            .oneshot(
+                Request::get(format!("/probes/Test%20probe/explain?run={}", first_run))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(StatusCode::OK, response.status());
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let explained: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(first_run.to_string(), explained["run_id"]);
+        assert_eq!("expectation", explained["error_kind"]);
+        assert_eq!("200", explained["failed_expectations"][0]["expected"]);
+        assert_eq!("503", explained["failed_expectations"][0]["actual"]);
+        assert_eq!(2, explained["consecutive_failures"]);
+        assert_eq!(false, explained["ignored"]);
+
+        let response = app_router(app_state)
+            .oneshot(
+                Request::get("/probes/unknown/explain")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, response.status());
+    }
+}
@@ -0,0 +1,563 @@
+use axum::{
+    body::Body,
+    extract::{Path, Query},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Extension,
+};
+use chrono::{DateTime, Utc};
+use futures::StreamExt;
+use serde::Serialize;
+use std::{collections::HashMap, convert::Infallible, sync::Arc};
+use tracing::debug;
+
+use crate::{
+    app_state::AppState,
+    probe::duration::as_millis_f64,
+    probe::model::{error_kind, ProbeResult, StoryResult},
+};
+
+use super::model::{
+    matches_group, rfc3339_millis, ExportQueryParams, ProbeExportQueryParams, ProbeQueryParams,
+};
+
+const CSV_HEADER: &str = "timestamp,success,duration_ms,status_code,error_kind,error";
+
+// A single exported run, also an NDJSON line. Bodies are never part of an export.
+#[derive(Serialize)]
+pub struct HistoryRow {
+    pub monitor: String,
+    #[serde(serialize_with = "rfc3339_millis::serialize")]
+    pub timestamp: DateTime<Utc>,
+    pub success: bool,
+    // Fractional milliseconds, sub-millisecond runs keep their precision
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status_code: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_kind: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl HistoryRow {
+    pub fn from_probe_result(monitor: &str, result: &ProbeResult) -> HistoryRow {
+        HistoryRow {
+            monitor: monitor.to_owned(),
+            timestamp: result.timestamp_started,
+            success: result.success,
+            duration_ms: result.duration.map(as_millis_f64),
+            status_code: result
+                .response
+                .as_ref()
+                .map(|response| response.status_code),
+            error_kind: result.error_kind(),
+            error: result.error_message.clone(),
+        }
+    }
+
+    pub fn from_story_result(monitor: &str, result: &StoryResult) -> HistoryRow {
+        let last_step = result.step_results.last();
+        let last_response = last_step.and_then(|step| step.response.as_ref());
+        HistoryRow {
+            monitor: monitor.to_owned(),
+            timestamp: result.timestamp_started,
+            success: result.success,
+            duration_ms: result.duration.map(as_millis_f64),
+            status_code: last_response.map(|response| response.status_code),
+            error_kind: error_kind(result.success, last_response.is_some()),
+            error: last_step.and_then(|step| step.error_message.clone()),
+        }
+    }
+
+    pub fn to_csv_line(&self, include_monitor: bool) -> String {
+        let mut fields = vec![];
+        if include_monitor {
+            fields.push(csv_field(&self.monitor));
+        }
+        fields.push(rfc3339_millis::format(&self.timestamp));
+        fields.push(self.success.to_string());
+        fields.push(self.duration_ms.map_or(String::new(), |ms| ms.to_string()));
+        fields.push(
+            self.status_code
+                .map_or(String::new(), |code| code.to_string()),
+        );
+        fields.push(self.error_kind.unwrap_or_default().to_owned());
+        fields.push(csv_field(self.error.as_deref().unwrap_or_default()));
+        format!("{}\n", fields.join(","))
+    }
+}
+
+// Quotes a field when it contains a separator, quote or line break, doubling embedded quotes
+pub fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_owned()
+    }
+}
+
+// Builds a filename such as `checkout_20240101-20240131.csv` from the monitor name and the exported date range
+fn export_filename(prefix: &str, rows: &[HistoryRow]) -> String {
+    let prefix: String = prefix
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .collect();
+    let from = rows.iter().map(|row| row.timestamp).min();
+    let to = rows.iter().map(|row| row.timestamp).max();
+    match (from, to) {
+        (Some(from), Some(to)) => format!(
+            "{}_{}-{}.csv",
+            prefix,
+            from.format("%Y%m%d"),
+            to.format("%Y%m%d")
+        ),
+        _ => format!("{}.csv", prefix),
+    }
+}
+
+// Streams the rows instead of building the whole document up front
+fn csv_response(rows: Vec<HistoryRow>, filename: String, include_monitor: bool) -> Response {
+    let header_line = if include_monitor {
+        format!("monitor,{}\n", CSV_HEADER)
+    } else {
+        format!("{}\n", CSV_HEADER)
+    };
+    let lines = std::iter::once(header_line)
+        .chain(
+            rows.into_iter()
+                .map(move |row| row.to_csv_line(include_monitor)),
+        )
+        .map(Ok::<_, Infallible>);
+
+    (
+        [
+            (header::CONTENT_TYPE, "text/csv; charset=utf-8".to_owned()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}\"", filename),
+            ),
+        ],
+        Body::from_stream(futures::stream::iter(lines)),
+    )
+        .into_response()
+}
+
+pub fn probe_history_csv_response(name: &str, state: &AppState) -> Response {
+    // Snapshot the rows so the lock isn't held while the client reads the stream
+    let Some(rows): Option<Vec<HistoryRow>> = state.probe_results.read(name, |results| {
+        results
+            .iter()
+            .map(|result| HistoryRow::from_probe_result(name, result))
+            .collect()
+    }) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    let filename = export_filename(name, &rows);
+    csv_response(rows, filename, false)
+}
+
+// One JSON line per run, serialized as the client reads the stream
+fn ndjson_response(rows: Vec<HistoryRow>) -> Response {
+    let lines = rows.into_iter().map(|row| {
+        let mut line = serde_json::to_string(&row)?;
+        line.push('\n');
+        Ok::<_, serde_json::Error>(line)
+    });
+
+    (
+        [(header::CONTENT_TYPE, "application/x-ndjson")],
+        Body::from_stream(futures::stream::iter(lines)),
+    )
+        .into_response()
+}
+
+pub fn probe_history_ndjson_response(
+    name: &str,
+    params: &ProbeQueryParams,
+    state: &AppState,
+) -> Response {
+    // Snapshot the rows so the lock isn't held while the client reads the stream
+    let Some(rows): Option<Vec<HistoryRow>> = state.probe_results.read(name, |results| {
+        params
+            .select(results, |result| result.timestamp_started)
+            .into_iter()
+            .map(|result| HistoryRow::from_probe_result(name, result))
+            .collect()
+    }) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    ndjson_response(rows)
+}
+
+pub async fn probe_history_ndjson(
+    Path(name): Path<String>,
+    Query(params): Query<ProbeQueryParams>,
+    Extension(state): Extension<Arc<AppState>>,
+) -> Response {
+    debug!("Get probe history ndjson called");
+    probe_history_ndjson_response(&name, &params, &state)
+}
+
+pub async fn story_history_ndjson(
+    Path(name): Path<String>,
+    Query(params): Query<ProbeQueryParams>,
+    Extension(state): Extension<Arc<AppState>>,
+) -> Response {
+    debug!("Get story history ndjson called");
+
+    let Some(rows): Option<Vec<HistoryRow>> = state.story_results.read(&name, |results| {
+        params
+            .select(results, |result| result.timestamp_started)
+            .into_iter()
+            .map(|result| HistoryRow::from_story_result(&name, result))
+            .collect()
+    }) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    ndjson_response(rows)
+}
+
+pub async fn probe_history_csv(
+    Path(name): Path<String>,
+    Extension(state): Extension<Arc<AppState>>,
+) -> Response {
+    debug!("Get probe history csv called");
+    probe_history_csv_response(&name, &state)
+}
+
+// Matches `tag=key` (tag is present) or `tag=key:value` (tag has the given value)
+fn matches_tag(tags: &Option<HashMap<String, String>>, filter: &Option<String>) -> bool {
+    let Some(filter) = filter else {
+        return true;
+    };
+    let Some(tags) = tags else {
+        return false;
+    };
+    match filter.split_once(':') {
+        Some((key, value)) => tags.get(key).is_some_and(|tag_value| tag_value == value),
+        None => tags.contains_key(filter.as_str()),
+    }
+}
+
+pub async fn export_history_csv(
+    Query(params): Query<ExportQueryParams>,
+    Extension(state): Extension<Arc<AppState>>,
+) -> Response {
+    debug!("Export history csv called");
+
+    let config = state.config();
+    let mut rows: Vec<HistoryRow> = vec![];
+    for probe in &config.probes {
+        if !matches_tag(&probe.tags, &params.tag) || !matches_group(&probe.group, &params.group) {
+            continue;
+        }
+        state.probe_results.read(&probe.name, |results| {
+            rows.extend(
+                results
+                    .iter()
+                    .map(|result| HistoryRow::from_probe_result(&probe.name, result)),
+            )
+        });
+    }
+    for story in &config.stories {
+        if !matches_tag(&story.tags, &params.tag) || !matches_group(&story.group, &params.group) {
+            continue;
+        }
+        state.story_results.read(&story.name, |results| {
+            rows.extend(
+                results
+                    .iter()
+                    .map(|result| HistoryRow::from_story_result(&story.name, result)),
+            )
+        });
+    }
+
+    let filename = export_filename("history", &rows);
+    csv_response(rows, filename, true)
+}
+
+// Every stored run of the probes as one `ProbeResult` per line, oldest first within each probe. Each
+// probe's runs are only snapshotted once the stream gets to it, so memory stays at one probe's
+// history however many probes there are.
+pub async fn export_probes_ndjson(
+    Query(params): Query<ProbeExportQueryParams>,
+    Extension(state): Extension<Arc<AppState>>,
+) -> Response {
+    debug!("Export probes ndjson called");
+
+    let names: Vec<String> = match params.probe {
+        Some(ref name) if state.probe_results.read(name, |_| ()).is_none() => {
+            return StatusCode::NOT_FOUND.into_response();
+        }
+        Some(ref name) => vec![name.clone()],
+        None => state
+            .config()
+            .probes
+            .iter()
+            .filter(|probe| matches_group(&probe.group, &params.group))
+            .map(|probe| probe.name.clone())
+            .collect(),
+    };
+    let show_response = params.show_response.unwrap_or(false);
+    let lines = futures::stream::iter(names).flat_map(move |name| {
+        let results: Vec<ProbeResult> = state
+            .probe_results
+            .read(&name, |results| {
+                results
+                    .iter()
+                    .filter(|result| {
+                        params
+                            .from
+                            .is_none_or(|from| result.timestamp_started >= from)
+                            && params.to.is_none_or(|to| result.timestamp_started <= to)
+                    })
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default();
+        futures::stream::iter(results.into_iter().map(move |mut result| {
+            if !show_response {
+                result.response = None;
+            }
+            let mut line = serde_json::to_string(&result)?;
+            line.push('\n');
+            Ok::<_, serde_json::Error>(line)
+        }))
+    });
+
+    (
+        [(header::CONTENT_TYPE, "application/x-ndjson")],
+        Body::from_stream(lines),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod export_tests {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use axum::body::Body;
+    use axum::http::{header, Request, StatusCode};
+    use chrono::{Duration, TimeZone, Utc};
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+
+    use crate::app_state::AppState;
+    use crate::config::Config;
+    use crate::probe::model::ProbeResult;
+    use crate::test_utils::probe_test_utils::probe_get_with_expected_status;
+    use crate::test_utils::result_test_utils::ProbeResultBuilder;
+    use crate::web_server::app_router;
+    use crate::web_server::export::csv_field;
+
+    fn app_state_with_results() -> Arc<AppState> {
+        let mut probe = probe_get_with_expected_status(
+            reqwest::StatusCode::OK,
+            "http://localhost/health".to_owned(),
+            "".to_owned(),
+        );
+        probe.name = "checkout".to_owned();
+        probe.tags = Some(HashMap::from([("team".to_owned(), "payments".to_owned())]));
+        let app_state = Arc::new(AppState::new(Config {
+            probes: vec![probe],
+            stories: vec![],
+            ..Default::default()
+        }));
+
+        let started = Utc.with_ymd_and_hms(2024, 1, 15, 10, 30, 0).unwrap();
+        app_state.add_probe_result(
+            "checkout".to_owned(),
+            ProbeResultBuilder::new("checkout")
+                .started_at(started)
+                .duration(std::time::Duration::from_millis(120))
+                .response(200, "secret body")
+                .build(),
+        );
+        app_state.add_probe_result(
+            "checkout".to_owned(),
+            ProbeResultBuilder::new("checkout")
+                .success(false)
+                .started_at(started + Duration::days(1))
+                .error_message("connection refused, \"retrying\"")
+                .build(),
+        );
+        app_state
+    }
+
+    async fn get(app_state: Arc<AppState>, uri: &str) -> (StatusCode, Option<String>, String) {
+        let response = app_router(app_state)
+            .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let disposition = response
+            .headers()
+            .get(header::CONTENT_DISPOSITION)
+            .map(|value| value.to_str().unwrap().to_owned());
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (
+            status,
+            disposition,
+            String::from_utf8(body.to_vec()).unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_csv_field_escaping() {
+        assert_eq!("plain", csv_field("plain"));
+        assert_eq!("\"a,b\"", csv_field("a,b"));
+        assert_eq!("\"say \"\"hi\"\"\"", csv_field("say \"hi\""));
+        assert_eq!("\"line\nbreak\"", csv_field("line\nbreak"));
+    }
+
+    #[tokio::test]
+    async fn test_probe_history_csv() {
+        let (status, disposition, body) =
+            get(app_state_with_results(), "/probes/checkout/history.csv").await;
+
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!(
+            "attachment; filename=\"checkout_20240115-20240116.csv\"",
+            disposition.unwrap()
+        );
+        let lines: Vec<&str> = body.lines().collect();
+        assert_eq!(
+            vec![
+                "timestamp,success,duration_ms,status_code,error_kind,error",
+                "2024-01-15T10:30:00.000Z,true,120,200,,",
+                "2024-01-16T10:30:00.000Z,false,,,request,\"connection refused, \"\"retrying\"\"\"",
+            ],
+            lines
+        );
+        assert!(!body.contains("secret body"));
+    }
+
+    #[tokio::test]
+    async fn test_results_format_csv_matches_history_csv() {
+        let app_state = app_state_with_results();
+        let (_, _, history) = get(app_state.clone(), "/probes/checkout/history.csv").await;
+        let (status, _, results) = get(app_state, "/probes/checkout/results?format=csv").await;
+
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!(history, results);
+    }
+
+    #[tokio::test]
+    async fn test_probe_history_csv_unknown_probe() {
+        let (status, _, _) = get(app_state_with_results(), "/probes/unknown/history.csv").await;
+        assert_eq!(StatusCode::NOT_FOUND, status);
+    }
+
+    #[tokio::test]
+    async fn test_probe_history_ndjson_streams_one_run_per_line() {
+        let response = app_router(app_state_with_results())
+            .oneshot(
+                Request::get("/probes/checkout/history.ndjson?order=oldest")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::OK, response.status());
+        assert_eq!(
+            "application/x-ndjson",
+            response.headers()[header::CONTENT_TYPE]
+        );
+
+        // Read frame by frame, each one a complete line
+        let mut body = response.into_body();
+        let mut lines = vec![];
+        while let Some(frame) = body.frame().await {
+            let data = frame.unwrap().into_data().unwrap();
+            let line = String::from_utf8(data.to_vec()).unwrap();
+            assert!(line.ends_with('\n'), "{:?}", line);
+            lines.push(serde_json::from_str::<serde_json::Value>(&line).unwrap());
+        }
+        assert_eq!(2, lines.len());
+        assert_eq!("2024-01-15T10:30:00.000Z", lines[0]["timestamp"]);
+        assert_eq!(120.0, lines[0]["duration_ms"]);
+        assert_eq!(200, lines[0]["status_code"]);
+        assert_eq!(false, lines[1]["success"]);
+        assert_eq!("request", lines[1]["error_kind"]);
+        assert!(lines[1].get("duration_ms").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_probe_history_ndjson_filters() {
+        let app_state = app_state_with_results();
+
+        let (status, _, newest) =
+            get(app_state.clone(), "/probes/checkout/history.ndjson?limit=1").await;
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!(1, newest.lines().count());
+        assert!(newest.contains("2024-01-16T10:30:00.000Z"));
+
+        let (_, _, since) = get(
+            app_state.clone(),
+            "/probes/checkout/results?format=ndjson&since=2024-01-16T00:00:00Z",
+        )
+        .await;
+        assert_eq!(newest, since);
+
+        let (status, _, _) = get(app_state, "/probes/unknown/history.ndjson").await;
+        assert_eq!(StatusCode::NOT_FOUND, status);
+    }
+
+    #[tokio::test]
+    async fn test_export_probes_ndjson() {
+        let app_state = app_state_with_results();
+
+        let (status, _, body) = get(app_state.clone(), "/-/export/probes.ndjson").await;
+        assert_eq!(StatusCode::OK, status);
+        let runs: Vec<ProbeResult> = body
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(2, runs.len());
+        assert!(runs[0].success);
+        assert!(runs[0].response.is_none());
+        assert_eq!(
+            Some("connection refused, \"retrying\""),
+            runs[1].error_message.as_deref()
+        );
+
+        let (_, _, filtered) = get(
+            app_state.clone(),
+            "/-/export/probes.ndjson?probe=checkout&from=2024-01-16T00:00:00Z&show_response=true",
+        )
+        .await;
+        assert_eq!(1, filtered.lines().count());
+        assert!(filtered.contains("2024-01-16T10:30:00Z"));
+
+        let (_, _, to) = get(
+            app_state.clone(),
+            "/-/export/probes.ndjson?to=2024-01-15T23:59:59Z&show_response=true",
+        )
+        .await;
+        assert!(to.contains("secret body"));
+        assert_eq!(1, to.lines().count());
+
+        let (status, _, _) = get(app_state, "/-/export/probes.ndjson?probe=unknown").await;
+        assert_eq!(StatusCode::NOT_FOUND, status);
+    }
+
+    #[tokio::test]
+    async fn test_bulk_export_filters_by_tag() {
+        let app_state = app_state_with_results();
+
+        let (status, _, body) =
+            get(app_state.clone(), "/export/history.csv?tag=team:payments").await;
+        assert_eq!(StatusCode::OK, status);
+        let lines: Vec<&str> = body.lines().collect();
+        assert_eq!(3, lines.len());
+        assert!(lines[0].starts_with("monitor,timestamp"));
+        assert!(lines[1].starts_with("checkout,"));
+
+        let (_, _, body) = get(app_state, "/export/history.csv?tag=team:search").await;
+        assert_eq!(1, body.lines().count());
+    }
+}
@@ -0,0 +1,232 @@
+use axum::{
+    extract::{Path, Query},
+    http::StatusCode,
+    Extension, Json,
+};
+use std::sync::Arc;
+use tracing::debug;
+use uuid::Uuid;
+
+use crate::{app_state::AppState, incidents::model::Incident};
+
+use super::model::{IncidentAckQueryParams, IncidentQueryParams};
+
+// Incidents of all monitors, newest first. `since` keeps the incidents that were open at or after it.
+pub async fn incidents(
+    Query(params): Query<IncidentQueryParams>,
+    Extension(state): Extension<Arc<AppState>>,
+) -> Json<Vec<Incident>> {
+    debug!("Get incidents called");
+
+    let config = state.config();
+    let mut incidents: Vec<Incident> = state
+        .incidents
+        .read()
+        .unwrap()
+        .values()
+        .flatten()
+        .filter(|incident| params.state.is_none_or(|wanted| incident.state() == wanted))
+        .filter(|incident| config.in_group(&incident.monitor, params.group.as_deref()))
+        .filter(|incident| {
+            params
+                .since
+                .is_none_or(|since| incident.ended.is_none_or(|ended| ended >= since))
+        })
+        .cloned()
+        .collect();
+    incidents.sort_by_key(|incident| std::cmp::Reverse(incident.started));
+
+    Json(incidents)
+}
+
+pub async fn probe_incidents(
+    Path(name): Path<String>,
+    Extension(state): Extension<Arc<AppState>>,
+) -> Result<Json<Vec<Incident>>, StatusCode> {
+    debug!("Get probe incidents called");
+
+    let known = state
+        .config
+        .read()
+        .unwrap()
+        .probes
+        .iter()
+        .any(|probe| probe.name == name);
+    monitor_incidents(&state, &name, known)
+}
+
+pub async fn story_incidents(
+    Path(name): Path<String>,
+    Extension(state): Extension<Arc<AppState>>,
+) -> Result<Json<Vec<Incident>>, StatusCode> {
+    debug!("Get story incidents called");
+
+    let known = state
+        .config
+        .read()
+        .unwrap()
+        .stories
+        .iter()
+        .any(|story| story.name == name);
+    monitor_incidents(&state, &name, known)
+}
+
+fn monitor_incidents(
+    state: &AppState,
+    name: &str,
+    known: bool,
+) -> Result<Json<Vec<Incident>>, StatusCode> {
+    if !known {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let mut incidents = state
+        .incidents
+        .read()
+        .unwrap()
+        .get(name)
+        .cloned()
+        .unwrap_or_default();
+    incidents.reverse();
+
+    Ok(Json(incidents))
+}
+
+pub async fn acknowledge_incident(
+    Path(id): Path<Uuid>,
+    Query(params): Query<IncidentAckQueryParams>,
+    Extension(state): Extension<Arc<AppState>>,
+) -> Result<Json<Incident>, StatusCode> {
+    debug!("Acknowledge incident called");
+
+    state
+        .acknowledge_incident(id, params.by)
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+#[cfg(test)]
+mod incidents_tests {
+    use std::sync::Arc;
+
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+    use chrono::Utc;
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+
+    use crate::app_state::AppState;
+    use crate::config::Config;
+    use crate::incidents::model::Incident;
+    use crate::test_utils::probe_test_utils::probe_get_with_expected_status;
+    use crate::web_server::app_router;
+
+    const RELOAD_TOKEN: &str = "test-reload-token";
+
+    // The route is token protected, see `require_reload_token`
+    fn authorized_post(uri: &str) -> Request<Body> {
+        std::env::set_var("XBP_RELOAD_TOKEN", RELOAD_TOKEN);
+        Request::post(uri)
+            .header("Authorization", format!("Bearer {}", RELOAD_TOKEN))
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    fn app_state_with_open_incident() -> Arc<AppState> {
+        let mut probe = probe_get_with_expected_status(
+            reqwest::StatusCode::OK,
+            "http://localhost/health".to_owned(),
+            "".to_owned(),
+        );
+        probe.name = "checkout".to_owned();
+        let app_state = Arc::new(AppState::new(Config {
+            probes: vec![probe],
+            ..Default::default()
+        }));
+        app_state.record_monitor_run("checkout", false, 1);
+        app_state.update_incident("checkout", false, Some("timeout"), Utc::now());
+        app_state
+    }
+
+    async fn send(
+        app_state: Arc<AppState>,
+        request: Request<Body>,
+    ) -> (StatusCode, Option<serde_json::Value>) {
+        let response = app_router(app_state).oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, serde_json::from_slice(&body).ok())
+    }
+
+    async fn get_incidents(app_state: Arc<AppState>, uri: &str) -> Vec<Incident> {
+        let (status, body) = send(app_state, Request::get(uri).body(Body::empty()).unwrap()).await;
+        assert_eq!(StatusCode::OK, status);
+        serde_json::from_value(body.unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_incidents_filter_by_state() {
+        let app_state = app_state_with_open_incident();
+
+        let open = get_incidents(app_state.clone(), "/incidents?state=open").await;
+        let closed = get_incidents(app_state.clone(), "/incidents?state=closed").await;
+        let recent = get_incidents(app_state, "/incidents?since=2020-01-01T00:00:00Z").await;
+
+        assert_eq!(1, open.len());
+        assert_eq!("checkout", open[0].monitor);
+        assert_eq!(Some("timeout".to_owned()), open[0].first_error);
+        assert!(closed.is_empty());
+        assert_eq!(1, recent.len());
+    }
+
+    #[tokio::test]
+    async fn test_probe_incidents() {
+        let app_state = app_state_with_open_incident();
+
+        let incidents = get_incidents(app_state.clone(), "/probes/checkout/incidents").await;
+        let (status, _) = send(
+            app_state.clone(),
+            Request::get("/probes/unknown/incidents")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        let (_, summary) = send(
+            app_state,
+            Request::get("/probes/checkout")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+
+        assert_eq!(1, incidents.len());
+        assert_eq!(StatusCode::NOT_FOUND, status);
+        assert_eq!(
+            incidents[0].id.to_string(),
+            summary.unwrap()["open_incident"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_acknowledge_incident() {
+        let app_state = app_state_with_open_incident();
+        let id = app_state.incidents.read().unwrap()["checkout"][0].id;
+
+        let (status, body) = send(
+            app_state.clone(),
+            authorized_post(&format!("/incidents/{}/ack?by=alice", id)),
+        )
+        .await;
+        let (unknown_status, _) = send(
+            app_state.clone(),
+            authorized_post(&format!("/incidents/{}/ack", uuid::Uuid::new_v4())),
+        )
+        .await;
+
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!("alice", body.unwrap()["acknowledged"]["by"]);
+        assert_eq!(StatusCode::NOT_FOUND, unknown_status);
+        assert!(app_state.incidents.read().unwrap()["checkout"][0]
+            .acknowledged
+            .is_some());
+    }
+}
@@ -0,0 +1,64 @@
+use axum::{extract::Request, http::HeaderValue, middleware::Next, response::Response, Extension};
+use std::sync::{atomic::Ordering, Arc};
+
+use crate::app_state::AppState;
+
+pub const INSTANCE_ID_HEADER: &str = "X-XBP-Instance-Id";
+pub const CONFIG_VERSION_HEADER: &str = "X-XBP-Config-Version";
+
+// Tells which process answered and which config it was running, to spot instances that loaded different configs
+pub async fn instance_headers(
+    Extension(state): Extension<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let mut response = next.run(request).await;
+    let headers = response.headers_mut();
+    if let Ok(instance_id) = HeaderValue::from_str(&state.instance_id.to_string()) {
+        headers.insert(INSTANCE_ID_HEADER, instance_id);
+    }
+    headers.insert(
+        CONFIG_VERSION_HEADER,
+        HeaderValue::from(state.config_version.load(Ordering::SeqCst)),
+    );
+    response
+}
+
+#[cfg(test)]
+mod instance_headers_tests {
+    use std::sync::Arc;
+
+    use axum::body::Body;
+    use axum::http::Request;
+    use tower::ServiceExt;
+
+    use crate::app_state::AppState;
+    use crate::config::Config;
+    use crate::web_server::app_router;
+
+    async fn get_headers(app_state: Arc<AppState>, uri: &str) -> (String, String) {
+        let response = app_router(app_state)
+            .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let header = |name: &str| response.headers()[name].to_str().unwrap().to_owned();
+        (header("X-XBP-Instance-Id"), header("X-XBP-Config-Version"))
+    }
+
+    #[tokio::test]
+    async fn test_responses_carry_instance_id_and_config_version() {
+        let app_state = Arc::new(AppState::new(Config::default()));
+
+        let (instance_id, version) = get_headers(app_state.clone(), "/").await;
+        assert_eq!(app_state.instance_id.to_string(), instance_id);
+        assert_eq!("0", version);
+
+        app_state.reload(Config::default()).await;
+
+        // Error responses carry the headers too
+        let (reloaded_instance_id, version) =
+            get_headers(app_state.clone(), "/probes/unknown").await;
+        assert_eq!(instance_id, reloaded_instance_id);
+        assert_eq!("1", version);
+    }
+}
@@ -1,30 +1,125 @@
+mod alerts;
+mod blackbox;
+mod explain;
+mod export;
+mod incidents;
+mod info;
+mod instance_headers;
 mod model;
+mod openmetrics;
 mod probes;
 mod prometheus_metrics;
+mod reload;
+mod reload_token;
+mod reports;
+mod runtime_monitors;
+mod schema;
+mod status;
 mod stories;
+mod timeline;
 
 use crate::web_server::{
-    probes::{get_probe_results, probe_trigger, probes},
-    stories::{get_story_results, stories, story_trigger},
+    alerts::test_alerts,
+    blackbox::blackbox_probe,
+    explain::explain_probe,
+    export::{
+        export_history_csv, export_probes_ndjson, probe_history_csv, probe_history_ndjson,
+        story_history_ndjson,
+    },
+    incidents::{acknowledge_incident, incidents, probe_incidents, story_incidents},
+    info::{about, info},
+    instance_headers::instance_headers,
+    probes::{get_probe, get_probe_results, probe_trigger, probes},
+    reload::{monitors, probes_alias, reload, resolved_config},
+    reload_token::require_reload_token,
+    reports::run_report_now,
+    runtime_monitors::{
+        add_probe, add_story, delete_probe, delete_story, disable_probe, enable_probe,
+    },
+    schema::api_schema,
+    status::status,
+    stories::{
+        get_story, get_story_results, stories, story_history, story_statuses, story_trigger,
+    },
+    timeline::timeline,
+};
+use axum::{
+    middleware,
+    routing::{delete, get, post},
+    Extension, Router,
 };
-use axum::{routing::get, Extension, Router};
 use std::{env, sync::Arc};
+use tokio::net::TcpListener;
 use tracing::{debug, info};
 
 use crate::app_state::AppState;
 
-pub async fn start_axum_server(app_state: Arc<AppState>) {
-    let app = Router::new()
+pub fn app_router(app_state: Arc<AppState>) -> Router {
+    app_router_with_extra_routes(app_state, None)
+}
+
+// For users embedding the crate, e.g. to add `/-/myapp/health`. The extra routes get the same
+// layers, so handlers can take `Extension<Arc<AppState>>`. Paths already served here panic on merge.
+pub fn app_router_with_extra_routes(
+    app_state: Arc<AppState>,
+    extra_routes: Option<Router>,
+) -> Router {
+    // Everything that changes the running config, or runs something on demand, goes through the
+    // same token check
+    let token_protected = Router::new()
+        .route("/-/reload", post(reload))
+        .route("/probes", post(add_probe))
+        .route("/probes/:name", delete(delete_probe))
+        .route("/stories", post(add_story))
+        .route("/stories/:name", delete(delete_story))
+        .route("/-/probes/:name/enable", post(enable_probe))
+        .route("/-/probes/:name/disable", post(disable_probe))
+        .route("/probes/:name/trigger", get(probe_trigger))
+        .route("/stories/:name/trigger", get(story_trigger))
+        .route("/incidents/:id/ack", post(acknowledge_incident))
+        .route("/-/alerts/test", post(test_alerts))
+        .route("/-/reports/:name/run", post(run_report_now))
+        .route_layer(middleware::from_fn(require_reload_token));
+
+    Router::new()
         .route("/", get(root))
+        .route("/probe", get(blackbox_probe))
         .route("/probes", get(probes))
+        .route("/probes/:name", get(get_probe))
         .route("/probes/:name/results", get(get_probe_results))
-        .route("/probes/:name/trigger", get(probe_trigger))
+        .route("/probes/:name/history.csv", get(probe_history_csv))
+        .route("/probes/:name/history.ndjson", get(probe_history_ndjson))
+        .route("/probes/:name/incidents", get(probe_incidents))
+        .route("/probes/:name/explain", get(explain_probe))
         .route("/stories", get(stories))
+        .route("/stories/:name", get(get_story))
         .route("/stories/:name/results", get(get_story_results))
-        .route("/stories/:name/trigger", get(story_trigger))
-        .layer(Extension(app_state.clone()));
+        .route("/stories/:name/history.ndjson", get(story_history_ndjson))
+        .route("/stories/:name/incidents", get(story_incidents))
+        .route("/status", get(status))
+        .route("/incidents", get(incidents))
+        .route("/export/history.csv", get(export_history_csv))
+        .route("/-/export/probes.ndjson", get(export_probes_ndjson))
+        .route("/-/monitors", get(monitors))
+        .route("/-/probes", get(probes_alias))
+        .route("/-/stories", get(story_statuses))
+        .route("/-/stories/:name", get(story_history))
+        .route("/-/config", get(resolved_config))
+        .route("/-/info", get(info))
+        .route("/-/about", get(about))
+        .route("/-/timeline", get(timeline))
+        .route("/-/schema.json", get(api_schema))
+        .route("/metrics", get(prometheus_metrics::metrics_handler))
+        .merge(token_protected)
+        .merge(extra_routes.unwrap_or_default())
+        .layer(middleware::from_fn(instance_headers))
+        .layer(Extension(app_state))
+}
+
+pub async fn start_axum_server(app_state: Arc<AppState>, extra_routes: Option<Router>) {
+    let app = app_router_with_extra_routes(app_state, extra_routes);
 
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
+    let listener = TcpListener::bind("0.0.0.0:3000").await.unwrap();
 
     info!("listening on {}", listener.local_addr().unwrap());
 
@@ -40,11 +135,7 @@ pub async fn start_prometheus_server(registry: Arc<prometheus::Registry>) {
         Ok(port) => port,
         Err(_) => "9464".to_owned(),
     };
-    let app = Router::new()
-        .route("/metrics", get(prometheus_metrics::metrics_handler))
-        .layer(Extension(registry));
-
-    let listener = tokio::net::TcpListener::bind(format!("{}:{}", host, port))
+    let listener = TcpListener::bind(format!("{}:{}", host, port))
         .await
         .unwrap();
 
@@ -53,6 +144,16 @@ pub async fn start_prometheus_server(registry: Arc<prometheus::Registry>) {
         listener.local_addr().unwrap()
     );
 
+    serve_prometheus(listener, registry).await;
+}
+
+// Runs on the caller's runtime, `main` spawns it next to the API server instead of giving it threads
+// of its own
+pub async fn serve_prometheus(listener: TcpListener, registry: Arc<prometheus::Registry>) {
+    let app = Router::new()
+        .route("/metrics", get(prometheus_metrics::metrics_handler))
+        .layer(Extension(registry));
+
     axum::serve(listener, app).await.unwrap();
 }
 
@@ -60,3 +161,118 @@ async fn root() -> &'static str {
     debug!("Application root called");
     "Roar!"
 }
+
+#[cfg(test)]
+mod web_server_tests {
+    use std::sync::Arc;
+
+    use axum::body::{to_bytes, Body};
+    use axum::http::{Request, StatusCode};
+    use axum::{routing::get, Extension, Router};
+    use tokio::net::TcpListener;
+    use tower::ServiceExt;
+
+    use crate::app_state::AppState;
+    use crate::config::Config;
+    use crate::web_server::{app_router, app_router_with_extra_routes, serve_prometheus};
+
+    // Both servers are spawned like `main` does, a single thread runtime has to drive them both
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_prometheus_and_api_servers_share_the_runtime() {
+        let prometheus_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let prometheus_addr = prometheus_listener.local_addr().unwrap();
+        let api_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let api_addr = api_listener.local_addr().unwrap();
+        let app_state = Arc::new(AppState::new(Config::default()));
+
+        let prometheus = tokio::spawn(serve_prometheus(
+            prometheus_listener,
+            Arc::new(prometheus::Registry::new()),
+        ));
+        let api = tokio::spawn(async move {
+            axum::serve(api_listener, app_router(app_state))
+                .await
+                .unwrap()
+        });
+
+        let metrics = reqwest::get(format!("http://{}/metrics", prometheus_addr))
+            .await
+            .unwrap();
+        let root = reqwest::get(format!("http://{}/", api_addr)).await.unwrap();
+
+        assert!(metrics.status().is_success());
+        assert!(root.status().is_success());
+        prometheus.abort();
+        api.abort();
+        assert!(prometheus.await.unwrap_err().is_cancelled());
+        assert!(api.await.unwrap_err().is_cancelled());
+    }
+
+    #[tokio::test]
+    async fn test_extra_routes_are_merged() {
+        let app_state = Arc::new(AppState::new(Config::default()));
+        let extra_routes = Router::new().route(
+            "/-/myapp/health",
+            get(|Extension(state): Extension<Arc<AppState>>| async move {
+                format!("{} probes", state.config().probes.len())
+            }),
+        );
+        let app = app_router_with_extra_routes(app_state, Some(extra_routes));
+
+        let health = app
+            .clone()
+            .oneshot(Request::get("/-/myapp/health").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let root = app
+            .oneshot(Request::get("/").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(StatusCode::OK, health.status());
+        assert!(health.headers().contains_key("X-XBP-Instance-Id"));
+        let body = to_bytes(health.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(b"0 probes", &body[..]);
+        assert_eq!(StatusCode::OK, root.status());
+    }
+
+    // axum's `get` answers HEAD with the GET handler and drops the body after setting Content-Length,
+    // so read-only routes need no separate HEAD route
+    #[tokio::test]
+    async fn test_head_requests_get_the_get_headers_without_a_body() {
+        let app = app_router(Arc::new(AppState::new(Config::default())));
+
+        // Routes whose response doesn't change from one request to the next
+        for uri in ["/", "/probes", "/stories", "/-/monitors", "/-/probes"] {
+            let get = app
+                .clone()
+                .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            let head = app
+                .clone()
+                .oneshot(Request::head(uri).body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+
+            assert_eq!(StatusCode::OK, head.status(), "{}", uri);
+            assert_eq!(
+                get.headers().get("content-type"),
+                head.headers().get("content-type"),
+                "{}",
+                uri
+            );
+            let body = to_bytes(get.into_body(), usize::MAX).await.unwrap();
+            assert_eq!(
+                Some(body.len().to_string().as_str()),
+                head.headers()
+                    .get("content-length")
+                    .and_then(|length| length.to_str().ok()),
+                "{}",
+                uri
+            );
+            let head_body = to_bytes(head.into_body(), usize::MAX).await.unwrap();
+            assert!(head_body.is_empty(), "{}", uri);
+        }
+    }
+}
@@ -1,14 +1,630 @@
 use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::app_state::MonitorState;
+use crate::config::Settings;
+use crate::errors::AlertChannel;
+use crate::incidents::model::IncidentState;
+use crate::probe::circuit_breaker::CircuitBreaker;
+use crate::probe::duration;
+use crate::probe::model::{
+    ConnectionDetails, FailedExpectation, PhaseTiming, ProbeExpectation, ProbeOptions,
+    ProbeScheduleParameters, StatusPattern, StoryResult,
+};
+use crate::result_store::MonitorActivity;
+use crate::self_alerts::SelfEvent;
 
 #[derive(Deserialize)]
 pub struct ProbeQueryParams {
     pub show_response: Option<bool>,
+    // `csv` returns the history as a CSV document, `ndjson` as one JSON line per run
+    pub format: Option<String>,
+    // RFC 3339 timestamp, leaves out runs that started before it
+    pub since: Option<DateTime<Utc>>,
+    // At most this many runs, the latest ones whatever the order
+    pub limit: Option<usize>,
+    // Newest first when unset
+    pub order: Option<HistoryOrder>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HistoryOrder {
+    #[default]
+    Newest,
+    Oldest,
+}
+
+impl ProbeQueryParams {
+    // The runs of a history (stored oldest first) passing `since` and `limit`, in `order`
+    pub fn select<'a, T: 'a>(
+        &self,
+        results: impl IntoIterator<Item = &'a T>,
+        started: impl Fn(&T) -> DateTime<Utc>,
+    ) -> Vec<&'a T> {
+        let mut selected: Vec<&T> = results
+            .into_iter()
+            .filter(|result| self.since.is_none_or(|since| started(*result) >= since))
+            .collect();
+        if let Some(limit) = self.limit {
+            selected = selected.split_off(selected.len().saturating_sub(limit));
+        }
+        if self.order.unwrap_or_default() == HistoryOrder::Newest {
+            selected.reverse();
+        }
+        selected
+    }
+}
+
+#[derive(Deserialize)]
+pub struct BlackboxProbeQueryParams {
+    pub target: String,
+    // Name of a module under `settings.probe_modules.modules`
+    pub module: String,
+}
+
+// `?group=` on the listings, limits them to the monitors of one `group`
+#[derive(Deserialize)]
+pub struct GroupQueryParams {
+    pub group: Option<String>,
+}
+
+// Whether a monitor in `monitor_group` passes a `?group=` filter, every monitor does without one
+pub fn matches_group(monitor_group: &Option<String>, filter: &Option<String>) -> bool {
+    filter
+        .as_ref()
+        .is_none_or(|filter| monitor_group.as_ref() == Some(filter))
+}
+
+#[derive(Deserialize)]
+pub struct ExportQueryParams {
+    // `key` or `key:value`, limits the export to monitors with a matching tag
+    pub tag: Option<String>,
+    pub group: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct ProbeExportQueryParams {
+    // Only this probe's runs, all configured probes when unset
+    pub probe: Option<String>,
+    // RFC 3339 timestamps, runs that started before `from` or after `to` are left out
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    // Keeps the response of each run, bodies of sensitive probes included
+    pub show_response: Option<bool>,
+    pub group: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct IncidentQueryParams {
+    pub state: Option<IncidentState>,
+    // RFC 3339 timestamp, leaves out incidents that ended before it
+    pub since: Option<DateTime<Utc>>,
+    pub group: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct IncidentAckQueryParams {
+    // Who acknowledged the incident, free text
+    pub by: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ProbeStatus {
+    Ok,
+    Error,
+    // Failing, but succeeding again without having reached the recovery threshold
+    Degraded,
+    // Configured, but not run yet
+    Unknown,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl ProbeStatus {
+    // From the success of the latest result, if there is one, and the monitor's recovery state
+    pub fn of(last_success: Option<bool>, monitor_state: Option<&MonitorState>) -> ProbeStatus {
+        match (last_success, monitor_state) {
+            (None, _) => ProbeStatus::Unknown,
+            (_, Some(state)) if state.pending_recovery() => ProbeStatus::Degraded,
+            (_, Some(state)) if state.failing => ProbeStatus::Error,
+            (_, Some(_)) => ProbeStatus::Ok,
+            (Some(true), None) => ProbeStatus::Ok,
+            (Some(false), None) => ProbeStatus::Error,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct ProbeResponse {
     pub name: String,
-    pub status: String,
-    pub last_probed: DateTime<Utc>,
-}
\ No newline at end of file
+    pub status: ProbeStatus,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "rfc3339_millis::option"
+    )]
+    #[schemars(with = "Option<DateTime<Utc>>")]
+    pub last_probed: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub recovery: Option<RecoveryProgress>,
+    // Id of the monitor's open incident, see `/incidents`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub open_incident: Option<Uuid>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "rfc3339_millis::option"
+    )]
+    #[schemars(with = "Option<DateTime<Utc>>")]
+    pub last_success_at: Option<DateTime<Utc>>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "rfc3339_millis::option"
+    )]
+    #[schemars(with = "Option<DateTime<Utc>>")]
+    pub last_failure_at: Option<DateTime<Utc>>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "rfc3339_millis::option"
+    )]
+    #[schemars(with = "Option<DateTime<Utc>>")]
+    pub last_state_change_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub success_streak: u32,
+    #[serde(default)]
+    pub failure_streak: u32,
+    // Backend the latest http run reached, see `settings.capture_headers`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connection: Option<ConnectionDetails>,
+    // State, transitions and current interval of the probe's `circuit_breaker`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub circuit_breaker: Option<CircuitBreaker>,
+}
+
+// The latest run of a story, as listed by `/-/stories`
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct StoryStatus {
+    pub name: String,
+    pub status: ProbeStatus,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "rfc3339_millis::option"
+    )]
+    #[schemars(with = "Option<DateTime<Utc>>")]
+    pub last_run: Option<DateTime<Utc>>,
+    #[serde(
+        default,
+        rename = "duration_ms",
+        with = "duration::millis_f64",
+        skip_serializing_if = "Option::is_none"
+    )]
+    #[schemars(with = "Option<f64>")]
+    pub duration: Option<Duration>,
+}
+
+// `/-/stories/:name`, the status followed by the stored runs with their steps
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct StoryHistoryResponse {
+    #[serde(flatten)]
+    pub status: StoryStatus,
+    pub runs: Vec<StoryResult>,
+}
+
+// Progress of a failing monitor towards being reported as OK again
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct RecoveryProgress {
+    pub successes: u32,
+    pub threshold: u32,
+}
+
+impl ProbeResponse {
+    // `last_run` holds the success and start time of the latest result, if there is one
+    pub fn new(
+        name: String,
+        last_run: Option<(bool, DateTime<Utc>)>,
+        monitor_state: Option<&MonitorState>,
+    ) -> ProbeResponse {
+        let status = ProbeStatus::of(last_run.map(|(success, _)| success), monitor_state);
+        let recovery = monitor_state
+            .filter(|state| state.pending_recovery())
+            .map(|state| RecoveryProgress {
+                successes: state.consecutive_successes,
+                threshold: state.recovery_threshold,
+            });
+
+        ProbeResponse {
+            name,
+            status,
+            last_probed: last_run.map(|(_, timestamp)| timestamp),
+            recovery,
+            open_incident: monitor_state.and_then(|state| state.open_incident),
+            last_success_at: None,
+            last_failure_at: None,
+            last_state_change_at: None,
+            success_streak: 0,
+            failure_streak: 0,
+            connection: None,
+            circuit_breaker: None,
+        }
+    }
+
+    // Adds when the monitor last succeeded and failed, which outlives the result history
+    pub fn with_activity(mut self, activity: Option<&MonitorActivity>) -> ProbeResponse {
+        if let Some(activity) = activity {
+            self.last_success_at = activity.last_success_at;
+            self.last_failure_at = activity.last_failure_at;
+            self.last_state_change_at = activity.last_state_change_at;
+            self.success_streak = activity.success_streak;
+            self.failure_streak = activity.failure_streak;
+        }
+        self
+    }
+
+    pub fn with_connection(mut self, connection: Option<&ConnectionDetails>) -> ProbeResponse {
+        self.connection = connection.cloned();
+        self
+    }
+
+    pub fn with_circuit_breaker(
+        mut self,
+        circuit_breaker: Option<CircuitBreaker>,
+    ) -> ProbeResponse {
+        self.circuit_breaker = circuit_breaker;
+        self
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ExplainQueryParams {
+    // The run to explain, the latest when unset
+    pub run: Option<Uuid>,
+}
+
+// Served by `/probes/:name/explain`, what a run's result means for whoever is on call
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct ExplainResponse {
+    pub probe_name: String,
+    pub run_id: Uuid,
+    pub timestamp_started: DateTime<Utc>,
+    pub success: bool,
+    // As in history exports, e.g. `request`, `expectation` or `kiss_of_death`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_kind: Option<&'static str>,
+    // What the error kind means, in a sentence
+    pub explanation: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_message: Option<String>,
+    // The received values are `<redacted>` for sensitive probes
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub failed_expectations: Vec<FailedExpectation>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub phases: Vec<PhaseTiming>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub slowest_phase: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub failed_phase: Option<String>,
+    pub during_reload: bool,
+    // Left out of monitor states, alerting and reports, see `settings.ignore_results_during_reload`
+    // and `settings.rate_limits.count_as_failure`
+    pub ignored: bool,
+    // The probe's current streak, which may have grown since the run
+    pub consecutive_failures: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub open_incident: Option<Uuid>,
+}
+
+// Served by `/-/about`, a smaller `/-/info` for tools that only need the version
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct AboutResponse {
+    pub version: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub build_timestamp: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub git_sha: Option<&'static str>,
+    // Masked like `/-/info` `config_source`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub config_path: Option<String>,
+    pub uptime_seconds: u64,
+}
+
+// Served by `/-/timeline`
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct TimelineResponse {
+    pub events: Vec<SelfEvent>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct MonitorsResponse {
+    pub probes: Vec<MonitorInfo>,
+    pub stories: Vec<MonitorInfo>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct MonitorInfo {
+    pub name: String,
+    // Normalized, e.g. "1h 30m" for `interval: 5400`
+    #[serde(
+        deserialize_with = "duration::deserialize_required_seconds",
+        serialize_with = "duration::serialize_required"
+    )]
+    #[schemars(with = "String")]
+    pub interval: Duration,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tags: Option<HashMap<String, String>>,
+    // Added through the API rather than the config file
+    #[serde(default)]
+    pub runtime_added: bool,
+    // Probes a story's steps request as well, see `Story::referenced_probes`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub referenced_probes: Vec<String>,
+    // Set while a rate limited probe waits for its next run, see `with.respect_retry_after`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deferred_until: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ResolvedConfigResponse {
+    // The config types are described by the README, the schema leaves them open
+    #[schemars(with = "serde_json::Value")]
+    pub settings: Settings,
+    pub probes: Vec<ResolvedMonitor>,
+    pub stories: Vec<ResolvedStory>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ResolvedStory {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base_url: Option<String>,
+    #[schemars(with = "serde_json::Value")]
+    pub schedule: ProbeScheduleParameters,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub setup: Vec<ResolvedMonitor>,
+    pub steps: Vec<ResolvedMonitor>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub teardown: Vec<ResolvedMonitor>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ResolvedMonitor {
+    pub name: String,
+    // Probes only, steps run on the schedule of their story
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(with = "Option<serde_json::Value>")]
+    pub schedule: Option<ProbeScheduleParameters>,
+    pub success_criteria: SuccessCriteria,
+    // Expectation sets are already flattened into this list
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(with = "Option<Vec<serde_json::Value>>")]
+    pub expectations: Option<Vec<ProbeExpectation>>,
+    // The typed `with` block, header values and sensitive bodies redacted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(with = "Option<serde_json::Value>")]
+    pub options: Option<ProbeOptions>,
+}
+
+// What decides whether a received response counts as a success
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct SuccessCriteria {
+    pub source: SuccessCriteriaSource,
+    // Serialized like `2xx` or `302`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(with = "Option<Vec<String>>")]
+    pub statuses: Option<Vec<StatusPattern>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SuccessCriteriaSource {
+    // A StatusCode expectation is configured
+    Expectations,
+    // The probe's own `success_statuses`
+    Probe,
+    // `settings.default_success_statuses`
+    Settings,
+    // Nothing configured, any received response is a success
+    AnyResponse,
+}
+
+#[derive(Deserialize)]
+pub struct AlertTestQueryParams {
+    // Tests every alert of the monitor
+    pub monitor: Option<String>,
+    // Tests a single entry of `alert_channels`
+    pub channel: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct AlertTestResult {
+    pub channel: AlertChannel,
+    pub success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<AlertFailure>,
+}
+
+// Where `/-/reload` reads the config from, the config path it was started with when unset
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReloadSource {
+    // The local config file, never a remote url
+    Local,
+    // The `url` of the request body, or the config path when that is a url
+    Remote,
+}
+
+#[derive(Deserialize)]
+pub struct ReloadQueryParams {
+    pub source: Option<ReloadSource>,
+    // Keep the running config when a verification run fails, verifies even without
+    // `settings.verify_on_reload`
+    #[serde(default)]
+    pub strict: bool,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ReloadRequest {
+    pub url: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ReloadResponse {
+    pub probes: usize,
+    pub stories: usize,
+    // Monitors that weren't in the previous config
+    pub added: Vec<String>,
+    // Monitors that are no longer configured, their history is dropped
+    pub removed: Vec<String>,
+    // Groups of the added, removed and changed monitors
+    #[serde(default)]
+    pub groups: Vec<String>,
+    // One run of every added or changed monitor, when verification is on
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub verification: Option<Vec<VerificationOutcome>>,
+}
+
+// A verification run, kept out of history, incidents and alerting
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct VerificationOutcome {
+    pub name: String,
+    pub passed: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ReportRunResponse {
+    // The rendered report as it was sent
+    pub report: String,
+    pub alerts: Vec<AlertTestResult>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct AlertFailure {
+    pub kind: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status_code: Option<u16>,
+    pub message: String,
+}
+
+// Serializes timestamps as RFC 3339 in UTC with millisecond precision, e.g. `2024-01-15T10:30:00.000Z`
+pub mod rfc3339_millis {
+    use chrono::{DateTime, SecondsFormat, Utc};
+    use serde::Serializer;
+
+    pub fn format(timestamp: &DateTime<Utc>) -> String {
+        timestamp.to_rfc3339_opts(SecondsFormat::Millis, true)
+    }
+
+    pub fn serialize<S: Serializer>(
+        timestamp: &DateTime<Utc>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format(timestamp))
+    }
+
+    pub fn parse(value: &str) -> Result<DateTime<Utc>, chrono::ParseError> {
+        DateTime::parse_from_rfc3339(value).map(|timestamp| timestamp.with_timezone(&Utc))
+    }
+
+    pub mod option {
+        use chrono::{DateTime, Utc};
+        use serde::{Deserialize, Deserializer, Serializer};
+
+        pub fn serialize<S: Serializer>(
+            timestamp: &Option<DateTime<Utc>>,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            match timestamp {
+                Some(timestamp) => super::serialize(timestamp, serializer),
+                None => serializer.serialize_none(),
+            }
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Option<DateTime<Utc>>, D::Error> {
+            Option::<String>::deserialize(deserializer)?
+                .map(|value| super::parse(&value).map_err(serde::de::Error::custom))
+                .transpose()
+        }
+    }
+}
+
+#[cfg(test)]
+mod model_tests {
+    use chrono::{TimeZone, Timelike, Utc};
+
+    use crate::app_state::MonitorState;
+    use crate::web_server::model::{ProbeResponse, ProbeStatus};
+
+    #[test]
+    fn test_probe_status_serializes_lowercase() {
+        let serialized: Vec<String> = [
+            ProbeStatus::Ok,
+            ProbeStatus::Error,
+            ProbeStatus::Degraded,
+            ProbeStatus::Unknown,
+        ]
+        .iter()
+        .map(|status| serde_json::to_string(status).unwrap())
+        .collect();
+
+        assert_eq!(
+            vec!["\"ok\"", "\"error\"", "\"degraded\"", "\"unknown\""],
+            serialized
+        );
+    }
+
+    #[test]
+    fn test_probe_response_status_from_state() {
+        let now = Utc::now();
+        let recovering = MonitorState {
+            failing: true,
+            consecutive_failures: 0,
+            consecutive_successes: 1,
+            recovery_threshold: 3,
+            ..Default::default()
+        };
+
+        let unknown = ProbeResponse::new("probe".to_owned(), None, None);
+        let failed = ProbeResponse::new("probe".to_owned(), Some((false, now)), None);
+        let degraded = ProbeResponse::new("probe".to_owned(), Some((true, now)), Some(&recovering));
+
+        assert_eq!(ProbeStatus::Unknown, unknown.status);
+        assert!(unknown.last_probed.is_none());
+        assert_eq!(ProbeStatus::Error, failed.status);
+        assert_eq!(ProbeStatus::Degraded, degraded.status);
+        assert_eq!(1, degraded.recovery.unwrap().successes);
+    }
+
+    #[test]
+    fn test_timestamps_serialize_as_rfc3339_millis() {
+        let last_probed = Utc
+            .with_ymd_and_hms(2024, 1, 15, 10, 30, 0)
+            .unwrap()
+            .with_nanosecond(123_456_789)
+            .unwrap();
+        let response = ProbeResponse::new("probe".to_owned(), Some((true, last_probed)), None);
+
+        let serialized = serde_json::to_value(&response).unwrap();
+        assert_eq!("2024-01-15T10:30:00.123Z", serialized["last_probed"]);
+
+        let whole_second = ProbeResponse::new(
+            "probe".to_owned(),
+            Some((true, Utc.with_ymd_and_hms(2024, 1, 15, 10, 30, 0).unwrap())),
+            None,
+        );
+        let serialized = serde_json::to_value(&whole_second).unwrap();
+        assert_eq!("2024-01-15T10:30:00.000Z", serialized["last_probed"]);
+
+        let deserialized: ProbeResponse = serde_json::from_value(serialized).unwrap();
+        assert_eq!(whole_second.last_probed, deserialized.last_probed);
+    }
+}
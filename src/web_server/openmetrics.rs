@@ -0,0 +1,206 @@
+// The OpenMetrics exposition of the gathered metric families. Unlike the classic text format it
+// carries exemplars, the `duration` histogram buckets link to the trace of a recent run.
+use std::fmt::Write;
+
+use prometheus::proto::{MetricFamily, MetricType};
+
+use crate::otel::exemplars::{self, Exemplar};
+
+pub const CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+// Whether the `Accept` header asks for OpenMetrics, e.g. Prometheus scraping with exemplar storage
+pub fn accepts(accept: &str) -> bool {
+    accept.split(',').any(|range| {
+        let mut parts = range.split(';').map(str::trim);
+        let media_type = parts.next().unwrap_or_default();
+        let refused = parts.any(|param| {
+            param
+                .strip_prefix("q=")
+                .and_then(|q| q.parse::<f64>().ok())
+                .is_some_and(|q| q == 0.0)
+        });
+        media_type.eq_ignore_ascii_case("application/openmetrics-text") && !refused
+    })
+}
+
+// The exporter suffixes the unit, e.g. `duration_milliseconds`
+fn has_exemplars(family: &MetricFamily) -> bool {
+    family.get_field_type() == MetricType::HISTOGRAM
+        && (family.name() == "duration" || family.name().starts_with("duration_"))
+}
+
+pub fn encode(metric_families: &[MetricFamily]) -> String {
+    let mut out = String::new();
+    for family in metric_families {
+        let metric_type = family.get_field_type();
+        // Counter families are named without the `_total` of their samples
+        let (name, type_name) = match metric_type {
+            MetricType::COUNTER => (
+                family
+                    .name()
+                    .strip_suffix("_total")
+                    .unwrap_or(family.name()),
+                "counter",
+            ),
+            MetricType::GAUGE => (family.name(), "gauge"),
+            MetricType::SUMMARY => (family.name(), "summary"),
+            MetricType::UNTYPED => (family.name(), "unknown"),
+            MetricType::HISTOGRAM => (family.name(), "histogram"),
+        };
+        let _ = writeln!(out, "# TYPE {} {}", name, type_name);
+        if !family.help().is_empty() {
+            let _ = writeln!(out, "# HELP {} {}", name, escape(family.help()));
+        }
+        let with_exemplars = has_exemplars(family);
+
+        for metric in family.get_metric() {
+            let labels = metric
+                .get_label()
+                .iter()
+                .map(|label| (label.name(), label.value()))
+                .collect::<Vec<_>>();
+            let timestamp_ms = metric.timestamp_ms();
+            let mut sample = |suffix: &str,
+                              extra: Option<(&str, String)>,
+                              value: f64,
+                              exemplar: Option<Exemplar>| {
+                write_sample(
+                    &mut out,
+                    Sample {
+                        name,
+                        suffix,
+                        labels: &labels,
+                        extra,
+                        value,
+                        timestamp_ms,
+                        exemplar,
+                    },
+                );
+            };
+            match metric_type {
+                MetricType::COUNTER => sample("_total", None, metric.get_counter().value(), None),
+                MetricType::GAUGE => sample("", None, metric.get_gauge().value(), None),
+                MetricType::UNTYPED => sample("", None, metric.untyped.value(), None),
+                MetricType::SUMMARY => {
+                    let summary = metric.get_summary();
+                    for quantile in summary.get_quantile() {
+                        sample(
+                            "",
+                            Some(("quantile", format_value(quantile.quantile()))),
+                            quantile.value(),
+                            None,
+                        );
+                    }
+                    sample("_sum", None, summary.sample_sum(), None);
+                    sample("_count", None, summary.sample_count() as f64, None);
+                }
+                MetricType::HISTOGRAM => {
+                    let histogram = metric.get_histogram();
+                    let mut buckets = histogram
+                        .get_bucket()
+                        .iter()
+                        .map(|bucket| (bucket.upper_bound(), bucket.cumulative_count()))
+                        .collect::<Vec<_>>();
+                    if buckets.last().map(|(upper, _)| *upper) != Some(f64::INFINITY) {
+                        buckets.push((f64::INFINITY, histogram.get_sample_count()));
+                    }
+                    let mut lower = f64::NEG_INFINITY;
+                    for (upper, count) in buckets {
+                        let exemplar = with_exemplars
+                            .then(|| exemplars::for_bucket(&labels, lower, upper))
+                            .flatten();
+                        sample(
+                            "_bucket",
+                            Some(("le", format_value(upper))),
+                            count as f64,
+                            exemplar,
+                        );
+                        lower = upper;
+                    }
+                    sample("_sum", None, histogram.get_sample_sum(), None);
+                    sample("_count", None, histogram.get_sample_count() as f64, None);
+                }
+            }
+        }
+    }
+    out.push_str("# EOF\n");
+    out
+}
+
+struct Sample<'a> {
+    name: &'a str,
+    suffix: &'a str,
+    labels: &'a [(&'a str, &'a str)],
+    // `le` of histogram buckets and `quantile` of summaries
+    extra: Option<(&'a str, String)>,
+    value: f64,
+    timestamp_ms: i64,
+    exemplar: Option<Exemplar>,
+}
+
+fn write_sample(out: &mut String, sample: Sample) {
+    out.push_str(sample.name);
+    out.push_str(sample.suffix);
+    let pairs = sample
+        .labels
+        .iter()
+        .map(|(key, value)| format!("{}=\"{}\"", key, escape(value)))
+        .chain(
+            sample
+                .extra
+                .map(|(key, value)| format!("{}=\"{}\"", key, value)),
+        )
+        .collect::<Vec<_>>();
+    if !pairs.is_empty() {
+        let _ = write!(out, "{{{}}}", pairs.join(","));
+    }
+    let _ = write!(out, " {}", format_value(sample.value));
+    // OpenMetrics timestamps are in seconds
+    if sample.timestamp_ms != 0 {
+        let _ = write!(out, " {}", sample.timestamp_ms as f64 / 1000.0);
+    }
+    if let Some(exemplar) = sample.exemplar {
+        let _ = write!(
+            out,
+            " # {{trace_id=\"{}\"}} {} {}",
+            exemplar.trace_id,
+            format_value(exemplar.value),
+            exemplar.timestamp.timestamp_millis() as f64 / 1000.0
+        );
+    }
+    out.push('\n');
+}
+
+fn format_value(value: f64) -> String {
+    if value == f64::INFINITY {
+        "+Inf".to_owned()
+    } else if value == f64::NEG_INFINITY {
+        "-Inf".to_owned()
+    } else if value.is_nan() {
+        "NaN".to_owned()
+    } else {
+        value.to_string()
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('\n', "\\n")
+        .replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod openmetrics_tests {
+    use super::accepts;
+
+    #[test]
+    fn test_accepts_openmetrics() {
+        assert!(accepts(
+            "application/openmetrics-text;version=1.0.0;q=0.5,text/plain;version=0.0.4;q=0.3,*/*;q=0.2"
+        ));
+        assert!(!accepts("text/plain;version=0.0.4"));
+        assert!(!accepts("application/openmetrics-text;q=0,text/plain"));
+        assert!(!accepts("*/*"));
+    }
+}
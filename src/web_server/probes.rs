@@ -1,30 +1,51 @@
 use axum::{
     extract::{Path, Query},
+    http::StatusCode,
+    response::{IntoResponse, Response},
     Extension, Json,
 };
 use std::sync::Arc;
-use tracing::debug;
+use tracing::{debug, warn};
 
 use crate::{
     app_state::AppState,
-    probe::{model::ProbeResult, probe_logic::Monitorable},
+    probe::{circuit_breaker::CircuitBreaker, model::ProbeResult, probe_logic::Monitorable},
+    storage::MonitorKind,
 };
 
-use super::model::{ProbeQueryParams, ProbeResponse};
+use super::export::{probe_history_csv_response, probe_history_ndjson_response};
+use super::model::{GroupQueryParams, ProbeQueryParams, ProbeResponse};
 
 pub async fn get_probe_results(
     Path(name): Path<String>,
     Query(params): Query<ProbeQueryParams>,
     Extension(state): Extension<Arc<AppState>>,
-) -> Json<Vec<ProbeResult>> {
+) -> Response {
     debug!("Get probe results called");
 
-    let show_response = params.show_response.unwrap_or(false);
-    let read_lock = state.probe_results.read().unwrap();
-    let results = read_lock.get(&name).unwrap();
+    match params.format.as_deref() {
+        Some("csv") => return probe_history_csv_response(&name, &state),
+        Some("ndjson") => return probe_history_ndjson_response(&name, &params, &state),
+        _ => {}
+    }
 
-    let mut cloned_results: Vec<ProbeResult> = results.clone();
-    cloned_results.reverse();
+    let show_response = params.show_response.unwrap_or(false);
+    let stored = match state
+        .storage
+        .recent(MonitorKind::Probe, &name, params.since, params.limit)
+        .await
+    {
+        Ok(stored) => stored.into_probes(),
+        Err(e) => {
+            warn!("{}, serving the in-memory results of {}", e, name);
+            state.probe_results.recent(&name).unwrap_or_default()
+        }
+    };
+    let mut cloned_results: Vec<ProbeResult> = params
+        .select(&stored, |result| result.timestamp_started)
+        .into_iter()
+        .cloned()
+        .collect();
 
     if !show_response {
         for result in &mut cloned_results {
@@ -32,42 +53,97 @@ pub async fn get_probe_results(
         }
     }
 
-    Json(cloned_results)
+    Json(cloned_results).into_response()
 }
 
-pub async fn probes(Extension(state): Extension<Arc<AppState>>) -> Json<Vec<ProbeResponse>> {
+pub async fn probes(
+    Query(params): Query<GroupQueryParams>,
+    Extension(state): Extension<Arc<AppState>>,
+) -> Json<Vec<ProbeResponse>> {
     debug!("Get probes called");
 
-    let read_lock = state.probe_results.read().unwrap();
+    let config = state.config();
+    let latest = state.probe_results.latest_all();
+    let monitor_states = state.monitor_states.read().unwrap();
 
     let mut probes: Vec<ProbeResponse> = vec![];
 
-    for (key, value) in read_lock.iter() {
-        let last = value.last().unwrap();
-        let status = if last.success { "OK" } else { "FAILING" };
-
-        probes.push(ProbeResponse {
-            name: key.clone(),
-            status: status.to_owned(),
-            last_probed: last.timestamp_started,
-        })
+    for (key, last, activity) in latest {
+        if !config.in_group(&key, params.group.as_deref()) {
+            continue;
+        }
+        probes.push(
+            ProbeResponse::new(
+                key.clone(),
+                Some((last.success, last.timestamp_started)),
+                monitor_states.get(&key),
+            )
+            .with_activity(Some(&activity))
+            .with_connection(last.connection.as_ref()),
+        )
     }
 
     Json(probes)
 }
 
+pub async fn get_probe(
+    Path(name): Path<String>,
+    Extension(state): Extension<Arc<AppState>>,
+) -> Result<Json<ProbeResponse>, StatusCode> {
+    debug!("Get probe called");
+
+    let Some(probe) = state
+        .config
+        .read()
+        .unwrap()
+        .probes
+        .iter()
+        .find(|x| x.name == name)
+        .cloned()
+    else {
+        return Err(StatusCode::NOT_FOUND);
+    };
+
+    let last = state.probe_results.latest(&name);
+    let activity = state.probe_results.summary(&name);
+    let monitor_states = state.monitor_states.read().unwrap();
+    // Closed until the first run is counted
+    let circuit_breaker = state.circuit_breaker(&name).or_else(|| {
+        probe
+            .circuit_breaker
+            .as_ref()
+            .map(|_| CircuitBreaker::new(probe.schedule.interval))
+    });
+
+    let last_run = last
+        .as_ref()
+        .map(|last| (last.success, last.timestamp_started));
+
+    Ok(Json(
+        ProbeResponse::new(name.clone(), last_run, monitor_states.get(&name))
+            .with_activity(activity.as_ref())
+            .with_connection(last.as_ref().and_then(|last| last.connection.as_ref()))
+            .with_circuit_breaker(circuit_breaker),
+    ))
+}
+
 pub async fn probe_trigger(
     Path(name): Path<String>,
     Extension(state): Extension<Arc<AppState>>,
 ) -> Json<ProbeResult> {
     debug!("Probe trigger called");
 
-    let probe = &state.config.probes.iter().find(|x| x.name == name).unwrap();
+    let probe = state
+        .config
+        .read()
+        .unwrap()
+        .probes
+        .iter()
+        .find(|x| x.name == name)
+        .cloned()
+        .unwrap();
 
     probe.probe_and_store_result(state.clone()).await;
 
-    let lock = state.probe_results.read().unwrap();
-    let probe_results = lock.get(&name).unwrap();
-
-    Json(probe_results.last().unwrap().clone())
+    Json(state.probe_results.latest(&name).unwrap())
 }
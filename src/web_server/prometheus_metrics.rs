@@ -1,21 +1,165 @@
-use axum::{Extension, http::StatusCode, response::IntoResponse};
+use axum::{
+    http::{header, HeaderMap, StatusCode},
+    response::IntoResponse,
+    Extension,
+};
 use prometheus::{Encoder, Registry, TextEncoder};
 use std::sync::Arc;
 
-pub async fn metrics_handler(Extension(registry): Extension<Arc<Registry>>) -> impl IntoResponse {
-    let encoder = TextEncoder::new();
+use super::openmetrics;
+
+const EXPORTER_NOT_CONFIGURED: &str = "The Prometheus exporter is not configured. Set OTEL_METRICS_EXPORTER=prometheus and scrape /metrics on OTEL_EXPORTER_PROMETHEUS_HOST:OTEL_EXPORTER_PROMETHEUS_PORT.";
+
+// Only the Prometheus server has the registry, elsewhere `/metrics` explains how to enable it.
+// Scrapers accepting OpenMetrics get exemplars, everything else the classic text format.
+pub async fn metrics_handler(
+    registry: Option<Extension<Arc<Registry>>>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let Some(Extension(registry)) = registry else {
+        return (StatusCode::SERVICE_UNAVAILABLE, EXPORTER_NOT_CONFIGURED).into_response();
+    };
     let metric_families = registry.gather();
+    let accepts_openmetrics = headers
+        .get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .is_some_and(openmetrics::accepts);
+    if accepts_openmetrics {
+        return (
+            StatusCode::OK,
+            [("content-type", openmetrics::CONTENT_TYPE)],
+            openmetrics::encode(&metric_families),
+        )
+            .into_response();
+    }
+
+    let encoder = TextEncoder::new();
     let mut buffer = vec![];
-    
+
     match encoder.encode(&metric_families, &mut buffer) {
         Ok(_) => (
             StatusCode::OK,
             [("content-type", encoder.format_type())],
             buffer,
-        ).into_response(),
+        )
+            .into_response(),
         Err(e) => (
             StatusCode::INTERNAL_SERVER_ERROR,
             format!("Failed to encode metrics: {}", e),
-        ).into_response(),
+        )
+            .into_response(),
+    }
+}
+
+#[cfg(test)]
+mod prometheus_metrics_tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+    use axum::routing::get;
+    use axum::{Extension, Router};
+    use http_body_util::BodyExt;
+    use opentelemetry::metrics::MeterProvider;
+    use opentelemetry::KeyValue;
+    use opentelemetry_sdk::metrics::SdkMeterProvider;
+    use tower::ServiceExt;
+
+    use crate::app_state::AppState;
+    use crate::config::{Config, DurationUnit};
+    use crate::otel::metrics::Metrics;
+    use crate::web_server::app_router;
+    use crate::web_server::prometheus_metrics::metrics_handler;
+
+    #[tokio::test]
+    async fn test_metrics_without_registry_is_unavailable() {
+        let app_state = Arc::new(AppState::new(Config::default()));
+
+        let response = app_router(app_state)
+            .oneshot(Request::get("/metrics").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(StatusCode::SERVICE_UNAVAILABLE, response.status());
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert!(String::from_utf8_lossy(&body).contains("OTEL_METRICS_EXPORTER=prometheus"));
+    }
+
+    #[tokio::test]
+    async fn test_metrics_with_empty_registry() {
+        let app = Router::new()
+            .route("/metrics", get(metrics_handler))
+            .layer(Extension(Arc::new(prometheus::Registry::new())));
+
+        let response = app
+            .oneshot(Request::get("/metrics").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(StatusCode::OK, response.status());
+    }
+
+    async fn scrape(app: Router, accept: &str) -> (String, String) {
+        let response = app
+            .oneshot(
+                Request::get("/metrics")
+                    .header("accept", accept)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::OK, response.status());
+        let content_type = response.headers()["content-type"]
+            .to_str()
+            .unwrap()
+            .to_owned();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (content_type, String::from_utf8_lossy(&body).into_owned())
+    }
+
+    #[tokio::test]
+    async fn test_exemplars_only_in_openmetrics_and_only_with_a_trace_id() {
+        let registry = prometheus::Registry::new();
+        let reader = opentelemetry_prometheus::exporter()
+            .with_registry(registry.clone())
+            .build()
+            .unwrap();
+        let provider = SdkMeterProvider::builder().with_reader(reader).build();
+        let metrics = Metrics::from_meter(&provider.meter("xbp"), DurationUnit::Ms);
+        metrics.record_run_duration(
+            Duration::from_millis(42),
+            &[KeyValue::new("name", "Traced probe")],
+            Some("4bf92f3577b34da6a3ce929d0e0e4736".to_owned()),
+        );
+        metrics.record_run_duration(
+            Duration::from_millis(42),
+            &[KeyValue::new("name", "Untraced probe")],
+            None,
+        );
+        let app = Router::new()
+            .route("/metrics", get(metrics_handler))
+            .layer(Extension(Arc::new(registry)));
+
+        let (content_type, openmetrics) =
+            scrape(app.clone(), "application/openmetrics-text;version=1.0.0").await;
+        assert!(content_type.starts_with("application/openmetrics-text"));
+        assert!(openmetrics.ends_with("# EOF\n"));
+        let exemplar_lines = openmetrics
+            .lines()
+            .filter(|line| line.contains(" # {"))
+            .collect::<Vec<_>>();
+        assert_eq!(1, exemplar_lines.len(), "{}", openmetrics);
+        assert!(exemplar_lines[0].starts_with("duration_milliseconds_bucket{"));
+        assert!(exemplar_lines[0].contains("name=\"Traced probe\""));
+        assert!(exemplar_lines[0].contains("le=\"50\""));
+        assert!(exemplar_lines[0].contains("# {trace_id=\"4bf92f3577b34da6a3ce929d0e0e4736\"} 42 "));
+
+        let (content_type, text) = scrape(app, "text/plain;version=0.0.4").await;
+        assert!(content_type.starts_with("text/plain"));
+        assert!(text.contains("name=\"Traced probe\""));
+        assert!(!text.contains("trace_id"));
+        assert!(!text.contains("# EOF"));
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,814 @@
+use axum::{extract::Query, http::StatusCode, Extension, Json};
+use futures::future::join_all;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tracing::{debug, warn};
+
+use crate::app_state::AppState;
+use crate::config::{
+    load_config, load_config_from_remote_url, remote_config_url, Config, Settings,
+};
+use crate::probe::expectations::has_status_expectation;
+use crate::probe::model::{ProbeExpectation, StatusPattern, Step};
+use crate::probe::probe_logic::Monitorable;
+use crate::self_alerts::{raise, SelfAlertKind};
+use crate::wait_healthy::{gating_config, wait_report};
+
+use super::model::{
+    matches_group, GroupQueryParams, MonitorInfo, MonitorsResponse, ReloadQueryParams,
+    ReloadRequest, ReloadResponse, ReloadSource, ResolvedConfigResponse, ResolvedMonitor,
+    ResolvedStory, SuccessCriteria, SuccessCriteriaSource, VerificationOutcome,
+};
+
+// Reads the config file again and restarts monitoring with it, behind `require_reload_token`.
+// `?source=local` or `?source=remote` pick where the config is read from, see `ReloadSource`.
+pub async fn reload(
+    Extension(state): Extension<Arc<AppState>>,
+    Query(params): Query<ReloadQueryParams>,
+    request: Option<Json<ReloadRequest>>,
+) -> Result<Json<ReloadResponse>, (StatusCode, String)> {
+    debug!("Reload called");
+
+    let config_path = state.config_path.clone();
+    let no_config_path = || {
+        (
+            StatusCode::CONFLICT,
+            "No config file to reload from".to_owned(),
+        )
+    };
+    let loaded = match params.source {
+        None => load_config(config_path.ok_or_else(no_config_path)?).await,
+        Some(ReloadSource::Local) => {
+            let config_path = config_path.ok_or_else(no_config_path)?;
+            if remote_config_url(&config_path).is_some() {
+                return Err((
+                    StatusCode::CONFLICT,
+                    "The config was loaded from a url, there is no local file to reload from"
+                        .to_owned(),
+                ));
+            }
+            load_config(config_path).await
+        }
+        // A one-off, later reloads without `source` read the config path again
+        Some(ReloadSource::Remote) => {
+            let url = request
+                .and_then(|Json(request)| request.url)
+                .or_else(|| {
+                    config_path
+                        .as_deref()
+                        .and_then(remote_config_url)
+                        .map(str::to_owned)
+                })
+                .ok_or((
+                    StatusCode::BAD_REQUEST,
+                    "`source=remote` needs a `url` in the request body".to_owned(),
+                ))?;
+            load_config_from_remote_url(&url).await
+        }
+    };
+    let config = loaded.map_err(|e| {
+        warn!("Reload failed, keeping the running config: {}", e);
+        reload_failed(&state, e.to_string());
+        (StatusCode::BAD_REQUEST, e.to_string())
+    })?;
+    state.validate_reload(&config).map_err(|e| {
+        warn!(
+            "Reload failed, runtime-added monitors don't fit the new config: {}",
+            e
+        );
+        let message = format!("Runtime-added monitors don't fit the new config: {}", e);
+        reload_failed(&state, message.clone());
+        (StatusCode::BAD_REQUEST, message)
+    })?;
+    // Verified before the new config is applied, so a strict reload that fails leaves the running
+    // monitors and their history untouched
+    let verification = if params.strict || config.settings.verify_on_reload {
+        Some(verify_monitors(&state.config(), &config).await)
+    } else {
+        None
+    };
+    let failed: Vec<_> = verification
+        .iter()
+        .flatten()
+        .filter(|outcome| !outcome.passed)
+        .collect();
+    if params.strict && !failed.is_empty() {
+        warn!("Reload failed verification, keeping the running config");
+        let details: Vec<_> = failed
+            .iter()
+            .map(|outcome| {
+                format!(
+                    "{}: {}",
+                    outcome.name,
+                    outcome.error.as_deref().unwrap_or_default()
+                )
+            })
+            .collect();
+        let message = format!(
+            "Verification failed, keeping the running config:\n{}",
+            details.join("\n")
+        );
+        reload_failed(&state, message.clone());
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, message));
+    }
+    let diff = state.reload(config).await;
+    // Counted after the reload, runtime-added monitors are carried over into the new config
+    let (probes, stories) = {
+        let config = state.config();
+        (config.probes.len(), config.stories.len())
+    };
+
+    Ok(Json(ReloadResponse {
+        probes,
+        stories,
+        added: diff.added,
+        removed: diff.removed,
+        groups: diff.groups,
+        verification,
+    }))
+}
+
+fn reload_failed(state: &AppState, message: String) {
+    state.metrics.config_reload_errors.add(1, &[]);
+    raise(state, SelfAlertKind::ConfigReload, message);
+}
+
+// One run of every monitor `config` adds or changes compared to `current`. The runs go to a
+// scratch state without alerts, so they never reach history, incidents or alerting.
+async fn verify_monitors(current: &Config, config: &Config) -> Vec<VerificationOutcome> {
+    // Monitor names are unique across probes and stories
+    let previous: HashMap<&str, serde_json::Value> = current
+        .probes
+        .iter()
+        .map(|probe| (probe.name.as_str(), serde_json::to_value(probe)))
+        .chain(
+            current
+                .stories
+                .iter()
+                .map(|story| (story.name.as_str(), serde_json::to_value(story))),
+        )
+        .filter_map(|(name, monitor)| Some((name, monitor.ok()?)))
+        .collect();
+    let changed = |name: &str, monitor: serde_json::Result<serde_json::Value>| {
+        monitor.ok().as_ref() != previous.get(name)
+    };
+    let mut candidates = config.clone();
+    candidates
+        .probes
+        .retain(|probe| changed(&probe.name, serde_json::to_value(probe)));
+    candidates
+        .stories
+        .retain(|story| changed(&story.name, serde_json::to_value(story)));
+
+    let scratch = Arc::new(AppState::new(gating_config(candidates, &[])));
+    let config = scratch.config();
+    let probes = config
+        .probes
+        .iter()
+        .map(|probe| probe.probe_and_store_result(scratch.clone()));
+    let stories = config
+        .stories
+        .iter()
+        .map(|story| story.probe_and_store_result(scratch.clone()));
+    futures::join!(join_all(probes), join_all(stories));
+
+    let report = wait_report(&scratch);
+    let passed = report.passed.into_iter().map(|name| VerificationOutcome {
+        name,
+        passed: true,
+        error: None,
+    });
+    let failed = report
+        .failing
+        .into_iter()
+        .map(|monitor| VerificationOutcome {
+            name: monitor.name,
+            passed: false,
+            error: monitor.error,
+        });
+    passed.chain(failed).collect()
+}
+
+pub async fn monitors(
+    Query(params): Query<GroupQueryParams>,
+    Extension(state): Extension<Arc<AppState>>,
+) -> Json<MonitorsResponse> {
+    debug!("Get monitors called");
+    monitors_inner(state, &params.group).await
+}
+
+// Alias of `/-/monitors` kept for tools migrating from the probe-only listing
+pub async fn probes_alias(
+    Query(params): Query<GroupQueryParams>,
+    Extension(state): Extension<Arc<AppState>>,
+) -> Json<MonitorsResponse> {
+    debug!("Get probes alias called");
+    monitors_inner(state, &params.group).await
+}
+
+async fn monitors_inner(state: Arc<AppState>, group: &Option<String>) -> Json<MonitorsResponse> {
+    let config = state.config();
+    let probes = config
+        .probes
+        .iter()
+        .filter(|probe| matches_group(&probe.group, group))
+        .map(|probe| MonitorInfo {
+            name: probe.name.clone(),
+            interval: probe.schedule.interval,
+            tags: probe.tags.clone(),
+            runtime_added: probe.runtime_added,
+            referenced_probes: vec![],
+            deferred_until: state.deferred_until(&probe.name),
+        })
+        .collect();
+    let stories = config
+        .stories
+        .iter()
+        .filter(|story| matches_group(&story.group, group))
+        .map(|story| MonitorInfo {
+            name: story.name.clone(),
+            interval: story.schedule.interval,
+            tags: story.tags.clone(),
+            runtime_added: story.runtime_added,
+            referenced_probes: story.referenced_probes(&config.probes),
+            deferred_until: None,
+        })
+        .collect();
+
+    Json(MonitorsResponse { probes, stories })
+}
+
+// The config as the monitors see it, with the effective success criteria of every probe and step
+pub async fn resolved_config(
+    Extension(state): Extension<Arc<AppState>>,
+) -> Json<ResolvedConfigResponse> {
+    debug!("Get resolved config called");
+    let config = state.config();
+    let settings = &config.settings;
+    let probes = config
+        .probes
+        .iter()
+        .map(|probe| ResolvedMonitor {
+            name: probe.name.clone(),
+            schedule: Some(probe.schedule.clone()),
+            success_criteria: success_criteria(
+                &probe.expectations,
+                probe.success_statuses.as_deref(),
+                settings,
+            ),
+            expectations: probe.expectations.clone(),
+            options: probe
+                .with
+                .as_ref()
+                .map(|options| options.resolved(probe.sensitive)),
+        })
+        .collect();
+    let stories = config
+        .stories
+        .iter()
+        .map(|story| {
+            let resolved_steps = |steps: &[Step]| -> Vec<ResolvedMonitor> {
+                steps
+                    .iter()
+                    .map(|step| ResolvedMonitor {
+                        name: step.name.clone(),
+                        schedule: None,
+                        success_criteria: success_criteria(&step.expectations, None, settings),
+                        expectations: step.expectations.clone(),
+                        options: step
+                            .with
+                            .as_ref()
+                            .map(|options| options.resolved(step.sensitive)),
+                    })
+                    .collect()
+            };
+            ResolvedStory {
+                name: story.name.clone(),
+                base_url: story.base_url.clone(),
+                schedule: story.schedule.clone(),
+                setup: resolved_steps(story.setup.as_deref().unwrap_or_default()),
+                steps: resolved_steps(&story.steps),
+                teardown: resolved_steps(story.teardown.as_deref().unwrap_or_default()),
+            }
+        })
+        .collect();
+
+    Json(ResolvedConfigResponse {
+        settings: settings.clone(),
+        probes,
+        stories,
+    })
+}
+
+fn success_criteria(
+    expectations: &Option<Vec<ProbeExpectation>>,
+    own_statuses: Option<&[StatusPattern]>,
+    settings: &Settings,
+) -> SuccessCriteria {
+    let (source, statuses) = if has_status_expectation(expectations) {
+        (SuccessCriteriaSource::Expectations, None)
+    } else if let Some(statuses) = own_statuses {
+        (SuccessCriteriaSource::Probe, Some(statuses.to_vec()))
+    } else if let Some(statuses) = &settings.default_success_statuses {
+        (SuccessCriteriaSource::Settings, Some(statuses.clone()))
+    } else {
+        (SuccessCriteriaSource::AnyResponse, None)
+    };
+    SuccessCriteria { source, statuses }
+}
+
+#[cfg(test)]
+mod reload_tests {
+    use std::collections::{BTreeMap, HashMap};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use crate::app_state::AppState;
+    use crate::config::{Config, Settings};
+    use crate::errors::AlertChannel;
+    use crate::otel::metrics::MetricsState;
+    use crate::probe::model::{ProbeAlert, StatusPattern};
+    use crate::test_utils::metrics_test_utils::counter_value;
+    use crate::test_utils::probe_test_utils::probe_get_with_expected_status;
+    use crate::web_server::app_router;
+    use crate::web_server::model::{MonitorsResponse, ReloadResponse};
+
+    const RELOAD_TOKEN: &str = "test-reload-token";
+
+    async fn post_reload(app_state: Arc<AppState>, token: &str) -> axum::response::Response {
+        std::env::set_var("XBP_RELOAD_TOKEN", RELOAD_TOKEN);
+        app_router(app_state)
+            .oneshot(
+                Request::post("/-/reload")
+                    .header("Authorization", format!("Bearer {}", token))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap()
+    }
+
+    async fn get_monitors(uri: &str) -> MonitorsResponse {
+        let probe = probe_get_with_expected_status(
+            reqwest::StatusCode::OK,
+            "http://localhost/health".to_owned(),
+            "".to_owned(),
+        );
+        let app_state = Arc::new(AppState::new(Config {
+            probes: vec![probe],
+            stories: vec![],
+            ..Default::default()
+        }));
+
+        let response = app_router(app_state)
+            .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::OK, response.status());
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_monitors_lists_configured_probes() {
+        let monitors = get_monitors("/-/monitors").await;
+
+        assert_eq!(1, monitors.probes.len());
+        assert_eq!("Test probe", monitors.probes[0].name);
+        assert!(monitors.stories.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_monitors_lists_probes_referenced_by_stories() {
+        let probe = probe_get_with_expected_status(
+            reqwest::StatusCode::OK,
+            "http://localhost/health".to_owned(),
+            "".to_owned(),
+        );
+        let story = serde_yaml::from_str(
+            r#"
+name: login
+schedule: { initial_delay: 0, interval: 60 }
+steps:
+  - name: health
+    url: http://localhost/health
+    http_method: get
+  - name: login
+    url: http://localhost/login
+    http_method: POST
+"#,
+        )
+        .unwrap();
+        let app_state = Arc::new(AppState::new(Config {
+            probes: vec![probe],
+            stories: vec![story],
+            ..Default::default()
+        }));
+
+        let response = app_router(app_state)
+            .oneshot(Request::get("/-/monitors").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let monitors: MonitorsResponse = serde_json::from_slice(&body).unwrap();
+        let raw: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+        assert_eq!(vec!["Test probe"], monitors.stories[0].referenced_probes);
+        assert!(monitors.probes[0].referenced_probes.is_empty());
+        assert_eq!("1m", raw["stories"][0]["interval"]);
+    }
+
+    #[tokio::test]
+    async fn test_monitors_filters_by_group() {
+        let probe = |name: &str, group: Option<&str>| {
+            let mut probe = probe_get_with_expected_status(
+                reqwest::StatusCode::OK,
+                "http://localhost/health".to_owned(),
+                "".to_owned(),
+            );
+            probe.name = name.to_owned();
+            probe.group = group.map(str::to_owned);
+            probe
+        };
+        let app_state = Arc::new(AppState::new(Config {
+            probes: vec![
+                probe("checkout", Some("team-a")),
+                probe("search", Some("team-b")),
+                probe("shared", None),
+            ],
+            ..Default::default()
+        }));
+
+        let response = app_router(app_state)
+            .oneshot(
+                Request::get("/-/monitors?group=team-a")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let monitors: MonitorsResponse = serde_json::from_slice(&body).unwrap();
+
+        let names: Vec<_> = monitors.probes.iter().map(|probe| &probe.name).collect();
+        assert_eq!(vec!["checkout"], names);
+    }
+
+    #[tokio::test]
+    async fn test_resolved_config_shows_success_criteria() {
+        let mut explicit = probe_get_with_expected_status(
+            reqwest::StatusCode::OK,
+            "http://localhost/health".to_owned(),
+            "".to_owned(),
+        );
+        explicit.name = "explicit".to_owned();
+        explicit.with = None;
+        let mut defaulted = explicit.clone();
+        defaulted.name = "defaulted".to_owned();
+        defaulted.expectations = None;
+        let mut overridden = defaulted.clone();
+        overridden.name = "overridden".to_owned();
+        overridden.success_statuses = Some(vec![StatusPattern::Exact(302)]);
+        let app_state = Arc::new(AppState::new(Config {
+            settings: Settings {
+                default_success_statuses: Some(vec![
+                    StatusPattern::Class(2),
+                    StatusPattern::Class(3),
+                ]),
+                ..Default::default()
+            },
+            probes: vec![explicit, defaulted, overridden],
+            ..Default::default()
+        }));
+
+        let response = app_router(app_state)
+            .oneshot(Request::get("/-/config").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let config: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        // Only the success criteria are compared, the probes also carry their schedule and expectations
+        let success_criteria: Vec<serde_json::Value> = config["probes"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|probe| {
+                serde_json::json!({
+                    "name": probe["name"],
+                    "success_criteria": probe["success_criteria"],
+                })
+            })
+            .collect();
+
+        assert_eq!(
+            vec![
+                serde_json::json!({ "name": "explicit", "success_criteria": { "source": "expectations" } }),
+                serde_json::json!({ "name": "defaulted", "success_criteria": { "source": "settings", "statuses": ["2xx", "3xx"] } }),
+                serde_json::json!({ "name": "overridden", "success_criteria": { "source": "probe", "statuses": ["302"] } }),
+            ],
+            success_criteria
+        );
+    }
+
+    #[tokio::test]
+    async fn test_probes_alias_matches_monitors() {
+        let monitors = get_monitors("/-/monitors").await;
+        let alias = get_monitors("/-/probes").await;
+
+        assert_eq!(
+            serde_json::to_value(monitors).unwrap(),
+            serde_json::to_value(alias).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reload_rejects_invalid_token() {
+        let app_state = Arc::new(AppState::new(Config::default()));
+
+        let response = post_reload(app_state, "wrong").await;
+
+        assert_eq!(StatusCode::UNAUTHORIZED, response.status());
+    }
+
+    #[tokio::test]
+    async fn test_reload_counts_successes_and_errors() {
+        let config_path = std::env::temp_dir().join(format!("xbp-{}.yaml", uuid::Uuid::new_v4()));
+        let metrics_state = MetricsState::for_testing();
+        let app_state = Arc::new(
+            AppState::with_metrics(Config::default(), metrics_state.metrics())
+                .with_config_path(&config_path),
+        );
+
+        std::fs::write(&config_path, "probes: not a list").unwrap();
+        let failed = post_reload(app_state.clone(), RELOAD_TOKEN).await;
+        std::fs::write(&config_path, "probes: []").unwrap();
+        let succeeded = post_reload(app_state.clone(), RELOAD_TOKEN).await;
+        std::fs::remove_file(&config_path).unwrap();
+
+        assert_eq!(StatusCode::BAD_REQUEST, failed.status());
+        assert_eq!(StatusCode::OK, succeeded.status());
+        let metrics = metrics_state.collect().unwrap();
+        assert_eq!(Some(1), counter_value(&metrics, "config_reloads", &[]));
+        assert_eq!(
+            Some(1),
+            counter_value(&metrics, "config_reload_errors", &[])
+        );
+        app_state.stop_monitoring();
+    }
+
+    #[tokio::test]
+    async fn test_reload_reads_config_file_again() {
+        let config_path = std::env::temp_dir().join(format!("xbp-{}.yaml", uuid::Uuid::new_v4()));
+        std::fs::write(
+            &config_path,
+            r#"
+probes:
+  - name: reloaded
+    url: http://localhost/health
+    http_method: GET
+    group: team-a
+    schedule:
+      initial_delay: 3600
+      interval: 60
+"#,
+        )
+        .unwrap();
+        let app_state = Arc::new(AppState::new(Config::default()).with_config_path(&config_path));
+
+        let response = post_reload(app_state.clone(), RELOAD_TOKEN).await;
+        std::fs::remove_file(&config_path).unwrap();
+
+        assert_eq!(StatusCode::OK, response.status());
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let reload: ReloadResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(1, reload.probes);
+        assert_eq!(vec!["reloaded".to_owned()], reload.added);
+        assert_eq!(vec!["team-a".to_owned()], reload.groups);
+        assert_eq!("reloaded", app_state.config.read().unwrap().probes[0].name);
+        app_state.stop_monitoring();
+    }
+
+    #[tokio::test]
+    async fn test_reload_lists_every_empty_substitution() {
+        std::env::remove_var("TEST_RELOAD_UNSET_HOST");
+        std::env::remove_var("TEST_RELOAD_UNSET_TOKEN");
+        let config_path = std::env::temp_dir().join(format!("xbp-{}.yaml", uuid::Uuid::new_v4()));
+        std::fs::write(
+            &config_path,
+            r#"
+probes:
+  - name: api
+    url: ${{ env.TEST_RELOAD_UNSET_HOST }}
+    schedule: { initial_delay: 3600, interval: 60 }
+    with:
+      headers:
+        X-Token: ${{ env.TEST_RELOAD_UNSET_TOKEN }}
+"#,
+        )
+        .unwrap();
+        let app_state = Arc::new(AppState::new(Config::default()).with_config_path(&config_path));
+
+        let response = post_reload(app_state.clone(), RELOAD_TOKEN).await;
+        std::fs::remove_file(&config_path).unwrap();
+
+        assert_eq!(StatusCode::BAD_REQUEST, response.status());
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(
+            "Invalid config: probe 'api': url is empty because environment variable TEST_RELOAD_UNSET_HOST is not set; probe 'api': header X-Token is empty because environment variable TEST_RELOAD_UNSET_TOKEN is not set, list it in `with.allow_empty` if that's intended",
+            String::from_utf8_lossy(&body)
+        );
+        assert!(app_state.config.read().unwrap().probes.is_empty());
+    }
+
+    async fn post_reload_from(
+        app_state: Arc<AppState>,
+        source: &str,
+        body: Option<serde_json::Value>,
+    ) -> axum::response::Response {
+        std::env::set_var("XBP_RELOAD_TOKEN", RELOAD_TOKEN);
+        let request = Request::post(format!("/-/reload?source={}", source))
+            .header("Authorization", format!("Bearer {}", RELOAD_TOKEN))
+            .header("Content-Type", "application/json");
+        let body = body.map_or_else(Body::empty, |body| Body::from(body.to_string()));
+        app_router(app_state)
+            .oneshot(request.body(body).unwrap())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_reload_from_remote_url_in_body() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/xbp.yaml"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                "probes: [{ name: remote, url: http://localhost/health, http_method: GET, schedule: { initial_delay: 3600, interval: 60 } }]",
+            ))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        let config_path = std::env::temp_dir().join(format!("xbp-{}.yaml", uuid::Uuid::new_v4()));
+        let app_state = Arc::new(AppState::new(Config::default()).with_config_path(&config_path));
+
+        let missing_url = post_reload_from(app_state.clone(), "remote", None).await;
+        let response = post_reload_from(
+            app_state.clone(),
+            "remote",
+            Some(serde_json::json!({ "url": format!("{}/xbp.yaml", mock_server.uri()) })),
+        )
+        .await;
+
+        assert_eq!(StatusCode::BAD_REQUEST, missing_url.status());
+        assert_eq!(StatusCode::OK, response.status());
+        assert_eq!("remote", app_state.config().probes[0].name);
+        assert_eq!(Some(config_path), app_state.config_path);
+        app_state.stop_monitoring();
+    }
+
+    #[tokio::test]
+    async fn test_reload_verifies_added_and_changed_monitors() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/stable"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0)
+            .mount(&mock_server)
+            .await;
+        let stable = format!(
+            "  - {{ name: stable, url: {}/stable, http_method: GET, schedule: {{ initial_delay: 3600, interval: 60 }} }}",
+            mock_server.uri()
+        );
+        let config_path = std::env::temp_dir().join(format!("xbp-{}.yaml", uuid::Uuid::new_v4()));
+        std::fs::write(&config_path, format!("probes:\n{}\n", stable)).unwrap();
+        let config = crate::config::load_config(&config_path).await.unwrap();
+        let app_state = Arc::new(AppState::new(config).with_config_path(&config_path));
+        std::fs::write(
+            &config_path,
+            format!(
+                "settings: {{ verify_on_reload: true }}\nprobes:\n{}\n  - {{ name: typo, url: http://127.0.0.1:1/health, http_method: GET, schedule: {{ initial_delay: 3600, interval: 60 }} }}\n",
+                stable
+            ),
+        )
+        .unwrap();
+
+        let strict = post_reload_from(app_state.clone(), "local&strict=true", None).await;
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, strict.status());
+        let body = strict.into_body().collect().await.unwrap().to_bytes();
+        assert!(String::from_utf8_lossy(&body).contains("typo: "));
+        assert_eq!(1, app_state.config().probes.len());
+
+        let response = post_reload_from(app_state.clone(), "local", None).await;
+        std::fs::remove_file(&config_path).unwrap();
+
+        assert_eq!(StatusCode::OK, response.status());
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let reload: ReloadResponse = serde_json::from_slice(&body).unwrap();
+        let verification = reload.verification.unwrap();
+        assert_eq!(1, verification.len());
+        assert_eq!("typo", verification[0].name);
+        assert!(!verification[0].passed);
+        assert!(verification[0].error.is_some());
+        assert_eq!(2, app_state.config().probes.len());
+        assert!(!app_state.probe_results.contains("typo"));
+        app_state.stop_monitoring();
+    }
+
+    #[tokio::test]
+    async fn test_local_reload_needs_local_config_file() {
+        let app_state = Arc::new(
+            AppState::new(Config::default())
+                .with_config_path("https://config.example.com/xbp.yaml"),
+        );
+
+        let response = post_reload_from(app_state, "local", None).await;
+
+        assert_eq!(StatusCode::CONFLICT, response.status());
+    }
+
+    #[tokio::test]
+    async fn test_reload_rejects_renamed_channel_of_runtime_monitor() {
+        let config_path = std::env::temp_dir().join(format!("xbp-{}.yaml", uuid::Uuid::new_v4()));
+        std::fs::write(&config_path, "alert_channels: { paging: { type: stdout } }").unwrap();
+        let mut probe = probe_get_with_expected_status(
+            reqwest::StatusCode::OK,
+            "http://localhost/health".to_owned(),
+            "".to_owned(),
+        );
+        probe.runtime_added = true;
+        probe.alerts = Some(vec![ProbeAlert {
+            channel: Some("oncall".to_owned()),
+            ..Default::default()
+        }]);
+        let app_state = Arc::new(
+            AppState::new(Config {
+                probes: vec![probe],
+                alert_channels: BTreeMap::from([(
+                    "oncall".to_owned(),
+                    ProbeAlert {
+                        channel_type: Some(AlertChannel::Stdout),
+                        ..Default::default()
+                    },
+                )]),
+                ..Default::default()
+            })
+            .with_config_path(&config_path),
+        );
+
+        let response = post_reload(app_state.clone(), RELOAD_TOKEN).await;
+        std::fs::remove_file(&config_path).unwrap();
+
+        assert_eq!(StatusCode::BAD_REQUEST, response.status());
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert!(String::from_utf8_lossy(&body)
+            .contains("unknown alert channel 'oncall', known channels: paging"));
+        assert!(app_state
+            .config
+            .read()
+            .unwrap()
+            .alert_channels
+            .contains_key("oncall"));
+    }
+
+    #[tokio::test]
+    async fn test_resolved_config_shows_typed_options() {
+        let mut probe = probe_get_with_expected_status(
+            reqwest::StatusCode::OK,
+            "http://localhost/health".to_owned(),
+            "{}".to_owned(),
+        );
+        let options = probe.with.as_mut().unwrap();
+        options.timeout_seconds = Some(Duration::from_secs(5));
+        options.headers = Some(HashMap::from([(
+            "Authorization".to_owned(),
+            "Bearer secret".to_owned(),
+        )]));
+        let app_state = Arc::new(AppState::new(Config {
+            probes: vec![probe],
+            ..Default::default()
+        }));
+
+        let response = app_router(app_state)
+            .oneshot(Request::get("/-/config").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let config: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+        assert_eq!(
+            serde_json::json!({
+                "headers": { "Authorization": "<redacted>" },
+                "body": "{}",
+                "timeout": "5s",
+            }),
+            config["probes"][0]["options"]
+        );
+    }
+}
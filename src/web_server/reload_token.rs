@@ -0,0 +1,204 @@
+use axum::{
+    extract::Request,
+    http::{header::AUTHORIZATION, HeaderMap, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+
+// Config changes are only possible when one of these is configured. `XBP_RELOAD_TOKENS` is a comma
+// separated list accepting each of the tokens, so a new token can be rolled out before the old one
+// is removed. `XBP_RELOAD_TOKEN` is a single token and is accepted alongside the list.
+pub const RELOAD_TOKEN_ENV: &str = "XBP_RELOAD_TOKEN";
+pub const RELOAD_TOKENS_ENV: &str = "XBP_RELOAD_TOKENS";
+
+// Guards every route that changes the running config, requires `Authorization: Bearer <token>`.
+// The variables are read per request, so rotating tokens doesn't need a restart.
+pub async fn require_reload_token(
+    request: Request,
+    next: Next,
+) -> Result<Response, (StatusCode, String)> {
+    let list = std::env::var(RELOAD_TOKENS_ENV).unwrap_or_default();
+    let single = std::env::var(RELOAD_TOKEN_ENV).unwrap_or_default();
+    check_reload_token(request.headers(), &configured_tokens(&list, &single))?;
+    Ok(next.run(request).await)
+}
+
+fn check_reload_token(headers: &HeaderMap, tokens: &[&str]) -> Result<(), (StatusCode, String)> {
+    if tokens.is_empty() {
+        return Err((
+            StatusCode::FORBIDDEN,
+            format!(
+                "Config changes are disabled, set {} or {} to enable them",
+                RELOAD_TOKENS_ENV, RELOAD_TOKEN_ENV
+            ),
+        ));
+    }
+    let provided = headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .unwrap_or_default();
+    if !token_matches(provided, tokens) {
+        return Err((StatusCode::UNAUTHORIZED, "Invalid reload token".to_owned()));
+    }
+    Ok(())
+}
+
+// Blank entries are ignored, no tokens at all disables config changes
+fn configured_tokens<'a>(list: &'a str, single: &'a str) -> Vec<&'a str> {
+    list.split(',')
+        .chain(std::iter::once(single))
+        .map(str::trim)
+        .filter(|token| !token.is_empty())
+        .collect()
+}
+
+// Compares against every token without stopping at the first match, in constant time per token.
+// `ct_eq` returns early on slices of different lengths, so digests are compared rather than the
+// tokens themselves to keep the length of the configured tokens from showing in response times.
+fn token_matches(provided: &str, tokens: &[&str]) -> bool {
+    let provided = Sha256::digest(provided.as_bytes());
+    tokens.iter().fold(false, |matched, token| {
+        let token = Sha256::digest(token.as_bytes());
+        matched | bool::from(provided.as_slice().ct_eq(token.as_slice()))
+    })
+}
+
+#[cfg(test)]
+mod reload_token_tests {
+    use std::sync::Arc;
+
+    use axum::body::Body;
+    use axum::http::{HeaderMap, HeaderValue, Request, StatusCode};
+    use tower::ServiceExt;
+
+    use crate::app_state::AppState;
+    use crate::config::Config;
+    use crate::web_server::app_router;
+    use crate::web_server::reload_token::{check_reload_token, configured_tokens, token_matches};
+
+    fn bearer(token: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "Authorization",
+            HeaderValue::from_str(&format!("Bearer {}", token)).unwrap(),
+        );
+        headers
+    }
+
+    #[test]
+    fn test_each_of_multiple_tokens_is_accepted() {
+        let configured = configured_tokens("new-token, old-token", "legacy-token");
+
+        assert!(check_reload_token(&bearer("new-token"), &configured).is_ok());
+        assert!(check_reload_token(&bearer("old-token"), &configured).is_ok());
+        assert!(check_reload_token(&bearer("legacy-token"), &configured).is_ok());
+        assert_eq!(
+            StatusCode::UNAUTHORIZED,
+            check_reload_token(&bearer("other-token"), &configured)
+                .unwrap_err()
+                .0
+        );
+    }
+
+    #[test]
+    fn test_token_comparison_is_exact() {
+        let tokens = ["s3cret-token"];
+
+        assert!(token_matches("s3cret-token", &tokens));
+        for provided in [
+            "",
+            "s3cret",
+            "s3cret-token ",
+            "s3cret-tokeN",
+            "s3cret-token-and-more",
+        ] {
+            assert!(!token_matches(provided, &tokens), "{:?}", provided);
+        }
+    }
+
+    #[test]
+    fn test_single_token_is_not_split() {
+        let configured = configured_tokens("", "first,second");
+
+        assert_eq!(vec!["first,second"], configured);
+        assert!(check_reload_token(&bearer("first,second"), &configured).is_ok());
+        assert!(check_reload_token(&bearer("first"), &configured).is_err());
+    }
+
+    #[test]
+    fn test_empty_tokens_never_allow_anything() {
+        for (list, single) in [("", ""), (" ", " "), (",", ""), (" , ", " ")] {
+            let configured = configured_tokens(list, single);
+            assert_eq!(
+                StatusCode::FORBIDDEN,
+                check_reload_token(&bearer(""), &configured).unwrap_err().0,
+                "{:?} {:?}",
+                list,
+                single
+            );
+            assert_eq!(
+                StatusCode::FORBIDDEN,
+                check_reload_token(&HeaderMap::new(), &configured)
+                    .unwrap_err()
+                    .0,
+                "{:?} {:?}",
+                list,
+                single
+            );
+        }
+        assert_eq!(
+            StatusCode::UNAUTHORIZED,
+            check_reload_token(&bearer(""), &configured_tokens("token,", ""))
+                .unwrap_err()
+                .0
+        );
+    }
+
+    #[tokio::test]
+    async fn test_protected_routes_require_token() {
+        let app_state = Arc::new(AppState::new(Config::default()));
+        let protected = [
+            ("POST", "/-/reload"),
+            ("POST", "/probes"),
+            ("DELETE", "/probes/api"),
+            ("POST", "/stories"),
+            ("DELETE", "/stories/checkout"),
+            ("POST", "/-/probes/api/enable"),
+            ("POST", "/-/probes/api/disable"),
+            ("GET", "/probes/api/trigger"),
+            ("GET", "/stories/checkout/trigger"),
+            (
+                "POST",
+                "/incidents/00000000-0000-0000-0000-000000000000/ack",
+            ),
+            ("POST", "/-/alerts/test"),
+            ("POST", "/-/reports/weekly/run"),
+        ];
+
+        for (method, uri) in protected {
+            let response = app_router(app_state.clone())
+                .oneshot(
+                    Request::builder()
+                        .method(method)
+                        .uri(uri)
+                        .header("Authorization", "Bearer wrong-token")
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+
+            // Rejected before the handler runs: 403 while unset, 401 for a wrong token
+            assert!(
+                [StatusCode::UNAUTHORIZED, StatusCode::FORBIDDEN].contains(&response.status()),
+                "{} {} returned {}",
+                method,
+                uri,
+                response.status()
+            );
+        }
+    }
+}
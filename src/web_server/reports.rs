@@ -0,0 +1,142 @@
+use axum::{extract::Path, http::StatusCode, Extension, Json};
+use chrono::Utc;
+use std::sync::Arc;
+use tracing::{debug, warn};
+
+use crate::{
+    app_state::AppState,
+    reports::{schedule::ParsedSchedule, summary::run_report},
+};
+
+use super::model::{AlertFailure, AlertTestResult, ReportRunResponse};
+
+// Runs a report on demand over one schedule interval ending now, without affecting the
+// added/removed monitors of the next scheduled run
+pub async fn run_report_now(
+    Path(name): Path<String>,
+    Extension(state): Extension<Arc<AppState>>,
+) -> Result<Json<ReportRunResponse>, StatusCode> {
+    debug!("Run report called");
+
+    let report = state
+        .config
+        .read()
+        .unwrap()
+        .reports
+        .iter()
+        .find(|report| report.name == name)
+        .cloned()
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    let schedule = ParsedSchedule::parse(&report.schedule).map_err(|e| {
+        warn!("Can't run report {}: {}", name, e);
+        StatusCode::UNPROCESSABLE_ENTITY
+    })?;
+    let period_end = Utc::now();
+    let period_start = schedule
+        .period_ending(period_end)
+        .ok_or(StatusCode::UNPROCESSABLE_ENTITY)?;
+
+    let run = run_report(&state, &report, period_start, period_end, false).await;
+
+    Ok(Json(ReportRunResponse {
+        report: run.text,
+        alerts: run
+            .deliveries
+            .into_iter()
+            .map(|(channel, result)| AlertTestResult {
+                channel,
+                success: result.is_ok(),
+                error: result.err().map(|err| AlertFailure {
+                    kind: err.kind().to_owned(),
+                    status_code: err.status_code(),
+                    message: err.to_string(),
+                }),
+            })
+            .collect(),
+    }))
+}
+
+#[cfg(test)]
+mod reports_tests {
+    use std::sync::Arc;
+
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+    use wiremock::matchers::{body_partial_json, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use crate::app_state::AppState;
+    use crate::config::Config;
+    use crate::probe::model::ProbeAlert;
+    use crate::reports::model::{Report, ReportSchedule};
+    use crate::web_server::app_router;
+    use crate::web_server::model::ReportRunResponse;
+
+    const RELOAD_TOKEN: &str = "test-reload-token";
+
+    // The route is token protected, see `require_reload_token`
+    fn authorized_post(uri: &str) -> Request<Body> {
+        std::env::set_var("XBP_RELOAD_TOKEN", RELOAD_TOKEN);
+        Request::post(uri)
+            .header("Authorization", format!("Bearer {}", RELOAD_TOKEN))
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    fn app_state(alert_url: String) -> Arc<AppState> {
+        Arc::new(AppState::new(Config {
+            reports: vec![Report {
+                name: "weekly".to_owned(),
+                schedule: ReportSchedule {
+                    cron: "0 8 * * Mon".to_owned(),
+                    timezone: Some("Europe/Amsterdam".to_owned()),
+                },
+                alerts: vec![ProbeAlert {
+                    url: alert_url,
+                    ..Default::default()
+                }],
+                tags: None,
+                template: Some("{{ report_name }}: {{ uptime_percent }}".to_owned()),
+            }],
+            ..Default::default()
+        }))
+    }
+
+    #[tokio::test]
+    async fn test_run_report_sends_to_alerts() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/report"))
+            .and(body_partial_json(
+                serde_json::json!({"report_name": "weekly", "report": "weekly: -"}),
+            ))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let response = app_router(app_state(format!("{}/report", mock_server.uri())))
+            .oneshot(authorized_post("/-/reports/weekly/run"))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::OK, response.status());
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let run: ReportRunResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!("weekly: -", run.report);
+        assert_eq!(1, run.alerts.len());
+        assert!(run.alerts[0].success);
+    }
+
+    #[tokio::test]
+    async fn test_run_unknown_report() {
+        let response = app_router(app_state("http://localhost/report".to_owned()))
+            .oneshot(authorized_post("/-/reports/missing/run"))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, response.status());
+    }
+}
@@ -0,0 +1,427 @@
+use axum::{extract::Path, http::StatusCode, Extension, Json};
+use std::sync::Arc;
+use tracing::{debug, warn};
+
+use crate::app_state::AppState;
+use crate::errors::RuntimeMonitorError;
+
+use super::model::MonitorInfo;
+
+// Adds a probe to the running config, behind `require_reload_token`
+pub async fn add_probe(
+    Extension(state): Extension<Arc<AppState>>,
+    Json(probe): Json<serde_json::Value>,
+) -> Result<(StatusCode, Json<MonitorInfo>), (StatusCode, String)> {
+    debug!("Add probe called");
+
+    let probe = state
+        .config()
+        .resolve_runtime_probe(probe)
+        .map_err(|e| runtime_monitor_error(RuntimeMonitorError::Invalid(e)))?;
+    let info = MonitorInfo {
+        name: probe.name.clone(),
+        interval: probe.schedule.interval,
+        tags: probe.tags.clone(),
+        runtime_added: true,
+        referenced_probes: vec![],
+        deferred_until: None,
+    };
+    state
+        .add_runtime_probe(probe)
+        .map_err(runtime_monitor_error)?;
+    persist(&state, &info.name).await?;
+
+    Ok((StatusCode::CREATED, Json(info)))
+}
+
+// Adds a story to the running config, behind `require_reload_token`
+pub async fn add_story(
+    Extension(state): Extension<Arc<AppState>>,
+    Json(story): Json<serde_json::Value>,
+) -> Result<(StatusCode, Json<MonitorInfo>), (StatusCode, String)> {
+    debug!("Add story called");
+
+    let story = state
+        .config()
+        .resolve_runtime_story(story)
+        .map_err(|e| runtime_monitor_error(RuntimeMonitorError::Invalid(e)))?;
+    let info = MonitorInfo {
+        name: story.name.clone(),
+        interval: story.schedule.interval,
+        tags: story.tags.clone(),
+        runtime_added: true,
+        referenced_probes: story.referenced_probes(&state.config.read().unwrap().probes),
+        deferred_until: None,
+    };
+    state
+        .add_runtime_story(story)
+        .map_err(runtime_monitor_error)?;
+    persist(&state, &info.name).await?;
+
+    Ok((StatusCode::CREATED, Json(info)))
+}
+
+pub async fn delete_probe(
+    Path(name): Path<String>,
+    Extension(state): Extension<Arc<AppState>>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    debug!("Delete probe called");
+
+    state
+        .remove_runtime_probe(&name)
+        .map_err(runtime_monitor_error)?;
+    persist(&state, &name).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn delete_story(
+    Path(name): Path<String>,
+    Extension(state): Extension<Arc<AppState>>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    debug!("Delete story called");
+
+    state
+        .remove_runtime_story(&name)
+        .map_err(runtime_monitor_error)?;
+    persist(&state, &name).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// Resumes the scheduled runs of a probe, whatever its `enabled` says, behind `require_reload_token`
+pub async fn enable_probe(
+    Path(name): Path<String>,
+    Extension(state): Extension<Arc<AppState>>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    debug!("Enable probe called");
+
+    state
+        .set_probe_enabled(&name, true)
+        .map_err(runtime_monitor_error)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// Skips the scheduled runs of a probe until it's enabled again, behind `require_reload_token`
+pub async fn disable_probe(
+    Path(name): Path<String>,
+    Extension(state): Extension<Arc<AppState>>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    debug!("Disable probe called");
+
+    state
+        .set_probe_enabled(&name, false)
+        .map_err(runtime_monitor_error)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+fn runtime_monitor_error(error: RuntimeMonitorError) -> (StatusCode, String) {
+    let status = match error {
+        RuntimeMonitorError::NameTaken(_) | RuntimeMonitorError::Configured(_) => {
+            StatusCode::CONFLICT
+        }
+        RuntimeMonitorError::NotFound(_) => StatusCode::NOT_FOUND,
+        RuntimeMonitorError::Invalid(_) => StatusCode::BAD_REQUEST,
+    };
+    (status, error.to_string())
+}
+
+// The change is already live when persisting fails, the error says so
+async fn persist(state: &AppState, name: &str) -> Result<(), (StatusCode, String)> {
+    state.persist_runtime_monitors().await.map_err(|e| {
+        warn!("Failed to persist runtime monitors: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!(
+                "'{}' was changed in the running config, but persisting it failed: {}",
+                name, e
+            ),
+        )
+    })
+}
+
+#[cfg(test)]
+mod runtime_monitors_tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+    use http_body_util::BodyExt;
+    use serde_json::json;
+    use tower::ServiceExt;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use crate::app_state::AppState;
+    use crate::config::{load_config, runtime_monitors_path, Config, Settings};
+    use crate::probe::model::ProbeExpectation;
+    use crate::test_utils::probe_test_utils::probe_get_with_expected_status;
+    use crate::web_server::app_router;
+    use crate::web_server::model::MonitorsResponse;
+
+    const RELOAD_TOKEN: &str = "test-reload-token";
+
+    fn probe_body(name: &str) -> serde_json::Value {
+        json!({
+            "name": name,
+            "url": "http://localhost:1/health",
+            "schedule": { "initial_delay": 3600, "interval": 60 },
+        })
+    }
+
+    async fn send(
+        app_state: Arc<AppState>,
+        method: &str,
+        uri: &str,
+        body: Option<serde_json::Value>,
+    ) -> StatusCode {
+        std::env::set_var("XBP_RELOAD_TOKEN", RELOAD_TOKEN);
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("Authorization", format!("Bearer {}", RELOAD_TOKEN))
+            .header("Content-Type", "application/json");
+        let body = body.map_or_else(Body::empty, |body| Body::from(body.to_string()));
+        app_router(app_state)
+            .oneshot(request.body(body).unwrap())
+            .await
+            .unwrap()
+            .status()
+    }
+
+    async fn get_monitors(app_state: Arc<AppState>) -> MonitorsResponse {
+        let response = app_router(app_state)
+            .oneshot(Request::get("/-/monitors").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    fn configured_app_state() -> Arc<AppState> {
+        let probe = probe_get_with_expected_status(
+            reqwest::StatusCode::OK,
+            "http://localhost/health".to_owned(),
+            "".to_owned(),
+        );
+        Arc::new(AppState::new(Config {
+            probes: vec![probe],
+            ..Default::default()
+        }))
+    }
+
+    #[tokio::test]
+    async fn test_disabled_probes_skip_scheduled_runs() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/health"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&mock_server)
+            .await;
+        let mut probe = probe_get_with_expected_status(
+            reqwest::StatusCode::OK,
+            format!("{}/health", mock_server.uri()),
+            "".to_owned(),
+        );
+        probe.schedule.interval = Duration::from_millis(20);
+        // The runtime override takes precedence over the config
+        probe.enabled = Some(false);
+        let app_state = Arc::new(AppState::new(Config {
+            probes: vec![probe],
+            ..Default::default()
+        }));
+        let results = |app_state: &AppState| {
+            app_state
+                .probe_results
+                .read("Test probe", |results| results.len())
+                .unwrap_or(0)
+        };
+
+        let unknown = send(app_state.clone(), "POST", "/-/probes/missing/disable", None).await;
+        assert_eq!(StatusCode::NOT_FOUND, unknown);
+        app_state.start_monitoring();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(0, results(&app_state));
+
+        let enabled = send(
+            app_state.clone(),
+            "POST",
+            "/-/probes/Test%20probe/enable",
+            None,
+        )
+        .await;
+        assert_eq!(StatusCode::NO_CONTENT, enabled);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(results(&app_state) > 0);
+
+        let disabled = send(
+            app_state.clone(),
+            "POST",
+            "/-/probes/Test%20probe/disable",
+            None,
+        )
+        .await;
+        assert_eq!(StatusCode::NO_CONTENT, disabled);
+        // A run in flight may still finish
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let stopped_at = results(&app_state);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(stopped_at, results(&app_state));
+        app_state.stop_monitoring();
+    }
+
+    #[tokio::test]
+    async fn test_runtime_probes_are_added_and_removed() {
+        let app_state = configured_app_state();
+
+        let added = send(
+            app_state.clone(),
+            "POST",
+            "/probes",
+            Some(probe_body("portal")),
+        )
+        .await;
+        assert_eq!(StatusCode::CREATED, added);
+
+        let monitors = get_monitors(app_state.clone()).await;
+        let runtime_added: Vec<(&str, bool)> = monitors
+            .probes
+            .iter()
+            .map(|probe| (probe.name.as_str(), probe.runtime_added))
+            .collect();
+        assert_eq!(vec![("Test probe", false), ("portal", true)], runtime_added);
+
+        let deleted = send(app_state.clone(), "DELETE", "/probes/portal", None).await;
+        assert_eq!(StatusCode::NO_CONTENT, deleted);
+        assert_eq!(1, get_monitors(app_state.clone()).await.probes.len());
+
+        app_state.stop_monitoring();
+    }
+
+    #[tokio::test]
+    async fn test_runtime_monitor_conflicts() {
+        let app_state = configured_app_state();
+
+        let taken = send(
+            app_state.clone(),
+            "POST",
+            "/probes",
+            Some(probe_body("Test probe")),
+        )
+        .await;
+        let configured = send(app_state.clone(), "DELETE", "/probes/Test%20probe", None).await;
+        let unknown = send(app_state.clone(), "DELETE", "/probes/unknown", None).await;
+        let invalid = send(
+            app_state.clone(),
+            "POST",
+            "/probes",
+            Some(json!({
+                "name": "invalid",
+                "url": "http://localhost/health",
+                "with": { "timeout": 1000, "timeout_seconds": 1 },
+                "schedule": { "initial_delay": 0, "interval": 60 },
+            })),
+        )
+        .await;
+
+        assert_eq!(StatusCode::CONFLICT, taken);
+        assert_eq!(StatusCode::CONFLICT, configured);
+        assert_eq!(StatusCode::NOT_FOUND, unknown);
+        assert_eq!(StatusCode::BAD_REQUEST, invalid);
+        assert_eq!(1, app_state.config.read().unwrap().probes.len());
+    }
+
+    #[tokio::test]
+    async fn test_runtime_monitors_use_the_running_expectation_sets() {
+        let config: Config = serde_yaml::from_str(
+            r#"
+expectation_sets:
+  json_health:
+    - { field: StatusCode, operation: Equals, value: "200" }
+"#,
+        )
+        .unwrap();
+        let app_state = Arc::new(AppState::new(config));
+        let mut probe = probe_body("portal");
+        probe["expectations"] = json!({
+            "use": ["json_health"],
+            "also": [{ "field": "Body", "operation": "Contains", "value": "ok" }],
+        });
+        let story = json!({
+            "name": "checkout",
+            "schedule": { "initial_delay": 3600, "interval": 60 },
+            "steps": [{
+                "name": "cart",
+                "url": "http://localhost:1/cart",
+                "expectations": { "use": ["json_health"] },
+            }],
+        });
+        let mut unknown_set = probe_body("unknown");
+        unknown_set["expectations"] = json!({ "use": ["missing"] });
+
+        let added_probe = send(app_state.clone(), "POST", "/probes", Some(probe)).await;
+        let added_story = send(app_state.clone(), "POST", "/stories", Some(story)).await;
+        let unknown = send(app_state.clone(), "POST", "/probes", Some(unknown_set)).await;
+        app_state.stop_monitoring();
+
+        assert_eq!(StatusCode::CREATED, added_probe);
+        assert_eq!(StatusCode::CREATED, added_story);
+        assert_eq!(StatusCode::BAD_REQUEST, unknown);
+        let config = app_state.config();
+        let values = |expectations: &Option<Vec<ProbeExpectation>>| -> Vec<String> {
+            expectations
+                .iter()
+                .flatten()
+                .map(|expectation| expectation.value.clone())
+                .collect()
+        };
+        assert_eq!(vec!["200", "ok"], values(&config.probes[0].expectations));
+        assert_eq!(
+            vec!["200"],
+            values(&config.stories[0].steps[0].expectations)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_runtime_monitors_are_persisted_and_loaded() {
+        let directory = std::env::temp_dir().join(format!("xbp-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let config_path = directory.join("xbp.yaml");
+        std::fs::write(
+            &config_path,
+            "settings:\n  persist_runtime_monitors: true\n",
+        )
+        .unwrap();
+        let app_state = Arc::new(
+            AppState::new(Config {
+                settings: Settings {
+                    persist_runtime_monitors: true,
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .with_config_path(&config_path),
+        );
+
+        let added = send(
+            app_state.clone(),
+            "POST",
+            "/probes",
+            Some(probe_body("portal")),
+        )
+        .await;
+        app_state.stop_monitoring();
+
+        assert_eq!(StatusCode::CREATED, added);
+        assert!(runtime_monitors_path(&config_path).exists());
+        let config = load_config(&config_path).await.unwrap();
+        assert_eq!(1, config.probes.len());
+        assert_eq!("portal", config.probes[0].name);
+        assert!(config.probes[0].runtime_added);
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
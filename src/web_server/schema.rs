@@ -0,0 +1,167 @@
+// Served by `/-/schema.json`, the JSON schema of the API responses to generate client types from.
+// Optional fields are left out rather than serialized as null, so the schema doesn't make them
+// nullable either.
+use std::collections::BTreeMap;
+
+use axum::Json;
+use schemars::gen::{SchemaGenerator, SchemaSettings};
+use schemars::schema::Schema;
+use schemars::JsonSchema;
+use serde_json::{json, Value};
+use tracing::debug;
+
+use crate::probe::model::{ProbeResult, StoryResult};
+
+use super::model::{
+    AboutResponse, AlertTestResult, ExplainResponse, MonitorsResponse, ProbeResponse,
+    ReloadResponse, ReportRunResponse, ResolvedConfigResponse, StoryHistoryResponse, StoryStatus,
+    TimelineResponse,
+};
+
+pub async fn api_schema() -> Json<Value> {
+    debug!("Get API schema called");
+    Json(schema())
+}
+
+pub fn schema() -> Value {
+    let mut generator = SchemaSettings::draft07()
+        .with(|settings| settings.option_add_null_type = false)
+        .into_generator();
+    let mut endpoints = BTreeMap::new();
+    let mut endpoint = |route: &str, generate: fn(&mut SchemaGenerator) -> Schema| {
+        endpoints.insert(route.to_owned(), generate(&mut generator));
+    };
+    endpoint("GET /probes", subschema::<Vec<ProbeResponse>>);
+    endpoint("GET /probes/:name", subschema::<ProbeResponse>);
+    endpoint("GET /probes/:name/results", subschema::<Vec<ProbeResult>>);
+    endpoint("GET /probes/:name/trigger", subschema::<ProbeResult>);
+    endpoint("GET /probes/:name/explain", subschema::<ExplainResponse>);
+    endpoint("GET /stories", subschema::<Vec<ProbeResponse>>);
+    endpoint("GET /stories/:name", subschema::<ProbeResponse>);
+    endpoint("GET /stories/:name/results", subschema::<Vec<StoryResult>>);
+    endpoint("GET /stories/:name/trigger", subschema::<StoryResult>);
+    endpoint("GET /-/monitors", subschema::<MonitorsResponse>);
+    endpoint("GET /-/probes", subschema::<MonitorsResponse>);
+    endpoint("GET /-/stories", subschema::<Vec<StoryStatus>>);
+    endpoint("GET /-/stories/:name", subschema::<StoryHistoryResponse>);
+    endpoint("GET /-/config", subschema::<ResolvedConfigResponse>);
+    endpoint("GET /-/about", subschema::<AboutResponse>);
+    endpoint("GET /-/timeline", subschema::<TimelineResponse>);
+    endpoint("POST /-/reload", subschema::<ReloadResponse>);
+    endpoint("POST /-/alerts/test", subschema::<Vec<AlertTestResult>>);
+    endpoint("POST /-/reports/:name/run", subschema::<ReportRunResponse>);
+
+    json!({
+        "$schema": "http://json-schema.org/draft-07/schema#",
+        "title": "xbp-monitoring API responses",
+        "endpoints": endpoints,
+        "definitions": generator.definitions(),
+    })
+}
+
+fn subschema<T: JsonSchema>(generator: &mut SchemaGenerator) -> Schema {
+    generator.subschema_for::<T>()
+}
+
+#[cfg(test)]
+mod schema_tests {
+    use serde_json::Value;
+
+    use super::schema;
+
+    // Every `$ref` points into `definitions`, and no type is nullable
+    fn check(value: &Value, definitions: &serde_json::Map<String, Value>) {
+        match value {
+            Value::Object(object) => {
+                if let Some(Value::String(reference)) = object.get("$ref") {
+                    let name = reference.trim_start_matches("#/definitions/");
+                    assert!(definitions.contains_key(name), "missing {}", reference);
+                }
+                let nullable = match object.get("type") {
+                    Some(Value::String(kind)) => kind == "null",
+                    Some(Value::Array(kinds)) => {
+                        kinds.iter().any(|kind| kind.as_str() == Some("null"))
+                    }
+                    _ => false,
+                };
+                assert!(!nullable, "nullable type in {}", value);
+                object.values().for_each(|value| check(value, definitions));
+            }
+            Value::Array(values) => values.iter().for_each(|value| check(value, definitions)),
+            _ => {}
+        }
+    }
+
+    #[test]
+    fn test_schema_references_resolve_and_nothing_is_nullable() {
+        let schema = schema();
+        let definitions = schema["definitions"].as_object().unwrap();
+
+        assert!(definitions.contains_key("ProbeResponse"));
+        assert!(definitions.contains_key("ProbeHttpResponse"));
+        assert!(definitions.contains_key("ProbeResult"));
+        check(&schema, definitions);
+        let probe = &definitions["ProbeResponse"];
+        let required = probe["required"].as_array().unwrap();
+        assert!(required.contains(&Value::from("name")));
+        assert!(!required.contains(&Value::from("last_probed")));
+    }
+}
+
+// The serialized responses of the read endpoints, against the fixed results of `seeded_app_state`.
+// A changed snapshot is a changed API, update it with `cargo insta review` once that's intended.
+#[cfg(test)]
+mod api_snapshot_tests {
+    use std::sync::Arc;
+
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+    use http_body_util::BodyExt;
+    use serde_json::Value;
+    use tower::ServiceExt;
+
+    use crate::test_utils::app_state_test_utils::seeded_app_state;
+    use crate::web_server::app_router;
+
+    async fn get_json(uri: &str) -> Value {
+        let response = app_router(Arc::new(seeded_app_state()))
+            .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::OK, response.status(), "{}", uri);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_probe_endpoints() {
+        insta::assert_json_snapshot!("probes", get_json("/probes").await);
+        insta::assert_json_snapshot!("probe", get_json("/probes/checkout-api").await);
+        insta::assert_json_snapshot!(
+            "probe_results",
+            get_json("/probes/checkout-api/results").await
+        );
+        insta::assert_json_snapshot!(
+            "probe_explain",
+            get_json("/probes/checkout-api/explain").await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_story_endpoints() {
+        insta::assert_json_snapshot!("stories", get_json("/stories").await);
+        insta::assert_json_snapshot!("story", get_json("/stories/signup-flow").await);
+        insta::assert_json_snapshot!(
+            "story_results",
+            get_json("/stories/signup-flow/results").await
+        );
+        insta::assert_json_snapshot!("story_statuses", get_json("/-/stories").await);
+        insta::assert_json_snapshot!("story_history", get_json("/-/stories/signup-flow").await);
+    }
+
+    #[tokio::test]
+    async fn test_admin_endpoints() {
+        insta::assert_json_snapshot!("monitors", get_json("/-/monitors").await);
+        insta::assert_json_snapshot!("timeline", get_json("/-/timeline").await);
+    }
+}
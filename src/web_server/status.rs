@@ -0,0 +1,83 @@
+use axum::{
+    extract::Query,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use std::sync::Arc;
+use tracing::debug;
+
+use crate::app_state::AppState;
+
+use super::model::GroupQueryParams;
+
+// Serves the summary as last computed by the background summarizer, without touching the results.
+// `?group=` narrows it to the group's monitors and scores the health of their probes alone.
+pub async fn status(
+    Query(params): Query<GroupQueryParams>,
+    Extension(state): Extension<Arc<AppState>>,
+) -> Response {
+    debug!("Get status called");
+
+    let summary = state.status_summary.summary();
+    match params.group {
+        None => Json(&*summary).into_response(),
+        Some(group) => {
+            let config = state.config();
+            Json(summary.filtered(|monitor| config.in_group(&monitor.name, Some(&group))))
+                .into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod status_tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+
+    use crate::app_state::AppState;
+    use crate::config::Config;
+    use crate::status_summary::{StatusSummarizer, StatusSummary};
+    use crate::test_utils::result_test_utils::ProbeResultBuilder;
+    use crate::web_server::app_router;
+
+    async fn get_status(app_state: Arc<AppState>) -> StatusSummary {
+        let response = app_router(app_state)
+            .oneshot(Request::get("/status").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::OK, response.status());
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_status_is_served_from_the_background_summary() {
+        let app_state = Arc::new(AppState::new(Config::default()));
+        tokio::spawn(StatusSummarizer::run(app_state.clone()));
+        app_state.add_probe_result(
+            "api".to_owned(),
+            ProbeResultBuilder::new("api")
+                .duration(Duration::from_millis(5))
+                .build(),
+        );
+
+        let mut summary = get_status(app_state.clone()).await;
+        for _ in 0..50 {
+            if !summary.monitors.is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            summary = get_status(app_state.clone()).await;
+        }
+
+        assert_eq!(1, summary.monitors.len());
+        assert_eq!("api", summary.monitors[0].name);
+        assert_eq!(Some(5.0), summary.monitors[0].p50_duration_ms);
+        assert!(summary.computed_at >= summary.monitors[0].last_run_at);
+    }
+}
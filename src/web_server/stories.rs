@@ -1,17 +1,21 @@
-
 use axum::{
     extract::{Path, Query},
+    http::StatusCode,
     Extension, Json,
 };
 use std::sync::Arc;
-use tracing::debug;
+use tracing::{debug, warn};
 
 use crate::{
-    app_state::AppState,
+    app_state::{AppState, MonitorState},
     probe::{model::StoryResult, probe_logic::Monitorable},
+    storage::MonitorKind,
 };
 
-use super::model::{ProbeQueryParams, ProbeResponse};
+use super::model::{
+    matches_group, GroupQueryParams, ProbeQueryParams, ProbeResponse, ProbeStatus,
+    StoryHistoryResponse, StoryStatus,
+};
 
 // TODO: Error handling for all of the endpoints
 
@@ -23,61 +27,265 @@ pub async fn get_story_results(
     debug!("Get story results called");
 
     let show_response = params.show_response.unwrap_or(false);
-    let read_lock = state.story_results.read().unwrap();
-    let results = read_lock.get(&name).unwrap();
-
-    let mut cloned_results: Vec<StoryResult> = results.clone();
-    cloned_results.reverse();
+    let stored = match state
+        .storage
+        .recent(MonitorKind::Story, &name, params.since, params.limit)
+        .await
+    {
+        Ok(stored) => stored.into_stories(),
+        Err(e) => {
+            warn!("{}, serving the in-memory results of {}", e, name);
+            state.story_results.recent(&name).unwrap_or_default()
+        }
+    };
+    let mut cloned_results: Vec<StoryResult> = params
+        .select(&stored, |result| result.timestamp_started)
+        .into_iter()
+        .cloned()
+        .collect();
 
     if !show_response {
-        for result in &mut cloned_results {
-            for step_result in &mut result.step_results {
-                step_result.response = None;
-            }
-        }
+        cloned_results.iter_mut().for_each(hide_responses);
     }
 
     Json(cloned_results)
 }
 
-pub async fn stories(Extension(state): Extension<Arc<AppState>>) -> Json<Vec<ProbeResponse>> {
-    debug!("Get stories called");
+fn hide_responses(result: &mut StoryResult) {
+    for step_result in result
+        .step_results
+        .iter_mut()
+        .chain(result.teardown_results.iter_mut())
+    {
+        step_result.response = None;
+    }
+}
 
-    let read_lock = state.story_results.read().unwrap();
+fn story_status(state: &AppState, name: &str, monitor_state: Option<&MonitorState>) -> StoryStatus {
+    let last = state
+        .story_results
+        .read(name, |results| {
+            results
+                .back()
+                .map(|last| (last.success, last.timestamp_started, last.duration))
+        })
+        .flatten();
+    StoryStatus {
+        name: name.to_owned(),
+        status: ProbeStatus::of(last.map(|(success, _, _)| success), monitor_state),
+        last_run: last.map(|(_, timestamp, _)| timestamp),
+        duration: last.and_then(|(_, _, duration)| duration),
+    }
+}
 
-    let mut stories: Vec<ProbeResponse> = vec![];
+// Every configured story with its latest run, `unknown` for those that haven't run yet
+pub async fn story_statuses(
+    Query(params): Query<GroupQueryParams>,
+    Extension(state): Extension<Arc<AppState>>,
+) -> Json<Vec<StoryStatus>> {
+    debug!("Get story statuses called");
+
+    let config = state.config();
+    let monitor_states = state.monitor_states.read().unwrap();
+
+    Json(
+        config
+            .stories
+            .iter()
+            .filter(|story| matches_group(&story.group, &params.group))
+            .map(|story| story_status(&state, &story.name, monitor_states.get(&story.name)))
+            .collect(),
+    )
+}
+
+// The status of a story and its stored runs with their steps, filtered like `/stories/:name/results`
+pub async fn story_history(
+    Path(name): Path<String>,
+    Query(params): Query<ProbeQueryParams>,
+    Extension(state): Extension<Arc<AppState>>,
+) -> Result<Json<StoryHistoryResponse>, StatusCode> {
+    debug!("Get story history called");
 
-    for (key, value) in read_lock.iter() {
-        let last = value.last().unwrap();
-        let status = if last.success { "OK" } else { "FAILING" };
+    if !state
+        .config()
+        .stories
+        .iter()
+        .any(|story| story.name == name)
+    {
+        return Err(StatusCode::NOT_FOUND);
+    }
 
-        stories.push(ProbeResponse {
-            name: key.clone(),
-            status: status.to_owned(),
-            last_probed: last.timestamp_started,
+    let mut runs: Vec<StoryResult> = state
+        .story_results
+        .read(&name, |results| {
+            params
+                .select(results, |result| result.timestamp_started)
+                .into_iter()
+                .cloned()
+                .collect()
         })
+        .unwrap_or_default();
+    if !params.show_response.unwrap_or(false) {
+        runs.iter_mut().for_each(hide_responses);
+    }
+    let status = {
+        let monitor_states = state.monitor_states.read().unwrap();
+        story_status(&state, &name, monitor_states.get(&name))
+    };
+
+    Ok(Json(StoryHistoryResponse { status, runs }))
+}
+
+pub async fn stories(
+    Query(params): Query<GroupQueryParams>,
+    Extension(state): Extension<Arc<AppState>>,
+) -> Json<Vec<ProbeResponse>> {
+    debug!("Get stories called");
+
+    let config = state.config();
+    let latest = state.story_results.latest_all();
+    let monitor_states = state.monitor_states.read().unwrap();
+
+    let mut stories: Vec<ProbeResponse> = vec![];
+
+    for (key, last, activity) in latest {
+        if !config.in_group(&key, params.group.as_deref()) {
+            continue;
+        }
+        stories.push(
+            ProbeResponse::new(
+                key.clone(),
+                Some((last.success, last.timestamp_started)),
+                monitor_states.get(&key),
+            )
+            .with_activity(Some(&activity)),
+        )
     }
 
     Json(stories)
 }
 
+pub async fn get_story(
+    Path(name): Path<String>,
+    Extension(state): Extension<Arc<AppState>>,
+) -> Result<Json<ProbeResponse>, StatusCode> {
+    debug!("Get story called");
+
+    if !state
+        .config
+        .read()
+        .unwrap()
+        .stories
+        .iter()
+        .any(|x| x.name == name)
+    {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    let last_run = state
+        .story_results
+        .latest(&name)
+        .map(|last| (last.success, last.timestamp_started));
+    let activity = state.story_results.summary(&name);
+    let monitor_states = state.monitor_states.read().unwrap();
+
+    Ok(Json(
+        ProbeResponse::new(name.clone(), last_run, monitor_states.get(&name))
+            .with_activity(activity.as_ref()),
+    ))
+}
+
 pub async fn story_trigger(
     Path(name): Path<String>,
     Extension(state): Extension<Arc<AppState>>,
 ) -> Json<StoryResult> {
     debug!("Story trigger called");
 
-    let story = &state
+    let story = state
         .config
+        .read()
+        .unwrap()
         .stories
         .iter()
         .find(|x| x.name == name)
+        .cloned()
         .unwrap();
 
     story.probe_and_store_result(state.clone()).await;
 
-    let lock = state.story_results.read().unwrap();
-    let story_results = lock.get(&name).unwrap();
+    Json(state.story_results.latest(&name).unwrap())
+}
+
+#[cfg(test)]
+mod stories_tests {
+    use std::sync::Arc;
+
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+
+    use crate::app_state::AppState;
+    use crate::config::Config;
+    use crate::test_utils::result_test_utils::story_result;
+    use crate::web_server::app_router;
+
+    async fn get_json(app_state: Arc<AppState>, uri: &str) -> (StatusCode, serde_json::Value) {
+        let response = app_router(app_state)
+            .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, serde_json::from_slice(&body).unwrap_or_default())
+    }
+
+    #[tokio::test]
+    async fn test_stories_list_their_latest_run() {
+        let config: Config = serde_yaml::from_str(
+            r#"
+stories:
+  - name: checkout
+    schedule: { initial_delay: 0, interval: 60 }
+    steps: [{ name: cart, url: http://localhost/cart }]
+  - name: signup
+    schedule: { initial_delay: 0, interval: 60 }
+    steps: [{ name: form, url: http://localhost/signup }]
+"#,
+        )
+        .unwrap();
+        let app_state = Arc::new(AppState::new(config));
+        app_state.add_story_result(
+            "checkout".to_owned(),
+            story_result("checkout", "cart", true),
+        );
+        app_state.add_story_result(
+            "checkout".to_owned(),
+            story_result("checkout", "cart", false),
+        );
+
+        let (status, stories) = get_json(app_state.clone(), "/-/stories").await;
+
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!("checkout", stories[0]["name"]);
+        assert_eq!("error", stories[0]["status"]);
+        assert_eq!(120.0, stories[0]["duration_ms"]);
+        assert!(stories[0]["last_run"].is_string());
+        assert_eq!("signup", stories[1]["name"]);
+        assert_eq!("unknown", stories[1]["status"]);
+        assert!(stories[1].get("last_run").is_none());
+
+        let (status, history) = get_json(app_state.clone(), "/-/stories/checkout").await;
 
-    Json(story_results.last().unwrap().clone())
-}
\ No newline at end of file
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!("error", history["status"]);
+        assert_eq!(2, history["runs"].as_array().unwrap().len());
+        assert_eq!("cart", history["runs"][1]["step_results"][0]["step_name"]);
+        assert!(history["runs"][1]["step_results"][0]
+            .get("response")
+            .is_none());
+
+        let (status, _) = get_json(app_state, "/-/stories/missing").await;
+        assert_eq!(StatusCode::NOT_FOUND, status);
+    }
+}
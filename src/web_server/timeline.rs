@@ -0,0 +1,57 @@
+use axum::{Extension, Json};
+use std::sync::Arc;
+use tracing::debug;
+
+use crate::app_state::AppState;
+
+use super::model::TimelineResponse;
+
+// Problems of xbp itself, oldest first, see `self_alerts`
+pub async fn timeline(Extension(state): Extension<Arc<AppState>>) -> Json<TimelineResponse> {
+    debug!("Get timeline called");
+    Json(TimelineResponse {
+        events: state.self_monitor.events(),
+    })
+}
+
+#[cfg(test)]
+mod timeline_tests {
+    use std::sync::Arc;
+
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+
+    use crate::app_state::AppState;
+    use crate::config::Config;
+    use crate::self_alerts::{raise, SelfAlertKind};
+    use crate::web_server::app_router;
+
+    #[tokio::test]
+    async fn test_timeline_lists_self_alert_events() {
+        let app_state = Arc::new(AppState::new(Config::default()));
+        raise(
+            &app_state,
+            SelfAlertKind::ConfigReload,
+            "Invalid config: duplicate probe names: api".to_owned(),
+        );
+
+        let response = app_router(app_state.clone())
+            .oneshot(Request::get("/-/timeline").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(StatusCode::OK, response.status());
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let timeline: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let event = &timeline["events"][0];
+        assert_eq!("config_reload", event["kind"]);
+        assert_eq!("xbp/config_reload", event["dedup_key"]);
+        assert_eq!(
+            "Invalid config: duplicate probe names: api",
+            event["message"]
+        );
+        assert_eq!(false, event["alerted"]);
+    }
+}
@@ -0,0 +1,58 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use xbp_monitoring::config::Config;
+use xbp_monitoring::probe::model::ProbeResult;
+use xbp_monitoring::AppState;
+
+const RESULT_WAIT: Duration = Duration::from_secs(15);
+
+// Parses a config the same way the binary does, so tests exercise the documented YAML format
+pub fn config_from_yaml(yaml: &str) -> Config {
+    serde_yaml::from_str(yaml).unwrap()
+}
+
+// A single GET probe running once right after scheduling
+pub fn probe_yaml(name: &str, url: &str, extra: &str) -> String {
+    format!(
+        r#"
+  - name: {name}
+    url: {url}
+    http_method: GET
+    schedule:
+      initial_delay: 0
+      interval: 3600
+{extra}"#
+    )
+}
+
+pub fn start_probes(config: Config) -> Arc<AppState> {
+    let app_state = Arc::new(AppState::new(config));
+    app_state.start_monitoring();
+    app_state
+}
+
+// Waits until the probe stored a result, panicking when none shows up in time
+pub async fn first_result(app_state: &AppState, probe_name: &str) -> ProbeResult {
+    let deadline = tokio::time::Instant::now() + RESULT_WAIT;
+    loop {
+        // Registered before reading, so a result stored in between still wakes us
+        let notified = app_state.new_result_notify.notified();
+        let result = app_state
+            .probe_results
+            .read(probe_name, |results| results.front().cloned())
+            .flatten();
+        if let Some(result) = result {
+            return result;
+        }
+        assert!(
+            tokio::time::timeout_at(deadline, notified).await.is_ok(),
+            "no result for probe {probe_name}"
+        );
+    }
+}
+
+pub fn duration_ms(result: &ProbeResult) -> i64 {
+    let response = result.response.as_ref().unwrap();
+    (response.timestamp_received - result.timestamp_started).num_milliseconds()
+}
@@ -0,0 +1,33 @@
+use std::sync::Arc;
+
+use axum::routing::get;
+use axum::Router;
+use tokio::net::TcpListener;
+use xbp_monitoring::{app_router, AppState, Config};
+
+use crate::common::{config_from_yaml, probe_yaml};
+
+#[tokio::test]
+async fn test_router_nests_in_a_host_application() {
+    let config: Config = config_from_yaml(&format!(
+        "probes:{}",
+        probe_yaml("embedded", "http://localhost:1/health", "")
+    ));
+    let app_state = Arc::new(AppState::new(config));
+    let app = Router::new()
+        .route("/health", get(|| async { "host" }))
+        .nest("/monitoring", app_router(app_state));
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+    let host = reqwest::get(format!("http://{}/health", address))
+        .await
+        .unwrap();
+    assert_eq!("host", host.text().await.unwrap());
+    let monitors = reqwest::get(format!("http://{}/monitoring/-/monitors", address))
+        .await
+        .unwrap();
+    assert_eq!(200, monitors.status().as_u16());
+    assert!(monitors.text().await.unwrap().contains("embedded"));
+}
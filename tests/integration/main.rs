@@ -0,0 +1,4 @@
+// Runs scheduled probes end to end against wiremock servers
+mod common;
+mod embedding;
+mod probes;
@@ -0,0 +1,170 @@
+use std::time::Duration;
+
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+use crate::common::{config_from_yaml, duration_ms, first_result, probe_yaml, start_probes};
+
+#[tokio::test]
+async fn test_successful_probe_is_stored() {
+    let mock_server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/health"))
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_millis(100)))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let probe = probe_yaml(
+        "healthy",
+        &format!("{}/health", mock_server.uri()),
+        r#"    expectations:
+      - field: StatusCode
+        operation: Equals
+        value: "200""#,
+    );
+    let app_state = start_probes(config_from_yaml(&format!("probes:{probe}")));
+
+    let result = first_result(&app_state, "healthy").await;
+
+    assert!(result.success);
+    assert_eq!(200, result.response.as_ref().unwrap().status_code);
+    assert!(duration_ms(&result) >= 100);
+    assert!(result.error_message.is_none());
+}
+
+#[tokio::test]
+async fn test_server_error_fails_expectation() {
+    let mock_server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/health"))
+        .respond_with(ResponseTemplate::new(503))
+        .mount(&mock_server)
+        .await;
+
+    let probe = probe_yaml(
+        "unavailable",
+        &format!("{}/health", mock_server.uri()),
+        r#"    expectations:
+      - field: StatusCode
+        operation: Equals
+        value: "200""#,
+    );
+    let app_state = start_probes(config_from_yaml(&format!("probes:{probe}")));
+
+    let result = first_result(&app_state, "unavailable").await;
+
+    assert!(!result.success);
+    assert_eq!(503, result.response.as_ref().unwrap().status_code);
+    assert!(result.error_message.is_some());
+}
+
+#[tokio::test]
+async fn test_server_error_fails_default_success_statuses() {
+    let mock_server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/health"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&mock_server)
+        .await;
+
+    let probe = probe_yaml("defaulted", &format!("{}/health", mock_server.uri()), "");
+    let app_state = start_probes(config_from_yaml(&format!(
+        r#"
+settings:
+  default_success_statuses: ["2xx", "3xx"]
+probes:{probe}"#
+    )));
+
+    let result = first_result(&app_state, "defaulted").await;
+
+    assert!(!result.success);
+    assert_eq!(500, result.response.as_ref().unwrap().status_code);
+}
+
+#[tokio::test]
+async fn test_slow_response_times_out() {
+    let mock_server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/slow"))
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(3)))
+        .mount(&mock_server)
+        .await;
+
+    let probe = probe_yaml(
+        "slow",
+        &format!("{}/slow", mock_server.uri()),
+        r#"    with:
+      timeout_seconds: 1"#,
+    );
+    let app_state = start_probes(config_from_yaml(&format!("probes:{probe}")));
+
+    let result = first_result(&app_state, "slow").await;
+
+    assert!(!result.success);
+    assert!(result.response.is_none());
+    assert!(result.error_message.is_some());
+}
+
+#[tokio::test]
+async fn test_redirect_chain_is_followed() {
+    let mock_server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/old"))
+        .respond_with(ResponseTemplate::new(301).insert_header("Location", "/moved"))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/moved"))
+        .respond_with(ResponseTemplate::new(302).insert_header("Location", "/current"))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/current"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("arrived"))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let probe = probe_yaml(
+        "redirected",
+        &format!("{}/old", mock_server.uri()),
+        r#"    with:
+      follow_redirects: true
+    expectations:
+      - field: Body
+        operation: Equals
+        value: arrived"#,
+    );
+    let app_state = start_probes(config_from_yaml(&format!("probes:{probe}")));
+
+    let result = first_result(&app_state, "redirected").await;
+
+    assert!(result.success);
+    assert_eq!(200, result.response.as_ref().unwrap().status_code);
+}
+
+#[tokio::test]
+async fn test_redirect_loop_fails() {
+    let mock_server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/loop"))
+        .respond_with(ResponseTemplate::new(302).insert_header("Location", "/loop"))
+        .mount(&mock_server)
+        .await;
+
+    let probe = probe_yaml(
+        "looping",
+        &format!("{}/loop", mock_server.uri()),
+        r#"    with:
+      follow_redirects: true"#,
+    );
+    let app_state = start_probes(config_from_yaml(&format!("probes:{probe}")));
+
+    let result = first_result(&app_state, "looping").await;
+
+    assert!(!result.success);
+    assert!(result.response.is_none());
+}